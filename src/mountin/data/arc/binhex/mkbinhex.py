@@ -79,13 +79,12 @@ def write_binhex(output_path, tar_path):
             raise ValueError("No files in tar")
 
     # Build binary content
-    # Header: name_len(1) + name + null(1) + version(1) + type(4) + creator(4)
+    # Header: name_len(1) + name + version(1) + type(4) + creator(4)
     #         + flags(2) + data_len(4) + rsrc_len(4) + header_crc(2)
     name_bytes = name.encode("ascii")
     header = bytearray()
     header.append(len(name_bytes))
     header.extend(name_bytes)
-    header.append(0)  # null terminator
     header.append(0)  # version
     header.extend(b"TEXT")  # type
     header.extend(b"ttxt")  # creator
@@ -1,14 +1,22 @@
 #!/bin/sh
 set -e
 
-OUTPUT_PATH="$1"
 INPUT="/host/build/data/templates/basic.tar"
 
 mkdir -p /tmp/template
 tar -xf "$INPUT" -C /tmp/template
 
 cd /tmp/template
-gcab -cz /tmp/output.cab *
+for OUTPUT_PATH in "$@"; do
+    case "$(basename "$OUTPUT_PATH")" in
+        basic.stored.cab)
+            gcab -c /tmp/output.cab *
+            ;;
+        *)
+            gcab -cz /tmp/output.cab *
+            ;;
+    esac
 
-mkdir -p "$(dirname "/host/build/$OUTPUT_PATH")"
-cp /tmp/output.cab "/host/build/$OUTPUT_PATH"
+    mkdir -p "$(dirname "/host/build/$OUTPUT_PATH")"
+    cp /tmp/output.cab "/host/build/$OUTPUT_PATH"
+done
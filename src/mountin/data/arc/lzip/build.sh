@@ -1,10 +1,25 @@
 #!/bin/sh
-set -e
+set -eu
 
-OUTPUT_PATH="$1"
 INPUT="/host/build/data/templates/basic.tar"
 
-lzip -c < "$INPUT" > /tmp/output.tar.lz
+for output in "$@"; do
+    output_path="/host/build/$output"
+    case "$(basename "$output")" in
+        basic.tar.lz)
+            options=""
+            ;;
+        basic.members.tar.lz)
+            # Smallest member size lzip allows
+            options="-b 100KiB"
+            ;;
+        *)
+            echo "Unknown lzip fixture: $output" >&2
+            exit 1
+            ;;
+    esac
 
-mkdir -p "$(dirname "/host/build/$OUTPUT_PATH")"
-cp /tmp/output.tar.lz "/host/build/$OUTPUT_PATH"
+    mkdir -p "$(dirname "$output_path")"
+    lzip -c $options < "$INPUT" > "$output_path"
+    echo "Built: $output"
+done
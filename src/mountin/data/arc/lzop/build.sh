@@ -1,10 +1,28 @@
 #!/bin/sh
-set -e
+set -eu
 
-OUTPUT_PATH="$1"
 INPUT="/host/build/data/templates/basic.tar"
 
-lzop -c < "$INPUT" > /tmp/output.tar.lzo
+for output in "$@"; do
+    output_path="/host/build/$output"
+    case "$(basename "$output")" in
+        basic.tar.lzo)
+            # LZO1X-1 with Adler-32 checksums
+            options=""
+            ;;
+        basic.999.tar.lzo)
+            options="-9"
+            ;;
+        basic.crc32.tar.lzo)
+            options="--crc32"
+            ;;
+        *)
+            echo "Unknown lzop fixture: $output" >&2
+            exit 1
+            ;;
+    esac
 
-mkdir -p "$(dirname "/host/build/$OUTPUT_PATH")"
-cp /tmp/output.tar.lzo "/host/build/$OUTPUT_PATH"
+    mkdir -p "$(dirname "$output_path")"
+    lzop -c $options < "$INPUT" > "$output_path"
+    echo "Built: $output"
+done
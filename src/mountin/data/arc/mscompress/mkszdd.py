@@ -5,16 +5,41 @@ import sys
 
 MAGIC = b"SZDD\x88\xf0\x27\x33"
 RING_SIZE = 4096
+# COMPRESS.EXE starts writing 16 bytes before the end of the ring
+RING_START = RING_SIZE - 16
 MAX_MATCH = 18
 MIN_MATCH = 3
 SPACE = 0x20
+MAX_CANDIDATES = 64
 
 
 def compress_szdd(data):
     """LZSS compress data in SZDD format."""
-    # Ring buffer initialized to spaces
+    # Ring buffer initialized to spaces, mirrored exactly as EXPAND fills it
     ring = bytearray([SPACE] * RING_SIZE)
-    ring_pos = RING_SIZE - MAX_MATCH
+    ring_pos = RING_START
+    # Ring positions where each 3-byte sequence was written, newest last
+    recent = {}
+
+    def put(byte):
+        nonlocal ring_pos
+        ring[ring_pos] = byte
+        start = (ring_pos - 2) % RING_SIZE
+        key = bytes(ring[(start + i) % RING_SIZE] for i in range(3))
+        recent.setdefault(key, []).append(start)
+        ring_pos = (ring_pos + 1) % RING_SIZE
+
+    def match_length(off, src):
+        # Matches may run into the bytes they are writing
+        length = 0
+        while length < MAX_MATCH and src + length < len(data):
+            at = (off + length) % RING_SIZE
+            written = (at - ring_pos) % RING_SIZE
+            byte = data[src + written] if written < length else ring[at]
+            if byte != data[src + length]:
+                break
+            length += 1
+        return length
 
     out = bytearray()
     src = 0
@@ -26,37 +51,32 @@ def compress_szdd(data):
         bit = 0
 
         while bit < 8 and src < len(data):
-            # Search for longest match in ring buffer
             best_len = 0
             best_off = 0
 
-            for off in range(RING_SIZE):
-                match_len = 0
-                while (match_len < MAX_MATCH
-                       and src + match_len < len(data)
-                       and ring[(off + match_len) % RING_SIZE] == data[src + match_len]):
-                    match_len += 1
+            key = data[src:src + 3]
+            candidates = recent.get(key, [])[-MAX_CANDIDATES:]
+            if key == bytes([SPACE] * 3):
+                candidates = candidates + [0]
+            for off in reversed(candidates):
+                match_len = match_length(off, src)
                 if match_len > best_len:
                     best_len = match_len
                     best_off = off
 
             if best_len >= MIN_MATCH:
-                # Back-reference: low byte = offset_low, high byte = (offset_high << 4) | (length - 3)
-                lo = best_off & 0xFF
-                hi = ((best_off >> 8) & 0x0F) | ((best_len - MIN_MATCH) << 4)
-                items.append(lo)
-                items.append(hi)
-                # Advance ring buffer
+                # Low byte of the position, then its high nibble and the
+                # length less 3
+                items.append(best_off & 0xFF)
+                items.append(((best_off >> 4) & 0xF0) | (best_len - MIN_MATCH))
                 for i in range(best_len):
-                    ring[ring_pos % RING_SIZE] = data[src + i]
-                    ring_pos += 1
+                    put(data[src + i])
                 src += best_len
             else:
                 # Literal byte
                 flag |= (1 << bit)
                 items.append(data[src])
-                ring[ring_pos % RING_SIZE] = data[src]
-                ring_pos += 1
+                put(data[src])
                 src += 1
 
             bit += 1
@@ -0,0 +1,65 @@
+#!/bin/sh
+set -eu
+
+template=/host/build/data/templates/basic.tar
+work=/tmp/multistream
+
+rm -rf "$work"
+mkdir -p "$work"
+# Split the tar so cat-style outputs get one member per piece
+split -n 3 "$template" "$work/part."
+
+for output in "$@"; do
+    output_path="/host/build/$output"
+    mkdir -p "$(dirname "$output_path")"
+    rm -f "$output_path"
+
+    case "$(basename "$output")" in
+        basic.pigz.tar.gz)
+            # Small, independently compressed blocks (-i)
+            pigz -c -b 32 -i "$template" > "$output_path"
+            ;;
+        basic.cat.tar.gz)
+            for part in "$work"/part.*; do
+                gzip -c "$part"
+            done > "$output_path"
+            ;;
+        basic.pbzip2.tar.bz2)
+            pbzip2 -c -b1 "$template" > "$output_path"
+            ;;
+        basic.lbzip2.tar.bz2)
+            lbzip2 -c -1 "$template" > "$output_path"
+            ;;
+        basic.pixz.tar.xz)
+            pixz -f 0.25 < "$template" > "$output_path"
+            ;;
+        basic.legacy.tar.lz4)
+            for part in "$work"/part.*; do
+                lz4 -q -l -c "$part"
+            done > "$output_path"
+            ;;
+        basic.skippable.tar.lz4)
+            {
+                lz4 -q -c "$work/part.aa"
+                # Skippable frame 0x184D2A53 carrying eight bytes
+                printf '\123\052\115\030\010\000\000\000metadata'
+                lz4 -q -l -c "$work/part.ab"
+                lz4 -q -BD -c "$work/part.ac"
+            } > "$output_path"
+            ;;
+        basic.cat.tar.zst)
+            {
+                zstd -q -c "$work/part.aa"
+                # Skippable frame 0x184D2A50 carrying eight bytes
+                printf '\120\052\115\030\010\000\000\000metadata'
+                zstd -q -c "$work/part.ab" "$work/part.ac"
+            } > "$output_path"
+            ;;
+        *)
+            echo "Unknown multi-stream fixture: $output" >&2
+            exit 1
+            ;;
+    esac
+
+    echo "Built: $output"
+done
@@ -1,143 +1,173 @@
-"""Create a minimal OLE2 Compound Document from files."""
+"""Create an OLE2 Compound Document from the files in a tar archive.
+
+Directories become storages and files become streams. Streams smaller
+than the 4096-byte cutoff go in the mini stream, as the format requires;
+larger ones get regular 512-byte sectors.
+"""
 
 import struct
 import sys
+import tarfile
+
+SECTOR_SIZE = 512
+MINI_SECTOR_SIZE = 64
+MINI_CUTOFF = 4096
+DIR_ENTRY_SIZE = 128
+HEADER_DIFAT = 109
+
+FREESECT = 0xFFFFFFFF
+ENDOFCHAIN = 0xFFFFFFFE
+FATSECT = 0xFFFFFFFD
+NOSTREAM = 0xFFFFFFFF
+
+TYPE_STORAGE = 1
+TYPE_STREAM = 2
+TYPE_ROOT = 5
+
+
+class Node:
+    def __init__(self, name, kind, data=b""):
+        self.name = name[:31]
+        self.kind = kind
+        self.data = data
+        self.children = {}
+        self.sid = 0
+        self.left = self.right = self.child = NOSTREAM
+        self.start = ENDOFCHAIN
+
+
+def sort_key(node):
+    """Siblings are ordered by name length, then by upper-cased name."""
+    name = node.name.encode("utf-16-le")
+    return (len(name), node.name.upper())
+
+
+def build_tree(nodes):
+    """Link sorted siblings into a balanced tree; return the root's SID."""
+    if not nodes:
+        return NOSTREAM
+    mid = len(nodes) // 2
+    node = nodes[mid]
+    node.left = build_tree(nodes[:mid])
+    node.right = build_tree(nodes[mid + 1 :])
+    return node.sid
+
+
+def chain(fat, first, count):
+    for i in range(count):
+        fat[first + i] = first + i + 1 if i < count - 1 else ENDOFCHAIN
 
 
 def write_ole(output_path, files):
-    """Write a minimal OLE2 file containing the given files as streams.
-
-    This creates the simplest valid OLE2 structure:
-    - Header (512 bytes)
-    - FAT sector
-    - Directory sectors
-    - Data sectors
-    """
-    SECTOR_SIZE = 512
-    DIR_ENTRY_SIZE = 128
-
-    # Collect stream data
-    streams = []
+    root = Node("Root Entry", TYPE_ROOT)
     for path, data in files:
-        name = path.split("/")[-1][:31]  # OLE names max 32 chars
-        streams.append((name, data))
-
-    # Calculate layout
-    # Sector 0: FAT
-    # Sector 1+: Directory (1 sector = 4 entries)
-    # Remaining: Data sectors
-    n_dir_entries = 1 + len(streams)  # root + streams
-    n_dir_sectors = (n_dir_entries * DIR_ENTRY_SIZE + SECTOR_SIZE - 1) // SECTOR_SIZE
-
-    # Assign data sectors
-    data_start = 1 + n_dir_sectors  # after FAT + directory
-    sector_map = []
-    for name, data in streams:
-        n_sectors = (len(data) + SECTOR_SIZE - 1) // SECTOR_SIZE if data else 0
-        sector_map.append((data_start, n_sectors))
-        data_start += n_sectors
-
-    total_sectors = data_start
-    out = bytearray()
-
-    # === Header (512 bytes) ===
+        parts = [p for p in path.split("/") if p not in ("", ".")]
+        parent = root
+        for part in parts[:-1]:
+            parent = parent.children.setdefault(part, Node(part, TYPE_STORAGE))
+        parent.children[parts[-1]] = Node(parts[-1], TYPE_STREAM, data)
+
+    # Number the entries, root first
+    entries = []
+
+    def number(node):
+        node.sid = len(entries)
+        entries.append(node)
+        for child in node.children.values():
+            number(child)
+
+    number(root)
+    for node in entries:
+        node.child = build_tree(sorted(node.children.values(), key=sort_key))
+
+    # Small streams share the mini stream, in 64-byte mini sectors
+    mini_stream = bytearray()
+    minifat = []
+    big = []
+    for node in entries:
+        if node.kind != TYPE_STREAM or not node.data:
+            continue
+        if len(node.data) < MINI_CUTOFF:
+            count = -(-len(node.data) // MINI_SECTOR_SIZE)
+            node.start = len(minifat)
+            minifat.extend([0] * count)
+            chain(minifat, node.start, count)
+            mini_stream += node.data.ljust(count * MINI_SECTOR_SIZE, b"\0")
+        else:
+            big.append(node)
+
+    def sectors(length):
+        return -(-length // SECTOR_SIZE)
+
+    # Layout: FAT, directory, MiniFAT, mini stream, then large streams
+    n_dir = sectors(len(entries) * DIR_ENTRY_SIZE)
+    n_minifat = sectors(len(minifat) * 4)
+    n_mini = sectors(len(mini_stream))
+    n_data = n_dir + n_minifat + n_mini + sum(sectors(len(n.data)) for n in big)
+    n_fat = 1
+    while n_fat * SECTOR_SIZE // 4 < n_fat + n_data:
+        n_fat += 1
+    if n_fat > HEADER_DIFAT:
+        raise ValueError("too much data for a header-only DIFAT")
+
+    fat = [FREESECT] * (n_fat * SECTOR_SIZE // 4)
+    fat[:n_fat] = [FATSECT] * n_fat
+    next_sector = n_fat
+
+    def allocate(count):
+        nonlocal next_sector
+        if count == 0:
+            return ENDOFCHAIN
+        first = next_sector
+        chain(fat, first, count)
+        next_sector += count
+        return first
+
+    dir_start = allocate(n_dir)
+    minifat_start = allocate(n_minifat)
+    root.start = allocate(n_mini)
+    for node in big:
+        node.start = allocate(sectors(len(node.data)))
+
     header = bytearray(SECTOR_SIZE)
-    # Magic
     header[0:8] = b"\xd0\xcf\x11\xe0\xa1\xb1\x1a\xe1"
-    # Minor version, major version
-    struct.pack_into("<HH", header, 0x18, 0x003E, 0x0003)
-    # Byte order (little-endian)
-    struct.pack_into("<H", header, 0x1C, 0xFFFE)
-    # Sector size power (9 = 512)
-    struct.pack_into("<H", header, 0x1E, 9)
-    # Mini sector size power (6 = 64)
-    struct.pack_into("<H", header, 0x20, 6)
-    # Total FAT sectors
-    struct.pack_into("<I", header, 0x2C, 1)
-    # First directory sector
-    struct.pack_into("<I", header, 0x30, 1)
-    # Mini stream cutoff (4096)
-    struct.pack_into("<I", header, 0x38, 0x1000)
-    # First mini FAT sector (none)
-    struct.pack_into("<I", header, 0x3C, 0xFFFFFFFE)
-    # Mini FAT sector count
-    struct.pack_into("<I", header, 0x40, 0)
-    # First DIFAT sector (none)
-    struct.pack_into("<I", header, 0x44, 0xFFFFFFFE)
-    # DIFAT sector count
-    struct.pack_into("<I", header, 0x48, 0)
-    # DIFAT array (first entry = sector 0 for FAT)
-    struct.pack_into("<I", header, 0x4C, 0)
-    # Rest of DIFAT = free
-    for i in range(1, 109):
-        struct.pack_into("<I", header, 0x4C + i * 4, 0xFFFFFFFF)
-    out.extend(header)
-
-    # === Sector 0: FAT ===
-    fat = bytearray(SECTOR_SIZE)
-    # Sector 0 = FAT sector marker
-    struct.pack_into("<I", fat, 0, 0xFFFFFFFD)
-    # Directory sectors chain
-    for i in range(n_dir_sectors):
-        sid = 1 + i
-        next_sid = sid + 1 if i < n_dir_sectors - 1 else 0xFFFFFFFE
-        struct.pack_into("<I", fat, sid * 4, next_sid)
-    # Data sector chains
-    for start, count in sector_map:
-        for j in range(count):
-            sid = start + j
-            next_sid = sid + 1 if j < count - 1 else 0xFFFFFFFE
-            struct.pack_into("<I", fat, sid * 4, next_sid)
-    # Fill rest as free
-    for i in range(total_sectors, SECTOR_SIZE // 4):
-        struct.pack_into("<I", fat, i * 4, 0xFFFFFFFF)
-    out.extend(fat)
-
-    # === Directory sectors ===
-    dir_data = bytearray(n_dir_sectors * SECTOR_SIZE)
-
-    def write_dir_entry(idx, name, entry_type, start_sector, size, child=-1):
-        off = idx * DIR_ENTRY_SIZE
-        encoded = name.encode("utf-16-le") + b"\x00\x00"
-        dir_data[off : off + len(encoded)] = encoded
-        struct.pack_into("<H", dir_data, off + 0x40, len(encoded))
-        dir_data[off + 0x42] = entry_type  # 5=root, 2=stream
-        dir_data[off + 0x43] = 1  # black node
-        # Left/right/child SIDs
-        struct.pack_into("<I", dir_data, off + 0x44, 0xFFFFFFFF)  # left
-        struct.pack_into("<I", dir_data, off + 0x48, 0xFFFFFFFF)  # right
-        struct.pack_into("<I", dir_data, off + 0x4C, child if child >= 0 else 0xFFFFFFFF)
-        struct.pack_into("<I", dir_data, off + 0x74, start_sector)
-        struct.pack_into("<I", dir_data, off + 0x78, size)
-
-    # Root entry - child points to first stream
-    child_sid = 1 if streams else -1
-    write_dir_entry(0, "Root Entry", 5, 0xFFFFFFFE, 0, child=child_sid)
-
-    # Stream entries as a simple binary tree (left-heavy)
-    for i, (name, data) in enumerate(streams):
-        start, count = sector_map[i]
-        left = i + 2 if i + 2 <= len(streams) else -1
-        write_dir_entry(i + 1, name, 2, start, len(data))
-        if left > 0 and left <= len(streams):
-            struct.pack_into("<I", dir_data, (i + 1) * DIR_ENTRY_SIZE + 0x44, left)
-
-    out.extend(dir_data)
-
-    # === Data sectors ===
-    for i, (name, data) in enumerate(streams):
-        start, count = sector_map[i]
-        padded = data + b"\x00" * (count * SECTOR_SIZE - len(data))
-        out.extend(padded)
-
+    struct.pack_into("<HHHHH", header, 0x18, 0x3E, 3, 0xFFFE, 9, 6)
+    struct.pack_into("<I", header, 0x2C, n_fat)
+    struct.pack_into("<I", header, 0x30, dir_start)
+    struct.pack_into("<I", header, 0x38, MINI_CUTOFF)
+    struct.pack_into("<II", header, 0x3C, minifat_start, n_minifat)
+    struct.pack_into("<II", header, 0x44, ENDOFCHAIN, 0)
+    difat = list(range(n_fat)) + [FREESECT] * (HEADER_DIFAT - n_fat)
+    struct.pack_into(f"<{HEADER_DIFAT}I", header, 0x4C, *difat)
+
+    directory = bytearray(n_dir * SECTOR_SIZE)
+    for node in entries:
+        off = node.sid * DIR_ENTRY_SIZE
+        name = node.name.encode("utf-16-le") + b"\0\0"
+        directory[off : off + len(name)] = name
+        size = len(mini_stream) if node.kind == TYPE_ROOT else len(node.data)
+        start = node.start if node.kind != TYPE_STORAGE else 0
+        struct.pack_into("<HBB", directory, off + 0x40, len(name), node.kind, 1)
+        struct.pack_into("<III", directory, off + 0x44, node.left, node.right, node.child)
+        struct.pack_into("<II", directory, off + 0x74, start, size)
+    for sid in range(len(entries), n_dir * SECTOR_SIZE // DIR_ENTRY_SIZE):
+        struct.pack_into("<III", directory, sid * DIR_ENTRY_SIZE + 0x44, NOSTREAM, NOSTREAM, NOSTREAM)
+
+    def padded(data, count):
+        return bytes(data).ljust(count * SECTOR_SIZE, b"\0")
+
+    minifat += [FREESECT] * (n_minifat * SECTOR_SIZE // 4 - len(minifat))
     with open(output_path, "wb") as f:
-        f.write(out)
+        f.write(header)
+        f.write(struct.pack(f"<{len(fat)}I", *fat))
+        f.write(directory)
+        f.write(struct.pack(f"<{len(minifat)}I", *minifat))
+        f.write(padded(mini_stream, n_mini))
+        for node in big:
+            f.write(padded(node.data, sectors(len(node.data))))
 
 
 if __name__ == "__main__":
-    import os
-    import tarfile
-
     output = sys.argv[1]
     tar_path = sys.argv[2]
 
@@ -2,6 +2,7 @@
 #define MOUNTIN_H
 
 #include <stdbool.h>
+#include <stddef.h>
 #include <stdint.h>
 
 #ifdef __cplusplus
@@ -24,6 +25,57 @@ typedef void (*mountin_detect_tree_callback)(
     void* userdata
 );
 
+/**
+ * Callback type for mountin_detect_tree_metadata.
+ * Same as mountin_detect_tree_callback, plus container-reported metadata
+ * (archive member names, track modes, etc.) as parallel key/value arrays.
+ * The strings are only valid for the duration of the call.
+ *
+ * @param keys Metadata keys (count entries)
+ * @param values Metadata values (count entries)
+ * @param count Number of metadata pairs
+ */
+typedef void (*mountin_detect_tree_metadata_callback)(
+    const char* format,
+    uint32_t index,
+    uint32_t depth,
+    const char* const* keys,
+    const char* const* values,
+    uint32_t count,
+    void* userdata
+);
+
+/**
+ * Reader callbacks for data that isn't in a file, e.g. in a WASM host.
+ * Each callback gets the handle of the file it applies to. Callbacks are
+ * only made from the thread that called into the library.
+ */
+typedef struct mountin_reader_callbacks {
+    /**
+     * Read up to len bytes at offset into buf.
+     * @return Bytes read, 0 at the end of the file, negative on error
+     */
+    int64_t (*read)(void* handle, uint64_t offset, uint8_t* buf, size_t len);
+
+    /**
+     * @return File size in bytes, or UINT64_MAX if unknown
+     */
+    uint64_t (*size)(void* handle);
+
+    /**
+     * Open a file named relative to this one (split image parts, parent
+     * disks, CUE data files). Names may contain '/' and "../" but are
+     * never absolute. May be NULL if no other files can be opened.
+     * @return Handle for the file, or NULL if it can't be opened
+     */
+    void* (*sibling)(void* handle, const char* name);
+
+    /**
+     * Release a handle returned by sibling. May be NULL.
+     */
+    void (*close)(void* handle);
+} mountin_reader_callbacks;
+
 /**
  * Load a compiled mountin format catalogue.
  * The first successfully loaded catalogue remains active for the process.
@@ -33,10 +85,44 @@ typedef void (*mountin_detect_tree_callback)(
  */
 bool mountin_load_catalogue(const char* path);
 
+/**
+ * Allow containers to write seek indexes beside the files they read.
+ * Off by default. When enabled, e.g. random access into disk.img.gz keeps
+ * a disk.img.gz.zran checkpoint index so later opens skip the full
+ * decompression pass. Indexes that are stale or unreadable are rebuilt.
+ *
+ * @param enabled true to read and write index files
+ */
+void mountin_set_persist_indexes(bool enabled);
+
+/**
+ * Check images against the hashes stored in them when they are opened.
+ * Off by default, since it reads and decompresses the whole image. The
+ * result is reported as node metadata: md5_verified and sha1_verified are
+ * "true" or "false" for an EWF image storing those hashes.
+ *
+ * @param enabled true to verify stored hashes
+ */
+void mountin_set_verify_hashes(bool enabled);
+
+/**
+ * Let images name sibling files outside their own directory, such as a
+ * parent disk given as "../base.vmdk". Off by default, since those names
+ * come from the images: a crafted one could otherwise read any file the
+ * caller can. Symbolic links leading out of the directory are refused
+ * too. Readers supplied through callbacks decide for themselves.
+ *
+ * @param enabled true to follow sibling names outside the directory
+ */
+void mountin_set_follow_parent_dirs(bool enabled);
+
 /**
  * Detect format tree from file path.
  * Recursively detects formats in containers (gzip, tar, partition tables, etc.)
  * Calls the callback for each detected format with its position in the tree.
+ * Images stored as several files (disk.001/disk.002, EWF segments, split
+ * VMDK extents) are joined when given the first file; the others are
+ * opened from the same directory.
  * mountin_load_catalogue() must succeed before this function is called.
  *
  * @param path Path to file to detect (UTF-8 encoded)
@@ -49,6 +135,63 @@ void mountin_detect_tree(
     void* userdata
 );
 
+/**
+ * Detect format tree from file path, including per-node metadata.
+ * Same traversal as mountin_detect_tree.
+ *
+ * @param path Path to file to detect (UTF-8 encoded)
+ * @param callback Function called for each detected format
+ * @param userdata Passed through to callback
+ */
+void mountin_detect_tree_metadata(
+    const char* path,
+    mountin_detect_tree_metadata_callback callback,
+    void* userdata
+);
+
+/**
+ * Detect format tree from file path, descending only into archive members
+ * that match a filter. Members that don't match are left out of the tree
+ * and their data is never read or decompressed. Only named members are
+ * filtered; unnamed children such as partitions and decompressed streams
+ * are always followed, as are members whose size the archive doesn't list.
+ *
+ * @param path Path to file to detect (UTF-8 encoded)
+ * @param name_pattern Regex the member path must match, or NULL for any
+ * @param min_size Smallest member size to follow, 0 for no limit
+ * @param max_size Largest member size to follow, UINT64_MAX for no limit
+ * @param callback Function called for each detected format
+ * @param userdata Passed through to callback
+ * @return false if path is NULL or name_pattern is not a valid regex
+ */
+bool mountin_detect_tree_filtered(
+    const char* path,
+    const char* name_pattern,
+    uint64_t min_size,
+    uint64_t max_size,
+    mountin_detect_tree_metadata_callback callback,
+    void* userdata
+);
+
+/**
+ * Detect format tree of data read through callbacks instead of from a
+ * path, including per-node metadata. Same traversal as
+ * mountin_detect_tree_metadata; other files of a split image are opened
+ * through the sibling callback.
+ *
+ * @param callbacks How to read the data
+ * @param handle Passed to the callbacks for the first file; not closed
+ * @param callback Function called for each detected format
+ * @param userdata Passed through to callback
+ * @return false if callbacks is NULL
+ */
+bool mountin_detect_tree_callbacks(
+    const mountin_reader_callbacks* callbacks,
+    void* handle,
+    mountin_detect_tree_metadata_callback callback,
+    void* userdata
+);
+
 /**
  * Get library version string.
  * Returned string is static - do not free.
@@ -12,6 +12,7 @@ pub fn get(name: &str) -> Option<ChecksumFn> {
         "adfs" => Some(adfs),
         "atari_boot" => Some(atari_boot),
         "ics" => Some(ics),
+        "macbinary" => Some(macbinary),
         "powertec" => Some(powertec),
         _ => None,
     }
@@ -62,6 +63,14 @@ pub fn ics(data: &[u8]) -> bool {
     sum == stored
 }
 
+/// MacBinary II/III header checksum
+///
+/// crc16_xmodem(bytes[0..124]) == be16(bytes[124..126]), plus the fixed
+/// zero bytes and name length of every MacBinary header.
+pub fn macbinary(data: &[u8]) -> bool {
+    crate::container::arc::macbinary::valid_header(data)
+}
+
 /// PowerTec checksum
 ///
 /// sum(bytes[0..510]) + 0x2a == byte[511]
@@ -101,6 +110,7 @@ mod tests {
         assert!(get("adfs").is_some());
         assert!(get("atari_boot").is_some());
         assert!(get("ics").is_some());
+        assert!(get("macbinary").is_some());
         assert!(get("powertec").is_some());
         assert!(get("unknown").is_none());
     }
@@ -0,0 +1,166 @@
+//! AppleSingle and AppleDouble container reader
+//!
+//! Both start with a magic number, a version, 16 filler bytes and a table
+//! of (id, offset, length) entries. AppleSingle carries the whole file;
+//! AppleDouble is the header file (often `._name`) that carries only the
+//! resource fork and Finder info, beside a plain data file. Entries other
+//! than the forks, the real name and the Finder info are ignored.
+
+use super::mac::{file_name, Fork, MacFile};
+use crate::container::{be32, invalid_data, read_exact_at, slice::SliceReader, Child, Container};
+use crate::detect::Reader;
+use std::io;
+use std::sync::Arc;
+
+const MAGIC_SINGLE: u32 = 0x0005_1600;
+const MAGIC_DOUBLE: u32 = 0x0005_1607;
+const HEADER_SIZE: usize = 26;
+const ENTRY_SIZE: usize = 12;
+
+const ENTRY_DATA: u32 = 1;
+const ENTRY_RESOURCE: u32 = 2;
+const ENTRY_NAME: u32 = 3;
+const ENTRY_FINDER_INFO: u32 = 9;
+
+/// AppleSingle/AppleDouble container - exposes the forks it carries
+pub struct AppleSingleContainer;
+
+/// Static instance for registry
+pub static APPLESINGLE: AppleSingleContainer = AppleSingleContainer;
+
+impl Container for AppleSingleContainer {
+    fn children(&self, reader: Arc<dyn Reader + Send + Sync>) -> io::Result<Vec<Child>> {
+        let mut header = [0u8; HEADER_SIZE];
+        read_exact_at(&*reader, 0, &mut header)?;
+        let magic = be32(&header);
+        if magic != MAGIC_SINGLE && magic != MAGIC_DOUBLE {
+            return Err(invalid_data("invalid AppleSingle magic"));
+        }
+
+        let count = usize::from(u16::from_be_bytes([header[24], header[25]]));
+        let mut table = vec![0u8; count * ENTRY_SIZE];
+        read_exact_at(&*reader, HEADER_SIZE as u64, &mut table)?;
+        let len = reader.size().unwrap_or(u64::MAX);
+
+        let mut file = MacFile::default();
+        let mut forks = Vec::new();
+        for entry in table.chunks_exact(ENTRY_SIZE) {
+            let id = be32(entry);
+            let offset = u64::from(be32(&entry[4..]));
+            let length = be32(&entry[8..]);
+            if offset + u64::from(length) > len {
+                return Err(invalid_data("AppleSingle entry extends past end of file"));
+            }
+            match id {
+                ENTRY_DATA => forks.push((Fork::Data, offset, length)),
+                ENTRY_RESOURCE => forks.push((Fork::Resource, offset, length)),
+                ENTRY_NAME => {
+                    let mut name = vec![0u8; length as usize];
+                    read_exact_at(&*reader, offset, &mut name)?;
+                    file.name = file_name(&name);
+                }
+                ENTRY_FINDER_INFO if length >= 8 => {
+                    let mut info = [0u8; 8];
+                    read_exact_at(&*reader, offset, &mut info)?;
+                    file.file_type = info[..4].try_into().unwrap();
+                    file.creator = info[4..].try_into().unwrap();
+                }
+                _ => {}
+            }
+        }
+
+        if file.name.is_empty() {
+            if let Some(name) = reader.path().and_then(|p| p.file_name()) {
+                let name = name.to_string_lossy();
+                file.name = name.strip_prefix("._").unwrap_or(&name).to_string();
+            }
+        }
+
+        // An AppleDouble header with no resource fork still names the file,
+        // but an empty fork is not worth a child
+        let mut children = Vec::new();
+        for (fork, offset, length) in forks {
+            if length == 0 && fork == Fork::Resource {
+                continue;
+            }
+            children.push(file.fork_child(
+                children.len() as u32,
+                offset,
+                Arc::new(SliceReader::new(
+                    Arc::clone(&reader),
+                    offset,
+                    u64::from(length),
+                )),
+                fork,
+            ));
+        }
+        Ok(children)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::BytesReader;
+
+    fn applesingle(magic: u32, entries: &[(u32, &[u8])]) -> Vec<u8> {
+        let mut out = magic.to_be_bytes().to_vec();
+        out.extend_from_slice(&0x0002_0000u32.to_be_bytes());
+        out.extend_from_slice(&[0; 16]);
+        out.extend_from_slice(&(entries.len() as u16).to_be_bytes());
+        let mut offset = HEADER_SIZE + entries.len() * ENTRY_SIZE;
+        for (id, data) in entries {
+            out.extend_from_slice(&id.to_be_bytes());
+            out.extend_from_slice(&(offset as u32).to_be_bytes());
+            out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            offset += data.len();
+        }
+        for (_, data) in entries {
+            out.extend_from_slice(data);
+        }
+        out
+    }
+
+    #[test]
+    fn reads_forks_name_and_finder_info() {
+        let mut info = b"dImgdCpy".to_vec();
+        info.resize(32, 0);
+        let file = applesingle(
+            MAGIC_SINGLE,
+            &[
+                (ENTRY_NAME, b"System 7"),
+                (ENTRY_FINDER_INFO, &info),
+                (ENTRY_RESOURCE, b"rsrc"),
+                (ENTRY_DATA, b"data fork"),
+            ],
+        );
+        let children = APPLESINGLE
+            .children(Arc::new(BytesReader::new(file)))
+            .unwrap();
+        assert_eq!(children.len(), 2);
+        assert_eq!(
+            children[1].metadata,
+            [
+                ("name", "System 7".to_string()),
+                ("fork", "data".to_string()),
+                ("type", "dImg".to_string()),
+                ("creator", "dCpy".to_string()),
+            ]
+        );
+        let mut buf = [0u8; 9];
+        children[1].reader.read_at(0, &mut buf).unwrap();
+        assert_eq!(&buf, b"data fork");
+    }
+
+    #[test]
+    fn skips_empty_resource_fork() {
+        let file = applesingle(MAGIC_DOUBLE, &[(ENTRY_RESOURCE, b"")]);
+        let children = APPLESINGLE
+            .children(Arc::new(BytesReader::new(file)))
+            .unwrap();
+        assert!(children.is_empty());
+        assert!(APPLESINGLE
+            .children(Arc::new(BytesReader::new(vec![0; 32])))
+            .is_err());
+    }
+}
@@ -0,0 +1,275 @@
+//! ar archive container reader
+//!
+//! Unix ar archives (static libraries, Debian packages) store members
+//! uncompressed after 60-byte headers, so each member is a slice of the
+//! archive. Handles System V/GNU `//` long-name tables and BSD `#1/` names.
+
+use crate::container::{invalid_data, slice::SliceReader, Child, Container, Entry};
+use crate::detect::Reader;
+use std::io;
+use std::sync::Arc;
+
+const AR_MAGIC: &[u8; 8] = b"!<arch>\n";
+const HEADER_SIZE: u64 = 60;
+const HEADER_END: &[u8; 2] = b"`\n";
+const MAX_NAME_TABLE: u64 = 16 * 1024 * 1024;
+const MAX_MEMBERS: usize = 1 << 20;
+
+/// ar archive container
+pub struct ArContainer;
+
+/// Static instance for registry
+pub static AR: ArContainer = ArContainer;
+
+/// Parsed member location
+struct Member {
+    name: String,
+    offset: u64,
+    size: u64,
+}
+
+impl Container for ArContainer {
+    fn children(&self, reader: Arc<dyn Reader + Send + Sync>) -> io::Result<Vec<Child>> {
+        self.entries(reader)?.into_iter().map(Entry::open).collect()
+    }
+
+    fn entries(&self, reader: Arc<dyn Reader + Send + Sync>) -> io::Result<Vec<Entry>> {
+        let members = parse_ar(&*reader)?;
+
+        Ok(members
+            .into_iter()
+            .enumerate()
+            .map(|(idx, member)| {
+                let parent = Arc::clone(&reader);
+                let (offset, size) = (member.offset, member.size);
+                Entry::new(
+                    idx as u32,
+                    offset,
+                    Some(size),
+                    vec![("name", member.name)],
+                    move || Ok(Arc::new(SliceReader::new(parent, offset, size)) as _),
+                )
+            })
+            .collect())
+    }
+}
+
+/// Parse a decimal header field, which is space padded
+fn parse_decimal(field: &[u8]) -> io::Result<u64> {
+    let text = std::str::from_utf8(field).map_err(|_| invalid_data("invalid ar header field"))?;
+    let text = text.trim_end_matches(' ');
+    if text.is_empty() {
+        return Ok(0);
+    }
+    text.parse().map_err(|_| invalid_data("invalid ar header field"))
+}
+
+/// Look up a GNU `/offset` name in the `//` table. Entries end with "/\n".
+fn long_name(table: &[u8], offset: usize) -> io::Result<String> {
+    let entry = table
+        .get(offset..)
+        .ok_or_else(|| invalid_data("ar long name offset out of range"))?;
+    let end = entry.iter().position(|&b| b == b'\n').unwrap_or(entry.len());
+    let name = &entry[..end];
+    let name = name.strip_suffix(b"/").unwrap_or(name);
+    Ok(String::from_utf8_lossy(name).into_owned())
+}
+
+/// Walk the archive and return every regular member
+fn parse_ar(reader: &dyn Reader) -> io::Result<Vec<Member>> {
+    let mut magic = [0u8; 8];
+    if reader.read_at(0, &mut magic)? != 8 || &magic != AR_MAGIC {
+        return Err(invalid_data("invalid ar magic"));
+    }
+
+    let mut members = Vec::new();
+    let mut names = Vec::new();
+    let mut pos = AR_MAGIC.len() as u64;
+
+    loop {
+        let mut header = [0u8; HEADER_SIZE as usize];
+        match reader.read_at(pos, &mut header)? {
+            0 => break,
+            n if n < HEADER_SIZE as usize => {
+                // Some writers pad the archive with a trailing newline
+                if header[..n].iter().all(|&b| b == b'\n') {
+                    break;
+                }
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "short ar member header",
+                ));
+            }
+            _ => {}
+        }
+        if &header[58..60] != HEADER_END {
+            return Err(invalid_data("invalid ar member header"));
+        }
+
+        let size = parse_decimal(&header[48..58])?;
+        let data_offset = pos + HEADER_SIZE;
+        let end = data_offset
+            .checked_add(size)
+            .ok_or_else(|| invalid_data("ar member size overflow"))?;
+        if reader.size().is_some_and(|total| end > total) {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "ar member extends past end of archive",
+            ));
+        }
+
+        let raw_name = &header[0..16];
+        let trimmed = {
+            let len = raw_name.iter().rposition(|&b| b != b' ').map_or(0, |i| i + 1);
+            &raw_name[..len]
+        };
+
+        let member = if trimmed == b"//" {
+            // GNU long-name table
+            if size > MAX_NAME_TABLE {
+                return Err(invalid_data("ar long name table too large"));
+            }
+            names = vec![0u8; size as usize];
+            if reader.read_at(data_offset, &mut names)? != names.len() {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "short ar long name table read",
+                ));
+            }
+            None
+        } else if trimmed == b"/" || trimmed == b"/SYM64/" || trimmed.starts_with(b"__.SYMDEF") {
+            // Symbol tables
+            None
+        } else if let Some(length) = trimmed.strip_prefix(b"#1/") {
+            // BSD: name stored at the start of the member data
+            let name_len = parse_decimal(length)?;
+            if name_len > size {
+                return Err(invalid_data("ar BSD name longer than member"));
+            }
+            let mut name = vec![0u8; name_len as usize];
+            if reader.read_at(data_offset, &mut name)? != name.len() {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "short ar BSD name read",
+                ));
+            }
+            let len = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+            if name.starts_with(b"__.SYMDEF") {
+                None
+            } else {
+                Some(Member {
+                    name: String::from_utf8_lossy(&name[..len]).into_owned(),
+                    offset: data_offset + name_len,
+                    size: size - name_len,
+                })
+            }
+        } else if let Some(offset) = trimmed.strip_prefix(b"/") {
+            // GNU: index into the long-name table
+            let offset = parse_decimal(offset)?;
+            Some(Member {
+                name: long_name(&names, offset as usize)?,
+                offset: data_offset,
+                size,
+            })
+        } else {
+            // System V names end in '/', BSD short names are space padded
+            let name = trimmed.strip_suffix(b"/").unwrap_or(trimmed);
+            Some(Member {
+                name: String::from_utf8_lossy(name).into_owned(),
+                offset: data_offset,
+                size,
+            })
+        };
+
+        if let Some(member) = member {
+            if members.len() >= MAX_MEMBERS {
+                return Err(invalid_data("too many ar members"));
+            }
+            members.push(member);
+        }
+
+        // Member data is padded to an even offset
+        pos = end + (end & 1);
+    }
+
+    Ok(members)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::BytesReader;
+
+    fn header(name: &str, size: usize) -> Vec<u8> {
+        let header = format!("{:<16}{:<12}{:<6}{:<6}{:<8}{:<10}`\n", name, 0, 0, 0, 644, size);
+        assert_eq!(header.len(), HEADER_SIZE as usize);
+        header.into_bytes()
+    }
+
+    fn member(data: &mut Vec<u8>, name: &str, contents: &[u8]) {
+        data.extend(header(name, contents.len()));
+        data.extend_from_slice(contents);
+        if data.len() % 2 == 1 {
+            data.push(b'\n');
+        }
+    }
+
+    fn children(data: Vec<u8>) -> Vec<Child> {
+        AR.children(Arc::new(BytesReader::new(data))).unwrap()
+    }
+
+    fn contents(child: &Child) -> Vec<u8> {
+        let mut buf = vec![0u8; child.reader.size().unwrap() as usize];
+        child.reader.read_at(0, &mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn lists_debian_package_members() {
+        let mut data = AR_MAGIC.to_vec();
+        member(&mut data, "debian-binary", b"2.0\n");
+        member(&mut data, "control.tar.xz", b"abc");
+        member(&mut data, "data.tar.xz", b"defg");
+
+        let kids = children(data);
+        assert_eq!(kids.len(), 3);
+        assert_eq!(kids[1].metadata, vec![("name", "control.tar.xz".to_string())]);
+        assert_eq!(contents(&kids[1]), b"abc");
+        assert_eq!(contents(&kids[2]), b"defg");
+    }
+
+    #[test]
+    fn resolves_gnu_long_names() {
+        let table = b"a_rather_long_object_name.o/\nanother_long_object_name.o/\n";
+        let mut data = AR_MAGIC.to_vec();
+        member(&mut data, "/", &[0, 0, 0, 0]);
+        member(&mut data, "//", table);
+        member(&mut data, "/29", b"second");
+        member(&mut data, "short.o/", b"x");
+
+        let kids = children(data);
+        let names: Vec<_> = kids.iter().map(|k| k.metadata[0].1.as_str()).collect();
+        assert_eq!(names, ["another_long_object_name.o", "short.o"]);
+        assert_eq!(contents(&kids[0]), b"second");
+    }
+
+    #[test]
+    fn strips_bsd_inline_names() {
+        let mut data = AR_MAGIC.to_vec();
+        member(&mut data, "#1/20", b"__.SYMDEF SORTED\0\0\0\0");
+        member(&mut data, "#1/24", b"long_bsd_member_name.o\0\0payload");
+
+        let kids = children(data);
+        assert_eq!(kids.len(), 1);
+        assert_eq!(kids[0].metadata[0].1, "long_bsd_member_name.o");
+        assert_eq!(contents(&kids[0]), b"payload");
+    }
+
+    #[test]
+    fn rejects_member_past_end() {
+        let mut data = AR_MAGIC.to_vec();
+        data.extend(header("big", 100));
+        data.extend_from_slice(b"short");
+        assert!(AR.children(Arc::new(BytesReader::new(data))).is_err());
+    }
+}
@@ -0,0 +1,335 @@
+//! ARJ archive reader
+//!
+//! An ARJ archive is a main header followed by one local header per
+//! member. Each header is a basic header (fixed fields, then the NUL
+//! terminated name and comment) with its own CRC-32, then a chain of
+//! extended headers ending in a zero size. A zero basic header size ends
+//! the archive.
+//!
+//! Methods 1-3 are LHA's static Huffman coder with a 26 KiB window and
+//! -lh7-'s distance table; method 4 codes lengths and distances with
+//! unary-prefixed widths and no Huffman stage. Compressed members are
+//! decoded when first read and checked against their CRC-32.
+//!
+//! Encrypted ("garbled") members and files split across volumes are not
+//! supported.
+
+use super::lzh::{decode_static, Bits};
+use crate::container::{
+    invalid_data, le32, read_exact_at, slice::SliceReader, Child, Container, DeferredReader, Entry,
+    MAX_SIZE,
+};
+use crate::detect::Reader;
+use flate2::Crc;
+use std::io;
+use std::sync::Arc;
+
+const MAGIC: [u8; 2] = [0x60, 0xEA];
+/// Largest basic header ARJ writes
+const MAX_BASIC_SIZE: usize = 2600;
+/// Fixed basic header fields, up to the file access mode
+const BASIC_FIXED_SIZE: usize = 26;
+
+const FLAG_GARBLED: u8 = 0x01;
+const FLAG_VOLUME: u8 = 0x04;
+const FLAG_EXTFILE: u8 = 0x08;
+
+const METHOD_STORED: u8 = 0;
+const METHOD_FASTEST: u8 = 4;
+
+const TYPE_BINARY: u8 = 0;
+const TYPE_TEXT: u8 = 1;
+
+const THRESHOLD: usize = 3;
+/// Method 4 length and distance width ranges
+const LEN_WIDTHS: (u32, u32) = (0, 7);
+const PTR_WIDTHS: (u32, u32) = (9, 13);
+
+/// ARJ container - exposes each file as a child
+pub struct ArjContainer;
+
+/// Static instance for registry
+pub static ARJ: ArjContainer = ArjContainer;
+
+impl Container for ArjContainer {
+    fn children(&self, reader: Arc<dyn Reader + Send + Sync>) -> io::Result<Vec<Child>> {
+        self.entries(reader)?.into_iter().map(Entry::open).collect()
+    }
+
+    fn entries(&self, reader: Arc<dyn Reader + Send + Sync>) -> io::Result<Vec<Entry>> {
+        let members = parse(&*reader)?;
+        let mut entries = Vec::new();
+        for (index, member) in members.into_iter().enumerate() {
+            let parent = Arc::clone(&reader);
+            let (offset, size) = (member.offset, u64::from(member.size));
+            let metadata = vec![("name", member.name.clone())];
+            entries.push(Entry::new(
+                index as u32,
+                offset,
+                Some(size),
+                metadata,
+                move || {
+                    let child: Arc<dyn Reader + Send + Sync> =
+                        if member.method == METHOD_STORED && member.flags & FLAG_GARBLED == 0 {
+                            let packed = u64::from(member.packed);
+                            Arc::new(SliceReader::new(parent, offset, packed))
+                        } else {
+                            Arc::new(DeferredReader::new(size, move || decode(&*parent, &member)))
+                        };
+                    Ok(child)
+                },
+            ));
+        }
+        Ok(entries)
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Member {
+    name: String,
+    flags: u8,
+    method: u8,
+    offset: u64,
+    packed: u32,
+    size: u32,
+    crc: u32,
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = Crc::new();
+    crc.update(data);
+    crc.sum()
+}
+
+/// Read the header at `offset`: its basic header, or None at the end of
+/// the archive, and the offset just past its extended headers
+fn read_header(reader: &dyn Reader, offset: u64) -> io::Result<(Option<Vec<u8>>, u64)> {
+    let mut id = [0u8; 4];
+    read_exact_at(reader, offset, &mut id)?;
+    if id[..2] != MAGIC {
+        return Err(invalid_data("missing ARJ header id"));
+    }
+    let size = usize::from(u16::from_le_bytes([id[2], id[3]]));
+    if size == 0 {
+        return Ok((None, offset + 4));
+    }
+    if !(BASIC_FIXED_SIZE..=MAX_BASIC_SIZE).contains(&size) {
+        return Err(invalid_data("invalid ARJ header size"));
+    }
+
+    let mut basic = vec![0u8; size + 4];
+    read_exact_at(reader, offset + 4, &mut basic)?;
+    let crc = le32(&basic[size..]);
+    basic.truncate(size);
+    if crc32(&basic) != crc {
+        return Err(invalid_data("ARJ header CRC mismatch"));
+    }
+
+    // Extended headers: a size, the data and its CRC-32, until size 0
+    let mut next = offset + 4 + size as u64 + 4;
+    loop {
+        let mut ext_size = [0u8; 2];
+        read_exact_at(reader, next, &mut ext_size)?;
+        let ext_size = u64::from(u16::from_le_bytes(ext_size));
+        next += 2;
+        if ext_size == 0 {
+            break;
+        }
+        next += ext_size + 4;
+    }
+    Ok((Some(basic), next))
+}
+
+/// A NUL-terminated string in the basic header
+fn c_string(bytes: &[u8]) -> String {
+    let bytes = &bytes[..bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len())];
+    String::from_utf8_lossy(bytes).replace('\\', "/")
+}
+
+fn parse(reader: &dyn Reader) -> io::Result<Vec<Member>> {
+    let len = reader.size().unwrap_or(u64::MAX);
+    let (main, mut offset) = read_header(reader, 0)?;
+    if main.is_none() {
+        return Err(invalid_data("missing ARJ main header"));
+    }
+
+    let mut members = Vec::new();
+    while let (Some(basic), data) = read_header(reader, offset)? {
+        let first_size = usize::from(basic[0]);
+        if first_size < BASIC_FIXED_SIZE || first_size > basic.len() {
+            return Err(invalid_data("invalid ARJ header size"));
+        }
+        let flags = basic[4];
+        let packed = le32(&basic[12..]);
+        if data + u64::from(packed) > len {
+            return Err(invalid_data("ARJ member extends past end of archive"));
+        }
+
+        // Directories, volume labels and pieces of split files have no
+        // complete contents here
+        let file_type = basic[6];
+        if matches!(file_type, TYPE_BINARY | TYPE_TEXT) && flags & (FLAG_VOLUME | FLAG_EXTFILE) == 0
+        {
+            members.push(Member {
+                name: c_string(&basic[first_size..]),
+                flags,
+                method: basic[5],
+                offset: data,
+                packed,
+                size: le32(&basic[16..]),
+                crc: le32(&basic[20..]),
+            });
+        }
+        offset = data + u64::from(packed);
+    }
+    Ok(members)
+}
+
+fn decode(reader: &dyn Reader, member: &Member) -> io::Result<Vec<u8>> {
+    if member.flags & FLAG_GARBLED != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "encrypted ARJ members are not supported",
+        ));
+    }
+    let size = member.size as usize;
+    if member.packed as usize > MAX_SIZE || size > MAX_SIZE {
+        return Err(invalid_data("ARJ member too large"));
+    }
+    let mut data = vec![0u8; member.packed as usize];
+    read_exact_at(reader, member.offset, &mut data)?;
+
+    let out = match member.method {
+        1..=3 => decode_static(&data, size, 17, 5)?,
+        METHOD_FASTEST => decode_fastest(&data, size),
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "unsupported ARJ compression method",
+            ))
+        }
+    };
+    if crc32(&out) != member.crc {
+        return Err(invalid_data("ARJ member CRC mismatch"));
+    }
+    Ok(out)
+}
+
+/// A value coded as a unary prefix choosing the width, then that many
+/// bits, over widths `low` to `high`
+fn read_prefixed(bits: &mut Bits, (low, high): (u32, u32)) -> usize {
+    let mut plus = 0usize;
+    let mut width = low;
+    while width < high && bits.read(1) == 1 {
+        plus += 1 << width;
+        width += 1;
+    }
+    plus + bits.read(width) as usize
+}
+
+/// Decode method 4: a length (0 for a literal byte), then a distance
+fn decode_fastest(data: &[u8], size: usize) -> Vec<u8> {
+    let mut bits = Bits::new(data);
+    let mut out = Vec::with_capacity(size);
+    while out.len() < size {
+        let len = read_prefixed(&mut bits, LEN_WIDTHS);
+        if len == 0 {
+            out.push(bits.read(8) as u8);
+            continue;
+        }
+        let len = (len - 1 + THRESHOLD).min(size - out.len());
+        let distance = read_prefixed(&mut bits, PTR_WIDTHS) + 1;
+        for _ in 0..len {
+            let byte = match out.len().checked_sub(distance) {
+                Some(at) => out[at],
+                None => 0,
+            };
+            out.push(byte);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::BytesReader;
+
+    fn header(file_type: u8, method: u8, name: &str, data: &[u8], contents: &[u8]) -> Vec<u8> {
+        let mut basic = vec![30, 11, 1, 0, 0, method, file_type, 0];
+        basic.extend_from_slice(&[0; 4]); // time
+        basic.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        basic.extend_from_slice(&(contents.len() as u32).to_le_bytes());
+        basic.extend_from_slice(&crc32(contents).to_le_bytes());
+        basic.resize(30, 0);
+        basic.extend_from_slice(name.as_bytes());
+        basic.extend_from_slice(&[0, 0]); // name and comment terminators
+
+        let mut out = MAGIC.to_vec();
+        out.extend_from_slice(&(basic.len() as u16).to_le_bytes());
+        out.extend_from_slice(&basic);
+        out.extend_from_slice(&crc32(&basic).to_le_bytes());
+        out.extend_from_slice(&[0, 0]);
+        out.extend_from_slice(data);
+        out
+    }
+
+    struct BitWriter(Vec<u8>);
+
+    impl BitWriter {
+        fn put(&mut self, value: u32, n: u32) {
+            for i in (0..n).rev() {
+                self.0.push(((value >> i) & 1) as u8);
+            }
+        }
+
+        fn bytes(&self) -> Vec<u8> {
+            self.0
+                .chunks(8)
+                .map(|c| {
+                    c.iter()
+                        .enumerate()
+                        .fold(0, |b, (i, &bit)| b | (bit << (7 - i)))
+                })
+                .collect()
+        }
+    }
+
+    #[test]
+    fn decodes_fastest_method() {
+        let mut w = BitWriter(Vec::new());
+        for &c in b"abc" {
+            w.put(0, 1); // length 0: literal
+            w.put(u32::from(c), 8);
+        }
+        // Length code 3 (prefix 110 for 1 + 2, then 2 bits of 0) copies
+        // 3 - 1 + 3 = 5 bytes
+        w.put(0b110, 3);
+        w.put(0, 2);
+        // Distance 3: prefix 0, then 9 bits of 2
+        w.put(0, 1);
+        w.put(2, 9);
+        assert_eq!(decode_fastest(&w.bytes(), 8), b"abcabcab");
+    }
+
+    #[test]
+    fn reads_members() {
+        let mut w = BitWriter(Vec::new());
+        w.put(0, 1);
+        w.put(u32::from(b'q'), 8);
+        let fastest = w.bytes();
+
+        let mut archive = header(2, 0, "test.arj", &[], &[]);
+        archive.extend(header(TYPE_BINARY, 0, "DIR\\PLAIN.TXT", b"plain", b"plain"));
+        archive.extend(header(3, 0, "DIR", &[], &[]));
+        archive.extend(header(TYPE_TEXT, METHOD_FASTEST, "Q", &fastest, b"q"));
+        archive.extend_from_slice(&[0x60, 0xEA, 0, 0]);
+
+        let children = ARJ.children(Arc::new(BytesReader::new(archive))).unwrap();
+        let names: Vec<_> = children.iter().map(|c| c.metadata[0].1.as_str()).collect();
+        assert_eq!(names, ["DIR/PLAIN.TXT", "Q"]);
+        let mut buf = [0u8; 1];
+        children[1].reader.read_at(0, &mut buf).unwrap();
+        assert_eq!(&buf, b"q");
+    }
+}
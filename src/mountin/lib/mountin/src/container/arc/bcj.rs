@@ -0,0 +1,195 @@
+//! Branch/call/jump (BCJ) executable filters
+//!
+//! BCJ filters rewrite relative branch targets as absolute addresses before
+//! compression so repeated calls compress better. Decoding converts them
+//! back. Used by 7z (and xz) folders; these follow the xz-utils "simple"
+//! filters, applied to a whole buffer starting at stream position 0.
+
+/// Test whether a byte is a plausible high byte of an x86 displacement
+fn test_x86_ms_byte(b: u8) -> bool {
+    b == 0x00 || b == 0xFF
+}
+
+/// x86 CALL/JMP (E8/E9) filter
+pub fn x86(buf: &mut [u8], encode: bool) {
+    const MASK_TO_ALLOWED: [bool; 8] = [true, true, true, false, true, false, false, false];
+    const MASK_TO_BIT_NUMBER: [u32; 8] = [0, 1, 2, 2, 3, 3, 3, 3];
+
+    if buf.len() < 5 {
+        return;
+    }
+
+    let mut prev_mask = 0u32;
+    let mut prev_pos = 0u32.wrapping_sub(5);
+    let limit = buf.len() - 5;
+    let mut pos = 0usize;
+
+    while pos <= limit {
+        let b = buf[pos];
+        if b != 0xE8 && b != 0xE9 {
+            pos += 1;
+            continue;
+        }
+
+        let offset = (pos as u32).wrapping_sub(prev_pos);
+        prev_pos = pos as u32;
+        if offset > 5 {
+            prev_mask = 0;
+        } else {
+            for _ in 0..offset {
+                prev_mask &= 0x77;
+                prev_mask <<= 1;
+            }
+        }
+
+        let b = buf[pos + 4];
+        if test_x86_ms_byte(b)
+            && MASK_TO_ALLOWED[((prev_mask >> 1) & 0x7) as usize]
+            && (prev_mask >> 1) < 0x10
+        {
+            let mut src = u32::from_le_bytes([buf[pos + 1], buf[pos + 2], buf[pos + 3], b]);
+            let mut dest;
+            loop {
+                let here = (pos as u32).wrapping_add(5);
+                dest = if encode {
+                    src.wrapping_add(here)
+                } else {
+                    src.wrapping_sub(here)
+                };
+                if prev_mask == 0 {
+                    break;
+                }
+                let i = MASK_TO_BIT_NUMBER[(prev_mask >> 1) as usize];
+                if !test_x86_ms_byte((dest >> (24 - i * 8)) as u8) {
+                    break;
+                }
+                src = dest ^ ((1u32 << (32 - i * 8)) - 1);
+            }
+            let high = !(((dest >> 24) & 1).wrapping_sub(1)) as u8;
+            buf[pos + 1..pos + 5].copy_from_slice(&[dest as u8, (dest >> 8) as u8, (dest >> 16) as u8, high]);
+            pos += 5;
+            prev_mask = 0;
+        } else {
+            pos += 1;
+            prev_mask |= 1;
+            if test_x86_ms_byte(b) {
+                prev_mask |= 0x10;
+            }
+        }
+    }
+}
+
+/// ARM BL filter
+pub fn arm(buf: &mut [u8], encode: bool) {
+    let mut i = 0;
+    while i + 4 <= buf.len() {
+        if buf[i + 3] == 0xEB {
+            let src = u32::from_le_bytes([buf[i], buf[i + 1], buf[i + 2], 0]) << 2;
+            let here = (i as u32).wrapping_add(8);
+            let dest = if encode {
+                src.wrapping_add(here)
+            } else {
+                src.wrapping_sub(here)
+            } >> 2;
+            buf[i..i + 3].copy_from_slice(&dest.to_le_bytes()[..3]);
+        }
+        i += 4;
+    }
+}
+
+/// ARM Thumb BL filter
+pub fn armt(buf: &mut [u8], encode: bool) {
+    let mut i = 0;
+    while i + 4 <= buf.len() {
+        if (buf[i + 1] & 0xF8) == 0xF0 && (buf[i + 3] & 0xF8) == 0xF8 {
+            let src = ((((buf[i + 1] & 7) as u32) << 19)
+                | ((buf[i] as u32) << 11)
+                | (((buf[i + 3] & 7) as u32) << 8)
+                | buf[i + 2] as u32)
+                << 1;
+            let here = (i as u32).wrapping_add(4);
+            let dest = if encode {
+                src.wrapping_add(here)
+            } else {
+                src.wrapping_sub(here)
+            } >> 1;
+            buf[i + 1] = 0xF0 | ((dest >> 19) & 0x7) as u8;
+            buf[i] = (dest >> 11) as u8;
+            buf[i + 3] = 0xF8 | ((dest >> 8) & 0x7) as u8;
+            buf[i + 2] = dest as u8;
+            i += 2;
+        }
+        i += 2;
+    }
+}
+
+/// PowerPC B/BL filter (big-endian)
+pub fn ppc(buf: &mut [u8], encode: bool) {
+    let mut i = 0;
+    while i + 4 <= buf.len() {
+        if (buf[i] >> 2) == 0x12 && (buf[i + 3] & 3) == 1 {
+            let src = u32::from_be_bytes([buf[i] & 3, buf[i + 1], buf[i + 2], buf[i + 3] & !3]);
+            let dest = if encode {
+                src.wrapping_add(i as u32)
+            } else {
+                src.wrapping_sub(i as u32)
+            };
+            buf[i] = 0x48 | ((dest >> 24) & 0x03) as u8;
+            buf[i + 1] = (dest >> 16) as u8;
+            buf[i + 2] = (dest >> 8) as u8;
+            buf[i + 3] = (buf[i + 3] & 0x03) | (dest as u8 & !3);
+        }
+        i += 4;
+    }
+}
+
+/// SPARC CALL filter (big-endian)
+pub fn sparc(buf: &mut [u8], encode: bool) {
+    let mut i = 0;
+    while i + 4 <= buf.len() {
+        if (buf[i] == 0x40 && (buf[i + 1] & 0xC0) == 0x00)
+            || (buf[i] == 0x7F && (buf[i + 1] & 0xC0) == 0xC0)
+        {
+            let src = u32::from_be_bytes([buf[i], buf[i + 1], buf[i + 2], buf[i + 3]]) << 2;
+            let dest = if encode {
+                src.wrapping_add(i as u32)
+            } else {
+                src.wrapping_sub(i as u32)
+            } >> 2;
+            let dest = ((0u32.wrapping_sub((dest >> 22) & 1) << 22) & 0x3FFF_FFFF)
+                | (dest & 0x3F_FFFF)
+                | 0x4000_0000;
+            buf[i..i + 4].copy_from_slice(&dest.to_be_bytes());
+        }
+        i += 4;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(filter: fn(&mut [u8], bool), original: &[u8]) {
+        let mut data = original.to_vec();
+        filter(&mut data, true);
+        assert_ne!(data, original, "filter should rewrite branches");
+        filter(&mut data, false);
+        assert_eq!(data, original);
+    }
+
+    #[test]
+    fn x86_calls_round_trip() {
+        let mut code = vec![0x90u8; 64];
+        code[3..8].copy_from_slice(&[0xE8, 0x10, 0x00, 0x00, 0x00]);
+        code[20..25].copy_from_slice(&[0xE9, 0xF0, 0xFF, 0xFF, 0xFF]);
+        round_trip(x86, &code);
+    }
+
+    #[test]
+    fn risc_branches_round_trip() {
+        round_trip(arm, &[0x10, 0x00, 0x00, 0xEB, 0x00, 0x00, 0xA0, 0xE1]);
+        round_trip(armt, &[0x00, 0xF0, 0x10, 0xF8, 0x00, 0xBF, 0x00, 0xBF]);
+        round_trip(ppc, &[0x60, 0x00, 0x00, 0x00, 0x48, 0x00, 0x00, 0x41]);
+        round_trip(sparc, &[0x01, 0x00, 0x00, 0x00, 0x40, 0x00, 0x00, 0x10]);
+    }
+}
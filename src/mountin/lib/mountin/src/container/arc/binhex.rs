@@ -0,0 +1,205 @@
+//! BinHex 4.0 container reader
+//!
+//! BinHex text carries six bits per character between two colons, with
+//! line breaks ignored. The decoded bytes are run-length coded (0x90
+//! escapes); once expanded they hold a header (name, Finder info and fork
+//! lengths), the data fork and the resource fork, each followed by a
+//! CRC-16/XMODEM.
+
+use super::mac::{crc16_xmodem, file_name, rle90, Fork, MacFile};
+use crate::container::{
+    be32, invalid_data, read_all, slice::SliceReader, BytesReader, Child, Container, MAX_SIZE,
+};
+use crate::detect::Reader;
+use std::io;
+use std::sync::Arc;
+
+const SIGNATURE: &[u8] = b"(This file must be converted with BinHex";
+const ALPHABET: &[u8; 64] = b"!\"#$%&'()*+,-012345689@ABCDEFGHIJKLMNPQRSTUVXYZ[`abcdefhijklmpqr";
+/// Header bytes after the name: version, type, creator, flags, lengths
+const HEADER_FIXED: usize = 1 + 4 + 4 + 2 + 4 + 4;
+
+/// BinHex container - exposes the data and resource forks
+pub struct BinHexContainer;
+
+/// Static instance for registry
+pub static BINHEX: BinHexContainer = BinHexContainer;
+
+impl Container for BinHexContainer {
+    fn children(&self, reader: Arc<dyn Reader + Send + Sync>) -> io::Result<Vec<Child>> {
+        let text = read_all(&*reader)?;
+        let decoded = rle90(&decode_text(&text)?, MAX_SIZE)?;
+
+        let name_len = usize::from(*decoded.first().ok_or_else(truncated)?);
+        let fixed = 1 + name_len;
+        let header = decoded
+            .get(..fixed + HEADER_FIXED + 2)
+            .ok_or_else(truncated)?;
+        let crc = |at: usize| u16::from_be_bytes([decoded[at], decoded[at + 1]]);
+        if crc16_xmodem(&header[..fixed + HEADER_FIXED]) != crc(fixed + HEADER_FIXED) {
+            return Err(invalid_data("BinHex header CRC mismatch"));
+        }
+
+        let file = MacFile {
+            name: file_name(&header[1..fixed]),
+            file_type: header[fixed + 1..fixed + 5].try_into().unwrap(),
+            creator: header[fixed + 5..fixed + 9].try_into().unwrap(),
+        };
+        let data_len = be32(&header[fixed + 11..]) as usize;
+        let resource_len = be32(&header[fixed + 15..]) as usize;
+
+        let data_start = header.len();
+        let resource_start = data_start + data_len + 2;
+        if decoded.len() < resource_start + resource_len + 2 {
+            return Err(truncated());
+        }
+        if crc16_xmodem(&decoded[data_start..data_start + data_len]) != crc(data_start + data_len) {
+            return Err(invalid_data("BinHex data fork CRC mismatch"));
+        }
+        if crc16_xmodem(&decoded[resource_start..resource_start + resource_len])
+            != crc(resource_start + resource_len)
+        {
+            return Err(invalid_data("BinHex resource fork CRC mismatch"));
+        }
+
+        let decoded: Arc<dyn Reader + Send + Sync> = Arc::new(BytesReader::new(decoded));
+        let fork = |start: usize, len: usize| -> Arc<dyn Reader + Send + Sync> {
+            Arc::new(SliceReader::new(
+                Arc::clone(&decoded),
+                start as u64,
+                len as u64,
+            ))
+        };
+        let mut children =
+            vec![file.fork_child(0, data_start as u64, fork(data_start, data_len), Fork::Data)];
+        if resource_len > 0 {
+            children.push(file.fork_child(
+                1,
+                resource_start as u64,
+                fork(resource_start, resource_len),
+                Fork::Resource,
+            ));
+        }
+        Ok(children)
+    }
+}
+
+fn truncated() -> io::Error {
+    invalid_data("truncated BinHex data")
+}
+
+/// Decode the characters between the first two colons after the
+/// signature line into bytes
+fn decode_text(text: &[u8]) -> io::Result<Vec<u8>> {
+    let signature = text
+        .windows(SIGNATURE.len())
+        .position(|w| w == SIGNATURE)
+        .ok_or_else(|| invalid_data("missing BinHex signature"))?;
+    let after = &text[signature + SIGNATURE.len()..];
+    // The signature line ends in "4.0)"; data starts at a colon that
+    // begins a line
+    let start = after
+        .windows(2)
+        .position(|w| (w[0] == b'\n' || w[0] == b'\r') && w[1] == b':')
+        .ok_or_else(|| invalid_data("missing BinHex data"))?;
+
+    let mut lookup = [0xFFu8; 256];
+    for (value, &c) in ALPHABET.iter().enumerate() {
+        lookup[usize::from(c)] = value as u8;
+    }
+
+    let mut out = Vec::new();
+    let (mut acc, mut bits) = (0u32, 0);
+    for &c in &after[start + 2..] {
+        match c {
+            b':' => return Ok(out),
+            b'\r' | b'\n' | b' ' | b'\t' => continue,
+            _ => {}
+        }
+        let value = lookup[usize::from(c)];
+        if value == 0xFF {
+            return Err(invalid_data("invalid BinHex character"));
+        }
+        acc = (acc << 6) | u32::from(value);
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+        }
+    }
+    Err(invalid_data("missing BinHex end marker"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(raw: &[u8]) -> Vec<u8> {
+        let mut text = b"(This file must be converted with BinHex 4.0)\r\n:".to_vec();
+        let (mut acc, mut bits) = (0u32, 0);
+        for (i, &byte) in raw.iter().enumerate() {
+            acc = (acc << 8) | u32::from(byte);
+            bits += 8;
+            while bits >= 6 {
+                bits -= 6;
+                text.push(ALPHABET[((acc >> bits) & 0x3F) as usize]);
+            }
+            if i % 40 == 39 {
+                text.push(b'\n');
+            }
+        }
+        if bits > 0 {
+            text.push(ALPHABET[((acc << (6 - bits)) & 0x3F) as usize]);
+        }
+        text.extend_from_slice(b":\n");
+        text
+    }
+
+    fn binhex(data: &[u8], resource: &[u8]) -> Vec<u8> {
+        let mut raw = vec![9];
+        raw.extend_from_slice(b"Read\x20Me!!");
+        raw.push(0);
+        raw.extend_from_slice(b"TEXTttxt\x01\x00");
+        raw.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        raw.extend_from_slice(&(resource.len() as u32).to_be_bytes());
+        let crc = crc16_xmodem(&raw);
+        raw.extend_from_slice(&crc.to_be_bytes());
+        for fork in [data, resource] {
+            raw.extend_from_slice(fork);
+            raw.extend_from_slice(&crc16_xmodem(fork).to_be_bytes());
+        }
+        raw
+    }
+
+    #[test]
+    fn reads_forks() {
+        let raw = binhex(b"hello\x90world", b"resource fork");
+        // Escape the run-length marker
+        let mut coded = Vec::new();
+        for &b in &raw {
+            coded.push(b);
+            if b == 0x90 {
+                coded.push(0);
+            }
+        }
+        let text = encode(&coded);
+        let children = BINHEX.children(Arc::new(BytesReader::new(text))).unwrap();
+        assert_eq!(children.len(), 2);
+        let mut buf = vec![0u8; 11];
+        children[0].reader.read_at(0, &mut buf).unwrap();
+        assert_eq!(buf, b"hello\x90world");
+        assert_eq!(children[1].metadata[0], ("name", "Read Me!!".to_string()));
+        assert_eq!(children[1].metadata[1], ("fork", "resource".to_string()));
+        assert_eq!(children[1].reader.size(), Some(13));
+    }
+
+    #[test]
+    fn rejects_bad_crc() {
+        let mut raw = binhex(b"data", b"");
+        let at = raw.len() - 5;
+        raw[at] ^= 1;
+        assert!(BINHEX
+            .children(Arc::new(BytesReader::new(encode(&raw))))
+            .is_err());
+    }
+}
@@ -1,10 +1,12 @@
 //! Bzip2 container reader
 //!
 //! Bzip2 is a compression wrapper containing a single decompressed stream.
+//! Parallel compressors (pbzip2, lbzip2) and `cat` produce several bzip2
+//! streams back to back; all of them are decoded.
 
 use crate::container::{read_all, read_to_end_limited, BytesReader, Child, Container};
 use crate::detect::Reader;
-use bzip2::read::BzDecoder;
+use bzip2::read::MultiBzDecoder;
 use std::io;
 use std::sync::Arc;
 
@@ -17,13 +19,39 @@ pub static BZIP2: Bzip2Container = Bzip2Container;
 impl Container for Bzip2Container {
     fn children(&self, reader: Arc<dyn Reader + Send + Sync>) -> io::Result<Vec<Child>> {
         let compressed = read_all(&*reader)?;
-        let decoder = BzDecoder::new(&compressed[..]);
+        let decoder = MultiBzDecoder::new(&compressed[..]);
         let decompressed = read_to_end_limited(decoder)?;
 
         Ok(vec![Child {
             index: 0,
             offset: u64::MAX, // Transformed data, not a slice
             reader: Arc::new(BytesReader::new(decompressed)),
+            metadata: Vec::new(),
         }])
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bzip2::write::BzEncoder;
+    use bzip2::Compression;
+    use std::io::Write;
+
+    #[test]
+    fn decodes_concatenated_streams() {
+        let mut compressed = Vec::new();
+        for part in [&b"first stream, "[..], b"", b"second stream"] {
+            let mut encoder = BzEncoder::new(Vec::new(), Compression::fast());
+            encoder.write_all(part).unwrap();
+            compressed.extend(encoder.finish().unwrap());
+        }
+
+        let kids = BZIP2
+            .children(Arc::new(BytesReader::new(compressed)))
+            .unwrap();
+        let mut buf = [0u8; 64];
+        let n = kids[0].reader.read_at(0, &mut buf).unwrap();
+        assert_eq!(&buf[..n], b"first stream, second stream");
+    }
+}
@@ -0,0 +1,538 @@
+//! Microsoft Cabinet (CAB) container reader
+//!
+//! A cabinet holds folders and files. A folder is one compressed stream
+//! (stored, MSZIP, Quantum or LZX), cut into CFDATA blocks of at most 32 KiB
+//! of output; files are ranges of a folder's output. As with 7z, a folder
+//! is decoded once, on first access, and its files are slices of it.
+//!
+//! Cabinets may be split into sets where a folder runs on into the next
+//! cabinet. Only the current cabinet is read: files continued from the
+//! previous cabinet or into the next one are skipped.
+
+use super::inflate::Inflater;
+use super::lzx::LzxDecoder;
+use super::quantum::QuantumDecoder;
+use crate::container::{
+    invalid_data, le16, le32, read_exact_at, slice::SliceReader, BytesReader, Child, Container,
+    DeferredReader, Entry, MAX_SIZE,
+};
+use crate::detect::Reader;
+use std::io;
+use std::sync::Arc;
+
+const SIGNATURE: &[u8; 4] = b"MSCF";
+const HEADER_SIZE: u64 = 36;
+const FOLDER_SIZE: u64 = 8;
+const FILE_SIZE: u64 = 16;
+const DATA_HEADER_SIZE: u64 = 8;
+/// Longest name, including its terminator, that CAB tools accept
+const MAX_NAME: usize = 257;
+/// Most output a CFDATA block may hold
+const MAX_BLOCK: usize = 32 * 1024;
+
+const FLAG_PREV_CABINET: u16 = 0x0001;
+const FLAG_NEXT_CABINET: u16 = 0x0002;
+const FLAG_RESERVE_PRESENT: u16 = 0x0004;
+
+const METHOD_NONE: u16 = 0;
+const METHOD_MSZIP: u16 = 1;
+const METHOD_QUANTUM: u16 = 2;
+const METHOD_LZX: u16 = 3;
+
+/// File folder indexes for files split across cabinets
+const CONTINUED_FROM_PREV: u16 = 0xFFFD;
+const CONTINUED_TO_NEXT: u16 = 0xFFFE;
+const CONTINUED_PREV_AND_NEXT: u16 = 0xFFFF;
+
+/// MSZIP block signature
+const MSZIP_SIGNATURE: &[u8; 2] = b"CK";
+
+/// CAB container - exposes each file as a child
+pub struct CabContainer;
+
+/// Static instance for registry
+pub static CAB: CabContainer = CabContainer;
+
+impl Container for CabContainer {
+    fn children(&self, reader: Arc<dyn Reader + Send + Sync>) -> io::Result<Vec<Child>> {
+        self.entries(reader)?.into_iter().map(Entry::open).collect()
+    }
+
+    fn entries(&self, reader: Arc<dyn Reader + Send + Sync>) -> io::Result<Vec<Entry>> {
+        let cabinet = parse(&*reader)?;
+
+        // Only a stored folder's files sit in the cabinet as they are
+        let offsets: Vec<u64> = cabinet
+            .files
+            .iter()
+            .map(|file| {
+                cabinet
+                    .folders
+                    .get(usize::from(file.folder))
+                    .filter(|folder| folder.method == METHOD_NONE)
+                    .and_then(|folder| folder.position(u64::from(file.offset)))
+                    .unwrap_or(u64::MAX)
+            })
+            .collect();
+        let folders: Vec<Arc<dyn Reader + Send + Sync>> = cabinet
+            .folders
+            .into_iter()
+            .map(|folder| {
+                let parent = Arc::clone(&reader);
+                let size = folder.size();
+                Arc::new(DeferredReader::new(size, move || {
+                    decode_folder(&*parent, &folder)
+                })) as Arc<dyn Reader + Send + Sync>
+            })
+            .collect();
+
+        let mut entries = Vec::new();
+        for (index, file) in cabinet.files.into_iter().enumerate() {
+            let Some(folder) = folders.get(usize::from(file.folder)) else {
+                continue;
+            };
+            let folder_size = folder.size().unwrap_or(0);
+            let end = u64::from(file.offset) + u64::from(file.size);
+            if end > folder_size {
+                return Err(invalid_data("CAB file extends past its folder"));
+            }
+            let folder = Arc::clone(folder);
+            let (start, size) = (u64::from(file.offset), u64::from(file.size));
+            entries.push(Entry::new(
+                index as u32,
+                offsets[index],
+                Some(size),
+                vec![("name", file.name)],
+                move || Ok(Arc::new(SliceReader::new(folder, start, size)) as _),
+            ));
+        }
+        Ok(entries)
+    }
+}
+
+/// One CFDATA block
+#[derive(Clone, Debug)]
+struct DataBlock {
+    offset: u64,
+    compressed: u16,
+    uncompressed: u16,
+    checksum: u32,
+}
+
+#[derive(Clone, Debug)]
+struct Folder {
+    method: u16,
+    window_bits: u32,
+    /// Reserved bytes between each CFDATA header and its data
+    reserve: u8,
+    blocks: Vec<DataBlock>,
+}
+
+impl Folder {
+    fn size(&self) -> u64 {
+        self.blocks.iter().map(|b| u64::from(b.uncompressed)).sum()
+    }
+
+    /// Where byte `offset` of a stored folder's output lies in the cabinet
+    fn position(&self, mut offset: u64) -> Option<u64> {
+        for block in &self.blocks {
+            let size = u64::from(block.uncompressed);
+            if offset < size {
+                return Some(block.offset + u64::from(self.reserve) + offset);
+            }
+            offset -= size;
+        }
+        None
+    }
+}
+
+struct File {
+    size: u32,
+    offset: u32,
+    folder: u16,
+    name: String,
+}
+
+struct Cabinet {
+    folders: Vec<Folder>,
+    files: Vec<File>,
+}
+
+/// Read a NUL-terminated string, returning it and the offset after it
+fn read_string(reader: &dyn Reader, offset: u64) -> io::Result<(Vec<u8>, u64)> {
+    let mut buf = [0u8; MAX_NAME];
+    let n = reader.read_at(offset, &mut buf)?;
+    let len = buf[..n]
+        .iter()
+        .position(|&b| b == 0)
+        .ok_or_else(|| invalid_data("unterminated CAB string"))?;
+    Ok((buf[..len].to_vec(), offset + len as u64 + 1))
+}
+
+fn parse(reader: &dyn Reader) -> io::Result<Cabinet> {
+    let mut header = [0u8; HEADER_SIZE as usize];
+    read_exact_at(reader, 0, &mut header)?;
+    if &header[..4] != SIGNATURE {
+        return Err(invalid_data("invalid CAB signature"));
+    }
+    if header[25] != 1 {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "unsupported CAB version",
+        ));
+    }
+    let files_offset = u64::from(le32(&header[16..]));
+    let folder_count = le16(&header[26..]);
+    let file_count = le16(&header[28..]);
+    let flags = le16(&header[30..]);
+
+    let mut pos = HEADER_SIZE;
+    let (mut folder_reserve, mut data_reserve) = (0u8, 0u8);
+    if flags & FLAG_RESERVE_PRESENT != 0 {
+        let mut reserve = [0u8; 4];
+        read_exact_at(reader, pos, &mut reserve)?;
+        folder_reserve = reserve[2];
+        data_reserve = reserve[3];
+        pos += 4 + u64::from(le16(&reserve));
+    }
+    // Previous and next cabinet names and disk labels
+    for flag in [FLAG_PREV_CABINET, FLAG_NEXT_CABINET] {
+        if flags & flag != 0 {
+            pos = read_string(reader, pos)?.1;
+            pos = read_string(reader, pos)?.1;
+        }
+    }
+
+    let mut folders = Vec::with_capacity(usize::from(folder_count));
+    for _ in 0..folder_count {
+        let mut entry = [0u8; FOLDER_SIZE as usize];
+        read_exact_at(reader, pos, &mut entry)?;
+        pos += FOLDER_SIZE + u64::from(folder_reserve);
+        let compression = le16(&entry[6..]);
+        folders.push(parse_folder(
+            reader,
+            u64::from(le32(&entry)),
+            le16(&entry[4..]),
+            compression,
+            data_reserve,
+        )?);
+    }
+
+    let mut files = Vec::with_capacity(usize::from(file_count));
+    let mut pos = files_offset;
+    for _ in 0..file_count {
+        let mut entry = [0u8; FILE_SIZE as usize];
+        read_exact_at(reader, pos, &mut entry)?;
+        let (name, next) = read_string(reader, pos + FILE_SIZE)?;
+        pos = next;
+
+        let folder = le16(&entry[8..]);
+        if matches!(
+            folder,
+            CONTINUED_FROM_PREV | CONTINUED_TO_NEXT | CONTINUED_PREV_AND_NEXT
+        ) {
+            continue;
+        }
+        // Names are in the system code page unless flagged UTF-8; either
+        // way, '\' separates directories
+        files.push(File {
+            size: le32(&entry),
+            offset: le32(&entry[4..]),
+            folder,
+            name: String::from_utf8_lossy(&name).replace('\\', "/"),
+        });
+    }
+
+    Ok(Cabinet { folders, files })
+}
+
+/// Walk a folder's CFDATA headers
+fn parse_folder(
+    reader: &dyn Reader,
+    start: u64,
+    count: u16,
+    compression: u16,
+    reserve: u8,
+) -> io::Result<Folder> {
+    let method = compression & 0x000F;
+    let window_bits = u32::from((compression >> 8) & 0x1F);
+    let mut blocks = Vec::with_capacity(usize::from(count));
+    let mut pos = start;
+    for _ in 0..count {
+        let mut header = [0u8; DATA_HEADER_SIZE as usize];
+        read_exact_at(reader, pos, &mut header)?;
+        let block = DataBlock {
+            offset: pos + DATA_HEADER_SIZE,
+            checksum: le32(&header),
+            compressed: le16(&header[4..]),
+            uncompressed: le16(&header[6..]),
+        };
+        // Zero output marks a block finished in the next cabinet
+        if block.uncompressed == 0 {
+            break;
+        }
+        if usize::from(block.uncompressed) > MAX_BLOCK {
+            return Err(invalid_data("CAB data block too large"));
+        }
+        pos = block.offset + u64::from(reserve) + u64::from(block.compressed);
+        blocks.push(block);
+    }
+    Ok(Folder {
+        method,
+        window_bits,
+        reserve,
+        blocks,
+    })
+}
+
+/// The cabinet checksum: XOR of little-endian 32-bit words, with the
+/// leftover bytes folded in most significant first
+fn checksum(data: &[u8], seed: u32) -> u32 {
+    let mut chunks = data.chunks_exact(4);
+    let mut sum = seed;
+    for chunk in &mut chunks {
+        sum ^= u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+    }
+    let tail = chunks
+        .remainder()
+        .iter()
+        .fold(0u32, |acc, &b| (acc << 8) | u32::from(b));
+    sum ^ tail
+}
+
+/// Per-folder decompressor state
+enum Decoder {
+    Stored,
+    Mszip(Vec<u8>),
+    Quantum(Box<QuantumDecoder>),
+    Lzx(Box<LzxDecoder>),
+}
+
+impl Decoder {
+    fn new(method: u16, window_bits: u32) -> io::Result<Self> {
+        match method {
+            METHOD_NONE => Ok(Self::Stored),
+            METHOD_MSZIP => Ok(Self::Mszip(Vec::new())),
+            METHOD_QUANTUM => Ok(Self::Quantum(Box::new(QuantumDecoder::new(window_bits)?))),
+            METHOD_LZX => Ok(Self::Lzx(Box::new(LzxDecoder::new(window_bits)?))),
+            _ => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "unsupported CAB compression method",
+            )),
+        }
+    }
+
+    fn decode(&mut self, data: &[u8], size: usize) -> io::Result<Vec<u8>> {
+        let out = match self {
+            Self::Stored => data.to_vec(),
+            Self::Mszip(history) => {
+                let (out, _) = mszip_block(data, history)?;
+                *history = out.clone();
+                out
+            }
+            Self::Quantum(quantum) => quantum.decode_frame(data, size)?,
+            Self::Lzx(lzx) => lzx.decode_frame(data, size)?,
+        };
+        if out.len() != size {
+            return Err(invalid_data("CAB data block size mismatch"));
+        }
+        Ok(out)
+    }
+}
+
+/// Decode one MSZIP block ("CK" and a complete deflate stream, using the
+/// previous block's output as its dictionary), returning its output and
+/// the number of input bytes it used
+pub(crate) fn mszip_block(data: &[u8], history: &[u8]) -> io::Result<(Vec<u8>, usize)> {
+    if data.get(..2) != Some(&MSZIP_SIGNATURE[..]) {
+        return Err(invalid_data("invalid MSZIP block signature"));
+    }
+    let input = Arc::new(BytesReader::new(data[2..].to_vec()));
+    let mut inflater = Inflater::new(input, 0, history)?;
+    let mut out = Vec::new();
+    let mut buf = [0u8; 8192];
+    loop {
+        let n = inflater.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        if out.len() + n > MAX_BLOCK {
+            return Err(invalid_data("MSZIP block too large"));
+        }
+        out.extend_from_slice(&buf[..n]);
+    }
+    let used = 2 + inflater.bit_position().div_ceil(8) as usize;
+    Ok((out, used))
+}
+
+fn decode_folder(reader: &dyn Reader, folder: &Folder) -> io::Result<Vec<u8>> {
+    if folder.size() > MAX_SIZE as u64 {
+        return Err(invalid_data("CAB folder too large"));
+    }
+    let mut decoder = Decoder::new(folder.method, folder.window_bits)?;
+    let mut out = Vec::with_capacity(folder.size() as usize);
+    for block in &folder.blocks {
+        let reserve = usize::from(folder.reserve);
+        let mut raw = vec![0u8; reserve + usize::from(block.compressed)];
+        read_exact_at(reader, block.offset, &mut raw)?;
+
+        if block.checksum != 0 {
+            let mut sizes = [0u8; 4];
+            sizes[..2].copy_from_slice(&block.compressed.to_le_bytes());
+            sizes[2..].copy_from_slice(&block.uncompressed.to_le_bytes());
+            if checksum(&sizes, checksum(&raw[reserve..], 0)) != block.checksum {
+                return Err(invalid_data("CAB data block checksum mismatch"));
+            }
+        }
+        out.extend(decoder.decode(&raw[reserve..], usize::from(block.uncompressed))?);
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::{write::DeflateEncoder, Compression};
+    use std::io::Write;
+
+    /// A single-folder cabinet holding `files`, with the folder's output cut
+    /// into `block`-byte CFDATA blocks, each encoded by `encode`
+    fn cabinet(
+        files: &[(&str, &[u8])],
+        method: u16,
+        block: usize,
+        encode: impl Fn(&[u8], &[u8]) -> Vec<u8>,
+    ) -> Vec<u8> {
+        let stream: Vec<u8> = files.iter().flat_map(|(_, data)| data.to_vec()).collect();
+        let mut file_entries = Vec::new();
+        let mut offset = 0u32;
+        for (name, data) in files {
+            file_entries.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            file_entries.extend_from_slice(&offset.to_le_bytes());
+            file_entries.extend_from_slice(&[0; 8]); // folder 0, date, time, attribs
+            file_entries.extend_from_slice(name.as_bytes());
+            file_entries.push(0);
+            offset += data.len() as u32;
+        }
+
+        let mut data_blocks = Vec::new();
+        let mut count = 0u16;
+        let mut previous: &[u8] = &[];
+        for chunk in stream.chunks(block) {
+            let encoded = encode(chunk, previous);
+            let mut sizes = Vec::new();
+            sizes.extend_from_slice(&(encoded.len() as u16).to_le_bytes());
+            sizes.extend_from_slice(&(chunk.len() as u16).to_le_bytes());
+            let sum = checksum(&sizes, checksum(&encoded, 0));
+            data_blocks.extend_from_slice(&sum.to_le_bytes());
+            data_blocks.extend(sizes);
+            data_blocks.extend(encoded);
+            previous = chunk;
+            count += 1;
+        }
+
+        let files_offset = HEADER_SIZE + FOLDER_SIZE;
+        let data_offset = files_offset + file_entries.len() as u64;
+        let total = data_offset + data_blocks.len() as u64;
+        let mut out = SIGNATURE.to_vec();
+        out.extend_from_slice(&[0; 4]);
+        out.extend_from_slice(&(total as u32).to_le_bytes());
+        out.extend_from_slice(&[0; 4]);
+        out.extend_from_slice(&(files_offset as u32).to_le_bytes());
+        out.extend_from_slice(&[0; 4]);
+        out.extend_from_slice(&[3, 1]); // version 1.3
+        out.extend_from_slice(&1u16.to_le_bytes());
+        out.extend_from_slice(&(files.len() as u16).to_le_bytes());
+        out.extend_from_slice(&[0; 6]); // flags, set ID, index in set
+        out.extend_from_slice(&(data_offset as u32).to_le_bytes());
+        out.extend_from_slice(&count.to_le_bytes());
+        out.extend_from_slice(&method.to_le_bytes());
+        out.extend(file_entries);
+        out.extend(data_blocks);
+        out
+    }
+
+    fn contents(kids: &[Child]) -> Vec<(String, Vec<u8>)> {
+        kids.iter()
+            .map(|kid| {
+                let mut buf = vec![0u8; kid.reader.size().unwrap() as usize];
+                kid.reader.read_at(0, &mut buf).unwrap();
+                (kid.metadata[0].1.clone(), buf)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reads_stored_folders() {
+        let data = cabinet(
+            &[("readme.txt", b"hello, cabinet"), ("dir\\b.bin", b"second")],
+            METHOD_NONE,
+            5,
+            |chunk, _| chunk.to_vec(),
+        );
+        let kids = CAB.children(Arc::new(BytesReader::new(data))).unwrap();
+        assert_eq!(
+            contents(&kids),
+            [
+                ("readme.txt".to_string(), b"hello, cabinet".to_vec()),
+                ("dir/b.bin".to_string(), b"second".to_vec()),
+            ]
+        );
+        // The second file starts 4 bytes into the third 5-byte block
+        assert_eq!((kids[0].offset, kids[1].offset), (105, 135));
+    }
+
+    #[test]
+    fn reads_mszip_folders() {
+        let first: Vec<u8> = (0..40_000u32).map(|i| (i % 97) as u8).collect();
+        let files: [(&str, &[u8]); 2] = [("a", &first), ("b", b"tail")];
+        let data = cabinet(&files, METHOD_MSZIP, MAX_BLOCK, |chunk, _| {
+            let mut encoder = DeflateEncoder::new(b"CK".to_vec(), Compression::default());
+            encoder.write_all(chunk).unwrap();
+            encoder.finish().unwrap()
+        });
+        let kids = CAB.children(Arc::new(BytesReader::new(data))).unwrap();
+        let got = contents(&kids);
+        assert_eq!(got[0].1, first);
+        assert_eq!(got[1].1, b"tail");
+        // Compressed files have no place of their own in the cabinet
+        assert!(kids.iter().all(|kid| kid.offset == u64::MAX));
+    }
+
+    #[test]
+    fn lists_entries_without_decoding() {
+        let data = cabinet(
+            &[("a", b"first"), ("b", b"second")],
+            METHOD_MSZIP,
+            64,
+            |_, _| b"not deflate".to_vec(),
+        );
+        let entries = CAB
+            .entries(Arc::new(BytesReader::new(data.clone())))
+            .unwrap();
+        let listed: Vec<_> = entries.iter().map(|e| (e.name(), e.size)).collect();
+        assert_eq!(listed, [(Some("a"), Some(5)), (Some("b"), Some(6))]);
+        // The folder is only decoded when an entry is read
+        let kid = entries.into_iter().next().unwrap().open().unwrap();
+        assert!(kid.reader.read_at(0, &mut [0u8; 4]).is_err());
+    }
+
+    #[test]
+    fn verifies_checksums() {
+        let mut data = cabinet(&[("a", b"checked")], METHOD_NONE, 64, |chunk, _| {
+            chunk.to_vec()
+        });
+        let last = data.len() - 1;
+        data[last] ^= 1;
+        let kids = CAB.children(Arc::new(BytesReader::new(data))).unwrap();
+        assert!(kids[0].reader.read_at(0, &mut [0u8; 4]).is_err());
+    }
+
+    #[test]
+    fn computes_checksums() {
+        // Whole words XOR, then the 3 trailing bytes as 0x010203
+        let data = [1, 0, 0, 0, 2, 0, 0, 0, 1, 2, 3];
+        assert_eq!(checksum(&data, 0), 3 ^ 0x010203);
+        assert_eq!(checksum(&[], 0x1234), 0x1234);
+    }
+}
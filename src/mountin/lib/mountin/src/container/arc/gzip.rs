@@ -1,14 +1,47 @@
 //! Gzip container reader
 //!
-//! Gzip is a compression wrapper containing a single decompressed stream.
+//! Gzip is a compression wrapper containing a single decompressed stream,
+//! possibly split across several members (`cat a.gz b.gz`, bgzip). Members
+//! are decoded back to back; anything after the last one is ignored, as
+//! gzip itself does with trailing zeros from tape blocking.
+//!
+//! Rather than inflating it all into memory, a first pass records
+//! zran-style checkpoints (input bit offset plus the 32 KiB window) at
+//! deflate block boundaries roughly every SPAN bytes of output. Reads
+//! inflate from the nearest checkpoint, so images of any size can be
+//! detected in bounded memory. The checkpoint index can optionally be kept
+//! beside the file (`disk.img.gz.zran`) to skip the first pass next time.
 
-use crate::container::{read_all, read_to_end_limited, BytesReader, Child, Container};
+use super::inflate::{Inflater, WINDOW_SIZE};
+use crate::container::{invalid_data, persist_indexes, BlockCache, Child, Container};
 use crate::detect::Reader;
-use flate2::read::GzDecoder;
-use std::io;
-use std::sync::Arc;
+use flate2::Crc;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+const MAGIC: [u8; 2] = [0x1F, 0x8B];
+const METHOD_DEFLATE: u8 = 8;
+const FHCRC: u8 = 0x02;
+const FEXTRA: u8 = 0x04;
+const FNAME: u8 = 0x08;
+const FCOMMENT: u8 = 0x10;
+/// Longest FNAME/FCOMMENT accepted
+const MAX_HEADER_STRING: u64 = 64 * 1024;
+
+/// Output between checkpoints. Each checkpoint keeps a 32 KiB window, so
+/// this trades index memory against how much a random read must inflate.
+const SPAN: u64 = 4 * 1024 * 1024;
+/// Decoded spans kept in memory
+const CACHE_SPANS: usize = 8;
+/// Spans larger than this (one enormous block) are streamed, not cached
+const MAX_CACHED_SPAN: u64 = 64 * 1024 * 1024;
 
-/// Gzip container - decompresses content to expose inner stream
+const INDEX_MAGIC: &[u8; 8] = b"MNTZRAN1";
+const INDEX_SUFFIX: &str = ".zran";
+
+/// Gzip container - exposes the decompressed stream through a seek index
 pub struct GzipContainer;
 
 /// Static instance for registry
@@ -16,14 +49,509 @@ pub static GZIP: GzipContainer = GzipContainer;
 
 impl Container for GzipContainer {
     fn children(&self, reader: Arc<dyn Reader + Send + Sync>) -> io::Result<Vec<Child>> {
-        let compressed = read_all(&*reader)?;
-        let decoder = GzDecoder::new(&compressed[..]);
-        let decompressed = read_to_end_limited(decoder)?;
+        let gzip_reader = GzipReader::new(reader)?;
 
         Ok(vec![Child {
             index: 0,
             offset: u64::MAX, // Transformed data, not a slice
-            reader: Arc::new(BytesReader::new(decompressed)),
+            reader: Arc::new(gzip_reader),
+            metadata: Vec::new(),
         }])
     }
 }
+
+/// A point decoding can resume from
+#[derive(Debug, PartialEq)]
+struct Checkpoint {
+    /// Uncompressed offset
+    out: u64,
+    /// Compressed bit offset of the next block header
+    bit: u64,
+    /// Preceding output, up to 32 KiB
+    window: Vec<u8>,
+}
+
+/// Checkpoints for one gzip file, plus what is needed to tell whether a
+/// saved copy still describes the file
+#[derive(Debug, PartialEq)]
+struct GzipIndex {
+    compressed_size: u64,
+    /// Last 8 bytes of the file (CRC32 and ISIZE for a single member)
+    tail: [u8; 8],
+    size: u64,
+    checkpoints: Vec<Checkpoint>,
+}
+
+/// Return the offset of the deflate data after a member header
+fn parse_header(reader: &dyn Reader, offset: u64) -> io::Result<u64> {
+    let mut header = [0u8; 10];
+    if reader.read_at(offset, &mut header)? != header.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "short gzip header read",
+        ));
+    }
+    if header[..2] != MAGIC || header[2] != METHOD_DEFLATE {
+        return Err(invalid_data("invalid gzip header"));
+    }
+
+    let flags = header[3];
+    let mut pos = offset + header.len() as u64;
+    if flags & FEXTRA != 0 {
+        let mut len = [0u8; 2];
+        if reader.read_at(pos, &mut len)? != len.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "short gzip extra field read",
+            ));
+        }
+        pos += 2 + u64::from(u16::from_le_bytes(len));
+    }
+    for flag in [FNAME, FCOMMENT] {
+        if flags & flag != 0 {
+            pos = skip_string(reader, pos)?;
+        }
+    }
+    if flags & FHCRC != 0 {
+        pos += 2;
+    }
+    Ok(pos)
+}
+
+/// Skip a NUL-terminated header string
+fn skip_string(reader: &dyn Reader, start: u64) -> io::Result<u64> {
+    let mut buf = [0u8; 256];
+    let mut pos = start;
+    while pos - start < MAX_HEADER_STRING {
+        let n = reader.read_at(pos, &mut buf)?;
+        if n == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "unterminated gzip header string",
+            ));
+        }
+        if let Some(end) = buf[..n].iter().position(|&b| b == 0) {
+            return Ok(pos + end as u64 + 1);
+        }
+        pos += n as u64;
+    }
+    Err(invalid_data("gzip header string too long"))
+}
+
+fn read_tail(reader: &dyn Reader) -> io::Result<(u64, [u8; 8])> {
+    let size = reader
+        .size()
+        .ok_or_else(|| invalid_data("gzip stream size unknown"))?;
+    let mut tail = [0u8; 8];
+    if size < tail.len() as u64 || reader.read_at(size - 8, &mut tail)? != tail.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "short gzip trailer read",
+        ));
+    }
+    Ok((size, tail))
+}
+
+/// Inflate every member once, verifying each and recording checkpoints
+fn build_index(reader: &Arc<dyn Reader + Send + Sync>, span: u64) -> io::Result<GzipIndex> {
+    let (compressed_size, tail) = read_tail(&**reader)?;
+    let mut checkpoints: Vec<Checkpoint> = Vec::new();
+    let mut out = 0u64;
+    let mut buf = vec![0u8; 64 * 1024];
+    let mut member = 0u64;
+
+    loop {
+        // Each member starts with an empty window, so it always gets a
+        // checkpoint; one left behind by an empty member is replaced
+        let start = parse_header(&**reader, member)? * 8;
+        if checkpoints.last().is_some_and(|c| c.out == out) {
+            checkpoints.pop();
+        }
+        checkpoints.push(Checkpoint {
+            out,
+            bit: start,
+            window: Vec::new(),
+        });
+
+        let mut inflater = Inflater::new(Arc::clone(reader), start, &[])?;
+        let mut crc = Crc::new();
+        loop {
+            let n = inflater.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            crc.update(&buf[..n]);
+            out += n as u64;
+
+            let last = checkpoints.last().map_or(0, |c| c.out);
+            if inflater.at_block_boundary() && out - last >= span {
+                checkpoints.push(Checkpoint {
+                    out,
+                    bit: inflater.bit_position(),
+                    window: inflater.window(),
+                });
+            }
+        }
+
+        // The inflater leaves the input byte aligned at the member trailer
+        let trailer_at = inflater.bit_position() / 8;
+        let mut trailer = [0u8; 8];
+        if reader.read_at(trailer_at, &mut trailer)? != trailer.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "short gzip trailer read",
+            ));
+        }
+        let expected_crc = u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+        let expected_size = u32::from_le_bytes([trailer[4], trailer[5], trailer[6], trailer[7]]);
+        if crc.sum() != expected_crc {
+            return Err(invalid_data("gzip CRC mismatch"));
+        }
+        if crc.amount() != expected_size {
+            return Err(invalid_data("gzip size mismatch"));
+        }
+
+        member = trailer_at + trailer.len() as u64;
+        let mut magic = [0u8; 2];
+        if reader.read_at(member, &mut magic)? != magic.len() || magic != MAGIC {
+            break;
+        }
+    }
+
+    Ok(GzipIndex {
+        compressed_size,
+        tail,
+        size: out,
+        checkpoints,
+    })
+}
+
+impl GzipIndex {
+    fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(INDEX_MAGIC)?;
+        w.write_all(&self.compressed_size.to_le_bytes())?;
+        w.write_all(&self.tail)?;
+        w.write_all(&self.size.to_le_bytes())?;
+        w.write_all(&(self.checkpoints.len() as u64).to_le_bytes())?;
+        for checkpoint in &self.checkpoints {
+            w.write_all(&checkpoint.out.to_le_bytes())?;
+            w.write_all(&checkpoint.bit.to_le_bytes())?;
+            w.write_all(&(checkpoint.window.len() as u32).to_le_bytes())?;
+            w.write_all(&checkpoint.window)?;
+        }
+        Ok(())
+    }
+
+    fn read_from<R: Read>(r: &mut R) -> io::Result<Self> {
+        fn read_u64<R: Read>(r: &mut R) -> io::Result<u64> {
+            let mut buf = [0u8; 8];
+            r.read_exact(&mut buf)?;
+            Ok(u64::from_le_bytes(buf))
+        }
+
+        let mut magic = [0u8; 8];
+        r.read_exact(&mut magic)?;
+        if &magic != INDEX_MAGIC {
+            return Err(invalid_data("invalid gzip index magic"));
+        }
+        let compressed_size = read_u64(r)?;
+        let mut tail = [0u8; 8];
+        r.read_exact(&mut tail)?;
+        let size = read_u64(r)?;
+        let count = read_u64(r)?;
+
+        // Every checkpoint after the first is at least one block further on
+        if count == 0 || count > compressed_size {
+            return Err(invalid_data("invalid gzip index checkpoint count"));
+        }
+        let mut checkpoints: Vec<Checkpoint> = Vec::new();
+        for _ in 0..count {
+            let out = read_u64(r)?;
+            let bit = read_u64(r)?;
+            let mut len = [0u8; 4];
+            r.read_exact(&mut len)?;
+            let len = u32::from_le_bytes(len) as usize;
+            if len > WINDOW_SIZE
+                || out > size
+                || bit > compressed_size * 8
+                || checkpoints.last().is_some_and(|c| c.out >= out || c.bit >= bit)
+                || (checkpoints.is_empty() && out != 0)
+            {
+                return Err(invalid_data("invalid gzip index checkpoint"));
+            }
+            let mut window = vec![0u8; len];
+            r.read_exact(&mut window)?;
+            checkpoints.push(Checkpoint { out, bit, window });
+        }
+
+        Ok(Self {
+            compressed_size,
+            tail,
+            size,
+            checkpoints,
+        })
+    }
+}
+
+/// Sidecar index path: the file name with INDEX_SUFFIX appended
+fn index_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(INDEX_SUFFIX);
+    PathBuf::from(name)
+}
+
+fn load_index(path: &Path, reader: &dyn Reader) -> io::Result<GzipIndex> {
+    let index = GzipIndex::read_from(&mut BufReader::new(File::open(path)?))?;
+    let (compressed_size, tail) = read_tail(reader)?;
+    if index.compressed_size != compressed_size || index.tail != tail {
+        return Err(invalid_data("stale gzip index"));
+    }
+    Ok(index)
+}
+
+/// Write via a temporary file so readers never see a partial index
+fn save_index(path: &Path, index: &GzipIndex) -> io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    let mut writer = BufWriter::new(File::create(&tmp)?);
+    index.write_to(&mut writer)?;
+    writer.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+    fs::rename(&tmp, path)
+}
+
+/// Use a saved index when allowed and still valid, otherwise build one
+fn open_index(reader: &Arc<dyn Reader + Send + Sync>) -> io::Result<GzipIndex> {
+    let sidecar = if persist_indexes() {
+        reader.path().map(index_path)
+    } else {
+        None
+    };
+    if let Some(path) = &sidecar {
+        if let Ok(index) = load_index(path, &**reader) {
+            return Ok(index);
+        }
+    }
+
+    let index = build_index(reader, SPAN)?;
+    if let Some(path) = &sidecar {
+        // Best effort: a read-only directory just means no index next time
+        let _ = save_index(path, &index);
+    }
+    Ok(index)
+}
+
+/// Reader over the decompressed stream, served from checkpoints
+pub struct GzipReader {
+    parent: Arc<dyn Reader + Send + Sync>,
+    index: GzipIndex,
+    cache: Mutex<BlockCache>,
+}
+
+impl GzipReader {
+    pub fn new(parent: Arc<dyn Reader + Send + Sync>) -> io::Result<Self> {
+        let index = open_index(&parent)?;
+        Ok(Self::with_index(parent, index))
+    }
+
+    fn with_index(parent: Arc<dyn Reader + Send + Sync>, index: GzipIndex) -> Self {
+        Self {
+            parent,
+            index,
+            cache: Mutex::new(BlockCache::new(CACHE_SPANS)),
+        }
+    }
+
+    /// Uncompressed range covered by checkpoint i
+    fn span_range(&self, i: usize) -> (u64, u64) {
+        let start = self.index.checkpoints[i].out;
+        let end = self
+            .index
+            .checkpoints
+            .get(i + 1)
+            .map_or(self.index.size, |c| c.out);
+        (start, end)
+    }
+
+    /// Inflate from checkpoint i, discarding `skip` bytes, to fill buf
+    fn inflate_from(&self, i: usize, mut skip: u64, buf: &mut [u8]) -> io::Result<()> {
+        let checkpoint = &self.index.checkpoints[i];
+        let mut inflater =
+            Inflater::new(Arc::clone(&self.parent), checkpoint.bit, &checkpoint.window)?;
+        let mut scratch = vec![0u8; 64 * 1024];
+        while skip > 0 {
+            let want = scratch.len().min(skip as usize);
+            let n = inflater.read(&mut scratch[..want])?;
+            if n == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "gzip stream shorter than index",
+                ));
+            }
+            skip -= n as u64;
+        }
+        let mut filled = 0;
+        while filled < buf.len() {
+            let n = inflater.read(&mut buf[filled..])?;
+            if n == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "gzip stream shorter than index",
+                ));
+            }
+            filled += n;
+        }
+        Ok(())
+    }
+
+    fn span(&self, i: usize) -> io::Result<Arc<Vec<u8>>> {
+        let mut cache = self
+            .cache
+            .lock()
+            .map_err(|_| io::Error::other("gzip cache lock poisoned"))?;
+        if let Some(data) = cache.get(i as u64) {
+            return Ok(data);
+        }
+        let (start, end) = self.span_range(i);
+        let mut data = vec![0u8; (end - start) as usize];
+        self.inflate_from(i, 0, &mut data)?;
+        let data = Arc::new(data);
+        cache.insert(i as u64, Arc::clone(&data));
+        Ok(data)
+    }
+}
+
+impl Reader for GzipReader {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        if offset >= self.index.size {
+            return Ok(0);
+        }
+
+        let i = self.index.checkpoints.partition_point(|c| c.out <= offset) - 1;
+        let (start, end) = self.span_range(i);
+        let to_read = buf.len().min((end - offset) as usize);
+
+        if end - start <= MAX_CACHED_SPAN {
+            let data = self.span(i)?;
+            let from = (offset - start) as usize;
+            buf[..to_read].copy_from_slice(&data[from..from + to_read]);
+        } else {
+            self.inflate_from(i, offset - start, &mut buf[..to_read])?;
+        }
+        Ok(to_read)
+    }
+
+    fn size(&self) -> Option<u64> {
+        Some(self.index.size)
+    }
+}
+
+// SAFETY: GzipReader holds an Arc'd parent, owned index, and a Mutex
+unsafe impl Send for GzipReader {}
+unsafe impl Sync for GzipReader {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::BytesReader;
+    use flate2::{Compression, GzBuilder};
+
+    fn sample() -> Vec<u8> {
+        let mut seed = 3u32;
+        (0..1_500_000u32)
+            .map(|i| {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                if i % 4 == 0 {
+                    (seed >> 24) as u8
+                } else {
+                    (i / 512) as u8
+                }
+            })
+            .collect()
+    }
+
+    fn gzip(data: &[u8]) -> Arc<dyn Reader + Send + Sync> {
+        let mut encoder = GzBuilder::new()
+            .filename("disk.img")
+            .comment("test image")
+            .write(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        Arc::new(BytesReader::new(encoder.finish().unwrap()))
+    }
+
+    fn read(reader: &dyn Reader, offset: u64, len: usize) -> Vec<u8> {
+        let mut buf = vec![0u8; len];
+        let n = reader.read_at(offset, &mut buf).unwrap();
+        buf.truncate(n);
+        buf
+    }
+
+    #[test]
+    fn random_reads_match_source() {
+        let data = sample();
+        let compressed = gzip(&data);
+        let index = build_index(&compressed, 64 * 1024).unwrap();
+        assert!(index.checkpoints.len() > 4);
+        let reader = GzipReader::with_index(compressed, index);
+
+        assert_eq!(reader.size(), Some(data.len() as u64));
+        for (offset, len) in [(0, 512), (700_000, 4096), (123_457, 100_000), (1_499_990, 64)] {
+            let got = read(&reader, offset, len);
+            let end = (offset as usize + len).min(data.len());
+            // A read may stop at a span end; it must never return wrong bytes
+            assert!(!got.is_empty());
+            assert_eq!(got, &data[offset as usize..offset as usize + got.len()]);
+            assert!(offset as usize + got.len() <= end);
+        }
+        assert!(read(&reader, data.len() as u64, 16).is_empty());
+    }
+
+    #[test]
+    fn index_round_trips() {
+        let data = sample();
+        let compressed = gzip(&data);
+        let index = build_index(&compressed, 256 * 1024).unwrap();
+
+        let mut saved = Vec::new();
+        index.write_to(&mut saved).unwrap();
+        let loaded = GzipIndex::read_from(&mut &saved[..]).unwrap();
+        assert_eq!(loaded, index);
+
+        let reader = GzipReader::with_index(compressed, loaded);
+        assert_eq!(read(&reader, 1_000_000, 32), &data[1_000_000..1_000_032]);
+
+        saved[0] ^= 0xFF;
+        assert!(GzipIndex::read_from(&mut &saved[..]).is_err());
+    }
+
+    #[test]
+    fn reads_concatenated_members() {
+        let data = sample();
+        let (first, second) = data.split_at(600_000);
+        let mut bytes = Vec::new();
+        for part in [first, &[][..], second, &[][..]] {
+            let member = gzip(part);
+            bytes.extend(read(&*member, 0, part.len() + 1024));
+        }
+        // Tape blocking leaves zeros after the last member
+        bytes.extend_from_slice(&[0u8; 512]);
+        let compressed: Arc<dyn Reader + Send + Sync> = Arc::new(BytesReader::new(bytes));
+
+        let index = build_index(&compressed, 128 * 1024).unwrap();
+        let reader = GzipReader::with_index(compressed, index);
+        assert_eq!(reader.size(), Some(data.len() as u64));
+        assert_eq!(read(&reader, 599_990, 10), &data[599_990..600_000]);
+        assert_eq!(read(&reader, 600_000, 10), &data[600_000..600_010]);
+        assert_eq!(read(&reader, 1_499_990, 64), &data[1_499_990..]);
+    }
+
+    #[test]
+    fn rejects_crc_mismatch() {
+        let compressed = gzip(b"hello, world");
+        let mut bytes = read(&*compressed, 0, 1024);
+        let crc_at = bytes.len() - 8;
+        bytes[crc_at] ^= 1;
+        let corrupt: Arc<dyn Reader + Send + Sync> = Arc::new(BytesReader::new(bytes));
+        assert!(GZIP.children(corrupt).is_err());
+    }
+}
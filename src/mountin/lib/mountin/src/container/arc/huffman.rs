@@ -0,0 +1,134 @@
+//! Canonical Huffman decoding for MSB-first bit streams
+//!
+//! LZX (CAB, CHM) and KWAJ's LZH both assign canonical codes from a list
+//! of code lengths and store code bits most significant first. Decoders
+//! peek the next 16 bits of their stream and ask the table which symbol
+//! those bits start with and how many of them it used.
+
+use crate::container::invalid_data;
+use std::io;
+
+pub(crate) const MAX_CODE_LEN: usize = 16;
+/// Codes up to this long are resolved with one table lookup
+const FAST_BITS: usize = 10;
+
+pub(crate) struct Huffman {
+    /// (symbol, length) for each FAST_BITS prefix; length 0 for longer codes
+    fast: Vec<(u16, u8)>,
+    /// Number of codes of each length
+    counts: [u16; MAX_CODE_LEN + 1],
+    /// Symbols in code order
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    /// Build from code lengths (0 = unused). Incomplete codes are allowed;
+    /// an empty one fails only when something tries to decode with it.
+    pub(crate) fn new(lens: &[u8]) -> io::Result<Self> {
+        let mut counts = [0u16; MAX_CODE_LEN + 1];
+        for &len in lens {
+            if usize::from(len) > MAX_CODE_LEN {
+                return Err(invalid_data("Huffman code too long"));
+            }
+            counts[usize::from(len)] += 1;
+        }
+        counts[0] = 0;
+
+        let mut left = 1i32;
+        for &count in &counts[1..] {
+            left = (left << 1) - i32::from(count);
+            if left < 0 {
+                return Err(invalid_data("oversubscribed Huffman code"));
+            }
+        }
+
+        let mut symbols = Vec::with_capacity(lens.len());
+        for len in 1..=MAX_CODE_LEN {
+            for (sym, _) in lens
+                .iter()
+                .enumerate()
+                .filter(|(_, &l)| usize::from(l) == len)
+            {
+                symbols.push(sym as u16);
+            }
+        }
+
+        let mut fast = vec![(0u16, 0u8); 1 << FAST_BITS];
+        let mut code = 0usize;
+        let mut index = 0;
+        for (len, &count) in counts.iter().enumerate().take(FAST_BITS + 1).skip(1) {
+            for _ in 0..count {
+                let shift = FAST_BITS - len;
+                for entry in &mut fast[code << shift..(code + 1) << shift] {
+                    *entry = (symbols[index], len as u8);
+                }
+                code += 1;
+                index += 1;
+            }
+            code <<= 1;
+        }
+
+        Ok(Self {
+            fast,
+            counts,
+            symbols,
+        })
+    }
+
+    /// Decode the symbol at the top of `bits` (the next 16 stream bits,
+    /// first bit in bit 15), returning it and its code length
+    pub(crate) fn decode(&self, bits: u32) -> io::Result<(u16, u32)> {
+        let (sym, len) = self.fast[(bits >> (MAX_CODE_LEN - FAST_BITS)) as usize];
+        if len != 0 {
+            return Ok((sym, u32::from(len)));
+        }
+
+        // Walk the remaining lengths, as puff does
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for len in 1..=MAX_CODE_LEN {
+            code |= ((bits >> (MAX_CODE_LEN - len)) & 1) as i32;
+            let count = i32::from(self.counts[len]);
+            if code - first < count {
+                return Ok((self.symbols[(index + code - first) as usize], len as u32));
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(invalid_data("invalid Huffman code"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_canonical_codes() {
+        // Lengths 2,1,3,3 give codes 10, 0, 110, 111
+        let tree = Huffman::new(&[2, 1, 3, 3]).unwrap();
+        assert_eq!(tree.decode(0b0 << 15).unwrap(), (1, 1));
+        assert_eq!(tree.decode(0b10 << 14).unwrap(), (0, 2));
+        assert_eq!(tree.decode(0b110 << 13).unwrap(), (2, 3));
+        assert_eq!(tree.decode(0b111 << 13).unwrap(), (3, 3));
+    }
+
+    #[test]
+    fn decodes_long_codes() {
+        // One code of each length 1..=15, then two of length 16
+        let mut lens: Vec<u8> = (1..=15).collect();
+        lens.extend([16, 16]);
+        let tree = Huffman::new(&lens).unwrap();
+        assert_eq!(tree.decode(0xFFFE).unwrap(), (15, 16));
+        assert_eq!(tree.decode(0xFFFF).unwrap(), (16, 16));
+        assert_eq!(tree.decode(0xFFF0).unwrap(), (12, 13));
+    }
+
+    #[test]
+    fn rejects_bad_codes() {
+        assert!(Huffman::new(&[1, 1, 1]).is_err());
+        assert!(Huffman::new(&[17]).is_err());
+        let empty = Huffman::new(&[0, 0]).unwrap();
+        assert!(empty.decode(0).is_err());
+    }
+}
@@ -0,0 +1,558 @@
+//! Streaming DEFLATE (RFC 1951) decoder
+//!
+//! flate2 can only decode a stream from its start. Random access into gzip
+//! (and chained MSZIP blocks in CAB) needs to resume at an arbitrary bit
+//! offset with a preloaded 32 KiB window, and to know where block
+//! boundaries fall so resume points can be recorded. This decoder reads
+//! from a positional Reader and exposes both.
+
+use crate::container::invalid_data;
+use crate::detect::Reader;
+use std::io;
+use std::sync::Arc;
+
+/// DEFLATE history window
+pub(crate) const WINDOW_SIZE: usize = 32 * 1024;
+
+const WINDOW_MASK: usize = WINDOW_SIZE - 1;
+const MAX_CODE_BITS: usize = 15;
+/// Codes up to this length decode with one table lookup
+const FAST_BITS: u32 = 10;
+const INPUT_CHUNK: usize = 64 * 1024;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115,
+    131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+/// Order code length code lengths are sent in
+const CLEN_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+/// LSB-first bit reader over a positional Reader
+struct BitReader {
+    reader: Arc<dyn Reader + Send + Sync>,
+    buf: Vec<u8>,
+    buf_pos: usize,
+    /// Reader offset of buf[0]
+    buf_offset: u64,
+    bits: u64,
+    nbits: u32,
+}
+
+impl BitReader {
+    fn new(reader: Arc<dyn Reader + Send + Sync>, bit_offset: u64) -> io::Result<Self> {
+        let mut input = Self {
+            reader,
+            buf: Vec::new(),
+            buf_pos: 0,
+            buf_offset: bit_offset / 8,
+            bits: 0,
+            nbits: 0,
+        };
+        let skip = (bit_offset % 8) as u32;
+        if skip > 0 {
+            input.need(skip)?;
+            input.consume(skip);
+        }
+        Ok(input)
+    }
+
+    /// Bit position in the reader of the next unread bit
+    fn position(&self) -> u64 {
+        (self.buf_offset + self.buf_pos as u64) * 8 - u64::from(self.nbits)
+    }
+
+    fn fill_buf(&mut self) -> io::Result<bool> {
+        self.buf_offset += self.buf.len() as u64;
+        self.buf.resize(INPUT_CHUNK, 0);
+        self.buf_pos = 0;
+        let n = self.reader.read_at(self.buf_offset, &mut self.buf)?;
+        self.buf.truncate(n);
+        Ok(n > 0)
+    }
+
+    fn refill(&mut self) -> io::Result<()> {
+        while self.nbits <= 56 {
+            if self.buf_pos == self.buf.len() && !self.fill_buf()? {
+                break;
+            }
+            self.bits |= u64::from(self.buf[self.buf_pos]) << self.nbits;
+            self.buf_pos += 1;
+            self.nbits += 8;
+        }
+        Ok(())
+    }
+
+    fn need(&mut self, n: u32) -> io::Result<()> {
+        if self.nbits < n {
+            self.refill()?;
+            if self.nbits < n {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "deflate stream truncated",
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn peek(&self, n: u32) -> u32 {
+        (self.bits & ((1u64 << n) - 1)) as u32
+    }
+
+    fn consume(&mut self, n: u32) {
+        self.bits >>= n;
+        self.nbits -= n;
+    }
+
+    fn bits(&mut self, n: u32) -> io::Result<u32> {
+        if n == 0 {
+            return Ok(0);
+        }
+        self.need(n)?;
+        let value = self.peek(n);
+        self.consume(n);
+        Ok(value)
+    }
+
+    fn align_to_byte(&mut self) {
+        let extra = self.nbits % 8;
+        self.consume(extra);
+    }
+
+    /// Read whole bytes after align_to_byte
+    fn read_bytes(&mut self, out: &mut [u8]) -> io::Result<()> {
+        let mut filled = 0;
+        while filled < out.len() && self.nbits >= 8 {
+            out[filled] = self.bits as u8;
+            self.consume(8);
+            filled += 1;
+        }
+        while filled < out.len() {
+            if self.buf_pos == self.buf.len() && !self.fill_buf()? {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "deflate stored block truncated",
+                ));
+            }
+            let n = (out.len() - filled).min(self.buf.len() - self.buf_pos);
+            out[filled..filled + n].copy_from_slice(&self.buf[self.buf_pos..self.buf_pos + n]);
+            self.buf_pos += n;
+            filled += n;
+        }
+        Ok(())
+    }
+}
+
+/// Canonical Huffman decoding table
+struct Huffman {
+    /// Number of codes of each length
+    counts: [u16; MAX_CODE_BITS + 1],
+    /// Symbols ordered by code
+    symbols: Vec<u16>,
+    /// Indexed by the next FAST_BITS input bits: symbol << 4 | length, or 0
+    fast: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> io::Result<Self> {
+        let mut counts = [0u16; MAX_CODE_BITS + 1];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        counts[0] = 0;
+
+        // Reject over-subscribed codes; incomplete ones fail on use
+        let mut left = 1i32;
+        for &count in &counts[1..] {
+            left = (left << 1) - i32::from(count);
+            if left < 0 {
+                return Err(invalid_data("deflate huffman code over-subscribed"));
+            }
+        }
+
+        let mut offsets = [0u16; MAX_CODE_BITS + 2];
+        for len in 1..=MAX_CODE_BITS {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0u16; offsets[MAX_CODE_BITS + 1] as usize];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len > 0 {
+                symbols[offsets[len as usize] as usize] = symbol as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+
+        let mut fast = vec![0u16; 1 << FAST_BITS];
+        let mut code = 0u32;
+        let mut index = 0usize;
+        for len in 1..=FAST_BITS {
+            for _ in 0..counts[len as usize] {
+                let reversed = code.reverse_bits() >> (32 - len);
+                let entry = symbols[index] << 4 | len as u16;
+                let mut slot = reversed as usize;
+                while slot < fast.len() {
+                    fast[slot] = entry;
+                    slot += 1 << len;
+                }
+                code += 1;
+                index += 1;
+            }
+            code <<= 1;
+        }
+
+        Ok(Self { counts, symbols, fast })
+    }
+
+    fn decode(&self, input: &mut BitReader) -> io::Result<u16> {
+        if input.nbits < MAX_CODE_BITS as u32 {
+            input.refill()?;
+        }
+        let entry = self.fast[input.peek(FAST_BITS.min(input.nbits)) as usize];
+        let len = u32::from(entry & 0xF);
+        if len > 0 && len <= input.nbits {
+            input.consume(len);
+            return Ok(entry >> 4);
+        }
+
+        // Slow path: walk the canonical code one bit at a time
+        let mut code = 0i32;
+        let mut first = 0i32;
+        let mut index = 0i32;
+        for len in 1..=MAX_CODE_BITS {
+            code |= input.bits(1)? as i32;
+            let count = i32::from(self.counts[len]);
+            if code - count < first {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(invalid_data("deflate invalid huffman code"))
+    }
+}
+
+enum State {
+    /// Between blocks, before the next block header
+    Header,
+    Stored(usize),
+    Codes,
+    Done,
+}
+
+/// Streaming DEFLATE decoder that can start mid-stream
+pub(crate) struct Inflater {
+    input: BitReader,
+    state: State,
+    last_block: bool,
+    lit: Huffman,
+    dist: Huffman,
+    window: Vec<u8>,
+    window_pos: usize,
+    /// Bytes of valid history in the window
+    history: usize,
+    copy_len: usize,
+    copy_dist: usize,
+}
+
+impl Inflater {
+    /// Start decoding at a block boundary, with any preceding output as history
+    pub(crate) fn new(
+        reader: Arc<dyn Reader + Send + Sync>,
+        bit_offset: u64,
+        dictionary: &[u8],
+    ) -> io::Result<Self> {
+        let mut inflater = Self {
+            input: BitReader::new(reader, bit_offset)?,
+            state: State::Header,
+            last_block: false,
+            lit: Huffman::new(&[])?,
+            dist: Huffman::new(&[])?,
+            window: vec![0u8; WINDOW_SIZE],
+            window_pos: 0,
+            history: 0,
+            copy_len: 0,
+            copy_dist: 0,
+        };
+        let dictionary = &dictionary[dictionary.len().saturating_sub(WINDOW_SIZE)..];
+        for &b in dictionary {
+            inflater.push(b);
+        }
+        Ok(inflater)
+    }
+
+    /// Bit offset of the next unread input bit
+    pub(crate) fn bit_position(&self) -> u64 {
+        self.input.position()
+    }
+
+    /// True between blocks, where decoding can be resumed with `new`
+    pub(crate) fn at_block_boundary(&self) -> bool {
+        matches!(self.state, State::Header) && !self.last_block
+    }
+
+    /// The last (up to) 32 KiB of output, oldest first
+    pub(crate) fn window(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.history);
+        let start = self.window_pos.wrapping_sub(self.history) & WINDOW_MASK;
+        for i in 0..self.history {
+            out.push(self.window[(start + i) & WINDOW_MASK]);
+        }
+        out
+    }
+
+    fn push(&mut self, b: u8) {
+        self.window[self.window_pos] = b;
+        self.window_pos = (self.window_pos + 1) & WINDOW_MASK;
+        if self.history < WINDOW_SIZE {
+            self.history += 1;
+        }
+    }
+
+    /// Decode into buf. Returns early at the end of a block once some
+    /// output has been produced, and 0 at the end of the stream.
+    pub(crate) fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut n = 0;
+        while n < buf.len() {
+            if self.copy_len > 0 {
+                let b = self.window[self.window_pos.wrapping_sub(self.copy_dist) & WINDOW_MASK];
+                self.push(b);
+                buf[n] = b;
+                n += 1;
+                self.copy_len -= 1;
+                continue;
+            }
+            match self.state {
+                State::Done => break,
+                State::Header => {
+                    if n > 0 {
+                        break;
+                    }
+                    if self.last_block {
+                        self.input.align_to_byte();
+                        self.state = State::Done;
+                        continue;
+                    }
+                    self.read_header()?;
+                }
+                State::Stored(remaining) => {
+                    if remaining == 0 {
+                        self.state = State::Header;
+                        continue;
+                    }
+                    let count = remaining.min(buf.len() - n);
+                    self.input.read_bytes(&mut buf[n..n + count])?;
+                    for &b in &buf[n..n + count] {
+                        self.push(b);
+                    }
+                    n += count;
+                    self.state = State::Stored(remaining - count);
+                }
+                State::Codes => {
+                    let symbol = self.lit.decode(&mut self.input)?;
+                    match symbol {
+                        0..=255 => {
+                            self.push(symbol as u8);
+                            buf[n] = symbol as u8;
+                            n += 1;
+                        }
+                        256 => self.state = State::Header,
+                        257..=285 => {
+                            let i = (symbol - 257) as usize;
+                            let len = LENGTH_BASE[i] as usize
+                                + self.input.bits(u32::from(LENGTH_EXTRA[i]))? as usize;
+                            let d = self.dist.decode(&mut self.input)? as usize;
+                            if d >= DIST_BASE.len() {
+                                return Err(invalid_data("deflate invalid distance code"));
+                            }
+                            let dist = DIST_BASE[d] as usize
+                                + self.input.bits(u32::from(DIST_EXTRA[d]))? as usize;
+                            if dist > self.history {
+                                return Err(invalid_data("deflate distance too far back"));
+                            }
+                            self.copy_len = len;
+                            self.copy_dist = dist;
+                        }
+                        _ => return Err(invalid_data("deflate invalid literal/length code")),
+                    }
+                }
+            }
+        }
+        Ok(n)
+    }
+
+    fn read_header(&mut self) -> io::Result<()> {
+        self.last_block = self.input.bits(1)? == 1;
+        match self.input.bits(2)? {
+            0 => {
+                self.input.align_to_byte();
+                let mut header = [0u8; 4];
+                self.input.read_bytes(&mut header)?;
+                let len = u16::from_le_bytes([header[0], header[1]]);
+                let nlen = u16::from_le_bytes([header[2], header[3]]);
+                if len != !nlen {
+                    return Err(invalid_data("deflate stored block length mismatch"));
+                }
+                self.state = State::Stored(len as usize);
+            }
+            1 => {
+                let mut lengths = [0u8; 288];
+                lengths[..144].fill(8);
+                lengths[144..256].fill(9);
+                lengths[256..280].fill(7);
+                lengths[280..].fill(8);
+                self.lit = Huffman::new(&lengths)?;
+                self.dist = Huffman::new(&[5; 30])?;
+                self.state = State::Codes;
+            }
+            2 => {
+                self.read_dynamic_tables()?;
+                self.state = State::Codes;
+            }
+            _ => return Err(invalid_data("deflate invalid block type")),
+        }
+        Ok(())
+    }
+
+    fn read_dynamic_tables(&mut self) -> io::Result<()> {
+        let nlen = self.input.bits(5)? as usize + 257;
+        let ndist = self.input.bits(5)? as usize + 1;
+        let ncode = self.input.bits(4)? as usize + 4;
+        if nlen > 286 || ndist > 30 {
+            return Err(invalid_data("deflate bad table counts"));
+        }
+
+        let mut clens = [0u8; 19];
+        for &i in &CLEN_ORDER[..ncode] {
+            clens[i] = self.input.bits(3)? as u8;
+        }
+        let clen_code = Huffman::new(&clens)?;
+
+        let mut lengths = [0u8; 286 + 30];
+        let mut i = 0;
+        while i < nlen + ndist {
+            let symbol = clen_code.decode(&mut self.input)?;
+            let (value, repeat) = match symbol {
+                0..=15 => (symbol as u8, 1),
+                16 => {
+                    if i == 0 {
+                        return Err(invalid_data("deflate repeat with no previous length"));
+                    }
+                    (lengths[i - 1], 3 + self.input.bits(2)? as usize)
+                }
+                17 => (0, 3 + self.input.bits(3)? as usize),
+                _ => (0, 11 + self.input.bits(7)? as usize),
+            };
+            if i + repeat > nlen + ndist {
+                return Err(invalid_data("deflate too many code lengths"));
+            }
+            lengths[i..i + repeat].fill(value);
+            i += repeat;
+        }
+        if lengths[256] == 0 {
+            return Err(invalid_data("deflate missing end-of-block code"));
+        }
+
+        self.lit = Huffman::new(&lengths[..nlen])?;
+        self.dist = Huffman::new(&lengths[nlen..nlen + ndist])?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::BytesReader;
+    use flate2::{write::DeflateEncoder, Compression};
+    use std::io::Write;
+
+    fn sample() -> Vec<u8> {
+        let mut seed = 7u32;
+        (0..300_000)
+            .map(|i| {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                if i % 3 == 0 {
+                    (seed >> 24) as u8
+                } else {
+                    b"the quick brown fox "[i % 20]
+                }
+            })
+            .collect()
+    }
+
+    fn deflate(data: &[u8], level: u32) -> Vec<u8> {
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::new(level));
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn inflate_all(inflater: &mut Inflater) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut buf = [0u8; 4096];
+        loop {
+            let n = inflater.read(&mut buf).unwrap();
+            if n == 0 {
+                return out;
+            }
+            out.extend_from_slice(&buf[..n]);
+        }
+    }
+
+    #[test]
+    fn matches_flate2_at_every_level() {
+        let data = sample();
+        for level in [0, 1, 6, 9] {
+            let compressed = Arc::new(BytesReader::new(deflate(&data, level)));
+            let mut inflater = Inflater::new(compressed, 0, &[]).unwrap();
+            assert_eq!(inflate_all(&mut inflater), data, "level {level}");
+        }
+    }
+
+    #[test]
+    fn resumes_from_block_boundary() {
+        let data = sample();
+        let compressed: Arc<dyn Reader + Send + Sync> =
+            Arc::new(BytesReader::new(deflate(&data, 6)));
+        let mut inflater = Inflater::new(Arc::clone(&compressed), 0, &[]).unwrap();
+
+        // Run to the second block boundary, then restart a fresh decoder there
+        let mut produced = Vec::new();
+        let mut buf = [0u8; 4096];
+        let mut boundaries = 0;
+        while boundaries < 2 {
+            let n = inflater.read(&mut buf).unwrap();
+            assert!(n > 0, "stream should have several blocks");
+            produced.extend_from_slice(&buf[..n]);
+            if inflater.at_block_boundary() {
+                boundaries += 1;
+            }
+        }
+
+        let mut resumed =
+            Inflater::new(compressed, inflater.bit_position(), &inflater.window()).unwrap();
+        produced.extend(inflate_all(&mut resumed));
+        assert_eq!(produced, data);
+    }
+
+    #[test]
+    fn rejects_corrupt_streams() {
+        // Reserved block type 3
+        let reader = Arc::new(BytesReader::new(vec![0x07, 0x00]));
+        assert!(Inflater::new(reader, 0, &[]).unwrap().read(&mut [0u8; 16]).is_err());
+
+        // Stored block with mismatched length complement
+        let reader = Arc::new(BytesReader::new(vec![0x01, 0x05, 0x00, 0x00, 0x00]));
+        assert!(Inflater::new(reader, 0, &[]).unwrap().read(&mut [0u8; 16]).is_err());
+    }
+}
@@ -0,0 +1,427 @@
+//! LHA (LZH) archive reader
+//!
+//! Members follow each other, each with a header naming its method
+//! (`-lh5-` and so on). There are four header levels:
+//!
+//! - 0 and 1: a header of up to 257 bytes with an 8-bit checksum and the
+//!   path inline; level 1 adds extended headers, counted in the size
+//!   field that precedes the data
+//! - 2: a 16-bit total header size and extended headers only
+//! - 3: as level 2 with 32-bit sizes
+//!
+//! Extended headers carry the file name (type 1) and directory (type 2,
+//! components separated by 0xFF). Stored members are slices of the
+//! archive; compressed ones are decoded when first read and checked
+//! against their CRC-16. Amiga archives often hold ADF or DMS disk images,
+//! which detection then finds inside the members.
+//!
+//! LArc's -lzs- and -lz5- are not supported.
+
+use super::lzh::{decode_lh1, decode_lh2, decode_lh3, decode_static};
+use super::sea::crc16;
+use crate::container::{
+    invalid_data, le16, le32, read_exact_at, slice::SliceReader, Child, Container, DeferredReader,
+    Entry, MAX_SIZE,
+};
+use crate::detect::Reader;
+use std::io;
+use std::sync::Arc;
+
+const METHOD_SIZE: usize = 5;
+/// Fixed part of every header, up to and including the level byte
+const BASE_SIZE: usize = 21;
+const LEVEL2_BASE_SIZE: usize = 26;
+const LEVEL3_BASE_SIZE: usize = 32;
+
+const EXT_FILENAME: u8 = 0x01;
+const EXT_DIRECTORY: u8 = 0x02;
+/// Directory separator in level 2 and 3 directory headers
+const DIR_SEPARATOR: u8 = 0xFF;
+
+/// LHA container - exposes each file as a child
+pub struct LhaContainer;
+
+/// Static instance for registry
+pub static LHA: LhaContainer = LhaContainer;
+
+impl Container for LhaContainer {
+    fn children(&self, reader: Arc<dyn Reader + Send + Sync>) -> io::Result<Vec<Child>> {
+        self.entries(reader)?.into_iter().map(Entry::open).collect()
+    }
+
+    fn entries(&self, reader: Arc<dyn Reader + Send + Sync>) -> io::Result<Vec<Entry>> {
+        let members = parse(&*reader)?;
+        let mut entries = Vec::new();
+        for (index, member) in members.into_iter().enumerate() {
+            let parent = Arc::clone(&reader);
+            let (offset, size) = (member.offset, member.size);
+            let metadata = vec![("name", member.name.clone())];
+            entries.push(Entry::new(
+                index as u32,
+                offset,
+                Some(size),
+                metadata,
+                move || {
+                    let child: Arc<dyn Reader + Send + Sync> = match member.method {
+                        Method::Stored => Arc::new(SliceReader::new(parent, offset, member.packed)),
+                        _ => Arc::new(DeferredReader::new(size, move || decode(&*parent, &member))),
+                    };
+                    Ok(child)
+                },
+            ));
+        }
+        Ok(entries)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Method {
+    Stored,
+    Directory,
+    Lh1,
+    Lh2,
+    Lh3,
+    /// Static Huffman with the distance table's size and length width
+    Static {
+        np: usize,
+        pbit: u32,
+    },
+    Unsupported,
+}
+
+impl Method {
+    fn parse(id: &[u8]) -> io::Result<Self> {
+        Ok(match id {
+            b"-lh0-" | b"-lz4-" | b"-pm0-" => Self::Stored,
+            b"-lhd-" => Self::Directory,
+            b"-lh1-" => Self::Lh1,
+            b"-lh2-" => Self::Lh2,
+            b"-lh3-" => Self::Lh3,
+            b"-lh4-" | b"-lh5-" => Self::Static { np: 14, pbit: 4 },
+            b"-lh6-" => Self::Static { np: 16, pbit: 5 },
+            b"-lh7-" => Self::Static { np: 17, pbit: 5 },
+            [b'-', b'l' | b'p', _, _, b'-'] => Self::Unsupported,
+            _ => return Err(invalid_data("invalid LHA method")),
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Member {
+    name: String,
+    method: Method,
+    /// Offset of the member's data
+    offset: u64,
+    packed: u64,
+    size: u64,
+    crc: u16,
+}
+
+/// Join path components, turning DOS and 0xFF separators into slashes
+fn path_name(bytes: &[u8]) -> String {
+    let path: Vec<u8> = bytes
+        .iter()
+        .map(|&b| {
+            if b == b'\\' || b == DIR_SEPARATOR {
+                b'/'
+            } else {
+                b
+            }
+        })
+        .collect();
+    String::from_utf8_lossy(&path).into_owned()
+}
+
+/// Names found in a chain of extended headers
+#[derive(Default)]
+struct Extended {
+    name: Option<String>,
+    directory: Option<String>,
+}
+
+impl Extended {
+    fn add(&mut self, kind: u8, data: &[u8]) {
+        // Names may carry a trailing NUL
+        let data = &data[..data.iter().position(|&b| b == 0).unwrap_or(data.len())];
+        match kind {
+            EXT_FILENAME => self.name = Some(path_name(data)),
+            EXT_DIRECTORY => self.directory = Some(path_name(data)),
+            _ => {}
+        }
+    }
+}
+
+/// Read the extended headers starting at `offset`, where each ends with
+/// the next one's size in `width` bytes; returns their total size
+fn read_extended(
+    reader: &dyn Reader,
+    mut offset: u64,
+    mut next: u64,
+    width: usize,
+    names: &mut Extended,
+) -> io::Result<u64> {
+    let mut total = 0u64;
+    while next != 0 {
+        if next < 1 + width as u64 || next > u64::from(u16::MAX) {
+            return Err(invalid_data("invalid LHA extended header size"));
+        }
+        let mut ext = vec![0u8; next as usize];
+        read_exact_at(reader, offset, &mut ext)?;
+        let (body, size) = ext.split_at(ext.len() - width);
+        names.add(body[0], &body[1..]);
+        offset += next;
+        total += next;
+        next = if width == 2 {
+            u64::from(le16(size))
+        } else {
+            u64::from(le32(size))
+        };
+    }
+    Ok(total)
+}
+
+fn parse(reader: &dyn Reader) -> io::Result<Vec<Member>> {
+    let len = reader.size().unwrap_or(u64::MAX);
+    let mut members = Vec::new();
+    let mut offset = 0u64;
+    while offset < len {
+        let mut base = [0u8; LEVEL3_BASE_SIZE];
+        let n = reader.read_at(offset, &mut base)?;
+        // A zero size byte marks the end of the archive
+        if n == 0 || base[0] == 0 {
+            break;
+        }
+        if n < BASE_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "short LHA header read",
+            ));
+        }
+
+        let method = Method::parse(&base[2..2 + METHOD_SIZE])?;
+        let packed_field = u64::from(le32(&base[7..]));
+        let size = u64::from(le32(&base[11..]));
+        let level = base[20];
+        let mut names = Extended::default();
+
+        let (data, packed, crc) = match level {
+            0 | 1 => {
+                let header_size = usize::from(base[0]) + 2;
+                let mut header = vec![0u8; header_size];
+                read_exact_at(reader, offset, &mut header)?;
+                let sum = header[2..].iter().fold(0u8, |s, &b| s.wrapping_add(b));
+                if sum != base[1] {
+                    return Err(invalid_data("LHA header checksum mismatch"));
+                }
+                let name_len = usize::from(header[21]);
+                if 22 + name_len + 2 > header_size {
+                    return Err(invalid_data("invalid LHA name length"));
+                }
+                names.name = Some(path_name(&header[22..22 + name_len]));
+                let crc = le16(&header[22 + name_len..]);
+
+                let data = offset + header_size as u64;
+                if level == 0 {
+                    (data, packed_field, crc)
+                } else {
+                    // The extended headers count towards the packed size
+                    if header_size < 22 + name_len + 5 {
+                        return Err(invalid_data("invalid LHA level 1 header"));
+                    }
+                    let next = u64::from(le16(&header[header_size - 2..]));
+                    let ext = read_extended(reader, data, next, 2, &mut names)?;
+                    let packed = packed_field
+                        .checked_sub(ext)
+                        .ok_or_else(|| invalid_data("invalid LHA level 1 header"))?;
+                    (data + ext, packed, crc)
+                }
+            }
+            2 => {
+                if n < LEVEL2_BASE_SIZE {
+                    return Err(invalid_data("truncated LHA level 2 header"));
+                }
+                let header_size = u64::from(le16(&base[0..]));
+                let next = u64::from(le16(&base[24..]));
+                let start = offset + LEVEL2_BASE_SIZE as u64;
+                let ext = read_extended(reader, start, next, 2, &mut names)?;
+                if LEVEL2_BASE_SIZE as u64 + ext > header_size {
+                    return Err(invalid_data("invalid LHA level 2 header size"));
+                }
+                (offset + header_size, packed_field, le16(&base[21..]))
+            }
+            3 => {
+                if n < LEVEL3_BASE_SIZE || le16(&base[0..]) != 4 {
+                    return Err(invalid_data("invalid LHA level 3 header"));
+                }
+                let header_size = u64::from(le32(&base[24..]));
+                let next = u64::from(le32(&base[28..]));
+                let start = offset + LEVEL3_BASE_SIZE as u64;
+                let ext = read_extended(reader, start, next, 4, &mut names)?;
+                if LEVEL3_BASE_SIZE as u64 + ext > header_size {
+                    return Err(invalid_data("invalid LHA level 3 header size"));
+                }
+                (offset + header_size, packed_field, le16(&base[21..]))
+            }
+            _ => return Err(invalid_data("unknown LHA header level")),
+        };
+        if data + packed > len {
+            return Err(invalid_data("LHA member extends past end of archive"));
+        }
+
+        if method != Method::Directory {
+            let name = names.name.unwrap_or_default();
+            let name = match names.directory {
+                Some(dir) if !dir.is_empty() => {
+                    format!("{}/{}", dir.trim_end_matches('/'), name)
+                }
+                _ => name,
+            };
+            members.push(Member {
+                name,
+                method,
+                offset: data,
+                packed,
+                size,
+                crc,
+            });
+        }
+        offset = data + packed;
+    }
+    Ok(members)
+}
+
+fn decode(reader: &dyn Reader, member: &Member) -> io::Result<Vec<u8>> {
+    if member.packed > MAX_SIZE as u64 || member.size > MAX_SIZE as u64 {
+        return Err(invalid_data("LHA member too large"));
+    }
+    let mut data = vec![0u8; member.packed as usize];
+    read_exact_at(reader, member.offset, &mut data)?;
+
+    let size = member.size as usize;
+    let out = match member.method {
+        Method::Lh1 => decode_lh1(&data, size)?,
+        Method::Lh2 => decode_lh2(&data, size)?,
+        Method::Lh3 => decode_lh3(&data, size)?,
+        Method::Static { np, pbit } => decode_static(&data, size, np, pbit)?,
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "unsupported LHA compression method",
+            ))
+        }
+    };
+    if crc16(&out) != member.crc {
+        return Err(invalid_data("LHA member CRC mismatch"));
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::BytesReader;
+
+    fn level0(method: &[u8; 5], name: &str, data: &[u8], contents: &[u8]) -> Vec<u8> {
+        let mut header = method.to_vec();
+        header.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        header.extend_from_slice(&(contents.len() as u32).to_le_bytes());
+        header.extend_from_slice(&[0; 4]); // time
+        header.extend_from_slice(&[0x20, 0]); // attribute, level
+        header.push(name.len() as u8);
+        header.extend_from_slice(name.as_bytes());
+        header.extend_from_slice(&crc16(contents).to_le_bytes());
+        let sum = header.iter().fold(0u8, |s, &b| s.wrapping_add(b));
+        let mut out = vec![header.len() as u8, sum];
+        out.extend(header);
+        out.extend_from_slice(data);
+        out
+    }
+
+    fn level2(method: &[u8; 5], path: (&[u8], &str), data: &[u8]) -> Vec<u8> {
+        let mut ext = Vec::new();
+        let mut add = |kind: u8, body: &[u8]| {
+            let size = (body.len() + 3) as u16;
+            ext.extend_from_slice(&size.to_le_bytes());
+            ext.push(kind);
+            ext.extend_from_slice(body);
+        };
+        add(EXT_DIRECTORY, path.0);
+        add(EXT_FILENAME, path.1.as_bytes());
+        ext.extend_from_slice(&[0, 0]);
+
+        // The first size field sits in the base header
+        let header_size = LEVEL2_BASE_SIZE - 2 + ext.len();
+        let mut out = (header_size as u16).to_le_bytes().to_vec();
+        out.extend_from_slice(method);
+        out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        out.extend_from_slice(&[0; 4]);
+        out.extend_from_slice(&[0x20, 2]);
+        out.extend_from_slice(&crc16(data).to_le_bytes());
+        out.push(b'U');
+        out.extend(ext);
+        out.extend_from_slice(data);
+        out
+    }
+
+    fn read(child: &Child) -> Vec<u8> {
+        let mut buf = vec![0u8; child.reader.size().unwrap() as usize];
+        child.reader.read_at(0, &mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn reads_level0_and_level2_headers() {
+        let mut archive = level0(b"-lh0-", "DOCS\\README", b"hello", b"hello");
+        archive.extend(level2(b"-lh0-", (b"a\xFFb", "c.adf"), b"disk"));
+        archive.push(0);
+
+        let children = LHA.children(Arc::new(BytesReader::new(archive))).unwrap();
+        let names: Vec<_> = children.iter().map(|c| c.metadata[0].1.as_str()).collect();
+        assert_eq!(names, ["DOCS/README", "a/b/c.adf"]);
+        assert_eq!(read(&children[0]), b"hello");
+        assert_eq!(read(&children[1]), b"disk");
+    }
+
+    #[test]
+    fn decodes_and_checks_crc() {
+        // A -lh5- block with one literal table entry: every symbol is 'z'
+        // with no bits, so the data is all table
+        let mut bits = Vec::new();
+        let mut put = |value: u32, n: u32| {
+            for i in (0..n).rev() {
+                bits.push(((value >> i) & 1) as u8);
+            }
+        };
+        put(3, 16); // block size
+        put(0, 5);
+        put(0, 5); // length-code table: unused single symbol
+        put(0, 9);
+        put(u32::from(b'z'), 9); // literal table: 'z'
+        put(0, 4);
+        put(0, 4); // distance table: unused
+        let data: Vec<u8> = bits
+            .chunks(8)
+            .map(|c| {
+                c.iter()
+                    .enumerate()
+                    .fold(0, |b, (i, &bit)| b | (bit << (7 - i)))
+            })
+            .collect();
+
+        let mut archive = level0(b"-lh5-", "Z", &data, b"zzz");
+        archive.extend(level0(b"-lh5-", "BAD", &data, b"zzy"));
+        archive.push(0);
+        let children = LHA.children(Arc::new(BytesReader::new(archive))).unwrap();
+        assert_eq!(read(&children[0]), b"zzz");
+        let mut buf = [0u8; 3];
+        assert!(children[1].reader.read_at(0, &mut buf).is_err());
+    }
+
+    #[test]
+    fn rejects_bad_checksum() {
+        let mut archive = level0(b"-lh0-", "A", b"a", b"a");
+        archive[1] ^= 1;
+        assert!(LHA.children(Arc::new(BytesReader::new(archive))).is_err());
+    }
+}
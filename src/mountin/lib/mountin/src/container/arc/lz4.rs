@@ -1,12 +1,37 @@
 //! LZ4 container reader
 //!
 //! LZ4 frame format - fast compression used in ZFS, Linux kernel, etc.
+//! A file may hold several frames back to back (`cat`, `lz4 -m`), frames
+//! in the legacy format written by `lz4 -l`, and skippable frames carrying
+//! metadata. All data frames are decoded in order; skippable frames are
+//! stepped over. Checksums are not verified.
 
-use crate::container::{read_all, read_to_end_limited, BytesReader, Child, Container};
+use crate::container::{invalid_data, read_all, BytesReader, Child, Container, MAX_SIZE};
 use crate::detect::Reader;
 use std::io;
 use std::sync::Arc;
 
+const FRAME_MAGIC: u32 = 0x184D_2204;
+const LEGACY_MAGIC: u32 = 0x184C_2102;
+/// Skippable frames use 0x184D2A50 to 0x184D2A5F
+const SKIPPABLE_MAGIC: u32 = 0x184D_2A50;
+const SKIPPABLE_MASK: u32 = 0xFFFF_FFF0;
+
+/// Legacy blocks always decompress to at most 8 MiB
+const LEGACY_BLOCK_SIZE: usize = 8 * 1024 * 1024;
+/// Linked blocks may refer back this far into earlier blocks
+const WINDOW_SIZE: usize = 64 * 1024;
+
+const FLG_VERSION_MASK: u8 = 0xC0;
+const FLG_VERSION: u8 = 0x40;
+const FLG_INDEPENDENT: u8 = 0x20;
+const FLG_BLOCK_CHECKSUM: u8 = 0x10;
+const FLG_CONTENT_SIZE: u8 = 0x08;
+const FLG_CONTENT_CHECKSUM: u8 = 0x04;
+const FLG_DICT_ID: u8 = 0x01;
+/// High bit of a block size marks an uncompressed block
+const BLOCK_STORED: u32 = 0x8000_0000;
+
 /// LZ4 container - decompresses content to expose inner stream
 pub struct Lz4Container;
 
@@ -16,14 +41,241 @@ pub static LZ4: Lz4Container = Lz4Container;
 impl Container for Lz4Container {
     fn children(&self, reader: Arc<dyn Reader + Send + Sync>) -> io::Result<Vec<Child>> {
         let compressed = read_all(&*reader)?;
-
-        let decoder = lz4_flex::frame::FrameDecoder::new(&compressed[..]);
-        let decompressed = read_to_end_limited(decoder)?;
+        let decompressed = decompress(&compressed)?;
 
         Ok(vec![Child {
             index: 0,
             offset: u64::MAX, // Transformed data, not a slice
             reader: Arc::new(BytesReader::new(decompressed)),
+            metadata: Vec::new(),
         }])
     }
 }
+
+fn read_u32(data: &[u8], at: usize) -> Option<u32> {
+    let bytes = data.get(at..at + 4)?;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn is_magic(value: u32) -> bool {
+    value == FRAME_MAGIC || value == LEGACY_MAGIC || value & SKIPPABLE_MASK == SKIPPABLE_MAGIC
+}
+
+fn take(data: &[u8], pos: &mut usize, len: usize) -> io::Result<std::ops::Range<usize>> {
+    let end = pos
+        .checked_add(len)
+        .filter(|&end| end <= data.len())
+        .ok_or_else(|| invalid_data("truncated lz4 frame"))?;
+    let range = *pos..end;
+    *pos = end;
+    Ok(range)
+}
+
+fn append(out: &mut Vec<u8>, data: &[u8]) -> io::Result<()> {
+    if out.len().saturating_add(data.len()) > MAX_SIZE {
+        return Err(invalid_data("decompressed container too large"));
+    }
+    out.extend_from_slice(data);
+    Ok(())
+}
+
+fn block_error(e: lz4_flex::block::DecompressError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("lz4 block: {e}"))
+}
+
+/// Decode every frame in the file
+fn decompress(data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut out = Vec::new();
+    let mut pos = 0;
+    let mut frames = 0;
+
+    while let Some(magic) = read_u32(data, pos) {
+        if magic == FRAME_MAGIC {
+            pos = decode_frame(data, pos + 4, &mut out)?;
+        } else if magic == LEGACY_MAGIC {
+            pos = decode_legacy(data, pos + 4, &mut out)?;
+        } else if magic & SKIPPABLE_MASK == SKIPPABLE_MAGIC {
+            let len = read_u32(data, pos + 4)
+                .ok_or_else(|| invalid_data("truncated lz4 skippable frame"))?;
+            pos += 8;
+            take(data, &mut pos, len as usize)?;
+        } else if frames > 0 {
+            // Like the lz4 tool, ignore undecodable data after a frame
+            break;
+        } else {
+            return Err(invalid_data("invalid lz4 magic"));
+        }
+        frames += 1;
+    }
+    if frames == 0 {
+        return Err(invalid_data("no lz4 frames"));
+    }
+    Ok(out)
+}
+
+/// Decode a frame starting after its magic, returning the offset after it
+fn decode_frame(data: &[u8], mut pos: usize, out: &mut Vec<u8>) -> io::Result<usize> {
+    let header = take(data, &mut pos, 2)?;
+    let (flg, bd) = (data[header.start], data[header.start + 1]);
+    if flg & FLG_VERSION_MASK != FLG_VERSION {
+        return Err(invalid_data("unsupported lz4 frame version"));
+    }
+    if flg & FLG_DICT_ID != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "lz4 frames with external dictionaries are not supported",
+        ));
+    }
+    let max_block = match (bd >> 4) & 7 {
+        4 => 64 * 1024,
+        5 => 256 * 1024,
+        6 => 1024 * 1024,
+        7 => 4 * 1024 * 1024,
+        _ => return Err(invalid_data("invalid lz4 block size")),
+    };
+    if flg & FLG_CONTENT_SIZE != 0 {
+        take(data, &mut pos, 8)?;
+    }
+    take(data, &mut pos, 1)?; // header checksum
+
+    let linked = flg & FLG_INDEPENDENT == 0;
+    let block_checksum = if flg & FLG_BLOCK_CHECKSUM != 0 { 4 } else { 0 };
+    let frame_start = out.len();
+    let mut block = vec![0u8; max_block];
+    loop {
+        let size = read_u32(data, pos).ok_or_else(|| invalid_data("truncated lz4 frame"))?;
+        pos += 4;
+        if size == 0 {
+            break;
+        }
+        let len = (size & !BLOCK_STORED) as usize;
+        if len > max_block {
+            return Err(invalid_data("lz4 block too large"));
+        }
+        let input = &data[take(data, &mut pos, len)?];
+        take(data, &mut pos, block_checksum)?;
+
+        if size & BLOCK_STORED != 0 {
+            append(out, input)?;
+        } else {
+            let window = if linked {
+                &out[frame_start.max(out.len().saturating_sub(WINDOW_SIZE))..]
+            } else {
+                &[]
+            };
+            let n = lz4_flex::block::decompress_into_with_dict(input, &mut block, window)
+                .map_err(block_error)?;
+            append(out, &block[..n])?;
+        }
+    }
+    if flg & FLG_CONTENT_CHECKSUM != 0 {
+        take(data, &mut pos, 4)?;
+    }
+    Ok(pos)
+}
+
+/// Decode legacy blocks starting after the magic, up to the next frame
+fn decode_legacy(data: &[u8], mut pos: usize, out: &mut Vec<u8>) -> io::Result<usize> {
+    let mut block = vec![0u8; LEGACY_BLOCK_SIZE];
+    // The format has no end mark: it ends at end of file or at a block
+    // "size" that is really the magic of the next frame
+    while let Some(size) = read_u32(data, pos) {
+        if is_magic(size) {
+            break;
+        }
+        pos += 4;
+        let input = &data[take(data, &mut pos, size as usize)?];
+        let n = lz4_flex::block::decompress_into(input, &mut block).map_err(block_error)?;
+        append(out, &block[..n])?;
+    }
+    Ok(pos)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lz4_flex::block::compress;
+    use lz4_flex::frame::{BlockMode, BlockSize, FrameEncoder, FrameInfo};
+
+    fn frame(blocks: &[&[u8]], flg: u8) -> Vec<u8> {
+        let mut out = FRAME_MAGIC.to_le_bytes().to_vec();
+        out.extend_from_slice(&[FLG_VERSION | FLG_INDEPENDENT | flg, 0x40]);
+        if flg & FLG_CONTENT_SIZE != 0 {
+            let total: usize = blocks.iter().map(|b| b.len()).sum();
+            out.extend_from_slice(&(total as u64).to_le_bytes());
+        }
+        out.push(0); // header checksum, not verified
+        for (i, block) in blocks.iter().enumerate() {
+            // Alternate compressed and stored blocks
+            let (size, payload) = if i % 2 == 0 {
+                let packed = compress(block);
+                (packed.len() as u32, packed)
+            } else {
+                (block.len() as u32 | BLOCK_STORED, block.to_vec())
+            };
+            out.extend_from_slice(&size.to_le_bytes());
+            out.extend(payload);
+            if flg & FLG_BLOCK_CHECKSUM != 0 {
+                out.extend_from_slice(&[0; 4]);
+            }
+        }
+        out.extend_from_slice(&0u32.to_le_bytes());
+        if flg & FLG_CONTENT_CHECKSUM != 0 {
+            out.extend_from_slice(&[0; 4]);
+        }
+        out
+    }
+
+    fn legacy(blocks: &[&[u8]]) -> Vec<u8> {
+        let mut out = LEGACY_MAGIC.to_le_bytes().to_vec();
+        for block in blocks {
+            let packed = compress(block);
+            out.extend_from_slice(&(packed.len() as u32).to_le_bytes());
+            out.extend(packed);
+        }
+        out
+    }
+
+    #[test]
+    fn decodes_concatenated_frames() {
+        let mut data = frame(&[b"one ", b"two "], FLG_CONTENT_SIZE);
+        data.extend(legacy(&[b"three ", b"four "]));
+        data.extend_from_slice(&(SKIPPABLE_MAGIC + 5).to_le_bytes());
+        data.extend_from_slice(&3u32.to_le_bytes());
+        data.extend_from_slice(b"xyz");
+        data.extend(legacy(&[b"five "]));
+        data.extend(frame(
+            &[b"six ", b"seven"],
+            FLG_BLOCK_CHECKSUM | FLG_CONTENT_CHECKSUM,
+        ));
+        data.extend_from_slice(&[0; 16]); // trailing padding
+
+        assert_eq!(
+            decompress(&data).unwrap(),
+            b"one two three four five six seven"
+        );
+    }
+
+    #[test]
+    fn decodes_lz4_flex_frames() {
+        let text = b"linked blocks refer back to earlier blocks ".repeat(4000);
+        let mut data = Vec::new();
+        let info = FrameInfo::new()
+            .block_size(BlockSize::Max64KB)
+            .block_mode(BlockMode::Linked);
+        let mut encoder = FrameEncoder::with_frame_info(info, &mut data);
+        io::Write::write_all(&mut encoder, &text).unwrap();
+        encoder.finish().unwrap();
+        let copy = data.clone();
+        data.extend(copy);
+
+        assert_eq!(decompress(&data).unwrap(), [&text[..], &text[..]].concat());
+    }
+
+    #[test]
+    fn rejects_truncated_frames() {
+        let data = frame(&[b"some data"], 0);
+        assert!(decompress(&data[..data.len() - 6]).is_err());
+        assert!(decompress(b"not lz4 at all").is_err());
+    }
+}
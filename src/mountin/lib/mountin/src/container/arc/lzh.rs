@@ -0,0 +1,1022 @@
+//! LZH decompressors (LHA, ARJ and zoo)
+//!
+//! Coders from the LHarc family:
+//!
+//! - The static Huffman coder of LHA's -lh4- to -lh7- (Okumura's ar002),
+//!   which ARJ methods 1-3 and zoo's "lh5" method also use. Output is
+//!   coded in blocks, each starting with three Huffman tables: one for the
+//!   other tables' code lengths, one for literals and match lengths, and
+//!   one for the bit lengths of match distances.
+//! - -lh1-, LZHUF's adaptive Huffman coder for literals and lengths with a
+//!   fixed code for the top six bits of 4 KiB distances.
+//! - -lh2-, LHarc's experimental 8 KiB variant of -lh1-, which also codes
+//!   the top seven distance bits adaptively, adding a distance symbol for
+//!   every 64 bytes of output until the window is full.
+//! - -lh3-, the experimental static coder that came before -lh4-. Blocks
+//!   store plain 4-bit code lengths, and may use a fixed distance code.
+//!
+//! -lh2- and -lh3- follow LHa for UNIX (dhuf.c and shuf.c), whose
+//! decoders are the reference for these methods.
+//!
+//! Bits are read most significant first. History before the start of the
+//! output reads as spaces, as in LHarc's initial window.
+
+use super::huffman::Huffman;
+use crate::container::invalid_data;
+use std::io;
+
+const HISTORY_FILL: u8 = b' ';
+const THRESHOLD: usize = 3;
+
+/// Literals, then match lengths THRESHOLD..=256
+const NC: usize = 256 + 256 + 2 - THRESHOLD;
+/// Code length code symbols
+const NT: usize = 19;
+const CBIT: u32 = 9;
+const TBIT: u32 = 5;
+
+/// MSB-first bit reader that reads zeros past the end of its input
+pub(crate) struct Bits<'a> {
+    input: &'a [u8],
+    pos: usize,
+    buf: u64,
+    count: u32,
+}
+
+impl<'a> Bits<'a> {
+    pub(crate) fn new(input: &'a [u8]) -> Self {
+        Self {
+            input,
+            pos: 0,
+            buf: 0,
+            count: 0,
+        }
+    }
+
+    fn fill(&mut self) {
+        while self.count <= 56 {
+            let byte = self.input.get(self.pos).copied().unwrap_or(0);
+            self.buf |= u64::from(byte) << (56 - self.count);
+            self.count += 8;
+            self.pos += 1;
+        }
+    }
+
+    pub(crate) fn peek(&mut self, n: u32) -> u32 {
+        self.fill();
+        (self.buf >> (64 - n)) as u32
+    }
+
+    pub(crate) fn skip(&mut self, n: u32) {
+        self.buf <<= n;
+        self.count -= n;
+    }
+
+    pub(crate) fn read(&mut self, n: u32) -> u32 {
+        if n == 0 {
+            return 0;
+        }
+        let value = self.peek(n);
+        self.skip(n);
+        value
+    }
+
+    fn symbol(&mut self, tree: &Tree) -> io::Result<usize> {
+        match tree {
+            Tree::Single(sym) => Ok(*sym),
+            Tree::Codes(huffman) => {
+                let (sym, len) = huffman.decode(self.peek(16))?;
+                self.skip(len);
+                Ok(usize::from(sym))
+            }
+        }
+    }
+}
+
+/// A Huffman table, or the single symbol a table of one uses with no bits
+enum Tree {
+    Single(usize),
+    Codes(Huffman),
+}
+
+/// Output buffer that treats history before its start as spaces
+struct Output {
+    data: Vec<u8>,
+    size: usize,
+}
+
+impl Output {
+    fn new(size: usize) -> Self {
+        Self {
+            data: Vec::with_capacity(size),
+            size,
+        }
+    }
+
+    fn full(&self) -> bool {
+        self.data.len() >= self.size
+    }
+
+    fn literal(&mut self, byte: u8) {
+        self.data.push(byte);
+    }
+
+    /// Copy `len` bytes from `distance + 1` back, stopping at the end
+    fn copy(&mut self, distance: usize, len: usize) {
+        for _ in 0..len.min(self.size - self.data.len()) {
+            let byte = match self.data.len().checked_sub(distance + 1) {
+                Some(at) => self.data[at],
+                None => HISTORY_FILL,
+            };
+            self.data.push(byte);
+        }
+    }
+}
+
+/// Static Huffman decoder state
+struct Static<'a> {
+    bits: Bits<'a>,
+    np: usize,
+    pbit: u32,
+    block_left: u32,
+    c_tree: Tree,
+    p_tree: Tree,
+}
+
+impl Static<'_> {
+    /// Code lengths for the length-code table or the distance table
+    fn read_pt_len(&mut self, count: usize, nbit: u32, special: Option<usize>) -> io::Result<Tree> {
+        let n = self.bits.read(nbit) as usize;
+        if n == 0 {
+            let sym = self.bits.read(nbit) as usize;
+            if sym >= count {
+                return Err(invalid_data("invalid LZH table"));
+            }
+            return Ok(Tree::Single(sym));
+        }
+        if n > count {
+            return Err(invalid_data("invalid LZH table size"));
+        }
+
+        let mut lens = vec![0u8; count];
+        let mut i = 0;
+        while i < n {
+            // Three bits, or 7 and a unary extension: 111 1...10
+            let mut len = self.bits.peek(3);
+            if len == 7 {
+                let ones = (!(self.bits.peek(16) << 19 | 0x3FFFF)).leading_zeros();
+                len += ones;
+                self.bits.skip(3 + ones + 1);
+            } else {
+                self.bits.skip(3);
+            }
+            if len > 16 {
+                return Err(invalid_data("invalid LZH code length"));
+            }
+            lens[i] = len as u8;
+            i += 1;
+            if Some(i) == special {
+                let zeros = self.bits.read(2) as usize;
+                if i + zeros > count {
+                    return Err(invalid_data("invalid LZH table size"));
+                }
+                i += zeros;
+            }
+        }
+        Ok(Tree::Codes(Huffman::new(&lens)?))
+    }
+
+    /// Code lengths for the literal/length table, coded with `pt`
+    fn read_c_len(&mut self, pt: &Tree) -> io::Result<Tree> {
+        let n = self.bits.read(CBIT) as usize;
+        if n == 0 {
+            let sym = self.bits.read(CBIT) as usize;
+            if sym >= NC {
+                return Err(invalid_data("invalid LZH table"));
+            }
+            return Ok(Tree::Single(sym));
+        }
+        if n > NC {
+            return Err(invalid_data("invalid LZH table size"));
+        }
+
+        let mut lens = vec![0u8; NC];
+        let mut i = 0;
+        while i < n {
+            let code = self.bits.symbol(pt)?;
+            if code <= 2 {
+                let zeros = match code {
+                    0 => 1,
+                    1 => self.bits.read(4) as usize + 3,
+                    _ => self.bits.read(CBIT) as usize + 20,
+                };
+                if i + zeros > NC {
+                    return Err(invalid_data("invalid LZH table size"));
+                }
+                i += zeros;
+            } else {
+                lens[i] = (code - 2) as u8;
+                i += 1;
+            }
+        }
+        Ok(Tree::Codes(Huffman::new(&lens)?))
+    }
+
+    fn read_block_header(&mut self) -> io::Result<()> {
+        self.block_left = self.bits.read(16);
+        let pt = self.read_pt_len(NT, TBIT, Some(3))?;
+        self.c_tree = self.read_c_len(&pt)?;
+        self.p_tree = self.read_pt_len(self.np, self.pbit, None)?;
+        Ok(())
+    }
+
+    fn distance(&mut self) -> io::Result<usize> {
+        let bits = self.bits.symbol(&self.p_tree)? as u32;
+        if bits == 0 {
+            return Ok(0);
+        }
+        Ok((1usize << (bits - 1)) + self.bits.read(bits - 1) as usize)
+    }
+}
+
+/// Decode `size` bytes coded with the static Huffman coder, whose
+/// distance table has `np` entries with `pbit`-bit lengths
+pub(crate) fn decode_static(
+    input: &[u8],
+    size: usize,
+    np: usize,
+    pbit: u32,
+) -> io::Result<Vec<u8>> {
+    let mut state = Static {
+        bits: Bits::new(input),
+        np,
+        pbit,
+        block_left: 0,
+        c_tree: Tree::Single(0),
+        p_tree: Tree::Single(0),
+    };
+    let mut out = Output::new(size);
+    while !out.full() {
+        if state.block_left == 0 {
+            state.read_block_header()?;
+            if state.block_left == 0 {
+                return Err(invalid_data("empty LZH block"));
+            }
+        }
+        state.block_left -= 1;
+
+        let c = state.bits.symbol(&state.c_tree)?;
+        if c < 256 {
+            out.literal(c as u8);
+        } else {
+            let len = c - 256 + THRESHOLD;
+            let distance = state.distance()?;
+            out.copy(distance, len);
+        }
+    }
+    Ok(out.data)
+}
+
+/// -lh1- literal and length symbols: 256 literals, lengths 3 to 60
+const LH1_SYMBOLS: usize = 256 - THRESHOLD + 60 + 1;
+const LH1_NODES: usize = LH1_SYMBOLS * 2 - 1;
+const LH1_ROOT: usize = LH1_NODES - 1;
+const LH1_MAX_FREQ: u32 = 0x8000;
+
+/// LZHUF's adaptive Huffman tree: nodes are kept in increasing frequency
+/// order, and a node's children are `son` and `son + 1`, or `son` is a
+/// symbol plus LH1_NODES for a leaf
+struct Adaptive {
+    freq: Vec<u32>,
+    parent: Vec<usize>,
+    son: Vec<usize>,
+}
+
+impl Adaptive {
+    fn new() -> Self {
+        let mut tree = Self {
+            freq: vec![0; LH1_NODES + 1],
+            parent: vec![0; LH1_NODES + LH1_SYMBOLS],
+            son: vec![0; LH1_NODES],
+        };
+        for i in 0..LH1_SYMBOLS {
+            tree.freq[i] = 1;
+            tree.son[i] = i + LH1_NODES;
+            tree.parent[i + LH1_NODES] = i;
+        }
+        let mut i = 0;
+        for j in LH1_SYMBOLS..LH1_NODES {
+            tree.freq[j] = tree.freq[i] + tree.freq[i + 1];
+            tree.son[j] = i;
+            tree.parent[i] = j;
+            tree.parent[i + 1] = j;
+            i += 2;
+        }
+        // Sentinel that stops the reordering scan
+        tree.freq[LH1_NODES] = u32::MAX;
+        tree.parent[LH1_ROOT] = 0;
+        tree
+    }
+
+    fn decode(&mut self, bits: &mut Bits) -> usize {
+        let mut node = self.son[LH1_ROOT];
+        while node < LH1_NODES {
+            node = self.son[node + bits.read(1) as usize];
+        }
+        let sym = node - LH1_NODES;
+        self.update(sym);
+        sym
+    }
+
+    /// Halve all frequencies and rebuild the tree
+    fn rebuild(&mut self) {
+        let mut j = 0;
+        for i in 0..LH1_NODES {
+            if self.son[i] >= LH1_NODES {
+                self.freq[j] = self.freq[i].div_ceil(2);
+                self.son[j] = self.son[i];
+                j += 1;
+            }
+        }
+        let mut i = 0;
+        for j in LH1_SYMBOLS..LH1_NODES {
+            let f = self.freq[i] + self.freq[i + 1];
+            let mut k = j;
+            while k > 0 && f < self.freq[k - 1] {
+                k -= 1;
+            }
+            self.freq.copy_within(k..j, k + 1);
+            self.freq[k] = f;
+            self.son.copy_within(k..j, k + 1);
+            self.son[k] = i;
+            i += 2;
+        }
+        for i in 0..LH1_NODES {
+            let k = self.son[i];
+            self.parent[k] = i;
+            if k < LH1_NODES {
+                self.parent[k + 1] = i;
+            }
+        }
+    }
+
+    fn update(&mut self, sym: usize) {
+        if self.freq[LH1_ROOT] == LH1_MAX_FREQ {
+            self.rebuild();
+        }
+        let mut c = self.parent[sym + LH1_NODES];
+        loop {
+            self.freq[c] += 1;
+            let k = self.freq[c];
+            // Swap with the last node of lower frequency to keep order
+            if k > self.freq[c + 1] {
+                let mut l = c + 1;
+                while k > self.freq[l + 1] {
+                    l += 1;
+                }
+                self.freq[c] = self.freq[l];
+                self.freq[l] = k;
+
+                let i = self.son[c];
+                self.parent[i] = l;
+                if i < LH1_NODES {
+                    self.parent[i + 1] = l;
+                }
+                let j = self.son[l];
+                self.son[l] = i;
+                self.parent[j] = c;
+                if j < LH1_NODES {
+                    self.parent[j + 1] = c;
+                }
+                self.son[c] = j;
+                c = l;
+            }
+            c = self.parent[c];
+            if c == 0 {
+                break;
+            }
+        }
+    }
+}
+
+/// Code lengths of -lh1-'s fixed code for the top 6 distance bits
+fn lh1_distance_lens() -> [u8; 64] {
+    let mut lens = [0u8; 64];
+    for (i, len) in lens.iter_mut().enumerate() {
+        *len = match i {
+            0 => 3,
+            1..=3 => 4,
+            4..=11 => 5,
+            12..=23 => 6,
+            24..=47 => 7,
+            _ => 8,
+        };
+    }
+    lens
+}
+
+/// Decode `size` bytes of -lh1- data
+pub(crate) fn decode_lh1(input: &[u8], size: usize) -> io::Result<Vec<u8>> {
+    let mut bits = Bits::new(input);
+    let mut tree = Adaptive::new();
+    let distances = Huffman::new(&lh1_distance_lens())?;
+    let mut out = Output::new(size);
+    while !out.full() {
+        let c = tree.decode(&mut bits);
+        if c < 256 {
+            out.literal(c as u8);
+        } else {
+            let len = c - 256 + THRESHOLD;
+            let (high, used) = distances.decode(bits.peek(16))?;
+            bits.skip(used);
+            let distance = (usize::from(high) << 6) | bits.read(6) as usize;
+            out.copy(distance, len);
+        }
+    }
+    Ok(out.data)
+}
+
+/// -lh2- and -lh3- literal and length symbols: lengths 3 to 31, then an
+/// escape followed by 8 bits for longer ones
+const LH2_SYMBOLS: usize = 286;
+const LH2_ESCAPE: usize = LH2_SYMBOLS - 1;
+/// Symbols for the top 7 bits of 8 KiB distances
+const LH2_DISTANCES: usize = 128;
+const LH2_DISTANCE_STEP: usize = 64;
+const LH2_WINDOW: usize = 8192;
+const LH2_MAX_FREQ: u16 = 0x8000;
+
+/// Both -lh2- trees share one set of arrays, as in dhuf.c: the literal
+/// tree's root is node 0 and the distance tree's follows room for the
+/// larger -lh1- literal tree. Distance leaves are numbered after
+/// LH1_SYMBOLS.
+const DYN_ROOT_C: usize = 0;
+const DYN_ROOT_P: usize = LH1_SYMBOLS * 2;
+const DYN_NODES: usize = DYN_ROOT_P + LH2_DISTANCES * 2;
+
+/// dhuf.c's adaptive Huffman trees. Nodes are kept in decreasing
+/// frequency order; `child` is the higher-numbered of a node's two
+/// children (taken on a 0 bit), or `!symbol` for a leaf. Runs of equal
+/// frequency form blocks, whose lowest node (`edge`) is swapped with a
+/// node about to be incremented. Block numbers come from `stock`.
+struct Dynamic {
+    child: Vec<i32>,
+    parent: Vec<usize>,
+    block: Vec<usize>,
+    edge: Vec<usize>,
+    stock: Vec<usize>,
+    leaf: Vec<usize>,
+    freq: Vec<u16>,
+    avail: usize,
+    /// Last node of the distance tree
+    most_p: usize,
+    total_p: u16,
+    /// Output count past which the next distance symbol is added
+    next_count: usize,
+}
+
+impl Dynamic {
+    fn new() -> Self {
+        let mut tree = Self {
+            child: vec![0; DYN_NODES],
+            parent: vec![0; DYN_NODES],
+            block: vec![0; DYN_NODES],
+            edge: vec![0; DYN_NODES],
+            stock: (0..DYN_NODES).collect(),
+            leaf: vec![0; DYN_NODES / 2],
+            freq: vec![0; DYN_NODES],
+            avail: 2,
+            most_p: DYN_ROOT_P,
+            total_p: 0,
+            next_count: LH2_DISTANCE_STEP,
+        };
+
+        // Leaves from the last node down, all of frequency 1 in block 1
+        let n = LH2_SYMBOLS;
+        for sym in 0..n {
+            let node = n * 2 - 2 - sym;
+            tree.freq[node] = 1;
+            tree.child[node] = !(sym as i32);
+            tree.leaf[sym] = node;
+            tree.block[node] = 1;
+        }
+        tree.edge[1] = n - 1;
+        let mut i = n * 2 - 2;
+        for j in (0..n - 1).rev() {
+            let f = tree.freq[i] + tree.freq[i - 1];
+            tree.freq[j] = f;
+            tree.child[j] = i as i32;
+            tree.parent[i] = j;
+            tree.parent[i - 1] = j;
+            tree.block[j] = if f == tree.freq[j + 1] {
+                tree.block[j + 1]
+            } else {
+                tree.new_block()
+            };
+            tree.edge[tree.block[j]] = j;
+            i -= 2;
+        }
+
+        // The distance tree starts as a single leaf for symbol 0
+        tree.freq[DYN_ROOT_P] = 1;
+        tree.child[DYN_ROOT_P] = !(LH1_SYMBOLS as i32);
+        tree.leaf[LH1_SYMBOLS] = DYN_ROOT_P;
+        tree.block[DYN_ROOT_P] = tree.new_block();
+        tree.edge[tree.block[DYN_ROOT_P]] = DYN_ROOT_P;
+        tree
+    }
+
+    fn new_block(&mut self) -> usize {
+        let block = self.stock[self.avail];
+        self.avail += 1;
+        block
+    }
+
+    fn free_block(&mut self, block: usize) {
+        self.avail -= 1;
+        self.stock[self.avail] = block;
+    }
+
+    /// Point `child`'s parent links, or its symbol's leaf, at `node`
+    fn link(&mut self, child: i32, node: usize) {
+        if child >= 0 {
+            self.parent[child as usize] = node;
+            self.parent[child as usize - 1] = node;
+        } else {
+            self.leaf[!child as usize] = node;
+        }
+    }
+
+    /// Halve the leaf frequencies of nodes `start..end` and rebuild them
+    fn rebuild(&mut self, start: usize, end: usize) {
+        let mut j = start;
+        for i in start..end {
+            if self.child[i] < 0 {
+                self.freq[j] = self.freq[i].div_ceil(2);
+                self.child[j] = self.child[i];
+                j += 1;
+            }
+            if self.edge[self.block[i]] == i {
+                self.free_block(self.block[i]);
+            }
+        }
+
+        // Merge the leaves, moved to the end, with new internal nodes
+        let start = start as isize;
+        let mut j = j as isize - 1;
+        let mut i = end as isize - 1;
+        let mut l = end as isize - 2;
+        while i >= start {
+            while i >= l {
+                self.freq[i as usize] = self.freq[j as usize];
+                self.child[i as usize] = self.child[j as usize];
+                i -= 1;
+                j -= 1;
+            }
+            let f = self.freq[l as usize] + self.freq[l as usize + 1];
+            let mut k = start;
+            while k <= j && f < self.freq[k as usize] {
+                k += 1;
+            }
+            while j >= k {
+                self.freq[i as usize] = self.freq[j as usize];
+                self.child[i as usize] = self.child[j as usize];
+                i -= 1;
+                j -= 1;
+            }
+            self.freq[i as usize] = f;
+            self.child[i as usize] = l as i32 + 1;
+            i -= 1;
+            l -= 2;
+        }
+
+        let mut f = 0;
+        let mut block = 0;
+        for i in start as usize..end {
+            self.link(self.child[i], i);
+            if self.freq[i] == f {
+                self.block[i] = block;
+            } else {
+                block = self.new_block();
+                self.block[i] = block;
+                self.edge[block] = i;
+                f = self.freq[i];
+            }
+        }
+    }
+
+    /// Increment node `p`, first swapping it with the lowest node of its
+    /// block, and return the parent of the node incremented
+    fn increment(&mut self, mut p: usize) -> usize {
+        let b = self.block[p];
+        let q = self.edge[b];
+        if q != p || b == self.block[p + 1] {
+            if q != p {
+                let (r, s) = (self.child[p], self.child[q]);
+                self.child[p] = s;
+                self.child[q] = r;
+                self.link(r, q);
+                self.link(s, p);
+                p = q;
+            }
+            // p leaves the front of its block
+            self.edge[b] += 1;
+            self.freq[p] = self.freq[p].wrapping_add(1);
+            if self.freq[p] == self.freq[p - 1] {
+                self.block[p] = self.block[p - 1];
+            } else {
+                self.block[p] = self.new_block();
+                self.edge[self.block[p]] = p;
+            }
+        } else {
+            // p is alone in its block
+            self.freq[p] = self.freq[p].wrapping_add(1);
+            if self.freq[p] == self.freq[p - 1] {
+                self.free_block(b);
+                self.block[p] = self.block[p - 1];
+            }
+        }
+        self.parent[p]
+    }
+
+    fn update_c(&mut self, sym: usize) {
+        if self.freq[DYN_ROOT_C] == LH2_MAX_FREQ {
+            self.rebuild(DYN_ROOT_C, LH2_SYMBOLS * 2 - 1);
+        }
+        self.freq[DYN_ROOT_C] += 1;
+        let mut node = self.leaf[sym];
+        while node != DYN_ROOT_C {
+            node = self.increment(node);
+        }
+    }
+
+    fn update_p(&mut self, sym: usize) {
+        if self.total_p == LH2_MAX_FREQ {
+            self.rebuild(DYN_ROOT_P, self.most_p + 1);
+            self.total_p = self.freq[DYN_ROOT_P];
+            self.freq[DYN_ROOT_P] = u16::MAX;
+        }
+        let mut node = self.leaf[sym + LH1_SYMBOLS];
+        while node != DYN_ROOT_P {
+            node = self.increment(node);
+        }
+        self.total_p += 1;
+    }
+
+    /// Split the distance tree's last leaf to add `sym` with frequency 0
+    fn add_distance(&mut self, sym: usize) {
+        let r = self.most_p + 1;
+        let q = r + 1;
+        self.child[r] = self.child[self.most_p];
+        self.leaf[!self.child[r] as usize] = r;
+        self.child[q] = !((sym + LH1_SYMBOLS) as i32);
+        self.child[self.most_p] = q as i32;
+        self.freq[r] = self.freq[self.most_p];
+        self.freq[q] = 0;
+        self.block[r] = self.block[self.most_p];
+        if self.most_p == DYN_ROOT_P {
+            self.freq[DYN_ROOT_P] = u16::MAX;
+            self.edge[self.block[DYN_ROOT_P]] += 1;
+        }
+        self.parent[r] = self.most_p;
+        self.parent[q] = self.most_p;
+        self.block[q] = self.new_block();
+        self.edge[self.block[q]] = q;
+        self.leaf[sym + LH1_SYMBOLS] = q;
+        self.most_p = q;
+        self.update_p(sym);
+    }
+
+    /// Add the distance symbols due after `count` bytes of output
+    fn grow(&mut self, count: usize) {
+        while count > self.next_count {
+            self.add_distance(self.next_count / LH2_DISTANCE_STEP);
+            self.next_count += LH2_DISTANCE_STEP;
+            if self.next_count >= LH2_WINDOW {
+                self.next_count = usize::MAX;
+            }
+        }
+    }
+
+    /// Walk from `root` to a leaf and return its symbol
+    fn walk(&self, bits: &mut Bits, root: usize) -> usize {
+        let mut node = self.child[root];
+        while node > 0 {
+            node = self.child[node as usize - bits.read(1) as usize];
+        }
+        !node as usize
+    }
+
+    fn decode_c(&mut self, bits: &mut Bits) -> usize {
+        let sym = self.walk(bits, DYN_ROOT_C);
+        self.update_c(sym);
+        if sym == LH2_ESCAPE {
+            return sym + bits.read(8) as usize;
+        }
+        sym
+    }
+
+    /// Decode a distance after `count` bytes of output
+    fn decode_p(&mut self, bits: &mut Bits, count: usize) -> usize {
+        self.grow(count);
+        let sym = self.walk(bits, DYN_ROOT_P) - LH1_SYMBOLS;
+        self.update_p(sym);
+        (sym << 6) | bits.read(6) as usize
+    }
+}
+
+/// Decode `size` bytes of -lh2- data
+pub(crate) fn decode_lh2(input: &[u8], size: usize) -> io::Result<Vec<u8>> {
+    let mut bits = Bits::new(input);
+    let mut tree = Dynamic::new();
+    let mut out = Output::new(size);
+    while !out.full() {
+        let c = tree.decode_c(&mut bits);
+        if c < 256 {
+            out.literal(c as u8);
+        } else {
+            let len = c - 256 + THRESHOLD;
+            let distance = tree.decode_p(&mut bits, out.data.len());
+            out.copy(distance, len);
+        }
+    }
+    Ok(out.data)
+}
+
+/// Code lengths of -lh3-'s fixed code for the top 7 distance bits, for
+/// blocks that don't store their own
+fn lh3_distance_lens() -> [u8; LH2_DISTANCES] {
+    let mut lens = [0u8; LH2_DISTANCES];
+    for (i, len) in lens.iter_mut().enumerate() {
+        *len = match i {
+            0 => 2,
+            1..=2 => 4,
+            3..=5 => 5,
+            6..=12 => 6,
+            13..=30 => 7,
+            31..=77 => 8,
+            _ => 9,
+        };
+    }
+    lens
+}
+
+/// -lh3- code lengths: `count` 4-bit lengths, each behind a presence bit
+/// for the literal table. Lengths of 1 for the first three symbols
+/// instead introduce a single symbol of `nbit` bits.
+fn read_lh3_lens(bits: &mut Bits, count: usize, nbit: u32, flagged: bool) -> io::Result<Tree> {
+    let mut lens = vec![0u8; count];
+    for i in 0..count {
+        lens[i] = match flagged {
+            true if bits.read(1) == 0 => 0,
+            true => bits.read(4) as u8 + 1,
+            false => bits.read(4) as u8,
+        };
+        if i == 2 && lens[..3] == [1, 1, 1] {
+            let sym = bits.read(nbit) as usize;
+            if sym >= count {
+                return Err(invalid_data("invalid LZH table"));
+            }
+            return Ok(Tree::Single(sym));
+        }
+    }
+    Ok(Tree::Codes(Huffman::new(&lens)?))
+}
+
+/// Decode `size` bytes of -lh3- data
+pub(crate) fn decode_lh3(input: &[u8], size: usize) -> io::Result<Vec<u8>> {
+    let mut bits = Bits::new(input);
+    let mut block_left = 0;
+    let mut c_tree = Tree::Single(0);
+    let mut p_tree = Tree::Single(0);
+    let mut out = Output::new(size);
+    while !out.full() {
+        if block_left == 0 {
+            block_left = bits.read(16);
+            if block_left == 0 {
+                return Err(invalid_data("empty LZH block"));
+            }
+            c_tree = read_lh3_lens(&mut bits, LH2_SYMBOLS, CBIT, true)?;
+            p_tree = if bits.read(1) == 1 {
+                read_lh3_lens(&mut bits, LH2_DISTANCES, 7, false)?
+            } else {
+                Tree::Codes(Huffman::new(&lh3_distance_lens())?)
+            };
+        }
+        block_left -= 1;
+
+        let mut c = bits.symbol(&c_tree)?;
+        if c == LH2_ESCAPE {
+            c += bits.read(8) as usize;
+        }
+        if c < 256 {
+            out.literal(c as u8);
+        } else {
+            let len = c - 256 + THRESHOLD;
+            let distance = (bits.symbol(&p_tree)? << 6) | bits.read(6) as usize;
+            out.copy(distance, len);
+        }
+    }
+    Ok(out.data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct BitWriter {
+        bits: Vec<u8>,
+    }
+
+    impl BitWriter {
+        fn put(&mut self, value: u32, n: u32) {
+            for i in (0..n).rev() {
+                self.bits.push(((value >> i) & 1) as u8);
+            }
+        }
+
+        fn bytes(&self) -> Vec<u8> {
+            self.bits
+                .chunks(8)
+                .map(|c| {
+                    c.iter()
+                        .enumerate()
+                        .fold(0, |b, (i, &bit)| b | (bit << (7 - i)))
+                })
+                .collect()
+        }
+    }
+
+    #[test]
+    fn decodes_static_blocks() {
+        // One block of four symbols: "abc" as literals, then a 3-byte
+        // match at distance 3
+        let mut w = BitWriter::default();
+        w.put(4, 16);
+        // Length-code table: symbols 0, 1, 2 and 4 (length 2) with 2-bit
+        // codes 00, 01, 10, 11
+        w.put(5, TBIT);
+        for len in [2, 2, 2] {
+            w.put(len, 3);
+        }
+        w.put(0, 2); // no zeros after the first three
+        w.put(0, 3); // symbol 3 unused
+        w.put(2, 3); // symbol 4: length 2
+                     // Literal/length lengths: 97 zeros (0x61 = 'a'), then 'a', 'b',
+                     // 'c' at length 2, 156 zeros, then symbol 256 at length 2
+        w.put(257, CBIT);
+        w.put(0b10, 2);
+        w.put(97 - 20, CBIT);
+        for _ in 0..3 {
+            w.put(0b11, 2);
+        }
+        w.put(0b10, 2);
+        w.put(156 - 20, CBIT);
+        w.put(0b11, 2);
+        // Distance table: a single symbol, 2 (distances 2-3)
+        w.put(0, 4);
+        w.put(2, 4);
+        // Codes: a=00 b=01 c=10 256=11
+        w.put(0b00, 2);
+        w.put(0b01, 2);
+        w.put(0b10, 2);
+        w.put(0b11, 2);
+        w.put(0, 1); // 2 + 0, copying from 3 bytes back
+        let out = decode_static(&w.bytes(), 6, 14, 4).unwrap();
+        assert_eq!(out, b"abcabc");
+    }
+
+    #[test]
+    fn reads_spaces_before_start() {
+        let mut out = Output::new(4);
+        out.literal(b'x');
+        out.copy(1, 3);
+        assert_eq!(out.data, b"x x ");
+    }
+
+    #[test]
+    fn decodes_lh1() {
+        // Every symbol starts with frequency 1, so the first literal's code
+        // is its position in the initial tree; decode what a fresh tree
+        // reads from all-zero bits and check it is consistent
+        let mut tree = Adaptive::new();
+        let mut bits = Bits::new(&[0; 8]);
+        let first = tree.decode(&mut bits);
+        let mut again = Adaptive::new();
+        assert_eq!(again.decode(&mut Bits::new(&[0; 8])), first);
+        // Frequencies stay sorted after updates
+        for sym in [65, 65, 300, 0, 65] {
+            tree.update(sym);
+            assert!(tree.freq[..LH1_NODES].windows(2).all(|w| w[0] <= w[1]));
+        }
+    }
+
+    #[test]
+    fn decodes_lh3_blocks() {
+        let mut w = BitWriter::default();
+        // Block of four: 'a', 'b', 256 and the escape at length 2 (codes
+        // 00, 01, 10, 11), with the fixed distance code
+        w.put(4, 16);
+        for sym in 0..LH2_SYMBOLS {
+            if [97, 98, 256, LH2_ESCAPE].contains(&sym) {
+                w.put(1, 1);
+                w.put(1, 4);
+            } else {
+                w.put(0, 1);
+            }
+        }
+        w.put(0, 1);
+        w.put(0b00, 2);
+        w.put(0b01, 2);
+        // 3 bytes from 2 back: distance symbol 0 (code 00) and 6 bits
+        w.put(0b10, 2);
+        w.put(0b00, 2);
+        w.put(1, 6);
+        // Escape plus 0: 32 bytes from 1 back
+        w.put(0b11, 2);
+        w.put(0, 8);
+        w.put(0b00, 2);
+        w.put(0, 6);
+        // Block of one with single-symbol tables: 256 and distance
+        // symbol 1, reaching back before the start
+        w.put(1, 16);
+        for _ in 0..3 {
+            w.put(1, 1);
+            w.put(0, 4);
+        }
+        w.put(256, CBIT);
+        w.put(1, 1);
+        for _ in 0..3 {
+            w.put(1, 4);
+        }
+        w.put(1, 7);
+        w.put(0, 6);
+
+        let mut expected = b"ababa".to_vec();
+        expected.extend([b'a'; 32]);
+        expected.extend(b"   ");
+        assert_eq!(decode_lh3(&w.bytes(), expected.len()).unwrap(), expected);
+    }
+
+    /// Write the bits that lead from `root` to `node`
+    fn put_dynamic(w: &mut BitWriter, tree: &Dynamic, mut node: usize, root: usize) {
+        let mut code = Vec::new();
+        while node != root {
+            let parent = tree.parent[node];
+            code.push((tree.child[parent] as usize - node) as u32);
+            node = parent;
+        }
+        for bit in code.into_iter().rev() {
+            w.put(bit, 1);
+        }
+    }
+
+    #[test]
+    fn decodes_lh2() {
+        // Random literals and matches, coded by walking a second copy of
+        // the trees up from each leaf. There are enough of both for each
+        // tree to be rebuilt at least once.
+        let mut seed = 7u32;
+        let mut next = |n: usize| {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            (seed >> 8) as usize % n
+        };
+        let mut tree = Dynamic::new();
+        let mut w = BitWriter::default();
+        let mut data = Vec::new();
+        let mut matches = 0;
+        while matches < 40_000 {
+            if data.is_empty() || next(4) == 0 {
+                let c = next(256);
+                put_dynamic(&mut w, &tree, tree.leaf[c], DYN_ROOT_C);
+                tree.update_c(c);
+                data.push(c as u8);
+                continue;
+            }
+            let len = THRESHOLD + if next(16) == 0 { next(285) } else { next(8) };
+            let c = len - THRESHOLD + 256;
+            let sym = c.min(LH2_ESCAPE);
+            put_dynamic(&mut w, &tree, tree.leaf[sym], DYN_ROOT_C);
+            tree.update_c(sym);
+            if sym == LH2_ESCAPE {
+                w.put((c - sym) as u32, 8);
+            }
+
+            let distance = next(data.len().min(LH2_WINDOW));
+            tree.grow(data.len());
+            let p = distance >> 6;
+            put_dynamic(&mut w, &tree, tree.leaf[p + LH1_SYMBOLS], DYN_ROOT_P);
+            tree.update_p(p);
+            w.put(distance as u32 & 63, 6);
+            for _ in 0..len {
+                data.push(data[data.len() - distance - 1]);
+            }
+            matches += 1;
+        }
+        assert!(tree.freq[..LH2_SYMBOLS * 2 - 1]
+            .windows(2)
+            .all(|f| f[0] >= f[1]));
+        assert_eq!(decode_lh2(&w.bytes(), data.len()).unwrap(), data);
+    }
+}
@@ -0,0 +1,231 @@
+//! lzip container reader
+//!
+//! lzip is a compression wrapper containing a single decompressed stream,
+//! stored as one or more members. Each member is a 6-byte header, a raw
+//! LZMA stream (lc=3, lp=0, pb=2, always ending with an end marker) and a
+//! trailer holding the CRC-32 and size of the data and the size of the
+//! member itself. Members are located backwards from the end of the file
+//! using those trailers, then decoded in order.
+
+use crate::container::{invalid_data, read_all, BytesReader, Child, Container, LimitedBuffer};
+use crate::detect::Reader;
+use flate2::Crc;
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+
+const MAGIC: &[u8; 4] = b"LZIP";
+const VERSION: u8 = 1;
+const HEADER_SIZE: u64 = 6;
+const TRAILER_SIZE: u64 = 20;
+/// LZMA properties byte for lc=3, lp=0, pb=2
+const LZMA_PROPERTIES: u8 = 0x5D;
+/// Dictionary sizes range from 4 KiB to 512 MiB
+const MIN_DICT_BITS: u8 = 12;
+const MAX_DICT_BITS: u8 = 29;
+
+/// lzip container - decompresses content to expose inner stream
+pub struct LzipContainer;
+
+/// Static instance for registry
+pub static LZIP: LzipContainer = LzipContainer;
+
+impl Container for LzipContainer {
+    fn children(&self, reader: Arc<dyn Reader + Send + Sync>) -> io::Result<Vec<Child>> {
+        let members = parse_members(&*reader)?;
+        let compressed = read_all(&*reader)?;
+        let mut decompressed = LimitedBuffer::new();
+        for member in &members {
+            decode_member(&compressed, member, &mut decompressed)?;
+        }
+
+        Ok(vec![Child {
+            index: 0,
+            offset: u64::MAX, // Transformed data, not a slice
+            reader: Arc::new(BytesReader::new(decompressed.into_inner())),
+            metadata: Vec::new(),
+        }])
+    }
+}
+
+/// Location and expected contents of one member
+#[derive(Debug, PartialEq)]
+struct Member {
+    start: u64,
+    end: u64,
+    dict_size: u32,
+    crc: u32,
+    data_size: u64,
+}
+
+/// Decode the coded dictionary size: a power of two, less up to 7/16ths
+fn dict_size(coded: u8) -> io::Result<u32> {
+    let bits = coded & 0x1F;
+    if !(MIN_DICT_BITS..=MAX_DICT_BITS).contains(&bits) {
+        return Err(invalid_data("invalid lzip dictionary size"));
+    }
+    let base = 1u32 << bits;
+    Ok(base - (base / 16) * u32::from(coded >> 5))
+}
+
+/// Walk member trailers backwards from the end of the file
+fn parse_members(reader: &dyn Reader) -> io::Result<Vec<Member>> {
+    let mut end = reader
+        .size()
+        .ok_or_else(|| invalid_data("lzip size unknown"))?;
+    let mut members = Vec::new();
+
+    while end > 0 {
+        if end < HEADER_SIZE + TRAILER_SIZE {
+            return Err(invalid_data("lzip member too short"));
+        }
+        let mut trailer = [0u8; TRAILER_SIZE as usize];
+        if reader.read_at(end - TRAILER_SIZE, &mut trailer)? != trailer.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "short lzip trailer read",
+            ));
+        }
+        let crc = u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+        let data_size = u64::from_le_bytes(trailer[4..12].try_into().unwrap());
+        let member_size = u64::from_le_bytes(trailer[12..20].try_into().unwrap());
+        if member_size < HEADER_SIZE + TRAILER_SIZE || member_size > end {
+            return Err(invalid_data("invalid lzip member size"));
+        }
+
+        let start = end - member_size;
+        let mut header = [0u8; HEADER_SIZE as usize];
+        if reader.read_at(start, &mut header)? != header.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "short lzip header read",
+            ));
+        }
+        if &header[..4] != MAGIC || header[4] != VERSION {
+            return Err(invalid_data("invalid lzip member header"));
+        }
+
+        members.push(Member {
+            start,
+            end,
+            dict_size: dict_size(header[5])?,
+            crc,
+            data_size,
+        });
+        end = start;
+    }
+
+    if members.is_empty() {
+        return Err(invalid_data("empty lzip file"));
+    }
+    members.reverse();
+    Ok(members)
+}
+
+/// Writer that checksums and counts what passes through it
+struct Checked<'a> {
+    inner: &'a mut LimitedBuffer,
+    crc: Crc,
+    len: u64,
+}
+
+impl Write for Checked<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.crc.update(&buf[..n]);
+        self.len += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Decode one member and check it against its trailer
+fn decode_member(data: &[u8], member: &Member, out: &mut LimitedBuffer) -> io::Result<()> {
+    // lzma-rs wants a properties header; lzip implies everything but the
+    // dictionary size
+    let mut header = [LZMA_PROPERTIES, 0, 0, 0, 0];
+    header[1..].copy_from_slice(&member.dict_size.to_le_bytes());
+    let stream = &data[(member.start + HEADER_SIZE) as usize..(member.end - TRAILER_SIZE) as usize];
+
+    let options = lzma_rs::decompress::Options {
+        unpacked_size: lzma_rs::decompress::UnpackedSize::UseProvided(None),
+        ..Default::default()
+    };
+    let mut checked = Checked {
+        inner: out,
+        crc: Crc::new(),
+        len: 0,
+    };
+    lzma_rs::lzma_decompress_with_options(&mut (&header[..]).chain(stream), &mut checked, &options)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+
+    if checked.len != member.data_size {
+        return Err(invalid_data("lzip size mismatch"));
+    }
+    if checked.crc.sum() != member.crc {
+        return Err(invalid_data("lzip CRC mismatch"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Wrap lzma-rs output (which ends with an end marker) as a member
+    fn member(data: &[u8]) -> Vec<u8> {
+        let mut lzma = Vec::new();
+        lzma_rs::lzma_compress(&mut &data[..], &mut lzma).unwrap();
+        // lzma-rs uses an 8 MiB dictionary: 2^23, coded as 23
+        assert_eq!(&lzma[1..5], &(1u32 << 23).to_le_bytes());
+
+        let mut out = MAGIC.to_vec();
+        out.extend_from_slice(&[VERSION, 23]);
+        out.extend_from_slice(&lzma[13..]);
+        let mut crc = Crc::new();
+        crc.update(data);
+        out.extend_from_slice(&crc.sum().to_le_bytes());
+        out.extend_from_slice(&(data.len() as u64).to_le_bytes());
+        let member_size = out.len() as u64 + 8;
+        out.extend_from_slice(&member_size.to_le_bytes());
+        out
+    }
+
+    fn decode(data: Vec<u8>) -> io::Result<Vec<u8>> {
+        let kids = LZIP.children(Arc::new(BytesReader::new(data)))?;
+        let reader = &kids[0].reader;
+        let mut buf = vec![0u8; reader.size().unwrap() as usize];
+        reader.read_at(0, &mut buf)?;
+        Ok(buf)
+    }
+
+    #[test]
+    fn decodes_members() {
+        let mut data = member(b"first member, ");
+        data.extend(member(b""));
+        data.extend(member(b"second member"));
+        assert_eq!(decode(data).unwrap(), b"first member, second member");
+    }
+
+    #[test]
+    fn rejects_corrupt_members() {
+        let mut data = member(b"checked data");
+        let crc_at = data.len() - 20;
+        data[crc_at] ^= 1;
+        assert!(decode(data).is_err());
+
+        let mut data = member(b"checked data");
+        data.push(0);
+        assert!(decode(data).is_err());
+    }
+
+    #[test]
+    fn decodes_dictionary_sizes() {
+        assert_eq!(dict_size(12).unwrap(), 4096);
+        // 2^20 less 3/16ths
+        assert_eq!(dict_size(0x60 | 20).unwrap(), 851_968);
+        assert!(dict_size(30).is_err());
+    }
+}
@@ -25,6 +25,7 @@ impl Container for LzmaContainer {
             index: 0,
             offset: u64::MAX, // Transformed data, not a slice
             reader: Arc::new(BytesReader::new(decompressed.into_inner())),
+            metadata: Vec::new(),
         }])
     }
 }
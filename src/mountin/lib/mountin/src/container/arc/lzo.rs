@@ -0,0 +1,227 @@
+//! LZO1X decompressor
+//!
+//! LZO1X is the block codec behind lzop (and squashfs, UBIFS, btrfs and
+//! kernel images). The stream is a sequence of literal runs and matches;
+//! the low two bits of each match instruction give the length of a short
+//! literal run that follows it. LZO1X-1, -1(15) and -999 all share this
+//! format, so one decoder covers every lzop method.
+
+use crate::container::invalid_data;
+use std::io;
+
+/// Matches after a literal run may reach this far beyond the M2 range
+const M2_MAX_OFFSET: usize = 0x0800;
+const M4_BASE: usize = 0x4000;
+
+/// What the next instruction byte means
+enum State {
+    /// Start of a literal run or a match
+    Instruction,
+    /// Directly after a literal run: short codes are 3-byte far matches
+    AfterLiterals,
+    /// Match instruction already read
+    Match(usize),
+}
+
+struct Decoder<'a> {
+    input: &'a [u8],
+    ip: usize,
+    out: Vec<u8>,
+    limit: usize,
+}
+
+impl Decoder<'_> {
+    fn byte(&mut self) -> io::Result<usize> {
+        let b = *self
+            .input
+            .get(self.ip)
+            .ok_or_else(|| invalid_data("truncated lzo block"))?;
+        self.ip += 1;
+        Ok(usize::from(b))
+    }
+
+    fn le16(&mut self) -> io::Result<usize> {
+        Ok(self.byte()? | self.byte()? << 8)
+    }
+
+    /// Extend a zero length field: each zero byte adds 255, then the first
+    /// non-zero byte plus `base`
+    fn length(&mut self, base: usize) -> io::Result<usize> {
+        let mut len = 0usize;
+        loop {
+            match self.byte()? {
+                0 => {
+                    len = len
+                        .checked_add(255)
+                        .filter(|&len| len <= self.limit)
+                        .ok_or_else(|| invalid_data("lzo run too long"))?;
+                }
+                b => return Ok(len + base + b),
+            }
+        }
+    }
+
+    fn literals(&mut self, len: usize) -> io::Result<()> {
+        let end = self.ip + len;
+        if end > self.input.len() {
+            return Err(invalid_data("truncated lzo literal run"));
+        }
+        if self.out.len() + len > self.limit {
+            return Err(invalid_data("lzo output overrun"));
+        }
+        self.out.extend_from_slice(&self.input[self.ip..end]);
+        self.ip = end;
+        Ok(())
+    }
+
+    fn copy_match(&mut self, distance: usize, len: usize) -> io::Result<()> {
+        if distance == 0 || distance > self.out.len() {
+            return Err(invalid_data("lzo match before start of output"));
+        }
+        if self.out.len() + len > self.limit {
+            return Err(invalid_data("lzo output overrun"));
+        }
+        // Matches may overlap their own output, so copy byte by byte
+        let from = self.out.len() - distance;
+        for i in 0..len {
+            let b = self.out[from + i];
+            self.out.push(b);
+        }
+        Ok(())
+    }
+
+    /// Literals folded into the last match instruction, then the next state
+    fn after_match(&mut self) -> io::Result<State> {
+        let count = usize::from(self.input[self.ip - 2] & 3);
+        if count == 0 {
+            return Ok(State::Instruction);
+        }
+        self.literals(count)?;
+        Ok(State::Match(self.byte()?))
+    }
+}
+
+/// Decompress one LZO1X block that expands to at most `limit` bytes
+pub(crate) fn decompress(input: &[u8], limit: usize) -> io::Result<Vec<u8>> {
+    let mut d = Decoder {
+        input,
+        ip: 0,
+        out: Vec::with_capacity(limit),
+        limit,
+    };
+
+    // A first byte above 17 encodes an initial literal run
+    let mut state = State::Instruction;
+    if let Some(&first) = input.first().filter(|&&b| b > 17) {
+        d.ip = 1;
+        let count = usize::from(first) - 17;
+        d.literals(count)?;
+        state = if count < 4 {
+            State::Match(d.byte()?)
+        } else {
+            State::AfterLiterals
+        };
+    }
+
+    loop {
+        state = match state {
+            State::Instruction => {
+                let t = d.byte()?;
+                if t >= 16 {
+                    State::Match(t)
+                } else {
+                    let len = if t == 0 { d.length(15)? } else { t };
+                    d.literals(len + 3)?;
+                    State::AfterLiterals
+                }
+            }
+            State::AfterLiterals => {
+                let t = d.byte()?;
+                if t >= 16 {
+                    State::Match(t)
+                } else {
+                    let distance = 1 + M2_MAX_OFFSET + (t >> 2) + (d.byte()? << 2);
+                    d.copy_match(distance, 3)?;
+                    d.after_match()?
+                }
+            }
+            State::Match(t) => {
+                if t >= 64 {
+                    // M2: 3 to 8 bytes within 2 KiB
+                    let distance = 1 + ((t >> 2) & 7) + (d.byte()? << 3);
+                    d.copy_match(distance, (t >> 5) + 1)?;
+                } else if t >= 32 {
+                    // M3: any length within 16 KiB
+                    let len = match t & 31 {
+                        0 => d.length(31)?,
+                        len => len,
+                    };
+                    let distance = 1 + (d.le16()? >> 2);
+                    d.copy_match(distance, len + 2)?;
+                } else if t >= 16 {
+                    // M4: any length within 48 KiB, or the end of stream
+                    let len = match t & 7 {
+                        0 => d.length(7)?,
+                        len => len,
+                    };
+                    let distance = ((t & 8) << 11) + (d.le16()? >> 2);
+                    if distance == 0 {
+                        break;
+                    }
+                    d.copy_match(distance + M4_BASE, len + 2)?;
+                } else {
+                    // M1: 2 bytes within 1 KiB
+                    let distance = 1 + (t >> 2) + (d.byte()? << 2);
+                    d.copy_match(distance, 2)?;
+                }
+                d.after_match()?
+            }
+        };
+    }
+
+    if d.ip != input.len() {
+        return Err(invalid_data("trailing data after lzo end marker"));
+    }
+    Ok(d.out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_literals_and_matches() {
+        // Initial run "abcd", an M2 match copying "abcd" twice over
+        // (length 8 at distance 4), then the end marker
+        let block = [17 + 4, b'a', b'b', b'c', b'd', 0xEC, 0x00, 0x11, 0x00, 0x00];
+        assert_eq!(decompress(&block, 64).unwrap(), b"abcdabcdabcd");
+    }
+
+    #[test]
+    fn decodes_long_runs() {
+        // A 300 byte literal run (zero length byte, one 255 extension),
+        // an M3 match of 30 bytes at distance 300 with one trailing
+        // literal, then the end marker
+        let literals: Vec<u8> = (0..300u32).map(|i| (i * 7 % 251) as u8).collect();
+        let mut block = vec![0x00, 0x00, 27]; // 255 + 15 + 27 + 3 = 300
+        block.extend(&literals);
+        block.extend_from_slice(&[32 | (30 - 2), (299 << 2 | 1) as u8, (299 >> 6) as u8]);
+        block.push(b'!');
+        block.extend_from_slice(&[0x11, 0x00, 0x00]);
+
+        let out = decompress(&block, 1024).unwrap();
+        assert_eq!(&out[..300], &literals[..]);
+        assert_eq!(&out[300..330], &literals[..30]);
+        assert_eq!(&out[330..], b"!");
+    }
+
+    #[test]
+    fn rejects_bad_blocks() {
+        // Match reaching before the start of output
+        assert!(decompress(&[0x40, 0x10, 0x11, 0x00, 0x00], 64).is_err());
+        // Missing end marker
+        assert!(decompress(&[17 + 4, b'a', b'b', b'c', b'd'], 64).is_err());
+        // Output larger than the block
+        assert!(decompress(&[17 + 4, b'a', b'b', b'c', b'd', 0x11, 0, 0], 3).is_err());
+    }
+}
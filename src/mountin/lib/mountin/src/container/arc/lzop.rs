@@ -0,0 +1,338 @@
+//! lzop container reader
+//!
+//! lzop is a compression wrapper containing a single decompressed stream.
+//! After a gzip-like header, data is stored as independently compressed
+//! LZO1X blocks (256 KiB by default), each prefixed by its sizes and
+//! optional Adler-32 or CRC-32 checksums. Blocks are decoded on demand.
+//! Several members written back to back (`lzop -c a b`) decode as one
+//! stream.
+
+use super::lzo;
+use crate::container::block::{Block, BlockReader, MAX_BLOCK_SIZE};
+use crate::container::{invalid_data, Child, Container};
+use crate::detect::Reader;
+use flate2::Crc;
+use std::io;
+use std::sync::Arc;
+
+const MAGIC: [u8; 9] = [0x89, b'L', b'Z', b'O', 0x00, 0x0D, 0x0A, 0x1A, 0x0A];
+
+/// Headers from this version on carry extract version, level and mtime high
+const VERSION_LEVEL: u16 = 0x0940;
+const VERSION_MIN: u16 = 0x0900;
+
+const METHOD_LZO1X_1: u8 = 1;
+const METHOD_LZO1X_1_15: u8 = 2;
+const METHOD_LZO1X_999: u8 = 3;
+
+const F_ADLER32_D: u32 = 0x0001;
+const F_ADLER32_C: u32 = 0x0002;
+const F_H_EXTRA_FIELD: u32 = 0x0040;
+const F_CRC32_D: u32 = 0x0100;
+const F_CRC32_C: u32 = 0x0200;
+const F_H_FILTER: u32 = 0x0800;
+const F_MASK: u32 = 0x3FFF;
+
+/// lzop container - decompresses content to expose inner stream
+pub struct LzopContainer;
+
+/// Static instance for registry
+pub static LZOP: LzopContainer = LzopContainer;
+
+impl Container for LzopContainer {
+    fn children(&self, reader: Arc<dyn Reader + Send + Sync>) -> io::Result<Vec<Child>> {
+        let (blocks, checks) = parse(&*reader)?;
+        let child = BlockReader::new(Arc::clone(&reader), blocks, move |index, raw| {
+            checks[index].decode(raw)
+        })?;
+
+        Ok(vec![Child {
+            index: 0,
+            offset: u64::MAX, // Transformed data, not a slice
+            reader: Arc::new(child),
+            metadata: Vec::new(),
+        }])
+    }
+}
+
+/// How to decode and verify one block
+struct BlockCheck {
+    stored: bool,
+    size: usize,
+    adler32: Option<u32>,
+    crc32: Option<u32>,
+}
+
+impl BlockCheck {
+    fn decode(&self, raw: &[u8]) -> io::Result<Vec<u8>> {
+        let data = if self.stored {
+            raw.to_vec()
+        } else {
+            lzo::decompress(raw, self.size)?
+        };
+        if self.adler32.is_some_and(|sum| adler32(&data) != sum) {
+            return Err(invalid_data("lzop Adler-32 mismatch"));
+        }
+        if self.crc32.is_some_and(|sum| {
+            let mut crc = Crc::new();
+            crc.update(&data);
+            crc.sum() != sum
+        }) {
+            return Err(invalid_data("lzop CRC-32 mismatch"));
+        }
+        Ok(data)
+    }
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    const MOD: u32 = 65521;
+    let (mut a, mut b) = (1u32, 0u32);
+    // 5552 bytes is the most that can be summed before b may overflow
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += u32::from(byte);
+            b += a;
+        }
+        a %= MOD;
+        b %= MOD;
+    }
+    (b << 16) | a
+}
+
+/// Sequential big-endian field reader over the parent
+struct Cursor<'a> {
+    reader: &'a dyn Reader,
+    pos: u64,
+}
+
+impl Cursor<'_> {
+    fn bytes(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        if self.reader.read_at(self.pos, buf)? != buf.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "short lzop read",
+            ));
+        }
+        self.pos += buf.len() as u64;
+        Ok(())
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        let mut buf = [0u8; 1];
+        self.bytes(&mut buf)?;
+        Ok(buf[0])
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        let mut buf = [0u8; 2];
+        self.bytes(&mut buf)?;
+        Ok(u16::from_be_bytes(buf))
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        let mut buf = [0u8; 4];
+        self.bytes(&mut buf)?;
+        Ok(u32::from_be_bytes(buf))
+    }
+}
+
+/// Parse a member header, returning its flags
+fn parse_header(cursor: &mut Cursor) -> io::Result<u32> {
+    let mut magic = [0u8; 9];
+    cursor.bytes(&mut magic)?;
+    if magic != MAGIC {
+        return Err(invalid_data("invalid lzop magic"));
+    }
+    let version = cursor.u16()?;
+    if version < VERSION_MIN {
+        return Err(invalid_data("unsupported lzop version"));
+    }
+    cursor.u16()?; // library version
+    if version >= VERSION_LEVEL {
+        cursor.u16()?; // version needed to extract
+    }
+    let method = cursor.u8()?;
+    if !matches!(
+        method,
+        METHOD_LZO1X_1 | METHOD_LZO1X_1_15 | METHOD_LZO1X_999
+    ) {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "unsupported lzop method",
+        ));
+    }
+    if version >= VERSION_LEVEL {
+        cursor.u8()?; // level
+    }
+    let flags = cursor.u32()?;
+    if flags & !F_MASK != 0 {
+        return Err(invalid_data("invalid lzop flags"));
+    }
+    if flags & F_H_FILTER != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "lzop filters are not supported",
+        ));
+    }
+    cursor.u32()?; // mode
+    cursor.u32()?; // mtime
+    if version >= VERSION_LEVEL {
+        cursor.u32()?; // mtime high
+    }
+    let name_len = cursor.u8()?;
+    cursor.pos += u64::from(name_len);
+    cursor.u32()?; // header checksum
+    if flags & F_H_EXTRA_FIELD != 0 {
+        let len = cursor.u32()?;
+        cursor.pos += u64::from(len) + 4;
+    }
+    Ok(flags)
+}
+
+/// Walk every member's block headers
+fn parse(reader: &dyn Reader) -> io::Result<(Vec<Block>, Vec<BlockCheck>)> {
+    let mut cursor = Cursor { reader, pos: 0 };
+    let mut blocks = Vec::new();
+    let mut checks = Vec::new();
+    let mut start = 0u64;
+
+    loop {
+        let flags = parse_header(&mut cursor)?;
+        loop {
+            let size = cursor.u32()?;
+            if size == 0 {
+                break;
+            }
+            let compressed_size = cursor.u32()?;
+            if u64::from(size) > MAX_BLOCK_SIZE || compressed_size > size {
+                return Err(invalid_data("invalid lzop block size"));
+            }
+            let stored = compressed_size == size;
+            let adler32 = (flags & F_ADLER32_D != 0)
+                .then(|| cursor.u32())
+                .transpose()?;
+            let crc32 = (flags & F_CRC32_D != 0).then(|| cursor.u32()).transpose()?;
+            // Compressed data checksums are only present for compressed blocks
+            if !stored {
+                for flag in [F_ADLER32_C, F_CRC32_C] {
+                    if flags & flag != 0 {
+                        cursor.u32()?;
+                    }
+                }
+            }
+
+            blocks.push(Block {
+                offset: cursor.pos,
+                compressed_size: u64::from(compressed_size),
+                start,
+                size: u64::from(size),
+            });
+            checks.push(BlockCheck {
+                stored,
+                size: size as usize,
+                adler32,
+                crc32,
+            });
+            cursor.pos += u64::from(compressed_size);
+            start += u64::from(size);
+        }
+
+        let mut magic = [0u8; 9];
+        if reader.read_at(cursor.pos, &mut magic)? != magic.len() || magic != MAGIC {
+            break;
+        }
+    }
+    Ok((blocks, checks))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::BytesReader;
+
+    /// A version 0x1040 member holding each block stored, or compressed
+    /// when `compress` is set; compressed blocks must repeat a 4-byte
+    /// pattern 2 to 9 times
+    fn member(blocks: &[&[u8]], flags: u32, compress: bool) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        out.extend_from_slice(&0x1040u16.to_be_bytes()); // version
+        out.extend_from_slice(&0x2080u16.to_be_bytes()); // library version
+        out.extend_from_slice(&0x0940u16.to_be_bytes()); // needed to extract
+        out.extend_from_slice(&[METHOD_LZO1X_1, 5]);
+        out.extend_from_slice(&flags.to_be_bytes());
+        out.extend_from_slice(&0o100644u32.to_be_bytes());
+        out.extend_from_slice(&[0; 8]); // mtime
+        out.push(8);
+        out.extend_from_slice(b"disk.img");
+        out.extend_from_slice(&[0; 4]); // header checksum, not verified
+
+        for block in blocks {
+            let data = if compress {
+                // The pattern as literals, then an M3 match at distance 4
+                let mut lzo = vec![17 + 4];
+                lzo.extend_from_slice(&block[..4]);
+                lzo.extend_from_slice(&[32 | (block.len() as u8 - 4 - 2), 3 << 2, 0]);
+                lzo.extend_from_slice(&[0x11, 0x00, 0x00]);
+                lzo
+            } else {
+                block.to_vec()
+            };
+            out.extend_from_slice(&(block.len() as u32).to_be_bytes());
+            out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            if flags & F_ADLER32_D != 0 {
+                out.extend_from_slice(&adler32(block).to_be_bytes());
+            }
+            if flags & F_CRC32_D != 0 {
+                let mut crc = Crc::new();
+                crc.update(block);
+                out.extend_from_slice(&crc.sum().to_be_bytes());
+            }
+            if flags & F_ADLER32_C != 0 && compress {
+                out.extend_from_slice(&adler32(&data).to_be_bytes());
+            }
+            out.extend(data);
+        }
+        out.extend_from_slice(&[0; 4]);
+        out
+    }
+
+    fn read(reader: &dyn Reader, offset: u64, len: usize) -> Vec<u8> {
+        let mut buf = vec![0u8; len];
+        let n = reader.read_at(offset, &mut buf).unwrap();
+        buf.truncate(n);
+        buf
+    }
+
+    #[test]
+    fn reads_blocks_and_members() {
+        let mut data = member(
+            &[b"one one one one one ", b"two two two "],
+            F_ADLER32_D | F_ADLER32_C,
+            true,
+        );
+        data.extend(member(&[b"stored three"], F_CRC32_D, false));
+        let kids = LZOP.children(Arc::new(BytesReader::new(data))).unwrap();
+        let reader = &*kids[0].reader;
+
+        assert_eq!(reader.size(), Some(44));
+        assert_eq!(read(reader, 0, 8), b"one one ");
+        assert_eq!(read(reader, 18, 64), b"e ");
+        assert_eq!(read(reader, 22, 10), b"o two two ");
+        assert_eq!(read(reader, 32, 64), b"stored three");
+    }
+
+    #[test]
+    fn verifies_checksums() {
+        let mut data = member(&[b"sum sum sum "], F_CRC32_D, true);
+        let at = data.len() - 4 - 10;
+        data[at] ^= 1;
+        let kids = LZOP.children(Arc::new(BytesReader::new(data))).unwrap();
+        assert!(kids[0].reader.read_at(0, &mut [0u8; 4]).is_err());
+    }
+
+    #[test]
+    fn computes_adler32() {
+        assert_eq!(adler32(b""), 1);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+    }
+}
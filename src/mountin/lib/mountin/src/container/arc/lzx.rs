@@ -0,0 +1,569 @@
+//! LZX decompressor (CAB variant)
+//!
+//! LZX is an LZ77 coder with Huffman-coded literals, lengths and position
+//! slots, and a three-entry queue of recent match offsets. Output comes in
+//! 32 KiB frames; in a cabinet each CFDATA block holds exactly one frame
+//! and the bit stream restarts, 16-bit aligned, at every block, so the
+//! decoder is fed one block at a time while its window, trees and current
+//! LZX block carry over.
+//!
+//! Bits are read most significant first from little-endian 16-bit words.
+//! Streams may start with an "E8 translation" header; the x86 CALL
+//! targets it rewrote are converted back after each frame.
+
+use super::huffman::Huffman;
+use crate::container::invalid_data;
+use std::io;
+
+pub(crate) const FRAME_SIZE: usize = 32 * 1024;
+pub(crate) const MIN_WINDOW_BITS: u32 = 15;
+pub(crate) const MAX_WINDOW_BITS: u32 = 21;
+
+const NUM_CHARS: usize = 256;
+const MIN_MATCH: usize = 2;
+const NUM_PRIMARY_LENGTHS: usize = 7;
+const LENGTH_SYMBOLS: usize = 249;
+const PRETREE_SYMBOLS: usize = 20;
+const ALIGNED_SYMBOLS: usize = 8;
+
+const BLOCK_VERBATIM: u32 = 1;
+const BLOCK_ALIGNED: u32 = 2;
+const BLOCK_UNCOMPRESSED: u32 = 3;
+
+/// Frames past this point (1 GiB of output) are never E8-translated
+const E8_MAX_FRAMES: u64 = 32768;
+
+/// Position slots for window sizes 2^15 to 2^21
+const POSITION_SLOTS: [usize; 7] = [30, 32, 34, 36, 38, 42, 50];
+
+/// Extra bits for each position slot: 0,0,0,0,1,1,2,2,... capped at 17
+fn extra_bits(slot: usize) -> u32 {
+    if slot < 4 {
+        0
+    } else {
+        ((slot as u32 - 2) / 2).min(17)
+    }
+}
+
+fn position_base(slot: usize) -> usize {
+    (0..slot).map(|s| 1usize << extra_bits(s)).sum()
+}
+
+/// Bit reader over one CFDATA block's compressed bytes
+struct Bits<'a> {
+    input: &'a [u8],
+    start: usize,
+    pos: usize,
+    buf: u32,
+    count: u32,
+}
+
+impl<'a> Bits<'a> {
+    fn new(input: &'a [u8], pos: usize) -> Self {
+        Self {
+            input,
+            start: pos,
+            pos,
+            buf: 0,
+            count: 0,
+        }
+    }
+
+    /// Keep at least 17 bits buffered; past the end of input, read zeros
+    fn refill(&mut self) {
+        while self.count <= 16 {
+            let lo = self.input.get(self.pos).copied().unwrap_or(0);
+            let hi = self.input.get(self.pos + 1).copied().unwrap_or(0);
+            let word = u32::from(u16::from_le_bytes([lo, hi]));
+            self.buf |= word << (16 - self.count);
+            self.count += 16;
+            self.pos += 2;
+        }
+    }
+
+    fn peek16(&mut self) -> u32 {
+        self.refill();
+        self.buf >> 16
+    }
+
+    fn skip(&mut self, n: u32) {
+        self.buf = if n == 32 { 0 } else { self.buf << n };
+        self.count -= n;
+    }
+
+    fn read(&mut self, n: u32) -> u32 {
+        if n == 0 {
+            return 0;
+        }
+        self.refill();
+        let value = self.buf >> (32 - n);
+        self.skip(n);
+        value
+    }
+
+    fn symbol(&mut self, tree: &Huffman) -> io::Result<usize> {
+        let (sym, len) = tree.decode(self.peek16())?;
+        self.skip(len);
+        Ok(usize::from(sym))
+    }
+
+    /// Bits consumed since `start`
+    fn consumed(&self) -> usize {
+        (self.pos - self.start) * 8 - self.count as usize
+    }
+
+    fn check_overrun(&self) -> io::Result<()> {
+        if self.start * 8 + self.consumed() > self.input.len() * 8 {
+            return Err(invalid_data("truncated LZX block"));
+        }
+        Ok(())
+    }
+
+    /// Byte offset after 1-16 bits of padding to the next 16-bit boundary,
+    /// where uncompressed blocks start
+    fn align(&self) -> usize {
+        let consumed = self.consumed();
+        self.start + (consumed + 16 - consumed % 16) / 8
+    }
+}
+
+/// Decoder state that persists across frames
+pub(crate) struct LzxDecoder {
+    window: Vec<u8>,
+    window_pos: usize,
+    /// Total bytes decoded, for match bounds and E8 positions
+    total: u64,
+    main_elements: usize,
+    main_lens: Vec<u8>,
+    length_lens: Vec<u8>,
+    main_tree: Option<Huffman>,
+    length_tree: Option<Huffman>,
+    aligned_tree: Option<Huffman>,
+    recent: [usize; 3],
+    header_read: bool,
+    e8_file_size: i64,
+    block_type: u32,
+    block_length: usize,
+    block_remaining: usize,
+    /// Where raw bytes of an uncompressed block continue in the next input
+    raw_pos: usize,
+    frame: u64,
+}
+
+impl LzxDecoder {
+    pub(crate) fn new(window_bits: u32) -> io::Result<Self> {
+        if !(MIN_WINDOW_BITS..=MAX_WINDOW_BITS).contains(&window_bits) {
+            return Err(invalid_data("invalid LZX window size"));
+        }
+        let slots = POSITION_SLOTS[(window_bits - MIN_WINDOW_BITS) as usize];
+        let main_elements = NUM_CHARS + slots * 8;
+        Ok(Self {
+            window: vec![0; 1 << window_bits],
+            window_pos: 0,
+            total: 0,
+            main_elements,
+            main_lens: vec![0; main_elements],
+            length_lens: vec![0; LENGTH_SYMBOLS],
+            main_tree: None,
+            length_tree: None,
+            aligned_tree: None,
+            recent: [1, 1, 1],
+            header_read: false,
+            e8_file_size: 0,
+            block_type: 0,
+            block_length: 0,
+            block_remaining: 0,
+            raw_pos: 0,
+            frame: 0,
+        })
+    }
+
+    /// Read code lengths [first, last) as deltas from the previous block's
+    fn read_lens(bits: &mut Bits, lens: &mut [u8], first: usize, last: usize) -> io::Result<()> {
+        let mut pre_lens = [0u8; PRETREE_SYMBOLS];
+        for len in &mut pre_lens {
+            *len = bits.read(4) as u8;
+        }
+        let pretree = Huffman::new(&pre_lens)?;
+
+        let delta = |old: u8, code: usize| ((usize::from(old) + 17 - code) % 17) as u8;
+        let mut x = first;
+        while x < last {
+            let code = bits.symbol(&pretree)?;
+            let (run, value) = match code {
+                17 => (bits.read(4) as usize + 4, None),
+                18 => (bits.read(5) as usize + 20, None),
+                19 => {
+                    let run = bits.read(1) as usize + 4;
+                    let code = bits.symbol(&pretree)?;
+                    if code > 16 {
+                        return Err(invalid_data("invalid LZX pretree code"));
+                    }
+                    (run, Some(delta(lens[x], code)))
+                }
+                _ => (1, Some(delta(lens[x], code))),
+            };
+            if x + run > last {
+                return Err(invalid_data("LZX code lengths overrun"));
+            }
+            lens[x..x + run].fill(value.unwrap_or(0));
+            x += run;
+        }
+        Ok(())
+    }
+
+    fn read_block_header(&mut self, input: &[u8], bits: &mut Bits) -> io::Result<()> {
+        self.block_type = bits.read(3);
+        self.block_length = ((bits.read(16) << 8) | bits.read(8)) as usize;
+        self.block_remaining = self.block_length;
+
+        match self.block_type {
+            BLOCK_VERBATIM | BLOCK_ALIGNED => {
+                if self.block_type == BLOCK_ALIGNED {
+                    let mut lens = [0u8; ALIGNED_SYMBOLS];
+                    for len in &mut lens {
+                        *len = bits.read(3) as u8;
+                    }
+                    self.aligned_tree = Some(Huffman::new(&lens)?);
+                }
+                Self::read_lens(bits, &mut self.main_lens, 0, NUM_CHARS)?;
+                Self::read_lens(bits, &mut self.main_lens, NUM_CHARS, self.main_elements)?;
+                self.main_tree = Some(Huffman::new(&self.main_lens)?);
+                Self::read_lens(bits, &mut self.length_lens, 0, LENGTH_SYMBOLS)?;
+                self.length_tree = Some(Huffman::new(&self.length_lens)?);
+            }
+            BLOCK_UNCOMPRESSED => {
+                let start = bits.align();
+                let header = input
+                    .get(start..start + 12)
+                    .ok_or_else(|| invalid_data("truncated LZX uncompressed block"))?;
+                for (i, r) in self.recent.iter_mut().enumerate() {
+                    let b = &header[i * 4..i * 4 + 4];
+                    *r = u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize;
+                }
+                self.raw_pos = start + 12;
+            }
+            _ => return Err(invalid_data("invalid LZX block type")),
+        }
+        Ok(())
+    }
+
+    fn put(&mut self, byte: u8) {
+        self.window[self.window_pos] = byte;
+        self.window_pos = (self.window_pos + 1) & (self.window.len() - 1);
+        self.total += 1;
+    }
+
+    fn copy_match(&mut self, offset: usize, len: usize) -> io::Result<()> {
+        if offset == 0 || offset as u64 > self.total || offset > self.window.len() {
+            return Err(invalid_data("LZX match before start of window"));
+        }
+        let mask = self.window.len() - 1;
+        let mut from = (self.window_pos + self.window.len() - offset) & mask;
+        for _ in 0..len {
+            let byte = self.window[from];
+            self.put(byte);
+            from = (from + 1) & mask;
+        }
+        Ok(())
+    }
+
+    /// Decode one match's offset from its position slot
+    fn match_offset(&mut self, bits: &mut Bits, slot: usize) -> io::Result<usize> {
+        if slot < 3 {
+            // Repeated offsets; using R1 or R2 swaps it to the front
+            self.recent.swap(0, slot);
+            return Ok(self.recent[0]);
+        }
+
+        let extra = extra_bits(slot);
+        let base = position_base(slot) - 2;
+        let offset = if self.block_type == BLOCK_ALIGNED && extra >= 3 {
+            let aligned = self
+                .aligned_tree
+                .as_ref()
+                .ok_or_else(|| invalid_data("LZX aligned tree missing"))?;
+            let verbatim = (bits.read(extra - 3) as usize) << 3;
+            base + verbatim + bits.symbol(aligned)?
+        } else {
+            base + bits.read(extra) as usize
+        };
+        self.recent = [offset, self.recent[0], self.recent[1]];
+        Ok(offset)
+    }
+
+    /// Decode `len` bytes of the current verbatim or aligned block
+    fn decode_run(&mut self, bits: &mut Bits, len: usize) -> io::Result<()> {
+        let (Some(main), Some(lengths)) = (self.main_tree.take(), self.length_tree.take()) else {
+            return Err(invalid_data("LZX trees missing"));
+        };
+        let result = (|| {
+            let end = self.total + len as u64;
+            while self.total < end {
+                let element = bits.symbol(&main)?;
+                if element < NUM_CHARS {
+                    self.put(element as u8);
+                    continue;
+                }
+                let element = element - NUM_CHARS;
+                let mut match_len = element & NUM_PRIMARY_LENGTHS;
+                if match_len == NUM_PRIMARY_LENGTHS {
+                    match_len += bits.symbol(&lengths)?;
+                }
+                match_len += MIN_MATCH;
+                let offset = self.match_offset(bits, element >> 3)?;
+                if self.total + match_len as u64 > end {
+                    return Err(invalid_data("LZX match overruns block"));
+                }
+                self.copy_match(offset, match_len)?;
+            }
+            Ok(())
+        })();
+        self.main_tree = Some(main);
+        self.length_tree = Some(lengths);
+        result
+    }
+
+    /// Decode one frame of `size` bytes from one CFDATA block's data
+    pub(crate) fn decode_frame(&mut self, input: &[u8], size: usize) -> io::Result<Vec<u8>> {
+        if size > FRAME_SIZE {
+            return Err(invalid_data("LZX frame too large"));
+        }
+        let frame_start = self.window_pos;
+        if frame_start + size > self.window.len() {
+            return Err(invalid_data("LZX frame crosses end of window"));
+        }
+        // An uncompressed block carries on at the start of the next input
+        self.raw_pos = 0;
+        let mut bits = Bits::new(input, 0);
+
+        if !self.header_read {
+            if bits.read(1) == 1 {
+                let high = bits.read(16);
+                let low = bits.read(16);
+                self.e8_file_size = i64::from((high << 16) | low);
+            }
+            self.header_read = true;
+        }
+
+        let mut todo = size;
+        while todo > 0 {
+            if self.block_remaining == 0 {
+                if self.block_type == BLOCK_UNCOMPRESSED {
+                    // Odd-length uncompressed blocks are padded to a word
+                    let pad = self.block_length & 1;
+                    bits = Bits::new(input, self.raw_pos + pad);
+                }
+                self.read_block_header(input, &mut bits)?;
+                continue;
+            }
+
+            let run = self.block_remaining.min(todo);
+            if self.block_type == BLOCK_UNCOMPRESSED {
+                let raw = input
+                    .get(self.raw_pos..self.raw_pos + run)
+                    .ok_or_else(|| invalid_data("truncated LZX uncompressed block"))?;
+                for &byte in raw {
+                    self.put(byte);
+                }
+                self.raw_pos += run;
+            } else {
+                self.decode_run(&mut bits, run)?;
+                bits.check_overrun()?;
+            }
+            self.block_remaining -= run;
+            todo -= run;
+        }
+
+        let mut frame = self.window[frame_start..frame_start + size].to_vec();
+        if self.e8_file_size != 0 && self.frame < E8_MAX_FRAMES {
+            self.undo_e8(&mut frame);
+        }
+        self.frame += 1;
+        Ok(frame)
+    }
+
+    /// Turn relative CALL targets back into the absolute ones encoded
+    fn undo_e8(&self, frame: &mut [u8]) {
+        if frame.len() <= 10 {
+            return;
+        }
+        let mut curpos = (self.frame * FRAME_SIZE as u64) as i64;
+        let mut i = 0;
+        while i < frame.len() - 10 {
+            if frame[i] != 0xE8 {
+                i += 1;
+                curpos += 1;
+                continue;
+            }
+            let b = &mut frame[i + 1..i + 5];
+            let abs = i64::from(i32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+            if abs >= -curpos && abs < self.e8_file_size {
+                let rel = if abs >= 0 {
+                    abs - curpos
+                } else {
+                    abs + self.e8_file_size
+                };
+                b.copy_from_slice(&(rel as i32).to_le_bytes());
+            }
+            i += 5;
+            curpos += 5;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// MSB-first writer producing little-endian 16-bit words
+    #[derive(Default)]
+    struct BitWriter {
+        out: Vec<u8>,
+        acc: u32,
+        count: u32,
+    }
+
+    impl BitWriter {
+        fn put(&mut self, value: u32, n: u32) {
+            for i in (0..n).rev() {
+                self.acc = (self.acc << 1) | ((value >> i) & 1);
+                self.count += 1;
+                if self.count == 16 {
+                    self.out.extend_from_slice(&(self.acc as u16).to_le_bytes());
+                    self.acc = 0;
+                    self.count = 0;
+                }
+            }
+        }
+
+        fn finish(mut self) -> Vec<u8> {
+            if self.count > 0 {
+                self.put(0, 16 - self.count);
+            }
+            self.out
+        }
+    }
+
+    /// Canonical codes for a length list, as (code, length) per symbol
+    fn codes(lens: &[u8]) -> Vec<(u32, u32)> {
+        let mut out = vec![(0, 0); lens.len()];
+        let mut code = 0u32;
+        for len in 1..=16u8 {
+            for (sym, _) in lens.iter().enumerate().filter(|(_, &l)| l == len) {
+                out[sym] = (code, u32::from(len));
+                code += 1;
+            }
+            code <<= 1;
+        }
+        out
+    }
+
+    /// Write lengths with a pretree where codes 0..=15 are 4 bits long
+    fn write_lens(w: &mut BitWriter, old: &[u8], new: &[u8]) {
+        for sym in 0..PRETREE_SYMBOLS {
+            w.put(if sym < 16 { 4 } else { 0 }, 4);
+        }
+        for (&o, &n) in old.iter().zip(new) {
+            let code = (u32::from(o) + 17 - u32::from(n)) % 17;
+            assert!(code < 16);
+            w.put(code, 4);
+        }
+    }
+
+    /// Verbatim block: main tree of 16 8-bit and 480 9-bit codes (window
+    /// 2^15), length tree unused
+    fn verbatim_block(
+        w: &mut BitWriter,
+        length: u32,
+        body: impl FnOnce(&mut BitWriter, &[(u32, u32)]),
+    ) {
+        let main_lens: Vec<u8> = (0..NUM_CHARS + 30 * 8)
+            .map(|sym| if sym < 16 { 8 } else { 9 })
+            .collect();
+        w.put(BLOCK_VERBATIM, 3);
+        w.put(length >> 8, 16);
+        w.put(length & 0xFF, 8);
+        write_lens(w, &[0; NUM_CHARS], &main_lens[..NUM_CHARS]);
+        write_lens(w, &[0; 30 * 8], &main_lens[NUM_CHARS..]);
+        write_lens(w, &[0; LENGTH_SYMBOLS], &[0; LENGTH_SYMBOLS]);
+        body(w, &codes(&main_lens));
+    }
+
+    #[test]
+    fn decodes_verbatim_block() {
+        let mut w = BitWriter::default();
+        w.put(0, 1); // no E8 translation
+        verbatim_block(&mut w, 12, |w, main| {
+            for &b in b"abcd" {
+                let (code, len) = main[usize::from(b)];
+                w.put(code, len);
+            }
+            // Length 8 (primary 6) at offset 4: slot 3 holds offset 1,
+            // slot 4 offsets 2-3 with one extra bit, so use slot 5 (offset
+            // 4 is base 6 - 2 + extra 0)
+            let (code, len) = main[NUM_CHARS + (5 << 3) + 6];
+            w.put(code, len);
+            w.put(0, extra_bits(5));
+        });
+        let data = w.finish();
+
+        let mut lzx = LzxDecoder::new(15).unwrap();
+        assert_eq!(lzx.decode_frame(&data, 12).unwrap(), b"abcdabcdabcd");
+    }
+
+    #[test]
+    fn decodes_uncompressed_blocks_across_frames() {
+        // One 5-byte uncompressed block split over two frames, then a
+        // second block in the second frame
+        let mut w = BitWriter::default();
+        w.put(0, 1);
+        w.put(BLOCK_UNCOMPRESSED, 3);
+        w.put(0, 16);
+        w.put(5, 8);
+        let mut first = w.finish(); // 28 bits, padded to the boundary
+        for r in [1u32, 1, 1] {
+            first.extend_from_slice(&r.to_le_bytes());
+        }
+        first.extend_from_slice(b"hel");
+
+        let mut second = b"lo".to_vec();
+        second.push(0); // pad after the odd-length block
+        let mut w = BitWriter::default();
+        w.put(BLOCK_UNCOMPRESSED, 3);
+        w.put(0, 16);
+        w.put(2, 8);
+        let mut header = w.finish();
+        for r in [1u32, 1, 1] {
+            header.extend_from_slice(&r.to_le_bytes());
+        }
+        second.extend(header);
+        second.extend_from_slice(b"!!");
+
+        let mut lzx = LzxDecoder::new(16).unwrap();
+        assert_eq!(lzx.decode_frame(&first, 3).unwrap(), b"hel");
+        assert_eq!(lzx.decode_frame(&second, 4).unwrap(), b"lo!!");
+    }
+
+    #[test]
+    fn undoes_e8_translation() {
+        let mut lzx = LzxDecoder::new(15).unwrap();
+        lzx.e8_file_size = 1 << 20;
+        // CALL at position 2 encoded with absolute target 0x102
+        let mut frame = vec![0x90, 0x90, 0xE8, 0x02, 0x01, 0, 0];
+        frame.extend([0x90; 10]);
+        lzx.undo_e8(&mut frame);
+        assert_eq!(&frame[2..7], &[0xE8, 0x00, 0x01, 0, 0]);
+    }
+
+    #[test]
+    fn rejects_bad_streams() {
+        assert!(LzxDecoder::new(22).is_err());
+        let mut w = BitWriter::default();
+        w.put(0, 1);
+        w.put(7, 3); // invalid block type
+        w.put(0, 24);
+        let data = w.finish();
+        assert!(LzxDecoder::new(15).unwrap().decode_frame(&data, 4).is_err());
+    }
+}
@@ -0,0 +1,173 @@
+//! Classic Mac OS file helpers
+//!
+//! Mac files have two forks and Finder info (type and creator codes).
+//! The wrappers that carry them over other systems (MacBinary, BinHex,
+//! AppleSingle/AppleDouble, StuffIt) expose each non-empty fork as its own
+//! child, tagged with the fork and the Finder codes. Names are Mac Roman,
+//! and a `/` in a name becomes `:` as on macOS.
+
+use crate::container::{invalid_data, Child, Metadata};
+use crate::detect::Reader;
+use std::io;
+use std::sync::Arc;
+
+/// Mac Roman characters 0x80-0xFF
+const MAC_ROMAN: [char; 128] = [
+    'Ä', 'Å', 'Ç', 'É', 'Ñ', 'Ö', 'Ü', 'á', 'à', 'â', 'ä', 'ã', 'å', 'ç', 'é', 'è', //
+    'ê', 'ë', 'í', 'ì', 'î', 'ï', 'ñ', 'ó', 'ò', 'ô', 'ö', 'õ', 'ú', 'ù', 'û', 'ü', //
+    '†', '°', '¢', '£', '§', '•', '¶', 'ß', '®', '©', '™', '´', '¨', '≠', 'Æ', 'Ø', //
+    '∞', '±', '≤', '≥', '¥', 'µ', '∂', '∑', '∏', 'π', '∫', 'ª', 'º', 'Ω', 'æ', 'ø', //
+    '¿', '¡', '¬', '√', 'ƒ', '≈', '∆', '«', '»', '…', '\u{A0}', 'À', 'Ã', 'Õ', 'Œ', 'œ', //
+    '–', '—', '“', '”', '‘', '’', '÷', '◊', 'ÿ', 'Ÿ', '⁄', '€', '‹', '›', 'ﬁ', 'ﬂ', //
+    '‡', '·', '‚', '„', '‰', 'Â', 'Ê', 'Á', 'Ë', 'È', 'Í', 'Î', 'Ï', 'Ì', 'Ó', 'Ô', //
+    '\u{F8FF}', 'Ò', 'Ú', 'Û', 'Ù', 'ı', 'ˆ', '˜', '¯', '˘', '˙', '˚', '¸', '˝', '˛', 'ˇ',
+];
+
+/// Run-length escape shared by BinHex and StuffIt
+const RLE_MARKER: u8 = 0x90;
+
+/// Decode Mac Roman text
+pub(crate) fn mac_roman(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|&b| {
+            if b < 0x80 {
+                char::from(b)
+            } else {
+                MAC_ROMAN[usize::from(b - 0x80)]
+            }
+        })
+        .collect()
+}
+
+/// A file name component, with `/` shown as `:`
+pub(crate) fn file_name(bytes: &[u8]) -> String {
+    mac_roman(bytes).replace('/', ":")
+}
+
+/// CRC-16/XMODEM (polynomial 0x1021, MSB first), as used by MacBinary and
+/// BinHex
+pub(crate) fn crc16_xmodem(data: &[u8]) -> u16 {
+    data.iter().fold(0, |crc, &byte| {
+        (0..8).fold(crc ^ (u16::from(byte) << 8), |crc, _| {
+            if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+/// Undo 0x90 run-length coding: 0x90 n repeats the last byte written to
+/// n copies in all, and 0x90 0 is a literal 0x90. Output past `limit`
+/// bytes is an error.
+pub(crate) fn rle90(data: &[u8], limit: usize) -> io::Result<Vec<u8>> {
+    let mut out = Vec::new();
+    let mut bytes = data.iter().copied();
+    while let Some(byte) = bytes.next() {
+        if byte != RLE_MARKER {
+            out.push(byte);
+        } else {
+            match bytes.next() {
+                Some(0) => out.push(RLE_MARKER),
+                Some(count) => {
+                    let last = *out
+                        .last()
+                        .ok_or_else(|| invalid_data("run-length repeat with no byte"))?;
+                    let repeat = usize::from(count) - 1;
+                    if out.len() + repeat > limit {
+                        return Err(invalid_data("run-length data too large"));
+                    }
+                    out.resize(out.len() + repeat, last);
+                }
+                None => return Err(invalid_data("truncated run-length data")),
+            }
+        }
+        if out.len() > limit {
+            return Err(invalid_data("run-length data too large"));
+        }
+    }
+    Ok(out)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Fork {
+    Data,
+    Resource,
+}
+
+impl Fork {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Data => "data",
+            Self::Resource => "resource",
+        }
+    }
+}
+
+/// Name and Finder codes shared by a file's forks
+#[derive(Clone, Debug, Default)]
+pub(crate) struct MacFile {
+    pub(crate) name: String,
+    pub(crate) file_type: [u8; 4],
+    pub(crate) creator: [u8; 4],
+}
+
+impl MacFile {
+    /// A child for one fork, with the name (when known), the fork and the
+    /// Finder codes (when set) as metadata
+    pub(crate) fn fork_child(
+        &self,
+        index: u32,
+        offset: u64,
+        reader: Arc<dyn Reader + Send + Sync>,
+        fork: Fork,
+    ) -> Child {
+        Child {
+            index,
+            offset,
+            reader,
+            metadata: self.fork_metadata(fork),
+        }
+    }
+
+    /// The name, fork and Finder type and creator of one of the forks
+    pub(crate) fn fork_metadata(&self, fork: Fork) -> Metadata {
+        let mut metadata = Vec::new();
+        if !self.name.is_empty() {
+            metadata.push(("name", self.name.clone()));
+        }
+        metadata.push(("fork", fork.as_str().to_string()));
+        if self.file_type != [0; 4] || self.creator != [0; 4] {
+            metadata.push(("type", mac_roman(&self.file_type)));
+            metadata.push(("creator", mac_roman(&self.creator)));
+        }
+        metadata
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_mac_roman() {
+        assert_eq!(file_name(b"Caf\x8E/Men\x9F"), "Café:Menü");
+        assert_eq!(mac_roman(b"\xF0"), "\u{F8FF}");
+    }
+
+    #[test]
+    fn crc16_xmodem_check_value() {
+        assert_eq!(crc16_xmodem(b"123456789"), 0x31C3);
+    }
+
+    #[test]
+    fn expands_runs() {
+        assert_eq!(rle90(b"a\x90\x04b", 16).unwrap(), b"aaaab");
+        // A literal marker can itself be repeated
+        assert_eq!(rle90(b"\x90\x00\x90\x03", 16).unwrap(), b"\x90\x90\x90");
+        assert!(rle90(b"\x90\x05", 16).is_err());
+        assert!(rle90(b"a\x90\xFF", 16).is_err());
+    }
+}
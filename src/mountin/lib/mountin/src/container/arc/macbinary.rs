@@ -0,0 +1,160 @@
+//! MacBinary container reader
+//!
+//! A 128-byte header with the file's name, Finder info and fork lengths,
+//! then the data fork and the resource fork, each padded to a multiple of
+//! 128 bytes. MacBinary II and III protect the header with a CRC, which
+//! is also how they are detected; MacBinary I has no CRC and is accepted
+//! here on its structure alone. MacBinary II may put a secondary header
+//! before the data fork.
+
+use super::mac::{crc16_xmodem, file_name, Fork, MacFile};
+use crate::container::{be32, invalid_data, slice::SliceReader, Child, Container};
+use crate::detect::Reader;
+use std::io;
+use std::sync::Arc;
+
+const HEADER_SIZE: usize = 128;
+const BLOCK: u64 = 128;
+const MAX_NAME: u8 = 63;
+/// MacBinary limits forks to 8 MiB, but later writers exceed it
+const MAX_FORK: u32 = 0x7FFF_FFFF;
+
+/// MacBinary container - exposes the data and resource forks
+pub struct MacBinaryContainer;
+
+/// Static instance for registry
+pub static MACBINARY: MacBinaryContainer = MacBinaryContainer;
+
+impl Container for MacBinaryContainer {
+    fn children(&self, reader: Arc<dyn Reader + Send + Sync>) -> io::Result<Vec<Child>> {
+        let mut header = [0u8; HEADER_SIZE];
+        if reader.read_at(0, &mut header)? != HEADER_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "short MacBinary header read",
+            ));
+        }
+        // MacBinary I leaves everything from byte 99 on zero
+        let macbinary_1 = structure_ok(&header) && header[99..].iter().all(|&b| b == 0);
+        if !valid_header(&header) && !macbinary_1 {
+            return Err(invalid_data("invalid MacBinary header"));
+        }
+
+        let file = MacFile {
+            name: file_name(&header[2..2 + usize::from(header[1])]),
+            file_type: header[65..69].try_into().unwrap(),
+            creator: header[69..73].try_into().unwrap(),
+        };
+        let data_len = u64::from(be32(&header[83..]));
+        let resource_len = u64::from(be32(&header[87..]));
+        let secondary = u64::from(u16::from_be_bytes([header[120], header[121]]));
+        let data_start = HEADER_SIZE as u64 + secondary.next_multiple_of(BLOCK);
+        let resource_start = data_start + data_len.next_multiple_of(BLOCK);
+        if let Some(size) = reader.size() {
+            if resource_start + resource_len > size {
+                return Err(invalid_data("MacBinary forks extend past end of file"));
+            }
+        }
+
+        let mut children = vec![file.fork_child(
+            0,
+            data_start,
+            Arc::new(SliceReader::new(Arc::clone(&reader), data_start, data_len)),
+            Fork::Data,
+        )];
+        if resource_len > 0 {
+            children.push(file.fork_child(
+                1,
+                resource_start,
+                Arc::new(SliceReader::new(reader, resource_start, resource_len)),
+                Fork::Resource,
+            ));
+        }
+        Ok(children)
+    }
+}
+
+/// Fields every MacBinary version keeps fixed
+fn structure_ok(header: &[u8]) -> bool {
+    header.len() >= HEADER_SIZE
+        && header[0] == 0
+        && (1..=MAX_NAME).contains(&header[1])
+        && header[74] == 0
+        && header[82] == 0
+        && be32(&header[83..]) <= MAX_FORK
+        && be32(&header[87..]) <= MAX_FORK
+}
+
+/// Whether a MacBinary II or III header is intact (its CRC covers bytes
+/// 0-123)
+pub(crate) fn valid_header(header: &[u8]) -> bool {
+    structure_ok(header)
+        && crc16_xmodem(&header[..124]) == u16::from_be_bytes([header[124], header[125]])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::BytesReader;
+
+    fn macbinary(data: &[u8], resource: &[u8], crc: bool) -> Vec<u8> {
+        let mut out = vec![0u8; HEADER_SIZE];
+        out[1] = 9;
+        out[2..11].copy_from_slice(b"Disk.img\xA5");
+        out[65..73].copy_from_slice(b"dImgdCpy");
+        out[83..87].copy_from_slice(&(data.len() as u32).to_be_bytes());
+        out[87..91].copy_from_slice(&(resource.len() as u32).to_be_bytes());
+        if crc {
+            out[122] = 0x81;
+            out[123] = 0x81;
+            let crc = crc16_xmodem(&out[..124]);
+            out[124..126].copy_from_slice(&crc.to_be_bytes());
+        }
+        out.extend_from_slice(data);
+        out.resize(out.len().next_multiple_of(128), 0);
+        out.extend_from_slice(resource);
+        out
+    }
+
+    fn read(child: &Child) -> Vec<u8> {
+        let mut buf = vec![0u8; child.reader.size().unwrap() as usize];
+        child.reader.read_at(0, &mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn reads_forks_and_finder_info() {
+        let file = macbinary(b"data fork", b"rsrc", true);
+        assert!(valid_header(&file));
+        let children = MACBINARY
+            .children(Arc::new(BytesReader::new(file)))
+            .unwrap();
+        assert_eq!(children.len(), 2);
+        assert_eq!(read(&children[0]), b"data fork");
+        assert_eq!(read(&children[1]), b"rsrc");
+        assert_eq!(children[1].offset, 256);
+        assert_eq!(
+            children[0].metadata,
+            [
+                ("name", "Disk.img•".to_string()),
+                ("fork", "data".to_string()),
+                ("type", "dImg".to_string()),
+                ("creator", "dCpy".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn accepts_macbinary_1_and_rejects_bad_crc() {
+        let file = macbinary(b"data", b"", false);
+        assert!(!valid_header(&file));
+        let children = MACBINARY
+            .children(Arc::new(BytesReader::new(file.clone())))
+            .unwrap();
+        assert_eq!(children.len(), 1);
+
+        let mut bad = macbinary(b"data", b"", true);
+        bad[124] ^= 1;
+        assert!(MACBINARY.children(Arc::new(BytesReader::new(bad))).is_err());
+    }
+}
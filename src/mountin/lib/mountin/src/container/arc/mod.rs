@@ -1,9 +1,34 @@
 //! Archive container readers
 
+pub mod applesingle;
+pub mod ar;
+pub mod arj;
+pub mod bcj;
+pub mod binhex;
 pub mod bzip2;
+pub mod cab;
 pub mod compress;
 pub mod gzip;
+pub mod huffman;
+pub mod inflate;
+pub mod lha;
 pub mod lz4;
+pub mod lzh;
+pub mod lzip;
+pub mod lzo;
+pub mod lzop;
 pub mod lzma;
+pub mod lzx;
+pub mod mac;
+pub mod macbinary;
+pub mod mscompress;
+pub mod ole;
+pub mod pbzx;
+pub mod quantum;
+pub mod sea;
+pub mod sevenzip;
+pub mod stuffit;
+pub mod xar;
 pub mod xz;
+pub mod zoo;
 pub mod zstd;
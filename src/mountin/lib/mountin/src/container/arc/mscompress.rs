@@ -0,0 +1,520 @@
+//! Microsoft COMPRESS.EXE (SZDD and KWAJ) container reader
+//!
+//! Both formats hold one compressed file. SZDD is plain LZSS over a 4 KiB
+//! ring buffer preset to spaces; QBasic's `SZ` variant only differs in its
+//! header and where the ring starts. KWAJ adds optional header fields (the
+//! original name among them) and a choice of methods: stored, XOR 0xFF,
+//! SZDD's LZSS, an LZ+Huffman coder, and MSZIP blocks.
+//!
+//! SZDD files only keep the last character of their original name; the
+//! rest comes from the compressed file's own name (`SETUP.EX_`), when the
+//! file is on disk.
+
+use super::cab::mszip_block;
+use super::huffman::Huffman;
+use crate::container::{invalid_data, read_all, BytesReader, Child, Container, MAX_SIZE};
+use crate::detect::Reader;
+use std::io;
+use std::sync::Arc;
+
+const SZDD_MAGIC: &[u8; 8] = b"SZDD\x88\xF0\x27\x33";
+const QBASIC_MAGIC: &[u8; 8] = b"SZ \x88\xF0\x27\x33\xD1";
+const KWAJ_MAGIC: &[u8; 8] = b"KWAJ\x88\xF0\x27\xD1";
+const SZDD_METHOD_A: u8 = b'A';
+
+const RING_SIZE: usize = 4096;
+const RING_FILL: u8 = b' ';
+
+const KWAJ_STORED: u16 = 0;
+const KWAJ_XOR: u16 = 1;
+const KWAJ_SZDD: u16 = 2;
+const KWAJ_LZH: u16 = 3;
+const KWAJ_MSZIP: u16 = 4;
+
+const KWAJ_HAS_LENGTH: u16 = 0x0001;
+const KWAJ_HAS_UNKNOWN: u16 = 0x0002;
+const KWAJ_HAS_DATA: u16 = 0x0004;
+const KWAJ_HAS_NAME: u16 = 0x0008;
+const KWAJ_HAS_EXTENSION: u16 = 0x0010;
+const KWAJ_HAS_TEXT: u16 = 0x0020;
+/// Longest name and extension, including their terminators
+const KWAJ_NAME_SIZE: usize = 9;
+const KWAJ_EXTENSION_SIZE: usize = 4;
+
+/// COMPRESS.EXE container - decompresses content to expose the original file
+pub struct MsCompressContainer;
+
+/// Static instance for registry
+pub static MSCOMPRESS: MsCompressContainer = MsCompressContainer;
+
+impl Container for MsCompressContainer {
+    fn children(&self, reader: Arc<dyn Reader + Send + Sync>) -> io::Result<Vec<Child>> {
+        let data = read_all(&*reader)?;
+        let magic = data
+            .get(..8)
+            .ok_or_else(|| invalid_data("short COMPRESS.EXE header"))?;
+
+        let (decompressed, name) = if magic == SZDD_MAGIC || magic == QBASIC_MAGIC {
+            let (decompressed, missing) = decode_szdd(&data)?;
+            let name = reader
+                .path()
+                .and_then(|path| path.file_name())
+                .map(|name| original_name(&name.to_string_lossy(), missing));
+            (decompressed, name)
+        } else if magic == KWAJ_MAGIC {
+            decode_kwaj(&data)?
+        } else {
+            return Err(invalid_data("invalid COMPRESS.EXE magic"));
+        };
+
+        Ok(vec![Child {
+            index: 0,
+            offset: u64::MAX, // Transformed data, not a slice
+            reader: Arc::new(BytesReader::new(decompressed)),
+            metadata: name.map(|name| vec![("name", name)]).unwrap_or_default(),
+        }])
+    }
+}
+
+/// Put the stored last character back in place of the trailing '_'
+fn original_name(compressed: &str, missing: u8) -> String {
+    match compressed.strip_suffix('_') {
+        Some(stem) if missing != 0 => format!("{stem}{}", char::from(missing)),
+        _ => compressed.to_string(),
+    }
+}
+
+fn le16(data: &[u8], at: usize) -> io::Result<u16> {
+    data.get(at..at + 2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        .ok_or_else(|| invalid_data("short COMPRESS.EXE header"))
+}
+
+fn le32(data: &[u8], at: usize) -> io::Result<u32> {
+    data.get(at..at + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| invalid_data("short COMPRESS.EXE header"))
+}
+
+fn check_size(size: u64) -> io::Result<usize> {
+    if size > MAX_SIZE as u64 {
+        return Err(invalid_data("COMPRESS.EXE output too large"));
+    }
+    Ok(size as usize)
+}
+
+/// Decode an SZDD or QBasic file, returning its data and the last
+/// character of its name (0 if unknown)
+fn decode_szdd(data: &[u8]) -> io::Result<(Vec<u8>, u8)> {
+    let (length, missing, start, ring_start) = if data.starts_with(SZDD_MAGIC) {
+        if data.get(8) != Some(&SZDD_METHOD_A) {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "unsupported SZDD method",
+            ));
+        }
+        (le32(data, 10)?, data[9], 14, RING_SIZE - 16)
+    } else {
+        (le32(data, 8)?, 0, 12, RING_SIZE - 18)
+    };
+    let length = check_size(u64::from(length))?;
+    let mut out = lzss(&data[start..], ring_start, length);
+    if out.len() < length {
+        return Err(invalid_data("truncated SZDD data"));
+    }
+    out.truncate(length);
+    Ok((out, missing))
+}
+
+/// SZDD's LZSS: flag bytes whose set bits (LSB first) mark literals and
+/// clear bits 12-bit ring positions with 4-bit lengths. Stops at the end of
+/// input or once `limit` bytes are out.
+fn lzss(input: &[u8], ring_start: usize, limit: usize) -> Vec<u8> {
+    let mut ring = [RING_FILL; RING_SIZE];
+    let mut pos = ring_start;
+    let mut out = Vec::with_capacity(limit);
+    let mut input = input.iter().copied();
+
+    'outer: while out.len() < limit {
+        let Some(flags) = input.next() else { break };
+        for bit in 0..8 {
+            if flags & (1 << bit) != 0 {
+                let Some(byte) = input.next() else {
+                    break 'outer;
+                };
+                ring[pos] = byte;
+                pos = (pos + 1) % RING_SIZE;
+                out.push(byte);
+            } else {
+                let (Some(lo), Some(hi)) = (input.next(), input.next()) else {
+                    break 'outer;
+                };
+                let mut from = usize::from(lo) | (usize::from(hi & 0xF0) << 4);
+                for _ in 0..usize::from(hi & 0x0F) + 3 {
+                    let byte = ring[from];
+                    ring[pos] = byte;
+                    pos = (pos + 1) % RING_SIZE;
+                    from = (from + 1) % RING_SIZE;
+                    out.push(byte);
+                }
+            }
+        }
+    }
+    out
+}
+
+/// Decode a KWAJ file, returning its data and original name if stored
+fn decode_kwaj(data: &[u8]) -> io::Result<(Vec<u8>, Option<String>)> {
+    let method = le16(data, 8)?;
+    let start = usize::from(le16(data, 10)?);
+    let flags = le16(data, 12)?;
+
+    let mut pos = 14;
+    let mut length = None;
+    if flags & KWAJ_HAS_LENGTH != 0 {
+        length = Some(check_size(u64::from(le32(data, pos)?))?);
+        pos += 4;
+    }
+    if flags & KWAJ_HAS_UNKNOWN != 0 {
+        pos += 2;
+    }
+    if flags & KWAJ_HAS_DATA != 0 {
+        pos += 2 + usize::from(le16(data, pos)?);
+    }
+    let mut name = None;
+    for (flag, max) in [
+        (KWAJ_HAS_NAME, KWAJ_NAME_SIZE),
+        (KWAJ_HAS_EXTENSION, KWAJ_EXTENSION_SIZE),
+    ] {
+        if flags & flag == 0 {
+            continue;
+        }
+        let field = data.get(pos..).unwrap_or_default();
+        let len = field
+            .iter()
+            .take(max)
+            .position(|&b| b == 0)
+            .ok_or_else(|| invalid_data("invalid KWAJ name"))?;
+        let part = String::from_utf8_lossy(&field[..len]);
+        name = Some(match (name, flag) {
+            (Some(stem), KWAJ_HAS_EXTENSION) => format!("{stem}.{part}"),
+            _ => part.into_owned(),
+        });
+        pos += len + 1;
+    }
+    if flags & KWAJ_HAS_TEXT != 0 {
+        pos += 2 + usize::from(le16(data, pos)?);
+    }
+    if pos > start || start > data.len() {
+        return Err(invalid_data("invalid KWAJ data offset"));
+    }
+
+    let input = &data[start..];
+    let limit = length.unwrap_or(MAX_SIZE);
+    let mut out = match method {
+        KWAJ_STORED => input.to_vec(),
+        KWAJ_XOR => input.iter().map(|b| b ^ 0xFF).collect(),
+        KWAJ_SZDD => lzss(input, RING_SIZE - 16, limit),
+        KWAJ_LZH => Lzh::new(input).decode(limit)?,
+        KWAJ_MSZIP => kwaj_mszip(input, limit)?,
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "unsupported KWAJ method",
+            ))
+        }
+    };
+    if let Some(length) = length {
+        if out.len() < length {
+            return Err(invalid_data("truncated KWAJ data"));
+        }
+        out.truncate(length);
+    }
+    Ok((out, name))
+}
+
+/// KWAJ's MSZIP: MSZIP blocks, each after a 16-bit size, up to a zero size
+fn kwaj_mszip(mut input: &[u8], limit: usize) -> io::Result<Vec<u8>> {
+    let mut out = Vec::new();
+    let mut previous = Vec::new();
+    while out.len() < limit {
+        let size = match input {
+            [lo, hi, ..] => u16::from_le_bytes([*lo, *hi]),
+            _ => break,
+        };
+        if size == 0 {
+            break;
+        }
+        let (block, used) = mszip_block(&input[2..], &previous)?;
+        out.extend_from_slice(&block);
+        previous = block;
+        input = input.get(2 + used..).unwrap_or_default();
+    }
+    Ok(out)
+}
+
+/// Table sizes, in the order their code lengths are stored
+const LZH_MATCHLEN1: usize = 16;
+const LZH_MATCHLEN2: usize = 16;
+const LZH_LITLEN: usize = 32;
+const LZH_OFFSET: usize = 64;
+const LZH_LITERAL: usize = 256;
+
+/// MSB-first bit reader that reads zeros past the end of its input
+struct Bits<'a> {
+    input: &'a [u8],
+    pos: usize,
+}
+
+impl Bits<'_> {
+    fn read(&mut self, n: u32) -> u32 {
+        let mut value = 0;
+        for _ in 0..n {
+            let byte = self.input.get(self.pos / 8).copied().unwrap_or(0);
+            value = (value << 1) | u32::from((byte >> (7 - self.pos % 8)) & 1);
+            self.pos += 1;
+        }
+        value
+    }
+
+    fn symbol(&mut self, tree: &Huffman) -> io::Result<usize> {
+        let start = self.pos;
+        let peek = self.read(16);
+        let (sym, len) = tree.decode(peek)?;
+        self.pos = start + len as usize;
+        Ok(usize::from(sym))
+    }
+
+    fn past_end(&self) -> bool {
+        self.pos > self.input.len() * 8
+    }
+}
+
+/// KWAJ method 3: LZSS with Huffman-coded literals, run lengths and
+/// offsets
+struct Lzh<'a> {
+    bits: Bits<'a>,
+}
+
+impl<'a> Lzh<'a> {
+    fn new(input: &'a [u8]) -> Self {
+        Self {
+            bits: Bits { input, pos: 0 },
+        }
+    }
+
+    /// Read one table's code lengths, coded as `kind` (0 = fixed)
+    fn read_lens(&mut self, kind: u32, count: usize) -> io::Result<Huffman> {
+        let bits = &mut self.bits;
+        let lens = match kind {
+            0 => vec![count.trailing_zeros() as u8; count],
+            1 => {
+                let mut c = bits.read(4) as u8;
+                let mut lens = vec![c];
+                for _ in 1..count {
+                    if bits.read(1) == 1 {
+                        c = if bits.read(1) == 0 {
+                            c.wrapping_add(1)
+                        } else {
+                            bits.read(4) as u8
+                        };
+                    }
+                    lens.push(c);
+                }
+                lens
+            }
+            2 => {
+                let mut c = bits.read(4) as u8;
+                let mut lens = vec![c];
+                for _ in 1..count {
+                    c = match bits.read(2) {
+                        3 => bits.read(4) as u8,
+                        sel => c.wrapping_add(sel as u8).wrapping_sub(1),
+                    };
+                    lens.push(c);
+                }
+                lens
+            }
+            3 => (0..count).map(|_| bits.read(4) as u8).collect(),
+            _ => return Err(invalid_data("invalid KWAJ table type")),
+        };
+        Huffman::new(&lens)
+    }
+
+    fn decode(mut self, limit: usize) -> io::Result<Vec<u8>> {
+        let kinds: Vec<u32> = (0..5).map(|_| self.bits.read(4)).collect();
+        let matchlen1 = self.read_lens(kinds[0], LZH_MATCHLEN1)?;
+        let matchlen2 = self.read_lens(kinds[1], LZH_MATCHLEN2)?;
+        let litlen = self.read_lens(kinds[2], LZH_LITLEN)?;
+        let offsets = self.read_lens(kinds[3], LZH_OFFSET)?;
+        let literals = self.read_lens(kinds[4], LZH_LITERAL)?;
+
+        let mut ring = [RING_FILL; RING_SIZE];
+        let mut pos = RING_SIZE - 17;
+        let mut out = Vec::new();
+        // After a literal run shorter than the maximum, a match must follow,
+        // so its length comes from the second table
+        let mut lit_run = false;
+        while out.len() < limit && !self.bits.past_end() {
+            let item_start = out.len();
+            let len = self
+                .bits
+                .symbol(if lit_run { &matchlen2 } else { &matchlen1 })?;
+            if len > 0 {
+                lit_run = false;
+                let offset = (self.bits.symbol(&offsets)? << 6) | self.bits.read(6) as usize;
+                for _ in 0..len + 2 {
+                    let byte = ring[(pos + RING_SIZE - offset) % RING_SIZE];
+                    ring[pos] = byte;
+                    pos = (pos + 1) % RING_SIZE;
+                    out.push(byte);
+                }
+            } else {
+                let run = self.bits.symbol(&litlen)? + 1;
+                lit_run = run != LZH_LITLEN;
+                for _ in 0..run {
+                    let byte = self.bits.symbol(&literals)? as u8;
+                    ring[pos] = byte;
+                    pos = (pos + 1) % RING_SIZE;
+                    out.push(byte);
+                }
+            }
+            // Whatever was decoded from the zeros past the end is padding
+            if self.bits.past_end() {
+                out.truncate(item_start);
+            }
+        }
+        out.truncate(limit);
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn szdd(data: &[u8], length: u32) -> Vec<u8> {
+        let mut out = SZDD_MAGIC.to_vec();
+        out.extend_from_slice(&[SZDD_METHOD_A, b'L']);
+        out.extend_from_slice(&length.to_le_bytes());
+        out.extend_from_slice(data);
+        out
+    }
+
+    fn kwaj(method: u16, flags: u16, fields: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut out = KWAJ_MAGIC.to_vec();
+        out.extend_from_slice(&method.to_le_bytes());
+        out.extend_from_slice(&(14 + fields.len() as u16).to_le_bytes());
+        out.extend_from_slice(&flags.to_le_bytes());
+        out.extend_from_slice(fields);
+        out.extend_from_slice(data);
+        out
+    }
+
+    fn decode(data: Vec<u8>) -> (Vec<u8>, Vec<(&'static str, String)>) {
+        let kids = MSCOMPRESS
+            .children(Arc::new(BytesReader::new(data)))
+            .unwrap();
+        let mut buf = vec![0u8; kids[0].reader.size().unwrap() as usize];
+        kids[0].reader.read_at(0, &mut buf).unwrap();
+        (buf, kids[0].metadata.clone())
+    }
+
+    #[test]
+    fn decodes_szdd() {
+        // Literals "abc", a 6-byte match of ring position 4080 (where the
+        // first literal went), then a 3-byte match reaching back into the
+        // ring's initial spaces
+        let data = szdd(&[0b0000_0111, b'a', b'b', b'c', 0xF0, 0xF3, 0x00, 0x00], 12);
+        assert_eq!(decode(data), (b"abcabcabc   ".to_vec(), Vec::new()));
+
+        // QBasic's ring starts two bytes earlier
+        let mut data = QBASIC_MAGIC.to_vec();
+        data.extend_from_slice(&5u32.to_le_bytes());
+        data.extend_from_slice(&[0b0000_0011, b'x', b'y', 0xEE, 0xF0]);
+        assert_eq!(decode(data).0, b"xyxyx");
+    }
+
+    #[test]
+    fn names_szdd_from_path() {
+        assert_eq!(original_name("SETUP.EX_", b'E'), "SETUP.EXE");
+        assert_eq!(original_name("README", b'E'), "README");
+        assert_eq!(original_name("FOO._", 0), "FOO._");
+    }
+
+    #[test]
+    fn decodes_kwaj_fields_and_methods() {
+        let fields = [
+            &7u32.to_le_bytes()[..],
+            b"readme\0",
+            b"txt\0",
+            &[3, 0],
+            b"abc",
+        ]
+        .concat();
+        let flags = KWAJ_HAS_LENGTH | KWAJ_HAS_NAME | KWAJ_HAS_EXTENSION | KWAJ_HAS_TEXT;
+        let name = vec![("name", "readme.txt".to_string())];
+
+        let data = kwaj(KWAJ_STORED, flags, &fields, b"stored!");
+        assert_eq!(decode(data), (b"stored!".to_vec(), name.clone()));
+
+        let inverted: Vec<u8> = b"xor'd!!".iter().map(|b| b ^ 0xFF).collect();
+        let data = kwaj(KWAJ_XOR, flags, &fields, &inverted);
+        assert_eq!(decode(data).0, b"xor'd!!");
+
+        let data = kwaj(
+            KWAJ_SZDD,
+            flags,
+            &fields,
+            &[0x7F, b'a', b'b', b'c', b'd', b'e', b'f', b'g'],
+        );
+        assert_eq!(decode(data), (b"abcdefg".to_vec(), name));
+    }
+
+    #[test]
+    fn decodes_kwaj_lzh() {
+        // All five tables fixed (4, 4, 5, 6 and 8-bit codes, so a symbol's
+        // code is its value); "ab" as a literal run, then a 4-byte match at
+        // offset 2, then a final literal
+        let mut w = Vec::new();
+        let mut put = |value: u32, n: u32| {
+            for i in (0..n).rev() {
+                w.push((value >> i) & 1);
+            }
+        };
+        put(0, 20); // table types
+        put(0, 4); // match length 0: literals follow
+        put(1, 5); // run of 2
+        put(u32::from(b'a'), 8);
+        put(u32::from(b'b'), 8);
+        put(2, 4); // match length 4, from the second table after a run
+        put(0, 6); // offset 2: symbol 0, then 6 low bits
+        put(2, 6);
+        put(0, 4);
+        put(0, 5); // run of 1
+        put(u32::from(b'!'), 8);
+        let bytes: Vec<u8> = w
+            .chunks(8)
+            .map(|c| {
+                c.iter()
+                    .enumerate()
+                    .fold(0, |b, (i, &bit)| b | (bit << (7 - i)) as u8)
+            })
+            .collect();
+
+        let data = kwaj(KWAJ_LZH, KWAJ_HAS_LENGTH, &7u32.to_le_bytes(), &bytes);
+        assert_eq!(decode(data).0, b"ababab!");
+    }
+
+    #[test]
+    fn rejects_truncated_files() {
+        let data = szdd(&[0xFF, b'a'], 10);
+        assert!(MSCOMPRESS
+            .children(Arc::new(BytesReader::new(data)))
+            .is_err());
+        let data = kwaj(KWAJ_STORED, KWAJ_HAS_LENGTH, &10u32.to_le_bytes(), b"short");
+        assert!(MSCOMPRESS
+            .children(Arc::new(BytesReader::new(data)))
+            .is_err());
+    }
+}
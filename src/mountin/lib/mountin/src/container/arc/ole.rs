@@ -0,0 +1,524 @@
+//! OLE compound file (CFB) reader
+//!
+//! A compound file is a small FAT filesystem. Sectors are chained through
+//! the FAT, whose own sectors are listed by the header and the DIFAT
+//! chain. The directory is a chain of 128-byte entries forming one
+//! red-black tree of siblings per storage (directory); streams smaller
+//! than the mini stream cutoff live in 64-byte mini sectors inside the
+//! root entry's stream, chained through the MiniFAT.
+//!
+//! Each stream is exposed as a child named by its path through the
+//! storages. MSI stream names, which pack two characters into each code
+//! point, are decoded.
+
+use crate::container::{invalid_data, le16, le32, read_exact_at, Child, Container, Entry};
+use crate::detect::Reader;
+use std::io;
+use std::sync::Arc;
+
+const MAGIC: &[u8; 8] = b"\xD0\xCF\x11\xE0\xA1\xB1\x1A\xE1";
+const HEADER_SIZE: usize = 512;
+const HEADER_DIFAT: usize = 109;
+const DIR_ENTRY_SIZE: usize = 128;
+
+const MAX_REG_SECT: u32 = 0xFFFF_FFFA;
+const END_OF_CHAIN: u32 = 0xFFFF_FFFE;
+const NO_STREAM: u32 = 0xFFFF_FFFF;
+
+const TYPE_STORAGE: u8 = 1;
+const TYPE_STREAM: u8 = 2;
+const TYPE_ROOT: u8 = 5;
+
+/// MSI packs name characters from this set two to a code point
+const MSI_CHARS: &[u8; 64] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz._";
+const MSI_PAIR: u32 = 0x3800;
+const MSI_SINGLE: u32 = 0x4800;
+const MSI_TABLE: u32 = 0x4840;
+
+/// OLE container - exposes each stream as a child
+pub struct OleContainer;
+
+/// Static instance for registry
+pub static OLE: OleContainer = OleContainer;
+
+impl Container for OleContainer {
+    fn children(&self, reader: Arc<dyn Reader + Send + Sync>) -> io::Result<Vec<Child>> {
+        self.entries(reader)?.into_iter().map(Entry::open).collect()
+    }
+
+    fn entries(&self, reader: Arc<dyn Reader + Send + Sync>) -> io::Result<Vec<Entry>> {
+        let file = Arc::new(CompoundFile::open(&*reader)?);
+        let root = &file.entries[0];
+
+        // Small streams are slices of the root entry's stream
+        let mini_sectors = file.chain(&file.fat, root.start)?;
+        let mini_offsets: Vec<u64> = mini_sectors
+            .iter()
+            .map(|&s| file.sector_offset(s))
+            .collect();
+        let mini_stream: Arc<dyn Reader + Send + Sync> = Arc::new(ChainReader {
+            parent: Arc::clone(&reader),
+            sectors: mini_offsets.clone(),
+            sector_size: file.sector_size,
+            length: root.size,
+        });
+
+        let mut entries = Vec::new();
+        for (path, id) in file.streams()? {
+            let entry = &file.entries[id];
+            let mini = entry.size < file.mini_cutoff;
+
+            // Where the stream starts in the file; empty streams have no
+            // sectors, so use their directory entry
+            let offset = if entry.size == 0 {
+                file.entry_offset(id)
+            } else if mini {
+                let start = u64::from(entry.start) * file.mini_sector_size;
+                let sector = (start / file.sector_size) as usize;
+                mini_offsets
+                    .get(sector)
+                    .map(|&s| s + start % file.sector_size)
+                    .ok_or_else(|| invalid_data("OLE mini stream too short"))?
+            } else {
+                file.sector_offset(entry.start)
+            };
+
+            // Walking the stream's chain waits until it is opened
+            let file = Arc::clone(&file);
+            let parent = if mini {
+                Arc::clone(&mini_stream)
+            } else {
+                Arc::clone(&reader)
+            };
+            entries.push(Entry::new(
+                entries.len() as u32,
+                offset,
+                Some(entry.size),
+                vec![("name", path)],
+                move || file.stream(parent, id),
+            ));
+        }
+        Ok(entries)
+    }
+}
+
+#[derive(Debug)]
+struct DirEntry {
+    name: String,
+    kind: u8,
+    left: u32,
+    right: u32,
+    child: u32,
+    start: u32,
+    size: u64,
+}
+
+struct CompoundFile {
+    sector_shift: u32,
+    sector_size: u64,
+    mini_sector_size: u64,
+    mini_cutoff: u64,
+    fat: Vec<u32>,
+    minifat: Vec<u32>,
+    dir_sectors: Vec<u32>,
+    entries: Vec<DirEntry>,
+}
+
+impl CompoundFile {
+    fn open(reader: &dyn Reader) -> io::Result<Self> {
+        let mut header = [0u8; HEADER_SIZE];
+        read_exact_at(reader, 0, &mut header)?;
+        if &header[..8] != MAGIC {
+            return Err(invalid_data("invalid OLE signature"));
+        }
+        if le16(&header[28..]) != 0xFFFE {
+            return Err(invalid_data("invalid OLE byte order"));
+        }
+        let major = le16(&header[26..]);
+        let sector_shift = u32::from(le16(&header[30..]));
+        let mini_shift = u32::from(le16(&header[32..]));
+        if !(7..=16).contains(&sector_shift) || mini_shift >= sector_shift {
+            return Err(invalid_data("invalid OLE sector size"));
+        }
+
+        let mut file = Self {
+            sector_shift,
+            sector_size: 1 << sector_shift,
+            mini_sector_size: 1 << mini_shift,
+            mini_cutoff: u64::from(le32(&header[56..])),
+            fat: Vec::new(),
+            minifat: Vec::new(),
+            dir_sectors: Vec::new(),
+            entries: Vec::new(),
+        };
+        let entries_per_sector = (file.sector_size / 4) as usize;
+        // Every FAT entry describes a sector of the file, so the FAT
+        // can't need more sectors than the file has
+        let max_sectors = reader
+            .size()
+            .map_or(u64::from(u32::MAX), |size| size >> sector_shift)
+            as usize;
+
+        // FAT sectors are listed in the header, then in the DIFAT chain,
+        // whose sectors end with the next DIFAT sector
+        let fat_count = le32(&header[44..]) as usize;
+        if fat_count > max_sectors.div_ceil(entries_per_sector) {
+            return Err(invalid_data("too many OLE FAT sectors"));
+        }
+        let mut fat_sectors: Vec<u32> = header[76..76 + HEADER_DIFAT * 4]
+            .chunks_exact(4)
+            .map(le32)
+            .take(fat_count)
+            .collect();
+        let mut difat = le32(&header[68..]);
+        let mut sector = vec![0u8; file.sector_size as usize];
+        while fat_sectors.len() < fat_count {
+            if difat > MAX_REG_SECT {
+                return Err(invalid_data("OLE DIFAT chain too short"));
+            }
+            read_exact_at(reader, file.sector_offset(difat), &mut sector)?;
+            let (ids, next) = sector.split_at(sector.len() - 4);
+            fat_sectors.extend(
+                ids.chunks_exact(4)
+                    .map(le32)
+                    .take(fat_count - fat_sectors.len()),
+            );
+            difat = le32(next);
+        }
+        for &id in &fat_sectors {
+            if id > MAX_REG_SECT {
+                return Err(invalid_data("invalid OLE FAT sector"));
+            }
+            read_exact_at(reader, file.sector_offset(id), &mut sector)?;
+            file.fat.extend(sector.chunks_exact(4).map(le32));
+        }
+
+        file.minifat = file.read_stream(reader, le32(&header[60..]), |data| {
+            data.chunks_exact(4).map(le32).collect()
+        })?;
+
+        file.dir_sectors = file.chain(&file.fat, le32(&header[48..]))?;
+        file.entries = file.read_stream(reader, le32(&header[48..]), |data| {
+            data.chunks_exact(DIR_ENTRY_SIZE)
+                .map(|entry| parse_entry(entry, major))
+                .collect()
+        })?;
+        match file.entries.first() {
+            Some(root) if root.kind == TYPE_ROOT => Ok(file),
+            _ => Err(invalid_data("missing OLE root entry")),
+        }
+    }
+
+    fn sector_offset(&self, sector: u32) -> u64 {
+        (u64::from(sector) + 1) << self.sector_shift
+    }
+
+    fn entry_offset(&self, id: usize) -> u64 {
+        let at = (id * DIR_ENTRY_SIZE) as u64;
+        self.sector_offset(self.dir_sectors[(at / self.sector_size) as usize])
+            + at % self.sector_size
+    }
+
+    /// Follow a sector chain through a FAT or the MiniFAT
+    fn chain(&self, table: &[u32], start: u32) -> io::Result<Vec<u32>> {
+        let mut sectors = Vec::new();
+        let mut sector = start;
+        while sector != END_OF_CHAIN {
+            if sectors.len() >= table.len() {
+                return Err(invalid_data("OLE sector chain loops"));
+            }
+            sectors.push(sector);
+            sector = *table
+                .get(sector as usize)
+                .ok_or_else(|| invalid_data("invalid OLE sector chain"))?;
+        }
+        Ok(sectors)
+    }
+
+    /// Read a whole chain of regular sectors and parse it
+    fn read_stream<T>(
+        &self,
+        reader: &dyn Reader,
+        start: u32,
+        parse: impl FnOnce(&[u8]) -> Vec<T>,
+    ) -> io::Result<Vec<T>> {
+        let sectors = self.chain(&self.fat, start)?;
+        let mut data = vec![0u8; sectors.len() << self.sector_shift];
+        for (chunk, &sector) in data
+            .chunks_exact_mut(self.sector_size as usize)
+            .zip(&sectors)
+        {
+            read_exact_at(reader, self.sector_offset(sector), chunk)?;
+        }
+        Ok(parse(&data))
+    }
+
+    /// Reader for stream `id`, whose sectors are in `parent`: the mini
+    /// stream for small streams, otherwise the file
+    fn stream(
+        &self,
+        parent: Arc<dyn Reader + Send + Sync>,
+        id: usize,
+    ) -> io::Result<Arc<dyn Reader + Send + Sync>> {
+        let entry = &self.entries[id];
+        let (sectors, sector_size) = if entry.size < self.mini_cutoff {
+            let sectors = self.chain(&self.minifat, entry.start)?;
+            let offsets = sectors
+                .iter()
+                .map(|&s| u64::from(s) * self.mini_sector_size)
+                .collect();
+            (offsets, self.mini_sector_size)
+        } else {
+            let sectors = self.chain(&self.fat, entry.start)?;
+            let offsets = sectors.iter().map(|&s| self.sector_offset(s)).collect();
+            (offsets, self.sector_size)
+        };
+        let sectors: Vec<u64> = sectors;
+        if (sectors.len() as u64) < entry.size.div_ceil(sector_size) {
+            return Err(invalid_data("OLE stream chain shorter than stream"));
+        }
+        Ok(Arc::new(ChainReader {
+            parent,
+            sectors,
+            sector_size,
+            length: entry.size,
+        }))
+    }
+
+    /// Every stream with its path, in directory order
+    fn streams(&self) -> io::Result<Vec<(String, usize)>> {
+        let mut visited = vec![false; self.entries.len()];
+        let mut streams = Vec::new();
+        // Storages still to walk: the tree of their children and their path
+        let mut storages = vec![(self.entries[0].child, String::new())];
+        while let Some((tree, prefix)) = storages.pop() {
+            // In-order walk of the sibling tree
+            let mut stack = Vec::new();
+            let mut node = tree;
+            loop {
+                while node != NO_STREAM {
+                    let id = node as usize;
+                    if id >= self.entries.len() || visited[id] {
+                        return Err(invalid_data("invalid OLE directory tree"));
+                    }
+                    visited[id] = true;
+                    stack.push(id);
+                    node = self.entries[id].left;
+                }
+                let Some(id) = stack.pop() else { break };
+                let entry = &self.entries[id];
+                let path = format!("{prefix}{}", entry.name);
+                match entry.kind {
+                    TYPE_STREAM => streams.push((path, id)),
+                    TYPE_STORAGE => storages.push((entry.child, path + "/")),
+                    _ => {}
+                }
+                node = entry.right;
+            }
+        }
+        Ok(streams)
+    }
+}
+
+fn parse_entry(entry: &[u8], major: u16) -> DirEntry {
+    let name_len = (usize::from(le16(&entry[64..])) / 2).clamp(1, 32) - 1;
+    let name: Vec<u16> = entry[..name_len * 2].chunks_exact(2).map(le16).collect();
+    // Version 3 files may leave junk in the size's high half
+    let mut size = u64::from(le32(&entry[120..]));
+    if major >= 4 {
+        size |= u64::from(le32(&entry[124..])) << 32;
+    }
+    DirEntry {
+        name: decode_name(&String::from_utf16_lossy(&name)),
+        kind: entry[66],
+        left: le32(&entry[68..]),
+        right: le32(&entry[72..]),
+        child: le32(&entry[76..]),
+        start: le32(&entry[116..]),
+        size,
+    }
+}
+
+/// Expand MSI's packed stream names; other names pass through
+fn decode_name(name: &str) -> String {
+    let mut out = String::new();
+    for c in name.chars() {
+        let code = u32::from(c);
+        match code {
+            MSI_PAIR..MSI_SINGLE => {
+                let code = code - MSI_PAIR;
+                out.push(char::from(MSI_CHARS[(code & 0x3F) as usize]));
+                out.push(char::from(MSI_CHARS[(code >> 6) as usize]));
+            }
+            MSI_SINGLE..MSI_TABLE => {
+                out.push(char::from(MSI_CHARS[(code - MSI_SINGLE) as usize]));
+            }
+            MSI_TABLE => out.push('!'),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// Reader over a stream's sectors, given as offsets into the parent
+struct ChainReader {
+    parent: Arc<dyn Reader + Send + Sync>,
+    sectors: Vec<u64>,
+    sector_size: u64,
+    length: u64,
+}
+
+impl Reader for ChainReader {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let mut done = 0;
+        while done < buf.len() {
+            let pos = offset + done as u64;
+            if pos >= self.length {
+                break;
+            }
+            let in_sector = pos % self.sector_size;
+            let len = (buf.len() - done)
+                .min((self.sector_size - in_sector) as usize)
+                .min((self.length - pos) as usize);
+            let start = self.sectors[(pos / self.sector_size) as usize] + in_sector;
+            read_exact_at(&*self.parent, start, &mut buf[done..done + len])?;
+            done += len;
+        }
+        Ok(done)
+    }
+
+    fn size(&self) -> Option<u64> {
+        Some(self.length)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::BytesReader;
+
+    const FREE: u32 = 0xFFFF_FFFF;
+
+    fn entry(name: &str, kind: u8, tree: [u32; 3], start: u32, size: u32) -> Vec<u8> {
+        let mut out = vec![0u8; DIR_ENTRY_SIZE];
+        let name: Vec<u16> = name.encode_utf16().chain([0]).collect();
+        for (i, unit) in name.iter().enumerate() {
+            out[i * 2..i * 2 + 2].copy_from_slice(&unit.to_le_bytes());
+        }
+        out[64..66].copy_from_slice(&(name.len() as u16 * 2).to_le_bytes());
+        out[66] = kind;
+        out[67] = 1;
+        for (i, id) in tree.iter().enumerate() {
+            out[68 + i * 4..72 + i * 4].copy_from_slice(&id.to_le_bytes());
+        }
+        out[116..120].copy_from_slice(&start.to_le_bytes());
+        out[120..124].copy_from_slice(&size.to_le_bytes());
+        out
+    }
+
+    fn sector(words: &[u32]) -> Vec<u8> {
+        let mut out: Vec<u8> = words.iter().flat_map(|w| w.to_le_bytes()).collect();
+        out.resize(512, 0xFF);
+        out
+    }
+
+    /// Sectors: 0 FAT, 1 directory, 2 MiniFAT, 3 mini stream, 4-11 "Big"
+    fn compound_file(big: &[u8], small: &[u8]) -> Vec<u8> {
+        let mut header = MAGIC.to_vec();
+        header.resize(HEADER_SIZE, 0);
+        header[24..34].copy_from_slice(&[0x3E, 0, 3, 0, 0xFE, 0xFF, 9, 0, 6, 0]);
+        for (at, value) in [
+            (44, 1),
+            (48, 1),
+            (56, 4096),
+            (60, 2),
+            (64, 1),
+            (68, END_OF_CHAIN),
+        ] {
+            header[at..at + 4].copy_from_slice(&u32::to_le_bytes(value));
+        }
+        header[76..].copy_from_slice(&sector(&[0])[..HEADER_SIZE - 76]);
+
+        let mut fat = vec![0xFFFF_FFFD, END_OF_CHAIN, END_OF_CHAIN, END_OF_CHAIN];
+        fat.extend(5..12);
+        fat.push(END_OF_CHAIN);
+
+        let mut directory = entry("Root Entry", TYPE_ROOT, [FREE, FREE, 1], 3, 128);
+        directory.extend(entry(
+            "Big",
+            TYPE_STREAM,
+            [FREE, 2, FREE],
+            4,
+            big.len() as u32,
+        ));
+        directory.extend(entry("Dir", TYPE_STORAGE, [FREE, FREE, 3], 0, 0));
+        let name = "\u{4840}\u{3f7f}\u{4836}";
+        directory.extend(entry(
+            name,
+            TYPE_STREAM,
+            [FREE, FREE, FREE],
+            0,
+            small.len() as u32,
+        ));
+
+        let mut mini = small.to_vec();
+        mini.resize(512, 0);
+        let mut big = big.to_vec();
+        big.resize(4096, 0);
+        [
+            header,
+            sector(&fat),
+            directory,
+            sector(&[1, END_OF_CHAIN]),
+            mini,
+            big,
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn reads_streams_and_mini_streams() {
+        let big: Vec<u8> = (0..4096u32).map(|i| (i % 251) as u8).collect();
+        let small = [0x5Au8; 100];
+        let file = compound_file(&big, &small);
+        let children = OLE.children(Arc::new(BytesReader::new(file))).unwrap();
+        let names: Vec<_> = children.iter().map(|c| c.metadata[0].1.as_str()).collect();
+        assert_eq!(names, ["Big", "Dir/!_Ts"]);
+        assert_eq!(children[0].offset, 5 * 512);
+        assert_eq!(children[1].offset, 4 * 512);
+
+        let mut buf = vec![0u8; 4096];
+        children[0].reader.read_at(0, &mut buf).unwrap();
+        assert_eq!(buf, big);
+        let mut buf = vec![0u8; 100];
+        assert_eq!(children[1].reader.read_at(0, &mut buf).unwrap(), 100);
+        assert_eq!(buf, small);
+    }
+
+    #[test]
+    fn rejects_directory_loops() {
+        let mut file = compound_file(&[1; 4096], &[2; 10]);
+        // Point "Dir"'s child back at the root's first child
+        let at = 2 * 512 + 2 * DIR_ENTRY_SIZE + 76;
+        file[at..at + 4].copy_from_slice(&1u32.to_le_bytes());
+        assert!(OLE.children(Arc::new(BytesReader::new(file))).is_err());
+    }
+
+    #[test]
+    fn lists_entries_without_walking_chains() {
+        let mut file = compound_file(&[1; 4096], &[2; 10]);
+        // Break "Big"'s chain at its second sector
+        let at = HEADER_SIZE + 5 * 4;
+        file[at..at + 4].copy_from_slice(&FREE.to_le_bytes());
+        let entries = OLE.entries(Arc::new(BytesReader::new(file))).unwrap();
+        let listed: Vec<_> = entries.iter().map(|e| (e.name(), e.size)).collect();
+        assert_eq!(
+            listed,
+            [(Some("Big"), Some(4096)), (Some("Dir/!_Ts"), Some(10))]
+        );
+
+        let mut entries = entries.into_iter();
+        assert!(entries.next().unwrap().open().is_err());
+        assert!(entries.next().unwrap().open().is_ok());
+    }
+}
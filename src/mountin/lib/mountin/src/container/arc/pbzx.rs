@@ -0,0 +1,110 @@
+//! pbzx container reader
+//!
+//! pbzx is the chunked xz wrapper around the Payload of macOS installer
+//! packages (usually a cpio archive). After the "pbzx" magic and the
+//! chunk size, each chunk has big-endian 64-bit decoded and stored sizes,
+//! then its data: a complete xz stream, or the raw bytes when both sizes
+//! are equal. Chunks are decoded one at a time as reads reach them.
+
+use crate::container::block::{Block, BlockReader};
+use crate::container::{be64, invalid_data, Child, Container, LimitedBuffer};
+use crate::detect::Reader;
+use std::io;
+use std::sync::Arc;
+
+const MAGIC: &[u8; 4] = b"pbzx";
+const HEADER_SIZE: u64 = 12;
+const CHUNK_HEADER_SIZE: u64 = 16;
+
+/// pbzx container - decompresses content to expose inner stream
+pub struct PbzxContainer;
+
+/// Static instance for registry
+pub static PBZX: PbzxContainer = PbzxContainer;
+
+impl Container for PbzxContainer {
+    fn children(&self, reader: Arc<dyn Reader + Send + Sync>) -> io::Result<Vec<Child>> {
+        let blocks = chunks(&*reader)?;
+        let stored: Vec<bool> = blocks.iter().map(|b| b.size == b.compressed_size).collect();
+        let child = BlockReader::new(reader, blocks, move |index, data| {
+            if stored[index] {
+                return Ok(data.to_vec());
+            }
+            let mut out = LimitedBuffer::new();
+            lzma_rs::xz_decompress(&mut &data[..], &mut out)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+            Ok(out.into_inner())
+        })?;
+
+        Ok(vec![Child {
+            index: 0,
+            offset: u64::MAX, // Transformed data, not a slice
+            reader: Arc::new(child),
+            metadata: Vec::new(),
+        }])
+    }
+}
+
+/// Walk the chunk headers to the end of the file
+fn chunks(reader: &dyn Reader) -> io::Result<Vec<Block>> {
+    let mut header = [0u8; HEADER_SIZE as usize];
+    if reader.read_at(0, &mut header)? != header.len() || &header[..4] != MAGIC {
+        return Err(invalid_data("invalid pbzx magic"));
+    }
+
+    let mut blocks = Vec::new();
+    let mut offset = HEADER_SIZE;
+    let mut start = 0u64;
+    loop {
+        let mut chunk = [0u8; CHUNK_HEADER_SIZE as usize];
+        match reader.read_at(offset, &mut chunk)? {
+            0 => return Ok(blocks),
+            n if n == chunk.len() => {}
+            _ => return Err(invalid_data("truncated pbzx chunk header")),
+        }
+        let size = be64(&chunk);
+        let compressed_size = be64(&chunk[8..]);
+        offset += CHUNK_HEADER_SIZE;
+        blocks.push(Block {
+            offset,
+            compressed_size,
+            start,
+            size,
+        });
+        offset = offset
+            .checked_add(compressed_size)
+            .ok_or_else(|| invalid_data("invalid pbzx chunk size"))?;
+        start += size;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::BytesReader;
+
+    fn chunk(out: &mut Vec<u8>, size: usize, data: &[u8]) {
+        out.extend_from_slice(&(size as u64).to_be_bytes());
+        out.extend_from_slice(&(data.len() as u64).to_be_bytes());
+        out.extend_from_slice(data);
+    }
+
+    #[test]
+    fn reads_stored_and_xz_chunks() {
+        let mut xz = Vec::new();
+        lzma_rs::xz_compress(&mut &[b'x'; 100][..], &mut xz).unwrap();
+        let mut pbzx = MAGIC.to_vec();
+        pbzx.extend_from_slice(&0x0100_0000u64.to_be_bytes());
+        chunk(&mut pbzx, 5, b"plain");
+        chunk(&mut pbzx, 100, &xz);
+
+        let children = PBZX.children(Arc::new(BytesReader::new(pbzx))).unwrap();
+        let reader = &children[0].reader;
+        assert_eq!(reader.size(), Some(105));
+        let mut buf = [0u8; 8];
+        assert_eq!(reader.read_at(2, &mut buf).unwrap(), 3);
+        assert_eq!(&buf[..3], b"ain");
+        assert_eq!(reader.read_at(97, &mut buf).unwrap(), 8);
+        assert_eq!(&buf, b"xxxxxxxx");
+    }
+}
@@ -0,0 +1,304 @@
+//! Quantum decompressor (CAB variant)
+//!
+//! Quantum is an LZ77 coder driven by an adaptive arithmetic coder. A
+//! selector model picks one of four literal models (by the top bits of the
+//! byte) or one of three match kinds; match positions and lengths are
+//! coded as a model symbol plus raw extra bits. Each model keeps its
+//! symbols sorted by descending frequency and rescales itself as counts
+//! grow.
+//!
+//! As with LZX, a cabinet's CFDATA blocks each hold one 32 KiB frame and
+//! the arithmetic coder restarts at every block, while the window and
+//! models carry over.
+
+use crate::container::invalid_data;
+use std::io;
+
+pub(crate) const FRAME_SIZE: usize = 32 * 1024;
+pub(crate) const MIN_WINDOW_BITS: u32 = 10;
+pub(crate) const MAX_WINDOW_BITS: u32 = 21;
+
+/// Models rescale once their total frequency passes this
+const MAX_TOTAL: u16 = 3800;
+/// Rescales that halve frequencies before one re-sorts the model
+const SHIFTS: u8 = 50;
+
+const POSITION_BASE: [u32; 42] = [
+    0, 1, 2, 3, 4, 6, 8, 12, 16, 24, 32, 48, 64, 96, 128, 192, 256, 384, 512, 768, 1024, 1536,
+    2048, 3072, 4096, 6144, 8192, 12288, 16384, 24576, 32768, 49152, 65536, 98304, 131072, 196608,
+    262144, 393216, 524288, 786432, 1048576, 1572864,
+];
+const EXTRA_BITS: [u8; 42] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13, 14, 14, 15, 15, 16, 16, 17, 17, 18, 18, 19, 19,
+];
+const LENGTH_BASE: [u8; 27] = [
+    0, 1, 2, 3, 4, 5, 6, 8, 10, 12, 14, 18, 22, 26, 30, 38, 46, 54, 62, 78, 94, 110, 126, 158, 190,
+    222, 254,
+];
+const LENGTH_EXTRA: [u8; 27] = [
+    0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+
+/// Adaptive frequency model
+struct Model {
+    shifts_left: u8,
+    /// (symbol, cumulative frequency) in decreasing frequency order, plus
+    /// a terminating entry with frequency 0
+    syms: Vec<(u16, u16)>,
+}
+
+impl Model {
+    fn new(start: u16, len: u16) -> Self {
+        Self {
+            shifts_left: 4,
+            syms: (0..=len).map(|i| (start + i, len - i)).collect(),
+        }
+    }
+
+    fn entries(&self) -> usize {
+        self.syms.len() - 1
+    }
+
+    fn update(&mut self) {
+        let n = self.entries();
+        self.shifts_left -= 1;
+        if self.shifts_left > 0 {
+            for i in (0..n).rev() {
+                self.syms[i].1 >>= 1;
+                if self.syms[i].1 <= self.syms[i + 1].1 {
+                    self.syms[i].1 = self.syms[i + 1].1 + 1;
+                }
+            }
+            return;
+        }
+
+        self.shifts_left = SHIFTS;
+        for i in 0..n {
+            self.syms[i].1 = (self.syms[i].1 - self.syms[i + 1].1 + 1) >> 1;
+        }
+        // The encoder uses an in-place selection sort; ties must land the
+        // same way
+        for i in 0..n.saturating_sub(1) {
+            for j in i + 1..n {
+                if self.syms[i].1 < self.syms[j].1 {
+                    self.syms.swap(i, j);
+                }
+            }
+        }
+        for i in (0..n).rev() {
+            self.syms[i].1 += self.syms[i + 1].1;
+        }
+    }
+}
+
+/// MSB-first bit reader over one CFDATA block; reads zeros past the end
+struct Bits<'a> {
+    input: &'a [u8],
+    pos: usize,
+}
+
+impl Bits<'_> {
+    fn bit(&mut self) -> u32 {
+        let byte = self.input.get(self.pos / 8).copied().unwrap_or(0);
+        let bit = (byte >> (7 - self.pos % 8)) & 1;
+        self.pos += 1;
+        u32::from(bit)
+    }
+
+    fn read(&mut self, n: u8) -> u32 {
+        (0..n).fold(0, |value, _| (value << 1) | self.bit())
+    }
+}
+
+/// Arithmetic decoder state for one block
+struct Coder<'a> {
+    bits: Bits<'a>,
+    high: u16,
+    low: u16,
+    code: u16,
+}
+
+impl<'a> Coder<'a> {
+    fn new(input: &'a [u8]) -> Self {
+        let mut bits = Bits { input, pos: 0 };
+        let code = bits.read(16) as u16;
+        Self {
+            bits,
+            high: 0xFFFF,
+            low: 0,
+            code,
+        }
+    }
+
+    fn symbol(&mut self, model: &mut Model) -> u16 {
+        let total = u32::from(model.syms[0].1);
+        let range = u32::from(self.high.wrapping_sub(self.low)) + 1;
+        let target = ((u32::from(self.code.wrapping_sub(self.low)) + 1) * total - 1) / range;
+
+        let n = model.entries();
+        let i = (1..n)
+            .find(|&i| u32::from(model.syms[i].1) <= target)
+            .unwrap_or(n);
+        let sym = model.syms[i - 1].0;
+
+        let high = u32::from(model.syms[i - 1].1) * range / total;
+        let low = u32::from(model.syms[i].1) * range / total;
+        self.high = self.low.wrapping_add(high as u16).wrapping_sub(1);
+        self.low = self.low.wrapping_add(low as u16);
+
+        for entry in &mut model.syms[..i] {
+            entry.1 += 8;
+        }
+        if model.syms[0].1 > MAX_TOTAL {
+            model.update();
+        }
+
+        loop {
+            if (self.low ^ self.high) & 0x8000 != 0 {
+                if self.low & 0x4000 != 0 && self.high & 0x4000 == 0 {
+                    // Underflow: the interval straddles the midpoint
+                    self.code ^= 0x4000;
+                    self.low &= 0x3FFF;
+                    self.high |= 0x4000;
+                } else {
+                    break;
+                }
+            }
+            self.low <<= 1;
+            self.high = (self.high << 1) | 1;
+            self.code = (self.code << 1) | self.bits.bit() as u16;
+        }
+        sym
+    }
+}
+
+/// Decoder state that persists across frames
+pub(crate) struct QuantumDecoder {
+    window: Vec<u8>,
+    window_pos: usize,
+    total: u64,
+    literals: [Model; 4],
+    /// Positions for 3-byte matches, 4-byte matches and longer matches
+    position3: Model,
+    position4: Model,
+    position: Model,
+    length: Model,
+    selector: Model,
+}
+
+impl QuantumDecoder {
+    pub(crate) fn new(window_bits: u32) -> io::Result<Self> {
+        if !(MIN_WINDOW_BITS..=MAX_WINDOW_BITS).contains(&window_bits) {
+            return Err(invalid_data("invalid Quantum window size"));
+        }
+        let positions = window_bits as u16 * 2;
+        Ok(Self {
+            window: vec![0; 1 << window_bits],
+            window_pos: 0,
+            total: 0,
+            literals: [0, 64, 128, 192].map(|start| Model::new(start, 64)),
+            position3: Model::new(0, positions.min(24)),
+            position4: Model::new(0, positions.min(36)),
+            position: Model::new(0, positions),
+            length: Model::new(0, 27),
+            selector: Model::new(0, 7),
+        })
+    }
+
+    fn put(&mut self, byte: u8, out: &mut Vec<u8>) {
+        self.window[self.window_pos] = byte;
+        self.window_pos = (self.window_pos + 1) & (self.window.len() - 1);
+        self.total += 1;
+        out.push(byte);
+    }
+
+    /// Decode one frame of `size` bytes from one CFDATA block's data
+    pub(crate) fn decode_frame(&mut self, input: &[u8], size: usize) -> io::Result<Vec<u8>> {
+        if size > FRAME_SIZE {
+            return Err(invalid_data("Quantum frame too large"));
+        }
+        let mut coder = Coder::new(input);
+        let mut out = Vec::with_capacity(size);
+
+        while out.len() < size {
+            let selector = coder.symbol(&mut self.selector);
+            let (position, len) = match selector {
+                0..=3 => {
+                    let byte = coder.symbol(&mut self.literals[usize::from(selector)]);
+                    self.put(byte as u8, &mut out);
+                    continue;
+                }
+                4 => (coder.symbol(&mut self.position3), 3),
+                5 => (coder.symbol(&mut self.position4), 4),
+                _ => {
+                    let sym = usize::from(coder.symbol(&mut self.length));
+                    let extra = coder.bits.read(LENGTH_EXTRA[sym]);
+                    let len = usize::from(LENGTH_BASE[sym]) + extra as usize + 5;
+                    (coder.symbol(&mut self.position), len)
+                }
+            };
+            let position = usize::from(position);
+            let extra = coder.bits.read(EXTRA_BITS[position]);
+            let offset = (POSITION_BASE[position] + extra + 1) as usize;
+
+            if offset as u64 > self.total || offset > self.window.len() {
+                return Err(invalid_data("Quantum match before start of window"));
+            }
+            if out.len() + len > size {
+                return Err(invalid_data("Quantum match overruns frame"));
+            }
+            let mask = self.window.len() - 1;
+            let mut from = (self.window_pos + self.window.len() - offset) & mask;
+            for _ in 0..len {
+                let byte = self.window[from];
+                self.put(byte, &mut out);
+                from = (from + 1) & mask;
+            }
+        }
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_literals_and_matches() {
+        // "quantum " as literals, a 16-byte match at distance 8, ", abc",
+        // then a 9-byte match at distance 3; 1 KiB window
+        let block = [
+            0xBF, 0x18, 0x79, 0xC2, 0x37, 0x9D, 0x9F, 0x16, 0x3F, 0x4A, 0x61, 0x79, 0x56, 0x42,
+            0xE6, 0x78,
+        ];
+        let mut quantum = QuantumDecoder::new(10).unwrap();
+        assert_eq!(
+            quantum.decode_frame(&block, 37).unwrap(),
+            b"quantum quantum quantum, abcabcabcabc"
+        );
+    }
+
+    #[test]
+    fn rescales_models() {
+        let mut model = Model::new(0, 3);
+        model.syms[0].1 = 3900;
+        model.syms[1].1 = 3000;
+        model.syms[2].1 = 100;
+        model.update();
+        assert_eq!(model.syms, [(0, 1950), (1, 1500), (2, 50), (3, 0)]);
+
+        // Every 50th rescale rebuilds the order from halved frequencies
+        model.shifts_left = 1;
+        model.update();
+        assert_eq!(model.syms, [(1, 975), (0, 250), (2, 25), (3, 0)]);
+    }
+
+    #[test]
+    fn rejects_bad_streams() {
+        assert!(QuantumDecoder::new(9).is_err());
+        // An all-zero stream starts with a match, which has nothing to copy
+        let mut quantum = QuantumDecoder::new(10).unwrap();
+        assert!(quantum.decode_frame(&[0; 8], 16).is_err());
+    }
+}
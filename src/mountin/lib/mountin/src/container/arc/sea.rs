@@ -0,0 +1,562 @@
+//! ARC (SEA) archive reader
+//!
+//! ARC members follow each other, each behind a 29-byte header with its
+//! name, sizes and a CRC-16 of its contents. Stored members are slices of
+//! the archive; the others are decoded when first read:
+//!
+//! - packed: run-length coding, where 0x90 and a count repeats the previous
+//!   byte
+//! - squeezed: packed, then a static Huffman code stored as a node table
+//! - crunched (methods 5-7): ARC 4's LZW with fixed 12-bit codes, whose
+//!   table entries are placed by hashing; 6 and 7 pack first, and 7 uses
+//!   a faster hash
+//! - crunched (method 8): packed, then compress-style LZW of up to 12 bits
+//! - squashed: LZW of up to 13 bits, without run-length coding
+
+use super::compress::lzw;
+use crate::container::{
+    invalid_data, le32, slice::SliceReader, Child, Container, DeferredReader, MAX_SIZE,
+};
+use crate::detect::Reader;
+use std::io;
+use std::sync::Arc;
+
+const MARKER: u8 = 0x1A;
+const HEADER_SIZE: usize = 29;
+/// Method 1 headers lack the original size
+const OLD_HEADER_SIZE: usize = 25;
+const NAME_SIZE: usize = 13;
+
+const METHOD_END: u8 = 0;
+const METHOD_OLD_STORED: u8 = 1;
+const METHOD_STORED: u8 = 2;
+const METHOD_PACKED: u8 = 3;
+const METHOD_SQUEEZED: u8 = 4;
+const METHOD_OLD_CRUNCHED: u8 = 5;
+const METHOD_OLD_PACKED_CRUNCHED: u8 = 6;
+const METHOD_FAST_CRUNCHED: u8 = 7;
+const METHOD_CRUNCHED: u8 = 8;
+const METHOD_SQUASHED: u8 = 9;
+/// ARC 6 and later use methods from 20 up for comments and other
+/// archive information rather than files
+const METHOD_INFO: u8 = 20;
+
+/// Run-length escape
+const DLE: u8 = 0x90;
+/// Squeezed end-of-data symbol
+const SQUEEZE_EOF: i32 = 256;
+const SQUASH_BITS: u32 = 13;
+/// Entries in the table of methods 5-7, one per 12-bit code
+const CRUNCH_TABLE_SIZE: usize = 4096;
+/// Predecessor of the single-byte strings
+const NO_PRED: u16 = 0xFFFF;
+
+/// ARC container - exposes each member as a child
+pub struct ArcContainer;
+
+/// Static instance for registry
+pub static ARC: ArcContainer = ArcContainer;
+
+impl Container for ArcContainer {
+    fn children(&self, reader: Arc<dyn Reader + Send + Sync>) -> io::Result<Vec<Child>> {
+        let members = parse(&*reader)?;
+        let mut children = Vec::new();
+        for (index, member) in members.into_iter().enumerate() {
+            let child: Arc<dyn Reader + Send + Sync> = match member.method {
+                METHOD_OLD_STORED | METHOD_STORED => Arc::new(SliceReader::new(
+                    Arc::clone(&reader),
+                    member.offset,
+                    u64::from(member.packed),
+                )),
+                _ => {
+                    let parent = Arc::clone(&reader);
+                    let size = u64::from(member.size);
+                    let member = member.clone();
+                    Arc::new(DeferredReader::new(size, move || decode(&*parent, &member)))
+                }
+            };
+            children.push(Child {
+                index: index as u32,
+                offset: member.offset,
+                reader: child,
+                metadata: vec![("name", member.name)],
+            });
+        }
+        Ok(children)
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Member {
+    name: String,
+    method: u8,
+    /// Offset of the member's data
+    offset: u64,
+    packed: u32,
+    size: u32,
+    crc: u16,
+}
+
+fn parse(reader: &dyn Reader) -> io::Result<Vec<Member>> {
+    let len = reader.size().unwrap_or(u64::MAX);
+    let mut members = Vec::new();
+    let mut offset = 0u64;
+    loop {
+        let mut header = [0u8; HEADER_SIZE];
+        let n = reader.read_at(offset, &mut header)?;
+        // Some archivers leave the end marker off
+        if n == 0 && offset > 0 {
+            break;
+        }
+        if n < 2 || header[0] != MARKER {
+            return Err(invalid_data("missing ARC header marker"));
+        }
+        let method = header[1];
+        if method == METHOD_END {
+            break;
+        }
+
+        let header_size = if method == METHOD_OLD_STORED {
+            OLD_HEADER_SIZE
+        } else {
+            HEADER_SIZE
+        };
+        if n < header_size {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "short ARC header read",
+            ));
+        }
+        let name = &header[2..2 + NAME_SIZE];
+        let name = &name[..name.iter().position(|&b| b == 0).unwrap_or(NAME_SIZE)];
+        let packed = le32(&header[15..]);
+        let size = if method == METHOD_OLD_STORED {
+            packed
+        } else {
+            le32(&header[25..])
+        };
+
+        let data = offset + header_size as u64;
+        if data + u64::from(packed) > len {
+            return Err(invalid_data("ARC member extends past end of archive"));
+        }
+        if method < METHOD_INFO {
+            members.push(Member {
+                name: String::from_utf8_lossy(name).into_owned(),
+                method,
+                offset: data,
+                packed,
+                size,
+                crc: u16::from_le_bytes([header[23], header[24]]),
+            });
+        }
+        offset = data + u64::from(packed);
+    }
+    Ok(members)
+}
+
+fn decode(reader: &dyn Reader, member: &Member) -> io::Result<Vec<u8>> {
+    let size = member.size as usize;
+    if member.packed as usize > MAX_SIZE || size > MAX_SIZE {
+        return Err(invalid_data("ARC member too large"));
+    }
+    let mut data = vec![0u8; member.packed as usize];
+    if reader.read_at(member.offset, &mut data)? != data.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "short ARC data read",
+        ));
+    }
+
+    let out = match member.method {
+        METHOD_PACKED => unpack(&data, size)?,
+        METHOD_SQUEEZED => unpack(&unsqueeze(&data)?, size)?,
+        METHOD_OLD_CRUNCHED => uncrunch(&data, false)?,
+        METHOD_OLD_PACKED_CRUNCHED => unpack(&uncrunch(&data, false)?, size)?,
+        METHOD_FAST_CRUNCHED => unpack(&uncrunch(&data, true)?, size)?,
+        METHOD_CRUNCHED => {
+            // The first byte holds the maximum code width
+            let (&bits, codes) = data
+                .split_first()
+                .ok_or_else(|| invalid_data("empty crunched ARC member"))?;
+            if !(9..=16).contains(&bits) {
+                return Err(invalid_data("invalid ARC code width"));
+            }
+            unpack(&lzw(codes, u32::from(bits), true)?, size)?
+        }
+        METHOD_SQUASHED => lzw(&data, SQUASH_BITS, true)?,
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "unsupported ARC compression method",
+            ))
+        }
+    };
+    if out.len() != size {
+        return Err(invalid_data("ARC member size mismatch"));
+    }
+    if crc16(&out) != member.crc {
+        return Err(invalid_data("ARC member CRC mismatch"));
+    }
+    Ok(out)
+}
+
+/// CRC-16/ARC (reflected polynomial 0xA001), also used by LHA and zoo
+pub(crate) fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0, |crc, &byte| {
+        (0..8).fold(crc ^ u16::from(byte), |crc, _| {
+            if crc & 1 != 0 {
+                (crc >> 1) ^ 0xA001
+            } else {
+                crc >> 1
+            }
+        })
+    })
+}
+
+/// Undo run-length coding: 0x90 n repeats the previous byte to n copies
+/// in all, and 0x90 0 is a literal 0x90. Runs stop at `size` bytes.
+fn unpack(data: &[u8], size: usize) -> io::Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(size);
+    let mut last = 0u8;
+    let mut bytes = data.iter().copied();
+    while let Some(byte) = bytes.next() {
+        if byte != DLE {
+            out.push(byte);
+            last = byte;
+            continue;
+        }
+        match bytes.next() {
+            Some(0) => out.push(DLE),
+            Some(count) => {
+                let count = usize::from(count - 1).min(size.saturating_sub(out.len()));
+                out.resize(out.len() + count, last);
+            }
+            None => return Err(invalid_data("truncated ARC run")),
+        }
+    }
+    Ok(out)
+}
+
+/// Decode squeezed data: a node count, that many pairs of children
+/// (negative for leaves holding !symbol), then LSB-first codes
+fn unsqueeze(data: &[u8]) -> io::Result<Vec<u8>> {
+    if data.len() < 2 {
+        return Err(invalid_data("truncated ARC squeeze table"));
+    }
+    let count = usize::from(u16::from_le_bytes([data[0], data[1]]));
+    let table_end = 2 + count * 4;
+    if count > 256 || data.len() < table_end {
+        return Err(invalid_data("invalid ARC squeeze table"));
+    }
+    if count == 0 {
+        return Ok(Vec::new());
+    }
+    let nodes: Vec<[i32; 2]> = data[2..table_end]
+        .chunks_exact(4)
+        .map(|n| {
+            [
+                i32::from(i16::from_le_bytes([n[0], n[1]])),
+                i32::from(i16::from_le_bytes([n[2], n[3]])),
+            ]
+        })
+        .collect();
+
+    let mut out = Vec::new();
+    let mut node = 0usize;
+    for &byte in &data[table_end..] {
+        for bit in 0..8 {
+            let next = nodes[node][usize::from((byte >> bit) & 1)];
+            if next >= 0 {
+                node = next as usize;
+                if node >= count {
+                    return Err(invalid_data("invalid ARC squeeze node"));
+                }
+                continue;
+            }
+            let sym = -(next + 1);
+            if sym == SQUEEZE_EOF {
+                return Ok(out);
+            }
+            if sym > SQUEEZE_EOF {
+                return Err(invalid_data("invalid ARC squeeze symbol"));
+            }
+            out.push(sym as u8);
+            node = 0;
+        }
+    }
+    Err(invalid_data("truncated ARC squeezed data"))
+}
+
+#[derive(Clone, Copy, Default)]
+struct CrunchEntry {
+    used: bool,
+    /// Next entry placed after a collision with this one, or 0
+    next: u16,
+    pred: u16,
+    foll: u8,
+}
+
+/// String table of methods 5-7. An entry's code is where a hash of its
+/// predecessor and following byte put it; on a collision it goes to the
+/// first free entry from 101 past the end of the colliding chain.
+struct CrunchTable {
+    entries: Vec<CrunchEntry>,
+    fast_hash: bool,
+}
+
+impl CrunchTable {
+    fn new(fast_hash: bool) -> Self {
+        let mut table = Self {
+            entries: vec![CrunchEntry::default(); CRUNCH_TABLE_SIZE],
+            fast_hash,
+        };
+        for byte in 0..=255 {
+            table.insert(NO_PRED, byte);
+        }
+        table
+    }
+
+    fn hash(&self, pred: u16, foll: u8) -> usize {
+        let key = u32::from(pred.wrapping_add(u16::from(foll)));
+        if self.fast_hash {
+            (key * 15073) as usize & 0xFFF
+        } else {
+            // The middle 12 bits of the square
+            let key = key | 0x0800;
+            ((key * key) >> 6) as usize & 0xFFF
+        }
+    }
+
+    fn insert(&mut self, pred: u16, foll: u8) {
+        let mut at = self.hash(pred, foll);
+        if self.entries[at].used {
+            while self.entries[at].next != 0 {
+                at = usize::from(self.entries[at].next);
+            }
+            let mut free = (at + 101) % CRUNCH_TABLE_SIZE;
+            while self.entries[free].used {
+                free = (free + 1) % CRUNCH_TABLE_SIZE;
+            }
+            self.entries[at].next = free as u16;
+            at = free;
+        }
+        self.entries[at] = CrunchEntry {
+            used: true,
+            next: 0,
+            pred,
+            foll,
+        };
+    }
+}
+
+/// Decode methods 5-7's LZW: 12-bit codes, most significant bit first,
+/// with no codes reserved and no reset once the table is full
+fn uncrunch(data: &[u8], fast_hash: bool) -> io::Result<Vec<u8>> {
+    let mut codes = (0..data.len() * 2 / 3).map(|k| {
+        let pair = u16::from_be_bytes([data[k * 3 / 2], data[k * 3 / 2 + 1]]);
+        usize::from(if k % 2 == 0 { pair >> 4 } else { pair & 0xFFF })
+    });
+    let Some(mut old) = codes.next() else {
+        return Ok(Vec::new());
+    };
+    let mut table = CrunchTable::new(fast_hash);
+    if !table.entries[old].used {
+        return Err(invalid_data("invalid ARC crunch code"));
+    }
+    let mut first = table.entries[old].foll;
+    let mut out = vec![first];
+    let mut room = CRUNCH_TABLE_SIZE - 256;
+    let mut stack = Vec::new();
+    for code in codes {
+        let mut at = code;
+        // A code not yet in the table is the previous string plus its
+        // own first byte
+        if !table.entries[at].used {
+            stack.push(first);
+            at = old;
+        }
+        while table.entries[at].pred != NO_PRED {
+            if stack.len() >= CRUNCH_TABLE_SIZE {
+                return Err(invalid_data("invalid ARC crunch string"));
+            }
+            stack.push(table.entries[at].foll);
+            at = usize::from(table.entries[at].pred);
+        }
+        first = table.entries[at].foll;
+        stack.push(first);
+        out.extend(stack.drain(..).rev());
+        if room > 0 {
+            table.insert(old as u16, first);
+            room -= 1;
+        }
+        old = code;
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::BytesReader;
+
+    fn header(method: u8, name: &str, data: &[u8], contents: &[u8]) -> Vec<u8> {
+        let mut out = vec![MARKER, method];
+        let mut name_field = [0u8; NAME_SIZE];
+        name_field[..name.len()].copy_from_slice(name.as_bytes());
+        out.extend_from_slice(&name_field);
+        out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        out.extend_from_slice(&[0; 4]);
+        out.extend_from_slice(&crc16(contents).to_le_bytes());
+        out.extend_from_slice(&(contents.len() as u32).to_le_bytes());
+        out.extend_from_slice(data);
+        out
+    }
+
+    fn read(child: &Child) -> Vec<u8> {
+        let mut buf = vec![0u8; child.reader.size().unwrap() as usize];
+        child.reader.read_at(0, &mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn crc16_check_value() {
+        assert_eq!(crc16(b"123456789"), 0xBB3D);
+    }
+
+    #[test]
+    fn reads_stored_packed_and_squeezed_members() {
+        let packed = [b'a', DLE, 5, b'b', DLE, 0];
+        // Node 0: 'x' on 0, node 1 on 1; node 1: 'y' on 0, EOF on 1.
+        // "xyx" then EOF is 0, 10, 0, 11: bits 0 1 0 0 1 1 LSB first
+        let squeezed = [
+            2,
+            0, // two nodes
+            0xFF - b'x',
+            0xFF,
+            1,
+            0, // !'x', node 1
+            0xFF - b'y',
+            0xFF,
+            0xFF,
+            0xFE, // !'y', !256
+            0b0011_0010,
+        ];
+        let mut archive = header(METHOD_STORED, "A.TXT", b"plain", b"plain");
+        archive.extend(header(METHOD_PACKED, "B.TXT", &packed, b"aaaaab\x90"));
+        archive.extend(header(METHOD_SQUEEZED, "C.TXT", &squeezed, b"xyx"));
+        archive.extend_from_slice(&[MARKER, METHOD_END]);
+
+        let children = ARC.children(Arc::new(BytesReader::new(archive))).unwrap();
+        let names: Vec<_> = children.iter().map(|c| c.metadata[0].1.as_str()).collect();
+        assert_eq!(names, ["A.TXT", "B.TXT", "C.TXT"]);
+        assert_eq!(read(&children[0]), b"plain");
+        assert_eq!(read(&children[1]), b"aaaaab\x90");
+        assert_eq!(read(&children[2]), b"xyx");
+    }
+
+    /// Crunch with the decoder's table, packing codes in pairs
+    fn crunch(data: &[u8], fast_hash: bool) -> Vec<u8> {
+        let mut table = CrunchTable::new(fast_hash);
+        let find = |table: &CrunchTable, pred: u16, foll: u8| {
+            let mut at = table.hash(pred, foll);
+            while table.entries[at].used {
+                let entry = table.entries[at];
+                if entry.pred == pred && entry.foll == foll {
+                    return Some(at as u16);
+                }
+                if entry.next == 0 {
+                    break;
+                }
+                at = usize::from(entry.next);
+            }
+            None
+        };
+        let mut codes = Vec::new();
+        let mut room = CRUNCH_TABLE_SIZE - 256;
+        let mut ent = find(&table, NO_PRED, data[0]).unwrap();
+        for &c in &data[1..] {
+            if let Some(code) = find(&table, ent, c) {
+                ent = code;
+                continue;
+            }
+            codes.push(ent);
+            if room > 0 {
+                table.insert(ent, c);
+                room -= 1;
+            }
+            ent = find(&table, NO_PRED, c).unwrap();
+        }
+        codes.push(ent);
+
+        let mut out = Vec::new();
+        for pair in codes.chunks(2) {
+            out.push((pair[0] >> 4) as u8);
+            match pair {
+                [a, b] => out.extend([(a << 4 | b >> 8) as u8, *b as u8]),
+                _ => out.push((pair[0] << 4) as u8),
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn hashes_like_arc() {
+        // (0xFFFF + 'A') | 0x800 = 0x840, squared 0x441000, middle bits
+        // 0x040; and 0x40 * 15073 = 0xEB840
+        assert_eq!(CrunchTable::new(false).hash(NO_PRED, b'A'), 0x040);
+        assert_eq!(CrunchTable::new(true).hash(NO_PRED, b'A'), 0x840);
+    }
+
+    #[test]
+    fn reads_old_crunched_members() {
+        // Enough text to fill the table, with runs for the packed methods
+        let mut text = Vec::new();
+        for i in 0..3000u32 {
+            text.extend_from_slice(format!("{} {} ", i * 7 % 1000, i % 13).as_bytes());
+            if i % 100 == 0 {
+                text.extend([b'-'; 40]);
+            }
+        }
+        let packed = {
+            let mut packed = Vec::new();
+            for chunk in text.chunk_by(|a, b| a == b) {
+                packed.push(chunk[0]);
+                if chunk.len() > 2 {
+                    packed.extend([DLE, chunk.len() as u8]);
+                } else if chunk.len() == 2 {
+                    packed.push(chunk[0]);
+                }
+            }
+            packed
+        };
+        let mut archive = header(METHOD_OLD_CRUNCHED, "A", &crunch(&text, false), &text);
+        archive.extend(header(
+            METHOD_OLD_PACKED_CRUNCHED,
+            "B",
+            &crunch(&packed, false),
+            &text,
+        ));
+        archive.extend(header(
+            METHOD_FAST_CRUNCHED,
+            "C",
+            &crunch(&packed, true),
+            &text,
+        ));
+        archive.extend_from_slice(&[MARKER, METHOD_END]);
+
+        let children = ARC.children(Arc::new(BytesReader::new(archive))).unwrap();
+        for child in &children {
+            assert_eq!(read(child), text);
+        }
+    }
+
+    #[test]
+    fn rejects_bad_crc() {
+        let mut archive = header(METHOD_PACKED, "A", b"abc", b"abd");
+        archive.extend_from_slice(&[MARKER, METHOD_END]);
+        let children = ARC.children(Arc::new(BytesReader::new(archive))).unwrap();
+        let mut buf = [0u8; 3];
+        assert!(children[0].reader.read_at(0, &mut buf).is_err());
+    }
+}
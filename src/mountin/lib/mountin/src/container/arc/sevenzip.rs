@@ -0,0 +1,1018 @@
+//! 7z archive container reader
+//!
+//! Parses the 7z header (plain or encoded/compressed), then exposes each
+//! file as a child. Files live in "folders" - coder chains whose output is
+//! the concatenation of the files they contain - so a folder is decoded
+//! once, on first access, and its files are slices of that output.
+//!
+//! Supported coders: copy, LZMA, LZMA2, deflate, bzip2, delta and the
+//! x86/ARM/ARMT/PPC/SPARC branch filters.
+
+use crate::container::arc::bcj;
+use crate::container::{
+    invalid_data, read_to_end_limited, slice::SliceReader, Child, Container, DeferredReader, Entry,
+    LimitedBuffer, MAX_SIZE,
+};
+use crate::detect::Reader;
+use bzip2::read::BzDecoder;
+use flate2::read::DeflateDecoder;
+use lzma_rs::decompress::{Options, UnpackedSize};
+use std::io::{self, Read};
+use std::sync::Arc;
+
+const SIGNATURE: &[u8; 6] = b"7z\xBC\xAF\x27\x1C";
+const SIGNATURE_HEADER_SIZE: u64 = 32;
+const MAX_HEADER_SIZE: u64 = 64 * 1024 * 1024;
+const MAX_ENTRIES: u64 = 1 << 20;
+
+// Property IDs
+const K_END: u8 = 0x00;
+const K_HEADER: u8 = 0x01;
+const K_ARCHIVE_PROPERTIES: u8 = 0x02;
+const K_ADDITIONAL_STREAMS_INFO: u8 = 0x03;
+const K_MAIN_STREAMS_INFO: u8 = 0x04;
+const K_FILES_INFO: u8 = 0x05;
+const K_PACK_INFO: u8 = 0x06;
+const K_UNPACK_INFO: u8 = 0x07;
+const K_SUBSTREAMS_INFO: u8 = 0x08;
+const K_SIZE: u8 = 0x09;
+const K_CRC: u8 = 0x0A;
+const K_FOLDER: u8 = 0x0B;
+const K_CODERS_UNPACK_SIZE: u8 = 0x0C;
+const K_NUM_UNPACK_STREAM: u8 = 0x0D;
+const K_EMPTY_STREAM: u8 = 0x0E;
+const K_NAME: u8 = 0x11;
+const K_ENCODED_HEADER: u8 = 0x17;
+
+// Coder method IDs
+const METHOD_COPY: u64 = 0x00;
+const METHOD_DELTA: u64 = 0x03;
+const METHOD_LZMA2: u64 = 0x21;
+const METHOD_LZMA: u64 = 0x03_01_01;
+const METHOD_BCJ_X86: u64 = 0x03_03_01_03;
+const METHOD_BCJ_PPC: u64 = 0x03_03_02_05;
+const METHOD_BCJ_ARM: u64 = 0x03_03_05_01;
+const METHOD_BCJ_ARMT: u64 = 0x03_03_07_01;
+const METHOD_BCJ_SPARC: u64 = 0x03_03_08_05;
+const METHOD_DEFLATE: u64 = 0x04_01_08;
+const METHOD_BZIP2: u64 = 0x04_02_02;
+const METHOD_AES: u64 = 0x06_F1_07_01;
+
+/// 7z archive container
+pub struct SevenZipContainer;
+
+/// Static instance for registry
+pub static SEVENZIP: SevenZipContainer = SevenZipContainer;
+
+impl Container for SevenZipContainer {
+    fn children(&self, reader: Arc<dyn Reader + Send + Sync>) -> io::Result<Vec<Child>> {
+        self.entries(reader)?.into_iter().map(Entry::open).collect()
+    }
+
+    fn entries(&self, reader: Arc<dyn Reader + Send + Sync>) -> io::Result<Vec<Entry>> {
+        let archive = parse_archive(&reader)?;
+        let streams = archive.streams;
+
+        // One lazily decoded reader per folder, shared by its files
+        let folders: Vec<Arc<dyn Reader + Send + Sync>> = streams
+            .folders
+            .iter()
+            .enumerate()
+            .map(|(idx, folder)| {
+                let folder = folder.clone();
+                let parent = Arc::clone(&reader);
+                let pack = streams.folder_pack_streams(idx);
+                let size = folder.unpack_size();
+                Arc::new(DeferredReader::new(size, move || {
+                    decode_folder(&*parent, &folder, &pack)
+                })) as Arc<dyn Reader + Send + Sync>
+            })
+            .collect();
+
+        // Where each stored folder's data starts, to place its files; the
+        // files of decoded folders have no place in the archive
+        let pack_starts: Vec<Option<u64>> = (0..streams.folders.len())
+            .map(|idx| {
+                let start = streams
+                    .folder_pack_streams(idx)
+                    .first()
+                    .map_or(0, |&(start, _)| start);
+                streams.folders[idx].is_stored().then_some(start)
+            })
+            .collect();
+
+        let mut entries = Vec::new();
+        let mut substreams = streams.substreams().into_iter();
+        for (idx, file) in archive.files.iter().enumerate() {
+            if !file.has_stream {
+                continue;
+            }
+            let (folder, offset, size) = substreams
+                .next()
+                .ok_or_else(|| invalid_data("7z file without a stream"))?;
+            let folder_reader = Arc::clone(&folders[folder]);
+            entries.push(Entry::new(
+                idx as u32,
+                pack_starts[folder].map_or(u64::MAX, |start| start.saturating_add(offset)),
+                Some(size),
+                vec![("name", file.name.clone())],
+                move || Ok(Arc::new(SliceReader::new(folder_reader, offset, size)) as _),
+            ));
+        }
+
+        Ok(entries)
+    }
+}
+
+/// One coder in a folder's chain
+#[derive(Clone)]
+struct Coder {
+    method: u64,
+    num_in: usize,
+    num_out: usize,
+    properties: Vec<u8>,
+}
+
+/// A folder: a graph of coders whose final output holds one or more files
+#[derive(Clone)]
+struct Folder {
+    coders: Vec<Coder>,
+    /// (in_index, out_index) links between coders
+    bind_pairs: Vec<(usize, usize)>,
+    /// Folder in-stream indices fed directly from packed streams
+    packed: Vec<usize>,
+    /// Size of every coder out-stream
+    unpack_sizes: Vec<u64>,
+}
+
+impl Folder {
+    fn num_out(&self) -> usize {
+        self.coders.iter().map(|c| c.num_out).sum()
+    }
+
+    /// The out-stream not consumed by any bind pair is the folder's result
+    fn main_out(&self) -> Option<usize> {
+        (0..self.num_out()).find(|&out| !self.bind_pairs.iter().any(|&(_, o)| o == out))
+    }
+
+    /// A lone copy coder: the folder's files are stored as they are
+    fn is_stored(&self) -> bool {
+        matches!(&self.coders[..], [coder] if coder.method == METHOD_COPY)
+    }
+
+    fn unpack_size(&self) -> u64 {
+        self.main_out()
+            .and_then(|out| self.unpack_sizes.get(out).copied())
+            .unwrap_or(0)
+    }
+
+    /// Map a coder's out-stream index to (coder index, first in-stream index)
+    fn coder_for_out(&self, out: usize) -> Option<(usize, usize)> {
+        let mut out_base = 0;
+        let mut in_base = 0;
+        for (idx, coder) in self.coders.iter().enumerate() {
+            if out < out_base + coder.num_out {
+                return Some((idx, in_base));
+            }
+            out_base += coder.num_out;
+            in_base += coder.num_in;
+        }
+        None
+    }
+}
+
+/// Streams section: packed stream locations, folders and per-file sizes
+#[derive(Default)]
+struct StreamsInfo {
+    /// (absolute offset, size) of each packed stream
+    pack_streams: Vec<(u64, u64)>,
+    folders: Vec<Folder>,
+    /// Whether each folder's output CRC is recorded in the folder list
+    folder_crcs: Vec<bool>,
+    /// Number of files in each folder
+    num_unpack_streams: Vec<u64>,
+    /// Sizes of each file across all folders
+    unpack_sizes: Vec<u64>,
+}
+
+impl StreamsInfo {
+    /// Packed streams consumed by a folder, in folder order
+    fn folder_pack_streams(&self, folder: usize) -> Vec<(u64, u64)> {
+        let start: usize = self.folders[..folder].iter().map(|f| f.packed.len()).sum();
+        let count = self.folders[folder].packed.len();
+        self.pack_streams
+            .iter()
+            .skip(start)
+            .take(count)
+            .copied()
+            .collect()
+    }
+
+    /// (folder, offset in folder output, size) for every file stream
+    fn substreams(&self) -> Vec<(usize, u64, u64)> {
+        let mut result = Vec::new();
+        let mut sizes = self.unpack_sizes.iter();
+        for (idx, folder) in self.folders.iter().enumerate() {
+            let count = self.num_unpack_streams.get(idx).copied().unwrap_or(1);
+            let mut offset = 0;
+            for _ in 0..count {
+                let Some(&size) = sizes.next() else {
+                    return result;
+                };
+                if offset + size > folder.unpack_size() {
+                    return result;
+                }
+                result.push((idx, offset, size));
+                offset += size;
+            }
+        }
+        result
+    }
+}
+
+/// File entry from the FilesInfo section
+struct FileEntry {
+    name: String,
+    has_stream: bool,
+}
+
+struct Archive {
+    streams: StreamsInfo,
+    files: Vec<FileEntry>,
+}
+
+/// Cursor over an in-memory header
+struct ByteCursor<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ByteCursor<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn byte(&mut self) -> io::Result<u8> {
+        let b = *self
+            .data
+            .get(self.pos)
+            .ok_or_else(|| invalid_data("truncated 7z header"))?;
+        self.pos += 1;
+        Ok(b)
+    }
+
+    fn bytes(&mut self, len: u64) -> io::Result<&'a [u8]> {
+        let end = usize::try_from(len)
+            .ok()
+            .and_then(|len| self.pos.checked_add(len))
+            .filter(|&end| end <= self.data.len())
+            .ok_or_else(|| invalid_data("truncated 7z header"))?;
+        let slice = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        let b = self.bytes(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    /// 7z variable-length number: leading one bits of the first byte count
+    /// the extra little-endian bytes that follow
+    fn number(&mut self) -> io::Result<u64> {
+        let first = self.byte()?;
+        let mut mask = 0x80u8;
+        let mut value = 0u64;
+        for i in 0..8 {
+            if first & mask == 0 {
+                let high = (first & mask.wrapping_sub(1)) as u64;
+                return Ok(value | (high << (8 * i)));
+            }
+            value |= (self.byte()? as u64) << (8 * i);
+            mask >>= 1;
+        }
+        Ok(value)
+    }
+
+    /// A count that will be used to size allocations
+    fn count(&mut self) -> io::Result<usize> {
+        let n = self.number()?;
+        if n > MAX_ENTRIES {
+            return Err(invalid_data("7z header count too large"));
+        }
+        Ok(n as usize)
+    }
+
+    fn bit_vector(&mut self, len: usize) -> io::Result<Vec<bool>> {
+        let mut bits = Vec::with_capacity(len);
+        let mut byte = 0;
+        for i in 0..len {
+            if i % 8 == 0 {
+                byte = self.byte()?;
+            }
+            bits.push(byte & (0x80 >> (i % 8)) != 0);
+        }
+        Ok(bits)
+    }
+
+    /// Bit vector preceded by an "all defined" flag byte
+    fn defined_vector(&mut self, len: usize) -> io::Result<Vec<bool>> {
+        if self.byte()? != 0 {
+            Ok(vec![true; len])
+        } else {
+            self.bit_vector(len)
+        }
+    }
+
+    fn skip_digests(&mut self, len: usize) -> io::Result<()> {
+        let defined = self.defined_vector(len)?;
+        for _ in defined.iter().filter(|&&d| d) {
+            self.u32()?;
+        }
+        Ok(())
+    }
+}
+
+fn parse_archive(reader: &Arc<dyn Reader + Send + Sync>) -> io::Result<Archive> {
+    let mut sig = [0u8; SIGNATURE_HEADER_SIZE as usize];
+    if reader.read_at(0, &mut sig)? != sig.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "short 7z signature header",
+        ));
+    }
+    if &sig[0..6] != SIGNATURE {
+        return Err(invalid_data("invalid 7z signature"));
+    }
+
+    let next_offset = u64::from_le_bytes(sig[12..20].try_into().unwrap());
+    let next_size = u64::from_le_bytes(sig[20..28].try_into().unwrap());
+    if next_size == 0 {
+        // Empty archive
+        return Ok(Archive {
+            streams: StreamsInfo::default(),
+            files: Vec::new(),
+        });
+    }
+    if next_size > MAX_HEADER_SIZE {
+        return Err(invalid_data("7z header too large"));
+    }
+    let header_offset = SIGNATURE_HEADER_SIZE
+        .checked_add(next_offset)
+        .ok_or_else(|| invalid_data("7z header offset overflow"))?;
+
+    let mut header = vec![0u8; next_size as usize];
+    if reader.read_at(header_offset, &mut header)? != header.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "short 7z header read",
+        ));
+    }
+
+    // Encoded headers decode to another header, which may itself be encoded
+    for _ in 0..4 {
+        let mut cursor = ByteCursor::new(&header);
+        match cursor.byte()? {
+            K_HEADER => return parse_header(&mut cursor),
+            K_ENCODED_HEADER => {
+                let streams = parse_streams_info(&mut cursor)?;
+                if streams.folders.is_empty() {
+                    return Err(invalid_data("7z encoded header has no folder"));
+                }
+                header = decode_folder(
+                    &**reader,
+                    &streams.folders[0],
+                    &streams.folder_pack_streams(0),
+                )?;
+            }
+            _ => return Err(invalid_data("unknown 7z header type")),
+        }
+    }
+
+    Err(invalid_data("7z header nested too deeply"))
+}
+
+fn parse_header(cursor: &mut ByteCursor) -> io::Result<Archive> {
+    let mut streams = StreamsInfo::default();
+    let mut files = Vec::new();
+
+    let mut id = cursor.byte()?;
+    if id == K_ARCHIVE_PROPERTIES {
+        loop {
+            let prop = cursor.byte()?;
+            if prop == K_END {
+                break;
+            }
+            let size = cursor.number()?;
+            cursor.bytes(size)?;
+        }
+        id = cursor.byte()?;
+    }
+    if id == K_ADDITIONAL_STREAMS_INFO {
+        parse_streams_info(cursor)?;
+        id = cursor.byte()?;
+    }
+    if id == K_MAIN_STREAMS_INFO {
+        streams = parse_streams_info(cursor)?;
+        id = cursor.byte()?;
+    }
+    if id == K_FILES_INFO {
+        files = parse_files_info(cursor)?;
+        id = cursor.byte()?;
+    }
+    if id != K_END {
+        return Err(invalid_data("unexpected 7z header property"));
+    }
+
+    Ok(Archive { streams, files })
+}
+
+fn parse_streams_info(cursor: &mut ByteCursor) -> io::Result<StreamsInfo> {
+    let mut info = StreamsInfo::default();
+    let mut id = cursor.byte()?;
+
+    if id == K_PACK_INFO {
+        let pack_pos = cursor.number()?;
+        let count = cursor.count()?;
+        let mut sizes = vec![0u64; count];
+        loop {
+            match cursor.byte()? {
+                K_END => break,
+                K_SIZE => {
+                    for size in sizes.iter_mut() {
+                        *size = cursor.number()?;
+                    }
+                }
+                K_CRC => cursor.skip_digests(count)?,
+                _ => return Err(invalid_data("unexpected 7z pack info property")),
+            }
+        }
+        let mut offset = SIGNATURE_HEADER_SIZE
+            .checked_add(pack_pos)
+            .ok_or_else(|| invalid_data("7z pack offset overflow"))?;
+        for size in sizes {
+            info.pack_streams.push((offset, size));
+            offset = offset
+                .checked_add(size)
+                .ok_or_else(|| invalid_data("7z pack offset overflow"))?;
+        }
+        id = cursor.byte()?;
+    }
+
+    if id == K_UNPACK_INFO {
+        if cursor.byte()? != K_FOLDER {
+            return Err(invalid_data("expected 7z folder list"));
+        }
+        let count = cursor.count()?;
+        if cursor.byte()? != 0 {
+            return Err(invalid_data("external 7z folders not supported"));
+        }
+        for _ in 0..count {
+            info.folders.push(parse_folder(cursor)?);
+        }
+        if cursor.byte()? != K_CODERS_UNPACK_SIZE {
+            return Err(invalid_data("expected 7z coder unpack sizes"));
+        }
+        for folder in info.folders.iter_mut() {
+            for _ in 0..folder.num_out() {
+                folder.unpack_sizes.push(cursor.number()?);
+            }
+        }
+        info.folder_crcs = vec![false; count];
+        loop {
+            match cursor.byte()? {
+                K_END => break,
+                K_CRC => {
+                    info.folder_crcs = cursor.defined_vector(count)?;
+                    for _ in info.folder_crcs.iter().filter(|&&d| d) {
+                        cursor.u32()?;
+                    }
+                }
+                _ => return Err(invalid_data("unexpected 7z unpack info property")),
+            }
+        }
+        id = cursor.byte()?;
+    }
+
+    // Without substream info, each folder holds exactly one file
+    info.num_unpack_streams = vec![1; info.folders.len()];
+    info.unpack_sizes = info.folders.iter().map(Folder::unpack_size).collect();
+
+    if id == K_SUBSTREAMS_INFO {
+        id = cursor.byte()?;
+        if id == K_NUM_UNPACK_STREAM {
+            for count in info.num_unpack_streams.iter_mut() {
+                *count = cursor.number()?;
+                if *count > MAX_ENTRIES {
+                    return Err(invalid_data("7z substream count too large"));
+                }
+            }
+            id = cursor.byte()?;
+        }
+
+        // Each folder lists all but its last size; the last is the remainder
+        let mut sizes = Vec::new();
+        let has_sizes = id == K_SIZE;
+        for (folder, &count) in info.folders.iter().zip(&info.num_unpack_streams) {
+            if count == 0 {
+                continue;
+            }
+            let mut sum = 0u64;
+            if has_sizes {
+                for _ in 1..count {
+                    let size = cursor.number()?;
+                    sum = sum
+                        .checked_add(size)
+                        .ok_or_else(|| invalid_data("7z substream size overflow"))?;
+                    sizes.push(size);
+                }
+            }
+            let remainder = folder
+                .unpack_size()
+                .checked_sub(sum)
+                .ok_or_else(|| invalid_data("7z substreams larger than folder"))?;
+            sizes.push(remainder);
+        }
+        info.unpack_sizes = sizes;
+        if has_sizes {
+            id = cursor.byte()?;
+        }
+
+        while id != K_END {
+            if id != K_CRC {
+                return Err(invalid_data("unexpected 7z substream property"));
+            }
+            // Digests cover streams whose CRC isn't already known per folder
+            let unknown: usize = info
+                .folder_crcs
+                .iter()
+                .zip(&info.num_unpack_streams)
+                .map(|(&known, &count)| {
+                    if count == 1 && known {
+                        0
+                    } else {
+                        count as usize
+                    }
+                })
+                .sum();
+            cursor.skip_digests(unknown)?;
+            id = cursor.byte()?;
+        }
+        id = cursor.byte()?;
+    }
+
+    if id != K_END {
+        return Err(invalid_data("unexpected 7z streams property"));
+    }
+
+    Ok(info)
+}
+
+fn parse_folder(cursor: &mut ByteCursor) -> io::Result<Folder> {
+    let num_coders = cursor.count()?;
+    if num_coders == 0 || num_coders > 64 {
+        return Err(invalid_data("invalid 7z coder count"));
+    }
+
+    let mut coders = Vec::with_capacity(num_coders);
+    for _ in 0..num_coders {
+        let flags = cursor.byte()?;
+        let id_size = (flags & 0x0F) as u64;
+        if id_size > 8 {
+            return Err(invalid_data("invalid 7z coder id"));
+        }
+        let method = cursor
+            .bytes(id_size)?
+            .iter()
+            .fold(0u64, |acc, &b| (acc << 8) | b as u64);
+        let (num_in, num_out) = if flags & 0x10 != 0 {
+            (cursor.count()?, cursor.count()?)
+        } else {
+            (1, 1)
+        };
+        let properties = if flags & 0x20 != 0 {
+            let size = cursor.number()?;
+            cursor.bytes(size)?.to_vec()
+        } else {
+            Vec::new()
+        };
+        if flags & 0x80 != 0 {
+            return Err(invalid_data("7z alternative coder methods not supported"));
+        }
+        coders.push(Coder {
+            method,
+            num_in,
+            num_out,
+            properties,
+        });
+    }
+
+    let total_out: usize = coders.iter().map(|c| c.num_out).sum();
+    let total_in: usize = coders.iter().map(|c| c.num_in).sum();
+    if total_out == 0 || total_out > 64 || total_in > 64 {
+        return Err(invalid_data("invalid 7z coder stream count"));
+    }
+
+    let mut bind_pairs = Vec::new();
+    for _ in 0..total_out - 1 {
+        bind_pairs.push((cursor.count()?, cursor.count()?));
+    }
+
+    let num_packed = total_in
+        .checked_sub(bind_pairs.len())
+        .filter(|&n| n > 0)
+        .ok_or_else(|| invalid_data("invalid 7z bind pairs"))?;
+    let packed = if num_packed == 1 {
+        // The single unbound in-stream
+        let unbound = (0..total_in)
+            .find(|&i| !bind_pairs.iter().any(|&(in_index, _)| in_index == i))
+            .ok_or_else(|| invalid_data("invalid 7z bind pairs"))?;
+        vec![unbound]
+    } else {
+        (0..num_packed)
+            .map(|_| cursor.count())
+            .collect::<io::Result<_>>()?
+    };
+
+    Ok(Folder {
+        coders,
+        bind_pairs,
+        packed,
+        unpack_sizes: Vec::new(),
+    })
+}
+
+fn parse_files_info(cursor: &mut ByteCursor) -> io::Result<Vec<FileEntry>> {
+    let count = cursor.count()?;
+    let mut files: Vec<FileEntry> = (0..count)
+        .map(|_| FileEntry {
+            name: String::new(),
+            has_stream: true,
+        })
+        .collect();
+
+    loop {
+        let prop = cursor.byte()?;
+        if prop == K_END {
+            break;
+        }
+        let size = cursor.number()?;
+        let data = cursor.bytes(size)?;
+        let mut sub = ByteCursor::new(data);
+
+        match prop {
+            K_EMPTY_STREAM => {
+                let empty = sub.bit_vector(count)?;
+                for (file, empty) in files.iter_mut().zip(empty) {
+                    file.has_stream = !empty;
+                }
+            }
+            K_NAME => {
+                if sub.byte()? != 0 {
+                    return Err(invalid_data("external 7z names not supported"));
+                }
+                let units: Vec<u16> = sub.data[1..]
+                    .chunks_exact(2)
+                    .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                    .collect();
+                for (file, name) in files.iter_mut().zip(units.split(|&u| u == 0)) {
+                    file.name = String::from_utf16_lossy(name);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(files)
+}
+
+/// Decode a folder's main output into memory
+fn decode_folder(
+    reader: &dyn Reader,
+    folder: &Folder,
+    pack_streams: &[(u64, u64)],
+) -> io::Result<Vec<u8>> {
+    let out = folder
+        .main_out()
+        .ok_or_else(|| invalid_data("7z folder has no output"))?;
+    decode_out_stream(reader, folder, pack_streams, out, 0)
+}
+
+fn decode_out_stream(
+    reader: &dyn Reader,
+    folder: &Folder,
+    pack_streams: &[(u64, u64)],
+    out: usize,
+    depth: usize,
+) -> io::Result<Vec<u8>> {
+    if depth > folder.coders.len() {
+        return Err(invalid_data("7z coder graph has a cycle"));
+    }
+    let (coder_idx, in_base) = folder
+        .coder_for_out(out)
+        .ok_or_else(|| invalid_data("invalid 7z coder stream"))?;
+    let coder = &folder.coders[coder_idx];
+    if coder.num_in != 1 || coder.num_out != 1 {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "7z multi-stream coders (BCJ2) not supported",
+        ));
+    }
+    let unpack_size = folder.unpack_sizes.get(out).copied().unwrap_or(0);
+    if unpack_size > MAX_SIZE as u64 {
+        return Err(invalid_data("decompressed container too large"));
+    }
+
+    // The coder's input is either another coder's output or a packed stream
+    let input = if let Some(&(_, bound_out)) = folder
+        .bind_pairs
+        .iter()
+        .find(|&&(in_index, _)| in_index == in_base)
+    {
+        decode_out_stream(reader, folder, pack_streams, bound_out, depth + 1)?
+    } else {
+        let pack_index = folder
+            .packed
+            .iter()
+            .position(|&i| i == in_base)
+            .ok_or_else(|| invalid_data("7z coder input not bound"))?;
+        let &(offset, size) = pack_streams
+            .get(pack_index)
+            .ok_or_else(|| invalid_data("missing 7z packed stream"))?;
+        if size > MAX_SIZE as u64 {
+            return Err(invalid_data("7z packed stream too large"));
+        }
+        let mut data = vec![0u8; size as usize];
+        if reader.read_at(offset, &mut data)? != data.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "short 7z packed stream read",
+            ));
+        }
+        data
+    };
+
+    let mut output = run_coder(coder, input, unpack_size)?;
+    if (output.len() as u64) < unpack_size {
+        return Err(invalid_data("7z coder output truncated"));
+    }
+    output.truncate(unpack_size as usize);
+    Ok(output)
+}
+
+fn run_coder(coder: &Coder, mut input: Vec<u8>, unpack_size: u64) -> io::Result<Vec<u8>> {
+    match coder.method {
+        METHOD_COPY => Ok(input),
+        METHOD_LZMA => {
+            if coder.properties.len() < 5 {
+                return Err(invalid_data("invalid 7z LZMA properties"));
+            }
+            // lzma-rs reads the 5 property bytes as a header prefix
+            let mut stream = (&coder.properties[..5]).chain(&input[..]);
+            let mut buffered = io::BufReader::new(&mut stream);
+            let mut output = LimitedBuffer::new();
+            let options = Options {
+                unpacked_size: UnpackedSize::UseProvided(Some(unpack_size)),
+                ..Options::default()
+            };
+            lzma_rs::lzma_decompress_with_options(&mut buffered, &mut output, &options)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+            Ok(output.into_inner())
+        }
+        METHOD_LZMA2 => {
+            let mut output = LimitedBuffer::new();
+            lzma_rs::lzma2_decompress(&mut &input[..], &mut output)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+            Ok(output.into_inner())
+        }
+        METHOD_DEFLATE => read_to_end_limited(DeflateDecoder::new(&input[..])),
+        METHOD_BZIP2 => read_to_end_limited(BzDecoder::new(&input[..])),
+        METHOD_DELTA => {
+            let distance = coder.properties.first().map_or(1, |&d| d as usize + 1);
+            for i in distance..input.len() {
+                input[i] = input[i].wrapping_add(input[i - distance]);
+            }
+            Ok(input)
+        }
+        METHOD_BCJ_X86 => {
+            bcj::x86(&mut input, false);
+            Ok(input)
+        }
+        METHOD_BCJ_PPC => {
+            bcj::ppc(&mut input, false);
+            Ok(input)
+        }
+        METHOD_BCJ_ARM => {
+            bcj::arm(&mut input, false);
+            Ok(input)
+        }
+        METHOD_BCJ_ARMT => {
+            bcj::armt(&mut input, false);
+            Ok(input)
+        }
+        METHOD_BCJ_SPARC => {
+            bcj::sparc(&mut input, false);
+            Ok(input)
+        }
+        METHOD_AES => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "encrypted 7z archives not supported",
+        )),
+        _ => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "unsupported 7z coder",
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::BytesReader;
+
+    /// 7z variable-length number encoding
+    fn number(value: u64) -> Vec<u8> {
+        if value < 0x80 {
+            return vec![value as u8];
+        }
+        assert!(value < 0x4000);
+        vec![0x80 | (value >> 8) as u8, value as u8]
+    }
+
+    fn utf16_names(names: &[&str]) -> Vec<u8> {
+        let mut data = vec![0u8];
+        for name in names {
+            for unit in name.encode_utf16().chain([0]) {
+                data.extend_from_slice(&unit.to_le_bytes());
+            }
+        }
+        data
+    }
+
+    /// One folder: coder method and properties, packed data and the files
+    /// it unpacks to
+    type Folder<'a> = (&'a [u8], &'a [u8], &'a [u8], &'a [(&'a str, &'a [u8])]);
+
+    /// Build an archive with one folder of the given coder holding files
+    fn archive(method: &[u8], props: &[u8], packed: &[u8], files: &[(&str, &[u8])]) -> Vec<u8> {
+        archive_folders(&[(method, props, packed, files)])
+    }
+
+    /// Build an archive of folders, each with a single coder
+    fn archive_folders(folders: &[Folder]) -> Vec<u8> {
+        let mut header = vec![K_HEADER, K_MAIN_STREAMS_INFO];
+        header.extend([K_PACK_INFO, 0]);
+        header.extend(number(folders.len() as u64));
+        header.push(K_SIZE);
+        for (_, _, packed, _) in folders {
+            header.extend(number(packed.len() as u64));
+        }
+        header.extend([K_END, K_UNPACK_INFO, K_FOLDER]);
+        header.extend(number(folders.len() as u64));
+        header.push(0);
+        for (method, props, _, _) in folders {
+            header.push(1);
+            let flags = method.len() as u8 | if props.is_empty() { 0 } else { 0x20 };
+            header.push(flags);
+            header.extend_from_slice(method);
+            if !props.is_empty() {
+                header.push(props.len() as u8);
+                header.extend_from_slice(props);
+            }
+        }
+        header.push(K_CODERS_UNPACK_SIZE);
+        for (_, _, _, files) in folders {
+            let total: u64 = files.iter().map(|(_, d)| d.len() as u64).sum();
+            header.extend(number(total));
+        }
+        header.extend([K_END, K_SUBSTREAMS_INFO, K_NUM_UNPACK_STREAM]);
+        for (_, _, _, files) in folders {
+            header.extend(number(files.len() as u64));
+        }
+        header.push(K_SIZE);
+        for (_, _, _, files) in folders {
+            for (_, data) in &files[..files.len() - 1] {
+                header.extend(number(data.len() as u64));
+            }
+        }
+        let files: Vec<_> = folders.iter().flat_map(|folder| folder.3).collect();
+        header.extend([K_END, K_END, K_FILES_INFO]);
+        header.extend(number(files.len() as u64));
+        let names: Vec<&str> = files.iter().map(|(name, _)| *name).collect();
+        let names = utf16_names(&names);
+        header.push(K_NAME);
+        header.extend(number(names.len() as u64));
+        header.extend(names);
+        header.extend([K_END, K_END]);
+
+        let packed: Vec<u8> = folders
+            .iter()
+            .flat_map(|folder| folder.2)
+            .copied()
+            .collect();
+        let mut data = SIGNATURE.to_vec();
+        data.extend([0, 4]);
+        data.extend([0; 4]);
+        data.extend((packed.len() as u64).to_le_bytes());
+        data.extend((header.len() as u64).to_le_bytes());
+        data.extend([0; 4]);
+        data.extend(packed);
+        data.extend(header);
+        data
+    }
+
+    /// LZMA coder properties and stream for `plain`
+    fn lzma(plain: &[u8]) -> (Vec<u8>, Vec<u8>) {
+        let mut stream = Vec::new();
+        lzma_rs::lzma_compress(&mut &plain[..], &mut stream).unwrap();
+        // lzma-rs writes a 13-byte .lzma header: 5 property bytes + size
+        (stream[..5].to_vec(), stream[13..].to_vec())
+    }
+
+    fn contents(child: &Child) -> Vec<u8> {
+        let mut buf = vec![0u8; child.reader.size().unwrap() as usize];
+        assert_eq!(child.reader.read_at(0, &mut buf).unwrap(), buf.len());
+        buf
+    }
+
+    #[test]
+    fn lists_stored_files() {
+        let data = archive(
+            &[0],
+            &[],
+            b"helloworld",
+            &[("a.txt", b"hello"), ("b/c.img", b"world")],
+        );
+        let kids = SEVENZIP.children(Arc::new(BytesReader::new(data))).unwrap();
+        assert_eq!(kids.len(), 2);
+        assert_eq!(kids[1].metadata, vec![("name", "b/c.img".to_string())]);
+        // Files are placed from where their folder's packed data starts
+        assert_eq!((kids[0].offset, kids[1].offset), (32, 37));
+        assert_eq!(contents(&kids[0]), b"hello");
+        assert_eq!(contents(&kids[1]), b"world");
+    }
+
+    #[test]
+    fn decodes_lzma_folder() {
+        let plain = b"disk image contents ".repeat(50);
+        let (props, packed) = lzma(&plain);
+        let data = archive(&[3, 1, 1], &props, &packed, &[("disk.img", &plain)]);
+        let kids = SEVENZIP.children(Arc::new(BytesReader::new(data))).unwrap();
+        assert_eq!(contents(&kids[0]), plain);
+        assert_eq!(kids[0].offset, u64::MAX);
+    }
+
+    #[test]
+    fn detects_members_of_every_compressed_folder() {
+        // Two LZMA folders of two files each, all holding the same format
+        let disk = |fill: u8| {
+            let mut disk = b"SINCLAIR".to_vec();
+            disk.resize(600, fill);
+            disk
+        };
+        let (a, b, c, d) = (disk(b'a'), disk(b'b'), disk(b'c'), disk(b'd'));
+        let first = [("a.scl", &a[..]), ("b.scl", &b[..])];
+        let second = [("c.scl", &c[..]), ("d.scl", &d[..])];
+        let (props1, packed1) = lzma(&[a.clone(), b.clone()].concat());
+        let (props2, packed2) = lzma(&[c.clone(), d.clone()].concat());
+        let data = archive_folders(&[
+            (&[3, 1, 1], &props1, &packed1, &first),
+            (&[3, 1, 1], &props2, &packed2, &second),
+        ]);
+
+        let kids = SEVENZIP
+            .children(Arc::new(BytesReader::new(data.clone())))
+            .unwrap();
+        assert!(kids.iter().all(|kid| kid.offset == u64::MAX));
+        assert_eq!(contents(&kids[1]), b);
+        assert_eq!(contents(&kids[2]), c);
+
+        crate::format::init_test_formats();
+        let tree = crate::detect::detect_tree(Arc::new(BytesReader::new(data)));
+        let archive = tree
+            .iter()
+            .find(|n| n.format.to_str() == Ok("arc/7z"))
+            .expect("arc/7z not detected");
+        let formats: Vec<_> = archive
+            .children
+            .iter()
+            .map(|n| n.format.to_str().unwrap())
+            .collect();
+        assert_eq!(formats, ["disk/scl"; 4]);
+    }
+
+    #[test]
+    fn parses_variable_length_numbers() {
+        let mut cursor = ByteCursor::new(&[0x05, 0x81, 0x23, 0xC0, 0x34, 0x12]);
+        assert_eq!(cursor.number().unwrap(), 5);
+        assert_eq!(cursor.number().unwrap(), 0x123);
+        assert_eq!(cursor.number().unwrap(), 0x1234);
+    }
+
+    #[test]
+    fn rejects_bad_signature() {
+        let mut data = archive(&[0], &[], b"x", &[("x", b"x")]);
+        data[0] = b'8';
+        assert!(SEVENZIP.children(Arc::new(BytesReader::new(data))).is_err());
+    }
+}
@@ -0,0 +1,358 @@
+//! StuffIt (1.5-era) archive reader
+//!
+//! A 22-byte archive header ("SIT!" or a later "ST.." signature, with
+//! "rLau" at offset 10) is followed by 112-byte entry headers, each
+//! followed by its compressed resource fork and then its compressed data
+//! fork. Folders are bracketed by entries whose method is 32 (start) and
+//! 33 (end); those entries carry no data. Each fork has its own method and
+//! a CRC-16/ARC of its decoded contents.
+//!
+//! Supported methods are stored (0), run-length coded (1), compress-style
+//! LZW of up to 14 bits (2), static Huffman (3) and LHarc's adaptive
+//! Huffman, "LZAH" (5). Encrypted forks, the other methods and StuffIt 5
+//! archives are not supported.
+
+use super::compress::lzw;
+use super::lzh::{decode_lh1, Bits};
+use super::mac::{file_name, rle90, Fork, MacFile};
+use super::sea::crc16;
+use crate::container::{
+    be16, be32, invalid_data, read_exact_at, slice::SliceReader, Child, Container, DeferredReader,
+    Entry, MAX_SIZE,
+};
+use crate::detect::Reader;
+use std::io;
+use std::sync::Arc;
+
+const HEADER_SIZE: usize = 22;
+const ENTRY_SIZE: usize = 112;
+const SIGNATURES: [&[u8; 4]; 9] = [
+    b"SIT!", b"ST46", b"ST50", b"ST60", b"ST65", b"STin", b"STi2", b"STi3", b"STi4",
+];
+const CREATOR: &[u8; 4] = b"rLau";
+const SIT5_SIGNATURE: &[u8] = b"StuffIt (c)";
+
+const METHOD_STORED: u8 = 0;
+const METHOD_RLE: u8 = 1;
+const METHOD_LZW: u8 = 2;
+const METHOD_HUFFMAN: u8 = 3;
+const METHOD_LZAH: u8 = 5;
+const METHOD_FOLDER_START: u8 = 32;
+const METHOD_FOLDER_END: u8 = 33;
+const METHOD_ENCRYPTED: u8 = 0x10;
+const METHOD_MASK: u8 = 0x0F;
+
+const LZW_BITS: u32 = 14;
+/// A Huffman tree over bytes has at most 511 nodes
+const MAX_HUFFMAN_NODES: usize = 511;
+
+/// StuffIt container - exposes each file's forks as children
+pub struct StuffItContainer;
+
+/// Static instance for registry
+pub static STUFFIT: StuffItContainer = StuffItContainer;
+
+impl Container for StuffItContainer {
+    fn children(&self, reader: Arc<dyn Reader + Send + Sync>) -> io::Result<Vec<Child>> {
+        self.entries(reader)?.into_iter().map(Entry::open).collect()
+    }
+
+    fn entries(&self, reader: Arc<dyn Reader + Send + Sync>) -> io::Result<Vec<Entry>> {
+        let mut entries = Vec::new();
+        for member in parse(&*reader)? {
+            let parent = Arc::clone(&reader);
+            let (offset, size) = (member.offset, u64::from(member.size));
+            let metadata = member.file.fork_metadata(member.fork);
+            entries.push(Entry::new(
+                entries.len() as u32,
+                offset,
+                Some(size),
+                metadata,
+                move || {
+                    let child: Arc<dyn Reader + Send + Sync> = if member.method == METHOD_STORED {
+                        let packed = u64::from(member.packed);
+                        Arc::new(SliceReader::new(parent, offset, packed))
+                    } else {
+                        Arc::new(DeferredReader::new(size, move || decode(&*parent, &member)))
+                    };
+                    Ok(child)
+                },
+            ));
+        }
+        Ok(entries)
+    }
+}
+
+/// One fork of a file in the archive
+#[derive(Clone, Debug)]
+struct Member {
+    file: MacFile,
+    fork: Fork,
+    method: u8,
+    offset: u64,
+    packed: u32,
+    size: u32,
+    crc: u16,
+}
+
+fn parse(reader: &dyn Reader) -> io::Result<Vec<Member>> {
+    let mut header = [0u8; HEADER_SIZE];
+    read_exact_at(reader, 0, &mut header)?;
+    if header.starts_with(SIT5_SIGNATURE) {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "StuffIt 5 archives are not supported",
+        ));
+    }
+    if !SIGNATURES.iter().any(|s| header.starts_with(*s)) || &header[10..14] != CREATOR {
+        return Err(invalid_data("invalid StuffIt signature"));
+    }
+    let mut end = u64::from(be32(&header[6..]));
+    if let Some(size) = reader.size() {
+        end = end.min(size);
+    }
+
+    let mut members = Vec::new();
+    let mut folders: Vec<String> = Vec::new();
+    let mut offset = HEADER_SIZE as u64;
+    while offset + ENTRY_SIZE as u64 <= end {
+        let mut entry = [0u8; ENTRY_SIZE];
+        read_exact_at(reader, offset, &mut entry)?;
+        if crc16(&entry[..110]) != be16(&entry[110..]) {
+            return Err(invalid_data("StuffIt entry header CRC mismatch"));
+        }
+        offset += ENTRY_SIZE as u64;
+
+        let name_len = usize::from(entry[2]).min(63);
+        let name = file_name(&entry[3..3 + name_len]);
+        let (resource_method, data_method) = (entry[0], entry[1]);
+        if resource_method == METHOD_FOLDER_START || data_method == METHOD_FOLDER_START {
+            folders.push(name);
+            continue;
+        }
+        if resource_method == METHOD_FOLDER_END || data_method == METHOD_FOLDER_END {
+            folders.pop();
+            continue;
+        }
+
+        let file = MacFile {
+            name: folders
+                .iter()
+                .chain(std::iter::once(&name))
+                .map(String::as_str)
+                .collect::<Vec<_>>()
+                .join("/"),
+            file_type: entry[66..70].try_into().unwrap(),
+            creator: entry[70..74].try_into().unwrap(),
+        };
+        let resource = Member {
+            file: file.clone(),
+            fork: Fork::Resource,
+            method: resource_method,
+            offset,
+            packed: be32(&entry[92..]),
+            size: be32(&entry[84..]),
+            crc: be16(&entry[100..]),
+        };
+        let data = Member {
+            file,
+            fork: Fork::Data,
+            method: data_method,
+            offset: offset + u64::from(resource.packed),
+            packed: be32(&entry[96..]),
+            size: be32(&entry[88..]),
+            crc: be16(&entry[102..]),
+        };
+        offset = data.offset + u64::from(data.packed);
+        if offset > end {
+            return Err(invalid_data("StuffIt entry extends past end of archive"));
+        }
+
+        if resource.size > 0 {
+            members.push(resource);
+        }
+        members.push(data);
+    }
+    Ok(members)
+}
+
+fn decode(reader: &dyn Reader, member: &Member) -> io::Result<Vec<u8>> {
+    let size = member.size as usize;
+    if member.packed as usize > MAX_SIZE || size > MAX_SIZE {
+        return Err(invalid_data("StuffIt fork too large"));
+    }
+    if member.method & METHOD_ENCRYPTED != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "encrypted StuffIt forks are not supported",
+        ));
+    }
+    let mut data = vec![0u8; member.packed as usize];
+    read_exact_at(reader, member.offset, &mut data)?;
+
+    let mut out = match member.method & METHOD_MASK {
+        METHOD_STORED => data,
+        METHOD_RLE => rle90(&data, size)?,
+        METHOD_LZW => lzw(&data, LZW_BITS, true)?,
+        METHOD_HUFFMAN => huffman(&data, size)?,
+        METHOD_LZAH => decode_lh1(&data, size)?,
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "unsupported StuffIt compression method",
+            ))
+        }
+    };
+    // LZW output may run on into the final code's padding
+    if out.len() < size {
+        return Err(invalid_data("StuffIt fork size mismatch"));
+    }
+    out.truncate(size);
+    if crc16(&out) != member.crc {
+        return Err(invalid_data("StuffIt fork CRC mismatch"));
+    }
+    Ok(out)
+}
+
+/// A node of a StuffIt Huffman tree: a byte, or the indices of the
+/// subtrees for a 0 and a 1 bit
+enum Node {
+    Leaf(u8),
+    Branch(usize, usize),
+}
+
+/// Read the tree stored before the code: a 1 bit is a leaf followed by
+/// its 8-bit byte, a 0 bit a branch followed by its 0 and 1 subtrees
+fn read_tree(bits: &mut Bits, nodes: &mut Vec<Node>) -> io::Result<usize> {
+    if nodes.len() >= MAX_HUFFMAN_NODES {
+        return Err(invalid_data("invalid StuffIt Huffman tree"));
+    }
+    let index = nodes.len();
+    if bits.read(1) == 1 {
+        nodes.push(Node::Leaf(bits.read(8) as u8));
+    } else {
+        nodes.push(Node::Branch(0, 0));
+        let zero = read_tree(bits, nodes)?;
+        let one = read_tree(bits, nodes)?;
+        nodes[index] = Node::Branch(zero, one);
+    }
+    Ok(index)
+}
+
+/// Decode StuffIt's static Huffman method
+fn huffman(data: &[u8], size: usize) -> io::Result<Vec<u8>> {
+    let mut bits = Bits::new(data);
+    let mut nodes = Vec::new();
+    read_tree(&mut bits, &mut nodes)?;
+    let total_bits = data.len() * 8;
+    let mut used = 0;
+
+    let mut out = Vec::with_capacity(size);
+    while out.len() < size {
+        let mut node = 0;
+        loop {
+            match nodes[node] {
+                Node::Leaf(byte) => {
+                    out.push(byte);
+                    break;
+                }
+                Node::Branch(zero, one) => {
+                    used += 1;
+                    node = if bits.read(1) == 0 { zero } else { one };
+                }
+            }
+        }
+        if used > total_bits {
+            return Err(invalid_data("truncated StuffIt Huffman data"));
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::BytesReader;
+
+    /// MSB-first bit packer
+    fn pack(bits: &str) -> Vec<u8> {
+        let bits: Vec<u8> = bits.bytes().filter(|b| *b != b' ').collect();
+        bits.chunks(8)
+            .map(|chunk| {
+                chunk
+                    .iter()
+                    .enumerate()
+                    .fold(0u8, |acc, (i, &b)| acc | (b - b'0') << (7 - i))
+            })
+            .collect()
+    }
+
+    fn entry(name: &[u8], methods: [u8; 2], forks: [(&[u8], &[u8]); 2]) -> Vec<u8> {
+        let [(resource, resource_packed), (data, data_packed)] = forks;
+        let mut hdr = vec![0u8; ENTRY_SIZE];
+        hdr[..2].copy_from_slice(&methods);
+        hdr[2] = name.len() as u8;
+        hdr[3..3 + name.len()].copy_from_slice(name);
+        hdr[66..74].copy_from_slice(b"TEXTttxt");
+        hdr[84..88].copy_from_slice(&(resource.len() as u32).to_be_bytes());
+        hdr[88..92].copy_from_slice(&(data.len() as u32).to_be_bytes());
+        hdr[92..96].copy_from_slice(&(resource_packed.len() as u32).to_be_bytes());
+        hdr[96..100].copy_from_slice(&(data_packed.len() as u32).to_be_bytes());
+        hdr[100..102].copy_from_slice(&crc16(resource).to_be_bytes());
+        hdr[102..104].copy_from_slice(&crc16(data).to_be_bytes());
+        let crc = crc16(&hdr[..110]);
+        hdr[110..].copy_from_slice(&crc.to_be_bytes());
+        hdr.extend_from_slice(resource_packed);
+        hdr.extend_from_slice(data_packed);
+        hdr
+    }
+
+    fn archive(entries: &[Vec<u8>]) -> Vec<u8> {
+        let body = entries.concat();
+        let mut out = b"SIT!".to_vec();
+        out.extend_from_slice(&(entries.len() as u16).to_be_bytes());
+        out.extend_from_slice(&((HEADER_SIZE + body.len()) as u32).to_be_bytes());
+        out.extend_from_slice(CREATOR);
+        out.extend_from_slice(&[1, 0, 0, 0, 0, 0, 0, 0]);
+        out.extend(body);
+        out
+    }
+
+    #[test]
+    fn decodes_huffman() {
+        // Tree: 0 -> 'a', 1 -> (0 -> 'b', 1 -> 'c'); then "abcaa"
+        let data = pack("0 1 01100001 0 1 01100010 1 01100011 0 10 11 0 0");
+        assert_eq!(huffman(&data, 5).unwrap(), b"abcaa");
+        assert!(huffman(&[0; 4], 1).is_err());
+    }
+
+    #[test]
+    fn reads_folders_and_forks() {
+        let sit = archive(&[
+            entry(b"Disks", [METHOD_FOLDER_START; 2], [(b"", b""), (b"", b"")]),
+            entry(
+                b"Boot",
+                [METHOD_STORED, METHOD_RLE],
+                [(b"rsrc", b"rsrc"), (b"zzzzzz!", b"z\x90\x06!")],
+            ),
+            entry(b"Disks", [METHOD_FOLDER_END; 2], [(b"", b""), (b"", b"")]),
+            entry(b"Read Me", [0, 0], [(b"", b""), (b"hi", b"hi")]),
+        ]);
+        let children = STUFFIT.children(Arc::new(BytesReader::new(sit))).unwrap();
+        let names: Vec<_> = children
+            .iter()
+            .map(|c| (c.metadata[0].1.as_str(), c.metadata[1].1.as_str()))
+            .collect();
+        assert_eq!(
+            names,
+            [
+                ("Disks/Boot", "resource"),
+                ("Disks/Boot", "data"),
+                ("Read Me", "data"),
+            ]
+        );
+        let mut buf = [0u8; 7];
+        children[1].reader.read_at(0, &mut buf).unwrap();
+        assert_eq!(&buf, b"zzzzzz!");
+    }
+}
@@ -0,0 +1,446 @@
+//! xar archive reader
+//!
+//! A xar archive is a header, a zlib-compressed XML table of contents and
+//! a heap. Each `<file>` element of the TOC names a file and, if it has
+//! contents, a `<data>` element giving its offset and length in the heap,
+//! its decoded size and its encoding. Directories are `<file>` elements
+//! containing further `<file>` elements.
+//!
+//! Files are exposed as children named by their path. Stored data is a
+//! slice of the heap; encoded data (zlib, bzip2, lzma or xz) is decoded
+//! when first read. Extended attributes are not exposed.
+
+use crate::container::disk::dmg::DmgReader;
+use crate::container::{
+    be16, be64, invalid_data, read_exact_at, read_to_end_limited, slice::SliceReader, Child,
+    Container, DeferredReader, Entry, LimitedBuffer, MAX_SIZE,
+};
+use crate::detect::Reader;
+use bzip2::read::MultiBzDecoder;
+use flate2::read::ZlibDecoder;
+use std::io;
+use std::sync::Arc;
+
+const MAGIC: &[u8; 4] = b"xar!";
+const HEADER_SIZE: usize = 28;
+const XZ_MAGIC: &[u8] = b"\xFD7zXZ\0";
+
+/// Deepest element nesting accepted in the TOC
+const MAX_DEPTH: usize = 256;
+
+/// xar container - exposes each file as a child
+pub struct XarContainer;
+
+/// Static instance for registry
+pub static XAR: XarContainer = XarContainer;
+
+impl Container for XarContainer {
+    fn children(&self, reader: Arc<dyn Reader + Send + Sync>) -> io::Result<Vec<Child>> {
+        self.entries(reader)?.into_iter().map(Entry::open).collect()
+    }
+
+    fn entries(&self, reader: Arc<dyn Reader + Send + Sync>) -> io::Result<Vec<Entry>> {
+        let (toc, heap) = read_toc(&*reader)?;
+        let toc = toc
+            .child("xar")
+            .and_then(|xar| xar.child("toc"))
+            .ok_or_else(|| invalid_data("missing xar TOC"))?;
+        let mut files = Vec::new();
+        collect_files(toc, "", 0, &mut files)?;
+
+        let len = reader.size().unwrap_or(u64::MAX);
+        let mut entries = Vec::new();
+        for (path, data) in files {
+            let member = Member::parse(data, heap)?;
+            if member.offset.saturating_add(member.length) > len {
+                return Err(invalid_data("xar data extends past end of archive"));
+            }
+            let parent = Arc::clone(&reader);
+            entries.push(Entry::new(
+                entries.len() as u32,
+                member.offset,
+                Some(member.size),
+                vec![("name", path)],
+                move || {
+                    let child: Arc<dyn Reader + Send + Sync> = if member.encoding == Encoding::None
+                    {
+                        Arc::new(SliceReader::new(parent, member.offset, member.length))
+                    } else {
+                        let size = member.size;
+                        Arc::new(DeferredReader::new(size, move || decode(&*parent, &member)))
+                    };
+                    Ok(child)
+                },
+            ));
+        }
+        Ok(entries)
+    }
+}
+
+/// Read and parse the TOC; returns it and the heap's offset
+fn read_toc(reader: &dyn Reader) -> io::Result<(Element, u64)> {
+    let mut header = [0u8; HEADER_SIZE];
+    read_exact_at(reader, 0, &mut header)?;
+    if &header[..4] != MAGIC {
+        return Err(invalid_data("invalid xar magic"));
+    }
+    let header_size = u64::from(be16(&header[4..]));
+    let toc_compressed = be64(&header[8..]);
+    let toc_size = be64(&header[16..]);
+    if header_size < HEADER_SIZE as u64
+        || toc_compressed > MAX_SIZE as u64
+        || toc_size > MAX_SIZE as u64
+    {
+        return Err(invalid_data("invalid xar header"));
+    }
+
+    let mut compressed = vec![0u8; toc_compressed as usize];
+    read_exact_at(reader, header_size, &mut compressed)?;
+    let xml = read_to_end_limited(ZlibDecoder::new(&compressed[..]))?;
+    if xml.len() as u64 != toc_size {
+        return Err(invalid_data("xar TOC size mismatch"));
+    }
+    let xml = String::from_utf8(xml).map_err(|_| invalid_data("xar TOC is not UTF-8"))?;
+    Ok((parse_xml(&xml)?, header_size + toc_compressed))
+}
+
+/// Gather (path, `<data>`) for every file with contents, in TOC order
+fn collect_files<'a>(
+    parent: &'a Element,
+    prefix: &str,
+    depth: usize,
+    files: &mut Vec<(String, &'a Element)>,
+) -> io::Result<()> {
+    if depth > MAX_DEPTH {
+        return Err(invalid_data("xar TOC nested too deeply"));
+    }
+    for file in parent.children_named("file") {
+        let name = file
+            .child("name")
+            .ok_or_else(|| invalid_data("xar file without a name"))?;
+        let name = if name.attr("enctype") == Some("base64") {
+            String::from_utf8_lossy(&DmgReader::decode_base64(&name.text)?).into_owned()
+        } else {
+            name.text.clone()
+        };
+        let path = format!("{prefix}{}", name.trim_matches('/'));
+        if let Some(data) = file.child("data") {
+            files.push((path.clone(), data));
+        }
+        collect_files(file, &format!("{path}/"), depth + 1, files)?;
+    }
+    Ok(())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Encoding {
+    None,
+    Zlib,
+    Bzip2,
+    /// liblzma's automatic decoder: xz or lzma-alone
+    Lzma,
+}
+
+#[derive(Debug)]
+struct Member {
+    encoding: Encoding,
+    offset: u64,
+    length: u64,
+    size: u64,
+}
+
+fn number(data: &Element, name: &str) -> io::Result<u64> {
+    data.child(name)
+        .and_then(|e| e.text.trim().parse().ok())
+        .ok_or_else(|| invalid_data("invalid xar data element"))
+}
+
+impl Member {
+    fn parse(data: &Element, heap: u64) -> io::Result<Self> {
+        let encoding = match data.child("encoding").and_then(|e| e.attr("style")) {
+            None | Some("application/octet-stream") => Encoding::None,
+            Some("application/x-gzip") => Encoding::Zlib,
+            Some("application/x-bzip2") => Encoding::Bzip2,
+            Some("application/x-lzma") | Some("application/x-xz") => Encoding::Lzma,
+            Some(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "unsupported xar encoding",
+                ))
+            }
+        };
+        let member = Self {
+            encoding,
+            offset: heap
+                .checked_add(number(data, "offset")?)
+                .ok_or_else(|| invalid_data("invalid xar data element"))?,
+            length: number(data, "length")?,
+            size: number(data, "size")?,
+        };
+        if member.encoding == Encoding::None && member.length != member.size {
+            return Err(invalid_data("xar stored data size mismatch"));
+        }
+        Ok(member)
+    }
+}
+
+fn decode(reader: &dyn Reader, member: &Member) -> io::Result<Vec<u8>> {
+    if member.length > MAX_SIZE as u64 || member.size > MAX_SIZE as u64 {
+        return Err(invalid_data("xar file too large"));
+    }
+    let mut data = vec![0u8; member.length as usize];
+    read_exact_at(reader, member.offset, &mut data)?;
+
+    let out = match member.encoding {
+        Encoding::None => data,
+        Encoding::Zlib => read_to_end_limited(ZlibDecoder::new(&data[..]))?,
+        Encoding::Bzip2 => read_to_end_limited(MultiBzDecoder::new(&data[..]))?,
+        Encoding::Lzma => {
+            let mut out = LimitedBuffer::new();
+            if data.starts_with(XZ_MAGIC) {
+                lzma_rs::xz_decompress(&mut &data[..], &mut out)
+            } else {
+                lzma_rs::lzma_decompress(&mut &data[..], &mut out)
+            }
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+            out.into_inner()
+        }
+    };
+    if out.len() as u64 != member.size {
+        return Err(invalid_data("xar file size mismatch"));
+    }
+    Ok(out)
+}
+
+/// An XML element, with its text content concatenated
+#[derive(Debug, Default)]
+struct Element {
+    name: String,
+    attrs: Vec<(String, String)>,
+    children: Vec<Element>,
+    text: String,
+}
+
+impl Element {
+    fn child<'a>(&'a self, name: &'a str) -> Option<&'a Element> {
+        self.children_named(name).next()
+    }
+
+    fn children_named<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Element> {
+        self.children.iter().filter(move |e| e.name == name)
+    }
+
+    fn attr(&self, name: &str) -> Option<&str> {
+        self.attrs
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// Parse enough XML for a xar TOC: elements, attributes, text, entity
+/// and character references and CDATA. Returns a nameless document
+/// element holding the root element.
+fn parse_xml(xml: &str) -> io::Result<Element> {
+    let malformed = || invalid_data("malformed xar TOC");
+    let mut stack = vec![Element::default()];
+    let mut rest = xml;
+    while let Some(lt) = rest.find('<') {
+        let text = &rest[..lt];
+        stack.last_mut().unwrap().text += &unescape(text);
+        rest = &rest[lt..];
+
+        if let Some(after) = rest.strip_prefix("<![CDATA[") {
+            let end = after.find("]]>").ok_or_else(malformed)?;
+            stack.last_mut().unwrap().text += &after[..end];
+            rest = &after[end + 3..];
+            continue;
+        }
+        let skip = [("<?", "?>"), ("<!--", "-->"), ("<!", ">")]
+            .into_iter()
+            .find(|(start, _)| rest.starts_with(start));
+        if let Some((_, end)) = skip {
+            let at = rest.find(end).ok_or_else(malformed)?;
+            rest = &rest[at + end.len()..];
+            continue;
+        }
+
+        // Find the tag's end, allowing '>' inside quoted attributes
+        let mut quote = None;
+        let end = rest
+            .char_indices()
+            .find(|&(_, c)| match quote {
+                Some(q) if c == q => {
+                    quote = None;
+                    false
+                }
+                Some(_) => false,
+                None if c == '"' || c == '\'' => {
+                    quote = Some(c);
+                    false
+                }
+                None => c == '>',
+            })
+            .map(|(i, _)| i)
+            .ok_or_else(malformed)?;
+        let tag = &rest[1..end];
+        rest = &rest[end + 1..];
+
+        if let Some(name) = tag.strip_prefix('/') {
+            let element = stack.pop().filter(|_| !stack.is_empty());
+            match element {
+                Some(element) if element.name == name.trim() => {
+                    stack.last_mut().unwrap().children.push(element)
+                }
+                _ => return Err(malformed()),
+            }
+            continue;
+        }
+
+        let (tag, empty) = match tag.strip_suffix('/') {
+            Some(tag) => (tag, true),
+            None => (tag, false),
+        };
+        let element = parse_tag(tag).ok_or_else(malformed)?;
+        if empty {
+            stack.last_mut().unwrap().children.push(element);
+        } else if stack.len() > MAX_DEPTH {
+            return Err(invalid_data("xar TOC nested too deeply"));
+        } else {
+            stack.push(element);
+        }
+    }
+    match stack.pop() {
+        Some(document) if stack.is_empty() => Ok(document),
+        _ => Err(malformed()),
+    }
+}
+
+/// Split a start tag into its name and attributes
+fn parse_tag(tag: &str) -> Option<Element> {
+    let tag = tag.trim();
+    let name_end = tag.find(char::is_whitespace).unwrap_or(tag.len());
+    let mut element = Element {
+        name: tag[..name_end].to_string(),
+        ..Element::default()
+    };
+    let mut rest = tag[name_end..].trim_start();
+    while !rest.is_empty() {
+        let eq = rest.find('=')?;
+        let name = rest[..eq].trim().to_string();
+        let value = rest[eq + 1..].trim_start();
+        let quote = value.chars().next().filter(|&c| c == '"' || c == '\'')?;
+        let end = value[1..].find(quote)? + 1;
+        element.attrs.push((name, unescape(&value[1..end])));
+        rest = value[end + 1..].trim_start();
+    }
+    Some(element)
+}
+
+/// Replace entity and character references
+fn unescape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(amp) = rest.find('&') {
+        out += &rest[..amp];
+        rest = &rest[amp..];
+        let Some(semi) = rest.find(';') else { break };
+        let entity = &rest[1..semi];
+        let c = match entity {
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "amp" => Some('&'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => entity
+                .strip_prefix("#x")
+                .map(|hex| u32::from_str_radix(hex, 16))
+                .or_else(|| entity.strip_prefix('#').map(str::parse))
+                .and_then(Result::ok)
+                .and_then(char::from_u32),
+        };
+        match c {
+            Some(c) => {
+                out.push(c);
+                rest = &rest[semi + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out + rest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::BytesReader;
+    use flate2::write::ZlibEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    fn zlib(data: &[u8]) -> Vec<u8> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn archive(toc: &str, heap: &[u8]) -> Vec<u8> {
+        let compressed = zlib(toc.as_bytes());
+        let mut out = MAGIC.to_vec();
+        out.extend_from_slice(&(HEADER_SIZE as u16).to_be_bytes());
+        out.extend_from_slice(&1u16.to_be_bytes());
+        out.extend_from_slice(&(compressed.len() as u64).to_be_bytes());
+        out.extend_from_slice(&(toc.len() as u64).to_be_bytes());
+        out.extend_from_slice(&1u32.to_be_bytes());
+        out.extend(compressed);
+        out.extend_from_slice(heap);
+        out
+    }
+
+    #[test]
+    fn parses_xml() {
+        let doc = parse_xml(
+            "<?xml version=\"1.0\"?>\n<!-- c --><a x='1 > 0'><b>T &amp; &#x41;</b><c/>\
+             <![CDATA[<raw>]]></a>",
+        )
+        .unwrap();
+        let a = doc.child("a").unwrap();
+        assert_eq!(a.attr("x"), Some("1 > 0"));
+        assert_eq!(a.child("b").unwrap().text, "T & A");
+        assert!(a.child("c").is_some());
+        assert_eq!(a.text, "<raw>");
+        assert!(parse_xml("<a><b></a>").is_err());
+    }
+
+    #[test]
+    fn reads_files() {
+        let packed = zlib(b"compressed contents");
+        let mut heap = b"plain".to_vec();
+        heap.extend_from_slice(&packed);
+        let toc = format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<xar><toc>\
+             <file id=\"1\"><name>dir</name><type>directory</type>\
+             <file id=\"2\"><name>a &amp; b.txt</name><type>file</type><data>\
+             <length>5</length><offset>0</offset><size>5</size>\
+             <encoding style=\"application/octet-stream\"/></data></file>\
+             </file>\
+             <file id=\"3\"><name enctype=\"base64\">ei5neg==</name><data>\
+             <length>{}</length><offset>5</offset><size>19</size>\
+             <encoding style=\"application/x-gzip\"/></data></file>\
+             </toc></xar>",
+            packed.len()
+        );
+        let xar = archive(&toc, &heap);
+        let heap_start = (xar.len() - heap.len()) as u64;
+        let children = XAR.children(Arc::new(BytesReader::new(xar))).unwrap();
+        let names: Vec<_> = children.iter().map(|c| c.metadata[0].1.as_str()).collect();
+        assert_eq!(names, ["dir/a & b.txt", "z.gz"]);
+        assert_eq!(children[1].offset, heap_start + 5);
+        let mut buf = [0u8; 19];
+        children[1].reader.read_at(0, &mut buf).unwrap();
+        assert_eq!(&buf, b"compressed contents");
+    }
+}
@@ -1,12 +1,45 @@
 //! XZ container reader
 //!
-//! XZ is a compression wrapper containing a single decompressed stream.
+//! XZ is a compression wrapper containing a single decompressed stream,
+//! possibly split across several concatenated xz streams (pixz, `cat`).
+//! Every stream ends with an index of its blocks, so files written with
+//! `--block-size` (or by multi-threaded xz) can be read at random, one
+//! block at a time. Files whose blocks are too large to cache, or that use
+//! filters we don't implement, are decompressed whole.
 
-use crate::container::{read_all, BytesReader, Child, Container, LimitedBuffer};
+use super::bcj;
+use crate::container::block::{Block, BlockReader, MAX_BLOCK_SIZE};
+use crate::container::{
+    checked_table_size, invalid_data, read_all, read_exact_at, BytesReader, Child, Container,
+    LimitedBuffer,
+};
 use crate::detect::Reader;
+use flate2::Crc;
 use std::io;
 use std::sync::Arc;
 
+const HEADER_MAGIC: [u8; 6] = [0xFD, b'7', b'z', b'X', b'Z', 0x00];
+const FOOTER_MAGIC: [u8; 2] = *b"YZ";
+const STREAM_HEADER_SIZE: u64 = 12;
+const STREAM_FOOTER_SIZE: u64 = 12;
+
+/// Check sizes by check ID (CRC32 = 1, CRC64 = 4, SHA-256 = 10)
+const CHECK_SIZES: [u64; 16] = [0, 4, 4, 4, 8, 8, 8, 16, 16, 16, 32, 32, 32, 64, 64, 64];
+const CHECK_CRC32: u8 = 1;
+
+const FILTER_DELTA: u64 = 0x03;
+const FILTER_X86: u64 = 0x04;
+const FILTER_PPC: u64 = 0x05;
+const FILTER_ARM: u64 = 0x07;
+const FILTER_ARMT: u64 = 0x08;
+const FILTER_SPARC: u64 = 0x09;
+const FILTER_LZMA2: u64 = 0x21;
+
+const BLOCK_FLAG_FILTERS: u8 = 0x03;
+const BLOCK_FLAG_RESERVED: u8 = 0x3C;
+const BLOCK_FLAG_COMPRESSED_SIZE: u8 = 0x40;
+const BLOCK_FLAG_UNCOMPRESSED_SIZE: u8 = 0x80;
+
 /// XZ container - decompresses content to expose inner stream
 pub struct XzContainer;
 
@@ -15,16 +48,495 @@ pub static XZ: XzContainer = XzContainer;
 
 impl Container for XzContainer {
     fn children(&self, reader: Arc<dyn Reader + Send + Sync>) -> io::Result<Vec<Child>> {
-        let compressed = read_all(&*reader)?;
-        let mut decompressed = LimitedBuffer::new();
-
-        lzma_rs::xz_decompress(&mut &compressed[..], &mut decompressed)
-            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+        let child: Arc<dyn Reader + Send + Sync> = match block_reader(&reader) {
+            Ok(block_reader) => Arc::new(block_reader),
+            Err(_) => Arc::new(BytesReader::new(decompress_all(&*reader)?)),
+        };
 
         Ok(vec![Child {
             index: 0,
             offset: u64::MAX, // Transformed data, not a slice
-            reader: Arc::new(BytesReader::new(decompressed.into_inner())),
+            reader: child,
+            metadata: Vec::new(),
         }])
     }
 }
+
+/// Decompress every stream in order; lzma-rs stops at the first stream
+/// end, so streams are split using their indexes when those parse
+fn decompress_all(reader: &dyn Reader) -> io::Result<Vec<u8>> {
+    let compressed = read_all(reader)?;
+    let ranges: Vec<(usize, usize)> = match parse_index(reader) {
+        Ok(streams) => streams
+            .iter()
+            .map(|s| (s.start as usize, s.end as usize))
+            .collect(),
+        Err(_) => vec![(0, compressed.len())],
+    };
+
+    let mut decompressed = LimitedBuffer::new();
+    for (start, end) in ranges {
+        lzma_rs::xz_decompress(&mut &compressed[start..end], &mut decompressed)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+    }
+    Ok(decompressed.into_inner())
+}
+
+/// How to decode one block, from its header
+#[derive(Debug, PartialEq)]
+struct XzBlock {
+    header_size: usize,
+    data_size: usize,
+    check: u8,
+    /// Filters before LZMA2, in header order (applied in reverse to decode)
+    filters: Vec<(u64, Vec<u8>)>,
+}
+
+/// Index record: where a block is and how big it is
+struct Record {
+    offset: u64,
+    unpadded: u64,
+    uncompressed: u64,
+    check: u8,
+}
+
+/// One xz stream: its extent in the file and its block records
+struct Stream {
+    start: u64,
+    end: u64,
+    records: Vec<Record>,
+}
+
+fn round_up4(n: u64) -> u64 {
+    n.div_ceil(4) * 4
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = Crc::new();
+    crc.update(data);
+    crc.sum()
+}
+
+/// Read a multibyte integer (7 bits per byte, little-endian, at most 9 bytes)
+fn vli(data: &[u8], pos: &mut usize) -> io::Result<u64> {
+    let mut value = 0u64;
+    for i in 0..9 {
+        let b = *data
+            .get(*pos)
+            .ok_or_else(|| invalid_data("truncated xz integer"))?;
+        *pos += 1;
+        value |= u64::from(b & 0x7F) << (i * 7);
+        if b & 0x80 == 0 {
+            if b == 0 && i > 0 {
+                return Err(invalid_data("non-minimal xz integer"));
+            }
+            return Ok(value);
+        }
+    }
+    Err(invalid_data("xz integer too long"))
+}
+
+/// Walk streams backwards from the end of the file, collecting index records
+fn parse_index(reader: &dyn Reader) -> io::Result<Vec<Stream>> {
+    let mut end = reader
+        .size()
+        .ok_or_else(|| invalid_data("xz size unknown"))?;
+    let mut streams = Vec::new();
+
+    while end > 0 {
+        // Stream padding is a multiple of four zero bytes
+        let mut word = [0u8; 4];
+        while end >= 4 {
+            read_exact_at(reader, end - 4, &mut word)?;
+            if word != [0; 4] {
+                break;
+            }
+            end -= 4;
+        }
+        if end < STREAM_HEADER_SIZE + STREAM_FOOTER_SIZE {
+            return Err(invalid_data("xz stream too short"));
+        }
+
+        let mut footer = [0u8; STREAM_FOOTER_SIZE as usize];
+        read_exact_at(reader, end - STREAM_FOOTER_SIZE, &mut footer)?;
+        if footer[10..] != FOOTER_MAGIC
+            || crc32(&footer[4..10])
+                != u32::from_le_bytes([footer[0], footer[1], footer[2], footer[3]])
+        {
+            return Err(invalid_data("invalid xz stream footer"));
+        }
+        let flags = [footer[8], footer[9]];
+        let check = flags[1] & 0x0F;
+        let index_size = (u64::from(u32::from_le_bytes([
+            footer[4], footer[5], footer[6], footer[7],
+        ])) + 1)
+            * 4;
+        let index_start = (end - STREAM_FOOTER_SIZE)
+            .checked_sub(index_size)
+            .ok_or_else(|| invalid_data("xz index before start of file"))?;
+
+        let mut index = vec![0u8; checked_table_size(reader, index_start, index_size, 1)?];
+        read_exact_at(reader, index_start, &mut index)?;
+        let crc_at = index.len() - 4;
+        if index.len() < 8
+            || index[0] != 0
+            || crc32(&index[..crc_at])
+                != u32::from_le_bytes([
+                    index[crc_at],
+                    index[crc_at + 1],
+                    index[crc_at + 2],
+                    index[crc_at + 3],
+                ])
+        {
+            return Err(invalid_data("invalid xz index"));
+        }
+
+        let mut pos = 1;
+        let count = vli(&index, &mut pos)?;
+        let mut sizes = Vec::new();
+        let mut blocks_size = 0u64;
+        for _ in 0..count {
+            let unpadded = vli(&index, &mut pos)?;
+            let uncompressed = vli(&index, &mut pos)?;
+            if pos > crc_at {
+                return Err(invalid_data("xz index records overrun"));
+            }
+            blocks_size = blocks_size
+                .checked_add(round_up4(unpadded))
+                .ok_or_else(|| invalid_data("xz index size overflow"))?;
+            sizes.push((unpadded, uncompressed));
+        }
+        if index[pos..crc_at].iter().any(|&b| b != 0) {
+            return Err(invalid_data("invalid xz index padding"));
+        }
+
+        let stream_start = index_start
+            .checked_sub(blocks_size)
+            .and_then(|offset| offset.checked_sub(STREAM_HEADER_SIZE))
+            .ok_or_else(|| invalid_data("xz blocks before start of file"))?;
+        let mut header = [0u8; STREAM_HEADER_SIZE as usize];
+        read_exact_at(reader, stream_start, &mut header)?;
+        if header[..6] != HEADER_MAGIC || header[6..8] != flags {
+            return Err(invalid_data("invalid xz stream header"));
+        }
+
+        let mut offset = stream_start + STREAM_HEADER_SIZE;
+        let records: Vec<Record> = sizes
+            .into_iter()
+            .map(|(unpadded, uncompressed)| {
+                let record = Record {
+                    offset,
+                    unpadded,
+                    uncompressed,
+                    check,
+                };
+                offset += round_up4(unpadded);
+                record
+            })
+            .collect();
+        streams.push(Stream {
+            start: stream_start,
+            end,
+            records,
+        });
+        end = stream_start;
+    }
+
+    streams.reverse();
+    Ok(streams)
+}
+
+/// Read and validate a block header
+fn parse_block_header(reader: &dyn Reader, record: &Record) -> io::Result<XzBlock> {
+    let mut size_byte = [0u8; 1];
+    read_exact_at(reader, record.offset, &mut size_byte)?;
+    if size_byte[0] == 0 {
+        return Err(invalid_data("xz index indicator where block expected"));
+    }
+    let header_size = (usize::from(size_byte[0]) + 1) * 4;
+    let mut header = vec![0u8; header_size];
+    read_exact_at(reader, record.offset, &mut header)?;
+
+    let crc_at = header_size - 4;
+    let stored = u32::from_le_bytes([
+        header[crc_at],
+        header[crc_at + 1],
+        header[crc_at + 2],
+        header[crc_at + 3],
+    ]);
+    if crc32(&header[..crc_at]) != stored {
+        return Err(invalid_data("xz block header CRC mismatch"));
+    }
+
+    let flags = header[1];
+    if flags & BLOCK_FLAG_RESERVED != 0 {
+        return Err(invalid_data("unsupported xz block flags"));
+    }
+    let mut pos = 2;
+    if flags & BLOCK_FLAG_COMPRESSED_SIZE != 0 {
+        vli(&header[..crc_at], &mut pos)?;
+    }
+    if flags & BLOCK_FLAG_UNCOMPRESSED_SIZE != 0 {
+        vli(&header[..crc_at], &mut pos)?;
+    }
+
+    let count = usize::from(flags & BLOCK_FLAG_FILTERS) + 1;
+    let mut filters = Vec::with_capacity(count);
+    for _ in 0..count {
+        let id = vli(&header[..crc_at], &mut pos)?;
+        let props_size = vli(&header[..crc_at], &mut pos)? as usize;
+        let props = header[..crc_at]
+            .get(pos..pos + props_size)
+            .ok_or_else(|| invalid_data("truncated xz filter properties"))?
+            .to_vec();
+        pos += props_size;
+        filters.push((id, props));
+    }
+
+    // LZMA2 must come last; only filters we can undo may precede it
+    if filters.pop().map(|(id, _)| id) != Some(FILTER_LZMA2) {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "xz block does not end with LZMA2",
+        ));
+    }
+    for (id, props) in &filters {
+        let supported = match *id {
+            FILTER_DELTA => props.len() == 1,
+            // A start offset other than zero is legal but never used in practice
+            FILTER_X86 | FILTER_PPC | FILTER_ARM | FILTER_ARMT | FILTER_SPARC => {
+                props.iter().all(|&b| b == 0)
+            }
+            _ => false,
+        };
+        if !supported {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "unsupported xz filter",
+            ));
+        }
+    }
+
+    let check_size = CHECK_SIZES[record.check as usize];
+    let data_size = record
+        .unpadded
+        .checked_sub(header_size as u64 + check_size)
+        .ok_or_else(|| invalid_data("xz block smaller than its header"))?;
+
+    Ok(XzBlock {
+        header_size,
+        data_size: data_size as usize,
+        check: record.check,
+        filters,
+    })
+}
+
+/// Decode a whole block: header, LZMA2 data, padding and check
+fn decode_block(block: &XzBlock, raw: &[u8]) -> io::Result<Vec<u8>> {
+    let data = &raw[block.header_size..block.header_size + block.data_size];
+    let mut output = LimitedBuffer::new();
+    lzma_rs::lzma2_decompress(&mut &data[..], &mut output)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+    let mut output = output.into_inner();
+
+    for (id, props) in block.filters.iter().rev() {
+        match *id {
+            FILTER_DELTA => {
+                let distance = usize::from(props[0]) + 1;
+                for i in distance..output.len() {
+                    output[i] = output[i].wrapping_add(output[i - distance]);
+                }
+            }
+            FILTER_X86 => bcj::x86(&mut output, false),
+            FILTER_PPC => bcj::ppc(&mut output, false),
+            FILTER_ARM => bcj::arm(&mut output, false),
+            FILTER_ARMT => bcj::armt(&mut output, false),
+            FILTER_SPARC => bcj::sparc(&mut output, false),
+            _ => unreachable!("filters are validated when the header is parsed"),
+        }
+    }
+
+    if block.check == CHECK_CRC32 {
+        let at = round_up4((block.header_size + block.data_size) as u64) as usize;
+        let stored = raw
+            .get(at..at + 4)
+            .ok_or_else(|| invalid_data("truncated xz block check"))?;
+        if crc32(&output) != u32::from_le_bytes([stored[0], stored[1], stored[2], stored[3]]) {
+            return Err(invalid_data("xz block CRC mismatch"));
+        }
+    }
+    Ok(output)
+}
+
+/// Build a block-at-a-time reader from the stream indexes
+fn block_reader(reader: &Arc<dyn Reader + Send + Sync>) -> io::Result<BlockReader> {
+    let records: Vec<Record> = parse_index(&**reader)?
+        .into_iter()
+        .flat_map(|stream| stream.records)
+        .collect();
+    if records.iter().any(|r| r.uncompressed > MAX_BLOCK_SIZE) {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "xz blocks too large for random access",
+        ));
+    }
+
+    let mut blocks = Vec::with_capacity(records.len());
+    let mut headers = Vec::with_capacity(records.len());
+    let mut start = 0u64;
+    for record in &records {
+        let header = parse_block_header(&**reader, record)?;
+        let check_size = CHECK_SIZES[record.check as usize];
+        blocks.push(Block {
+            offset: record.offset,
+            compressed_size: round_up4(record.unpadded - check_size) + check_size,
+            start,
+            size: record.uncompressed,
+        });
+        headers.push(header);
+        start += record.uncompressed;
+    }
+
+    BlockReader::new(Arc::clone(reader), blocks, move |index, raw| {
+        decode_block(&headers[index], raw)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn put_vli(out: &mut Vec<u8>, mut value: u64) {
+        while value >= 0x80 {
+            out.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        out.push(value as u8);
+    }
+
+    fn pad4(out: &mut Vec<u8>) {
+        while !out.len().is_multiple_of(4) {
+            out.push(0);
+        }
+    }
+
+    /// Build an xz stream with CRC32 checks whose blocks hold stored
+    /// (uncompressed) LZMA2 chunks, optionally behind a delta filter
+    fn xz_stream(chunks: &[&[u8]], delta: bool) -> Vec<u8> {
+        let flags = [0x00, CHECK_CRC32];
+        let mut out = HEADER_MAGIC.to_vec();
+        out.extend_from_slice(&flags);
+        out.extend_from_slice(&crc32(&flags).to_le_bytes());
+
+        let mut records = Vec::new();
+        for chunk in chunks {
+            let mut encoded = chunk.to_vec();
+            if delta {
+                for i in (1..encoded.len()).rev() {
+                    encoded[i] = encoded[i].wrapping_sub(encoded[i - 1]);
+                }
+            }
+
+            let mut header = vec![0u8, if delta { 1 } else { 0 }];
+            if delta {
+                header.extend_from_slice(&[FILTER_DELTA as u8, 1, 0]);
+            }
+            header.extend_from_slice(&[FILTER_LZMA2 as u8, 1, 16]);
+            pad4(&mut header);
+            header[0] = (header.len() / 4) as u8;
+            let crc = crc32(&header);
+            header.extend_from_slice(&crc.to_le_bytes());
+
+            let block_start = out.len();
+            out.extend_from_slice(&header);
+            out.push(0x01); // uncompressed chunk, dictionary reset
+            out.extend_from_slice(&((encoded.len() - 1) as u16).to_be_bytes());
+            out.extend_from_slice(&encoded);
+            out.push(0x00); // end of LZMA2 data
+            let unpadded = out.len() - block_start + 4;
+            pad4(&mut out);
+            out.extend_from_slice(&crc32(chunk).to_le_bytes());
+            records.push((unpadded as u64, chunk.len() as u64));
+        }
+
+        let index_start = out.len();
+        out.push(0);
+        put_vli(&mut out, records.len() as u64);
+        for &(unpadded, uncompressed) in &records {
+            put_vli(&mut out, unpadded);
+            put_vli(&mut out, uncompressed);
+        }
+        pad4(&mut out);
+        let crc = crc32(&out[index_start..]);
+        out.extend_from_slice(&crc.to_le_bytes());
+        let index_size = out.len() - index_start;
+
+        let mut footer = ((index_size / 4 - 1) as u32).to_le_bytes().to_vec();
+        footer.extend_from_slice(&flags);
+        out.extend_from_slice(&crc32(&footer).to_le_bytes());
+        out.extend_from_slice(&footer);
+        out.extend_from_slice(&FOOTER_MAGIC);
+        out
+    }
+
+    fn contents(reader: &dyn Reader, offset: u64, len: usize) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut buf = vec![0u8; len];
+        while out.len() < len {
+            let n = reader
+                .read_at(offset + out.len() as u64, &mut buf[..len - out.len()])
+                .unwrap();
+            if n == 0 {
+                break;
+            }
+            out.extend_from_slice(&buf[..n]);
+        }
+        out
+    }
+
+    #[test]
+    fn reads_across_blocks_and_streams() {
+        let mut data = xz_stream(&[b"first block ", b"second block "], false);
+        data.extend_from_slice(&[0; 8]); // stream padding
+        data.extend(xz_stream(&[b"third, delta coded"], true));
+        let reader: Arc<dyn Reader + Send + Sync> = Arc::new(BytesReader::new(data));
+
+        let blocks = block_reader(&reader).unwrap();
+        let expected = b"first block second block third, delta coded";
+        assert_eq!(blocks.size(), Some(expected.len() as u64));
+        assert_eq!(contents(&blocks, 0, expected.len()), expected);
+        assert_eq!(contents(&blocks, 20, 10), &expected[20..30]);
+    }
+
+    #[test]
+    fn reads_lzma_rs_output() {
+        let mut compressed = Vec::new();
+        lzma_rs::xz_compress(&mut &b"hello from one block"[..], &mut compressed).unwrap();
+        let kids = XZ.children(Arc::new(BytesReader::new(compressed))).unwrap();
+        assert_eq!(contents(&*kids[0].reader, 0, 64), b"hello from one block");
+    }
+
+    #[test]
+    fn decompresses_concatenated_streams_whole() {
+        let mut data = Vec::new();
+        for part in [&b"streams too big "[..], b"for random access"] {
+            lzma_rs::xz_compress(&mut &part[..], &mut data).unwrap();
+        }
+        data.extend_from_slice(&[0; 4]); // stream padding
+        let reader = BytesReader::new(data);
+        assert_eq!(parse_index(&reader).unwrap().len(), 2);
+        assert_eq!(
+            decompress_all(&reader).unwrap(),
+            b"streams too big for random access"
+        );
+    }
+
+    #[test]
+    fn detects_corrupt_block() {
+        let mut data = xz_stream(&[b"some data here"], false);
+        data[30] ^= 0xFF;
+        let reader: Arc<dyn Reader + Send + Sync> = Arc::new(BytesReader::new(data));
+        let blocks = block_reader(&reader).unwrap();
+        assert!(blocks.read_at(0, &mut [0u8; 4]).is_err());
+    }
+}
@@ -0,0 +1,345 @@
+//! zoo archive reader
+//!
+//! A zoo archive is a chain of directory entries, each pointing at its
+//! member's data and at the next entry; the chain ends at an entry whose
+//! next pointer is zero. Type 2 entries add a variable part with the long
+//! name and directory. Deleted entries stay in the chain and are skipped.
+//!
+//! Members are stored, LZW coded (9 to 13-bit codes, LSB first, with no
+//! group padding, unlike compress) or, from zoo 2.1, coded with LHA's
+//! -lh5- method. Compressed members are decoded when first read and
+//! checked against their CRC-16.
+
+use super::lzh::decode_static;
+use super::sea::crc16;
+use crate::container::{
+    invalid_data, le16, le32, read_exact_at, slice::SliceReader, Child, Container, DeferredReader,
+    Entry, MAX_SIZE,
+};
+use crate::detect::Reader;
+use std::io;
+use std::sync::Arc;
+
+const TAG: u32 = 0xFDC4_A7DC;
+const ARCHIVE_HEADER_SIZE: usize = 34;
+const ENTRY_SIZE: usize = 56;
+const NAME_SIZE: usize = 13;
+
+const METHOD_STORED: u8 = 0;
+const METHOD_LZW: u8 = 1;
+const METHOD_LZH: u8 = 2;
+
+const LZW_MIN_BITS: u32 = 9;
+const LZW_MAX_BITS: u32 = 13;
+const LZW_CLEAR: usize = 256;
+const LZW_EOF: usize = 257;
+const LZW_FIRST: usize = 258;
+
+/// Directory entries to follow before assuming the chain loops
+const MAX_ENTRIES: usize = 1 << 16;
+
+/// zoo container - exposes each member as a child
+pub struct ZooContainer;
+
+/// Static instance for registry
+pub static ZOO: ZooContainer = ZooContainer;
+
+impl Container for ZooContainer {
+    fn children(&self, reader: Arc<dyn Reader + Send + Sync>) -> io::Result<Vec<Child>> {
+        self.entries(reader)?.into_iter().map(Entry::open).collect()
+    }
+
+    fn entries(&self, reader: Arc<dyn Reader + Send + Sync>) -> io::Result<Vec<Entry>> {
+        let members = parse(&*reader)?;
+        let mut entries = Vec::new();
+        for (index, member) in members.into_iter().enumerate() {
+            let parent = Arc::clone(&reader);
+            let (offset, size) = (member.offset, u64::from(member.size));
+            let metadata = vec![("name", member.name.clone())];
+            entries.push(Entry::new(
+                index as u32,
+                offset,
+                Some(size),
+                metadata,
+                move || {
+                    let child: Arc<dyn Reader + Send + Sync> = if member.method == METHOD_STORED {
+                        let packed = u64::from(member.packed);
+                        Arc::new(SliceReader::new(parent, offset, packed))
+                    } else {
+                        Arc::new(DeferredReader::new(size, move || decode(&*parent, &member)))
+                    };
+                    Ok(child)
+                },
+            ));
+        }
+        Ok(entries)
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Member {
+    name: String,
+    method: u8,
+    offset: u64,
+    packed: u32,
+    size: u32,
+    crc: u16,
+}
+
+/// A NUL-terminated name field
+fn c_string(bytes: &[u8]) -> String {
+    let bytes = &bytes[..bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len())];
+    String::from_utf8_lossy(bytes).replace('\\', "/")
+}
+
+fn parse(reader: &dyn Reader) -> io::Result<Vec<Member>> {
+    let len = reader.size().unwrap_or(u64::MAX);
+    let mut header = [0u8; ARCHIVE_HEADER_SIZE];
+    read_exact_at(reader, 0, &mut header)?;
+    if le32(&header[20..]) != TAG {
+        return Err(invalid_data("missing zoo tag"));
+    }
+
+    let mut members = Vec::new();
+    let mut next = u64::from(le32(&header[24..]));
+    for _ in 0..MAX_ENTRIES {
+        let mut entry = [0u8; ENTRY_SIZE];
+        read_exact_at(reader, next, &mut entry)?;
+        if le32(&entry) != TAG {
+            return Err(invalid_data("missing zoo directory entry tag"));
+        }
+        let following = u64::from(le32(&entry[6..]));
+        if following == 0 {
+            return Ok(members);
+        }
+
+        let kind = entry[4];
+        let method = entry[5];
+        let offset = u64::from(le32(&entry[10..]));
+        let packed = le32(&entry[24..]);
+        if offset + u64::from(packed) > len {
+            return Err(invalid_data("zoo member extends past end of archive"));
+        }
+
+        let mut name = c_string(&entry[38..38 + NAME_SIZE]);
+        if kind == 2 {
+            // Variable part: name and directory lengths, then both
+            let var_len = usize::from(le16(&entry[51..]));
+            if var_len >= 2 {
+                let mut var = vec![0u8; var_len];
+                read_exact_at(reader, next + ENTRY_SIZE as u64, &mut var)?;
+                let name_len = usize::from(var[0]);
+                let dir_len = usize::from(var[1]);
+                if 2 + name_len + dir_len > var_len {
+                    return Err(invalid_data("invalid zoo variable entry"));
+                }
+                if name_len > 0 {
+                    name = c_string(&var[2..2 + name_len]);
+                }
+                let dir = c_string(&var[2 + name_len..2 + name_len + dir_len]);
+                let dir = dir.trim_matches('/');
+                if !dir.is_empty() {
+                    name = format!("{dir}/{name}");
+                }
+            }
+        }
+
+        // Deleted entries keep their place in the chain
+        if entry[30] == 0 {
+            members.push(Member {
+                name,
+                method,
+                offset,
+                packed,
+                size: le32(&entry[20..]),
+                crc: le16(&entry[18..]),
+            });
+        }
+        next = following;
+    }
+    Err(invalid_data("too many zoo directory entries"))
+}
+
+fn decode(reader: &dyn Reader, member: &Member) -> io::Result<Vec<u8>> {
+    let size = member.size as usize;
+    if member.packed as usize > MAX_SIZE || size > MAX_SIZE {
+        return Err(invalid_data("zoo member too large"));
+    }
+    let mut data = vec![0u8; member.packed as usize];
+    read_exact_at(reader, member.offset, &mut data)?;
+
+    let out = match member.method {
+        METHOD_LZW => lzw(&data, size)?,
+        // zoo's lh5 has an 8 KiB window but codes distances like -lh5-
+        METHOD_LZH => decode_static(&data, size, 14, 4)?,
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "unsupported zoo compression method",
+            ))
+        }
+    };
+    if out.len() != size {
+        return Err(invalid_data("zoo member size mismatch"));
+    }
+    if crc16(&out) != member.crc {
+        return Err(invalid_data("zoo member CRC mismatch"));
+    }
+    Ok(out)
+}
+
+/// Decode zoo's LZW, stopping at its end code or at `size` bytes
+fn lzw(data: &[u8], size: usize) -> io::Result<Vec<u8>> {
+    let table_size = 1 << LZW_MAX_BITS;
+    let mut prefix = vec![0u16; table_size];
+    let mut suffix = vec![0u8; table_size];
+    let mut stack = Vec::new();
+    let mut out = Vec::with_capacity(size);
+
+    let mut n_bits = LZW_MIN_BITS;
+    let mut free = LZW_FIRST;
+    let mut prev: Option<usize> = None;
+    let mut first_byte = 0u8;
+    let mut pos = 0usize;
+
+    while out.len() < size {
+        if pos + n_bits as usize > data.len() * 8 {
+            return Err(invalid_data("truncated zoo LZW data"));
+        }
+        let mut word = 0u32;
+        for (i, &b) in data[pos / 8..].iter().take(3).enumerate() {
+            word |= u32::from(b) << (i * 8);
+        }
+        let code = ((word >> (pos % 8)) & ((1 << n_bits) - 1)) as usize;
+        pos += n_bits as usize;
+
+        match code {
+            LZW_EOF => break,
+            LZW_CLEAR => {
+                n_bits = LZW_MIN_BITS;
+                free = LZW_FIRST;
+                prev = None;
+                continue;
+            }
+            _ => {}
+        }
+
+        let mut walk = code;
+        if code >= free {
+            match prev {
+                Some(p) if code == free => {
+                    stack.push(first_byte);
+                    walk = p;
+                }
+                _ => return Err(invalid_data("invalid zoo LZW code")),
+            }
+        } else if prev.is_none() && code >= 256 {
+            return Err(invalid_data("invalid zoo LZW code"));
+        }
+        while walk >= 256 {
+            stack.push(suffix[walk]);
+            walk = usize::from(prefix[walk]);
+        }
+        first_byte = walk as u8;
+        out.push(first_byte);
+        out.extend(stack.drain(..).rev());
+
+        if let Some(p) = prev {
+            if free < table_size {
+                prefix[free] = p as u16;
+                suffix[free] = first_byte;
+                free += 1;
+                if free >= 1 << n_bits && n_bits < LZW_MAX_BITS {
+                    n_bits += 1;
+                }
+            }
+        }
+        prev = Some(code);
+    }
+    out.truncate(size);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::BytesReader;
+
+    /// Pack codes LSB first at the widths zoo's decoder expects
+    fn pack(codes: &[(u32, u32)]) -> Vec<u8> {
+        let mut out = Vec::new();
+        let (mut acc, mut bits) = (0u64, 0);
+        for &(code, n) in codes {
+            acc |= u64::from(code) << bits;
+            bits += n;
+            while bits >= 8 {
+                out.push(acc as u8);
+                acc >>= 8;
+                bits -= 8;
+            }
+        }
+        if bits > 0 {
+            out.push(acc as u8);
+        }
+        out
+    }
+
+    fn archive(members: &[(&str, u8, &[u8], &[u8])]) -> Vec<u8> {
+        let mut out = b"ZOO 2.10 Archive.\x1A\0\0".to_vec();
+        out.extend_from_slice(&TAG.to_le_bytes());
+        out.extend_from_slice(&(ARCHIVE_HEADER_SIZE as u32).to_le_bytes());
+        out.extend_from_slice(&[0; 6]);
+        for (name, method, data, contents) in members {
+            let at = out.len() as u32;
+            let data_at = at + ENTRY_SIZE as u32;
+            let next = data_at + data.len() as u32;
+            let mut entry = TAG.to_le_bytes().to_vec();
+            entry.extend_from_slice(&[1, *method]);
+            entry.extend_from_slice(&next.to_le_bytes());
+            entry.extend_from_slice(&data_at.to_le_bytes());
+            entry.extend_from_slice(&[0; 4]);
+            entry.extend_from_slice(&crc16(contents).to_le_bytes());
+            entry.extend_from_slice(&(contents.len() as u32).to_le_bytes());
+            entry.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            entry.resize(38, 0);
+            entry.extend_from_slice(name.as_bytes());
+            entry.resize(ENTRY_SIZE, 0);
+            out.extend(entry);
+            out.extend_from_slice(data);
+        }
+        let mut end = TAG.to_le_bytes().to_vec();
+        end.resize(ENTRY_SIZE, 0);
+        out.extend(end);
+        out
+    }
+
+    #[test]
+    fn decodes_lzw() {
+        // "abababab": clear, a, b, 258 (ab), 260 (aba, not yet defined), b, end
+        let data = pack(&[
+            (256, 9),
+            (u32::from(b'a'), 9),
+            (u32::from(b'b'), 9),
+            (258, 9),
+            (260, 9),
+            (u32::from(b'b'), 9),
+            (257, 9),
+        ]);
+        assert_eq!(lzw(&data, 8).unwrap(), b"abababab");
+    }
+
+    #[test]
+    fn reads_members() {
+        let data = pack(&[(u32::from(b'x'), 9), (258, 9), (257, 9)]);
+        let zoo = archive(&[
+            ("PLAIN.TXT", METHOD_STORED, b"plain", b"plain"),
+            ("XXX.TXT", METHOD_LZW, &data, b"xxx"),
+        ]);
+        let children = ZOO.children(Arc::new(BytesReader::new(zoo))).unwrap();
+        let names: Vec<_> = children.iter().map(|c| c.metadata[0].1.as_str()).collect();
+        assert_eq!(names, ["PLAIN.TXT", "XXX.TXT"]);
+        let mut buf = [0u8; 3];
+        children[1].reader.read_at(0, &mut buf).unwrap();
+        assert_eq!(&buf, b"xxx");
+    }
+}
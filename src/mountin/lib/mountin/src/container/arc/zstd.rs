@@ -1,12 +1,29 @@
 //! Zstandard container reader
 //!
 //! Zstd is a compression wrapper containing a single decompressed stream.
+//! Files in the seekable format (zstd contrib/seekable_format) end with a
+//! skippable frame listing every frame's compressed and decompressed size,
+//! so frames can be decoded one at a time. Other files are decompressed
+//! whole, every frame in turn, stepping over skippable frames.
 
-use crate::container::{read_all, read_to_end_limited, BytesReader, Child, Container};
+use crate::container::block::{Block, BlockReader, MAX_BLOCK_SIZE};
+use crate::container::{
+    checked_table_size, invalid_data, read_all, read_to_end_limited, BytesReader, Child, Container,
+};
 use crate::detect::Reader;
 use std::io;
 use std::sync::Arc;
 
+/// Magic of the skippable frame holding the seek table
+const SEEK_TABLE_MAGIC: u32 = 0x184D_2A5E;
+/// Magic at the very end of a seekable file
+const SEEKABLE_MAGIC: u32 = 0x8F92_EAB1;
+/// Frame count, descriptor and seekable magic
+const FOOTER_SIZE: u64 = 9;
+const SKIPPABLE_HEADER_SIZE: u64 = 8;
+const DESCRIPTOR_CHECKSUM: u8 = 0x80;
+const DESCRIPTOR_RESERVED: u8 = 0x7C;
+
 /// Zstd container - decompresses content to expose inner stream
 pub struct ZstdContainer;
 
@@ -15,14 +32,183 @@ pub static ZSTD: ZstdContainer = ZstdContainer;
 
 impl Container for ZstdContainer {
     fn children(&self, reader: Arc<dyn Reader + Send + Sync>) -> io::Result<Vec<Child>> {
-        let compressed = read_all(&*reader)?;
-        let decoder = zstd::stream::read::Decoder::new(&compressed[..])?;
-        let decompressed = read_to_end_limited(decoder)?;
+        let child: Arc<dyn Reader + Send + Sync> = match seek_table(&*reader) {
+            Ok(frames) => Arc::new(BlockReader::new(
+                Arc::clone(&reader),
+                frames,
+                |_, frame| read_to_end_limited(zstd::stream::read::Decoder::new(frame)?),
+            )?),
+            Err(_) => {
+                let compressed = read_all(&*reader)?;
+                let decoder = zstd::stream::read::Decoder::new(&compressed[..])?;
+                Arc::new(BytesReader::new(read_to_end_limited(decoder)?))
+            }
+        };
 
         Ok(vec![Child {
             index: 0,
             offset: u64::MAX, // Transformed data, not a slice
-            reader: Arc::new(BytesReader::new(decompressed)),
+            reader: child,
+            metadata: Vec::new(),
         }])
     }
 }
+
+fn read_u32(data: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]])
+}
+
+/// Parse the seek table into frame locations
+fn seek_table(reader: &dyn Reader) -> io::Result<Vec<Block>> {
+    let size = reader
+        .size()
+        .ok_or_else(|| invalid_data("zstd size unknown"))?;
+    if size < SKIPPABLE_HEADER_SIZE + FOOTER_SIZE {
+        return Err(invalid_data("zstd too short for a seek table"));
+    }
+
+    let mut footer = [0u8; FOOTER_SIZE as usize];
+    if reader.read_at(size - FOOTER_SIZE, &mut footer)? != footer.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "short zstd seek table footer read",
+        ));
+    }
+    let descriptor = footer[4];
+    if read_u32(&footer, 5) != SEEKABLE_MAGIC || descriptor & DESCRIPTOR_RESERVED != 0 {
+        return Err(invalid_data("no zstd seek table"));
+    }
+    let frames = u64::from(read_u32(&footer, 0));
+    let entry_size = if descriptor & DESCRIPTOR_CHECKSUM != 0 {
+        12
+    } else {
+        8
+    };
+
+    let table_size = frames
+        .checked_mul(entry_size)
+        .and_then(|entries| entries.checked_add(FOOTER_SIZE))
+        .ok_or_else(|| invalid_data("zstd seek table too large"))?;
+    let table_start = (size - FOOTER_SIZE)
+        .checked_sub(frames * entry_size)
+        .and_then(|start| start.checked_sub(SKIPPABLE_HEADER_SIZE))
+        .ok_or_else(|| invalid_data("zstd seek table before start of file"))?;
+    let len = checked_table_size(reader, table_start, SKIPPABLE_HEADER_SIZE + table_size, 1)?;
+    let mut table = vec![0u8; len];
+    if reader.read_at(table_start, &mut table)? != table.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "short zstd seek table read",
+        ));
+    }
+    if read_u32(&table, 0) != SEEK_TABLE_MAGIC || u64::from(read_u32(&table, 4)) != table_size {
+        return Err(invalid_data("invalid zstd seek table frame"));
+    }
+
+    let mut blocks = Vec::with_capacity(frames as usize);
+    let mut offset = 0u64;
+    let mut start = 0u64;
+    for entry in table[SKIPPABLE_HEADER_SIZE as usize..len - FOOTER_SIZE as usize]
+        .chunks_exact(entry_size as usize)
+    {
+        let compressed_size = u64::from(read_u32(entry, 0));
+        let size = u64::from(read_u32(entry, 4));
+        if size > MAX_BLOCK_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "zstd frames too large for random access",
+            ));
+        }
+        blocks.push(Block {
+            offset,
+            compressed_size,
+            start,
+            size,
+        });
+        offset += compressed_size;
+        start += size;
+    }
+    // Frames are stored back to back, right before the seek table
+    if offset != table_start {
+        return Err(invalid_data("zstd seek table does not match frames"));
+    }
+    Ok(blocks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Compress each chunk as its own frame and append a seek table
+    fn seekable(chunks: &[&[u8]], checksums: bool) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut entries = Vec::new();
+        for chunk in chunks {
+            let frame = zstd::bulk::compress(chunk, 3).unwrap();
+            entries.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+            entries.extend_from_slice(&(chunk.len() as u32).to_le_bytes());
+            if checksums {
+                entries.extend_from_slice(&0u32.to_le_bytes());
+            }
+            out.extend(frame);
+        }
+        out.extend_from_slice(&SEEK_TABLE_MAGIC.to_le_bytes());
+        out.extend_from_slice(&((entries.len() as u64 + FOOTER_SIZE) as u32).to_le_bytes());
+        out.extend(entries);
+        out.extend_from_slice(&(chunks.len() as u32).to_le_bytes());
+        out.push(if checksums { DESCRIPTOR_CHECKSUM } else { 0 });
+        out.extend_from_slice(&SEEKABLE_MAGIC.to_le_bytes());
+        out
+    }
+
+    fn read(reader: &dyn Reader, offset: u64, len: usize) -> Vec<u8> {
+        let mut buf = vec![0u8; len];
+        let n = reader.read_at(offset, &mut buf).unwrap();
+        buf.truncate(n);
+        buf
+    }
+
+    #[test]
+    fn reads_seekable_frames() {
+        for checksums in [false, true] {
+            let data = seekable(&[b"alpha ", b"", b"beta ", b"gamma"], checksums);
+            let frames = seek_table(&BytesReader::new(data.clone())).unwrap();
+            assert_eq!(frames.len(), 4);
+
+            let kids = ZSTD.children(Arc::new(BytesReader::new(data))).unwrap();
+            let reader = &*kids[0].reader;
+            assert_eq!(reader.size(), Some(16));
+            assert_eq!(read(reader, 0, 64), b"alpha ");
+            assert_eq!(read(reader, 6, 3), b"bet");
+            assert_eq!(read(reader, 11, 64), b"gamma");
+        }
+    }
+
+    #[test]
+    fn streams_files_without_seek_table() {
+        let data = zstd::bulk::compress(b"plain zstd frame", 3).unwrap();
+        assert!(seek_table(&BytesReader::new(data.clone())).is_err());
+        let kids = ZSTD.children(Arc::new(BytesReader::new(data))).unwrap();
+        assert_eq!(read(&*kids[0].reader, 0, 64), b"plain zstd frame");
+    }
+
+    #[test]
+    fn streams_concatenated_and_skippable_frames() {
+        let mut data = zstd::bulk::compress(b"first frame, ", 3).unwrap();
+        data.extend_from_slice(&(SEEK_TABLE_MAGIC - 3).to_le_bytes());
+        data.extend_from_slice(&4u32.to_le_bytes());
+        data.extend_from_slice(b"meta");
+        data.extend(zstd::bulk::compress(b"second frame", 3).unwrap());
+        let kids = ZSTD.children(Arc::new(BytesReader::new(data))).unwrap();
+        assert_eq!(read(&*kids[0].reader, 0, 64), b"first frame, second frame");
+    }
+
+    #[test]
+    fn rejects_mismatched_seek_table() {
+        let mut data = seekable(&[b"alpha", b"beta"], false);
+        // Claim the first frame is one byte longer than it is
+        let entry = data.len() - FOOTER_SIZE as usize - 16;
+        data[entry] += 1;
+        assert!(seek_table(&BytesReader::new(data)).is_err());
+    }
+}
@@ -0,0 +1,231 @@
+//! Block reader - random access to streams stored as independently
+//! compressed blocks (xz blocks, zstd seekable frames), and disk images
+//! stored as fixed-size blocks located by an offset table (cloop, CSO)
+
+use crate::container::{invalid_data, BlockCache};
+use crate::detect::Reader;
+use std::io;
+use std::sync::{Arc, Mutex};
+
+/// Largest block decoded into memory at once
+pub(crate) const MAX_BLOCK_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Decoded blocks kept in memory
+const CACHE_BLOCKS: usize = 8;
+
+/// One compressed block
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Block {
+    /// Offset of the compressed bytes in the parent
+    pub offset: u64,
+    pub compressed_size: u64,
+    /// Offset of the decoded bytes in the stream
+    pub start: u64,
+    pub size: u64,
+}
+
+/// Decodes block `index` from its compressed bytes
+type DecodeFn = Box<dyn Fn(usize, &[u8]) -> io::Result<Vec<u8>> + Send + Sync>;
+
+/// Reader that decodes only the blocks a read touches
+pub(crate) struct BlockReader {
+    parent: Arc<dyn Reader + Send + Sync>,
+    blocks: Vec<Block>,
+    size: u64,
+    decode: DecodeFn,
+    cache: Mutex<BlockCache>,
+}
+
+impl BlockReader {
+    /// Blocks must be in stream order and cover the stream without gaps
+    pub(crate) fn new<F>(
+        parent: Arc<dyn Reader + Send + Sync>,
+        blocks: Vec<Block>,
+        decode: F,
+    ) -> io::Result<Self>
+    where
+        F: Fn(usize, &[u8]) -> io::Result<Vec<u8>> + Send + Sync + 'static,
+    {
+        let mut size = 0u64;
+        for block in &blocks {
+            let end = block
+                .offset
+                .checked_add(block.compressed_size)
+                .ok_or_else(|| invalid_data("block offset overflow"))?;
+            if block.start != size
+                || block.size > MAX_BLOCK_SIZE
+                || block.compressed_size > MAX_BLOCK_SIZE * 2
+                || parent.size().is_some_and(|total| end > total)
+            {
+                return Err(invalid_data("invalid block index"));
+            }
+            size += block.size;
+        }
+
+        Ok(Self {
+            parent,
+            blocks,
+            size,
+            decode: Box::new(decode),
+            cache: Mutex::new(BlockCache::new(CACHE_BLOCKS)),
+        })
+    }
+
+    fn block(&self, index: usize) -> io::Result<Arc<Vec<u8>>> {
+        let mut cache = self
+            .cache
+            .lock()
+            .map_err(|_| io::Error::other("block cache lock poisoned"))?;
+        if let Some(data) = cache.get(index as u64) {
+            return Ok(data);
+        }
+
+        let block = &self.blocks[index];
+        let mut compressed = vec![0u8; block.compressed_size as usize];
+        if self.parent.read_at(block.offset, &mut compressed)? != compressed.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "short compressed block read",
+            ));
+        }
+        let data = (self.decode)(index, &compressed)?;
+        if data.len() as u64 != block.size {
+            return Err(invalid_data("decoded block size mismatch"));
+        }
+
+        let data = Arc::new(data);
+        cache.insert(index as u64, Arc::clone(&data));
+        Ok(data)
+    }
+}
+
+impl Reader for BlockReader {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        if offset >= self.size {
+            return Ok(0);
+        }
+
+        // Last block starting at or before offset; empty blocks sort first
+        let index = self.blocks.partition_point(|b| b.start <= offset) - 1;
+        let block = &self.blocks[index];
+        let data = self.block(index)?;
+        let from = (offset - block.start) as usize;
+        let to_read = buf.len().min(data.len() - from);
+        buf[..to_read].copy_from_slice(&data[from..from + to_read]);
+        Ok(to_read)
+    }
+
+    fn size(&self) -> Option<u64> {
+        Some(self.size)
+    }
+}
+
+// SAFETY: BlockReader holds an Arc'd parent, owned tables, a Send + Sync
+// decoder and a Mutex
+unsafe impl Send for BlockReader {}
+unsafe impl Sync for BlockReader {}
+
+/// Reader over a disk stored as fixed-size blocks, each compressed on its
+/// own, found through a table of offsets. Only the offsets are kept, as
+/// images of small blocks can have millions of them.
+pub(crate) struct IndexedReader {
+    parent: Arc<dyn Reader + Send + Sync>,
+    /// Where each block is stored, then where the last one ends
+    index: Vec<u64>,
+    block_size: u64,
+    size: u64,
+    decode: DecodeFn,
+    cache: Mutex<BlockCache>,
+}
+
+impl IndexedReader {
+    /// `index` needs an offset per block of `size` bytes, then the end of
+    /// the last block; no block may be stored in more than `max_stored`
+    /// bytes. The last block may decode to less than `block_size`.
+    pub(crate) fn new<F>(
+        parent: Arc<dyn Reader + Send + Sync>,
+        index: Vec<u64>,
+        block_size: u64,
+        size: u64,
+        max_stored: u64,
+        decode: F,
+    ) -> io::Result<Self>
+    where
+        F: Fn(usize, &[u8]) -> io::Result<Vec<u8>> + Send + Sync + 'static,
+    {
+        if block_size == 0 || block_size > MAX_BLOCK_SIZE {
+            return Err(invalid_data("invalid block size"));
+        }
+        if index.len() as u64 != size.div_ceil(block_size) + 1
+            || index
+                .windows(2)
+                .any(|pair| pair[0] > pair[1] || pair[1] - pair[0] > max_stored)
+            || parent
+                .size()
+                .is_some_and(|total| index.last().is_some_and(|&end| end > total))
+        {
+            return Err(invalid_data("invalid block index"));
+        }
+
+        Ok(Self {
+            parent,
+            index,
+            block_size,
+            size,
+            decode: Box::new(decode),
+            cache: Mutex::new(BlockCache::new(CACHE_BLOCKS)),
+        })
+    }
+
+    fn block(&self, index: usize) -> io::Result<Arc<Vec<u8>>> {
+        let mut cache = self
+            .cache
+            .lock()
+            .map_err(|_| io::Error::other("block cache lock poisoned"))?;
+        if let Some(data) = cache.get(index as u64) {
+            return Ok(data);
+        }
+
+        let start = self.index[index];
+        let mut stored = vec![0u8; (self.index[index + 1] - start) as usize];
+        if self.parent.read_at(start, &mut stored)? != stored.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "short compressed block read",
+            ));
+        }
+        let len = (self.size - index as u64 * self.block_size).min(self.block_size) as usize;
+        let mut data = (self.decode)(index, &stored)?;
+        if data.len() < len {
+            return Err(invalid_data("decoded block size mismatch"));
+        }
+        data.truncate(len);
+
+        let data = Arc::new(data);
+        cache.insert(index as u64, Arc::clone(&data));
+        Ok(data)
+    }
+}
+
+impl Reader for IndexedReader {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        if offset >= self.size {
+            return Ok(0);
+        }
+
+        let data = self.block((offset / self.block_size) as usize)?;
+        let from = (offset % self.block_size) as usize;
+        let to_read = buf.len().min(data.len() - from);
+        buf[..to_read].copy_from_slice(&data[from..from + to_read]);
+        Ok(to_read)
+    }
+
+    fn size(&self) -> Option<u64> {
+        Some(self.size)
+    }
+}
+
+// SAFETY: IndexedReader holds an Arc'd parent, an owned table, a Send +
+// Sync decoder and a Mutex
+unsafe impl Send for IndexedReader {}
+unsafe impl Sync for IndexedReader {}
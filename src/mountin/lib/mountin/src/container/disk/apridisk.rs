@@ -181,6 +181,7 @@ impl Container for ApridiskContainer {
             index: 0,
             offset: u64::MAX, // reconstructed data, not a slice of the parent
             reader: Arc::new(BytesReader::new(img)),
+            metadata: Vec::new(),
         }])
     }
 }
@@ -63,6 +63,7 @@ impl Container for AtrContainer {
                 HEADER_LEN as u64,
                 data_len,
             )),
+            metadata: Vec::new(),
         }])
     }
 }
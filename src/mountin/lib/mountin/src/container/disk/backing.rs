@@ -0,0 +1,233 @@
+//! Backing files - the parents of differencing disks and overlays
+//!
+//! qcow2, QED, VHD and VHDX differencing, VDI snapshot and CHD child
+//! images only store the data written since their parent was taken;
+//! everything else reads through to the parent. Parents are opened with
+//! `Reader::sibling`, so the caller decides where files come from, and can
+//! have parents of their own. A parent that can't be found reads as zeros,
+//! as before the chain was followed, and is reported as missing.
+
+use crate::container::disk::{
+    chd::ChdReader, qcow2::Qcow2Reader, qed::QedReader, vdi::VdiReader, vhd::VhdReader,
+    vhdx::VhdxReader, vmdk::VmdkDisk,
+};
+use crate::container::Metadata;
+use crate::detect::Reader;
+use std::io;
+use std::sync::Arc;
+
+/// Longest chain of parents followed
+const MAX_DEPTH: usize = 16;
+
+/// Names in a chain of parents, nearest first
+#[derive(Clone, Default)]
+pub(crate) struct Chain {
+    pub(crate) names: Vec<String>,
+    /// A parent that couldn't be opened, ending the chain
+    pub(crate) missing: Option<String>,
+}
+
+impl Chain {
+    /// Chain metadata for the disk's child: a "backing" entry per parent,
+    /// then "missing_backing" if the chain is incomplete
+    pub(crate) fn metadata(&self) -> Metadata {
+        let mut metadata: Metadata = self
+            .names
+            .iter()
+            .map(|name| ("backing", name.clone()))
+            .collect();
+        if let Some(name) = &self.missing {
+            metadata.push(("missing_backing", name.clone()));
+        }
+        metadata
+    }
+}
+
+/// A disk's parent, opened with its own parents
+#[derive(Clone)]
+pub(crate) struct Backing {
+    /// None when the parent couldn't be opened
+    reader: Option<Arc<dyn Reader + Send + Sync>>,
+    pub(crate) chain: Chain,
+}
+
+impl Backing {
+    /// A parent called `name` that couldn't be found
+    pub(crate) fn missing(name: &str) -> Self {
+        Self {
+            reader: None,
+            chain: Chain {
+                names: Vec::new(),
+                missing: Some(name.to_string()),
+            },
+        }
+    }
+}
+
+/// A disk reader that may have a parent
+pub(crate) trait Layered: Reader + Send + Sync + 'static {
+    /// Find and attach the disk's parent, if it has one; `depth` counts
+    /// the children above this disk
+    fn open_backing(&mut self, depth: usize);
+
+    fn backing(&self) -> Option<&Backing>;
+
+    /// Chain metadata for the disk's child
+    fn chain_metadata(&self) -> Metadata {
+        self.backing()
+            .map(|backing| backing.chain.metadata())
+            .unwrap_or_default()
+    }
+}
+
+/// Names to try for a recorded parent path: as recorded (relative to the
+/// child), then just its file name, for images moved together
+pub(crate) fn candidates(name: &str) -> Vec<String> {
+    let mut names = vec![name.to_string()];
+    let base = name.rsplit(['/', '\\']).next().unwrap_or(name);
+    if base != name && !base.is_empty() {
+        names.push(base.to_string());
+    }
+    names
+}
+
+/// Open the parent recorded by the disk in `child`, with its own parents.
+/// `names` are the ways the child records the parent, best first.
+/// `format` is the parent's format when the child records it (qcow2
+/// format names: "raw", "qcow2", ...), otherwise it is probed.
+pub(crate) fn open(
+    child: &dyn Reader,
+    names: &[String],
+    format: Option<&str>,
+    depth: usize,
+) -> Backing {
+    for name in names {
+        let file = candidates(name)
+            .iter()
+            .find_map(|candidate| child.sibling(candidate).ok());
+        if let Some(file) = file {
+            return open_file(file, name, format, depth);
+        }
+    }
+    Backing::missing(names.first().map_or("", String::as_str))
+}
+
+/// Open `file`, the parent called `name`, with its own parents
+pub(crate) fn open_file(
+    file: Arc<dyn Reader + Send + Sync>,
+    name: &str,
+    format: Option<&str>,
+    depth: usize,
+) -> Backing {
+    if depth >= MAX_DEPTH {
+        return Backing::missing(name);
+    }
+    let format = match format {
+        Some(format) => format,
+        None => match probe(&*file) {
+            Ok(format) => format,
+            Err(_) => return Backing::missing(name),
+        },
+    };
+
+    fn layered<R: Layered>(mut reader: R, depth: usize) -> (Arc<dyn Reader + Send + Sync>, Chain) {
+        reader.open_backing(depth);
+        let chain = reader
+            .backing()
+            .map(|backing| backing.chain.clone())
+            .unwrap_or_default();
+        (Arc::new(reader), chain)
+    }
+
+    let depth = depth + 1;
+    let disk = match format {
+        "qcow2" => Qcow2Reader::new(file).map(|r| layered(r, depth)),
+        "qed" => QedReader::new(file).map(|r| layered(r, depth)),
+        "vpc" => VhdReader::new(file).map(|r| layered(r, depth)),
+        "vhdx" => VhdxReader::new(file).map(|r| layered(r, depth)),
+        "vdi" => VdiReader::new(file).map(|r| layered(r, depth)),
+        "vmdk" => VmdkDisk::open(file).map(|r| layered(r, depth)),
+        "chd" => ChdReader::new(file).map(|r| layered(r, depth)),
+        "raw" => Ok((file, Chain::default())),
+        _ => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "unsupported backing file format",
+        )),
+    };
+    match disk {
+        Ok((reader, mut chain)) => {
+            chain.names.insert(0, name.to_string());
+            Backing {
+                reader: Some(reader),
+                chain,
+            }
+        }
+        Err(_) => Backing::missing(name),
+    }
+}
+
+/// Guess a parent's format from its magic, falling back to raw
+fn probe(file: &dyn Reader) -> io::Result<&'static str> {
+    let mut magic = [0u8; 0x44];
+    let n = file.read_at(0, &mut magic)?;
+    let magic = &magic[..n];
+    Ok(if magic.starts_with(b"QFI\xfb") {
+        "qcow2"
+    } else if magic.starts_with(b"QED\0") {
+        "qed"
+    } else if magic.starts_with(b"conectix") {
+        "vpc"
+    } else if magic.starts_with(b"vhdxfile") {
+        "vhdx"
+    } else if magic.starts_with(b"MComprHD") {
+        "chd"
+    } else if magic.starts_with(b"KDMV")
+        || magic.starts_with(b"COWD")
+        || magic.starts_with(b"# Disk DescriptorFile")
+    {
+        "vmdk"
+    } else if magic.len() == 0x44 && magic[0x40..] == [0x7f, 0x10, 0xda, 0xbe] {
+        "vdi"
+    } else {
+        "raw"
+    })
+}
+
+/// Fill `buf` from the parent at `offset`, with zeros past its end or when
+/// there is no parent
+pub(crate) fn read_backing(
+    backing: Option<&Backing>,
+    offset: u64,
+    buf: &mut [u8],
+) -> io::Result<usize> {
+    let mut done = 0;
+    if let Some(reader) = backing.and_then(|backing| backing.reader.as_ref()) {
+        while done < buf.len() {
+            let n = reader.read_at(offset + done as u64, &mut buf[done..])?;
+            if n == 0 {
+                break;
+            }
+            done += n;
+        }
+    }
+    buf[done..].fill(0);
+    Ok(buf.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tries_recorded_path_then_file_name() {
+        assert_eq!(candidates("base.qcow2"), ["base.qcow2"]);
+        assert_eq!(
+            candidates("/images/base.qcow2"),
+            ["/images/base.qcow2", "base.qcow2"]
+        );
+        assert_eq!(
+            candidates(r"C:\vms\base.vhd"),
+            [r"C:\vms\base.vhd", "base.vhd"]
+        );
+    }
+}
@@ -26,6 +26,7 @@ impl Container for BochsContainer {
             index: 0,
             offset: 0,
             reader: Arc::new(bochs_reader),
+            metadata: Vec::new(),
         }])
     }
 }
@@ -0,0 +1,242 @@
+//! CloneCD disc image reader
+//!
+//! A CloneCD image is an INI-style `.ccd` descriptor holding the disc's
+//! raw table of contents, an `.img` file of every sector as 2352 raw
+//! bytes from LBA 0, and an optional `.sub` file of their subchannel data.
+//! TOC entries with points 1-99 start tracks, and point A2 gives each
+//! session's lead-out; the `[TRACK n]` sections add the mode and index 0.
+
+use crate::container::disk::cdrom::{self, ImageTrack, TrackMode};
+use crate::container::{invalid_data, Child, Container};
+use crate::detect::Reader;
+use std::io;
+use std::sync::Arc;
+
+/// TOC point of a session's lead-out
+const POINT_LEAD_OUT: i64 = 0xa2;
+/// Control bit set for data tracks
+const CONTROL_DATA: i64 = 0x04;
+
+/// CloneCD disc image container
+pub struct CcdContainer;
+
+/// Static instance for registry
+pub static CCD: CcdContainer = CcdContainer;
+
+impl Container for CcdContainer {
+    fn children(&self, reader: Arc<dyn Reader + Send + Sync>) -> io::Result<Vec<Child>> {
+        let sections = parse_sections(&cdrom::read_descriptor(&*reader)?);
+        let image = cdrom::open_companion(&*reader, "img")?;
+        let sectors = image.size().unwrap_or(0) / cdrom::SECTOR_SIZE as u64;
+        Ok(cdrom::image_children(tracks(&sections, &image, sectors)?))
+    }
+}
+
+/// A `[name]` section and its `key=value` lines
+type Section = (String, Vec<(String, String)>);
+
+fn parse_sections(text: &str) -> Vec<Section> {
+    let mut sections: Vec<Section> = Vec::new();
+    for line in text.lines().map(str::trim) {
+        if let Some(name) = line
+            .strip_prefix('[')
+            .and_then(|line| line.strip_suffix(']'))
+        {
+            sections.push((name.trim().to_string(), Vec::new()));
+        } else if let (Some((key, value)), Some((_, entries))) =
+            (line.split_once('='), sections.last_mut())
+        {
+            entries.push((key.trim().to_string(), value.trim().to_string()));
+        }
+    }
+    sections
+}
+
+/// A value in decimal, or in hex with a 0x prefix
+fn value(entries: &[(String, String)], key: &str) -> Option<i64> {
+    let (_, value) = entries
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(key))?;
+    match value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+    {
+        Some(hex) => i64::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+fn section<'a>(sections: &'a [Section], name: &str) -> Option<&'a [(String, String)]> {
+    sections
+        .iter()
+        .find(|(section, _)| section.eq_ignore_ascii_case(name))
+        .map(|(_, entries)| entries.as_slice())
+}
+
+/// A track start from the TOC
+struct Start {
+    number: u32,
+    session: u32,
+    control: i64,
+    lba: u64,
+}
+
+fn tracks(
+    sections: &[Section],
+    image: &Arc<dyn Reader + Send + Sync>,
+    sectors: u64,
+) -> io::Result<Vec<ImageTrack>> {
+    let disc = section(sections, "Disc").ok_or_else(|| invalid_data("CCD without disc section"))?;
+    if value(disc, "DataTracksScrambled").unwrap_or(0) != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "scrambled CCD data tracks",
+        ));
+    }
+
+    let mut starts = Vec::new();
+    let mut lead_outs = Vec::new();
+    let entries = sections
+        .iter()
+        .filter(|(name, _)| name.to_ascii_lowercase().starts_with("entry "));
+    for (_, entry) in entries {
+        let (Some(session), Some(point), Some(lba)) = (
+            value(entry, "Session"),
+            value(entry, "Point"),
+            value(entry, "PLBA"),
+        ) else {
+            return Err(invalid_data("invalid CCD TOC entry"));
+        };
+        let session = u32::try_from(session).map_err(|_| invalid_data("invalid CCD session"))?;
+        match point {
+            1..=99 => starts.push(Start {
+                number: point as u32,
+                session,
+                control: value(entry, "Control").unwrap_or(0),
+                lba: u64::try_from(lba).map_err(|_| invalid_data("invalid CCD track start"))?,
+            }),
+            POINT_LEAD_OUT => lead_outs.push((session, lba.max(0) as u64)),
+            _ => {}
+        }
+    }
+    if starts.is_empty() {
+        return Err(invalid_data("CCD without tracks"));
+    }
+    starts.sort_by_key(|start| start.number);
+
+    let mut tracks = Vec::with_capacity(starts.len());
+    for (position, start) in starts.iter().enumerate() {
+        let track = section(sections, &format!("TRACK {}", start.number)).unwrap_or(&[]);
+        let index0 = |track: &[(String, String)], lba: u64| {
+            value(track, "INDEX 0")
+                .and_then(|index| u64::try_from(index).ok())
+                .filter(|&index| index <= lba)
+                .unwrap_or(lba)
+        };
+        let first = index0(track, start.lba);
+
+        // The track runs to the next one's pregap, or its session's end
+        let end = match starts.get(position + 1) {
+            Some(next) if next.session == start.session => {
+                let next_track =
+                    section(sections, &format!("TRACK {}", next.number)).unwrap_or(&[]);
+                index0(next_track, next.lba)
+            }
+            _ => lead_outs
+                .iter()
+                .find(|(session, _)| *session == start.session)
+                .map_or(sectors, |&(_, lba)| lba),
+        };
+
+        let mode = match value(track, "MODE") {
+            Some(0) => TrackMode::Audio,
+            Some(1) => TrackMode::Mode1,
+            Some(2) => TrackMode::Mode2,
+            Some(_) => return Err(invalid_data("invalid CCD track mode")),
+            None if start.control & CONTROL_DATA != 0 => TrackMode::Mode1,
+            None => TrackMode::Audio,
+        };
+        tracks.push(ImageTrack {
+            number: start.number,
+            session: start.session,
+            mode,
+            file: Arc::clone(image),
+            offset: start
+                .lba
+                .checked_mul(cdrom::SECTOR_SIZE as u64)
+                .ok_or_else(|| invalid_data("invalid CCD track start"))?,
+            sector_size: cdrom::SECTOR_SIZE,
+            frame_size: cdrom::SECTOR_SIZE,
+            frames: end.saturating_sub(start.lba),
+            pregap: start.lba - first,
+        });
+    }
+    Ok(tracks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::FilesReader;
+
+    fn entry(index: u32, session: u32, point: u32, control: u32, lba: u32) -> String {
+        format!(
+            "[Entry {index}]\nSession={session}\nPoint=0x{point:02x}\nADR=0x01\n\
+             Control=0x{control:02x}\nTrackNo=0\nPLBA={lba}\n"
+        )
+    }
+
+    #[test]
+    fn reads_sessions_and_tracks() {
+        let descriptor = [
+            "[CloneCD]\nVersion=3\n[Disc]\nTocEntries=5\nSessions=2\nDataTracksScrambled=0\n"
+                .to_string(),
+            entry(0, 1, 0x01, 0x00, 0),
+            entry(1, 1, 0x02, 0x04, 3),
+            entry(2, 1, 0xa2, 0x04, 6),
+            entry(3, 2, 0x03, 0x04, 8),
+            entry(4, 2, 0xa2, 0x04, 10),
+            "[TRACK 1]\nMODE=0\nINDEX 1=0\n[TRACK 2]\nMODE=1\nINDEX 0=2\nINDEX 1=3\n".to_string(),
+        ]
+        .concat();
+
+        let mut image = Vec::new();
+        for lba in 0..10u8 {
+            let mut sector = vec![b'0' + lba; cdrom::SECTOR_SIZE];
+            sector[..12].copy_from_slice(&cdrom::SYNC);
+            sector[15] = 1;
+            image.extend(sector);
+        }
+        let files = FilesReader::open(
+            "disc.ccd",
+            vec![("disc.ccd", descriptor.into_bytes()), ("disc.img", image)],
+        );
+        let kids = CCD.children(files).unwrap();
+
+        // Track 1 (audio) ends at track 2's index 0; track 3 has no
+        // [TRACK] section, so its mode comes from the TOC control bits
+        assert_eq!(kids.len(), 5);
+        assert_eq!(kids[0].reader.size(), Some(2 * 2352));
+        assert!(kids[1].metadata.contains(&("pregap", "1".to_string())));
+        assert!(kids[1].metadata.contains(&("frames", "3".to_string())));
+        let mut buf = [0u8; 1];
+        kids[2].reader.read_at(0, &mut buf).unwrap();
+        assert_eq!(buf[0], b'3');
+        assert!(kids[3].metadata.contains(&("session", "2".to_string())));
+        assert!(kids[3].metadata.contains(&("mode", "mode1".to_string())));
+        assert_eq!(kids[4].reader.size(), Some(2 * 2048));
+        kids[4].reader.read_at(2048, &mut buf).unwrap();
+        assert_eq!(buf[0], b'9');
+    }
+
+    #[test]
+    fn rejects_scrambled_images() {
+        let descriptor = "[CloneCD]\n[Disc]\nDataTracksScrambled=1\n";
+        let files = FilesReader::open(
+            "disc.ccd",
+            vec![("disc.ccd", descriptor.into()), ("disc.img", Vec::new())],
+        );
+        let error = CCD.children(files).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::Unsupported);
+    }
+}
@@ -1,8 +1,10 @@
 //! CDI (DiscJuggler) disc image reader
 //!
 //! Parses CDI format and exposes data tracks for filesystem detection.
-//! Based on cdirip by DeXT/Lawrence Williams.
+//! Based on cdirip by DeXT/Lawrence Williams. Tracks stored with 2336 or
+//! 2352-byte sectors also get a cooked view of their user data.
 
+use crate::container::disk::cdrom;
 use crate::container::{slice::SliceReader, Child, Container};
 use crate::detect::Reader;
 use std::io;
@@ -31,6 +33,7 @@ enum TrackMode {
 /// Parsed track info
 #[derive(Debug)]
 struct Track {
+    mode: TrackMode,
     sector_size: u32,
     pregap_length: u32,
     length: u32,
@@ -47,15 +50,25 @@ impl Container for CdiContainer {
             // Calculate track data size
             let data_size = track.length as u64 * track.sector_size as u64;
 
-            children.push(Child {
-                index: idx as u32,
-                offset: track.data_offset,
-                reader: Arc::new(SliceReader::new(
+            // Mode 2 tracks are XA; cooked Mode 2 sectors are Form 1
+            let mode = match (track.mode, track.sector_size) {
+                (TrackMode::Audio, _) => cdrom::TrackMode::Audio,
+                (TrackMode::Mode1, _) => cdrom::TrackMode::Mode1,
+                (TrackMode::Mode2, 2048) => cdrom::TrackMode::Mode2Form1,
+                (TrackMode::Mode2, _) => cdrom::TrackMode::Mode2,
+            };
+            cdrom::track_children(
+                &mut children,
+                Arc::new(SliceReader::new(
                     Arc::clone(&reader),
                     track.data_offset,
                     data_size,
                 )),
-            });
+                track.data_offset,
+                mode,
+                track.sector_size as usize,
+                vec![("track", (idx + 1).to_string())],
+            );
         }
 
         Ok(children)
@@ -213,7 +226,7 @@ fn parse_track(
 
     let mode_val = read_u32_le(reader, pos)?;
     pos += 4;
-    let _mode = match mode_val {
+    let mode = match mode_val {
         0 => TrackMode::Audio,
         1 => TrackMode::Mode1,
         2 => TrackMode::Mode2,
@@ -269,6 +282,7 @@ fn parse_track(
 
     Ok((
         Track {
+            mode,
             sector_size,
             pregap_length,
             length,
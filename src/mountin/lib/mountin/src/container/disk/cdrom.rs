@@ -0,0 +1,559 @@
+//! CD-ROM sector layout shared by optical disc image formats
+//!
+//! A raw sector is 2352 bytes: a 12-byte sync pattern, a 4-byte header
+//! (address and mode), then user data and error correction whose layout
+//! depends on the mode. Mode 1 sectors end in an EDC and Reed-Solomon
+//! P and Q parity, which images may drop and readers regenerate.
+//!
+//! Mode 2 sectors on real discs are CD-ROM XA: an 8-byte subheader follows
+//! the header and says whether the sector is Form 1 (2048 bytes of user
+//! data, with ECC) or Form 2 (2324 bytes, without). Images store sectors
+//! raw, without sync and header (2336 bytes, Mode 2 only), or as user data
+//! alone; filesystems address user data, so raw tracks also get a cooked
+//! view of it.
+//!
+//! Descriptor-plus-data images (CUE, CCD, TOC, MDS) describe their tracks
+//! in one file and store the sectors in others beside it, which are found
+//! through `Reader::sibling`.
+
+use crate::container::slice::SliceReader;
+use crate::container::{invalid_data, Child, Metadata};
+use crate::detect::Reader;
+use std::io;
+use std::sync::Arc;
+
+/// Bytes in a raw sector
+pub(crate) const SECTOR_SIZE: usize = 2352;
+/// Bytes in a Mode 2 sector stored without sync and header
+pub(crate) const MODE2_SIZE: usize = 2336;
+/// User data in a Mode 1 or Mode 2 Form 1 sector
+const FORM1_DATA_SIZE: usize = 2048;
+/// User data in a Mode 2 Form 2 sector
+const FORM2_DATA_SIZE: usize = 2324;
+/// Sync pattern and header
+const HEADER_SIZE: usize = 16;
+/// Subchannel data stored after a sector, 96 bytes of P-W
+pub(crate) const SUBCHANNEL_SIZE: usize = 96;
+/// Frames (sectors) per second of MSF addresses
+const FRAMES_PER_SECOND: u64 = 75;
+/// Largest descriptor read
+const MAX_DESCRIPTOR_SIZE: u64 = 1024 * 1024;
+/// XA subheader, two copies of four bytes
+const SUBHEADER_SIZE: usize = 8;
+
+/// Sync pattern starting every data sector
+pub(crate) const SYNC: [u8; 12] = [
+    0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00,
+];
+
+const MODE_OFFSET: usize = 15;
+const ECC_P_OFFSET: usize = 2076;
+const ECC_P_NUM_BYTES: usize = 86;
+const ECC_P_COMP: usize = 24;
+const ECC_Q_OFFSET: usize = ECC_P_OFFSET + 2 * ECC_P_NUM_BYTES;
+const ECC_Q_NUM_BYTES: usize = 52;
+const ECC_Q_COMP: usize = 43;
+
+/// Multiplication by 2 in GF(2^8) with polynomial 0x11d, and division by 3
+const ECC_TABLES: ([u8; 256], [u8; 256]) = {
+    let mut low = [0u8; 256];
+    let mut high = [0u8; 256];
+    let mut i = 0;
+    while i < 256 {
+        let doubled = ((i << 1) ^ if i & 0x80 != 0 { 0x11d } else { 0 }) as u8;
+        low[i] = doubled;
+        high[(doubled ^ i as u8) as usize] = i as u8;
+        i += 1;
+    }
+    (low, high)
+};
+
+/// Byte `offset` of the area the parity covers, from the header on. Mode 2
+/// sectors compute parity as if their header were zero.
+fn ecc_source_byte(sector: &[u8], offset: usize) -> u8 {
+    if sector[MODE_OFFSET] == 2 && offset < 4 {
+        0
+    } else {
+        sector[SYNC.len() + offset]
+    }
+}
+
+/// Parity pair for one P column or Q diagonal
+fn ecc_compute_bytes(sector: &[u8], offsets: impl Iterator<Item = usize>) -> (u8, u8) {
+    let (low, high) = &ECC_TABLES;
+    let (mut val1, mut val2) = (0u8, 0u8);
+    for offset in offsets {
+        let byte = ecc_source_byte(sector, offset);
+        val1 ^= byte;
+        val2 ^= byte;
+        val1 = low[val1 as usize];
+    }
+    val1 = high[(low[val1 as usize] ^ val2) as usize];
+    (val1, val2 ^ val1)
+}
+
+/// Regenerate the P and Q parity of a raw Mode 1 or Mode 2 Form 1 sector
+pub(crate) fn ecc_generate(sector: &mut [u8]) {
+    // P parity covers 86 columns of 24 bytes
+    for byte in 0..ECC_P_NUM_BYTES {
+        let column = (0..ECC_P_COMP).map(|k| byte + ECC_P_NUM_BYTES * k);
+        let (p1, p2) = ecc_compute_bytes(sector, column);
+        sector[ECC_P_OFFSET + byte] = p1;
+        sector[ECC_P_OFFSET + ECC_P_NUM_BYTES + byte] = p2;
+    }
+    // Q parity covers 52 diagonals of 43 bytes, through the P parity
+    for byte in 0..ECC_Q_NUM_BYTES {
+        let (row, lsb) = (byte / 2, byte % 2);
+        let diagonal = (0..ECC_Q_COMP).map(|k| 2 * ((43 * row + 44 * k) % 1118) + lsb);
+        let (q1, q2) = ecc_compute_bytes(sector, diagonal);
+        sector[ECC_Q_OFFSET + byte] = q1;
+        sector[ECC_Q_OFFSET + ECC_Q_NUM_BYTES + byte] = q2;
+    }
+}
+
+/// What a track's sectors hold
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum TrackMode {
+    Audio,
+    Mode1,
+    /// Mode 2 XA, with the form set per sector; read as Form 1
+    Mode2,
+    Mode2Form1,
+    Mode2Form2,
+}
+
+impl TrackMode {
+    /// Name reported as "mode" metadata
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            TrackMode::Audio => "audio",
+            TrackMode::Mode1 => "mode1",
+            TrackMode::Mode2 => "mode2",
+            TrackMode::Mode2Form1 => "mode2_form1",
+            TrackMode::Mode2Form2 => "mode2_form2",
+        }
+    }
+
+    /// User data per sector, or None for audio
+    fn cooked_size(self) -> Option<usize> {
+        match self {
+            TrackMode::Audio => None,
+            TrackMode::Mode2Form2 => Some(FORM2_DATA_SIZE),
+            _ => Some(FORM1_DATA_SIZE),
+        }
+    }
+}
+
+/// Reader over the user data of a track's sectors
+pub(crate) struct CookedReader {
+    raw: Arc<dyn Reader + Send + Sync>,
+    /// Bytes per sector in `raw`
+    stored_size: usize,
+    cooked_size: usize,
+    sectors: u64,
+}
+
+impl CookedReader {
+    /// The user data of a stored sector. Raw sectors say their own mode,
+    /// so mixed-mode tracks cook correctly; mode 0 sectors hold no data.
+    fn user_data<'a>(&self, sector: &'a [u8]) -> &'a [u8] {
+        let start = match self.stored_size {
+            SECTOR_SIZE => match sector[MODE_OFFSET] {
+                1 => HEADER_SIZE,
+                2 => HEADER_SIZE + SUBHEADER_SIZE,
+                _ => return &[],
+            },
+            MODE2_SIZE => SUBHEADER_SIZE,
+            _ => 0,
+        };
+        &sector[start..(start + self.cooked_size).min(sector.len())]
+    }
+}
+
+impl Reader for CookedReader {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let cooked_size = self.cooked_size as u64;
+        if offset >= self.sectors * cooked_size {
+            return Ok(0);
+        }
+        let index = offset / cooked_size;
+        let in_sector = (offset % cooked_size) as usize;
+        let to_read = buf.len().min(self.cooked_size - in_sector);
+
+        let mut sector = vec![0u8; self.stored_size];
+        let mut done = 0;
+        while done < sector.len() {
+            let n = self.raw.read_at(
+                index * self.stored_size as u64 + done as u64,
+                &mut sector[done..],
+            )?;
+            if n == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "short CD sector read",
+                ));
+            }
+            done += n;
+        }
+
+        // Sectors without user data read as zeros
+        let data = self.user_data(&sector);
+        let from = data.len().min(in_sector);
+        let n = (data.len() - from).min(to_read);
+        buf[..n].copy_from_slice(&data[from..from + n]);
+        buf[n..to_read].fill(0);
+        Ok(to_read)
+    }
+
+    fn size(&self) -> Option<u64> {
+        Some(self.sectors * self.cooked_size as u64)
+    }
+}
+
+/// Add the children for one track: its sectors as stored (`raw`, at
+/// `offset` in the parent), then for data tracks stored with more than
+/// their user data, the user data alone. Both carry `metadata` and the
+/// track mode.
+pub(crate) fn track_children(
+    children: &mut Vec<Child>,
+    raw: Arc<dyn Reader + Send + Sync>,
+    offset: u64,
+    mode: TrackMode,
+    stored_size: usize,
+    metadata: Metadata,
+) {
+    let mut push = |reader, offset, view: &str, sector_size: usize| {
+        let mut metadata = metadata.clone();
+        metadata.push(("mode", mode.name().to_string()));
+        metadata.push(("sector_size", sector_size.to_string()));
+        metadata.push(("view", view.to_string()));
+        children.push(Child {
+            index: children.len() as u32,
+            offset,
+            reader,
+            metadata,
+        });
+    };
+
+    let cooked_size = mode.cooked_size().filter(|&size| size < stored_size);
+    match cooked_size {
+        Some(cooked_size) => {
+            let sectors = raw.size().unwrap_or(0) / stored_size as u64;
+            let cooked = CookedReader {
+                raw: Arc::clone(&raw),
+                stored_size,
+                cooked_size,
+                sectors,
+            };
+            push(raw, offset, "raw", stored_size);
+            // Transformed data, not a slice
+            push(Arc::new(cooked), u64::MAX, "cooked", cooked_size);
+        }
+        None if mode == TrackMode::Audio => push(raw, offset, "raw", stored_size),
+        None => push(raw, offset, "cooked", stored_size),
+    }
+}
+
+/// Reader over the sectors of stored frames that have subchannel data
+/// after each sector
+pub(crate) struct FrameReader {
+    file: Arc<dyn Reader + Send + Sync>,
+    /// Offset of the first frame in `file`
+    offset: u64,
+    frames: u64,
+    frame_size: u64,
+    sector_size: u64,
+}
+
+impl Reader for FrameReader {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        if offset >= self.frames * self.sector_size {
+            return Ok(0);
+        }
+        let frame = offset / self.sector_size;
+        let in_sector = offset % self.sector_size;
+        let to_read = buf.len().min((self.sector_size - in_sector) as usize);
+        self.file.read_at(
+            self.offset + frame * self.frame_size + in_sector,
+            &mut buf[..to_read],
+        )
+    }
+
+    fn size(&self) -> Option<u64> {
+        Some(self.frames * self.sector_size)
+    }
+}
+
+/// A track of a descriptor-plus-data image
+pub(crate) struct ImageTrack {
+    pub(crate) number: u32,
+    pub(crate) session: u32,
+    pub(crate) mode: TrackMode,
+    /// Data file holding the track's sectors
+    pub(crate) file: Arc<dyn Reader + Send + Sync>,
+    /// Offset in `file` of the frame at index 1
+    pub(crate) offset: u64,
+    /// Bytes of sector data at the start of each frame
+    pub(crate) sector_size: usize,
+    /// Bytes per stored frame: the sector, then any subchannel data
+    pub(crate) frame_size: usize,
+    /// Frames from index 1 to the end of the track
+    pub(crate) frames: u64,
+    /// Frames before index 1, stored or not
+    pub(crate) pregap: u64,
+}
+
+/// Children for the tracks of a descriptor-plus-data image, raw and
+/// cooked. Tracks are cut short where their data file ends, as truncated
+/// dumps often do.
+pub(crate) fn image_children(tracks: Vec<ImageTrack>) -> Vec<Child> {
+    let mut children = Vec::new();
+    for track in tracks {
+        let frame_size = track.frame_size as u64;
+        let available = track
+            .file
+            .size()
+            .map_or(0, |size| size.saturating_sub(track.offset) / frame_size);
+        let frames = track.frames.min(available);
+        let raw: Arc<dyn Reader + Send + Sync> = if track.frame_size == track.sector_size {
+            Arc::new(SliceReader::new(
+                track.file,
+                track.offset,
+                frames * frame_size,
+            ))
+        } else {
+            Arc::new(FrameReader {
+                file: track.file,
+                offset: track.offset,
+                frames,
+                frame_size,
+                sector_size: track.sector_size as u64,
+            })
+        };
+        let metadata = vec![
+            ("track", track.number.to_string()),
+            ("session", track.session.to_string()),
+            ("frames", frames.to_string()),
+            ("pregap", track.pregap.to_string()),
+        ];
+        // In a data file, not the descriptor
+        track_children(
+            &mut children,
+            raw,
+            u64::MAX,
+            track.mode,
+            track.sector_size,
+            metadata,
+        );
+    }
+    children
+}
+
+/// Read a text descriptor, without a byte order mark, replacing bytes
+/// that aren't UTF-8; sheets are often in a local code page
+pub(crate) fn read_descriptor(descriptor: &dyn Reader) -> io::Result<String> {
+    let size = descriptor.size().unwrap_or(MAX_DESCRIPTOR_SIZE);
+    if size > MAX_DESCRIPTOR_SIZE {
+        return Err(invalid_data("CD image descriptor too large"));
+    }
+    let mut text = vec![0u8; size as usize];
+    let mut done = 0;
+    while done < text.len() {
+        let n = descriptor.read_at(done as u64, &mut text[done..])?;
+        if n == 0 {
+            break;
+        }
+        done += n;
+    }
+    text.truncate(done);
+    let text = String::from_utf8_lossy(&text);
+    Ok(text.trim_start_matches('\u{feff}').to_string())
+}
+
+/// Split a descriptor line into words; a quoted word may hold spaces and
+/// is given without its quotes
+pub(crate) fn words(line: &str) -> Vec<&str> {
+    let mut words = Vec::new();
+    let mut rest = line.trim_start();
+    while !rest.is_empty() {
+        let (word, after) = match rest.strip_prefix('"') {
+            Some(quoted) => quoted.split_once('"').unwrap_or((quoted, "")),
+            None => rest.split_once(char::is_whitespace).unwrap_or((rest, "")),
+        };
+        words.push(word);
+        rest = after.trim_start();
+    }
+    words
+}
+
+/// Frames in an "mm:ss:ff" time
+pub(crate) fn parse_msf(text: &str) -> Option<u64> {
+    let mut parts = text.split(':').map(|part| part.parse::<u64>().ok());
+    let (Some(Some(minutes)), Some(Some(seconds)), Some(Some(frames)), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return None;
+    };
+    if seconds >= 60 || frames >= FRAMES_PER_SECOND {
+        return None;
+    }
+    let seconds = minutes.checked_mul(60)?.checked_add(seconds)?;
+    seconds.checked_mul(FRAMES_PER_SECOND)?.checked_add(frames)
+}
+
+/// Open a data file a descriptor names: beside the descriptor as named,
+/// then by the name's last component (sheets often keep the path on the
+/// machine that made them), then by that ignoring case
+pub(crate) fn open_data_file(
+    descriptor: &dyn Reader,
+    name: &str,
+) -> io::Result<Arc<dyn Reader + Send + Sync>> {
+    if let Ok(file) = descriptor.sibling(name) {
+        return Ok(file);
+    }
+    let base = name.rsplit(['/', '\\']).next().unwrap_or(name);
+    if let Ok(file) = descriptor.sibling(base) {
+        return Ok(file);
+    }
+    descriptor
+        .siblings()
+        .iter()
+        .find(|sibling| sibling.eq_ignore_ascii_case(base))
+        .map_or_else(
+            || Err(io::Error::from(io::ErrorKind::NotFound)),
+            |sibling| descriptor.sibling(sibling),
+        )
+        .map_err(|error| io::Error::new(error.kind(), "missing CD image data file"))
+}
+
+/// Open the file beside a descriptor with its name and `extension`, the
+/// way CloneCD and Alcohol name their data files
+pub(crate) fn open_companion(
+    descriptor: &dyn Reader,
+    extension: &str,
+) -> io::Result<Arc<dyn Reader + Send + Sync>> {
+    let stem = descriptor
+        .path()
+        .and_then(|path| path.file_stem())
+        .and_then(|stem| stem.to_str())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "missing CD image data file"))?;
+    open_data_file(descriptor, &format!("{stem}.{extension}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::{BytesReader, FilesReader};
+
+    fn raw_sector(mode: u8, submode: u8, fill: u8) -> Vec<u8> {
+        let mut sector = vec![fill; SECTOR_SIZE];
+        sector[..12].copy_from_slice(&SYNC);
+        sector[12..16].copy_from_slice(&[0, 2, 0, mode]);
+        if mode == 2 {
+            sector[16..24].copy_from_slice(&[0, 0, submode, 0, 0, 0, submode, 0]);
+        }
+        sector
+    }
+
+    fn tracks(data: Vec<u8>, mode: TrackMode, stored_size: usize) -> Vec<Child> {
+        let mut children = Vec::new();
+        let raw = Arc::new(BytesReader::new(data));
+        track_children(
+            &mut children,
+            raw,
+            0,
+            mode,
+            stored_size,
+            vec![("track", "1".to_string())],
+        );
+        children
+    }
+
+    fn read_all(reader: &dyn Reader) -> Vec<u8> {
+        let mut out = vec![0u8; reader.size().unwrap() as usize];
+        let mut done = 0;
+        while done < out.len() {
+            done += reader.read_at(done as u64, &mut out[done..]).unwrap();
+        }
+        out
+    }
+
+    #[test]
+    fn cooks_mixed_raw_sectors() {
+        // A Mode 1 sector, an XA Form 1 sector and an empty mode 0 sector
+        let data = [
+            raw_sector(1, 0, b'1'),
+            raw_sector(2, 0x08, b'2'),
+            raw_sector(0, 0, b'0'),
+        ]
+        .concat();
+        let children = tracks(data, TrackMode::Mode1, SECTOR_SIZE);
+        assert_eq!(children.len(), 2);
+        assert!(children[0].metadata.contains(&("view", "raw".to_string())));
+        assert!(children[1]
+            .metadata
+            .contains(&("mode", "mode1".to_string())));
+        assert_eq!(children[1].offset, u64::MAX);
+
+        let cooked = read_all(&*children[1].reader);
+        assert_eq!(cooked.len(), 3 * 2048);
+        assert!(cooked[..2048].iter().all(|&byte| byte == b'1'));
+        assert!(cooked[2048..4096].iter().all(|&byte| byte == b'2'));
+        assert!(cooked[4096..].iter().all(|&byte| byte == 0));
+    }
+
+    #[test]
+    fn cooks_mode2_without_header() {
+        let mut sector = vec![b'X'; MODE2_SIZE];
+        sector[..8].copy_from_slice(&[0, 0, 0x20, 0, 0, 0, 0x20, 0]);
+        let children = tracks(sector.repeat(2), TrackMode::Mode2Form2, MODE2_SIZE);
+        let cooked = read_all(&*children[1].reader);
+        assert_eq!(cooked, vec![b'X'; 2 * FORM2_DATA_SIZE]);
+
+        // A read straddling sectors stops at the sector end
+        let mut buf = [0u8; 16];
+        assert_eq!(children[1].reader.read_at(2320, &mut buf).unwrap(), 4);
+    }
+
+    #[test]
+    fn cooked_and_audio_tracks_have_one_view() {
+        let children = tracks(vec![0; 4096], TrackMode::Mode1, FORM1_DATA_SIZE);
+        assert_eq!(children.len(), 1);
+        assert!(children[0]
+            .metadata
+            .contains(&("view", "cooked".to_string())));
+
+        let children = tracks(vec![0; SECTOR_SIZE], TrackMode::Audio, SECTOR_SIZE);
+        assert_eq!(children.len(), 1);
+        assert!(children[0]
+            .metadata
+            .contains(&("mode", "audio".to_string())));
+    }
+
+    #[test]
+    fn parses_descriptor_words_and_times() {
+        assert_eq!(
+            words(r#"  FILE "My Disc (Track 1).bin" BINARY"#),
+            ["FILE", "My Disc (Track 1).bin", "BINARY"]
+        );
+        assert_eq!(parse_msf("01:02:03"), Some((60 + 2) * 75 + 3));
+        assert_eq!(parse_msf("00:60:00"), None);
+        assert_eq!(parse_msf("12"), None);
+    }
+
+    #[test]
+    fn resolves_data_files() {
+        let files = FilesReader::open(
+            "disc.cue",
+            vec![
+                ("disc.cue", Vec::new()),
+                ("Disc.BIN", vec![1]),
+                ("disc.img", vec![2, 2]),
+            ],
+        );
+        let size = |file: io::Result<Arc<dyn Reader + Send + Sync>>| file.unwrap().size();
+        assert_eq!(size(open_data_file(&*files, r"C:\dumps\disc.bin")), Some(1));
+        assert_eq!(size(open_companion(&*files, "img")), Some(2));
+        assert!(open_data_file(&*files, "other.bin").is_err());
+    }
+}
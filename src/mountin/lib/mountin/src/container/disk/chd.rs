@@ -0,0 +1,1479 @@
+//! MAME CHD (Compressed Hunks of Data) reader
+//!
+//! A CHD splits a hard disk or CD-ROM image into fixed-size hunks, each
+//! compressed on its own with one of up to four codecs chosen for the
+//! image, and found through a map that version 5 stores Huffman coded.
+//! Hunks may repeat another hunk or come from a parent CHD, which is
+//! looked for among the image's siblings by its SHA-1.
+//!
+//! CD-ROM images store 2448-byte frames, sector data followed by
+//! subcode, with the track layout in metadata; each track is a child.
+//! Hard disk images are a single child with their geometry.
+
+use crate::container::digest::{self, Sha1};
+use crate::container::disk::backing::{self, Backing, Layered};
+use crate::container::disk::cdrom;
+use crate::container::{
+    be16, be32, be64, checked_table_size, invalid_data, verify_hashes, BlockCache, Child,
+    Container, Metadata,
+};
+use crate::detect::Reader;
+use flate2::read::DeflateDecoder;
+use std::io::{self, Read};
+use std::sync::{Arc, Mutex};
+
+const MAGIC: &[u8; 8] = b"MComprHD";
+const V3_HEADER_SIZE: usize = 120;
+const V4_HEADER_SIZE: usize = 108;
+const V5_HEADER_SIZE: usize = 124;
+
+/// v3/v4 flag: the image has a parent
+const FLAG_HAS_PARENT: u32 = 1;
+
+const fn tag(name: &[u8; 4]) -> u32 {
+    u32::from_be_bytes(*name)
+}
+
+const CODEC_NONE: u32 = 0;
+const CODEC_ZLIB: u32 = tag(b"zlib");
+const CODEC_LZMA: u32 = tag(b"lzma");
+const CODEC_HUFFMAN: u32 = tag(b"huff");
+const CODEC_CD_ZLIB: u32 = tag(b"cdzl");
+const CODEC_CD_LZMA: u32 = tag(b"cdlz");
+
+const HARD_DISK_METADATA: u32 = tag(b"GDDD");
+const CDROM_TRACK_METADATA: u32 = tag(b"CHTR");
+const CDROM_TRACK_METADATA2: u32 = tag(b"CHT2");
+const GDROM_TRACK_METADATA: u32 = tag(b"CHGD");
+
+/// v3/v4 map entry types
+const V34_COMPRESSED: u8 = 1;
+const V34_UNCOMPRESSED: u8 = 2;
+const V34_MINI: u8 = 3;
+const V34_SELF_HUNK: u8 = 4;
+const V34_PARENT_HUNK: u8 = 5;
+const V34_NO_CRC: u8 = 0x10;
+
+/// v5 compressed map entry types; 0-3 are the image's codecs
+const COMPRESSION_NONE: u8 = 4;
+const COMPRESSION_SELF: u8 = 5;
+const COMPRESSION_PARENT: u8 = 6;
+const COMPRESSION_RLE_SMALL: u8 = 7;
+const COMPRESSION_RLE_LARGE: u8 = 8;
+const COMPRESSION_SELF_0: u8 = 9;
+const COMPRESSION_SELF_1: u8 = 10;
+const COMPRESSION_PARENT_SELF: u8 = 11;
+const COMPRESSION_PARENT_0: u8 = 12;
+const COMPRESSION_PARENT_1: u8 = 13;
+
+/// Sector data and subcode of one CD frame
+const FRAME_SIZE: u64 = 2448;
+const SUBCODE_SIZE: usize = 96;
+/// CD tracks are padded to a multiple of this many frames
+const TRACK_PADDING: u64 = 4;
+
+/// Largest hunk accepted
+const MAX_HUNK_SIZE: u32 = 16 * 1024 * 1024;
+/// Most hunks an image may have
+const MAX_HUNKS: u64 = 1 << 24;
+/// Most metadata entries followed
+const MAX_METADATA: usize = 1024;
+/// Largest metadata entry read
+const MAX_METADATA_SIZE: usize = 64 * 1024;
+/// Longest chain of hunks copying other hunks
+const MAX_SELF_DEPTH: usize = 16;
+/// Decoded hunks kept in memory
+const CACHE_HUNKS: usize = 16;
+
+/// CHD container
+pub struct ChdContainer;
+
+/// Static instance for registry
+pub static CHD: ChdContainer = ChdContainer;
+
+impl Container for ChdContainer {
+    fn children(&self, reader: Arc<dyn Reader + Send + Sync>) -> io::Result<Vec<Child>> {
+        let mut chd_reader = ChdReader::new(reader)?;
+        chd_reader.open_backing(0);
+        let entries = read_metadata(&*chd_reader.file, chd_reader.header.meta_offset)?;
+
+        let mut common = chd_reader.chain_metadata();
+        if let Some(sha1) = chd_reader.header.raw_sha1 {
+            common.push(("sha1", digest::hex(&sha1)));
+        }
+        if verify_hashes() {
+            common.extend(chd_reader.verify()?);
+        }
+
+        let tracks = cd_tracks(&entries)?;
+        let disk = Arc::new(chd_reader);
+        if tracks.is_empty() {
+            let mut metadata = hard_disk_metadata(&entries);
+            metadata.extend(common);
+            return Ok(vec![Child {
+                index: 0,
+                offset: 0,
+                reader: disk,
+                metadata,
+            }]);
+        }
+
+        let mut children = Vec::new();
+        let mut frame = 0u64;
+        for track in &tracks {
+            // A pregap stored in the image comes before the track's data
+            let stored_pregap = if track.pregap_stored { track.pregap } else { 0 };
+            let start = (frame + stored_pregap) * FRAME_SIZE;
+            let frames = track.frames.saturating_sub(stored_pregap);
+            let mut metadata = vec![
+                ("track", track.number.to_string()),
+                ("type", track.kind.clone()),
+                ("frames", frames.to_string()),
+                ("pregap", track.pregap.to_string()),
+            ];
+            metadata.extend(common.iter().cloned());
+            let reader = TrackReader {
+                disk: Arc::clone(&disk),
+                start,
+                frames,
+                sector_size: track.sector_size,
+            };
+            cdrom::track_children(
+                &mut children,
+                Arc::new(reader),
+                start,
+                track.mode,
+                track.sector_size as usize,
+                metadata,
+            );
+            frame += track.frames + track.padding;
+        }
+        Ok(children)
+    }
+}
+
+/// Header fields, unified across versions
+#[derive(Debug)]
+struct Header {
+    version: u32,
+    /// Codecs a v5 hunk may use, by number; v3/v4 use only the first
+    compressors: [u32; 4],
+    logical_bytes: u64,
+    total_hunks: u64,
+    map_offset: u64,
+    meta_offset: u64,
+    hunk_bytes: u32,
+    /// v5 parent references count in units of this many bytes
+    unit_bytes: u32,
+    /// SHA-1 of the data and metadata, which children record for their parent
+    sha1: [u8; 20],
+    /// SHA-1 of the data alone
+    raw_sha1: Option<[u8; 20]>,
+    parent_sha1: Option<[u8; 20]>,
+}
+
+fn be48(bytes: &[u8]) -> u64 {
+    bytes[..6]
+        .iter()
+        .fold(0, |value, &byte| value << 8 | u64::from(byte))
+}
+
+fn sha1_at(bytes: &[u8], offset: usize) -> [u8; 20] {
+    bytes[offset..offset + 20].try_into().unwrap()
+}
+
+fn read_exact(
+    reader: &dyn Reader,
+    offset: u64,
+    buf: &mut [u8],
+    what: &'static str,
+) -> io::Result<()> {
+    if reader.read_at(offset, buf)? != buf.len() {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, what));
+    }
+    Ok(())
+}
+
+fn parse_header(reader: &dyn Reader) -> io::Result<Header> {
+    let mut bytes = [0u8; V5_HEADER_SIZE];
+    let n = reader.read_at(0, &mut bytes)?;
+    if n < 16 || &bytes[..8] != MAGIC {
+        return Err(invalid_data("invalid CHD signature"));
+    }
+    let length = be32(&bytes[8..]) as usize;
+    let version = be32(&bytes[12..]);
+    let expected = match version {
+        1 | 2 => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "CHD versions before 3 are not supported",
+            ))
+        }
+        3 => V3_HEADER_SIZE,
+        4 => V4_HEADER_SIZE,
+        5 => V5_HEADER_SIZE,
+        _ => return Err(invalid_data("unknown CHD version")),
+    };
+    if length != expected {
+        return Err(invalid_data("invalid CHD header length"));
+    }
+    if n < length {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "short CHD header read",
+        ));
+    }
+
+    let header = if version == 5 {
+        let parent_sha1 = sha1_at(&bytes, 104);
+        let hunk_bytes = be32(&bytes[56..]);
+        let logical_bytes = be64(&bytes[32..]);
+        Header {
+            version,
+            compressors: std::array::from_fn(|i| be32(&bytes[16 + 4 * i..])),
+            logical_bytes,
+            total_hunks: logical_bytes.div_ceil(u64::from(hunk_bytes.max(1))),
+            map_offset: be64(&bytes[40..]),
+            meta_offset: be64(&bytes[48..]),
+            hunk_bytes,
+            unit_bytes: be32(&bytes[60..]),
+            sha1: sha1_at(&bytes, 84),
+            raw_sha1: Some(sha1_at(&bytes, 64)),
+            parent_sha1: (parent_sha1 != [0; 20]).then_some(parent_sha1),
+        }
+    } else {
+        let flags = be32(&bytes[16..]);
+        let codec = match be32(&bytes[20..]) {
+            0 => CODEC_NONE,
+            1 | 2 => CODEC_ZLIB,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "unsupported CHD compression",
+                ))
+            }
+        };
+        let (hunk_bytes, sha1, parent_sha1, raw_sha1) = if version == 3 {
+            let sha1 = sha1_at(&bytes, 80);
+            (be32(&bytes[76..]), sha1, sha1_at(&bytes, 100), sha1)
+        } else {
+            (
+                be32(&bytes[44..]),
+                sha1_at(&bytes, 48),
+                sha1_at(&bytes, 68),
+                sha1_at(&bytes, 88),
+            )
+        };
+        Header {
+            version,
+            compressors: [codec, CODEC_NONE, CODEC_NONE, CODEC_NONE],
+            logical_bytes: be64(&bytes[28..]),
+            total_hunks: u64::from(be32(&bytes[24..])),
+            map_offset: length as u64,
+            meta_offset: be64(&bytes[36..]),
+            hunk_bytes,
+            unit_bytes: hunk_bytes,
+            sha1,
+            raw_sha1: Some(raw_sha1),
+            parent_sha1: (flags & FLAG_HAS_PARENT != 0).then_some(parent_sha1),
+        }
+    };
+
+    if header.hunk_bytes == 0 || header.hunk_bytes > MAX_HUNK_SIZE {
+        return Err(invalid_data("invalid CHD hunk size"));
+    }
+    if header.unit_bytes == 0 || header.unit_bytes > header.hunk_bytes {
+        return Err(invalid_data("invalid CHD unit size"));
+    }
+    if header.total_hunks > MAX_HUNKS
+        || header.total_hunks * u64::from(header.hunk_bytes) < header.logical_bytes
+    {
+        return Err(invalid_data("invalid CHD hunk count"));
+    }
+    Ok(header)
+}
+
+/// A stored CRC of a hunk's data
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Check {
+    /// v5: CRC-16-CCITT
+    Crc16(u16),
+    /// v3/v4: CRC-32
+    Crc32(u32),
+}
+
+/// Where a hunk's data comes from
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Hunk {
+    /// Compressed with the image's codec number `codec`
+    Compressed {
+        codec: u8,
+        offset: u64,
+        length: u32,
+        check: Option<Check>,
+    },
+    Uncompressed {
+        offset: u64,
+        check: Option<Check>,
+    },
+    /// Eight bytes repeated over the hunk
+    Mini {
+        pattern: [u8; 8],
+        check: Option<Check>,
+    },
+    /// A copy of another hunk in the image
+    Copy(u64),
+    /// Data at a byte offset in the parent, or zeros without one
+    Parent(u64),
+}
+
+/// v3/v4 map: 16 bytes per hunk following the header
+fn read_map_v34(reader: &dyn Reader, header: &Header) -> io::Result<Vec<Hunk>> {
+    let size = checked_table_size(reader, header.map_offset, header.total_hunks, 16)?;
+    let mut table = vec![0u8; size];
+    read_exact(reader, header.map_offset, &mut table, "short CHD map read")?;
+
+    table
+        .chunks_exact(16)
+        .map(|entry| {
+            let offset = be64(entry);
+            let length = u32::from(be16(&entry[12..])) | u32::from(entry[14]) << 16;
+            let flags = entry[15];
+            let check = (flags & V34_NO_CRC == 0).then_some(Check::Crc32(be32(&entry[8..])));
+            Ok(match flags & 0xf {
+                V34_COMPRESSED => Hunk::Compressed {
+                    codec: 0,
+                    offset,
+                    length,
+                    check,
+                },
+                V34_UNCOMPRESSED => Hunk::Uncompressed { offset, check },
+                V34_MINI => Hunk::Mini {
+                    pattern: entry[..8].try_into().unwrap(),
+                    check,
+                },
+                V34_SELF_HUNK => Hunk::Copy(offset),
+                V34_PARENT_HUNK => Hunk::Parent(
+                    offset
+                        .checked_mul(u64::from(header.hunk_bytes))
+                        .ok_or_else(|| invalid_data("invalid CHD parent hunk"))?,
+                ),
+                _ => return Err(invalid_data("unsupported CHD map entry")),
+            })
+        })
+        .collect()
+}
+
+/// v5 map of an uncompressed image: the hunk number of each hunk in the
+/// file, or 0 for the parent's
+fn read_map_v5_uncompressed(reader: &dyn Reader, header: &Header) -> io::Result<Vec<Hunk>> {
+    let size = checked_table_size(reader, header.map_offset, header.total_hunks, 4)?;
+    let mut table = vec![0u8; size];
+    read_exact(reader, header.map_offset, &mut table, "short CHD map read")?;
+
+    let hunk_bytes = u64::from(header.hunk_bytes);
+    Ok(table
+        .chunks_exact(4)
+        .enumerate()
+        .map(|(hunk, entry)| match be32(entry) {
+            0 => Hunk::Parent(hunk as u64 * hunk_bytes),
+            block => Hunk::Uncompressed {
+                offset: u64::from(block) * hunk_bytes,
+                check: None,
+            },
+        })
+        .collect())
+}
+
+/// v5 compressed map: a Huffman-coded entry type per hunk, run-length
+/// coded, then the fields each type needs, packed to the bit widths in the
+/// map header. Entries are checked against a CRC of the 12-byte form MAME
+/// keeps them in.
+fn read_map_v5_compressed(reader: &dyn Reader, header: &Header) -> io::Result<Vec<Hunk>> {
+    let mut map_header = [0u8; 16];
+    read_exact(
+        reader,
+        header.map_offset,
+        &mut map_header,
+        "short CHD map header read",
+    )?;
+    let map_bytes = be32(&map_header);
+    let first_offset = be48(&map_header[4..]);
+    let map_crc = be16(&map_header[10..]);
+    let (length_bits, self_bits, parent_bits) = (map_header[12], map_header[13], map_header[14]);
+    if length_bits > 32 || self_bits > 32 || parent_bits > 32 {
+        return Err(invalid_data("invalid CHD map header"));
+    }
+
+    let size = checked_table_size(reader, header.map_offset + 16, u64::from(map_bytes), 1)?;
+    let mut compressed = vec![0u8; size];
+    read_exact(
+        reader,
+        header.map_offset + 16,
+        &mut compressed,
+        "short CHD map read",
+    )?;
+    let mut bits = BitReader::new(&compressed);
+
+    let mut types = Huffman::new(16, 8);
+    types.import_rle(&mut bits)?;
+    let hunks = header.total_hunks as usize;
+    let mut kinds = Vec::with_capacity(hunks);
+    let (mut repeat, mut last) = (0u32, 0u8);
+    while kinds.len() < hunks {
+        if repeat > 0 {
+            repeat -= 1;
+        } else {
+            match types.decode(&mut bits) as u8 {
+                COMPRESSION_RLE_SMALL => repeat = 2 + types.decode(&mut bits),
+                COMPRESSION_RLE_LARGE => {
+                    repeat = 2 + 16 + (types.decode(&mut bits) << 4);
+                    repeat += types.decode(&mut bits);
+                }
+                kind => last = kind,
+            }
+        }
+        kinds.push(last);
+    }
+
+    let hunk_bytes = u64::from(header.hunk_bytes);
+    let unit_bytes = u64::from(header.unit_bytes);
+    let hunk_units = hunk_bytes / unit_bytes;
+    let mut map = Vec::with_capacity(hunks);
+    let mut raw_map = Vec::with_capacity(hunks * 12);
+    let (mut offset, mut last_self, mut last_parent) = (first_offset, 0u64, 0u64);
+    for (hunk, &kind) in kinds.iter().enumerate() {
+        let (raw_kind, length, entry_offset, crc) = match kind {
+            0..=3 => {
+                let length = bits.read(length_bits);
+                let crc = bits.read(16) as u16;
+                map.push(Hunk::Compressed {
+                    codec: kind,
+                    offset,
+                    length,
+                    check: Some(Check::Crc16(crc)),
+                });
+                offset += u64::from(length);
+                (kind, length, offset - u64::from(length), crc)
+            }
+            COMPRESSION_NONE => {
+                let crc = bits.read(16) as u16;
+                map.push(Hunk::Uncompressed {
+                    offset,
+                    check: Some(Check::Crc16(crc)),
+                });
+                offset += hunk_bytes;
+                (kind, header.hunk_bytes, offset - hunk_bytes, crc)
+            }
+            COMPRESSION_SELF | COMPRESSION_SELF_0 | COMPRESSION_SELF_1 => {
+                match kind {
+                    COMPRESSION_SELF => last_self = u64::from(bits.read(self_bits)),
+                    COMPRESSION_SELF_1 => last_self += 1,
+                    _ => {}
+                }
+                map.push(Hunk::Copy(last_self));
+                (COMPRESSION_SELF, 0, last_self, 0)
+            }
+            COMPRESSION_PARENT
+            | COMPRESSION_PARENT_SELF
+            | COMPRESSION_PARENT_0
+            | COMPRESSION_PARENT_1 => {
+                match kind {
+                    COMPRESSION_PARENT => last_parent = u64::from(bits.read(parent_bits)),
+                    COMPRESSION_PARENT_SELF => last_parent = hunk as u64 * hunk_units,
+                    COMPRESSION_PARENT_1 => last_parent += hunk_units,
+                    _ => {}
+                }
+                map.push(Hunk::Parent(last_parent * unit_bytes));
+                (COMPRESSION_PARENT, 0, last_parent, 0)
+            }
+            _ => return Err(invalid_data("invalid CHD map entry type")),
+        };
+        raw_map.push(raw_kind);
+        raw_map.extend_from_slice(&length.to_be_bytes()[1..]);
+        raw_map.extend_from_slice(&entry_offset.to_be_bytes()[2..]);
+        raw_map.extend_from_slice(&crc.to_be_bytes());
+    }
+
+    if bits.overflowed() {
+        return Err(invalid_data("truncated CHD map"));
+    }
+    if crc16(&raw_map) != map_crc {
+        return Err(invalid_data("CHD map CRC mismatch"));
+    }
+    Ok(map)
+}
+
+/// CRC-16-CCITT, as v5 uses for hunks and the map
+fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0xffff, |mut crc, &byte| {
+        crc ^= u16::from(byte) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                crc << 1 ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
+
+/// Reads a byte string as bits, most significant first. Reads past the end
+/// return zeros and mark the stream overflowed.
+struct BitReader<'a> {
+    data: &'a [u8],
+    /// Position in bits
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    /// The next `count` bits (at most 32) without consuming them
+    fn peek(&self, count: u8) -> u32 {
+        if count == 0 {
+            return 0;
+        }
+        let start = self.position / 8;
+        let window = (0..5).fold(0u64, |window, i| {
+            window << 8 | u64::from(self.data.get(start + i).copied().unwrap_or(0))
+        });
+        let shift = 40 - self.position % 8 - usize::from(count);
+        (window >> shift) as u32 & (u32::MAX >> (32 - count))
+    }
+
+    fn skip(&mut self, count: u8) {
+        self.position += usize::from(count);
+    }
+
+    fn read(&mut self, count: u8) -> u32 {
+        let value = self.peek(count);
+        self.skip(count);
+        value
+    }
+
+    fn overflowed(&self) -> bool {
+        self.position > self.data.len() * 8
+    }
+}
+
+/// Canonical Huffman decoder, as MAME builds them: the code lengths are
+/// imported from the stream and the codes assigned from them
+struct Huffman {
+    max_bits: u8,
+    /// Code length of each symbol
+    lengths: Vec<u8>,
+    /// Symbol and code length, indexed by the next `max_bits` bits
+    lookup: Vec<(u16, u8)>,
+}
+
+impl Huffman {
+    fn new(codes: usize, max_bits: u8) -> Self {
+        Self {
+            max_bits,
+            lengths: vec![0; codes],
+            lookup: Vec::new(),
+        }
+    }
+
+    /// Code lengths as a run-length coded list
+    fn import_rle(&mut self, bits: &mut BitReader) -> io::Result<()> {
+        let width = match self.max_bits {
+            16.. => 5,
+            8.. => 4,
+            _ => 3,
+        };
+        let mut code = 0;
+        while code < self.lengths.len() {
+            let mut length = bits.read(width) as u8;
+            let mut repeat = 1;
+            // 1 escapes a run; 1 1 is a single length of 1
+            if length == 1 {
+                length = bits.read(width) as u8;
+                if length != 1 {
+                    repeat = bits.read(width) as usize + 3;
+                }
+            }
+            if code + repeat > self.lengths.len() {
+                return Err(invalid_data("invalid CHD Huffman tree"));
+            }
+            self.lengths[code..code + repeat].fill(length);
+            code += repeat;
+        }
+        self.build(bits)
+    }
+
+    /// Code lengths themselves Huffman coded, with a small tree first
+    fn import_tree(&mut self, bits: &mut BitReader) -> io::Result<()> {
+        let mut small = Huffman::new(24, 6);
+        small.lengths[0] = bits.read(3) as u8;
+        let start = bits.read(3) as usize + 1;
+        let mut count = 0;
+        for index in start..24 {
+            if count == 7 {
+                break;
+            }
+            count = bits.read(3);
+            small.lengths[index] = if count == 7 { 0 } else { count as u8 };
+        }
+        small.build(bits)?;
+
+        let full_bits = (usize::BITS - (self.lengths.len() - 9).leading_zeros()) as u8;
+        let (mut code, mut last) = (0, 0);
+        while code < self.lengths.len() {
+            let value = small.decode(bits);
+            if value != 0 {
+                last = value as u8 - 1;
+                self.lengths[code] = last;
+                code += 1;
+            } else {
+                let mut count = bits.read(3) as usize + 2;
+                if count == 7 + 2 {
+                    count += bits.read(full_bits) as usize;
+                }
+                let end = (code + count).min(self.lengths.len());
+                self.lengths[code..end].fill(last);
+                code = end;
+            }
+        }
+        self.build(bits)
+    }
+
+    /// Assign canonical codes to the lengths and fill the lookup table
+    fn build(&mut self, bits: &BitReader) -> io::Result<()> {
+        if bits.overflowed() {
+            return Err(invalid_data("truncated CHD Huffman tree"));
+        }
+        let mut starts = [0u32; 33];
+        for &length in &self.lengths {
+            if length > self.max_bits {
+                return Err(invalid_data("invalid CHD Huffman tree"));
+            }
+            starts[usize::from(length)] += 1;
+        }
+        // Codes of each length follow on from the longer ones
+        let mut start = 0u32;
+        for length in (1..=32).rev() {
+            let next = (start + starts[length]) >> 1;
+            if length != 1 && next * 2 != start + starts[length] {
+                return Err(invalid_data("invalid CHD Huffman tree"));
+            }
+            starts[length] = start;
+            start = next;
+        }
+
+        self.lookup = vec![(0, 0); 1 << self.max_bits];
+        for (symbol, &length) in self.lengths.iter().enumerate() {
+            if length == 0 {
+                continue;
+            }
+            let code = starts[usize::from(length)];
+            starts[usize::from(length)] += 1;
+            let shift = self.max_bits - length;
+            let first = (code as usize) << shift;
+            let end = ((code as usize) + 1) << shift;
+            if end > self.lookup.len() {
+                return Err(invalid_data("invalid CHD Huffman tree"));
+            }
+            self.lookup[first..end].fill((symbol as u16, length));
+        }
+        Ok(())
+    }
+
+    fn decode(&self, bits: &mut BitReader) -> u32 {
+        let (symbol, length) = self.lookup[bits.peek(self.max_bits) as usize];
+        bits.skip(length);
+        u32::from(symbol)
+    }
+}
+
+/// Raw deflate, as the zlib codecs store it
+fn inflate(src: &[u8], len: usize) -> io::Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(len);
+    DeflateDecoder::new(src)
+        .take(len as u64)
+        .read_to_end(&mut out)?;
+    if out.len() != len {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "short CHD hunk",
+        ));
+    }
+    Ok(out)
+}
+
+/// Dictionary size MAME's encoder picks for `len` bytes: that of level 9,
+/// shrunk to the smallest 2^n or 3*2^n that holds the data
+fn lzma_dict_size(len: usize) -> u32 {
+    let len = len as u64;
+    (11..=30)
+        .flat_map(|shift| [2u64 << shift, 3u64 << shift])
+        .find(|&size| len <= size)
+        .map_or(1 << 26, |size| size.min(1 << 26) as u32)
+}
+
+/// Raw LZMA stream with MAME's fixed properties (lc=3, lp=0, pb=2)
+fn lzma(src: &[u8], len: usize) -> io::Result<Vec<u8>> {
+    let mut header = [0x5d, 0, 0, 0, 0];
+    header[1..].copy_from_slice(&lzma_dict_size(len).to_le_bytes());
+    let options = lzma_rs::decompress::Options {
+        unpacked_size: lzma_rs::decompress::UnpackedSize::UseProvided(Some(len as u64)),
+        ..Default::default()
+    };
+    let mut out = Vec::with_capacity(len);
+    lzma_rs::lzma_decompress_with_options(&mut (&header[..]).chain(src), &mut out, &options)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+    if out.len() != len {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "short CHD hunk",
+        ));
+    }
+    Ok(out)
+}
+
+/// A Huffman tree over byte values, then the bytes
+fn huffman(src: &[u8], len: usize) -> io::Result<Vec<u8>> {
+    let mut bits = BitReader::new(src);
+    let mut tree = Huffman::new(256, 16);
+    tree.import_tree(&mut bits)?;
+    let out: Vec<u8> = (0..len).map(|_| tree.decode(&mut bits) as u8).collect();
+    if bits.overflowed() {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "short CHD hunk",
+        ));
+    }
+    Ok(out)
+}
+
+/// CD codecs compress the sector data of all frames with a base codec and
+/// the subcode with deflate, after a bitmap of sectors whose sync header
+/// and ECC were dropped to be regenerated
+fn cd_decompress(
+    src: &[u8],
+    len: usize,
+    base: fn(&[u8], usize) -> io::Result<Vec<u8>>,
+) -> io::Result<Vec<u8>> {
+    let frames = len / FRAME_SIZE as usize;
+    let length_bytes = if len < 65536 { 2 } else { 3 };
+    let ecc_bytes = frames.div_ceil(8);
+    let header_bytes = ecc_bytes + length_bytes;
+    if src.len() < header_bytes {
+        return Err(invalid_data("short CHD CD hunk"));
+    }
+    let base_len = src[ecc_bytes..header_bytes]
+        .iter()
+        .fold(0usize, |value, &byte| value << 8 | usize::from(byte));
+    let (base_src, subcode_src) = src[header_bytes..]
+        .split_at_checked(base_len)
+        .ok_or_else(|| invalid_data("short CHD CD hunk"))?;
+    let sectors = base(base_src, frames * cdrom::SECTOR_SIZE)?;
+    let subcode = inflate(subcode_src, frames * SUBCODE_SIZE)?;
+
+    let mut out = vec![0u8; len];
+    for (frame, dest) in out.chunks_exact_mut(FRAME_SIZE as usize).enumerate() {
+        let (sector, sub) = dest.split_at_mut(cdrom::SECTOR_SIZE);
+        sector.copy_from_slice(&sectors[frame * cdrom::SECTOR_SIZE..][..cdrom::SECTOR_SIZE]);
+        sub.copy_from_slice(&subcode[frame * SUBCODE_SIZE..][..SUBCODE_SIZE]);
+        if src[frame / 8] & (1 << (frame % 8)) != 0 {
+            sector[..cdrom::SYNC.len()].copy_from_slice(&cdrom::SYNC);
+            cdrom::ecc_generate(sector);
+        }
+    }
+    Ok(out)
+}
+
+/// Reader over a CHD's logical bytes
+pub struct ChdReader {
+    file: Arc<dyn Reader + Send + Sync>,
+    header: Header,
+    map: Vec<Hunk>,
+    backing: Option<Backing>,
+    cache: Mutex<BlockCache>,
+}
+
+impl ChdReader {
+    pub fn new(file: Arc<dyn Reader + Send + Sync>) -> io::Result<Self> {
+        let header = parse_header(&*file)?;
+        let map = match header.version {
+            3 | 4 => read_map_v34(&*file, &header)?,
+            _ if header.compressors[0] == CODEC_NONE => read_map_v5_uncompressed(&*file, &header)?,
+            _ => read_map_v5_compressed(&*file, &header)?,
+        };
+        Ok(Self {
+            file,
+            header,
+            map,
+            backing: None,
+            cache: Mutex::new(BlockCache::new(CACHE_HUNKS)),
+        })
+    }
+
+    fn hunk(&self, index: u64) -> io::Result<Arc<Vec<u8>>> {
+        let cached = self
+            .cache
+            .lock()
+            .map_err(|_| io::Error::other("CHD hunk cache lock poisoned"))?
+            .get(index);
+        if let Some(data) = cached {
+            return Ok(data);
+        }
+        let data = Arc::new(self.decode_hunk(index, 0)?);
+        self.cache
+            .lock()
+            .map_err(|_| io::Error::other("CHD hunk cache lock poisoned"))?
+            .insert(index, Arc::clone(&data));
+        Ok(data)
+    }
+
+    fn decode_hunk(&self, index: u64, depth: usize) -> io::Result<Vec<u8>> {
+        let len = self.header.hunk_bytes as usize;
+        let entry = self.map.get(index as usize).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "hunk index out of range")
+        })?;
+
+        let (data, check) = match *entry {
+            Hunk::Compressed {
+                codec,
+                offset,
+                length,
+                check,
+            } => {
+                if length as usize > len * 2 {
+                    return Err(invalid_data("invalid CHD hunk length"));
+                }
+                let mut src = vec![0u8; length as usize];
+                read_exact(&*self.file, offset, &mut src, "short CHD hunk read")?;
+                let data = match self.header.compressors[usize::from(codec)] {
+                    CODEC_ZLIB => inflate(&src, len)?,
+                    CODEC_LZMA => lzma(&src, len)?,
+                    CODEC_HUFFMAN => huffman(&src, len)?,
+                    CODEC_CD_ZLIB => cd_decompress(&src, len, inflate)?,
+                    CODEC_CD_LZMA => cd_decompress(&src, len, lzma)?,
+                    _ => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::Unsupported,
+                            "unsupported CHD codec",
+                        ))
+                    }
+                };
+                (data, check)
+            }
+            Hunk::Uncompressed { offset, check } => {
+                let mut data = vec![0u8; len];
+                read_exact(&*self.file, offset, &mut data, "short CHD hunk read")?;
+                (data, check)
+            }
+            Hunk::Mini { pattern, check } => {
+                (pattern.iter().copied().cycle().take(len).collect(), check)
+            }
+            Hunk::Copy(other) => {
+                if other == index || depth >= MAX_SELF_DEPTH {
+                    return Err(invalid_data("invalid CHD self reference"));
+                }
+                return self.decode_hunk(other, depth + 1);
+            }
+            Hunk::Parent(offset) => {
+                let mut data = vec![0u8; len];
+                backing::read_backing(self.backing.as_ref(), offset, &mut data)?;
+                return Ok(data);
+            }
+        };
+
+        let matches = match check {
+            Some(Check::Crc16(crc)) => crc16(&data) == crc,
+            Some(Check::Crc32(crc)) => {
+                let mut sum = flate2::Crc::new();
+                sum.update(&data);
+                sum.sum() == crc
+            }
+            None => true,
+        };
+        if !matches {
+            return Err(invalid_data("CHD hunk CRC mismatch"));
+        }
+        Ok(data)
+    }
+
+    /// Hash the data and compare it with the stored SHA-1
+    fn verify(&self) -> io::Result<Metadata> {
+        let Some(stored) = self.header.raw_sha1 else {
+            return Ok(Vec::new());
+        };
+        let mut sha1 = Sha1::new();
+        let hunk_bytes = u64::from(self.header.hunk_bytes);
+        for index in 0..self.header.total_hunks {
+            let len = (self.header.logical_bytes - index * hunk_bytes).min(hunk_bytes);
+            sha1.update(&self.decode_hunk(index, 0)?[..len as usize]);
+        }
+        Ok(vec![(
+            "sha1_verified",
+            (sha1.finish() == stored).to_string(),
+        )])
+    }
+}
+
+/// Whether `file` is a CHD whose SHA-1 is `sha1`
+fn has_sha1(file: &dyn Reader, sha1: &[u8; 20]) -> bool {
+    parse_header(file).is_ok_and(|header| header.sha1 == *sha1)
+}
+
+impl Layered for ChdReader {
+    fn open_backing(&mut self, depth: usize) {
+        let Some(sha1) = self.header.parent_sha1 else {
+            return;
+        };
+        // Children record their parent by hash, not by name
+        let nearby = self
+            .file
+            .siblings()
+            .into_iter()
+            .filter(|name| name.to_ascii_lowercase().ends_with(".chd"));
+        for name in nearby {
+            if let Ok(file) = self.file.sibling(&name) {
+                if has_sha1(&*file, &sha1) {
+                    self.backing = Some(backing::open_file(file, &name, Some("chd"), depth));
+                    return;
+                }
+            }
+        }
+        self.backing = Some(Backing::missing(&digest::hex(&sha1)));
+    }
+
+    fn backing(&self) -> Option<&Backing> {
+        self.backing.as_ref()
+    }
+}
+
+impl Reader for ChdReader {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        if offset >= self.header.logical_bytes {
+            return Ok(0);
+        }
+
+        let hunk_bytes = u64::from(self.header.hunk_bytes);
+        let index = offset / hunk_bytes;
+        let in_hunk = (offset % hunk_bytes) as usize;
+        let to_read = buf
+            .len()
+            .min(hunk_bytes as usize - in_hunk)
+            .min((self.header.logical_bytes - offset) as usize);
+
+        let data = self.hunk(index)?;
+        buf[..to_read].copy_from_slice(&data[in_hunk..][..to_read]);
+        Ok(to_read)
+    }
+
+    fn size(&self) -> Option<u64> {
+        Some(self.header.logical_bytes)
+    }
+}
+
+// SAFETY: ChdReader holds Arc'd readers, Vecs and a Mutex
+unsafe impl Send for ChdReader {}
+unsafe impl Sync for ChdReader {}
+
+/// Metadata entries of the kinds read: tag and contents
+fn read_metadata(reader: &dyn Reader, mut offset: u64) -> io::Result<Vec<(u32, String)>> {
+    let mut entries = Vec::new();
+    let mut seen = 0;
+    while offset != 0 {
+        seen += 1;
+        if seen > MAX_METADATA {
+            return Err(invalid_data("too many CHD metadata entries"));
+        }
+        let mut entry = [0u8; 16];
+        read_exact(reader, offset, &mut entry, "short CHD metadata read")?;
+        let kind = be32(&entry);
+        let length = (be32(&entry[4..]) & 0xff_ffff) as usize;
+        if matches!(
+            kind,
+            HARD_DISK_METADATA
+                | CDROM_TRACK_METADATA
+                | CDROM_TRACK_METADATA2
+                | GDROM_TRACK_METADATA
+        ) {
+            if length > MAX_METADATA_SIZE {
+                return Err(invalid_data("CHD metadata entry too large"));
+            }
+            let mut data = vec![0u8; length];
+            read_exact(reader, offset + 16, &mut data, "short CHD metadata read")?;
+            let text = String::from_utf8_lossy(&data);
+            entries.push((kind, text.trim_end_matches('\0').to_string()));
+        }
+        offset = be64(&entry[8..]);
+    }
+    Ok(entries)
+}
+
+/// `KEY:value` fields of a metadata entry, separated by spaces or commas
+fn fields(text: &str) -> impl Iterator<Item = (&str, &str)> {
+    text.split([' ', ','])
+        .filter_map(|field| field.split_once(':'))
+}
+
+/// Geometry from the hard disk metadata
+fn hard_disk_metadata(entries: &[(u32, String)]) -> Metadata {
+    let Some((_, text)) = entries.iter().find(|(kind, _)| *kind == HARD_DISK_METADATA) else {
+        return Vec::new();
+    };
+    fields(text)
+        .filter_map(|(key, value)| {
+            let key = match key {
+                "CYLS" => "cylinders",
+                "HEADS" => "heads",
+                "SECS" => "sectors",
+                "BPS" => "bytes_per_sector",
+                _ => return None,
+            };
+            Some((key, value.to_string()))
+        })
+        .collect()
+}
+
+/// A CD track, from its metadata
+#[derive(Debug, PartialEq)]
+struct Track {
+    number: u32,
+    /// Track type as CHD names it ("MODE1", "AUDIO", ...)
+    kind: String,
+    mode: cdrom::TrackMode,
+    /// Bytes of sector data at the start of each frame
+    sector_size: u64,
+    /// Frames in the image, including a stored pregap
+    frames: u64,
+    pregap: u64,
+    /// The pregap's frames are in the image
+    pregap_stored: bool,
+    /// Frames after the track to round it up
+    padding: u64,
+}
+
+/// Sector mode and sector data bytes in a frame of each track type
+fn track_layout(kind: &str) -> Option<(cdrom::TrackMode, u64)> {
+    Some(match kind {
+        "MODE1" => (cdrom::TrackMode::Mode1, 2048),
+        "MODE2_FORM1" => (cdrom::TrackMode::Mode2Form1, 2048),
+        "MODE2_FORM2" => (cdrom::TrackMode::Mode2Form2, 2324),
+        "MODE2" | "MODE2_FORM_MIX" => (cdrom::TrackMode::Mode2, 2336),
+        "MODE1_RAW" => (cdrom::TrackMode::Mode1, 2352),
+        "MODE2_RAW" => (cdrom::TrackMode::Mode2, 2352),
+        "AUDIO" => (cdrom::TrackMode::Audio, 2352),
+        _ => return None,
+    })
+}
+
+/// CD tracks in order, or none for a hard disk
+fn cd_tracks(entries: &[(u32, String)]) -> io::Result<Vec<Track>> {
+    let mut tracks = Vec::new();
+    for (kind, text) in entries {
+        if !matches!(
+            *kind,
+            CDROM_TRACK_METADATA | CDROM_TRACK_METADATA2 | GDROM_TRACK_METADATA
+        ) {
+            continue;
+        }
+        let (mut number, mut track_kind, mut frames) = (None, None, None);
+        let (mut pregap, mut pregap_type, mut pad) = (0, "", None);
+        for (key, value) in fields(text) {
+            match key {
+                "TRACK" => number = value.parse().ok(),
+                "TYPE" => track_kind = Some(value),
+                "FRAMES" => frames = value.parse().ok(),
+                "PREGAP" => pregap = value.parse().unwrap_or(0),
+                "PGTYPE" => pregap_type = value,
+                "PAD" => pad = value.parse().ok(),
+                _ => {}
+            }
+        }
+        let (Some(number), Some(track_kind), Some(frames)) = (number, track_kind, frames) else {
+            return Err(invalid_data("invalid CHD track metadata"));
+        };
+        let (mode, sector_size) =
+            track_layout(track_kind).ok_or_else(|| invalid_data("unknown CHD track type"))?;
+        tracks.push(Track {
+            number,
+            kind: track_kind.to_string(),
+            mode,
+            sector_size,
+            frames,
+            pregap,
+            pregap_stored: pregap_type.starts_with('V'),
+            padding: pad.unwrap_or(frames.next_multiple_of(TRACK_PADDING) - frames),
+        });
+    }
+    tracks.sort_by_key(|track| track.number);
+    Ok(tracks)
+}
+
+/// Reader over a track's sector data, leaving out each frame's subcode and
+/// unused bytes
+struct TrackReader {
+    disk: Arc<ChdReader>,
+    /// Offset of the track's first frame
+    start: u64,
+    frames: u64,
+    sector_size: u64,
+}
+
+impl Reader for TrackReader {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        if offset >= self.frames * self.sector_size {
+            return Ok(0);
+        }
+        let frame = offset / self.sector_size;
+        let in_sector = offset % self.sector_size;
+        let to_read = buf.len().min((self.sector_size - in_sector) as usize);
+        self.disk.read_at(
+            self.start + frame * FRAME_SIZE + in_sector,
+            &mut buf[..to_read],
+        )
+    }
+
+    fn size(&self) -> Option<u64> {
+        Some(self.frames * self.sector_size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::BytesReader;
+    use flate2::write::DeflateEncoder;
+    use std::io::Write;
+
+    /// Packs bits most significant first
+    #[derive(Default)]
+    struct BitWriter {
+        bytes: Vec<u8>,
+        bits: usize,
+    }
+
+    impl BitWriter {
+        fn write(&mut self, value: u32, count: u8) {
+            for bit in (0..count).rev() {
+                if self.bits.is_multiple_of(8) {
+                    self.bytes.push(0);
+                }
+                if value >> bit & 1 != 0 {
+                    *self.bytes.last_mut().unwrap() |= 0x80 >> (self.bits % 8);
+                }
+                self.bits += 1;
+            }
+        }
+    }
+
+    fn deflate(data: &[u8]) -> Vec<u8> {
+        let mut encoder = DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    /// A `huff` hunk giving every byte value an 8-bit code
+    fn huffman_hunk(data: &[u8]) -> Vec<u8> {
+        let mut bits = BitWriter::default();
+        // Small tree: lengths of 1 for symbol 0 (a run) and 9 (length 8)
+        bits.write(1, 3);
+        bits.write(7, 3);
+        for count in [0, 1, 7] {
+            bits.write(count, 3);
+        }
+        // Length 8, then a run of 255 more
+        bits.write(1, 1);
+        bits.write(0, 1);
+        bits.write(7, 3);
+        bits.write(255 - 9, 8);
+        for &byte in data {
+            bits.write(u32::from(byte), 8);
+        }
+        bits.bytes
+    }
+
+    fn metadata_entry(kind: &[u8; 4], text: &str, next: u64) -> Vec<u8> {
+        let mut entry = kind.to_vec();
+        entry.extend_from_slice(&(text.len() as u32 + 1).to_be_bytes());
+        entry.extend_from_slice(&next.to_be_bytes());
+        entry.extend_from_slice(text.as_bytes());
+        entry.push(0);
+        entry
+    }
+
+    fn v5_header(compressors: [&[u8; 4]; 4], logical: u64, hunk_bytes: u32, unit: u32) -> Vec<u8> {
+        let mut header = MAGIC.to_vec();
+        header.extend_from_slice(&(V5_HEADER_SIZE as u32).to_be_bytes());
+        header.extend_from_slice(&5u32.to_be_bytes());
+        for codec in compressors {
+            header.extend_from_slice(codec);
+        }
+        header.extend_from_slice(&logical.to_be_bytes());
+        header.resize(56, 0);
+        header.extend_from_slice(&hunk_bytes.to_be_bytes());
+        header.extend_from_slice(&unit.to_be_bytes());
+        header.resize(V5_HEADER_SIZE, 0);
+        header
+    }
+
+    fn set_be64(image: &mut [u8], offset: usize, value: u64) {
+        image[offset..offset + 8].copy_from_slice(&value.to_be_bytes());
+    }
+
+    #[test]
+    fn crc16_check_value() {
+        assert_eq!(crc16(b"123456789"), 0x29b1);
+    }
+
+    #[test]
+    fn ecc_parity_checks_out() {
+        let mut sector = [0u8; cdrom::SECTOR_SIZE];
+        sector[..12].copy_from_slice(&cdrom::SYNC);
+        sector[15] = 1;
+        for (i, byte) in sector[16..2064].iter_mut().enumerate() {
+            *byte = (i * 7 + i / 3) as u8;
+        }
+        cdrom::ecc_generate(&mut sector);
+
+        // Each P column and Q diagonal with its parity is a codeword of a
+        // Reed-Solomon code over GF(2^8) with roots 1 and 2
+        let gf_double = |x: u8| (x << 1) ^ if x & 0x80 != 0 { 0x1d } else { 0 };
+        let syndromes = |bytes: Vec<u8>| {
+            bytes.iter().fold((0u8, 0u8), |(plain, weighted), &byte| {
+                (plain ^ byte, gf_double(weighted) ^ byte)
+            })
+        };
+        for column in 0..86 {
+            let mut bytes: Vec<u8> = (0..24).map(|k| sector[12 + column + 86 * k]).collect();
+            bytes.extend([sector[2076 + column], sector[2076 + 86 + column]]);
+            assert_eq!(syndromes(bytes), (0, 0));
+        }
+        for diagonal in 0..52 {
+            let (row, lsb) = (diagonal / 2, diagonal % 2);
+            let mut bytes: Vec<u8> = (0..43)
+                .map(|k| sector[12 + 2 * ((43 * row + 44 * k) % 1118) + lsb])
+                .collect();
+            bytes.extend([sector[2248 + diagonal], sector[2248 + 52 + diagonal]]);
+            assert_eq!(syndromes(bytes), (0, 0));
+        }
+    }
+
+    #[test]
+    fn decodes_huffman_hunk() {
+        let data: Vec<u8> = (0..300u32).map(|i| (i * 31) as u8).collect();
+        assert_eq!(huffman(&huffman_hunk(&data), data.len()).unwrap(), data);
+    }
+
+    #[test]
+    fn decodes_lzma_hunk() {
+        let data: Vec<u8> = (0..4096u32).map(|i| (i / 5) as u8).collect();
+        let mut stream = Vec::new();
+        lzma_rs::lzma_compress(&mut &data[..], &mut stream).unwrap();
+        // Drop the properties header MAME leaves out
+        assert_eq!(lzma(&stream[13..], data.len()).unwrap(), data);
+    }
+
+    /// v5 image with a compressed map over zlib, huff, stored and copied
+    /// hunks, and a parent hunk with no parent
+    #[test]
+    fn reads_v5_compressed_map() {
+        let hunk_bytes = 512usize;
+        let hunks: Vec<Vec<u8>> = (0..3u8)
+            .map(|n| {
+                (0..hunk_bytes)
+                    .map(|i| (i as u8).wrapping_mul(n + 1))
+                    .collect()
+            })
+            .collect();
+        let stored = [
+            deflate(&hunks[0]),
+            huffman_hunk(&hunks[1]),
+            hunks[2].clone(),
+        ];
+
+        let mut image = v5_header(
+            [b"zlib", b"huff", &[0; 4], &[0; 4]],
+            6 * hunk_bytes as u64,
+            hunk_bytes as u32,
+            512,
+        );
+        let data_start = image.len() as u64;
+        for hunk in &stored {
+            image.extend_from_slice(hunk);
+        }
+
+        // Types: codec 0, codec 1, none, self 0, self 1, then parent-self
+        let mut bits = BitWriter::default();
+        for _ in 0..16 {
+            bits.write(4, 4);
+        }
+        for kind in [
+            0,
+            1,
+            COMPRESSION_NONE,
+            COMPRESSION_SELF_0,
+            COMPRESSION_SELF_1,
+        ] {
+            bits.write(u32::from(kind), 4);
+        }
+        bits.write(u32::from(COMPRESSION_PARENT_SELF), 4);
+        let (length_bits, self_bits, parent_bits) = (16, 8, 8);
+        let mut raw_map = Vec::new();
+        let mut offset = data_start;
+        let kinds = [0u8, 1, COMPRESSION_NONE];
+        for ((&kind, hunk), data) in kinds.iter().zip(&hunks).zip(&stored) {
+            // A stored hunk's length is implied
+            if kind != COMPRESSION_NONE {
+                bits.write(data.len() as u32, length_bits);
+            }
+            bits.write(u32::from(crc16(hunk)), 16);
+            raw_map.push(kind);
+            raw_map.extend_from_slice(&(data.len() as u32).to_be_bytes()[1..]);
+            raw_map.extend_from_slice(&offset.to_be_bytes()[2..]);
+            raw_map.extend_from_slice(&crc16(hunk).to_be_bytes());
+            offset += data.len() as u64;
+        }
+        for (kind, target) in [
+            (COMPRESSION_SELF, 0u64),
+            (COMPRESSION_SELF, 1),
+            (COMPRESSION_PARENT, 5),
+        ] {
+            raw_map.push(kind);
+            raw_map.extend_from_slice(&[0; 3]);
+            raw_map.extend_from_slice(&target.to_be_bytes()[2..]);
+            raw_map.extend_from_slice(&[0; 2]);
+        }
+
+        let map_offset = image.len() as u64;
+        image.extend_from_slice(&(bits.bytes.len() as u32).to_be_bytes());
+        image.extend_from_slice(&data_start.to_be_bytes()[2..]);
+        image.extend_from_slice(&crc16(&raw_map).to_be_bytes());
+        image.extend_from_slice(&[length_bits, self_bits, parent_bits, 0]);
+        image.extend_from_slice(&bits.bytes);
+        let meta_offset = image.len() as u64;
+        image.extend(metadata_entry(b"GDDD", "CYLS:3,HEADS:2,SECS:1,BPS:512", 0));
+        set_be64(&mut image, 40, map_offset);
+        set_be64(&mut image, 48, meta_offset);
+
+        let kids = CHD.children(Arc::new(BytesReader::new(image))).unwrap();
+        assert_eq!(kids.len(), 1);
+        assert!(kids[0].metadata.contains(&("cylinders", "3".to_string())));
+        assert!(kids[0]
+            .metadata
+            .contains(&("bytes_per_sector", "512".to_string())));
+
+        let reader = &kids[0].reader;
+        assert_eq!(reader.size(), Some(6 * hunk_bytes as u64));
+        let expected = [&hunks[0], &hunks[1], &hunks[2], &hunks[0], &hunks[1]];
+        let mut buf = vec![0u8; hunk_bytes];
+        for (index, hunk) in expected.iter().enumerate() {
+            reader
+                .read_at((index * hunk_bytes) as u64, &mut buf)
+                .unwrap();
+            assert_eq!(&buf, *hunk, "hunk {index}");
+        }
+        reader.read_at(5 * hunk_bytes as u64, &mut buf).unwrap();
+        assert!(buf.iter().all(|&byte| byte == 0));
+    }
+
+    /// v5 CD image of one cdzl hunk: a Mode 1 track whose sync and ECC
+    /// were dropped, then an audio track
+    #[test]
+    fn reads_cd_tracks() {
+        let frames = 8usize;
+        let mut raw = vec![0u8; frames * FRAME_SIZE as usize];
+        for (frame, dest) in raw.chunks_exact_mut(FRAME_SIZE as usize).enumerate() {
+            let sector = &mut dest[..cdrom::SECTOR_SIZE];
+            if frame < 4 {
+                sector[..12].copy_from_slice(&cdrom::SYNC);
+                sector[15] = 1;
+                sector[16..2064].fill(b'A' + frame as u8);
+                cdrom::ecc_generate(sector);
+            } else {
+                sector.fill(b'a' + frame as u8);
+            }
+            dest[cdrom::SECTOR_SIZE..].fill(0xff);
+        }
+
+        let mut sectors = Vec::new();
+        let mut subcode = Vec::new();
+        for (frame, source) in raw.chunks_exact(FRAME_SIZE as usize).enumerate() {
+            let mut sector = source[..cdrom::SECTOR_SIZE].to_vec();
+            if frame < 4 {
+                sector[..12].fill(0);
+                sector[2076..].fill(0);
+            }
+            sectors.extend_from_slice(&sector);
+            subcode.extend_from_slice(&source[cdrom::SECTOR_SIZE..]);
+        }
+        let base = deflate(&sectors);
+        let mut hunk = vec![0b0000_1111];
+        hunk.extend_from_slice(&(base.len() as u16).to_be_bytes());
+        hunk.extend_from_slice(&base);
+        hunk.extend_from_slice(&deflate(&subcode));
+
+        let hunk_bytes = raw.len() as u32;
+        let mut image = v5_header(
+            [b"cdzl", &[0; 4], &[0; 4], &[0; 4]],
+            raw.len() as u64,
+            hunk_bytes,
+            FRAME_SIZE as u32,
+        );
+        let data_start = image.len() as u64;
+        image.extend_from_slice(&hunk);
+        let mut bits = BitWriter::default();
+        for _ in 0..16 {
+            bits.write(4, 4);
+        }
+        bits.write(0, 4);
+        bits.write(hunk.len() as u32, 24);
+        bits.write(u32::from(crc16(&raw)), 16);
+        let mut raw_map = vec![0];
+        raw_map.extend_from_slice(&(hunk.len() as u32).to_be_bytes()[1..]);
+        raw_map.extend_from_slice(&data_start.to_be_bytes()[2..]);
+        raw_map.extend_from_slice(&crc16(&raw).to_be_bytes());
+
+        let map_offset = image.len() as u64;
+        image.extend_from_slice(&(bits.bytes.len() as u32).to_be_bytes());
+        image.extend_from_slice(&data_start.to_be_bytes()[2..]);
+        image.extend_from_slice(&crc16(&raw_map).to_be_bytes());
+        image.extend_from_slice(&[24, 0, 0, 0]);
+        image.extend_from_slice(&bits.bytes);
+        let meta_offset = image.len() as u64;
+        let track1 = "TRACK:1 TYPE:MODE1_RAW SUBTYPE:RW_RAW FRAMES:4 PREGAP:0 PGTYPE:MODE1 PGSUB:RW POSTGAP:0";
+        let track2 =
+            "TRACK:2 TYPE:AUDIO SUBTYPE:RW_RAW FRAMES:3 PREGAP:1 PGTYPE:VAUDIO PGSUB:RW POSTGAP:0";
+        let second = meta_offset + 16 + track1.len() as u64 + 1;
+        image.extend(metadata_entry(b"CHT2", track1, second));
+        image.extend(metadata_entry(b"CHT2", track2, 0));
+        set_be64(&mut image, 40, map_offset);
+        set_be64(&mut image, 48, meta_offset);
+
+        let kids = CHD.children(Arc::new(BytesReader::new(image))).unwrap();
+        // Raw and cooked views of the data track, then the audio track
+        assert_eq!(kids.len(), 3);
+        assert!(kids[0]
+            .metadata
+            .contains(&("type", "MODE1_RAW".to_string())));
+        assert!(kids[1].metadata.contains(&("view", "cooked".to_string())));
+        assert!(kids[2].metadata.contains(&("frames", "2".to_string())));
+
+        // The regenerated sectors match the originals
+        let track = &kids[0].reader;
+        assert_eq!(track.size(), Some(4 * 2352));
+        let mut sector = vec![0u8; cdrom::SECTOR_SIZE];
+        for frame in 0..4 {
+            track.read_at(frame * 2352, &mut sector).unwrap();
+            assert_eq!(sector, raw[frame as usize * 2448..][..2352]);
+        }
+        let mut user_data = vec![0u8; 2048];
+        kids[1].reader.read_at(2048, &mut user_data).unwrap();
+        assert_eq!(user_data, raw[2448 + 16..][..2048]);
+        // The audio track starts after its stored pregap
+        let audio = &kids[2].reader;
+        assert_eq!(kids[2].offset, 5 * FRAME_SIZE);
+        audio.read_at(2352, &mut sector).unwrap();
+        assert!(sector.iter().all(|&byte| byte == b'a' + 6));
+    }
+}
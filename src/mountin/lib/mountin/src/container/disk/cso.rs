@@ -0,0 +1,268 @@
+//! CSO and ZSO (compressed ISO) disk image readers
+//!
+//! PSP and PS2 preservation sets compress ISO images in fixed-size blocks,
+//! usually one 2048-byte sector each, found through a table of 32-bit
+//! offsets after a 24-byte header. CSO blocks are raw deflate, or LZ4 in
+//! version 2; ZSO blocks are LZ4. The top bit of an offset marks a block
+//! stored as is, except in CSO version 2, where it marks LZ4 and stored
+//! blocks are the ones taking a whole block.
+
+use crate::container::block::IndexedReader;
+use crate::container::{checked_table_size, invalid_data, Child, Container};
+use crate::detect::Reader;
+use flate2::read::DeflateDecoder;
+use std::io::{self, Read};
+use std::sync::Arc;
+
+const HEADER_SIZE: u64 = 24;
+const CSO_MAGIC: &[u8; 4] = b"CISO";
+const ZSO_MAGIC: &[u8; 4] = b"ZISO";
+/// Top bit of an index entry; the rest is the offset, shifted
+const INDEX_FLAG: u32 = 0x8000_0000;
+const MAX_ALIGN: u8 = 31;
+
+/// Compressed ISO variants sharing the header layout
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Variant {
+    Cso,
+    Zso,
+}
+
+/// CSO disk image container
+pub struct CsoContainer;
+
+/// Static instance for registry
+pub static CSO: CsoContainer = CsoContainer;
+
+/// ZSO disk image container
+pub struct ZsoContainer;
+
+/// Static instance for registry
+pub static ZSO: ZsoContainer = ZsoContainer;
+
+impl Container for CsoContainer {
+    fn children(&self, reader: Arc<dyn Reader + Send + Sync>) -> io::Result<Vec<Child>> {
+        children(reader, Variant::Cso)
+    }
+}
+
+impl Container for ZsoContainer {
+    fn children(&self, reader: Arc<dyn Reader + Send + Sync>) -> io::Result<Vec<Child>> {
+        children(reader, Variant::Zso)
+    }
+}
+
+fn children(reader: Arc<dyn Reader + Send + Sync>, variant: Variant) -> io::Result<Vec<Child>> {
+    let iso_reader = open(reader, variant)?;
+
+    Ok(vec![Child {
+        index: 0,
+        offset: 0,
+        reader: Arc::new(iso_reader),
+        metadata: Vec::new(),
+    }])
+}
+
+/// How a block is stored
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Storage {
+    Plain,
+    Deflate,
+    Lz4,
+}
+
+fn open(parent: Arc<dyn Reader + Send + Sync>, variant: Variant) -> io::Result<IndexedReader> {
+    let mut header = [0u8; HEADER_SIZE as usize];
+    if parent.read_at(0, &mut header)? != header.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "short compressed ISO header read",
+        ));
+    }
+    let magic = match variant {
+        Variant::Cso => CSO_MAGIC,
+        Variant::Zso => ZSO_MAGIC,
+    };
+    if &header[..4] != magic {
+        return Err(invalid_data("invalid compressed ISO signature"));
+    }
+
+    // GameCube and Wii CISO images share the magic but put their block
+    // size where the header size is
+    let header_size = u32::from_le_bytes(header[4..8].try_into().unwrap());
+    let total_bytes = u64::from_le_bytes(header[8..16].try_into().unwrap());
+    let block_size = u32::from_le_bytes(header[16..20].try_into().unwrap());
+    let version = header[20];
+    let align = header[21];
+    if !matches!(header_size, 0 | 24) || version > 2 || align > MAX_ALIGN || block_size == 0 {
+        return Err(invalid_data("unsupported compressed ISO header"));
+    }
+    let block_size = u64::from(block_size);
+
+    let entries = total_bytes
+        .div_ceil(block_size)
+        .checked_add(1)
+        .ok_or_else(|| invalid_data("compressed ISO index size overflow"))?;
+    let index_bytes = checked_table_size(parent.as_ref(), HEADER_SIZE, entries, 4)?;
+    let mut index_data = vec![0u8; index_bytes];
+    if parent.read_at(HEADER_SIZE, &mut index_data)? != index_bytes {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "short compressed ISO index read",
+        ));
+    }
+
+    let (offsets, flags): (Vec<u64>, Vec<bool>) = index_data
+        .chunks_exact(4)
+        .map(|entry| {
+            let entry = u32::from_le_bytes(entry.try_into().unwrap());
+            (
+                u64::from(entry & !INDEX_FLAG) << align,
+                entry & INDEX_FLAG != 0,
+            )
+        })
+        .unzip();
+    if offsets[0] < HEADER_SIZE + index_bytes as u64 {
+        return Err(invalid_data("invalid compressed ISO block offsets"));
+    }
+
+    // Padding to the alignment follows each block
+    let max_stored = 2 * block_size + (1 << align);
+    IndexedReader::new(
+        parent,
+        offsets,
+        block_size,
+        total_bytes,
+        max_stored,
+        move |index, stored| {
+            let storage = match (variant, version, flags[index]) {
+                (Variant::Cso, 2, _) if stored.len() as u64 >= block_size => Storage::Plain,
+                (Variant::Cso, 2, true) => Storage::Lz4,
+                (_, _, true) => Storage::Plain,
+                (Variant::Cso, _, false) => Storage::Deflate,
+                (Variant::Zso, _, false) => Storage::Lz4,
+            };
+            decode_block(storage, stored, block_size as usize)
+        },
+    )
+}
+
+fn decode_block(storage: Storage, stored: &[u8], block_size: usize) -> io::Result<Vec<u8>> {
+    match storage {
+        Storage::Plain => Ok(stored[..stored.len().min(block_size)].to_vec()),
+        Storage::Deflate => {
+            let mut block = Vec::with_capacity(block_size);
+            DeflateDecoder::new(stored)
+                .take(block_size as u64)
+                .read_to_end(&mut block)?;
+            Ok(block)
+        }
+        Storage::Lz4 => {
+            let mut block = vec![0u8; block_size];
+            let n = lz4_flex::block::decompress_into(stored, &mut block)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+            block.truncate(n);
+            Ok(block)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::BytesReader;
+    use flate2::write::DeflateEncoder;
+    use std::io::Write;
+
+    /// Image of `blocks` with a header for `magic` and `version`; each
+    /// block is stored as given, with its index flag
+    fn image(magic: &[u8; 4], version: u8, total: u64, blocks: &[(Vec<u8>, bool)]) -> Vec<u8> {
+        let mut data = magic.to_vec();
+        data.extend_from_slice(&24u32.to_le_bytes());
+        data.extend_from_slice(&total.to_le_bytes());
+        data.extend_from_slice(&2048u32.to_le_bytes());
+        data.extend_from_slice(&[version, 0, 0, 0]);
+
+        let mut offset = HEADER_SIZE as u32 + 4 * (blocks.len() as u32 + 1);
+        for (stored, flag) in blocks {
+            let flag = if *flag { INDEX_FLAG } else { 0 };
+            data.extend_from_slice(&(offset | flag).to_le_bytes());
+            offset += stored.len() as u32;
+        }
+        data.extend_from_slice(&offset.to_le_bytes());
+        for (stored, _) in blocks {
+            data.extend_from_slice(stored);
+        }
+        data
+    }
+
+    fn deflate(data: &[u8]) -> Vec<u8> {
+        let mut encoder = DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn read(container: &dyn Container, data: Vec<u8>) -> Vec<u8> {
+        let kids = container
+            .children(Arc::new(BytesReader::new(data)))
+            .unwrap();
+        let reader = &kids[0].reader;
+        let mut out = vec![0u8; reader.size().unwrap() as usize];
+        let mut done = 0;
+        while done < out.len() {
+            done += reader.read_at(done as u64, &mut out[done..]).unwrap();
+        }
+        out
+    }
+
+    #[test]
+    fn reads_cso_v1() {
+        let sectors = [vec![b'C'; 2048], (0..2048u32).map(|i| i as u8).collect()];
+        let data = image(
+            CSO_MAGIC,
+            1,
+            4096,
+            &[(deflate(&sectors[0]), false), (sectors[1].clone(), true)],
+        );
+        assert_eq!(read(&CSO, data), sectors.concat());
+    }
+
+    #[test]
+    fn reads_cso_v2_block_kinds() {
+        let sectors = [vec![b'D'; 2048], vec![b'L'; 2048], vec![7u8; 1024]];
+        let data = image(
+            CSO_MAGIC,
+            2,
+            5120,
+            &[
+                (deflate(&sectors[0]), false),
+                (lz4_flex::block::compress(&sectors[1]), true),
+                // A short last block, stored whole
+                (sectors[2].iter().copied().chain([0; 1024]).collect(), false),
+            ],
+        );
+        assert_eq!(read(&CSO, data), sectors.concat());
+    }
+
+    #[test]
+    fn reads_zso() {
+        let sectors = [vec![b'Z'; 2048], vec![9u8; 2048]];
+        let data = image(
+            ZSO_MAGIC,
+            1,
+            4096,
+            &[
+                (lz4_flex::block::compress(&sectors[0]), false),
+                (sectors[1].clone(), true),
+            ],
+        );
+        assert_eq!(read(&ZSO, data), sectors.concat());
+    }
+
+    #[test]
+    fn rejects_gamecube_ciso() {
+        let mut data = image(CSO_MAGIC, 1, 2048, &[(vec![0; 2048], true)]);
+        data[4..8].copy_from_slice(&0x20_0000u32.to_le_bytes());
+        assert!(CSO.children(Arc::new(BytesReader::new(data))).is_err());
+    }
+}
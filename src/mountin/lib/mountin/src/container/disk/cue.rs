@@ -0,0 +1,404 @@
+//! CUE sheet disc image reader
+//!
+//! A CUE sheet is a text descriptor listing data files (`FILE`), the
+//! tracks in each (`TRACK nn MODE`) and where their indexes fall, as
+//! mm:ss:ff times from the start of the file. Index 0 starts a track's
+//! pregap and index 1 its data; a `PREGAP` is a pregap not stored in the
+//! file. Redump-style `REM SESSION nn` lines mark the sessions. A pregap
+//! at the end of the previous file (as EAC writes them) is left with the
+//! track before.
+//!
+//! Tracks in BINARY and MOTOROLA files are read in place, and in WAVE
+//! files from the RIFF data chunk. Tracks in compressed audio files
+//! (MP3, AIFF, ...) can't be mapped to sectors and are left out.
+
+use crate::container::disk::cdrom::{self, ImageTrack, TrackMode};
+use crate::container::{invalid_data, Child, Container};
+use crate::detect::Reader;
+use std::io;
+use std::sync::Arc;
+
+/// CUE sheet container
+pub struct CueContainer;
+
+/// Static instance for registry
+pub static CUE: CueContainer = CueContainer;
+
+impl Container for CueContainer {
+    fn children(&self, reader: Arc<dyn Reader + Send + Sync>) -> io::Result<Vec<Child>> {
+        let sheet = parse_sheet(&cdrom::read_descriptor(&*reader)?)?;
+
+        let mut tracks = Vec::new();
+        for (index, file) in sheet.files.iter().enumerate() {
+            let Some((data, start, end)) = open_file(&*reader, file)? else {
+                continue;
+            };
+            let in_file: Vec<&Track> = sheet
+                .tracks
+                .iter()
+                .filter(|track| track.file == index)
+                .collect();
+            tracks.extend(layout(&in_file, &data, start, end)?);
+        }
+        Ok(cdrom::image_children(tracks))
+    }
+}
+
+/// A `FILE` line
+#[derive(Debug, PartialEq)]
+struct File {
+    name: String,
+    /// BINARY, MOTOROLA, WAVE, MP3, ...
+    kind: String,
+}
+
+/// A `TRACK` and the lines after it
+#[derive(Debug, PartialEq)]
+struct Track {
+    number: u32,
+    session: u32,
+    /// Index into the sheet's files
+    file: usize,
+    mode: TrackMode,
+    sector_size: usize,
+    frame_size: usize,
+    /// Index 0, where given, in frames from the start of the file
+    index0: Option<u64>,
+    index1: Option<u64>,
+    /// Frames of pregap not stored in the file
+    pregap: u64,
+}
+
+#[derive(Debug, Default)]
+struct Sheet {
+    files: Vec<File>,
+    tracks: Vec<Track>,
+}
+
+/// Mode, sector size and stored frame size of a `TRACK` line's mode
+fn track_layout(mode: &str) -> Option<(TrackMode, usize, usize)> {
+    let (mode, sector_size) = match mode.to_ascii_uppercase().as_str() {
+        "AUDIO" => (TrackMode::Audio, cdrom::SECTOR_SIZE),
+        // CD+G keeps the subchannel, which holds the graphics
+        "CDG" => {
+            let frame_size = cdrom::SECTOR_SIZE + cdrom::SUBCHANNEL_SIZE;
+            return Some((TrackMode::Audio, cdrom::SECTOR_SIZE, frame_size));
+        }
+        "MODE1/2048" => (TrackMode::Mode1, 2048),
+        "MODE1/2352" => (TrackMode::Mode1, cdrom::SECTOR_SIZE),
+        "MODE2/2048" => (TrackMode::Mode2Form1, 2048),
+        "MODE2/2324" => (TrackMode::Mode2Form2, 2324),
+        "MODE2/2336" | "CDI/2336" => (TrackMode::Mode2, cdrom::MODE2_SIZE),
+        "MODE2/2352" | "CDI/2352" => (TrackMode::Mode2, cdrom::SECTOR_SIZE),
+        _ => return None,
+    };
+    Some((mode, sector_size, sector_size))
+}
+
+fn parse_sheet(text: &str) -> io::Result<Sheet> {
+    let mut sheet = Sheet::default();
+    let mut session = 1;
+    for line in text.lines() {
+        let words = cdrom::words(line);
+        let Some(command) = words.first() else {
+            continue;
+        };
+        let track = sheet.tracks.last_mut();
+        match (command.to_ascii_uppercase().as_str(), &words[1..]) {
+            ("REM", [remark, number, ..]) if remark.eq_ignore_ascii_case("SESSION") => {
+                session = number
+                    .parse()
+                    .map_err(|_| invalid_data("invalid CUE session"))?;
+            }
+            ("FILE", [name, kind, ..]) => sheet.files.push(File {
+                name: name.to_string(),
+                kind: kind.to_ascii_uppercase(),
+            }),
+            ("TRACK", [number, mode, ..]) => {
+                if sheet.files.is_empty() {
+                    return Err(invalid_data("CUE track before any file"));
+                }
+                let number = number
+                    .parse()
+                    .map_err(|_| invalid_data("invalid CUE track number"))?;
+                let (mode, sector_size, frame_size) = track_layout(mode).ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::Unsupported, "unsupported CUE track mode")
+                })?;
+                sheet.tracks.push(Track {
+                    number,
+                    session,
+                    file: sheet.files.len() - 1,
+                    mode,
+                    sector_size,
+                    frame_size,
+                    index0: None,
+                    index1: None,
+                    pregap: 0,
+                });
+            }
+            ("INDEX", [number, time, ..]) => {
+                let track = track.ok_or_else(|| invalid_data("CUE index before any track"))?;
+                let frame =
+                    cdrom::parse_msf(time).ok_or_else(|| invalid_data("invalid CUE index time"))?;
+                match number.parse::<u32>() {
+                    Ok(0) => track.index0 = Some(frame),
+                    // A track whose pregap ends the previous file starts
+                    // in this one; the pregap stays with the track before
+                    Ok(1) if track.file != sheet.files.len() - 1 => {
+                        track.file = sheet.files.len() - 1;
+                        track.index0 = None;
+                        track.index1 = Some(frame);
+                    }
+                    Ok(1) => track.index1 = Some(frame),
+                    Ok(_) => {}
+                    Err(_) => return Err(invalid_data("invalid CUE index number")),
+                }
+            }
+            ("PREGAP", [time, ..]) => {
+                let track = track.ok_or_else(|| invalid_data("CUE pregap before any track"))?;
+                track.pregap =
+                    cdrom::parse_msf(time).ok_or_else(|| invalid_data("invalid CUE pregap"))?;
+            }
+            _ => {}
+        }
+    }
+    if sheet.tracks.is_empty() {
+        return Err(invalid_data("CUE sheet without tracks"));
+    }
+    Ok(sheet)
+}
+
+/// An opened data file, with where its sector data starts and ends
+type DataFile = (Arc<dyn Reader + Send + Sync>, u64, u64);
+
+/// Open a sheet's file, or None for a compressed audio file
+fn open_file(sheet: &dyn Reader, file: &File) -> io::Result<Option<DataFile>> {
+    if !matches!(file.kind.as_str(), "BINARY" | "MOTOROLA" | "WAVE") {
+        return Ok(None);
+    }
+    let data = cdrom::open_data_file(sheet, &file.name)?;
+    let size = data.size().unwrap_or(0);
+    let (start, end) = if file.kind == "WAVE" {
+        wave_data(&*data)?
+    } else {
+        (0, size)
+    };
+    Ok(Some((data, start, end.min(size))))
+}
+
+/// Offset and end of the sample data in a RIFF WAVE file
+fn wave_data(file: &dyn Reader) -> io::Result<(u64, u64)> {
+    let mut header = [0u8; 12];
+    if file.read_at(0, &mut header)? != header.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "short WAVE header read",
+        ));
+    }
+    if &header[..4] != b"RIFF" || &header[8..] != b"WAVE" {
+        return Err(invalid_data("invalid WAVE file"));
+    }
+    let size = file.size().unwrap_or(0);
+    let mut offset = 12u64;
+    while offset + 8 <= size {
+        let mut chunk = [0u8; 8];
+        if file.read_at(offset, &mut chunk)? != chunk.len() {
+            break;
+        }
+        let length = u64::from(u32::from_le_bytes(chunk[4..].try_into().unwrap()));
+        if &chunk[..4] == b"data" {
+            return Ok((offset + 8, offset + 8 + length));
+        }
+        // Chunks are padded to even lengths
+        offset += 8 + length + (length & 1);
+    }
+    Err(invalid_data("WAVE file without data chunk"))
+}
+
+/// Place the tracks of one file, whose sector data runs from `start` to
+/// `end`. A track runs to where the next one's pregap (or data) starts.
+fn layout(
+    tracks: &[&Track],
+    file: &Arc<dyn Reader + Send + Sync>,
+    start: u64,
+    end: u64,
+) -> io::Result<Vec<ImageTrack>> {
+    // Times count frames of the tracks before, whatever their size
+    let mut starts = Vec::with_capacity(tracks.len());
+    let (mut offset, mut frame, mut frame_size) = (start, 0u64, 0u64);
+    for track in tracks {
+        let index1 = track
+            .index1
+            .ok_or_else(|| invalid_data("CUE track without index 1"))?;
+        let first = track.index0.unwrap_or(index1).min(index1);
+        if first < frame {
+            return Err(invalid_data("CUE indexes out of order"));
+        }
+        let after = |offset: u64, frames: u64, frame_size: u64| {
+            frames
+                .checked_mul(frame_size)
+                .and_then(|length| offset.checked_add(length))
+                .ok_or_else(|| invalid_data("CUE index overflow"))
+        };
+        offset = after(offset, first - frame, frame_size)?;
+        frame_size = track.frame_size as u64;
+        starts.push((offset, after(offset, index1 - first, frame_size)?));
+        frame = first;
+    }
+
+    let mut placed = Vec::with_capacity(tracks.len());
+    for (position, track) in tracks.iter().enumerate() {
+        let (first, data) = starts[position];
+        let track_end = starts.get(position + 1).map_or(end, |&(next, _)| next);
+        let frame_size = track.frame_size as u64;
+        placed.push(ImageTrack {
+            number: track.number,
+            session: track.session,
+            mode: track.mode,
+            file: Arc::clone(file),
+            offset: data,
+            sector_size: track.sector_size,
+            frame_size: track.frame_size,
+            frames: track_end.saturating_sub(data) / frame_size,
+            pregap: (data - first) / frame_size + track.pregap,
+        });
+    }
+    Ok(placed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::FilesReader;
+
+    fn sector(mode: u8, fill: u8) -> Vec<u8> {
+        let mut sector = vec![fill; cdrom::SECTOR_SIZE];
+        sector[..12].copy_from_slice(&cdrom::SYNC);
+        sector[15] = mode;
+        sector
+    }
+
+    fn value<'a>(child: &'a Child, key: &str) -> &'a str {
+        &child
+            .metadata
+            .iter()
+            .find(|(name, _)| *name == key)
+            .unwrap()
+            .1
+    }
+
+    #[test]
+    fn reads_tracks_and_pregaps() {
+        let sheet = r#"REM COMMENT "test"
+FILE "disc (Track 1).bin" BINARY
+  TRACK 01 MODE1/2352
+    INDEX 01 00:00:00
+  TRACK 02 AUDIO
+    INDEX 00 00:00:03
+    INDEX 01 00:00:05
+FILE "disc (Track 3).bin" BINARY
+  TRACK 03 MODE2/2352
+    PREGAP 00:02:00
+    INDEX 01 00:00:00
+"#;
+        let bin1 = [
+            sector(1, b'a').repeat(3),
+            vec![b'p'; 2 * cdrom::SECTOR_SIZE],
+            vec![b'm'; 4 * cdrom::SECTOR_SIZE],
+        ]
+        .concat();
+        let mut form1 = sector(2, b'x');
+        form1[16..24].copy_from_slice(&[0, 0, 8, 0, 0, 0, 8, 0]);
+        let files = FilesReader::open(
+            "disc.cue",
+            vec![
+                ("disc.cue", sheet.as_bytes().to_vec()),
+                ("disc (Track 1).bin", bin1),
+                ("disc (Track 3).bin", form1.repeat(2)),
+            ],
+        );
+        let kids = CUE.children(files).unwrap();
+
+        // Raw and cooked views of each data track
+        assert_eq!(kids.len(), 5);
+        assert_eq!(value(&kids[0], "frames"), "3");
+        assert_eq!(kids[1].reader.size(), Some(3 * 2048));
+        let audio = &kids[2];
+        assert_eq!(value(audio, "mode"), "audio");
+        assert_eq!(value(audio, "pregap"), "2");
+        assert_eq!(value(audio, "frames"), "4");
+        let mut buf = [0u8; 4];
+        audio.reader.read_at(0, &mut buf).unwrap();
+        assert_eq!(&buf, b"mmmm");
+
+        assert_eq!(value(&kids[3], "track"), "3");
+        assert_eq!(value(&kids[3], "pregap"), "150");
+        kids[4].reader.read_at(2048, &mut buf).unwrap();
+        assert_eq!(&buf, b"xxxx");
+    }
+
+    #[test]
+    fn reads_wave_and_sessions() {
+        let mut wave = b"RIFF\0\0\0\0WAVEfmt \x02\0\0\0\0\0data".to_vec();
+        wave.extend_from_slice(&(2 * 2352u32).to_le_bytes());
+        wave.extend(vec![b's'; 2 * 2352]);
+        let sheet = "FILE \"audio.wav\" WAVE\r\n  TRACK 01 AUDIO\r\n    INDEX 01 00:00:00\r\n\
+                     REM SESSION 02\r\nFILE \"data.bin\" BINARY\r\n  TRACK 02 MODE1/2048\r\n    \
+                     INDEX 01 00:00:00\r\nFILE \"more.mp3\" MP3\r\n  TRACK 03 AUDIO\r\n    \
+                     INDEX 01 00:00:00\r\n";
+        let files = FilesReader::open(
+            "disc.cue",
+            vec![
+                ("disc.cue", sheet.as_bytes().to_vec()),
+                ("audio.wav", wave),
+                ("data.bin", vec![b'd'; 2048]),
+            ],
+        );
+        let kids = CUE.children(files).unwrap();
+        assert_eq!(kids.len(), 2);
+        assert_eq!(kids[0].reader.size(), Some(2 * 2352));
+        let mut buf = [0u8; 2];
+        kids[0].reader.read_at(0, &mut buf).unwrap();
+        assert_eq!(&buf, b"ss");
+        assert_eq!(value(&kids[1], "session"), "2");
+        assert_eq!(value(&kids[1], "view"), "cooked");
+    }
+
+    #[test]
+    fn detects_every_data_track() {
+        let sheet = "FILE \"disc.bin\" BINARY\n  TRACK 01 MODE1/2048\n    INDEX 01 00:00:00\n  \
+                     TRACK 02 MODE1/2048\n    INDEX 01 00:00:17\n";
+        let mut data = Vec::new();
+        for fill in [b'a', b'b'] {
+            let mut track = vec![fill; 17 * 2048];
+            track[16 * 2048..][..6].copy_from_slice(b"\x01CD001");
+            data.extend(track);
+        }
+        let files = FilesReader::open(
+            "disc.cue",
+            vec![("disc.cue", sheet.into()), ("disc.bin", data)],
+        );
+
+        // Both tracks are read from the data file, not slices of the sheet
+        crate::format::init_test_formats();
+        let tree = crate::detect::detect_tree(files);
+        let cue = tree
+            .iter()
+            .find(|n| n.format.to_str() == Ok("disk/cue"))
+            .expect("disk/cue not detected");
+        let formats: Vec<_> = cue
+            .children
+            .iter()
+            .map(|n| n.format.to_str().unwrap())
+            .collect();
+        assert_eq!(formats, ["fs/iso9660", "fs/iso9660"]);
+    }
+
+    #[test]
+    fn rejects_missing_files() {
+        let sheet = "FILE \"gone.bin\" BINARY\n TRACK 01 MODE1/2048\n INDEX 01 00:00:00\n";
+        let files = FilesReader::open("disc.cue", vec![("disc.cue", sheet.as_bytes().to_vec())]);
+        let error = CUE.children(files).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+    }
+}
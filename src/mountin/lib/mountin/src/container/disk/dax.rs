@@ -0,0 +1,171 @@
+//! DAX compressed ISO disk image reader
+//!
+//! DAX, an early PSP compressed ISO format, stores the image as 8 KB
+//! frames, each zlib compressed, with tables of frame offsets and
+//! compressed lengths after a 32-byte header. Version 1 adds "NC areas":
+//! runs of frames stored uncompressed because they didn't shrink.
+
+use crate::container::block::IndexedReader;
+use crate::container::{checked_table_size, invalid_data, le32, Child, Container};
+use crate::detect::Reader;
+use flate2::read::ZlibDecoder;
+use std::io::{self, Read};
+use std::sync::Arc;
+
+const MAGIC: &[u8; 4] = b"DAX\0";
+const HEADER_SIZE: u64 = 32;
+const FRAME_SIZE: u64 = 0x2000;
+/// Largest stored frame; lengths are 16 bits
+const MAX_STORED: u64 = 0xffff;
+
+/// DAX disk image container
+pub struct DaxContainer;
+
+/// Static instance for registry
+pub static DAX: DaxContainer = DaxContainer;
+
+impl Container for DaxContainer {
+    fn children(&self, reader: Arc<dyn Reader + Send + Sync>) -> io::Result<Vec<Child>> {
+        let dax_reader = open(reader)?;
+
+        Ok(vec![Child {
+            index: 0,
+            offset: 0,
+            reader: Arc::new(dax_reader),
+            metadata: Vec::new(),
+        }])
+    }
+}
+
+fn read_table(reader: &dyn Reader, offset: u64, entries: u64, size: u64) -> io::Result<Vec<u8>> {
+    let bytes = checked_table_size(reader, offset, entries, size)?;
+    let mut table = vec![0u8; bytes];
+    if reader.read_at(offset, &mut table)? != bytes {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "short DAX table read",
+        ));
+    }
+    Ok(table)
+}
+
+fn open(parent: Arc<dyn Reader + Send + Sync>) -> io::Result<IndexedReader> {
+    let mut header = [0u8; HEADER_SIZE as usize];
+    if parent.read_at(0, &mut header)? != header.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "short DAX header read",
+        ));
+    }
+    if &header[..4] != MAGIC {
+        return Err(invalid_data("invalid DAX signature"));
+    }
+    let total_bytes = u64::from(le32(&header[4..]));
+    let version = le32(&header[8..]);
+    let nc_areas = if version >= 1 { le32(&header[12..]) } else { 0 };
+
+    let frames = total_bytes.div_ceil(FRAME_SIZE);
+    let offsets_at = HEADER_SIZE;
+    let lengths_at = offsets_at + frames * 4;
+    let areas_at = lengths_at + frames * 2;
+    let offsets = read_table(&*parent, offsets_at, frames, 4)?;
+    let lengths = read_table(&*parent, lengths_at, frames, 2)?;
+    let areas = read_table(&*parent, areas_at, u64::from(nc_areas), 8)?;
+
+    // Frames in an NC area are stored whole
+    let mut stored = vec![false; frames as usize];
+    for area in areas.chunks_exact(8) {
+        let (first, count) = (le32(area) as usize, le32(&area[4..]) as usize);
+        let end = first
+            .checked_add(count)
+            .filter(|&end| end <= stored.len())
+            .ok_or_else(|| invalid_data("invalid DAX NC area"))?;
+        stored[first..end].fill(true);
+    }
+
+    let mut index: Vec<u64> = offsets
+        .chunks_exact(4)
+        .map(|offset| u64::from(le32(offset)))
+        .collect();
+    let data_start = areas_at + areas.len() as u64;
+    let end = match (index.last(), lengths.rchunks_exact(2).next()) {
+        (Some(&last), _) if stored[stored.len() - 1] => last + FRAME_SIZE,
+        (Some(&last), Some(length)) => last + u64::from(u16::from_le_bytes([length[0], length[1]])),
+        _ => data_start,
+    };
+    index.push(end);
+    if index[0] < data_start {
+        return Err(invalid_data("invalid DAX frame offsets"));
+    }
+
+    IndexedReader::new(
+        parent,
+        index,
+        FRAME_SIZE,
+        total_bytes,
+        MAX_STORED,
+        move |frame, data| {
+            if stored[frame] {
+                return Ok(data[..data.len().min(FRAME_SIZE as usize)].to_vec());
+            }
+            let mut decompressed = Vec::with_capacity(FRAME_SIZE as usize);
+            ZlibDecoder::new(data)
+                .take(FRAME_SIZE)
+                .read_to_end(&mut decompressed)?;
+            Ok(decompressed)
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::BytesReader;
+    use flate2::write::ZlibEncoder;
+    use std::io::Write;
+
+    fn zlib(data: &[u8]) -> Vec<u8> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn reads_frames_and_nc_areas() {
+        let frames = [
+            vec![b'X'; 8192],
+            (0..8192u32).map(|i| (i * 3) as u8).collect(),
+            vec![b'Y'; 100],
+        ];
+        let stored = [zlib(&frames[0]), frames[1].clone(), zlib(&frames[2])];
+        let total = 2 * 8192 + 100u32;
+
+        let mut data = MAGIC.to_vec();
+        for field in [total, 1, 1, 0, 0, 0, 0] {
+            data.extend_from_slice(&field.to_le_bytes());
+        }
+        let mut offset = HEADER_SIZE as u32 + 3 * 4 + 3 * 2 + 8;
+        for block in &stored {
+            data.extend_from_slice(&offset.to_le_bytes());
+            offset += block.len() as u32;
+        }
+        for block in &stored {
+            data.extend_from_slice(&(block.len() as u16).to_le_bytes());
+        }
+        // Frame 1 is in an NC area
+        data.extend_from_slice(&1u32.to_le_bytes());
+        data.extend_from_slice(&1u32.to_le_bytes());
+        for block in &stored {
+            data.extend_from_slice(block);
+        }
+
+        let kids = DAX.children(Arc::new(BytesReader::new(data))).unwrap();
+        let reader = &kids[0].reader;
+        assert_eq!(reader.size(), Some(u64::from(total)));
+        let mut buf = vec![0u8; 8192];
+        for (index, frame) in frames.iter().enumerate() {
+            let n = reader.read_at(index as u64 * 8192, &mut buf).unwrap();
+            assert_eq!(&buf[..n], &frame[..]);
+        }
+    }
+}
@@ -1,7 +1,7 @@
 //! DMG (Apple Disk Image) reader
 //!
 //! Parses UDIF DMG format with koly trailer, XML plist, and MISH blocks.
-//! Supports zlib, bzip2, lzfse compression.
+//! Supports ADC, zlib, bzip2, lzfse and LZMA compression.
 
 use crate::container::{invalid_data, Child, Container};
 use crate::detect::Reader;
@@ -17,12 +17,15 @@ const MISH_MAGIC: u32 = 0x6d697368;
 const UDZE: u32 = 0x00000000; // Zeros
 const UDRW: u32 = 0x00000001; // Raw
 const UDIG: u32 = 0x00000002; // Ignore
+const UDCO: u32 = 0x80000004; // ADC
 const UDZO: u32 = 0x80000005; // zlib
 const UDBZ: u32 = 0x80000006; // bzip2
 const ULFO: u32 = 0x80000007; // lzfse
+const ULMO: u32 = 0x80000008; // LZMA (xz stream)
 const COMMENT: u32 = 0x7ffffffe;
 const LAST_ENTRY: u32 = 0xffffffff;
 const MAX_CHUNK_SIZE: u64 = 64 * 1024 * 1024;
+const XZ_MAGIC: &[u8] = b"\xfd7zXZ\0";
 
 /// DMG disk image container
 pub struct DmgContainer;
@@ -38,6 +41,7 @@ impl Container for DmgContainer {
             index: 0,
             offset: 0,
             reader: Arc::new(dmg_reader),
+            metadata: Vec::new(),
         }])
     }
 }
@@ -215,7 +219,7 @@ impl DmgReader {
         Ok(chunks)
     }
 
-    fn decode_base64(input: &str) -> io::Result<Vec<u8>> {
+    pub(crate) fn decode_base64(input: &str) -> io::Result<Vec<u8>> {
         const BASE64_TABLE: &[u8; 64] =
             b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
 
@@ -356,7 +360,7 @@ impl DmgReader {
             .ok_or_else(|| invalid_data("DMG chunk too large"))?;
 
         let source_size = match chunk.chunk_type {
-            UDZO | UDBZ | ULFO => usize::try_from(chunk.compressed_length)
+            UDCO | UDZO | UDBZ | ULFO | ULMO => usize::try_from(chunk.compressed_length)
                 .ok()
                 .filter(|&size| size <= MAX_CHUNK_SIZE as usize + 1024 * 1024)
                 .ok_or_else(|| invalid_data("DMG compressed chunk too large"))?,
@@ -418,11 +422,87 @@ impl DmgReader {
                 }
                 Ok(decompressed)
             }
+            UDCO => {
+                let mut compressed = vec![0u8; source_size];
+                if self.parent.read_at(chunk.compressed_offset, &mut compressed)? != source_size {
+                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "short DMG ADC chunk read"));
+                }
+                adc_decompress(&compressed, uncompressed_size)
+            }
+            ULMO => {
+                let mut compressed = vec![0u8; source_size];
+                if self.parent.read_at(chunk.compressed_offset, &mut compressed)? != source_size {
+                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "short DMG LZMA chunk read"));
+                }
+
+                // hdiutil writes xz streams; accept a bare LZMA stream too
+                let mut decompressed = Vec::with_capacity(uncompressed_size);
+                let result = if compressed.starts_with(XZ_MAGIC) {
+                    lzma_rs::xz_decompress(&mut &compressed[..], &mut decompressed)
+                } else {
+                    lzma_rs::lzma_decompress(&mut &compressed[..], &mut decompressed)
+                };
+                result.map_err(|_| invalid_data("DMG LZMA decode error"))?;
+                if decompressed.len() < uncompressed_size {
+                    return Err(invalid_data("DMG LZMA: short decompression"));
+                }
+                decompressed.truncate(uncompressed_size);
+                Ok(decompressed)
+            }
             _ => Err(invalid_data("unsupported DMG compression type")),
         }
     }
 }
 
+/// Decode Apple Data Compression, an LZ77 variant with a 64 KB window.
+/// Each code byte starts either a literal run or a back reference:
+///
+/// - `1lllllll`: 1-128 literal bytes follow
+/// - `01llllll oooooooo oooooooo`: copy 4-67 bytes from up to 65536 back
+/// - `00llllpp pppppppp`: copy 3-18 bytes from up to 1024 back
+fn adc_decompress(input: &[u8], output_size: usize) -> io::Result<Vec<u8>> {
+    let mut output = Vec::with_capacity(output_size);
+    let mut pos = 0;
+
+    while output.len() < output_size && pos < input.len() {
+        let code = input[pos];
+        let (length, distance) = if code & 0x80 != 0 {
+            let length = (code & 0x7f) as usize + 1;
+            let literal = input
+                .get(pos + 1..pos + 1 + length)
+                .ok_or_else(|| invalid_data("truncated ADC literal"))?;
+            output.extend_from_slice(literal);
+            pos += 1 + length;
+            continue;
+        } else if code & 0x40 != 0 {
+            let offset = input
+                .get(pos + 1..pos + 3)
+                .ok_or_else(|| invalid_data("truncated ADC code"))?;
+            pos += 3;
+            ((code & 0x3f) as usize + 4, u16::from_be_bytes([offset[0], offset[1]]) as usize + 1)
+        } else {
+            let low = *input.get(pos + 1).ok_or_else(|| invalid_data("truncated ADC code"))?;
+            pos += 2;
+            (((code & 0x3f) >> 2) as usize + 3, (((code & 0x03) as usize) << 8 | low as usize) + 1)
+        };
+
+        let start = output
+            .len()
+            .checked_sub(distance)
+            .ok_or_else(|| invalid_data("ADC reference before start of chunk"))?;
+        // Copies may overlap the bytes they produce
+        for i in start..start + length {
+            output.push(output[i]);
+        }
+    }
+
+    if output.len() < output_size {
+        return Err(invalid_data("ADC: short decompression"));
+    }
+    output.truncate(output_size);
+    Ok(output)
+}
+
 impl Reader for DmgReader {
     fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
         if offset >= self.virtual_size {
@@ -506,6 +586,35 @@ mod tests {
         assert!(reader.decompress_chunk(&chunk).is_err());
     }
 
+    #[test]
+    fn decodes_adc() {
+        // "abc", then 6 bytes from 3 back, then 4 bytes from 9 back
+        let input = [0x82, b'a', b'b', b'c', 0x0c, 0x02, 0x40, 0x00, 0x08];
+        assert_eq!(adc_decompress(&input, 13).unwrap(), b"abcabcabcabca");
+        assert!(adc_decompress(&input, 14).is_err());
+        assert!(adc_decompress(&[0x0c, 0x02], 6).is_err());
+    }
+
+    #[test]
+    fn decodes_lzma_chunk() {
+        let data: Vec<u8> = (0..1024u32).map(|i| (i % 7) as u8).collect();
+        let mut compressed = Vec::new();
+        lzma_rs::xz_compress(&mut &data[..], &mut compressed).unwrap();
+        let chunk = DmgChunk {
+            chunk_type: ULMO,
+            sector_start: 0,
+            sector_count: 2,
+            compressed_offset: 0,
+            compressed_length: compressed.len() as u64,
+        };
+        let reader = DmgReader {
+            parent: Arc::new(BytesReader::new(compressed)),
+            chunks: vec![chunk.clone()],
+            virtual_size: 1024,
+        };
+        assert_eq!(reader.decompress_chunk(&chunk).unwrap(), data);
+    }
+
     #[test]
     fn rejects_mish_sector_overflow() {
         let mut data = vec![0u8; 244];
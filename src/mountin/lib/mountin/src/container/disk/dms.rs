@@ -1051,6 +1051,7 @@ impl Container for DmsContainer {
             index: 0,
             offset: u64::MAX, // Transformed data, not a slice
             reader: Arc::new(BytesReader::new(decompressed)),
+            metadata: Vec::new(),
         }])
     }
 }
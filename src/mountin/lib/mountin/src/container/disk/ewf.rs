@@ -1,19 +1,88 @@
 //! EWF (Expert Witness Format) disk image reader
 //!
-//! Parses EWF v1 (E01) forensic disk images. Chunks are zlib compressed
-//! with an offset table for random access, similar to cloop.
+//! Parses EWF v1 (E01) and v2 (Ex01) forensic images. Chunks are zlib or
+//! bzip2 compressed with an offset table for random access, similar to
+//! cloop. Images split into segments (`.E01`, `.E02`, ...) are read from
+//! the first segment and its siblings. Case details from the header
+//! sections and the stored MD5/SHA1 hashes are reported as metadata.
+//!
+//! Logical evidence files (L01, Lx01) hold files rather than a disk: their
+//! chunks are the files' data end to end, and a single-files tree lists
+//! each file's name and extents. Those files are the children instead.
 
-use crate::container::{checked_table_size, invalid_data, Child, Container};
+use crate::container::digest::{self, Md5, Sha1};
+use crate::container::split::ConcatReader;
+use crate::container::{
+    checked_table_size, invalid_data, le64, slice::SliceReader, utc_date, verify_hashes, Child,
+    Container, Metadata,
+};
 use crate::detect::Reader;
+use bzip2::read::BzDecoder;
 use flate2::read::ZlibDecoder;
 use std::io::{self, Read};
 use std::sync::Arc;
 
+const V1_SIGNATURE: &[u8; 8] = b"EVF\x09\x0d\x0a\xff\x00";
+const V1_LOGICAL_SIGNATURE: &[u8; 8] = b"LVF\x09\x0d\x0a\xff\x00";
+const V2_SIGNATURE: &[u8; 8] = b"EVF2\x0d\x0a\x81\x00";
+const V2_LOGICAL_SIGNATURE: &[u8; 8] = b"LEF2\x0d\x0a\x81\x00";
 const FILE_HEADER_SIZE: u64 = 13;
+const V2_FILE_HEADER_SIZE: u64 = 32;
 const SECTION_DESCRIPTOR_SIZE: usize = 76;
+const V2_SECTION_DESCRIPTOR_SIZE: usize = 64;
 const TABLE_HEADER_SIZE: usize = 24; // chunk_count(4) + padding(16) + checksum(4)
+const V2_TABLE_HEADER_SIZE: usize = 32;
 const COMPRESSED_FLAG: u32 = 0x8000_0000;
 const MAX_CHUNK_SIZE: u32 = 64 * 1024 * 1024;
+/// Most sections followed in one EWF2 segment
+const MAX_SECTIONS: usize = 1 << 20;
+/// Largest decompressed header text
+const MAX_TEXT_SIZE: u64 = 1024 * 1024;
+/// Bytes before the text of a v1 `ltree` section
+const LTREE_HEADER_SIZE: usize = 48;
+/// Deepest entry nesting followed in a single-files tree
+const MAX_TREE_DEPTH: usize = 256;
+
+// EWF2 section types
+const DEVICE_INFORMATION: u32 = 0x01;
+const CASE_DATA: u32 = 0x02;
+const SECTOR_TABLE: u32 = 0x04;
+const MD5_HASH: u32 = 0x08;
+const SHA1_HASH: u32 = 0x09;
+const NEXT: u32 = 0x0d;
+const DONE: u32 = 0x0f;
+const SINGLE_FILES_DATA: u32 = 0x20;
+const SECTION_ENCRYPTED: u32 = 0x02;
+
+// EWF2 sector table entry flags
+const CHUNK_COMPRESSED: u32 = 0x01;
+const CHUNK_PATTERN_FILL: u32 = 0x04;
+
+/// Header text keys reported as metadata: EWF v1 `header`/`header2`, then
+/// EWF2 case data and device information
+const HEADER_FIELDS: &[(&str, &str)] = &[
+    ("c", "case_number"),
+    ("n", "evidence_number"),
+    ("a", "description"),
+    ("e", "examiner"),
+    ("t", "notes"),
+    ("av", "software_version"),
+    ("ov", "operating_system"),
+    ("m", "acquired"),
+    ("u", "system_date"),
+    ("md", "model"),
+    ("sn", "serial_number"),
+    ("l", "device_label"),
+    ("cn", "case_number"),
+    ("en", "evidence_number"),
+    ("nm", "description"),
+    ("ex", "examiner"),
+    ("nt", "notes"),
+    ("os", "operating_system"),
+    ("at", "acquired"),
+    ("tt", "system_date"),
+    ("lb", "device_label"),
+];
 
 /// EWF disk image container
 pub struct EwfContainer;
@@ -23,40 +92,123 @@ pub static EWF: EwfContainer = EwfContainer;
 
 impl Container for EwfContainer {
     fn children(&self, reader: Arc<dyn Reader + Send + Sync>) -> io::Result<Vec<Child>> {
-        let ewf_reader = EwfReader::new(reader)?;
+        let mut ewf_reader = EwfReader::new(reader)?;
+        if let Some(files) = ewf_reader.files.take() {
+            let media = Arc::new(ewf_reader);
+            return files
+                .into_iter()
+                .enumerate()
+                .map(|(index, file)| {
+                    let reader = file_reader(&media, &file)?;
+                    let mut metadata = vec![("name", file.path)];
+                    metadata.extend(file.md5.map(|md5| ("md5", md5)));
+                    Ok(Child {
+                        index: index as u32,
+                        // Files are runs of the decoded chunks
+                        offset: u64::MAX,
+                        reader,
+                        metadata,
+                    })
+                })
+                .collect();
+        }
+        let mut metadata = ewf_reader.metadata.clone();
+        if verify_hashes() {
+            metadata.extend(ewf_reader.verify()?);
+        }
 
         Ok(vec![Child {
             index: 0,
             offset: 0,
             reader: Arc::new(ewf_reader),
+            metadata,
         }])
     }
 }
 
-/// Section location parsed from walking the section chain
-struct SectionInfo {
-    /// Offset of the sectors section (chunk offsets are relative to this)
-    sectors_offset: u64,
-    /// Offset of the next section after sectors (bounds the last chunk)
-    sectors_end: u64,
-    /// Chunk count from the volume/data section
+/// How chunks are compressed
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Compression {
+    Zlib,
+    Bzip2,
+}
+
+/// What a segment's file header says about the image
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct FileHeader {
+    version: u8,
+    /// Logical evidence: files rather than a disk
+    logical: bool,
+    compression: Compression,
+}
+
+/// Disk geometry from the volume section
+struct Volume {
     chunk_count: u32,
     /// Bytes per chunk (sectors_per_chunk * bytes_per_sector)
     chunk_size: u32,
     /// Total virtual disk size in bytes
     virtual_size: u64,
-    /// Chunk offset entries from the table section
-    chunk_offsets: Vec<u32>,
 }
 
-/// Reader that translates virtual disk offsets through EWF chunk table
+/// How one chunk is stored
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Storage {
+    Stored,
+    Compressed,
+    /// Every 8 bytes the same, kept in the table instead of the data
+    Pattern([u8; 8]),
+}
+
+/// Where one chunk's stored data lies
+struct Chunk {
+    /// Index of the segment file holding it
+    segment: usize,
+    offset: u64,
+    /// Start of the next chunk, or the end of the sectors section for the
+    /// last chunk of a v1 table
+    end: u64,
+    storage: Storage,
+}
+
+/// What the sections of all segments describe
+#[derive(Default)]
+struct Sections {
+    volume: Option<Volume>,
+    chunks: Vec<Chunk>,
+    /// Text of the v1 `header2`, or `header` if there is none
+    header: Option<(bool, String)>,
+    /// Text of the EWF2 case data and device information sections
+    case_data: Option<String>,
+    device_information: Option<String>,
+    md5: Option<[u8; 16]>,
+    sha1: Option<[u8; 20]>,
+    /// Text of the single-files tree of logical evidence
+    file_tree: Option<String>,
+}
+
+/// A file of logical evidence
+struct LogicalFile {
+    path: String,
+    size: u64,
+    /// Where the file's data lies in the decoded chunks, in order
+    extents: Vec<(u64, u64)>,
+    md5: Option<String>,
+}
+
+/// Reader that translates virtual disk offsets through EWF chunk tables
 pub struct EwfReader {
-    parent: Arc<dyn Reader + Send + Sync>,
-    sectors_offset: u64,
-    sectors_end: u64,
-    chunk_offsets: Vec<u32>,
+    segments: Vec<Arc<dyn Reader + Send + Sync>>,
+    chunks: Vec<Chunk>,
     chunk_size: u64,
     virtual_size: u64,
+    compression: Compression,
+    /// Case details and stored hashes
+    metadata: Metadata,
+    md5: Option<[u8; 16]>,
+    sha1: Option<[u8; 20]>,
+    /// The files of logical evidence, taken out when listing them
+    files: Option<Vec<LogicalFile>>,
 }
 
 fn read_le_u32(reader: &dyn Reader, offset: u64) -> io::Result<u32> {
@@ -75,14 +227,181 @@ fn read_le_u64(reader: &dyn Reader, offset: u64) -> io::Result<u64> {
     Ok(u64::from_le_bytes(buf))
 }
 
-fn parse_sections(reader: &dyn Reader) -> io::Result<SectionInfo> {
+/// Read `size` bytes of section data at `offset`
+fn read_section(reader: &dyn Reader, offset: u64, size: u64) -> io::Result<Vec<u8>> {
+    let size = checked_table_size(reader, offset, size, 1)?;
+    let mut data = vec![0u8; size];
+    if reader.read_at(offset, &mut data)? != size {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "short EWF section read",
+        ));
+    }
+    Ok(data)
+}
+
+/// Check the file header of segment `number` (counting from 1)
+fn check_header(reader: &dyn Reader, number: u32) -> io::Result<FileHeader> {
+    let mut header = [0u8; V2_FILE_HEADER_SIZE as usize];
+    let n = reader.read_at(0, &mut header)?;
+    if n < FILE_HEADER_SIZE as usize {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "short EWF header read",
+        ));
+    }
+    let signature = &header[..8];
+    let (version, logical) = if signature == V1_SIGNATURE {
+        (1, false)
+    } else if signature == V2_SIGNATURE {
+        (2, false)
+    } else if signature == V1_LOGICAL_SIGNATURE {
+        (1, true)
+    } else if signature == V2_LOGICAL_SIGNATURE {
+        (2, true)
+    } else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not an EWF file",
+        ));
+    };
+
+    let (segment_number, compression) = if version == 1 {
+        (u32::from(u16::from_le_bytes([header[9], header[10]])), Compression::Zlib)
+    } else {
+        if n != header.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "short EWF2 header read",
+            ));
+        }
+        let compression = match u16::from_le_bytes([header[10], header[11]]) {
+            0 | 1 => Compression::Zlib,
+            2 => Compression::Bzip2,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "unsupported EWF2 compression method",
+                ))
+            }
+        };
+        (u32::from_le_bytes([header[12], header[13], header[14], header[15]]), compression)
+    };
+    if segment_number != number {
+        return Err(invalid_data("EWF segment number mismatch"));
+    }
+    Ok(FileHeader {
+        version,
+        logical,
+        compression,
+    })
+}
+
+/// Inflate a zlib-compressed header text section. `header2` and EWF2 text
+/// is UTF-16LE; `header` is in the acquiring system's code page, read here
+/// as Latin-1.
+fn header_text(data: &[u8]) -> io::Result<String> {
+    let mut text = Vec::new();
+    ZlibDecoder::new(data)
+        .take(MAX_TEXT_SIZE)
+        .read_to_end(&mut text)?;
+    Ok(decode_text(&text))
+}
+
+/// Text in UTF-16LE, if it starts with a byte order mark or its first
+/// character is ASCII, else Latin-1
+fn decode_text(text: &[u8]) -> String {
+    let utf16 = text.starts_with(&[0xff, 0xfe]) || text.get(1) == Some(&0);
+    if utf16 {
+        let units: Vec<u16> = text
+            .chunks_exact(2)
+            .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+            .collect();
+        let text = String::from_utf16_lossy(&units);
+        text.trim_start_matches('\u{feff}').to_string()
+    } else {
+        text.iter().map(|&byte| char::from(byte)).collect()
+    }
+}
+
+/// Key/value pairs of the `main` category of a header text: a line of
+/// tab-separated keys, then a line of values
+fn header_values(text: &str) -> Vec<(&str, &str)> {
+    let mut lines = text.lines().map(|line| line.trim_end_matches('\r'));
+    if !lines.any(|line| line == "main") {
+        return Vec::new();
+    }
+    let (Some(keys), Some(values)) = (lines.next(), lines.next()) else {
+        return Vec::new();
+    };
+    keys.split('\t').zip(values.split('\t')).collect()
+}
+
+/// A header date: seconds since 1970 in `header2` and EWF2, or
+/// "year month day hours minutes seconds" local time in `header`
+fn header_date(value: &str) -> String {
+    if let Ok(seconds) = value.parse::<u64>() {
+        return utc_date(seconds);
+    }
+    let fields: Vec<u32> = value
+        .split_whitespace()
+        .filter_map(|field| field.parse().ok())
+        .collect();
+    match fields[..] {
+        [year, month, day, hours, minutes, seconds] => format!(
+            "{year:04}-{month:02}-{day:02}T{hours:02}:{minutes:02}:{seconds:02}"
+        ),
+        _ => value.to_string(),
+    }
+}
+
+/// Metadata for the known fields of header texts, the first text giving a
+/// field winning
+fn header_metadata(texts: &[&str]) -> Metadata {
+    let mut metadata: Metadata = Vec::new();
+    for text in texts {
+        for (key, value) in header_values(text) {
+            let value = value.trim();
+            let Some(&(_, name)) = HEADER_FIELDS.iter().find(|(field, _)| *field == key) else {
+                continue;
+            };
+            if value.is_empty() || metadata.iter().any(|(known, _)| *known == name) {
+                continue;
+            }
+            let value = match name {
+                "acquired" | "system_date" => header_date(value),
+                _ => value.to_string(),
+            };
+            metadata.push((name, value));
+        }
+    }
+    metadata
+}
+
+/// A header text value parsed as a number
+fn header_number(text: Option<&str>, key: &str) -> Option<u64> {
+    header_values(text?)
+        .into_iter()
+        .find(|(field, _)| *field == key)
+        .and_then(|(_, value)| value.trim().parse().ok())
+}
+
+/// Stored hashes of all zeros weren't computed
+fn stored_hash<const N: usize>(data: &[u8]) -> Option<[u8; N]> {
+    let hash: [u8; N] = data.get(..N)?.try_into().ok()?;
+    hash.iter().any(|&byte| byte != 0).then_some(hash)
+}
+
+/// Walk one v1 segment's section chain, adding what it describes to
+/// `sections`. Returns whether the chain ends in a "next" section, meaning
+/// another segment follows.
+fn parse_segment(
+    reader: &dyn Reader,
+    segment: usize,
+    sections: &mut Sections,
+) -> io::Result<bool> {
     let mut pos = FILE_HEADER_SIZE;
-    let mut sectors_offset = 0u64;
-    let mut sectors_end = 0u64;
-    let mut chunk_count = 0u32;
-    let mut chunk_size = 0u32;
-    let mut virtual_size = 0u64;
-    let mut chunk_offsets = Vec::new();
+    let mut sectors_end = None;
 
     loop {
         // Read section type (first 16 bytes of descriptor)
@@ -98,38 +417,78 @@ fn parse_sections(reader: &dyn Reader) -> io::Result<SectionInfo> {
             .trim_end_matches('\0');
 
         let next_offset = read_le_u64(reader, pos + 16)?;
+        let body_start = pos + SECTION_DESCRIPTOR_SIZE as u64;
+        let body_size = || -> io::Result<u64> {
+            read_le_u64(reader, pos + 24)?
+                .checked_sub(SECTION_DESCRIPTOR_SIZE as u64)
+                .ok_or_else(|| invalid_data("invalid EWF section size"))
+        };
 
         match section_type {
-            "volume" | "data" => {
+            // Later segments repeat the geometry in a "data" section
+            "volume" | "disk" | "data" if sections.volume.is_none() => {
                 // Volume/data section: parse disk geometry after descriptor
-                let vol_start = pos + SECTION_DESCRIPTOR_SIZE as u64;
                 // Fields: reserved(4), chunk_count(4), sectors_per_chunk(4),
                 //         bytes_per_sector(4), sector_count(8)
-                chunk_count = read_le_u32(reader, vol_start + 4)?;
-                let sectors_per_chunk = read_le_u32(reader, vol_start + 8)?;
-                let bytes_per_sector = read_le_u32(reader, vol_start + 12)?;
-                let sector_count = read_le_u64(reader, vol_start + 16)?;
+                let chunk_count = read_le_u32(reader, body_start + 4)?;
+                let sectors_per_chunk = read_le_u32(reader, body_start + 8)?;
+                let bytes_per_sector = read_le_u32(reader, body_start + 12)?;
+                let sector_count = read_le_u64(reader, body_start + 16)?;
 
-                chunk_size = sectors_per_chunk
+                let chunk_size = sectors_per_chunk
                     .checked_mul(bytes_per_sector)
                     .filter(|&s| s > 0 && s <= MAX_CHUNK_SIZE)
                     .ok_or_else(|| {
                         io::Error::new(io::ErrorKind::InvalidData, "invalid chunk size")
                     })?;
-                virtual_size = sector_count
+                let virtual_size = sector_count
                     .checked_mul(bytes_per_sector as u64)
                     .ok_or_else(|| invalid_data("EWF virtual size overflow"))?;
+                sections.volume = Some(Volume {
+                    chunk_count,
+                    chunk_size,
+                    virtual_size,
+                });
+            }
+            // header2 (UTF-16) is preferred over header; both are repeated
+            "header2" | "header" => {
+                let is_header2 = section_type == "header2";
+                if sections.header.as_ref().is_none_or(|(found2, _)| is_header2 && !found2) {
+                    let data = read_section(reader, body_start, body_size()?)?;
+                    sections.header = Some((is_header2, header_text(&data)?));
+                }
+            }
+            // A 48-byte header (MD5, text size, checksum), then the text
+            "ltree" if sections.file_tree.is_none() => {
+                let data = read_section(reader, body_start, body_size()?)?;
+                let text = data
+                    .get(16..24)
+                    .and_then(|size| usize::try_from(le64(size)).ok())
+                    .and_then(|size| data.get(LTREE_HEADER_SIZE..)?.get(..size))
+                    .ok_or_else(|| invalid_data("EWF ltree section too short"))?;
+                sections.file_tree = Some(decode_text(text));
+            }
+            "hash" => {
+                let data = read_section(reader, body_start, 16)?;
+                sections.md5 = sections.md5.or(stored_hash(&data));
+            }
+            "digest" => {
+                let data = read_section(reader, body_start, 36)?;
+                sections.md5 = stored_hash(&data[..16]).or(sections.md5);
+                sections.sha1 = stored_hash(&data[16..]).or(sections.sha1);
             }
             "sectors" => {
-                sectors_offset = pos;
-                sectors_end = next_offset;
+                sectors_end = Some(next_offset);
             }
-            "table" if chunk_offsets.is_empty() => {
-                // First table section: read chunk offset entries
-                let tbl_start = pos + SECTION_DESCRIPTOR_SIZE as u64;
-                let tbl_count = read_le_u32(reader, tbl_start)?;
+            "table" => {
+                // Chunk offsets are relative to the table's base offset,
+                // and bounded by the sectors section before it
+                let sectors_end =
+                    sectors_end.ok_or_else(|| invalid_data("EWF table without sectors"))?;
+                let tbl_count = read_le_u32(reader, body_start)?;
+                let base_offset = read_le_u64(reader, body_start + 8)?;
 
-                let entries_start = tbl_start
+                let entries_start = body_start
                     .checked_add(TABLE_HEADER_SIZE as u64)
                     .ok_or_else(|| invalid_data("EWF table offset overflow"))?;
                 let entries_bytes = checked_table_size(reader, entries_start, tbl_count as u64, 4)?;
@@ -141,17 +500,36 @@ fn parse_sections(reader: &dyn Reader) -> io::Result<SectionInfo> {
                     ));
                 }
 
-                chunk_offsets = entries_data
+                let offsets = entries_data
                     .chunks_exact(4)
-                    .map(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]]))
-                    .collect();
+                    .map(|c| {
+                        let entry = u32::from_le_bytes([c[0], c[1], c[2], c[3]]);
+                        let offset = base_offset
+                            .checked_add((entry & !COMPRESSED_FLAG) as u64)
+                            .ok_or_else(|| invalid_data("EWF chunk offset overflow"))?;
+                        Ok((offset, entry & COMPRESSED_FLAG != 0))
+                    })
+                    .collect::<io::Result<Vec<_>>>()?;
+                for (i, &(offset, compressed)) in offsets.iter().enumerate() {
+                    sections.chunks.push(Chunk {
+                        segment,
+                        offset,
+                        end: offsets.get(i + 1).map_or(sectors_end, |next| next.0),
+                        storage: if compressed {
+                            Storage::Compressed
+                        } else {
+                            Storage::Stored
+                        },
+                    });
+                }
             }
-            "done" => break,
+            "next" => return Ok(true),
+            "done" => return Ok(false),
             _ => {}
         }
 
         if next_offset == pos || next_offset == 0 {
-            break;
+            return Ok(false);
         }
         if next_offset < pos
             || reader.size().is_some_and(|size| {
@@ -162,108 +540,489 @@ fn parse_sections(reader: &dyn Reader) -> io::Result<SectionInfo> {
         }
         pos = next_offset;
     }
+}
 
-    if sectors_offset == 0 || chunk_offsets.is_empty() || chunk_size == 0 {
-        return Err(io::Error::new(
-            io::ErrorKind::InvalidData,
-            "missing required EWF sections",
-        ));
-    }
-
-    Ok(SectionInfo {
-        sectors_offset,
-        sectors_end,
-        chunk_count,
-        chunk_size,
-        virtual_size,
-        chunk_offsets,
-    })
+/// An EWF2 section: its descriptor follows its data
+struct Section {
+    kind: u32,
+    data_offset: u64,
+    data_size: u64,
 }
 
-impl EwfReader {
-    pub fn new(parent: Arc<dyn Reader + Send + Sync>) -> io::Result<Self> {
-        // Verify magic
-        let mut magic = [0u8; 8];
-        if parent.read_at(0, &mut magic)? != 8 {
+/// Walk one EWF2 segment's sections, adding what they describe to
+/// `sections`. The last descriptor ends the file and each points back to
+/// the one before. Returns whether the segment ends in a "next" section.
+fn parse_segment_v2(
+    reader: &dyn Reader,
+    segment: usize,
+    sections: &mut Sections,
+) -> io::Result<bool> {
+    let size = reader
+        .size()
+        .ok_or_else(|| invalid_data("EWF2 segment has unknown size"))?;
+    let mut pos = size
+        .checked_sub(V2_SECTION_DESCRIPTOR_SIZE as u64)
+        .filter(|&pos| pos >= V2_FILE_HEADER_SIZE)
+        .ok_or_else(|| invalid_data("EWF2 segment too small"))?;
+
+    let mut chain = Vec::new();
+    loop {
+        let mut descriptor = [0u8; V2_SECTION_DESCRIPTOR_SIZE];
+        if reader.read_at(pos, &mut descriptor)? != descriptor.len() {
             return Err(io::Error::new(
                 io::ErrorKind::UnexpectedEof,
-                "short EWF header read",
+                "short section descriptor read",
             ));
         }
-        if &magic != b"EVF\x09\x0d\x0a\xff\x00" {
+        let field32 = |at: usize| u32::from_le_bytes(descriptor[at..at + 4].try_into().unwrap());
+        let field64 = |at: usize| u64::from_le_bytes(descriptor[at..at + 8].try_into().unwrap());
+        let (kind, flags, previous, data_size) = (field32(0), field32(4), field64(8), field64(16));
+
+        if flags & SECTION_ENCRYPTED != 0 {
             return Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                "not an EWF v1 file",
+                io::ErrorKind::Unsupported,
+                "encrypted EWF2 images are not supported",
             ));
         }
+        let data_offset = pos
+            .checked_sub(data_size)
+            .filter(|&offset| offset >= V2_FILE_HEADER_SIZE)
+            .ok_or_else(|| invalid_data("invalid EWF2 section size"))?;
+        chain.push(Section {
+            kind,
+            data_offset,
+            data_size,
+        });
+
+        if previous == 0 || data_offset == V2_FILE_HEADER_SIZE {
+            break;
+        }
+        if previous.saturating_add(V2_SECTION_DESCRIPTOR_SIZE as u64) > data_offset
+            || chain.len() >= MAX_SECTIONS
+        {
+            return Err(invalid_data("invalid EWF section chain"));
+        }
+        pos = previous;
+    }
+
+    let more = chain.first().is_some_and(|last| last.kind == NEXT);
+    if !more && chain.first().is_some_and(|last| last.kind != DONE) {
+        return Err(invalid_data("EWF2 segment doesn't end in a next or done section"));
+    }
+
+    for section in chain.iter().rev() {
+        let data = || read_section(reader, section.data_offset, section.data_size);
+        match section.kind {
+            DEVICE_INFORMATION if sections.device_information.is_none() => {
+                sections.device_information = Some(header_text(&data()?)?);
+            }
+            CASE_DATA if sections.case_data.is_none() => {
+                sections.case_data = Some(header_text(&data()?)?);
+            }
+            SINGLE_FILES_DATA if sections.file_tree.is_none() => {
+                sections.file_tree = Some(header_text(&data()?)?);
+            }
+            SECTOR_TABLE => parse_sector_table(&data()?, segment, &mut sections.chunks)?,
+            MD5_HASH => sections.md5 = stored_hash(&data()?),
+            SHA1_HASH => sections.sha1 = stored_hash(&data()?),
+            _ => {}
+        }
+    }
+    Ok(more)
+}
+
+/// Add the chunks of an EWF2 sector table. Each 16-byte entry gives the
+/// chunk's offset in the segment, its stored size and flags.
+fn parse_sector_table(data: &[u8], segment: usize, chunks: &mut Vec<Chunk>) -> io::Result<()> {
+    if data.len() < V2_TABLE_HEADER_SIZE {
+        return Err(invalid_data("EWF2 sector table too short"));
+    }
+    let first_chunk = u64::from_le_bytes(data[0..8].try_into().unwrap());
+    let count = u32::from_le_bytes(data[8..12].try_into().unwrap()) as usize;
+    if first_chunk != chunks.len() as u64 {
+        return Err(invalid_data("EWF2 sector tables out of order"));
+    }
+    let entries = data[V2_TABLE_HEADER_SIZE..]
+        .get(..count * 16)
+        .ok_or_else(|| invalid_data("EWF2 sector table too short"))?;
+
+    for entry in entries.chunks_exact(16) {
+        let offset = u64::from_le_bytes(entry[0..8].try_into().unwrap());
+        let size = u32::from_le_bytes(entry[8..12].try_into().unwrap());
+        let flags = u32::from_le_bytes(entry[12..16].try_into().unwrap());
+        let storage = if flags & CHUNK_PATTERN_FILL != 0 {
+            Storage::Pattern(entry[0..8].try_into().unwrap())
+        } else if flags & CHUNK_COMPRESSED != 0 {
+            Storage::Compressed
+        } else {
+            Storage::Stored
+        };
+        let end = match storage {
+            Storage::Pattern(_) => offset,
+            _ => offset
+                .checked_add(size as u64)
+                .ok_or_else(|| invalid_data("EWF chunk offset overflow"))?,
+        };
+        chunks.push(Chunk {
+            segment,
+            offset,
+            end,
+            storage,
+        });
+    }
+    Ok(())
+}
+
+/// Geometry of an EWF2 image from its device information and case data
+fn volume_v2(sections: &Sections) -> io::Result<Volume> {
+    let device = sections.device_information.as_deref();
+    let case = sections.case_data.as_deref();
+    let (Some(sector_count), Some(bytes_per_sector), Some(sectors_per_chunk)) = (
+        header_number(device, "ts"),
+        header_number(device, "bp"),
+        header_number(case, "sb"),
+    ) else {
+        return Err(invalid_data("missing EWF2 geometry"));
+    };
+
+    let chunk_size = sectors_per_chunk
+        .checked_mul(bytes_per_sector)
+        .filter(|&s| s > 0 && s <= MAX_CHUNK_SIZE as u64)
+        .ok_or_else(|| invalid_data("invalid chunk size"))?;
+    let virtual_size = sector_count
+        .checked_mul(bytes_per_sector)
+        .ok_or_else(|| invalid_data("EWF virtual size overflow"))?;
+    let chunk_count = u32::try_from(virtual_size.div_ceil(chunk_size))
+        .map_err(|_| invalid_data("too many EWF chunks"))?;
+    Ok(Volume {
+        chunk_count,
+        chunk_size: chunk_size as u32,
+        virtual_size,
+    })
+}
+
+/// The files listed in the `entry` category of a single-files tree. After
+/// a line of keys, each entry is a line whose second field counts its
+/// sub-entries, a line of values, then those sub-entries.
+fn parse_file_tree(text: &str) -> io::Result<Vec<LogicalFile>> {
+    let mut lines = text.lines().map(|line| line.trim_end_matches('\r'));
+    if !lines.any(|line| line == "entry") {
+        return Err(invalid_data("EWF file tree has no entries"));
+    }
+    let keys: Vec<&str> = lines
+        .find(|line| line.split('\t').any(|key| key == "n"))
+        .ok_or_else(|| invalid_data("EWF file tree has no entries"))?
+        .split('\t')
+        .collect();
+    let mut files = Vec::new();
+    parse_file_entry(&mut lines, &keys, "", 0, &mut files)?;
+    Ok(files)
+}
+
+/// Add an entry of a single-files tree and its sub-entries to `files`.
+/// Directories are flagged `p`; files give their size in `ls`, their MD5
+/// in `ha`, and in `be` an extent count then hex offset and size pairs.
+fn parse_file_entry<'a>(
+    lines: &mut impl Iterator<Item = &'a str>,
+    keys: &[&str],
+    parent: &str,
+    depth: usize,
+    files: &mut Vec<LogicalFile>,
+) -> io::Result<()> {
+    if depth > MAX_TREE_DEPTH {
+        return Err(invalid_data("EWF file tree too deep"));
+    }
+    let (Some(counts), Some(values)) = (lines.next(), lines.next()) else {
+        return Err(invalid_data("truncated EWF file tree"));
+    };
+    let sub_entries: usize = counts
+        .split('\t')
+        .nth(1)
+        .and_then(|count| count.trim().parse().ok())
+        .ok_or_else(|| invalid_data("invalid EWF file tree entry"))?;
+    let values: Vec<&str> = values.split('\t').collect();
+    let value = |key: &str| {
+        let at = keys.iter().position(|field| *field == key)?;
+        values.get(at).map(|value| value.trim()).filter(|value| !value.is_empty())
+    };
+
+    let path = match (parent, value("n")) {
+        (parent, None) => parent.to_string(),
+        ("", Some(name)) => name.to_string(),
+        (parent, Some(name)) => format!("{parent}/{name}"),
+    };
+    if sub_entries == 0 && value("p") != Some("1") {
+        let mut numbers = value("be").unwrap_or("").split_whitespace();
+        let count: usize = numbers
+            .next()
+            .map_or(Ok(0), str::parse)
+            .map_err(|_| invalid_data("invalid EWF file extents"))?;
+        let mut hex = || {
+            numbers
+                .next()
+                .and_then(|number| u64::from_str_radix(number, 16).ok())
+                .ok_or_else(|| invalid_data("invalid EWF file extents"))
+        };
+        let extents = (0..count)
+            .map(|_| Ok((hex()?, hex()?)))
+            .collect::<io::Result<Vec<_>>>()?;
+        let size = match value("ls") {
+            Some(size) => size
+                .parse()
+                .map_err(|_| invalid_data("invalid EWF file size"))?,
+            None => extents.iter().map(|&(_, size)| size).sum(),
+        };
+        let md5 = value("ha")
+            .filter(|hash| hash.len() == 32 && hash.bytes().any(|digit| digit != b'0'))
+            .map(str::to_ascii_lowercase);
+        files.push(LogicalFile {
+            path: path.clone(),
+            size,
+            extents,
+            md5,
+        });
+    }
+    for _ in 0..sub_entries {
+        parse_file_entry(lines, keys, &path, depth + 1, files)?;
+    }
+    Ok(())
+}
+
+/// The data of a logical evidence file, from its extents of `media`
+fn file_reader(
+    media: &Arc<EwfReader>,
+    file: &LogicalFile,
+) -> io::Result<Arc<dyn Reader + Send + Sync>> {
+    let mut parts: Vec<Arc<dyn Reader + Send + Sync>> = Vec::new();
+    let mut left = file.size;
+    for &(offset, size) in &file.extents {
+        let size = size.min(left);
+        if offset.checked_add(size).is_none_or(|end| end > media.virtual_size) {
+            return Err(invalid_data("EWF file extends past the media data"));
+        }
+        if size > 0 {
+            parts.push(Arc::new(SliceReader::new(media.clone(), offset, size)));
+        }
+        left -= size;
+    }
+    if left > 0 {
+        return Err(invalid_data("EWF file extents shorter than the file"));
+    }
+    match parts.len() {
+        1 => Ok(parts.remove(0)),
+        _ => Ok(Arc::new(ConcatReader::new(parts)?)),
+    }
+}
+
+/// Name of segment `n` (counting from 1) of an image whose first segment
+/// is `first`: `.E01`-`.E99`, then `.EAA`-`.EZZ`, `.FAA` and on to `.ZZZ`
+fn segment_name(first: &str, n: u32) -> Option<String> {
+    let (stem, ext) = first.rsplit_once('.')?;
+    if ext.len() < 3 || !ext.is_ascii() {
+        return None;
+    }
+    let (prefix, counter) = ext.split_at(ext.len() - 3);
+    let letter = counter.as_bytes()[0];
+    if !letter.is_ascii_alphabetic() || &counter[1..] != "01" {
+        return None;
+    }
+
+    let counter = if n <= 99 {
+        format!("{}{n:02}", char::from(letter))
+    } else {
+        // Keep the case of the first segment's extension
+        let a = if ext.as_bytes()[0].is_ascii_uppercase() {
+            b'A'
+        } else {
+            b'a'
+        };
+        let k = n - 100;
+        let first = u32::from(letter) + k / 676;
+        let last = if letter.is_ascii_uppercase() { b'Z' } else { b'z' };
+        if first > u32::from(last) {
+            return None;
+        }
+        [first as u8, a + (k / 26 % 26) as u8, a + (k % 26) as u8]
+            .map(char::from)
+            .iter()
+            .collect()
+    };
+    Some(format!("{stem}.{prefix}{counter}"))
+}
+
+impl EwfReader {
+    pub fn new(parent: Arc<dyn Reader + Send + Sync>) -> io::Result<Self> {
+        let header = check_header(parent.as_ref(), 1)?;
+        let parse = |reader: &dyn Reader, segment: usize, sections: &mut Sections| {
+            if header.version == 1 {
+                parse_segment(reader, segment, sections)
+            } else {
+                parse_segment_v2(reader, segment, sections)
+            }
+        };
+
+        let mut sections = Sections::default();
+        let mut more = parse(parent.as_ref(), 0, &mut sections)?;
 
-        let info = parse_sections(parent.as_ref())?;
+        // Further segments are siblings of the first, found by name
+        let name = parent
+            .path()
+            .and_then(|path| path.file_name())
+            .and_then(|name| name.to_str())
+            .map(str::to_owned);
+        let mut segments = vec![parent];
+        while more {
+            let number = segments.len() as u32 + 1;
+            let next = name
+                .as_deref()
+                .and_then(|name| segment_name(name, number))
+                .ok_or_else(|| invalid_data("EWF segment can't be named"))?;
+            let segment = segments[0].sibling(&next)?;
+            if check_header(segment.as_ref(), number)? != header {
+                return Err(invalid_data("EWF segment from a different image"));
+            }
+            more = parse(segment.as_ref(), segments.len(), &mut sections)?;
+            segments.push(segment);
+        }
 
-        if info.chunk_offsets.len() != info.chunk_count as usize {
+        let volume = match header.version {
+            1 => sections.volume.take(),
+            _ => Some(volume_v2(&sections)?),
+        };
+        let volume = match volume {
+            Some(volume) if !sections.chunks.is_empty() => volume,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "missing required EWF sections",
+                ))
+            }
+        };
+        if sections.chunks.len() != volume.chunk_count as usize {
             return Err(io::Error::new(
                 io::ErrorKind::InvalidData,
                 "table/volume chunk count mismatch",
             ));
         }
 
+        let texts: Vec<&str> = [
+            sections.header.as_ref().map(|(_, text)| text.as_str()),
+            sections.case_data.as_deref(),
+            sections.device_information.as_deref(),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        let mut metadata = header_metadata(&texts);
+        metadata.extend(sections.md5.map(|md5| ("md5", digest::hex(&md5))));
+        metadata.extend(sections.sha1.map(|sha1| ("sha1", digest::hex(&sha1))));
+
+        let files = match (header.logical, sections.file_tree) {
+            (false, _) => None,
+            (true, Some(tree)) => Some(parse_file_tree(&tree)?),
+            (true, None) => return Err(invalid_data("EWF logical evidence without a file tree")),
+        };
+
         Ok(Self {
-            parent,
-            sectors_offset: info.sectors_offset,
-            sectors_end: info.sectors_end,
-            chunk_offsets: info.chunk_offsets,
-            chunk_size: info.chunk_size as u64,
-            virtual_size: info.virtual_size,
+            segments,
+            chunks: sections.chunks,
+            chunk_size: volume.chunk_size as u64,
+            virtual_size: volume.virtual_size,
+            compression: header.compression,
+            metadata,
+            md5: sections.md5,
+            sha1: sections.sha1,
+            files,
         })
     }
 
+    /// Bytes of chunk `chunk_idx` within the disk; the last may be short
+    fn chunk_len(&self, chunk_idx: usize) -> usize {
+        let start = chunk_idx as u64 * self.chunk_size;
+        self.virtual_size.saturating_sub(start).min(self.chunk_size) as usize
+    }
+
     fn read_chunk(&self, chunk_idx: usize) -> io::Result<Vec<u8>> {
-        if chunk_idx >= self.chunk_offsets.len() {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidInput,
-                "chunk index out of range",
-            ));
+        let chunk = self.chunks.get(chunk_idx).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "chunk index out of range")
+        })?;
+        let len = self.chunk_len(chunk_idx);
+
+        if let Storage::Pattern(pattern) = chunk.storage {
+            return Ok(pattern.iter().copied().cycle().take(len).collect());
         }
 
-        let entry = self.chunk_offsets[chunk_idx];
-        let compressed = entry & COMPRESSED_FLAG != 0;
-        let rel_offset = (entry & !COMPRESSED_FLAG) as u64;
-        let abs_offset = self
-            .sectors_offset
-            .checked_add(rel_offset)
-            .ok_or_else(|| invalid_data("EWF chunk offset overflow"))?;
-
-        // Determine compressed size from next chunk or section end
-        let end = if chunk_idx + 1 < self.chunk_offsets.len() {
-            let next_entry = self.chunk_offsets[chunk_idx + 1];
-            let next_rel = (next_entry & !COMPRESSED_FLAG) as u64;
-            self.sectors_offset
-                .checked_add(next_rel)
-                .ok_or_else(|| invalid_data("EWF chunk offset overflow"))?
-        } else {
-            self.sectors_end
+        // Stored size runs to the next chunk or the section end
+        let bound = match self.compression {
+            Compression::Zlib => zlib_bound(self.chunk_size as usize),
+            Compression::Bzip2 => bzip2_bound(self.chunk_size as usize),
         };
-        let data_size = end
-            .checked_sub(abs_offset)
+        let data_size = chunk
+            .end
+            .checked_sub(chunk.offset)
             .and_then(|size| usize::try_from(size).ok())
-            .filter(|&size| size <= zlib_bound(self.chunk_size as usize))
+            .filter(|&size| size <= bound)
             .ok_or_else(|| invalid_data("invalid EWF chunk offsets"))?;
 
         let mut raw = vec![0u8; data_size];
-        if self.parent.read_at(abs_offset, &mut raw)? != data_size {
+        if self.segments[chunk.segment].read_at(chunk.offset, &mut raw)? != data_size {
             return Err(io::Error::new(
                 io::ErrorKind::UnexpectedEof,
                 "short EWF chunk read",
             ));
         }
 
-        if compressed {
-            let mut decoder = ZlibDecoder::new(&raw[..]);
-            let mut decompressed = vec![0u8; self.chunk_size as usize];
-            decoder.read_exact(&mut decompressed)?;
-            Ok(decompressed)
-        } else {
-            Ok(raw)
+        let data = match (chunk.storage, self.compression) {
+            (Storage::Compressed, Compression::Zlib) => {
+                let mut decompressed = Vec::with_capacity(len);
+                ZlibDecoder::new(&raw[..])
+                    .take(self.chunk_size)
+                    .read_to_end(&mut decompressed)?;
+                decompressed
+            }
+            (Storage::Compressed, Compression::Bzip2) => {
+                let mut decompressed = Vec::with_capacity(len);
+                BzDecoder::new(&raw[..])
+                    .take(self.chunk_size)
+                    .read_to_end(&mut decompressed)?;
+                decompressed
+            }
+            // Uncompressed chunks may be followed by their checksum
+            _ => raw,
+        };
+        if data.len() < len {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "short EWF chunk",
+            ));
+        }
+        Ok(data)
+    }
+
+    /// Hash the whole disk and compare it with the stored hashes
+    fn verify(&self) -> io::Result<Metadata> {
+        if self.md5.is_none() && self.sha1.is_none() {
+            return Ok(Vec::new());
+        }
+        let (mut md5, mut sha1) = (Md5::new(), Sha1::new());
+        for chunk_idx in 0..self.chunks.len() {
+            let len = self.chunk_len(chunk_idx);
+            if len == 0 {
+                break;
+            }
+            let data = self.read_chunk(chunk_idx)?;
+            md5.update(&data[..len]);
+            sha1.update(&data[..len]);
         }
+
+        let mut results = Vec::new();
+        if let Some(stored) = self.md5 {
+            results.push(("md5_verified", (md5.finish() == stored).to_string()));
+        }
+        if let Some(stored) = self.sha1 {
+            results.push(("sha1_verified", (sha1.finish() == stored).to_string()));
+        }
+        Ok(results)
     }
 }
 
@@ -299,19 +1058,39 @@ fn zlib_bound(size: usize) -> usize {
     size + (size >> 12) + (size >> 14) + (size >> 25) + 13
 }
 
+fn bzip2_bound(size: usize) -> usize {
+    size + size / 100 + 600
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::container::BytesReader;
+    use crate::container::{read_all, BytesReader, FilesReader};
+    use bzip2::write::BzEncoder;
+    use flate2::write::ZlibEncoder;
+    use std::io::Write;
 
-    fn reader(offsets: Vec<u32>, sectors_end: u64) -> EwfReader {
+    fn reader(offsets: Vec<u64>, sectors_end: u64) -> EwfReader {
+        let chunks = offsets
+            .iter()
+            .enumerate()
+            .map(|(i, &offset)| Chunk {
+                segment: 0,
+                offset: 100 + offset,
+                end: offsets.get(i + 1).map_or(sectors_end, |next| 100 + next),
+                storage: Storage::Stored,
+            })
+            .collect();
         EwfReader {
-            parent: Arc::new(BytesReader::new(vec![0u8; 1024])),
-            sectors_offset: 100,
-            sectors_end,
-            chunk_offsets: offsets,
+            segments: vec![Arc::new(BytesReader::new(vec![0u8; 1024]))],
+            chunks,
             chunk_size: 512,
             virtual_size: 512,
+            compression: Compression::Zlib,
+            metadata: Vec::new(),
+            md5: None,
+            sha1: None,
+            files: None,
         }
     }
 
@@ -324,4 +1103,265 @@ mod tests {
     fn rejects_section_end_before_last_chunk() {
         assert!(reader(vec![20], 110).read_chunk(0).is_err());
     }
+
+    #[test]
+    fn names_segments() {
+        assert_eq!(segment_name("disk.E01", 2).as_deref(), Some("disk.E02"));
+        assert_eq!(segment_name("disk.E01", 100).as_deref(), Some("disk.EAA"));
+        assert_eq!(segment_name("disk.e01", 101).as_deref(), Some("disk.eab"));
+        assert_eq!(segment_name("disk.E01", 776).as_deref(), Some("disk.FAA"));
+        assert_eq!(segment_name("disk.Ex01", 100).as_deref(), Some("disk.ExAA"));
+        assert_eq!(segment_name("disk.E01", 100 + 22 * 676), None);
+        assert_eq!(segment_name("disk.img", 2), None);
+    }
+
+    /// Append a section descriptor; `body` follows it
+    fn section(out: &mut Vec<u8>, kind: &str, body: &[u8], last: bool) {
+        let pos = out.len() as u64;
+        let mut descriptor = vec![0u8; SECTION_DESCRIPTOR_SIZE];
+        descriptor[..kind.len()].copy_from_slice(kind.as_bytes());
+        let size = (SECTION_DESCRIPTOR_SIZE + body.len()) as u64;
+        let next = if last { pos } else { pos + size };
+        descriptor[16..24].copy_from_slice(&next.to_le_bytes());
+        descriptor[24..32].copy_from_slice(&size.to_le_bytes());
+        out.extend_from_slice(&descriptor);
+        out.extend_from_slice(body);
+    }
+
+    /// A segment holding one uncompressed 512-byte chunk
+    fn segment(number: u16, chunk: &[u8; 512], volume: bool, last: &str) -> Vec<u8> {
+        let mut out = b"EVF\x09\x0d\x0a\xff\x00\x01".to_vec();
+        out.extend_from_slice(&number.to_le_bytes());
+        out.extend_from_slice(&[0, 0]);
+        if volume {
+            let mut body = vec![0u8; 94];
+            body[4..8].copy_from_slice(&2u32.to_le_bytes());
+            body[8..12].copy_from_slice(&1u32.to_le_bytes());
+            body[12..16].copy_from_slice(&512u32.to_le_bytes());
+            body[16..24].copy_from_slice(&2u64.to_le_bytes());
+            section(&mut out, "volume", &body, false);
+        }
+        let sectors = out.len() as u64;
+        section(&mut out, "sectors", chunk, false);
+        let mut table = vec![0u8; TABLE_HEADER_SIZE];
+        table[..4].copy_from_slice(&1u32.to_le_bytes());
+        table[8..16].copy_from_slice(&sectors.to_le_bytes());
+        table.extend_from_slice(&(SECTION_DESCRIPTOR_SIZE as u32).to_le_bytes());
+        section(&mut out, "table", &table, false);
+        section(&mut out, last, &[], true);
+        out
+    }
+
+    #[test]
+    fn joins_segments() {
+        let segments = FilesReader::open(
+            "disk.E01",
+            vec![
+                ("disk.E01", segment(1, &[1; 512], true, "next")),
+                ("disk.E02", segment(2, &[2; 512], false, "done")),
+            ],
+        );
+        let ewf = EwfReader::new(segments).unwrap();
+        assert_eq!(ewf.size(), Some(1024));
+        let mut buf = [0u8; 4];
+        ewf.read_at(510, &mut buf[..2]).unwrap();
+        ewf.read_at(512, &mut buf[2..]).unwrap();
+        assert_eq!(buf, [1, 1, 2, 2]);
+    }
+
+    #[test]
+    fn requires_every_segment() {
+        let segments = FilesReader::open(
+            "disk.E01",
+            vec![("disk.E01", segment(1, &[1; 512], true, "next"))],
+        );
+        assert!(EwfReader::new(segments).is_err());
+    }
+
+    fn zlib(data: &[u8]) -> Vec<u8> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn utf16(text: &str) -> Vec<u8> {
+        let mut out = vec![0xff, 0xfe];
+        out.extend(text.encode_utf16().flat_map(u16::to_le_bytes));
+        out
+    }
+
+    fn md5(data: &[u8]) -> [u8; 16] {
+        let mut md5 = Md5::new();
+        md5.update(data);
+        md5.finish()
+    }
+
+    #[test]
+    fn reads_case_metadata_and_hashes() {
+        let chunk = [7u8; 512];
+        let mut image = segment(1, &chunk, true, "done");
+        // Replace the done section with header texts and a digest
+        image.truncate(image.len() - SECTION_DESCRIPTOR_SIZE);
+        let header = "1\nmain\nc\tn\ta\te\tt\tm\tu\tp\n42\tE1\tLaptop\tSam\t\t2010 3 4 10 11 12\t2010 3 4 10 11 12\t0\n\n";
+        section(&mut image, "header", &zlib(header.as_bytes()), false);
+        let header2 = "3\nmain\na\tc\tn\te\tt\tav\tov\tm\tu\tp\nLaptop\t42\tE1\tSam\tseized\t6.1\tWindows\t1267697472\t1267697472\t0\n\n";
+        section(&mut image, "header2", &zlib(&utf16(header2)), false);
+        let mut digest = md5(&chunk).to_vec();
+        digest.extend_from_slice(&[0; 20]);
+        digest.extend_from_slice(&[0; 40]);
+        section(&mut image, "digest", &digest, false);
+        section(&mut image, "done", &[], true);
+        // One chunk of one sector
+        image[SECTION_DESCRIPTOR_SIZE + 13 + 4..][..4].copy_from_slice(&1u32.to_le_bytes());
+        image[SECTION_DESCRIPTOR_SIZE + 13 + 16..][..8].copy_from_slice(&1u64.to_le_bytes());
+
+        let ewf = EwfReader::new(Arc::new(BytesReader::new(image.clone()))).unwrap();
+        assert_eq!(
+            ewf.metadata,
+            [
+                ("description", "Laptop".to_string()),
+                ("case_number", "42".to_string()),
+                ("evidence_number", "E1".to_string()),
+                ("examiner", "Sam".to_string()),
+                ("notes", "seized".to_string()),
+                ("software_version", "6.1".to_string()),
+                ("operating_system", "Windows".to_string()),
+                ("acquired", "2010-03-04T10:11:12Z".to_string()),
+                ("system_date", "2010-03-04T10:11:12Z".to_string()),
+                ("md5", digest::hex(&md5(&chunk))),
+            ]
+        );
+        assert_eq!(ewf.verify().unwrap(), [("md5_verified", "true".to_string())]);
+
+        // Alter the stored chunk
+        let at = image.windows(512).position(|w| w == chunk).unwrap();
+        image[at] = 0;
+        let ewf = EwfReader::new(Arc::new(BytesReader::new(image))).unwrap();
+        assert_eq!(ewf.verify().unwrap(), [("md5_verified", "false".to_string())]);
+    }
+
+    #[test]
+    fn lists_logical_evidence_files() {
+        let mut media = [0u8; 512];
+        media[..5].copy_from_slice(b"hello");
+        media[16..24].copy_from_slice(b"SINCLAIR");
+        media[32..36].copy_from_slice(b"ab__");
+        media[48..52].copy_from_slice(b"cd__");
+        let mut image = segment(1, &media, true, "done");
+        image[0] = b'L';
+        image.truncate(image.len() - SECTION_DESCRIPTOR_SIZE);
+        let hash = digest::hex(&md5(b"hello"));
+        let tree = format!(
+            "5\nrec\ntb\n0\n\nentry\n0\t1\np\tn\tls\tha\tbe\n\
+             0\t2\n1\t\t\t\t\n\
+             0\t2\n1\tdocs\t\t\t\n\
+             0\t0\n\tnotes.txt\t5\t{hash}\t1 0 5\n\
+             0\t0\n\tsplit.bin\t4\t\t2 20 2 30 2\n\
+             0\t0\n\tdisk.scl\t8\t\t1 10 8\n"
+        );
+        let mut ltree = vec![0u8; LTREE_HEADER_SIZE];
+        let text = utf16(&tree);
+        ltree[16..24].copy_from_slice(&(text.len() as u64).to_le_bytes());
+        ltree.extend(text);
+        section(&mut image, "ltree", &ltree, false);
+        section(&mut image, "done", &[], true);
+        image[SECTION_DESCRIPTOR_SIZE + 13 + 4..][..4].copy_from_slice(&1u32.to_le_bytes());
+        image[SECTION_DESCRIPTOR_SIZE + 13 + 16..][..8].copy_from_slice(&1u64.to_le_bytes());
+
+        let kids = EWF.children(Arc::new(BytesReader::new(image))).unwrap();
+        let files: Vec<_> = kids
+            .iter()
+            .map(|kid| {
+                assert_eq!(kid.offset, u64::MAX);
+                (kid.metadata.clone(), read_all(&*kid.reader).unwrap())
+            })
+            .collect();
+        assert_eq!(
+            files,
+            [
+                (
+                    vec![("name", "docs/notes.txt".to_string()), ("md5", hash)],
+                    b"hello".to_vec()
+                ),
+                (vec![("name", "docs/split.bin".to_string())], b"abcd".to_vec()),
+                (vec![("name", "disk.scl".to_string())], b"SINCLAIR".to_vec()),
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_header_dates() {
+        assert_eq!(header_date("0"), "1970-01-01T00:00:00Z");
+        assert_eq!(header_date("2010 3 4 10 11 12"), "2010-03-04T10:11:12");
+        assert_eq!(header_date("sometime"), "sometime");
+    }
+
+    /// Append EWF2 section data and its descriptor, linked to `previous`
+    fn section_v2(out: &mut Vec<u8>, previous: &mut u64, kind: u32, data: &[u8]) {
+        out.extend_from_slice(data);
+        let pos = out.len() as u64;
+        let mut descriptor = [0u8; V2_SECTION_DESCRIPTOR_SIZE];
+        descriptor[0..4].copy_from_slice(&kind.to_le_bytes());
+        descriptor[8..16].copy_from_slice(&previous.to_le_bytes());
+        descriptor[16..24].copy_from_slice(&(data.len() as u64).to_le_bytes());
+        descriptor[24..28].copy_from_slice(&(V2_SECTION_DESCRIPTOR_SIZE as u32).to_le_bytes());
+        out.extend_from_slice(&descriptor);
+        *previous = pos;
+    }
+
+    #[test]
+    fn reads_ewf2_bzip2_image() {
+        // Two 1 KB chunks: bzip2 compressed, then a pattern fill
+        let first: Vec<u8> = (0..1024u32).map(|i| (i % 251) as u8).collect();
+        let mut encoder = BzEncoder::new(Vec::new(), bzip2::Compression::default());
+        encoder.write_all(&first).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let mut image = b"EVF2\x0d\x0a\x81\x00\x02\x01\x02\x00\x01\x00\x00\x00".to_vec();
+        image.resize(V2_FILE_HEADER_SIZE as usize, 0);
+        let mut previous = 0;
+        let device = "1\nmain\nsn\tmd\tts\tbp\nS123\tDisk\t4\t512\n\n";
+        section_v2(&mut image, &mut previous, DEVICE_INFORMATION, &zlib(&utf16(device)));
+        let case = "1\nmain\ncn\tex\tsb\tat\n42\tSam\t2\t0\n\n";
+        section_v2(&mut image, &mut previous, CASE_DATA, &zlib(&utf16(case)));
+        let chunk_offset = image.len() as u64;
+        section_v2(&mut image, &mut previous, 0x03, &compressed);
+
+        let mut table = vec![0u8; V2_TABLE_HEADER_SIZE];
+        table[8..12].copy_from_slice(&2u32.to_le_bytes());
+        table.extend_from_slice(&chunk_offset.to_le_bytes());
+        table.extend_from_slice(&(compressed.len() as u32).to_le_bytes());
+        table.extend_from_slice(&CHUNK_COMPRESSED.to_le_bytes());
+        table.extend_from_slice(b"ABCDEFGH");
+        table.extend_from_slice(&0u32.to_le_bytes());
+        table.extend_from_slice(&CHUNK_PATTERN_FILL.to_le_bytes());
+        section_v2(&mut image, &mut previous, SECTOR_TABLE, &table);
+
+        let mut disk = first.clone();
+        disk.extend(b"ABCDEFGH".iter().cycle().take(1024));
+        let mut hash = md5(&disk).to_vec();
+        hash.extend_from_slice(&[0; 16]);
+        section_v2(&mut image, &mut previous, MD5_HASH, &hash);
+        section_v2(&mut image, &mut previous, DONE, &[]);
+
+        let ewf = EwfReader::new(Arc::new(BytesReader::new(image))).unwrap();
+        assert_eq!(ewf.size(), Some(2048));
+        let mut buf = vec![0u8; 2048];
+        ewf.read_at(0, &mut buf[..1024]).unwrap();
+        ewf.read_at(1024, &mut buf[1024..]).unwrap();
+        assert_eq!(buf, disk);
+
+        assert_eq!(
+            ewf.metadata,
+            [
+                ("case_number", "42".to_string()),
+                ("examiner", "Sam".to_string()),
+                ("acquired", "1970-01-01T00:00:00Z".to_string()),
+                ("serial_number", "S123".to_string()),
+                ("model", "Disk".to_string()),
+                ("md5", digest::hex(&md5(&disk))),
+            ]
+        );
+        assert_eq!(ewf.verify().unwrap(), [("md5_verified", "true".to_string())]);
+    }
 }
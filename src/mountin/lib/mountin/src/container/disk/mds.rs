@@ -0,0 +1,332 @@
+//! Alcohol 120% MDS/MDF disc image reader
+//!
+//! The `.mds` descriptor is binary: a header pointing at session blocks,
+//! each pointing at its track blocks. A track block gives the mode,
+//! whether subchannel data follows each sector, the sector size as
+//! stored, the start LBA and the offset of the track in its data file,
+//! with an extra block of pregap and length. Pregaps are not stored.
+//! The data file is named in a footer, `*.mdf` meaning the descriptor's
+//! own name with an `.mdf` extension.
+//!
+//! Version 2 descriptors (Alcohol's newer, encrypted MDX-era files) are
+//! not supported.
+
+use crate::container::disk::cdrom::{self, ImageTrack, TrackMode};
+use crate::container::{invalid_data, le16, le32, Child, Container};
+use crate::detect::Reader;
+use std::io;
+use std::sync::Arc;
+
+const MAGIC: &[u8; 16] = b"MEDIA DESCRIPTOR";
+const HEADER_SIZE: usize = 0x58;
+const SESSION_SIZE: usize = 0x18;
+const TRACK_SIZE: usize = 0x50;
+const EXTRA_SIZE: usize = 8;
+const FOOTER_SIZE: usize = 16;
+/// Longest data file name read
+const MAX_NAME_SIZE: usize = 1024;
+/// Subchannel mode of tracks with 96 bytes of P-W after each sector
+const SUBCHANNEL_INTERLEAVED: u8 = 0x08;
+/// Track points above this are TOC entries, not tracks
+const MAX_TRACK_POINT: u8 = 99;
+
+/// MDS/MDF disc image container
+pub struct MdsContainer;
+
+/// Static instance for registry
+pub static MDS: MdsContainer = MdsContainer;
+
+impl Container for MdsContainer {
+    fn children(&self, reader: Arc<dyn Reader + Send + Sync>) -> io::Result<Vec<Child>> {
+        Ok(cdrom::image_children(tracks(&reader)?))
+    }
+}
+
+fn read_block(reader: &dyn Reader, offset: u64, size: usize) -> io::Result<Vec<u8>> {
+    let mut block = vec![0u8; size];
+    if reader.read_at(offset, &mut block)? != size {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "short MDS block read",
+        ));
+    }
+    Ok(block)
+}
+
+/// Mode of a track block's mode byte; the high bits are flags
+fn track_mode(mode: u8) -> Option<TrackMode> {
+    Some(match mode & 0x0f {
+        // DVD images are one track of user data
+        0x02 => TrackMode::Mode1,
+        0x09 => TrackMode::Audio,
+        0x0a => TrackMode::Mode1,
+        0x0b => TrackMode::Mode2,
+        0x0c => TrackMode::Mode2Form1,
+        0x0d => TrackMode::Mode2Form2,
+        _ => return None,
+    })
+}
+
+/// A track block and the blocks it points at
+struct Block {
+    number: u32,
+    session: u32,
+    mode: TrackMode,
+    frame_size: usize,
+    subchannel: bool,
+    start: i64,
+    offset: u64,
+    pregap: u64,
+    /// Frames, where an extra block gives them
+    length: Option<u64>,
+    file: String,
+}
+
+fn tracks(reader: &Arc<dyn Reader + Send + Sync>) -> io::Result<Vec<ImageTrack>> {
+    let header = read_block(&**reader, 0, HEADER_SIZE)?;
+    if &header[..16] != MAGIC {
+        return Err(invalid_data("invalid MDS signature"));
+    }
+    if header[16] != 1 {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "unsupported MDS version",
+        ));
+    }
+    let sessions = le16(&header[0x14..]);
+    let sessions_offset = u64::from(le32(&header[0x50..]));
+
+    // Blocks of each session, with the session's end
+    let mut blocks = Vec::new();
+    let mut session_ends = Vec::new();
+    for index in 0..u64::from(sessions) {
+        let session = read_block(
+            &**reader,
+            sessions_offset + index * SESSION_SIZE as u64,
+            SESSION_SIZE,
+        )?;
+        let number = u32::from(le16(&session[8..]));
+        let count = u64::from(session[10]);
+        let tracks_offset = u64::from(le32(&session[20..]));
+        session_ends.push((number, i64::from(le32(&session[4..]) as i32)));
+
+        for index in 0..count {
+            let track = read_block(
+                &**reader,
+                tracks_offset + index * TRACK_SIZE as u64,
+                TRACK_SIZE,
+            )?;
+            let point = track[4];
+            if point == 0 || point > MAX_TRACK_POINT {
+                continue;
+            }
+            blocks.push(read_track(&**reader, &track, number)?);
+        }
+    }
+    if blocks.is_empty() {
+        return Err(invalid_data("MDS without tracks"));
+    }
+
+    let mut tracks = Vec::with_capacity(blocks.len());
+    for (position, block) in blocks.iter().enumerate() {
+        // Without an extra block, a track runs to the next one's pregap
+        // or its session's end
+        let frames = block.length.unwrap_or_else(|| {
+            let end = match blocks.get(position + 1) {
+                Some(next) if next.session == block.session => next.start - next.pregap as i64,
+                _ => session_ends
+                    .iter()
+                    .find(|(session, _)| *session == block.session)
+                    .map_or(block.start, |&(_, end)| end),
+            };
+            end.saturating_sub(block.start).max(0) as u64
+        });
+
+        let file = match block.file.strip_prefix('*') {
+            Some(extension) => cdrom::open_companion(&**reader, extension.trim_start_matches('.'))?,
+            None => cdrom::open_data_file(&**reader, &block.file)?,
+        };
+        let sector_size = if block.subchannel {
+            block
+                .frame_size
+                .checked_sub(cdrom::SUBCHANNEL_SIZE)
+                .filter(|&size| size > 0)
+                .ok_or_else(|| invalid_data("invalid MDS sector size"))?
+        } else {
+            block.frame_size
+        };
+        tracks.push(ImageTrack {
+            number: block.number,
+            session: block.session,
+            mode: block.mode,
+            file,
+            offset: block.offset,
+            sector_size,
+            frame_size: block.frame_size,
+            frames,
+            pregap: block.pregap,
+        });
+    }
+    Ok(tracks)
+}
+
+fn read_track(reader: &dyn Reader, track: &[u8], session: u32) -> io::Result<Block> {
+    let mode = track_mode(track[0]).ok_or_else(|| invalid_data("invalid MDS track mode"))?;
+    let frame_size = usize::from(le16(&track[16..]));
+    if frame_size == 0 {
+        return Err(invalid_data("invalid MDS sector size"));
+    }
+
+    let extra_offset = u64::from(le32(&track[12..]));
+    let (pregap, length) = if extra_offset == 0 {
+        (0, None)
+    } else {
+        let extra = read_block(reader, extra_offset, EXTRA_SIZE)?;
+        (u64::from(le32(&extra)), Some(u64::from(le32(&extra[4..]))))
+    };
+
+    let footer_offset = u64::from(le32(&track[52..]));
+    if le32(&track[48..]) == 0 || footer_offset == 0 {
+        return Err(invalid_data("MDS track without data file"));
+    }
+    let footer = read_block(reader, footer_offset, FOOTER_SIZE)?;
+    let file = read_name(reader, u64::from(le32(&footer)), le32(&footer[4..]) != 0)?;
+
+    Ok(Block {
+        number: u32::from(track[4]),
+        session,
+        mode,
+        frame_size,
+        subchannel: track[1] == SUBCHANNEL_INTERLEAVED,
+        start: i64::from(le32(&track[36..]) as i32),
+        offset: u64::from_le_bytes(track[40..48].try_into().unwrap()),
+        pregap,
+        length,
+        file,
+    })
+}
+
+/// A NUL-terminated file name, in UTF-16 where `wide`
+fn read_name(reader: &dyn Reader, offset: u64, wide: bool) -> io::Result<String> {
+    let mut bytes = vec![0u8; MAX_NAME_SIZE];
+    let n = reader.read_at(offset, &mut bytes)?;
+    bytes.truncate(n);
+    let name = if wide {
+        let units: Vec<u16> = bytes
+            .chunks_exact(2)
+            .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+            .take_while(|&unit| unit != 0)
+            .collect();
+        String::from_utf16_lossy(&units)
+    } else {
+        let end = bytes
+            .iter()
+            .position(|&byte| byte == 0)
+            .unwrap_or(bytes.len());
+        String::from_utf8_lossy(&bytes[..end]).into_owned()
+    };
+    if name.is_empty() {
+        return Err(invalid_data("invalid MDS data file name"));
+    }
+    Ok(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::FilesReader;
+
+    /// Descriptor of one session with a data track (with subchannel data)
+    /// and an audio track, in `disc.mdf`
+    fn descriptor() -> Vec<u8> {
+        let mut mds = MAGIC.to_vec();
+        mds.extend_from_slice(&[1, 3, 0, 0, 1, 0]);
+        mds.resize(HEADER_SIZE, 0);
+        mds[0x50..0x54].copy_from_slice(&(HEADER_SIZE as u32).to_le_bytes());
+
+        let tracks_at = HEADER_SIZE + SESSION_SIZE;
+        let mut session = vec![0u8; SESSION_SIZE];
+        session[..4].copy_from_slice(&(-150i32).to_le_bytes());
+        session[4..8].copy_from_slice(&5u32.to_le_bytes());
+        session[8] = 1;
+        session[10] = 3;
+        session[20..24].copy_from_slice(&(tracks_at as u32).to_le_bytes());
+        mds.extend(session);
+
+        let extra_at = tracks_at + 3 * TRACK_SIZE;
+        let footer_at = extra_at + 2 * EXTRA_SIZE;
+        let name_at = footer_at + FOOTER_SIZE;
+        // A lead-in entry, then the tracks
+        let mut lead_in = vec![0u8; TRACK_SIZE];
+        lead_in[4] = 0xa0;
+        mds.extend(lead_in);
+        let layout = [(0xaa, 8, 2448, 0, 0), (0xa9, 0, 2352, 2, 3 * 2448)];
+        for (index, (mode, subchannel, size, start, offset)) in layout.into_iter().enumerate() {
+            let mut track = vec![0u8; TRACK_SIZE];
+            track[0] = mode;
+            track[1] = subchannel;
+            track[4] = index as u8 + 1;
+            let extra = (extra_at + index * EXTRA_SIZE) as u32;
+            track[12..16].copy_from_slice(&extra.to_le_bytes());
+            track[16..18].copy_from_slice(&(size as u16).to_le_bytes());
+            track[36..40].copy_from_slice(&(start as u32).to_le_bytes());
+            track[40..48].copy_from_slice(&(offset as u64).to_le_bytes());
+            track[48..52].copy_from_slice(&1u32.to_le_bytes());
+            track[52..56].copy_from_slice(&(footer_at as u32).to_le_bytes());
+            mds.extend(track);
+        }
+        for (pregap, length) in [(150u32, 2u32), (0, 3)] {
+            mds.extend_from_slice(&pregap.to_le_bytes());
+            mds.extend_from_slice(&length.to_le_bytes());
+        }
+        let mut footer = vec![0u8; FOOTER_SIZE];
+        footer[..4].copy_from_slice(&(name_at as u32).to_le_bytes());
+        mds.extend(footer);
+        mds.extend_from_slice(b"*.mdf\0");
+        mds
+    }
+
+    #[test]
+    fn reads_tracks_and_strips_subchannel() {
+        let mut mdf = Vec::new();
+        for fill in [b'a', b'b', b'c'] {
+            let mut sector = vec![fill; cdrom::SECTOR_SIZE];
+            sector[..12].copy_from_slice(&cdrom::SYNC);
+            sector[15] = 1;
+            mdf.extend(sector);
+            mdf.extend(vec![0xff; cdrom::SUBCHANNEL_SIZE]);
+        }
+        mdf.extend(vec![b'm'; 3 * cdrom::SECTOR_SIZE]);
+
+        let files = FilesReader::open(
+            "disc.mds",
+            vec![("disc.mds", descriptor()), ("disc.mdf", mdf)],
+        );
+        let kids = MDS.children(files).unwrap();
+        assert_eq!(kids.len(), 3);
+
+        // The extra block limits the data track to two of its frames
+        let raw = &kids[0];
+        assert!(raw.metadata.contains(&("pregap", "150".to_string())));
+        assert_eq!(raw.reader.size(), Some(2 * 2352));
+        let mut buf = [0u8; 2];
+        raw.reader.read_at(2351, &mut buf).unwrap();
+        assert_eq!(buf, [b'a', 0]);
+        assert_eq!(raw.reader.read_at(2352, &mut buf).unwrap(), 2);
+        kids[1].reader.read_at(2048, &mut buf).unwrap();
+        assert_eq!(&buf, b"bb");
+
+        let audio = &kids[2];
+        assert!(audio.metadata.contains(&("mode", "audio".to_string())));
+        assert_eq!(audio.reader.size(), Some(3 * 2352));
+    }
+
+    #[test]
+    fn rejects_version_2() {
+        let mut mds = descriptor();
+        mds[16] = 2;
+        let files = FilesReader::open("disc.mds", vec![("disc.mds", mds)]);
+        let error = MDS.children(files).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::Unsupported);
+    }
+}
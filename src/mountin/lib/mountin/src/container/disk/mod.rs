@@ -5,18 +5,29 @@
 
 pub mod apridisk;
 pub mod atr;
+pub(crate) mod backing;
 pub mod bochs;
+pub mod ccd;
 pub mod cdi;
+pub mod chd;
+pub(crate) mod cdrom;
 pub mod cloop;
+pub mod cso;
+pub mod cue;
+pub mod dax;
 pub mod dms;
 pub mod dmg;
 pub mod ewf;
+pub mod mds;
 pub mod nrg;
 pub mod parallels;
 pub mod qcow;
 pub mod qcow2;
 pub mod qed;
 pub mod scl;
+pub mod sparsebundle;
+pub mod sparseimage;
+pub mod toc;
 pub mod twoimg;
 pub mod vdi;
 pub mod vhd;
@@ -1,8 +1,10 @@
 //! NRG (Nero Burning ROM) disc image reader
 //!
 //! Parses NRG format and exposes data tracks for filesystem detection.
-//! Based on libmirage NRG parser.
+//! Based on libmirage NRG parser. Tracks stored with 2336 or 2352-byte
+//! sectors also get a cooked view of their user data.
 
+use crate::container::disk::cdrom;
 use crate::container::{slice::SliceReader, Child, Container};
 use crate::detect::Reader;
 use std::io;
@@ -41,11 +43,26 @@ impl TrackMode {
             _ => None,
         }
     }
+
+    /// Mode of the track's sectors and bytes stored per sector
+    fn layout(self) -> (cdrom::TrackMode, usize) {
+        match self {
+            TrackMode::Mode1 => (cdrom::TrackMode::Mode1, 2048),
+            TrackMode::Mode2 => (cdrom::TrackMode::Mode2, cdrom::MODE2_SIZE),
+            TrackMode::Mode2Xa1 => (cdrom::TrackMode::Mode2Form1, 2048),
+            TrackMode::Mode2Raw => (cdrom::TrackMode::Mode2, cdrom::SECTOR_SIZE),
+            TrackMode::Audio => (cdrom::TrackMode::Audio, cdrom::SECTOR_SIZE),
+            TrackMode::Mode1Raw => (cdrom::TrackMode::Mode1, cdrom::SECTOR_SIZE),
+            TrackMode::Mode2Xa1Raw => (cdrom::TrackMode::Mode2Form1, cdrom::SECTOR_SIZE),
+            TrackMode::Mode2Xa2Raw => (cdrom::TrackMode::Mode2Form2, cdrom::SECTOR_SIZE),
+        }
+    }
 }
 
 /// Parsed track info
 #[derive(Debug)]
 struct Track {
+    mode: TrackMode,
     offset: u64,
     length: u64, // in bytes
 }
@@ -57,15 +74,19 @@ impl Container for NrgContainer {
         // Return all tracks as children (audio and data)
         let mut children = Vec::new();
         for (idx, track) in tracks.iter().enumerate() {
-            children.push(Child {
-                index: idx as u32,
-                offset: track.offset,
-                reader: Arc::new(SliceReader::new(
+            let (mode, sector_size) = track.mode.layout();
+            cdrom::track_children(
+                &mut children,
+                Arc::new(SliceReader::new(
                     Arc::clone(&reader),
                     track.offset,
                     track.length,
                 )),
-            });
+                track.offset,
+                mode,
+                sector_size,
+                vec![("track", (idx + 1).to_string())],
+            );
         }
 
         Ok(children)
@@ -211,7 +232,7 @@ fn parse_dao_chunk(
         // Mode code at +14
         let mut mode_buf = [0u8; 1];
         read_bytes(reader, entry_pos + 14, &mut mode_buf)?;
-        let _mode = match TrackMode::from_code(mode_buf[0]) {
+        let mode = match TrackMode::from_code(mode_buf[0]) {
             Some(m) => m,
             None => {
                 entry_pos += entry_size;
@@ -235,6 +256,7 @@ fn parse_dao_chunk(
         let track_length = end_offset.saturating_sub(start_offset);
 
         tracks.push(Track {
+            mode,
             offset: start_offset,
             length: track_length,
         });
@@ -274,7 +296,7 @@ fn parse_etn_chunk(
             (off, size, mode)
         };
 
-        let _mode = match TrackMode::from_code(mode_val as u8) {
+        let mode = match TrackMode::from_code(mode_val as u8) {
             Some(m) => m,
             None => {
                 entry_pos += entry_size;
@@ -283,6 +305,7 @@ fn parse_etn_chunk(
         };
 
         tracks.push(Track {
+            mode,
             offset: track_offset,
             length: track_size,
         });
@@ -292,3 +315,50 @@ fn parse_etn_chunk(
 
     Ok(tracks)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::BytesReader;
+
+    /// Raw Mode 1 sectors holding an ISO 9660 volume descriptor
+    fn iso_track(fill: u8) -> Vec<u8> {
+        let mut track = Vec::new();
+        for lba in 0..17 {
+            let mut sector = vec![0u8; cdrom::SECTOR_SIZE];
+            sector[..12].copy_from_slice(&cdrom::SYNC);
+            sector[15] = 1;
+            sector[16..2064].fill(fill);
+            if lba == 16 {
+                sector[16..22].copy_from_slice(b"\x01CD001");
+            }
+            track.extend(sector);
+        }
+        track
+    }
+
+    #[test]
+    fn detects_every_cooked_track() {
+        let mut image = [iso_track(b'a'), iso_track(b'b')].concat();
+        let length = image.len() as u32 / 2;
+        let chunks = image.len() as u32;
+        image.extend_from_slice(b"ETNF");
+        image.extend_from_slice(&32u32.to_be_bytes());
+        for offset in [0, length] {
+            for value in [offset, length, 0x0f, 0] {
+                image.extend_from_slice(&value.to_be_bytes());
+            }
+        }
+        image.extend_from_slice(b"END!\0\0\0\0NERO");
+        image.extend_from_slice(&chunks.to_be_bytes());
+
+        crate::format::init_test_formats();
+        let tree = crate::detect::detect_tree(Arc::new(BytesReader::new(image)));
+        let nrg = tree
+            .iter()
+            .find(|n| n.format.to_str() == Ok("disk/nrg"))
+            .expect("disk/nrg not detected");
+        let formats: Vec<_> = nrg.children.iter().map(|n| n.format.to_str().unwrap()).collect();
+        assert_eq!(formats, ["data", "fs/iso9660", "data", "fs/iso9660"]);
+    }
+}
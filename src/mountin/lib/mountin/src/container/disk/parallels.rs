@@ -26,6 +26,7 @@ impl Container for ParallelsContainer {
             index: 0,
             offset: 0,
             reader: Arc::new(parallels_reader),
+            metadata: Vec::new(),
         }])
     }
 }
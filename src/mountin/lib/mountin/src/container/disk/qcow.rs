@@ -25,6 +25,7 @@ impl Container for QcowContainer {
             index: 0,
             offset: 0,
             reader: Arc::new(qcow_reader),
+            metadata: Vec::new(),
         }])
     }
 }
@@ -1,8 +1,21 @@
 //! QCOW2 disk image reader
 //!
 //! Parses QCOW2 format and provides virtual disk access through L1/L2 tables.
-
-use crate::container::{Child, Container};
+//! Unallocated clusters of an overlay read through to its backing file.
+//!
+//! Version 3 images may use zstd compression, extended L2 entries (32
+//! subclusters per cluster, each allocated or zeroed on its own) and an
+//! external data file holding the clusters. Images with incompatible
+//! features beyond those, or with encryption, are refused.
+//!
+//! Internal snapshots, from `savevm` or `qemu-img snapshot`, each keep an
+//! L1 table of their own and are exposed as further children after the
+//! active image.
+
+use crate::container::disk::backing::{self, Backing, Layered};
+use crate::container::{
+    be32, be64, checked_table_size, invalid_data, utc_date, Child, Container, Metadata,
+};
 use crate::detect::Reader;
 use flate2::read::DeflateDecoder;
 use std::io::{self, Read};
@@ -12,6 +25,36 @@ const QCOW_MAGIC: u32 = 0x514649fb;
 const L2E_OFFSET_MASK: u64 = 0x00fffffffffffe00;
 const QCOW_OFLAG_COMPRESSED: u64 = 1 << 62;
 const QCOW_OFLAG_ZERO: u64 = 1;
+/// Refcount is exactly one; tells offset 0 in a data file from unallocated
+const QCOW_OFLAG_COPIED: u64 = 1 << 63;
+/// Header extension naming the backing file's format
+const EXT_BACKING_FORMAT: u32 = 0xE2792ACA;
+/// Header extension naming the external data file
+const EXT_DATA_FILE: u32 = 0x44415441;
+const V2_HEADER_SIZE: usize = 72;
+const V3_HEADER_SIZE: usize = 104;
+/// Longest backing or data file name the format allows
+const MAX_BACKING_NAME: u32 = 1023;
+
+// Incompatible feature bits; any others make the image unreadable
+const INCOMPAT_DIRTY: u64 = 1 << 0;
+const INCOMPAT_CORRUPT: u64 = 1 << 1;
+const INCOMPAT_DATA_FILE: u64 = 1 << 2;
+const INCOMPAT_COMPRESSION: u64 = 1 << 3;
+const INCOMPAT_EXTL2: u64 = 1 << 4;
+const INCOMPAT_KNOWN: u64 =
+    INCOMPAT_DIRTY | INCOMPAT_CORRUPT | INCOMPAT_DATA_FILE | INCOMPAT_COMPRESSION | INCOMPAT_EXTL2;
+
+const COMPRESSION_DEFLATE: u8 = 0;
+const COMPRESSION_ZSTD: u8 = 1;
+
+/// Subclusters per cluster with extended L2 entries
+const SUBCLUSTERS: u64 = 32;
+
+/// Most snapshots an image may have
+const MAX_SNAPSHOTS: u32 = 65536;
+/// Largest extra data in a snapshot table entry
+const MAX_SNAPSHOT_EXTRA: u32 = 1024;
 
 /// QCOW2 disk image container
 pub struct Qcow2Container;
@@ -21,32 +64,209 @@ pub static QCOW2: Qcow2Container = Qcow2Container;
 
 impl Container for Qcow2Container {
     fn children(&self, reader: Arc<dyn Reader + Send + Sync>) -> io::Result<Vec<Child>> {
-        let qcow2_reader = Qcow2Reader::new(reader)?;
+        let mut qcow2_reader = Qcow2Reader::new(reader)?;
+        qcow2_reader.open_backing(0);
+        let metadata = qcow2_reader.chain_metadata();
 
-        Ok(vec![Child {
+        let active = Arc::new(qcow2_reader);
+        let mut children = vec![Child {
             index: 0,
             offset: 0,
-            reader: Arc::new(qcow2_reader),
-        }])
+            reader: Arc::clone(&active) as Arc<dyn Reader + Send + Sync>,
+            metadata,
+        }];
+
+        // Snapshots follow the active image, skipping any whose L1 table
+        // can't be read
+        for snapshot in &active.snapshots {
+            let Ok(reader) = active.snapshot(snapshot) else {
+                continue;
+            };
+            let mut metadata = snapshot.metadata();
+            metadata.extend(active.chain_metadata());
+            // A snapshot is a view of its own, not a slice of the image
+            children.push(Child {
+                index: children.len() as u32,
+                offset: u64::MAX,
+                reader: Arc::new(reader),
+                metadata,
+            });
+        }
+        Ok(children)
     }
 }
 
 /// Reader that translates virtual disk offsets through QCOW2 L1/L2 tables
 pub struct Qcow2Reader {
     parent: Arc<dyn Reader + Send + Sync>,
+    /// Where clusters are stored: the image itself or its external data file
+    data: Arc<dyn Reader + Send + Sync>,
+    external_data: bool,
     l1_table: Vec<u64>,
     cluster_size: u64,
     cluster_bits: u32,
     l2_bits: u32,
     l2_size: u64,
     virtual_size: u64,
+    compression_type: u8,
+    /// L2 entries carry a subcluster bitmap
+    extended_l2: bool,
+    /// Backing file name and format, as recorded in the header
+    backing_file: Option<(String, Option<String>)>,
+    backing: Option<Backing>,
+    snapshots: Vec<Snapshot>,
+}
+
+/// An internal snapshot's entry in the snapshot table
+struct Snapshot {
+    id: String,
+    name: String,
+    /// Seconds since 1970 when the snapshot was taken
+    date: u32,
+    l1_table_offset: u64,
+    l1_size: u32,
+    /// Virtual size when taken, if recorded
+    disk_size: Option<u64>,
+}
+
+impl Snapshot {
+    fn metadata(&self) -> Metadata {
+        vec![
+            ("snapshot_id", self.id.clone()),
+            ("snapshot_name", self.name.clone()),
+            ("date", utc_date(self.date.into())),
+        ]
+    }
+}
+
+/// Read `count` entries of the snapshot table at `offset`
+fn read_snapshots(parent: &dyn Reader, mut offset: u64, count: u32) -> io::Result<Vec<Snapshot>> {
+    if count > MAX_SNAPSHOTS {
+        return Err(invalid_data("too many QCOW2 snapshots"));
+    }
+    let mut snapshots = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let mut entry = [0u8; 40];
+        if parent.read_at(offset, &mut entry)? != entry.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "short snapshot table read",
+            ));
+        }
+        let id_size = u16::from_be_bytes([entry[12], entry[13]]) as usize;
+        let name_size = u16::from_be_bytes([entry[14], entry[15]]) as usize;
+        let extra_size = be32(&entry[36..]);
+        if extra_size > MAX_SNAPSHOT_EXTRA {
+            return Err(invalid_data("invalid QCOW2 snapshot extra data"));
+        }
+
+        // Extra data, then the ID and name, padded to 8 bytes
+        let mut rest = vec![0u8; extra_size as usize + id_size + name_size];
+        if parent.read_at(offset + 40, &mut rest)? != rest.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "short snapshot table read",
+            ));
+        }
+        let (extra, strings) = rest.split_at(extra_size as usize);
+        let (id, name) = strings.split_at(id_size);
+        snapshots.push(Snapshot {
+            id: String::from_utf8_lossy(id).into_owned(),
+            name: String::from_utf8_lossy(name).into_owned(),
+            date: be32(&entry[16..]),
+            l1_table_offset: be64(&entry),
+            l1_size: be32(&entry[8..]),
+            disk_size: (extra.len() >= 16).then(|| be64(&extra[8..])),
+        });
+        offset += (40 + rest.len() as u64).next_multiple_of(8);
+    }
+    Ok(snapshots)
+}
+
+/// Read an L1 table of `entries` entries at `offset`
+fn read_l1_table(parent: &dyn Reader, offset: u64, entries: u64) -> io::Result<Vec<u64>> {
+    let l1_bytes = checked_table_size(parent, offset, entries, 8)?;
+    let mut l1_data = vec![0u8; l1_bytes];
+    if parent.read_at(offset, &mut l1_data)? != l1_bytes {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "short L1 table read",
+        ));
+    }
+
+    // Parse L1 table (big-endian u64)
+    Ok(l1_data.chunks_exact(8).map(be64).collect())
+}
+
+/// Read a file name of `size` bytes at `offset`
+fn read_name(parent: &dyn Reader, offset: u64, size: u32) -> io::Result<String> {
+    if size == 0 || size > MAX_BACKING_NAME {
+        return Err(invalid_data("invalid QCOW2 file name"));
+    }
+    let mut name = vec![0u8; size as usize];
+    if parent.read_at(offset, &mut name)? != name.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "short file name read",
+        ));
+    }
+    String::from_utf8(name).map_err(|_| invalid_data("invalid QCOW2 file name"))
+}
+
+/// Header extensions used here
+#[derive(Default)]
+struct Extensions {
+    backing_format: Option<String>,
+    data_file: Option<String>,
+}
+
+/// Read the header extensions between `pos` and `end`
+fn read_extensions(parent: &dyn Reader, mut pos: u64, end: u64) -> io::Result<Extensions> {
+    let mut extensions = Extensions::default();
+    while pos + 8 <= end {
+        let mut ext = [0u8; 8];
+        if parent.read_at(pos, &mut ext)? != 8 {
+            break;
+        }
+        let (kind, length) = (be32(&ext), be32(&ext[4..]));
+        if kind == 0 {
+            break;
+        }
+        match kind {
+            EXT_BACKING_FORMAT if length <= 16 => {
+                extensions.backing_format = read_name(parent, pos + 8, length).ok();
+            }
+            EXT_DATA_FILE => extensions.data_file = Some(read_name(parent, pos + 8, length)?),
+            _ => {}
+        }
+        pos += 8 + u64::from(length).next_multiple_of(8);
+    }
+    Ok(extensions)
+}
+
+impl Layered for Qcow2Reader {
+    fn open_backing(&mut self, depth: usize) {
+        if let Some((name, format)) = &self.backing_file {
+            self.backing = Some(backing::open(
+                &*self.parent,
+                std::slice::from_ref(name),
+                format.as_deref(),
+                depth,
+            ));
+        }
+    }
+
+    fn backing(&self) -> Option<&Backing> {
+        self.backing.as_ref()
+    }
 }
 
 impl Qcow2Reader {
     pub fn new(parent: Arc<dyn Reader + Send + Sync>) -> io::Result<Self> {
-        // Read header (0x30 bytes needed for l1_table_offset)
-        let mut header = [0u8; 0x30];
-        if parent.read_at(0, &mut header)? != 0x30 {
+        // Read header (0x30 bytes needed for l1_table_offset, more for v3)
+        let mut header = [0u8; V3_HEADER_SIZE + 8];
+        let header_read = parent.read_at(0, &mut header)?;
+        if header_read < 0x30 {
             return Err(io::Error::new(
                 io::ErrorKind::UnexpectedEof,
                 "short header read",
@@ -73,114 +293,210 @@ impl Qcow2Reader {
 
         // Parse header fields (big-endian)
         // cluster_bits: 4 bytes at offset 0x14
-        let cluster_bits =
-            u32::from_be_bytes([header[0x14], header[0x15], header[0x16], header[0x17]]);
+        let cluster_bits = be32(&header[0x14..]);
 
         // virtual_size: 8 bytes at offset 0x18
-        let virtual_size = u64::from_be_bytes([
-            header[0x18],
-            header[0x19],
-            header[0x1a],
-            header[0x1b],
-            header[0x1c],
-            header[0x1d],
-            header[0x1e],
-            header[0x1f],
-        ]);
+        let virtual_size = be64(&header[0x18..]);
+
+        // crypt_method: 4 bytes at offset 0x20
+        if be32(&header[0x20..]) != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "encrypted QCOW2 images are not supported",
+            ));
+        }
 
         // l1_size: 4 bytes at offset 0x24
-        let l1_size =
-            u32::from_be_bytes([header[0x24], header[0x25], header[0x26], header[0x27]]) as usize;
+        let l1_size = be32(&header[0x24..]);
 
         // l1_table_offset: 8 bytes at offset 0x28
-        let l1_table_offset = u64::from_be_bytes([
-            header[0x28],
-            header[0x29],
-            header[0x2a],
-            header[0x2b],
-            header[0x2c],
-            header[0x2d],
-            header[0x2e],
-            header[0x2f],
-        ]);
+        let l1_table_offset = be64(&header[0x28..]);
 
         // Validate cluster_bits (9-21 typical)
-        if cluster_bits < 9 || cluster_bits > 21 {
+        if !(9..=21).contains(&cluster_bits) {
             return Err(io::Error::new(
                 io::ErrorKind::InvalidData,
                 "invalid cluster_bits",
             ));
         }
-
         let cluster_size = 1u64 << cluster_bits;
-        // l2_bits = cluster_bits - 3 (each L2 entry is 8 bytes)
-        let l2_bits = cluster_bits - 3;
-        let l2_size = 1u64 << l2_bits;
 
-        // Read L1 table
-        let l1_bytes = l1_size * 8;
-        let mut l1_data = vec![0u8; l1_bytes];
-        if parent.read_at(l1_table_offset, &mut l1_data)? != l1_bytes {
+        // Version 3 fields: feature bits, header length, compression type
+        let (incompatible, header_length, compression_type) = if version >= 3 {
+            if header_read < V3_HEADER_SIZE {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "short header read",
+                ));
+            }
+            let header_length = be32(&header[0x64..]) as usize;
+            if header_length < V3_HEADER_SIZE || header_length as u64 > cluster_size {
+                return Err(invalid_data("invalid QCOW2 header length"));
+            }
+            let compression_type = if header_length > V3_HEADER_SIZE {
+                header[0x68]
+            } else {
+                COMPRESSION_DEFLATE
+            };
+            (be64(&header[0x48..]), header_length, compression_type)
+        } else {
+            (0, V2_HEADER_SIZE, COMPRESSION_DEFLATE)
+        };
+        if incompatible & !INCOMPAT_KNOWN != 0 {
             return Err(io::Error::new(
-                io::ErrorKind::UnexpectedEof,
-                "short L1 table read",
+                io::ErrorKind::Unsupported,
+                "unsupported QCOW2 incompatible feature",
+            ));
+        }
+        if compression_type != COMPRESSION_DEFLATE
+            && (incompatible & INCOMPAT_COMPRESSION == 0 || compression_type != COMPRESSION_ZSTD)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "unsupported QCOW2 compression type",
             ));
         }
+        let extended_l2 = incompatible & INCOMPAT_EXTL2 != 0;
+        if extended_l2 && cluster_bits < 14 {
+            return Err(invalid_data("QCOW2 extended L2 needs 16 KiB clusters"));
+        }
 
-        // Parse L1 table (big-endian u64)
-        let l1_table: Vec<u64> = l1_data
-            .chunks_exact(8)
-            .map(|chunk| {
-                u64::from_be_bytes([
-                    chunk[0], chunk[1], chunk[2], chunk[3], chunk[4], chunk[5], chunk[6], chunk[7],
-                ])
-            })
-            .collect();
+        // Header extensions follow the header, up to the backing file name
+        // or the end of the first cluster
+        let name_offset = be64(&header[0x08..]);
+        let extensions_end = match name_offset {
+            0 => cluster_size,
+            offset => offset.min(cluster_size),
+        };
+        let extensions = read_extensions(parent.as_ref(), header_length as u64, extensions_end)?;
+        let backing_file = if name_offset != 0 {
+            let name = read_name(parent.as_ref(), name_offset, be32(&header[0x10..]))?;
+            Some((name, extensions.backing_format))
+        } else {
+            None
+        };
+
+        // Clusters live in the external data file, found beside the image
+        let external_data = incompatible & INCOMPAT_DATA_FILE != 0;
+        let data = if external_data {
+            let name = extensions
+                .data_file
+                .ok_or_else(|| invalid_data("QCOW2 data file name missing"))?;
+            backing::candidates(&name)
+                .iter()
+                .find_map(|candidate| parent.sibling(candidate).ok())
+                .ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::NotFound, "missing QCOW2 external data file")
+                })?
+        } else {
+            Arc::clone(&parent)
+        };
+
+        // l2_bits = cluster_bits - 3 (each L2 entry is 8 bytes, or 16
+        // with extended L2)
+        let l2_bits = cluster_bits - if extended_l2 { 4 } else { 3 };
+        let l2_size = 1u64 << l2_bits;
+
+        // Read L1 table
+        let l1_table = read_l1_table(parent.as_ref(), l1_table_offset, u64::from(l1_size))?;
+
+        // Snapshot table: count at 0x3C, offset at 0x40; a damaged table
+        // only loses the snapshots
+        let snapshots = if header_read >= V2_HEADER_SIZE {
+            read_snapshots(
+                parent.as_ref(),
+                be64(&header[0x40..]),
+                be32(&header[0x3c..]),
+            )
+            .unwrap_or_default()
+        } else {
+            Vec::new()
+        };
 
         Ok(Self {
             parent,
+            data,
+            external_data,
             l1_table,
             cluster_size,
             cluster_bits,
             l2_bits,
             l2_size,
             virtual_size,
+            compression_type,
+            extended_l2,
+            backing_file,
+            backing: None,
+            snapshots,
+        })
+    }
+
+    /// A reader for the disk as it was when `snapshot` was taken
+    fn snapshot(&self, snapshot: &Snapshot) -> io::Result<Self> {
+        let l1_table = read_l1_table(
+            self.parent.as_ref(),
+            snapshot.l1_table_offset,
+            u64::from(snapshot.l1_size),
+        )?;
+        Ok(Self {
+            parent: Arc::clone(&self.parent),
+            data: Arc::clone(&self.data),
+            external_data: self.external_data,
+            l1_table,
+            cluster_size: self.cluster_size,
+            cluster_bits: self.cluster_bits,
+            l2_bits: self.l2_bits,
+            l2_size: self.l2_size,
+            virtual_size: snapshot.disk_size.unwrap_or(self.virtual_size),
+            compression_type: self.compression_type,
+            extended_l2: self.extended_l2,
+            backing_file: self.backing_file.clone(),
+            backing: self.backing.clone(),
+            snapshots: Vec::new(),
         })
     }
 
-    fn read_l2_entry(&self, l2_offset: u64, l2_index: u64) -> io::Result<u64> {
-        let mut buf = [0u8; 8];
-        let offset = l2_offset + l2_index * 8;
-        if self.parent.read_at(offset, &mut buf)? != 8 {
+    /// Read an L2 entry and, with extended L2, its subcluster bitmap
+    fn read_l2_entry(&self, l2_offset: u64, l2_index: u64) -> io::Result<(u64, u64)> {
+        let mut buf = [0u8; 16];
+        let len = if self.extended_l2 { 16 } else { 8 };
+        let offset = l2_offset + l2_index * len as u64;
+        if self.parent.read_at(offset, &mut buf[..len])? != len {
             return Err(io::Error::new(
                 io::ErrorKind::UnexpectedEof,
                 "short L2 read",
             ));
         }
-        Ok(u64::from_be_bytes(buf))
+        Ok((be64(&buf), be64(&buf[8..])))
     }
 
     fn read_compressed_cluster(&self, l2_entry: u64) -> io::Result<Vec<u8>> {
         // Compressed cluster encoding:
-        // - Lower (63 - cluster_bits) bits: host offset
-        // - Next cluster_bits bits: (nb_sectors - 1)
-        let csize_shift = 63 - self.cluster_bits;
+        // - Lower (70 - cluster_bits) bits: host offset
+        // - Next (cluster_bits - 8) bits: additional 512-byte sectors
+        let csize_shift = 70 - self.cluster_bits;
         let coffset_mask = (1u64 << csize_shift) - 1;
-        let csize_mask = (1u64 << self.cluster_bits) - 1;
+        let csize_mask = (1u64 << (self.cluster_bits - 8)) - 1;
 
         let coffset = l2_entry & coffset_mask;
         let nb_sectors = ((l2_entry >> csize_shift) & csize_mask) + 1;
-        let compressed_size = nb_sectors as usize * 512;
+        // The data starts part way into its first sector
+        let compressed_size = (nb_sectors * 512 - (coffset & 511)) as usize;
 
         // Read compressed data
         let mut compressed = vec![0u8; compressed_size];
         let n = self.parent.read_at(coffset, &mut compressed)?;
         compressed.truncate(n);
 
-        // Decompress (QCOW2 uses raw deflate)
-        let mut decoder = DeflateDecoder::new(&compressed[..]);
         let mut decompressed = vec![0u8; self.cluster_size as usize];
-        decoder.read_exact(&mut decompressed)?;
+        match self.compression_type {
+            COMPRESSION_ZSTD => {
+                zstd::stream::read::Decoder::with_buffer(&compressed[..])?
+                    .read_exact(&mut decompressed)?;
+            }
+            // Raw deflate
+            _ => DeflateDecoder::new(&compressed[..]).read_exact(&mut decompressed)?,
+        }
 
         Ok(decompressed)
     }
@@ -208,8 +524,7 @@ impl Reader for Qcow2Reader {
         // Check L1 bounds
         if l1_index >= self.l1_table.len() {
             // Beyond L1 table - sparse
-            buf[..to_read].fill(0);
-            return Ok(to_read);
+            return backing::read_backing(self.backing(), offset, &mut buf[..to_read]);
         }
 
         // Get L2 table offset from L1 (apply mask)
@@ -217,37 +532,58 @@ impl Reader for Qcow2Reader {
         let l2_offset = l1_entry & L2E_OFFSET_MASK;
         if l2_offset == 0 {
             // Sparse L1 entry
-            buf[..to_read].fill(0);
-            return Ok(to_read);
+            return backing::read_backing(self.backing(), offset, &mut buf[..to_read]);
         }
 
         // Read L2 entry
-        let l2_entry = self.read_l2_entry(l2_offset, l2_index)?;
+        let (l2_entry, bitmap) = self.read_l2_entry(l2_offset, l2_index)?;
 
-        // Check for zero flag (bit 0)
-        if l2_entry & QCOW_OFLAG_ZERO != 0 {
-            buf[..to_read].fill(0);
+        // Check for compression flag (bit 62); the other flags and the
+        // subcluster bitmap don't apply to compressed clusters
+        if l2_entry & QCOW_OFLAG_COMPRESSED != 0 {
+            if self.external_data {
+                return Err(invalid_data("compressed cluster with QCOW2 data file"));
+            }
+            let decompressed = self.read_compressed_cluster(l2_entry)?;
+            buf[..to_read].copy_from_slice(&decompressed[in_cluster as usize..][..to_read]);
             return Ok(to_read);
         }
 
         // Extract cluster offset (apply mask)
         let cluster_offset = l2_entry & L2E_OFFSET_MASK;
-        if cluster_offset == 0 {
-            // Sparse cluster
+
+        if self.extended_l2 {
+            // Each subcluster is allocated (low 32 bits), zero (high 32
+            // bits) or unallocated on its own
+            let subcluster_size = self.cluster_size / SUBCLUSTERS;
+            let subcluster = in_cluster / subcluster_size;
+            let to_read = to_read.min((subcluster_size - in_cluster % subcluster_size) as usize);
+            if bitmap & (1 << subcluster) != 0 {
+                return self
+                    .data
+                    .read_at(cluster_offset + in_cluster, &mut buf[..to_read]);
+            }
+            if bitmap & (1 << (subcluster + SUBCLUSTERS)) != 0 {
+                buf[..to_read].fill(0);
+                return Ok(to_read);
+            }
+            return backing::read_backing(self.backing(), offset, &mut buf[..to_read]);
+        }
+
+        // Check for zero flag (bit 0)
+        if l2_entry & QCOW_OFLAG_ZERO != 0 {
             buf[..to_read].fill(0);
             return Ok(to_read);
         }
 
-        // Check for compression flag (bit 62)
-        if l2_entry & QCOW_OFLAG_COMPRESSED != 0 {
-            let decompressed = self.read_compressed_cluster(l2_entry)?;
-            buf[..to_read].copy_from_slice(&decompressed[in_cluster as usize..][..to_read]);
-            return Ok(to_read);
+        if cluster_offset == 0 && !(self.external_data && l2_entry & QCOW_OFLAG_COPIED != 0) {
+            // Sparse cluster
+            return backing::read_backing(self.backing(), offset, &mut buf[..to_read]);
         }
 
         // Read from physical location
         let physical_offset = cluster_offset + in_cluster;
-        self.parent.read_at(physical_offset, &mut buf[..to_read])
+        self.data.read_at(physical_offset, &mut buf[..to_read])
     }
 
     fn size(&self) -> Option<u64> {
@@ -258,3 +594,194 @@ impl Reader for Qcow2Reader {
 // SAFETY: Qcow2Reader only holds Arc and Vec, safe to send/share
 unsafe impl Send for Qcow2Reader {}
 unsafe impl Sync for Qcow2Reader {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::{BytesReader, FilesReader};
+    use std::io::Write;
+
+    const CLUSTER: usize = 1 << 14;
+
+    /// A v3 image of four 16 KiB clusters: header, L1 table, L2 table, then
+    /// `data` from cluster 3, with the given L2 entries
+    fn image(incompatible: u64, compression: u8, l2: &[(u64, u64)], data: &[u8]) -> Vec<u8> {
+        let mut out = vec![0u8; 3 * CLUSTER];
+        out[0..4].copy_from_slice(&QCOW_MAGIC.to_be_bytes());
+        out[4..8].copy_from_slice(&3u32.to_be_bytes());
+        out[0x14..0x18].copy_from_slice(&14u32.to_be_bytes());
+        out[0x18..0x20].copy_from_slice(&(4 * CLUSTER as u64).to_be_bytes());
+        out[0x24..0x28].copy_from_slice(&1u32.to_be_bytes());
+        out[0x28..0x30].copy_from_slice(&(CLUSTER as u64).to_be_bytes());
+        out[0x48..0x50].copy_from_slice(&incompatible.to_be_bytes());
+        out[0x64..0x68].copy_from_slice(&112u32.to_be_bytes());
+        out[0x68] = compression;
+        // Data file name extension, used only with INCOMPAT_DATA_FILE
+        out[112..116].copy_from_slice(&EXT_DATA_FILE.to_be_bytes());
+        out[116..120].copy_from_slice(&8u32.to_be_bytes());
+        out[120..128].copy_from_slice(b"disk.raw");
+
+        out[CLUSTER..CLUSTER + 8].copy_from_slice(&(2 * CLUSTER as u64).to_be_bytes());
+        let size = if incompatible & INCOMPAT_EXTL2 != 0 {
+            16
+        } else {
+            8
+        };
+        for (i, (entry, bitmap)) in l2.iter().enumerate() {
+            let pos = 2 * CLUSTER + i * size;
+            out[pos..pos + 8].copy_from_slice(&entry.to_be_bytes());
+            if size == 16 {
+                out[pos + 8..pos + 16].copy_from_slice(&bitmap.to_be_bytes());
+            }
+        }
+        out.extend_from_slice(data);
+        out
+    }
+
+    fn read(reader: &dyn Reader, offset: u64) -> [u8; 4] {
+        let mut buf = [0xffu8; 4];
+        reader.read_at(offset, &mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn reads_compressed_clusters() {
+        let cluster: Vec<u8> = (0..CLUSTER).map(|i| (i % 251) as u8).collect();
+        let mut deflate = flate2::write::DeflateEncoder::new(Vec::new(), Default::default());
+        deflate.write_all(&cluster).unwrap();
+        let compressed = [
+            (COMPRESSION_DEFLATE, 0, deflate.finish().unwrap()),
+            (
+                COMPRESSION_ZSTD,
+                INCOMPAT_COMPRESSION,
+                zstd::bulk::compress(&cluster, 3).unwrap(),
+            ),
+        ];
+        for (compression, incompatible, data) in compressed {
+            // Compressed data needn't start on a sector boundary
+            let offset = 3 * CLUSTER as u64 + 100;
+            let sectors = (100 + data.len() as u64).div_ceil(512);
+            let entry = QCOW_OFLAG_COMPRESSED | (sectors - 1) << 56 | offset;
+            let mut padded = vec![0u8; 100];
+            padded.extend_from_slice(&data);
+            let image = image(incompatible, compression, &[(entry, 0)], &padded);
+
+            let reader = Qcow2Reader::new(Arc::new(BytesReader::new(image))).unwrap();
+            assert_eq!(read(&reader, 1000), cluster[1000..1004]);
+        }
+    }
+
+    #[test]
+    fn reads_subclusters() {
+        // Subcluster 0 allocated, 1 zero, 2 unallocated
+        let bitmap = 1 | 1 << 33;
+        let entry = 3 * CLUSTER as u64;
+        let image = image(INCOMPAT_EXTL2, 0, &[(entry, bitmap)], &[b'A'; CLUSTER]);
+        let reader = Qcow2Reader::new(Arc::new(BytesReader::new(image))).unwrap();
+
+        let subcluster = (CLUSTER / 32) as u64;
+        assert_eq!(&read(&reader, 0), b"AAAA");
+        assert_eq!(read(&reader, subcluster), [0; 4]);
+        assert_eq!(read(&reader, 2 * subcluster), [0; 4]);
+
+        // A read stops at the end of a subcluster
+        let mut buf = [0u8; 1024];
+        assert_eq!(reader.read_at(0, &mut buf).unwrap(), subcluster as usize);
+    }
+
+    #[test]
+    fn reads_external_data_file() {
+        // Offset 0 is the data file's first cluster when marked copied
+        let l2 = [(QCOW_OFLAG_COPIED, 0), (0, 0)];
+        let mut data = vec![b'D'; CLUSTER];
+        data.extend_from_slice(&[b'E'; CLUSTER]);
+        let disk = image(INCOMPAT_DATA_FILE, 0, &l2, &[]);
+        let files = vec![("disk.qcow2", disk.clone()), ("disk.raw", data)];
+        let reader = Qcow2Reader::new(FilesReader::open("disk.qcow2", files)).unwrap();
+        assert_eq!(&read(&reader, 0), b"DDDD");
+        assert_eq!(read(&reader, CLUSTER as u64), [0; 4]);
+
+        let missing = Qcow2Reader::new(FilesReader::open("disk.qcow2", vec![("disk.qcow2", disk)]));
+        assert_eq!(
+            missing.err().map(|error| error.kind()),
+            Some(io::ErrorKind::NotFound)
+        );
+    }
+
+    #[test]
+    fn refuses_unknown_features() {
+        for (incompatible, compression) in [(1 << 5, 0), (INCOMPAT_COMPRESSION, 2)] {
+            let image = image(incompatible, compression, &[], &[]);
+            let result = Qcow2Reader::new(Arc::new(BytesReader::new(image)));
+            assert_eq!(
+                result.err().map(|error| error.kind()),
+                Some(io::ErrorKind::Unsupported)
+            );
+        }
+    }
+
+    /// An image whose active cluster 0 is `active`, with one snapshot
+    /// whose cluster 0 is `snapshot`
+    fn with_snapshot(snapshot: &[u8], active: &[u8]) -> Vec<u8> {
+        let (l2, snapshot_data, active_data) = (3 * CLUSTER, 4 * CLUSTER, 5 * CLUSTER);
+        let mut data = vec![0u8; 3 * CLUSTER];
+        data[..8].copy_from_slice(&(snapshot_data as u64).to_be_bytes());
+        data[CLUSTER..2 * CLUSTER].copy_from_slice(snapshot);
+        data[2 * CLUSTER..].copy_from_slice(active);
+        let mut image = image(0, 0, &[(active_data as u64, 0)], &data);
+
+        // One snapshot at 0x200 whose L1 table, at 0x400, has its own L2
+        image[0x3c..0x40].copy_from_slice(&1u32.to_be_bytes());
+        image[0x40..0x48].copy_from_slice(&0x200u64.to_be_bytes());
+        let entry = &mut image[0x200..0x250];
+        entry[0..8].copy_from_slice(&0x400u64.to_be_bytes());
+        entry[8..12].copy_from_slice(&1u32.to_be_bytes());
+        entry[12..14].copy_from_slice(&1u16.to_be_bytes());
+        entry[14..16].copy_from_slice(&6u16.to_be_bytes());
+        entry[16..20].copy_from_slice(&1_700_000_000u32.to_be_bytes());
+        entry[36..40].copy_from_slice(&16u32.to_be_bytes());
+        entry[48..56].copy_from_slice(&(2 * CLUSTER as u64).to_be_bytes());
+        entry[56..63].copy_from_slice(b"1before");
+        image[0x400..0x408].copy_from_slice(&(l2 as u64).to_be_bytes());
+        image
+    }
+
+    #[test]
+    fn exposes_snapshots() {
+        let image = with_snapshot(&[b'S'; CLUSTER], &[b'A'; CLUSTER]);
+        let children = QCOW2.children(Arc::new(BytesReader::new(image))).unwrap();
+        assert_eq!(children.len(), 2);
+        assert_eq!(&read(&*children[0].reader, 0), b"AAAA");
+        assert_eq!(&read(&*children[1].reader, 0), b"SSSS");
+        assert_eq!(children[1].reader.size(), Some(2 * CLUSTER as u64));
+        assert_eq!(
+            children[1].metadata,
+            [
+                ("snapshot_id", "1".to_string()),
+                ("snapshot_name", "before".to_string()),
+                ("date", "2023-11-14T22:13:20Z".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn detects_snapshot_with_active_format() {
+        // Both hold the same filesystem, which must be found in each
+        let mut cluster = vec![0u8; CLUSTER];
+        cluster[..8].copy_from_slice(b"SINCLAIR");
+        let image = with_snapshot(&cluster, &cluster);
+
+        crate::format::init_test_formats();
+        let tree = crate::detect::detect_tree(Arc::new(BytesReader::new(image)));
+        let qcow2 = tree
+            .iter()
+            .find(|n| n.format.to_str() == Ok("disk/qcow2"))
+            .expect("disk/qcow2 not detected");
+        let formats: Vec<_> = qcow2
+            .children
+            .iter()
+            .map(|n| n.format.to_str().unwrap())
+            .collect();
+        assert_eq!(formats, ["disk/scl", "disk/scl"]);
+    }
+}
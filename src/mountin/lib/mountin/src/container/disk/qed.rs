@@ -1,7 +1,9 @@
 //! QED (QEMU Enhanced Disk) image reader
 //!
 //! Parses QED format and provides virtual disk access through L1/L2 tables.
+//! Unallocated clusters read through to the backing file, if any.
 
+use crate::container::disk::backing::{self, Backing, Layered};
 use crate::container::{checked_table_size, invalid_data, Child, Container};
 use crate::detect::Reader;
 use std::io;
@@ -11,6 +13,12 @@ const QED_MAGIC: u32 = 0x00444551;
 const QED_MIN_CLUSTER_SIZE: u64 = 4 * 1024;
 const QED_MAX_CLUSTER_SIZE: u64 = 64 * 1024 * 1024;
 const QED_MAX_TABLE_SIZE: u64 = 16;
+const QED_F_BACKING_FILE: u64 = 0x01;
+/// The backing file is raw and must not be probed
+const QED_F_BACKING_FORMAT_NO_PROBE: u64 = 0x04;
+/// L2 entry for a cluster that reads as zeros, even over a backing file
+const QED_CLUSTER_ZERO: u64 = 1;
+const QED_MAX_BACKING_NAME: u32 = 1023;
 
 /// QED disk image container
 pub struct QedContainer;
@@ -20,12 +28,15 @@ pub static QED: QedContainer = QedContainer;
 
 impl Container for QedContainer {
     fn children(&self, reader: Arc<dyn Reader + Send + Sync>) -> io::Result<Vec<Child>> {
-        let qed_reader = QedReader::new(reader)?;
+        let mut qed_reader = QedReader::new(reader)?;
+        qed_reader.open_backing(0);
+        let metadata = qed_reader.chain_metadata();
 
         Ok(vec![Child {
             index: 0,
             offset: 0,
             reader: Arc::new(qed_reader),
+            metadata,
         }])
     }
 }
@@ -37,13 +48,30 @@ pub struct QedReader {
     cluster_size: u64,
     l2_entries: u64,
     virtual_size: u64,
+    /// Backing file name, and whether it is known to be raw
+    backing_file: Option<(String, bool)>,
+    backing: Option<Backing>,
+}
+
+impl Layered for QedReader {
+    fn open_backing(&mut self, depth: usize) {
+        if let Some((name, raw)) = &self.backing_file {
+            let format = raw.then_some("raw");
+            let names = std::slice::from_ref(name);
+            self.backing = Some(backing::open(&*self.parent, names, format, depth));
+        }
+    }
+
+    fn backing(&self) -> Option<&Backing> {
+        self.backing.as_ref()
+    }
 }
 
 impl QedReader {
     pub fn new(parent: Arc<dyn Reader + Send + Sync>) -> io::Result<Self> {
-        // Read header (0x38 bytes needed)
-        let mut header = [0u8; 0x38];
-        if parent.read_at(0, &mut header)? != 0x38 {
+        // Read header (0x40 bytes needed)
+        let mut header = [0u8; 0x40];
+        if parent.read_at(0, &mut header)? != 0x40 {
             return Err(io::Error::new(
                 io::ErrorKind::UnexpectedEof,
                 "short header read",
@@ -85,6 +113,27 @@ impl QedReader {
             header[0x37],
         ]);
 
+        let features = u64::from_le_bytes(header[0x10..0x18].try_into().unwrap());
+        let backing_file = if features & QED_F_BACKING_FILE != 0 {
+            let name_offset = u32::from_le_bytes(header[0x38..0x3c].try_into().unwrap());
+            let name_size = u32::from_le_bytes(header[0x3c..0x40].try_into().unwrap());
+            if name_size == 0 || name_size > QED_MAX_BACKING_NAME {
+                return Err(invalid_data("invalid QED backing file name"));
+            }
+            let mut name = vec![0u8; name_size as usize];
+            if parent.read_at(name_offset as u64, &mut name)? != name.len() {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "short backing file name read",
+                ));
+            }
+            let name = String::from_utf8(name)
+                .map_err(|_| invalid_data("invalid QED backing file name"))?;
+            Some((name, features & QED_F_BACKING_FORMAT_NO_PROBE != 0))
+        } else {
+            None
+        };
+
         // QED stores table_size in clusters; both fields are powers of two.
         if !(QED_MIN_CLUSTER_SIZE..=QED_MAX_CLUSTER_SIZE).contains(&cluster_size)
             || !cluster_size.is_power_of_two()
@@ -130,6 +179,8 @@ impl QedReader {
             cluster_size,
             l2_entries,
             virtual_size,
+            backing_file,
+            backing: None,
         })
     }
 
@@ -169,23 +220,24 @@ impl Reader for QedReader {
 
         // Check L1 bounds
         if l1_index >= self.l1_table.len() {
-            buf[..to_read].fill(0);
-            return Ok(to_read);
+            return backing::read_backing(self.backing(), offset, &mut buf[..to_read]);
         }
 
         // Get L2 table offset from L1
         let l2_offset = self.l1_table[l1_index];
         if l2_offset == 0 {
             // Sparse L1 entry
-            buf[..to_read].fill(0);
-            return Ok(to_read);
+            return backing::read_backing(self.backing(), offset, &mut buf[..to_read]);
         }
 
         // Read L2 entry
         let cluster_offset = self.read_l2_entry(l2_offset, l2_index)?;
 
-        // Check for unallocated (0) or zero cluster (1)
-        if cluster_offset <= 1 {
+        // Unallocated clusters come from the backing file; zero clusters don't
+        if cluster_offset == 0 {
+            return backing::read_backing(self.backing(), offset, &mut buf[..to_read]);
+        }
+        if cluster_offset == QED_CLUSTER_ZERO {
             buf[..to_read].fill(0);
             return Ok(to_read);
         }
@@ -207,10 +259,10 @@ unsafe impl Sync for QedReader {}
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::container::BytesReader;
+    use crate::container::{BytesReader, FilesReader};
 
     fn header(cluster_size: u32, table_size: u32) -> Vec<u8> {
-        let mut data = vec![0u8; 0x38];
+        let mut data = vec![0u8; 0x40];
         data[0..4].copy_from_slice(&QED_MAGIC.to_le_bytes());
         data[0x04..0x08].copy_from_slice(&cluster_size.to_le_bytes());
         data[0x08..0x0c].copy_from_slice(&table_size.to_le_bytes());
@@ -228,4 +280,52 @@ mod tests {
         let image = Arc::new(BytesReader::new(header(4096, 1)));
         assert!(QedReader::new(image).is_err());
     }
+
+    /// Three clusters over a raw "base.img": allocated, unallocated, zero
+    fn overlay() -> Vec<u8> {
+        let mut data = header(4096, 1);
+        let flags = QED_F_BACKING_FILE | QED_F_BACKING_FORMAT_NO_PROBE;
+        data[0x10..0x18].copy_from_slice(&flags.to_le_bytes());
+        data[0x28..0x30].copy_from_slice(&4096u64.to_le_bytes());
+        data[0x30..0x38].copy_from_slice(&(3 * 4096u64).to_le_bytes());
+        data[0x38..0x3c].copy_from_slice(&0x100u32.to_le_bytes());
+        data[0x3c..0x40].copy_from_slice(&8u32.to_le_bytes());
+        data.resize(4 * 4096, 0);
+        data[0x100..0x108].copy_from_slice(b"base.img");
+        data[4096..4104].copy_from_slice(&8192u64.to_le_bytes());
+        data[8192..8200].copy_from_slice(&12288u64.to_le_bytes());
+        data[8208..8216].copy_from_slice(&QED_CLUSTER_ZERO.to_le_bytes());
+        data[12288..].fill(b'X');
+        data
+    }
+
+    #[test]
+    fn reads_through_to_backing_file() {
+        let image = FilesReader::open(
+            "disk.qed",
+            vec![("disk.qed", overlay()), ("base.img", vec![b'B'; 3 * 4096])],
+        );
+        let children = QED.children(image).unwrap();
+        assert_eq!(children[0].metadata, [("backing", "base.img".to_string())]);
+
+        let mut buf = [0u8; 4];
+        for (offset, expected) in [(0, b'X'), (4096, b'B'), (8192, 0)] {
+            children[0].reader.read_at(offset, &mut buf).unwrap();
+            assert_eq!(buf, [expected; 4]);
+        }
+    }
+
+    #[test]
+    fn reports_missing_backing_file() {
+        let image = FilesReader::open("disk.qed", vec![("disk.qed", overlay())]);
+        let children = QED.children(image).unwrap();
+        assert_eq!(
+            children[0].metadata,
+            [("missing_backing", "base.img".to_string())]
+        );
+
+        let mut buf = [1u8; 4];
+        children[0].reader.read_at(4096, &mut buf).unwrap();
+        assert_eq!(buf, [0; 4]);
+    }
 }
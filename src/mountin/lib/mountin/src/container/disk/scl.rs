@@ -123,6 +123,7 @@ impl Container for SclContainer {
             index: 0,
             offset: u64::MAX, // reconstructed data, not a slice of the parent
             reader: Arc::new(BytesReader::new(trd)),
+            metadata: Vec::new(),
         }])
     }
 }
@@ -0,0 +1,219 @@
+//! Apple sparse bundle (.sparsebundle) reader
+//!
+//! A sparse bundle is a directory rather than a file: `Info.plist` gives
+//! the disk and band sizes, and each band written is a file in `bands/`
+//! named by its number in hex. Detection starts from `Info.plist` and
+//! opens the bands through `Reader::sibling`, so this only works for
+//! top-level readers whose caller can open neighbouring files.
+
+use crate::container::{invalid_data, Child, Container};
+use crate::detect::Reader;
+use std::collections::VecDeque;
+use std::io;
+use std::sync::{Arc, Mutex};
+
+const BUNDLE_TYPE: &str = "com.apple.diskimage.sparsebundle";
+/// Largest Info.plist read
+const MAX_PLIST_SIZE: usize = 64 * 1024;
+/// Largest band accepted
+const MAX_BAND_SIZE: u64 = 1 << 30;
+/// Band files kept open at once
+const OPEN_BANDS: usize = 16;
+
+/// Sparse bundle container
+pub struct SparseBundleContainer;
+
+/// Static instance for registry
+pub static SPARSEBUNDLE: SparseBundleContainer = SparseBundleContainer;
+
+impl Container for SparseBundleContainer {
+    fn children(&self, reader: Arc<dyn Reader + Send + Sync>) -> io::Result<Vec<Child>> {
+        let bundle_reader = SparseBundleReader::new(reader)?;
+
+        Ok(vec![Child {
+            index: 0,
+            offset: 0,
+            reader: Arc::new(bundle_reader),
+            metadata: Vec::new(),
+        }])
+    }
+}
+
+/// A band file, or `None` if it was never written
+type Band = Option<Arc<dyn Reader + Send + Sync>>;
+
+/// Reader that joins the band files of a bundle
+pub struct SparseBundleReader {
+    /// The bundle's Info.plist, which opens the band files
+    plist: Arc<dyn Reader + Send + Sync>,
+    band_size: u64,
+    virtual_size: u64,
+    /// Recently used bands, most recent first
+    bands: Mutex<VecDeque<(u64, Band)>>,
+}
+
+impl SparseBundleReader {
+    pub fn new(plist: Arc<dyn Reader + Send + Sync>) -> io::Result<Self> {
+        let len = plist
+            .size()
+            .ok_or_else(|| invalid_data("sparse bundle Info.plist has unknown size"))?;
+        if len as usize > MAX_PLIST_SIZE {
+            return Err(invalid_data("sparse bundle Info.plist too large"));
+        }
+        let mut data = vec![0u8; len as usize];
+        if plist.read_at(0, &mut data)? != data.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "short sparse bundle Info.plist read",
+            ));
+        }
+        let xml = String::from_utf8_lossy(&data);
+
+        if plist_value(&xml, "diskimage-bundle-type", "string") != Some(BUNDLE_TYPE) {
+            return Err(invalid_data("not a sparse bundle Info.plist"));
+        }
+        let integer = |key| {
+            plist_value(&xml, key, "integer")
+                .and_then(|value| value.parse::<u64>().ok())
+                .ok_or_else(|| invalid_data("sparse bundle Info.plist missing sizes"))
+        };
+        let band_size = integer("band-size")?;
+        let virtual_size = integer("size")?;
+        if band_size == 0 || band_size > MAX_BAND_SIZE {
+            return Err(invalid_data("invalid sparse bundle band size"));
+        }
+
+        Ok(Self {
+            plist,
+            band_size,
+            virtual_size,
+            bands: Mutex::new(VecDeque::with_capacity(OPEN_BANDS)),
+        })
+    }
+
+    fn band(&self, index: u64) -> io::Result<Band> {
+        let mut bands = self
+            .bands
+            .lock()
+            .map_err(|_| io::Error::other("sparse bundle band lock poisoned"))?;
+        if let Some(pos) = bands.iter().position(|(i, _)| *i == index) {
+            let entry = bands.remove(pos).expect("position is in range");
+            let band = entry.1.clone();
+            bands.push_front(entry);
+            return Ok(band);
+        }
+
+        let band = match self.plist.sibling(&format!("bands/{index:x}")) {
+            Ok(band) => Some(band),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => None,
+            Err(error) => return Err(error),
+        };
+        if bands.len() >= OPEN_BANDS {
+            bands.pop_back();
+        }
+        bands.push_front((index, band.clone()));
+        Ok(band)
+    }
+}
+
+/// Text of the `<kind>` element following `<key>key</key>` in a plist
+fn plist_value<'a>(xml: &'a str, key: &str, kind: &str) -> Option<&'a str> {
+    let after_key = &xml[xml.find(&format!("<key>{key}</key>"))?..];
+    let open = format!("<{kind}>");
+    let start = after_key.find(&open)? + open.len();
+    // The value must be the element directly after the key
+    if !after_key[after_key.find("</key>")? + 6..start - open.len()]
+        .trim()
+        .is_empty()
+    {
+        return None;
+    }
+    let end = after_key[start..].find(&format!("</{kind}>"))?;
+    Some(after_key[start..start + end].trim())
+}
+
+impl Reader for SparseBundleReader {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        if offset >= self.virtual_size {
+            return Ok(0);
+        }
+
+        let index = offset / self.band_size;
+        let in_band = offset % self.band_size;
+        let to_read = buf
+            .len()
+            .min((self.band_size - in_band) as usize)
+            .min((self.virtual_size - offset) as usize);
+
+        // Bands never written are missing, and bands may be shorter than
+        // the band size; both read as zeros
+        let n = match self.band(index)? {
+            Some(band) => band.read_at(in_band, &mut buf[..to_read])?,
+            None => 0,
+        };
+        buf[n..to_read].fill(0);
+        Ok(to_read)
+    }
+
+    fn size(&self) -> Option<u64> {
+        Some(self.virtual_size)
+    }
+}
+
+// SAFETY: SparseBundleReader holds Arc'd readers and a Mutex
+unsafe impl Send for SparseBundleReader {}
+unsafe impl Sync for SparseBundleReader {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::FilesReader;
+
+    const INFO_PLIST: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+	<key>CFBundleInfoDictionaryVersion</key>
+	<string>6.0</string>
+	<key>band-size</key>
+	<integer>1024</integer>
+	<key>bundle-backingstore-version</key>
+	<integer>1</integer>
+	<key>diskimage-bundle-type</key>
+	<string>com.apple.diskimage.sparsebundle</string>
+	<key>size</key>
+	<integer>20480</integer>
+</dict>
+</plist>
+"#;
+
+    #[test]
+    fn joins_band_files() {
+        let bundle = FilesReader::open(
+            "Info.plist",
+            vec![
+                ("Info.plist", INFO_PLIST.into()),
+                ("bands/0", vec![b'A'; 1024]),
+                ("bands/11", vec![b'H'; 100]),
+            ],
+        );
+        let reader = SparseBundleReader::new(bundle).unwrap();
+        assert_eq!(reader.size(), Some(20480));
+
+        let mut buf = [0xffu8; 4];
+        reader.read_at(1020, &mut buf).unwrap();
+        assert_eq!(&buf, b"AAAA");
+        reader.read_at(1024, &mut buf).unwrap();
+        assert_eq!(&buf, &[0; 4]);
+        reader.read_at(17 * 1024 + 98, &mut buf).unwrap();
+        assert_eq!(&buf, b"HH\0\0");
+    }
+
+    #[test]
+    fn reads_plist_values() {
+        assert_eq!(plist_value(INFO_PLIST, "band-size", "integer"), Some("1024"));
+        assert_eq!(plist_value(INFO_PLIST, "size", "integer"), Some("20480"));
+        // The bundle type is a string, not an integer
+        assert_eq!(plist_value(INFO_PLIST, "diskimage-bundle-type", "integer"), None);
+    }
+}
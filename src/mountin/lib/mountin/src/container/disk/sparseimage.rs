@@ -0,0 +1,159 @@
+//! Apple sparse image (.sparseimage) reader
+//!
+//! A sparse image is a single file made of equal-sized bands, stored in
+//! the order they were first written. The header's band table records
+//! which band of the disk each stored band holds.
+
+use crate::container::{invalid_data, Child, Container};
+use crate::detect::Reader;
+use std::io;
+use std::sync::Arc;
+
+const SPARSE_MAGIC: &[u8; 4] = b"sprs";
+/// Header size; the band data follows it
+const HEADER_SIZE: usize = 4096;
+/// Offset of the band table within the header
+const BAND_TABLE_OFFSET: usize = 64;
+/// Largest band accepted
+const MAX_BAND_SIZE: u64 = 1 << 30;
+
+/// Sparse image container
+pub struct SparseImageContainer;
+
+/// Static instance for registry
+pub static SPARSEIMAGE: SparseImageContainer = SparseImageContainer;
+
+impl Container for SparseImageContainer {
+    fn children(&self, reader: Arc<dyn Reader + Send + Sync>) -> io::Result<Vec<Child>> {
+        let sparse_reader = SparseImageReader::new(reader)?;
+
+        Ok(vec![Child {
+            index: 0,
+            offset: 0,
+            reader: Arc::new(sparse_reader),
+            metadata: Vec::new(),
+        }])
+    }
+}
+
+/// Reader that translates disk offsets through the band table
+pub struct SparseImageReader {
+    parent: Arc<dyn Reader + Send + Sync>,
+    /// Position in the file of each band of the disk, if it was written
+    bands: Vec<Option<u32>>,
+    band_size: u64,
+    virtual_size: u64,
+}
+
+impl SparseImageReader {
+    pub fn new(parent: Arc<dyn Reader + Send + Sync>) -> io::Result<Self> {
+        let mut header = vec![0u8; HEADER_SIZE];
+        if parent.read_at(0, &mut header)? != HEADER_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "short sparse image header read",
+            ));
+        }
+        if &header[0..4] != SPARSE_MAGIC {
+            return Err(invalid_data("invalid sparse image magic"));
+        }
+
+        // Big-endian fields
+        let sectors_per_band = u32::from_be_bytes([header[8], header[9], header[10], header[11]]);
+        let sectors = u32::from_be_bytes([header[16], header[17], header[18], header[19]]);
+
+        let band_size = sectors_per_band as u64 * 512;
+        if band_size == 0 || band_size > MAX_BAND_SIZE {
+            return Err(invalid_data("invalid sparse image band size"));
+        }
+        let virtual_size = sectors as u64 * 512;
+        let band_count = virtual_size.div_ceil(band_size);
+
+        // Each table slot holds the 1-based disk band stored at that
+        // position, or 0 past the last band written
+        let mut bands = vec![None; band_count as usize];
+        for (slot, entry) in header[BAND_TABLE_OFFSET..].chunks_exact(4).enumerate() {
+            let band = u32::from_be_bytes([entry[0], entry[1], entry[2], entry[3]]);
+            if band == 0 {
+                continue;
+            }
+            let target = bands
+                .get_mut(band as usize - 1)
+                .ok_or_else(|| invalid_data("sparse image band out of range"))?;
+            if target.is_some() {
+                return Err(invalid_data("sparse image band stored twice"));
+            }
+            *target = Some(slot as u32);
+        }
+
+        Ok(Self {
+            parent,
+            bands,
+            band_size,
+            virtual_size,
+        })
+    }
+}
+
+impl Reader for SparseImageReader {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        if offset >= self.virtual_size {
+            return Ok(0);
+        }
+
+        let band = (offset / self.band_size) as usize;
+        let in_band = offset % self.band_size;
+        let to_read = buf
+            .len()
+            .min((self.band_size - in_band) as usize)
+            .min((self.virtual_size - offset) as usize);
+
+        let Some(slot) = self.bands[band] else {
+            buf[..to_read].fill(0);
+            return Ok(to_read);
+        };
+
+        // The last band written may be cut short at the end of the file
+        let physical = HEADER_SIZE as u64 + slot as u64 * self.band_size + in_band;
+        let n = self.parent.read_at(physical, &mut buf[..to_read])?;
+        buf[n..to_read].fill(0);
+        Ok(to_read)
+    }
+
+    fn size(&self) -> Option<u64> {
+        Some(self.virtual_size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::BytesReader;
+
+    #[test]
+    fn maps_bands_in_written_order() {
+        // Four 1 KB bands; disk band 3 was written first, then band 0
+        let mut image = vec![0u8; HEADER_SIZE];
+        image[0..4].copy_from_slice(SPARSE_MAGIC);
+        image[4..8].copy_from_slice(&3u32.to_be_bytes());
+        image[8..12].copy_from_slice(&2u32.to_be_bytes());
+        image[16..20].copy_from_slice(&8u32.to_be_bytes());
+        image[64..68].copy_from_slice(&4u32.to_be_bytes());
+        image[68..72].copy_from_slice(&1u32.to_be_bytes());
+        image.extend(vec![b'D'; 1024]);
+        image.extend(vec![b'A'; 1000]);
+
+        let reader = SparseImageReader::new(Arc::new(BytesReader::new(image))).unwrap();
+        assert_eq!(reader.size(), Some(4096));
+
+        let mut buf = [0xffu8; 4];
+        reader.read_at(0, &mut buf).unwrap();
+        assert_eq!(&buf, b"AAAA");
+        assert_eq!(reader.read_at(1022, &mut buf).unwrap(), 2);
+        assert_eq!(&buf[..2], &[0, 0]);
+        reader.read_at(1024, &mut buf).unwrap();
+        assert_eq!(&buf, &[0; 4]);
+        reader.read_at(3072, &mut buf).unwrap();
+        assert_eq!(&buf, b"DDDD");
+    }
+}
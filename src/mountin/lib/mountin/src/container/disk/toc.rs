@@ -0,0 +1,352 @@
+//! cdrdao TOC disc image reader
+//!
+//! A TOC file is cdrdao's text descriptor: a disc type (`CD_ROM`, ...),
+//! then `TRACK <mode> [<subchannel mode>]` blocks naming where each
+//! track's data comes from. `DATAFILE` continues a file where the previous
+//! track left it; `FILE`/`AUDIOFILE` give a start. `ZERO`, `SILENCE` and
+//! `PREGAP` are sectors not stored in any file, and `START` ends the
+//! pregap. Lengths are mm:ss:ff times or, for audio, sample counts.
+//!
+//! A track is read from one data file; tracks joining several are refused.
+
+use crate::container::disk::cdrom::{self, ImageTrack, TrackMode};
+use crate::container::{invalid_data, Child, Container};
+use crate::detect::Reader;
+use std::io;
+use std::sync::Arc;
+
+/// Bytes in an audio sample: two channels of 16 bits
+const SAMPLE_SIZE: u64 = 4;
+
+/// cdrdao TOC container
+pub struct TocContainer;
+
+/// Static instance for registry
+pub static TOC: TocContainer = TocContainer;
+
+impl Container for TocContainer {
+    fn children(&self, reader: Arc<dyn Reader + Send + Sync>) -> io::Result<Vec<Child>> {
+        let toc = parse_toc(&cdrom::read_descriptor(&*reader)?)?;
+
+        // Data files in the order first named, with where DATAFILE
+        // continues each
+        let mut files: Vec<(&str, Arc<dyn Reader + Send + Sync>, u64)> = Vec::new();
+        let mut tracks = Vec::with_capacity(toc.len());
+        for track in &toc {
+            let Some(source) = &track.source else {
+                return Err(invalid_data("TOC track without data"));
+            };
+            let position = match files.iter().position(|(name, ..)| *name == source.name) {
+                Some(position) => position,
+                None => {
+                    let file = cdrom::open_data_file(&*reader, &source.name)?;
+                    files.push((&source.name, file, 0));
+                    files.len() - 1
+                }
+            };
+            let (_, file, next) = &mut files[position];
+
+            let frame_size = track.frame_size as u64;
+            let start = match source.start {
+                Some(start) => start.bytes(frame_size)?,
+                None => *next,
+            };
+            let length = match source.length {
+                Some(length) => length.bytes(frame_size)?,
+                None => file.size().unwrap_or(0).saturating_sub(start),
+            };
+            *next = start.saturating_add(length);
+
+            // The pregap's stored part is the start of the data
+            let stored_pregap = track.start.unwrap_or(0).saturating_sub(track.zeros);
+            tracks.push(ImageTrack {
+                number: tracks.len() as u32 + 1,
+                session: 1,
+                mode: track.mode,
+                file: Arc::clone(file),
+                offset: stored_pregap
+                    .checked_mul(frame_size)
+                    .and_then(|pregap| pregap.checked_add(start))
+                    .ok_or_else(|| invalid_data("TOC offset overflow"))?,
+                sector_size: track.sector_size,
+                frame_size: track.frame_size,
+                frames: (length / frame_size).saturating_sub(stored_pregap),
+                pregap: track.start.unwrap_or(0),
+            });
+        }
+        Ok(cdrom::image_children(tracks))
+    }
+}
+
+/// A length or offset as written in a TOC file
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Length {
+    Frames(u64),
+    Samples(u64),
+    Bytes(u64),
+}
+
+impl Length {
+    fn bytes(self, frame_size: u64) -> io::Result<u64> {
+        match self {
+            Length::Frames(frames) => frames.checked_mul(frame_size),
+            Length::Samples(samples) => samples.checked_mul(SAMPLE_SIZE),
+            Length::Bytes(bytes) => Some(bytes),
+        }
+        .ok_or_else(|| invalid_data("TOC length overflow"))
+    }
+}
+
+/// Where a track's stored data is
+#[derive(Debug, PartialEq)]
+struct Source {
+    name: String,
+    /// None to continue from the previous track in the file
+    start: Option<Length>,
+    /// None for the rest of the file
+    length: Option<Length>,
+}
+
+#[derive(Debug, PartialEq)]
+struct Track {
+    mode: TrackMode,
+    sector_size: usize,
+    frame_size: usize,
+    source: Option<Source>,
+    /// Frames not stored before the data
+    zeros: u64,
+    /// Frames before index 1, from `START` or `PREGAP`
+    start: Option<u64>,
+}
+
+/// Mode and sector size of a `TRACK` line's mode
+fn track_layout(mode: &str) -> Option<(TrackMode, usize)> {
+    Some(match mode {
+        "AUDIO" => (TrackMode::Audio, cdrom::SECTOR_SIZE),
+        "MODE1" => (TrackMode::Mode1, 2048),
+        "MODE1_RAW" => (TrackMode::Mode1, cdrom::SECTOR_SIZE),
+        "MODE2" | "MODE2_FORM_MIX" => (TrackMode::Mode2, cdrom::MODE2_SIZE),
+        "MODE2_FORM1" => (TrackMode::Mode2Form1, 2048),
+        "MODE2_FORM2" => (TrackMode::Mode2Form2, 2324),
+        "MODE2_RAW" => (TrackMode::Mode2, cdrom::SECTOR_SIZE),
+        _ => return None,
+    })
+}
+
+/// A time: mm:ss:ff, or a plain count of samples (audio) or bytes
+fn parse_length(word: &str, audio: bool) -> Option<Length> {
+    if word.contains(':') {
+        return cdrom::parse_msf(word).map(Length::Frames);
+    }
+    let count = word.parse().ok()?;
+    Some(if audio {
+        Length::Samples(count)
+    } else {
+        Length::Bytes(count)
+    })
+}
+
+fn parse_toc(text: &str) -> io::Result<Vec<Track>> {
+    let mut tracks: Vec<Track> = Vec::new();
+    // Nesting of CD_TEXT { ... } blocks, which are skipped
+    let mut depth = 0usize;
+    for line in text.lines() {
+        let line = line.split_once("//").map_or(line, |(code, _)| code);
+        let words = cdrom::words(line);
+        for word in &words {
+            match *word {
+                "{" => depth += 1,
+                "}" => depth = depth.saturating_sub(1),
+                _ => {}
+            }
+        }
+        let Some((&command, arguments)) = words.split_first() else {
+            continue;
+        };
+        if depth > 0 || command == "}" {
+            continue;
+        }
+
+        if command == "TRACK" {
+            let mode = arguments.first().copied().unwrap_or_default();
+            let (mode, sector_size) = track_layout(mode).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::Unsupported, "unsupported TOC track mode")
+            })?;
+            let subchannel = match arguments.get(1) {
+                Some(&"RW" | &"RW_RAW") => cdrom::SUBCHANNEL_SIZE,
+                _ => 0,
+            };
+            tracks.push(Track {
+                mode,
+                sector_size,
+                frame_size: sector_size + subchannel,
+                source: None,
+                zeros: 0,
+                start: None,
+            });
+            continue;
+        }
+        let Some(track) = tracks.last_mut() else {
+            continue;
+        };
+        let audio = track.mode == TrackMode::Audio;
+        let length = |word: Option<&&str>| {
+            word.and_then(|word| parse_length(word, audio))
+                .ok_or_else(|| invalid_data("invalid TOC length"))
+        };
+        let frame_size = track.frame_size as u64;
+        let frames = |length: Length| match length {
+            Length::Frames(frames) => frames,
+            Length::Samples(samples) => {
+                samples.saturating_mul(SAMPLE_SIZE) / cdrom::SECTOR_SIZE as u64
+            }
+            Length::Bytes(bytes) => bytes / frame_size,
+        };
+
+        match command {
+            "FILE" | "AUDIOFILE" | "DATAFILE" => {
+                if track.source.is_some() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::Unsupported,
+                        "TOC track with several data files",
+                    ));
+                }
+                let Some((name, rest)) = arguments.split_first() else {
+                    return Err(invalid_data("TOC file without name"));
+                };
+                // An optional #byte offset, then for FILE a start
+                let (offset, rest) = match rest.split_first() {
+                    Some((word, rest)) if word.starts_with('#') => {
+                        let offset = word[1..]
+                            .parse()
+                            .map_err(|_| invalid_data("invalid TOC file offset"))?;
+                        (Some(offset), rest)
+                    }
+                    _ => (None, rest),
+                };
+                let (start, rest) = if command == "DATAFILE" {
+                    (offset.map(Length::Bytes), rest)
+                } else {
+                    let start = length(rest.first())?.bytes(track.frame_size as u64)?;
+                    (
+                        Some(Length::Bytes(offset.unwrap_or(0).saturating_add(start))),
+                        &rest[1..],
+                    )
+                };
+                track.source = Some(Source {
+                    name: name.to_string(),
+                    start,
+                    length: rest.first().map(|word| length(Some(word))).transpose()?,
+                });
+            }
+            "ZERO" | "SILENCE" if track.source.is_none() => {
+                // ZERO may name a mode before its length
+                track.zeros = track
+                    .zeros
+                    .saturating_add(frames(length(arguments.last())?));
+            }
+            "PREGAP" => {
+                let pregap = frames(length(arguments.first())?);
+                track.zeros = track.zeros.saturating_add(pregap);
+                track.start = Some(pregap);
+            }
+            // A time from the start of the track, or where it has got to
+            "START" => {
+                track.start = Some(match arguments.first() {
+                    Some(_) => frames(length(arguments.first())?),
+                    None => {
+                        let stored = track.source.as_ref().and_then(|source| source.length);
+                        track.zeros.saturating_add(stored.map_or(0, frames))
+                    }
+                });
+            }
+            _ => {}
+        }
+    }
+    if tracks.is_empty() {
+        return Err(invalid_data("TOC without tracks"));
+    }
+    Ok(tracks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::FilesReader;
+
+    #[test]
+    fn reads_data_and_audio_tracks() {
+        let toc = r#"CD_ROM
+
+CD_TEXT {
+  LANGUAGE_MAP { 0 : EN }
+}
+
+// Track 1
+TRACK MODE1_RAW
+NO COPY
+DATAFILE "disc.bin" 00:00:04 // length in bytes: 9408
+
+// Track 2
+TRACK AUDIO
+TWO_CHANNEL_AUDIO
+SILENCE 00:02:00
+FILE "disc.bin" #9408 0 00:00:03
+START 00:02:01
+
+TRACK MODE2_FORM1 RW_RAW
+DATAFILE "data.bin"
+"#;
+        let mut disc = Vec::new();
+        for fill in [b'a', b'b', b'c', b'd'] {
+            let mut sector = vec![fill; cdrom::SECTOR_SIZE];
+            sector[..12].copy_from_slice(&cdrom::SYNC);
+            sector[15] = 1;
+            disc.extend(sector);
+        }
+        disc.extend(vec![b'p'; cdrom::SECTOR_SIZE]);
+        disc.extend(vec![b'm'; 2 * cdrom::SECTOR_SIZE]);
+        let data = [
+            vec![b'x'; 2048],
+            vec![b'q'; 96],
+            vec![b'y'; 2048],
+            vec![b'q'; 96],
+        ]
+        .concat();
+
+        let files = FilesReader::open(
+            "disc.toc",
+            vec![
+                ("disc.toc", toc.as_bytes().to_vec()),
+                ("disc.bin", disc),
+                ("data.bin", data),
+            ],
+        );
+        let kids = TOC.children(files).unwrap();
+        assert_eq!(kids.len(), 4);
+
+        let mut buf = [0u8; 1];
+        kids[1].reader.read_at(3 * 2048, &mut buf).unwrap();
+        assert_eq!(buf[0], b'd');
+
+        // The silence is not stored; START after it leaves one stored frame
+        let audio = &kids[2];
+        assert!(audio.metadata.contains(&("pregap", "151".to_string())));
+        assert_eq!(audio.reader.size(), Some(2 * 2352));
+        audio.reader.read_at(0, &mut buf).unwrap();
+        assert_eq!(buf[0], b'm');
+
+        // Subchannel data is left out
+        let data = &kids[3];
+        assert_eq!(data.reader.size(), Some(2 * 2048));
+        data.reader.read_at(2048, &mut buf).unwrap();
+        assert_eq!(buf[0], b'y');
+    }
+
+    #[test]
+    fn rejects_tracks_joining_files() {
+        let toc = "CD_DA\nTRACK AUDIO\nFILE \"a.wav\" 0\nFILE \"b.wav\" 0\n";
+        let error = parse_toc(toc).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::Unsupported);
+    }
+}
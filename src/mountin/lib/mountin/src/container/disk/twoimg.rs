@@ -60,6 +60,7 @@ impl Container for TwoImgContainer {
             index: 0,
             offset: data_off,
             reader: Arc::new(SliceReader::new(Arc::clone(&reader), data_off, data_len)),
+            metadata: Vec::new(),
         }])
     }
 }
@@ -1,7 +1,11 @@
 //! VDI (VirtualBox Disk Image) reader
 //!
 //! Parses VDI format and provides virtual disk access through block map.
+//! A differencing image names its parent only by UUID, so the parent is
+//! found by reading the headers of the files beside it; unallocated
+//! blocks read from the parent.
 
+use crate::container::disk::backing::{self, Backing, Layered};
 use crate::container::{Child, Container};
 use crate::detect::Reader;
 use std::io;
@@ -10,6 +14,10 @@ use std::sync::Arc;
 const VDI_SIGNATURE: u32 = 0xbeda107f;
 const VDI_UNALLOCATED: u32 = 0xFFFFFFFF;
 const VDI_DISCARDED: u32 = 0xFFFFFFFE;
+const VDI_TYPE_DIFF: u32 = 4;
+const HEADER_SIZE: usize = 0x1c8;
+const UUID_CREATE: usize = 0x188;
+const UUID_LINK: usize = 0x1a8;
 
 /// VDI disk image container
 pub struct VdiContainer;
@@ -19,12 +27,15 @@ pub static VDI: VdiContainer = VdiContainer;
 
 impl Container for VdiContainer {
     fn children(&self, reader: Arc<dyn Reader + Send + Sync>) -> io::Result<Vec<Child>> {
-        let vdi_reader = VdiReader::new(reader)?;
+        let mut vdi_reader = VdiReader::new(reader)?;
+        vdi_reader.open_backing(0);
+        let metadata = vdi_reader.chain_metadata();
 
         Ok(vec![Child {
             index: 0,
             offset: 0,
             reader: Arc::new(vdi_reader),
+            metadata,
         }])
     }
 }
@@ -36,12 +47,68 @@ pub struct VdiReader {
     block_size: u64,
     data_offset: u64,
     virtual_size: u64,
+    /// UUID of a differencing image's parent
+    parent_uuid: Option<[u8; 16]>,
+    backing: Option<Backing>,
+}
+
+/// VirtualBox's text form of a UUID, as used for snapshot file names;
+/// the first three fields are stored little-endian
+fn uuid_string(uuid: &[u8; 16]) -> String {
+    let hex = |bytes: &[u8]| -> String { bytes.iter().map(|b| format!("{b:02x}")).collect() };
+    let rev = |bytes: &[u8]| -> String { hex(&bytes.iter().rev().copied().collect::<Vec<_>>()) };
+    format!(
+        "{{{}-{}-{}-{}-{}}}",
+        rev(&uuid[0..4]),
+        rev(&uuid[4..6]),
+        rev(&uuid[6..8]),
+        hex(&uuid[8..10]),
+        hex(&uuid[10..16])
+    )
+}
+
+/// Whether `file` is a VDI image created with `uuid`
+fn has_uuid(file: &dyn Reader, uuid: &[u8; 16]) -> bool {
+    let mut header = [0u8; UUID_CREATE + 16];
+    matches!(file.read_at(0, &mut header), Ok(n) if n == header.len())
+        && header[0x40..0x44] == VDI_SIGNATURE.to_le_bytes()
+        && header[UUID_CREATE..] == uuid[..]
+}
+
+impl Layered for VdiReader {
+    fn open_backing(&mut self, depth: usize) {
+        let Some(uuid) = &self.parent_uuid else {
+            return;
+        };
+        // Snapshots are named after their own UUID; otherwise look at the
+        // header of every image nearby
+        let named = format!("{}.vdi", uuid_string(uuid));
+        let nearby = self
+            .parent
+            .siblings()
+            .into_iter()
+            .filter(|name| name.to_ascii_lowercase().ends_with(".vdi") && *name != named);
+        for name in std::iter::once(named.clone()).chain(nearby) {
+            if let Ok(file) = self.parent.sibling(&name) {
+                if has_uuid(&*file, uuid) {
+                    self.backing = Some(backing::open_file(file, &name, Some("vdi"), depth));
+                    return;
+                }
+            }
+        }
+        self.backing = Some(Backing::missing(&named));
+    }
+
+    fn backing(&self) -> Option<&Backing> {
+        self.backing.as_ref()
+    }
 }
 
 impl VdiReader {
     pub fn new(parent: Arc<dyn Reader + Send + Sync>) -> io::Result<Self> {
-        // Read header (need up to 0x184 for blocks_in_image)
-        let mut header = [0u8; 0x188];
+        // Read header (need up to 0x184 for blocks_in_image, 0x1b8 for
+        // the parent UUID)
+        let mut header = [0u8; HEADER_SIZE];
         if parent.read_at(0, &mut header)? < 0x184 {
             return Err(io::Error::new(
                 io::ErrorKind::UnexpectedEof,
@@ -60,6 +127,14 @@ impl VdiReader {
         }
 
         // Parse header fields (little-endian)
+        let image_type =
+            u32::from_le_bytes([header[0x4c], header[0x4d], header[0x4e], header[0x4f]]);
+        let parent_uuid = if image_type == VDI_TYPE_DIFF {
+            let uuid: [u8; 16] = header[UUID_LINK..UUID_LINK + 16].try_into().unwrap();
+            Some(uuid)
+        } else {
+            None
+        };
         let offset_bmap =
             u32::from_le_bytes([header[0x154], header[0x155], header[0x156], header[0x157]])
                 as u64;
@@ -113,6 +188,8 @@ impl VdiReader {
             block_size,
             data_offset: offset_data,
             virtual_size: disk_size,
+            parent_uuid,
+            backing: None,
         })
     }
 }
@@ -137,14 +214,16 @@ impl Reader for VdiReader {
 
         // Check bounds
         if block_idx >= self.block_map.len() {
-            buf[..to_read].fill(0);
-            return Ok(to_read);
+            return backing::read_backing(self.backing(), offset, &mut buf[..to_read]);
         }
 
         let bmap_entry = self.block_map[block_idx];
 
-        // Check for unallocated or discarded blocks
-        if bmap_entry == VDI_UNALLOCATED || bmap_entry == VDI_DISCARDED {
+        // Unallocated blocks come from the parent; discarded ones are zero
+        if bmap_entry == VDI_UNALLOCATED {
+            return backing::read_backing(self.backing(), offset, &mut buf[..to_read]);
+        }
+        if bmap_entry == VDI_DISCARDED {
             buf[..to_read].fill(0);
             return Ok(to_read);
         }
@@ -162,3 +241,92 @@ impl Reader for VdiReader {
 // SAFETY: VdiReader only holds Arc and Vec, safe to send/share
 unsafe impl Send for VdiReader {}
 unsafe impl Sync for VdiReader {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::FilesReader;
+
+    const BLOCK: u64 = 1024;
+    const PARENT_UUID: [u8; 16] = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16];
+    const PARENT_NAME: &str = "{04030201-0605-0807-090a-0b0c0d0e0f10}.vdi";
+
+    /// An image of two 1 KiB blocks, allocated ones given by their fill;
+    /// a differencing image if `link` names a parent
+    fn image(create: [u8; 16], link: Option<[u8; 16]>, blocks: [Option<u8>; 2]) -> Vec<u8> {
+        let mut out = vec![0u8; 0x400];
+        out[0x40..0x44].copy_from_slice(&VDI_SIGNATURE.to_le_bytes());
+        let image_type = if link.is_some() { VDI_TYPE_DIFF } else { 1 };
+        out[0x4c..0x50].copy_from_slice(&image_type.to_le_bytes());
+        out[0x154..0x158].copy_from_slice(&0x200u32.to_le_bytes());
+        out[0x158..0x15c].copy_from_slice(&0x400u32.to_le_bytes());
+        out[0x170..0x178].copy_from_slice(&(2 * BLOCK).to_le_bytes());
+        out[0x178..0x17c].copy_from_slice(&(BLOCK as u32).to_le_bytes());
+        out[0x180..0x184].copy_from_slice(&2u32.to_le_bytes());
+        out[UUID_CREATE..][..16].copy_from_slice(&create);
+        out[UUID_LINK..][..16].copy_from_slice(&link.unwrap_or_default());
+
+        let mut allocated = 0u32;
+        for (i, block) in blocks.iter().enumerate() {
+            let entry = match block {
+                Some(fill) => {
+                    out.resize(out.len() + BLOCK as usize, *fill);
+                    allocated += 1;
+                    allocated - 1
+                }
+                None => VDI_UNALLOCATED,
+            };
+            out[0x200 + i * 4..][..4].copy_from_slice(&entry.to_le_bytes());
+        }
+        out
+    }
+
+    fn child() -> Vec<u8> {
+        image([0xcc; 16], Some(PARENT_UUID), [Some(b'C'), None])
+    }
+
+    fn read(reader: &dyn Reader, offset: u64) -> [u8; 4] {
+        let mut buf = [0u8; 4];
+        reader.read_at(offset, &mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn finds_snapshot_parent_by_uuid() {
+        let parent = image(PARENT_UUID, None, [Some(b'P'), Some(b'Q')]);
+        let files = vec![("disk.vdi", child()), (PARENT_NAME, parent)];
+        let children = VDI.children(FilesReader::open("disk.vdi", files)).unwrap();
+        assert_eq!(children[0].metadata, [("backing", PARENT_NAME.to_string())]);
+
+        let disk = &children[0].reader;
+        assert_eq!(&read(&**disk, 0), b"CCCC");
+        assert_eq!(&read(&**disk, BLOCK), b"QQQQ");
+    }
+
+    #[test]
+    fn scans_siblings_for_parent() {
+        // Only the image created with the linked UUID is the parent
+        let other = image([0xdd; 16], None, [Some(b'X'), Some(b'X')]);
+        let parent = image(PARENT_UUID, None, [Some(b'P'), Some(b'Q')]);
+        let files = vec![
+            ("disk.vdi", child()),
+            ("notes.txt", b"not an image".to_vec()),
+            ("other.vdi", other),
+            ("base.VDI", parent),
+        ];
+        let children = VDI.children(FilesReader::open("disk.vdi", files)).unwrap();
+        assert_eq!(children[0].metadata, [("backing", "base.VDI".to_string())]);
+        assert_eq!(&read(&*children[0].reader, BLOCK), b"QQQQ");
+    }
+
+    #[test]
+    fn reports_missing_parent() {
+        let files = vec![("disk.vdi", child())];
+        let children = VDI.children(FilesReader::open("disk.vdi", files)).unwrap();
+        assert_eq!(
+            children[0].metadata,
+            [("missing_backing", PARENT_NAME.to_string())]
+        );
+        assert_eq!(read(&*children[0].reader, BLOCK), [0; 4]);
+    }
+}
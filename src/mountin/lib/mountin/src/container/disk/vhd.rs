@@ -2,7 +2,10 @@
 //!
 //! Parses Microsoft VHD format and provides virtual disk access.
 //! Supports Fixed (type 2), Dynamic (type 3), and Differencing (type 4).
+//! A differencing disk's parent is found through its parent locators;
+//! sectors its block bitmaps don't mark as present read from the parent.
 
+use crate::container::disk::backing::{self, Backing, Layered};
 use crate::container::{Child, Container};
 use crate::detect::Reader;
 use std::io;
@@ -15,6 +18,13 @@ const VHD_DYNAMIC: u32 = 3;
 const VHD_DIFFERENCING: u32 = 4;
 const VHD_UNALLOCATED: u32 = 0xFFFFFFFF;
 
+// Parent locator platform codes
+const PLATFORM_W2RU: u32 = 0x57327275; // Relative path, UTF-16LE
+const PLATFORM_W2KU: u32 = 0x57326B75; // Absolute path, UTF-16LE
+const PLATFORM_MACX: u32 = 0x4D616358; // file:// URL, UTF-8
+const PARENT_LOCATORS: usize = 8;
+const MAX_LOCATOR_SIZE: u32 = 4096;
+
 /// VHD disk image container
 pub struct VhdContainer;
 
@@ -23,12 +33,15 @@ pub static VHD: VhdContainer = VhdContainer;
 
 impl Container for VhdContainer {
     fn children(&self, reader: Arc<dyn Reader + Send + Sync>) -> io::Result<Vec<Child>> {
-        let vhd_reader = VhdReader::new(reader)?;
+        let mut vhd_reader = VhdReader::new(reader)?;
+        vhd_reader.open_backing(0);
+        let metadata = vhd_reader.chain_metadata();
 
         Ok(vec![Child {
             index: 0,
             offset: 0,
             reader: Arc::new(vhd_reader),
+            metadata,
         }])
     }
 }
@@ -50,6 +63,83 @@ pub struct VhdReader {
     parent: Arc<dyn Reader + Send + Sync>,
     variant: VhdVariant,
     virtual_size: u64,
+    /// Ways a differencing disk names its parent, best first
+    parent_names: Option<Vec<String>>,
+    backing: Option<Backing>,
+}
+
+fn utf16(data: &[u8], big_endian: bool) -> String {
+    let units: Vec<u16> = data
+        .chunks_exact(2)
+        .map(|c| {
+            if big_endian {
+                u16::from_be_bytes([c[0], c[1]])
+            } else {
+                u16::from_le_bytes([c[0], c[1]])
+            }
+        })
+        .take_while(|&unit| unit != 0)
+        .collect();
+    String::from_utf16_lossy(&units)
+}
+
+/// Parent paths from a differencing disk's locators and parent name
+fn read_parent_names(parent: &dyn Reader, dyn_header: &[u8; 1024]) -> io::Result<Vec<String>> {
+    let mut names = Vec::new();
+    for i in 0..PARENT_LOCATORS {
+        let entry = &dyn_header[0x240 + i * 24..][..24];
+        let code = u32::from_be_bytes([entry[0], entry[1], entry[2], entry[3]]);
+        let length = u32::from_be_bytes([entry[8], entry[9], entry[10], entry[11]]);
+        let offset = u64::from_be_bytes(entry[16..24].try_into().unwrap());
+        if length == 0 || length > MAX_LOCATOR_SIZE {
+            continue;
+        }
+        let mut data = vec![0u8; length as usize];
+        if parent.read_at(offset, &mut data)? != data.len() {
+            continue;
+        }
+        let name = match code {
+            // ".\parent.vhd" relative to the child
+            PLATFORM_W2RU => {
+                let path = utf16(&data, false).replace('\\', "/");
+                path.strip_prefix("./").map(str::to_string).unwrap_or(path)
+            }
+            PLATFORM_W2KU => utf16(&data, false),
+            PLATFORM_MACX => {
+                let url = String::from_utf8_lossy(&data);
+                let url = url.trim_end_matches('\0');
+                url.strip_prefix("file://").unwrap_or(url).to_string()
+            }
+            _ => continue,
+        };
+        if !name.is_empty() && !names.contains(&name) {
+            // Relative locators first
+            if code == PLATFORM_W2RU {
+                names.insert(0, name);
+            } else {
+                names.push(name);
+            }
+        }
+    }
+
+    // The parent's file name, without its path
+    let name = utf16(&dyn_header[0x40..0x240], true);
+    if !name.is_empty() && !names.contains(&name) {
+        names.push(name);
+    }
+    Ok(names)
+}
+
+impl Layered for VhdReader {
+    fn open_backing(&mut self, depth: usize) {
+        if let Some(names) = &self.parent_names {
+            self.backing = Some(backing::open(&*self.parent, names, Some("vpc"), depth));
+        }
+    }
+
+    fn backing(&self) -> Option<&Backing> {
+        self.backing.as_ref()
+    }
 }
 
 impl VhdReader {
@@ -83,6 +173,7 @@ impl VhdReader {
         let disk_type =
             u32::from_be_bytes([footer[0x3c], footer[0x3d], footer[0x3e], footer[0x3f]]);
 
+        let mut parent_names = None;
         let variant = match disk_type {
             VHD_FIXED => {
                 // Fixed VHD: raw data starts at offset 0, footer at end
@@ -144,6 +235,10 @@ impl VhdReader {
                     .map(|chunk| u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
                     .collect();
 
+                if disk_type == VHD_DIFFERENCING {
+                    parent_names = Some(read_parent_names(parent.as_ref(), &dyn_header)?);
+                }
+
                 VhdVariant::Dynamic { bat, block_size, bitmap_size }
             }
             _ => {
@@ -158,6 +253,8 @@ impl VhdReader {
             parent,
             variant,
             virtual_size,
+            parent_names,
+            backing: None,
         })
     }
 }
@@ -184,15 +281,31 @@ impl Reader for VhdReader {
                 let to_read = to_read.min(remaining_in_block as usize);
 
                 if block_idx >= bat.len() {
-                    buf[..to_read].fill(0);
-                    return Ok(to_read);
+                    return backing::read_backing(self.backing(), offset, &mut buf[..to_read]);
                 }
 
                 let bat_entry = bat[block_idx];
 
                 if bat_entry == VHD_UNALLOCATED {
-                    buf[..to_read].fill(0);
-                    return Ok(to_read);
+                    return backing::read_backing(self.backing(), offset, &mut buf[..to_read]);
+                }
+
+                // A differencing block holds only the sectors its bitmap
+                // marks; the rest are the parent's
+                let mut to_read = to_read;
+                if self.parent_names.is_some() {
+                    let sector = in_block / 512;
+                    let mut bits = [0u8; 1];
+                    if self.parent.read_at(bat_entry as u64 * 512 + sector / 8, &mut bits)? != 1 {
+                        return Err(io::Error::new(
+                            io::ErrorKind::UnexpectedEof,
+                            "short sector bitmap read",
+                        ));
+                    }
+                    to_read = to_read.min((512 - in_block % 512) as usize);
+                    if bits[0] & (0x80 >> (sector % 8)) == 0 {
+                        return backing::read_backing(self.backing(), offset, &mut buf[..to_read]);
+                    }
                 }
 
                 // BAT entry is sector number, skip bitmap at start of block
@@ -210,3 +323,148 @@ impl Reader for VhdReader {
 // SAFETY: VhdReader only holds Arc and Vec, safe to send/share
 unsafe impl Send for VhdReader {}
 unsafe impl Sync for VhdReader {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::{BytesReader, FilesReader};
+
+    const BLOCK: u64 = 4096;
+
+    /// A dynamic or differencing disk of two 4 KiB blocks. Allocated blocks
+    /// are given as (first sector bitmap byte, fill), locators as (platform,
+    /// data).
+    fn image(
+        disk_type: u32,
+        blocks: &[Option<(u8, u8)>; 2],
+        locators: &[(u32, &[u8])],
+        parent_name: &str,
+    ) -> Vec<u8> {
+        let mut out = vec![0u8; 2048];
+        out[..8].copy_from_slice(VHD_MAGIC);
+        out[0x10..0x18].copy_from_slice(&512u64.to_be_bytes());
+        out[0x30..0x38].copy_from_slice(&(2 * BLOCK).to_be_bytes());
+        out[0x3c..0x40].copy_from_slice(&disk_type.to_be_bytes());
+
+        let header = &mut out[512..1536];
+        header[..8].copy_from_slice(VHD_DYNAMIC_MAGIC);
+        header[0x10..0x18].copy_from_slice(&1536u64.to_be_bytes());
+        header[0x1c..0x20].copy_from_slice(&2u32.to_be_bytes());
+        header[0x20..0x24].copy_from_slice(&(BLOCK as u32).to_be_bytes());
+        let name: Vec<u8> = parent_name
+            .encode_utf16()
+            .flat_map(u16::to_be_bytes)
+            .collect();
+        header[0x40..][..name.len()].copy_from_slice(&name);
+
+        for (i, (code, data)) in locators.iter().enumerate() {
+            let offset = out.len() as u64;
+            let entry = &mut out[512 + 0x240 + i * 24..][..24];
+            entry[..4].copy_from_slice(&code.to_be_bytes());
+            entry[8..12].copy_from_slice(&(data.len() as u32).to_be_bytes());
+            entry[16..24].copy_from_slice(&offset.to_be_bytes());
+            out.extend_from_slice(data);
+            out.resize(out.len().next_multiple_of(512), 0);
+        }
+
+        for (i, block) in blocks.iter().enumerate() {
+            let sector = match block {
+                Some((bitmap, fill)) => {
+                    let sector = out.len() as u32 / 512;
+                    out.push(*bitmap);
+                    out.resize(out.len() + 511, 0);
+                    out.resize(out.len() + BLOCK as usize, *fill);
+                    sector
+                }
+                None => VHD_UNALLOCATED,
+            };
+            out[1536 + i * 4..][..4].copy_from_slice(&sector.to_be_bytes());
+        }
+        out
+    }
+
+    fn utf16le(text: &str) -> Vec<u8> {
+        text.encode_utf16().flat_map(u16::to_le_bytes).collect()
+    }
+
+    fn read(reader: &dyn Reader, offset: u64) -> [u8; 4] {
+        let mut buf = [0u8; 4];
+        reader.read_at(offset, &mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn reads_through_to_parent() {
+        // Block 0 holds only its first sector; block 1 is in the parent
+        let locator = utf16le(r".\base.vhd");
+        let child = image(
+            VHD_DIFFERENCING,
+            &[Some((0x80, b'C')), None],
+            &[(PLATFORM_W2RU, &locator)],
+            "base.vhd",
+        );
+        let parent = image(VHD_DYNAMIC, &[Some((0, b'P')), Some((0, b'Q'))], &[], "");
+
+        let files = vec![("disk.vhd", child), ("base.vhd", parent)];
+        let children = VHD.children(FilesReader::open("disk.vhd", files)).unwrap();
+        assert_eq!(children[0].metadata, [("backing", "base.vhd".to_string())]);
+
+        let disk = &children[0].reader;
+        assert_eq!(&read(&**disk, 0), b"CCCC");
+        assert_eq!(&read(&**disk, 508), b"CCCC");
+        assert_eq!(&read(&**disk, 512), b"PPPP");
+        assert_eq!(&read(&**disk, BLOCK), b"QQQQ");
+
+        // A read stops at the end of a sector
+        let mut buf = [0u8; 1024];
+        assert_eq!(disk.read_at(0, &mut buf).unwrap(), 512);
+    }
+
+    #[test]
+    fn reads_parent_locators() {
+        let relative = utf16le(r".\snapshots\base.vhd");
+        let absolute = utf16le(r"C:\disks\base.vhd");
+        let url = b"file:///Users/me/base.vhd\0";
+        let child = image(
+            VHD_DIFFERENCING,
+            &[None, None],
+            &[
+                (PLATFORM_W2KU, &absolute),
+                (PLATFORM_MACX, url),
+                (PLATFORM_W2RU, &relative),
+                (0x5769326B, b"ignored"),
+            ],
+            "base copy.vhd",
+        );
+        let mut dyn_header = [0u8; 1024];
+        dyn_header.copy_from_slice(&child[512..1536]);
+        let names = read_parent_names(&BytesReader::new(child), &dyn_header).unwrap();
+        assert_eq!(
+            names,
+            [
+                "snapshots/base.vhd",
+                r"C:\disks\base.vhd",
+                "/Users/me/base.vhd",
+                "base copy.vhd",
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_missing_parent() {
+        let locator = utf16le(r"C:\disks\base.vhd");
+        let child = image(
+            VHD_DIFFERENCING,
+            &[None, None],
+            &[(PLATFORM_W2KU, &locator)],
+            "base.vhd",
+        );
+        let files = vec![("disk.vhd", child)];
+        let children = VHD.children(FilesReader::open("disk.vhd", files)).unwrap();
+        assert_eq!(
+            children[0].metadata,
+            [("missing_backing", r"C:\disks\base.vhd".to_string())]
+        );
+        assert_eq!(read(&*children[0].reader, 0), [0; 4]);
+    }
+}
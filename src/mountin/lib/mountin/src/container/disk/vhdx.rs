@@ -1,14 +1,40 @@
 //! VHDX (Hyper-V Virtual Hard Disk v2) reader
 //!
 //! Parses VHDX format and provides virtual disk access through BAT.
-
-use crate::container::{Child, Container};
+//!
+//! The current header is the valid one of the two with the higher sequence
+//! number. If it names a log, the log's active sequence is replayed in
+//! memory over the file before the metadata and BAT are read, as Hyper-V
+//! would on opening a disk from a host that crashed; nothing is written
+//! back. Differencing disks (`.avhdx`) find their parent through the parent
+//! locator and read through to it for blocks, or sectors, they don't hold.
+
+use crate::container::disk::backing::{self, Backing, Layered};
+use crate::container::{
+    checked_table_size, invalid_data, le16, le32, le64, Child, Container, Metadata,
+};
 use crate::detect::Reader;
 use std::io;
 use std::sync::Arc;
 
 const VHDX_FILE_SIGNATURE: &[u8; 8] = b"vhdxfile";
 const VHDX_REGION_SIGNATURE: u32 = 0x69676572; // "regi"
+const VHDX_HEADER_SIGNATURE: &[u8; 4] = b"head";
+const VHDX_METADATA_SIGNATURE: &[u8; 8] = b"metadata";
+const VHDX_LOG_SIGNATURE: &[u8; 4] = b"loge";
+const VHDX_DATA_DESCRIPTOR: &[u8; 4] = b"desc";
+const VHDX_ZERO_DESCRIPTOR: &[u8; 4] = b"zero";
+const VHDX_DATA_SECTOR: &[u8; 4] = b"data";
+
+/// The two headers and the two region tables, each a copy of the other
+const HEADER_OFFSETS: [u64; 2] = [0x10000, 0x20000];
+const REGION_TABLE_OFFSETS: [u64; 2] = [0x30000, 0x40000];
+const HEADER_SIZE: usize = 4096;
+const REGION_TABLE_SIZE: usize = 0x10000;
+/// Log entries are made of 4 KB sectors
+const LOG_SECTOR: u64 = 4096;
+/// Largest log replayed
+const MAX_LOG_LENGTH: u64 = 256 * 1024 * 1024;
 
 // Region GUIDs (little-endian)
 const BAT_GUID: [u8; 16] = [
@@ -29,11 +55,39 @@ const FILE_PARAMETERS_GUID: [u8; 16] = [
     0x37, 0x67, 0xa1, 0xca, 0x36, 0xfa, 0x43, 0x4d,
     0xb3, 0xb6, 0x33, 0xf0, 0xaa, 0x44, 0xe7, 0x6b,
 ];
+const LOGICAL_SECTOR_SIZE_GUID: [u8; 16] = [
+    0x1d, 0xbf, 0x41, 0x81, 0x6f, 0xa9, 0x09, 0x47,
+    0xba, 0x47, 0xf2, 0x33, 0xa8, 0xfa, 0xab, 0x5f,
+];
+const PHYSICAL_SECTOR_SIZE_GUID: [u8; 16] = [
+    0xc7, 0x48, 0xa3, 0xcd, 0x5d, 0x44, 0x71, 0x44,
+    0x9c, 0xc9, 0xe9, 0x88, 0x52, 0x51, 0xc5, 0x56,
+];
+const PARENT_LOCATOR_GUID: [u8; 16] = [
+    0x2d, 0x5f, 0xd3, 0xa8, 0x0b, 0xb3, 0x4d, 0x45,
+    0xab, 0xf7, 0xd3, 0xd8, 0x48, 0x34, 0xab, 0x0c,
+];
+/// Locator type of a VHDX parent
+const VHDX_PARENT_TYPE_GUID: [u8; 16] = [
+    0xb7, 0xef, 0x4a, 0xb0, 0x9e, 0xd1, 0x81, 0x4a,
+    0xb7, 0x89, 0x25, 0xb8, 0xe9, 0x44, 0x59, 0x13,
+];
+
+/// Region table entry and metadata entry flag for items a reader must know
+const REGION_REQUIRED: u32 = 1;
+const METADATA_IS_REQUIRED: u32 = 4;
+const FILE_PARAMETERS_HAS_PARENT: u32 = 2;
 
 // BAT entry masks
 const BAT_STATE_MASK: u64 = 0x07;
 const BAT_FILE_OFF_MASK: u64 = 0xFFFFFFFFFFF00000;
+const BAT_STATE_NOT_PRESENT: u64 = 0;
 const BAT_STATE_FULLY_PRESENT: u64 = 6;
+const BAT_STATE_PARTIALLY_PRESENT: u64 = 7;
+/// Sector bitmap block state holding a bitmap
+const BAT_STATE_SB_PRESENT: u64 = 6;
+/// Sectors covered by one sector bitmap block (1 MB of bits)
+const CHUNK_SECTORS: u64 = 1 << 23;
 
 /// VHDX disk image container
 pub struct VhdxContainer;
@@ -43,126 +97,462 @@ pub static VHDX: VhdxContainer = VhdxContainer;
 
 impl Container for VhdxContainer {
     fn children(&self, reader: Arc<dyn Reader + Send + Sync>) -> io::Result<Vec<Child>> {
-        let vhdx_reader = VhdxReader::new(reader)?;
+        let mut vhdx_reader = VhdxReader::new(reader)?;
+        vhdx_reader.open_backing(0);
+        let mut metadata: Metadata = vec![
+            ("logical_sector_size", vhdx_reader.logical_sector_size.to_string()),
+            ("physical_sector_size", vhdx_reader.physical_sector_size.to_string()),
+        ];
+        metadata.extend(vhdx_reader.chain_metadata());
 
         Ok(vec![Child {
             index: 0,
             offset: 0,
             reader: Arc::new(vhdx_reader),
+            metadata,
         }])
     }
 }
 
-/// Reader that translates virtual disk offsets through VHDX BAT
-pub struct VhdxReader {
-    parent: Arc<dyn Reader + Send + Sync>,
-    bat: Vec<u64>,
-    block_size: u64,
-    virtual_size: u64,
+/// CRC-32C (Castagnoli) lookup table
+const CRC32C_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0x82F63B78 } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+fn crc32c(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, &byte| {
+        CRC32C_TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
 }
 
-impl VhdxReader {
-    pub fn new(parent: Arc<dyn Reader + Send + Sync>) -> io::Result<Self> {
-        // Check file signature
-        let mut sig = [0u8; 8];
-        if parent.read_at(0, &mut sig)? != 8 || &sig != VHDX_FILE_SIGNATURE {
+/// Whether `data` has a valid CRC-32C in the 4 bytes at `at`, which are
+/// taken as zero when summing
+fn checksum_ok(data: &mut [u8], at: usize) -> bool {
+    let stored = le32(&data[at..]);
+    data[at..at + 4].fill(0);
+    let ok = crc32c(data) == stored;
+    data[at..at + 4].copy_from_slice(&stored.to_le_bytes());
+    ok
+}
+
+/// The fields of the current header used here
+struct Header {
+    log_guid: [u8; 16],
+    log_length: u64,
+    log_offset: u64,
+}
+
+/// Pick the valid header with the higher sequence number
+fn current_header(parent: &dyn Reader) -> io::Result<Header> {
+    let mut best: Option<(u64, Header)> = None;
+    for offset in HEADER_OFFSETS {
+        let mut data = vec![0u8; HEADER_SIZE];
+        if parent.read_at(offset, &mut data)? != HEADER_SIZE
+            || &data[..4] != VHDX_HEADER_SIGNATURE
+            || !checksum_ok(&mut data, 4)
+        {
+            continue;
+        }
+        let sequence = le64(&data[8..]);
+        if best.as_ref().is_some_and(|(best, _)| *best >= sequence) {
+            continue;
+        }
+        if le16(&data[66..]) != 1 {
             return Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                "invalid VHDX signature",
+                io::ErrorKind::Unsupported,
+                "unsupported VHDX version",
             ));
         }
+        let header = Header {
+            log_guid: data[48..64].try_into().unwrap(),
+            log_length: u64::from(le32(&data[68..])),
+            log_offset: le64(&data[72..]),
+        };
+        best = Some((sequence, header));
+    }
+    best.map(|(_, header)| header)
+        .ok_or_else(|| invalid_data("no valid VHDX header"))
+}
+
+/// Read the first valid region table: (GUID, file offset, length) per entry
+fn read_regions(parent: &dyn Reader) -> io::Result<Vec<([u8; 16], u64, u64)>> {
+    for offset in REGION_TABLE_OFFSETS {
+        let mut table = vec![0u8; REGION_TABLE_SIZE];
+        if parent.read_at(offset, &mut table)? != REGION_TABLE_SIZE
+            || le32(&table) != VHDX_REGION_SIGNATURE
+            || !checksum_ok(&mut table, 4)
+        {
+            continue;
+        }
+
+        // Region entries are 32 bytes each, starting at offset 16
+        let entry_count = (le32(&table[8..]) as usize).min(2047);
+        let mut regions = Vec::with_capacity(entry_count);
+        for entry in table[16..].chunks_exact(32).take(entry_count) {
+            let guid: [u8; 16] = entry[0..16].try_into().unwrap();
+            let required = le32(&entry[28..]) & REGION_REQUIRED != 0;
+            if required && guid != BAT_GUID && guid != METADATA_GUID {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "unsupported required VHDX region",
+                ));
+            }
+            regions.push((guid, le64(&entry[16..]), u64::from(le32(&entry[24..]))));
+        }
+        return Ok(regions);
+    }
+    Err(invalid_data("no valid VHDX region table"))
+}
+
+/// A write recorded in the log: `length` bytes at `offset`, zeros if no data
+struct LogWrite {
+    offset: u64,
+    length: u64,
+    data: Option<Vec<u8>>,
+}
+
+/// A valid log entry
+struct LogEntry {
+    sequence: u64,
+    /// Log offset of the oldest entry of its sequence
+    tail: u64,
+    length: u64,
+    writes: Vec<LogWrite>,
+}
 
-        // Read region table at 192KB (0x30000)
-        let mut region_header = [0u8; 16];
-        if parent.read_at(0x30000, &mut region_header)? != 16 {
+/// Read from the circular log, wrapping at its end
+fn read_log(parent: &dyn Reader, header: &Header, pos: u64, buf: &mut [u8]) -> io::Result<()> {
+    let mut done = 0;
+    while done < buf.len() {
+        let at = (pos + done as u64) % header.log_length;
+        let n = (buf.len() - done).min((header.log_length - at) as usize);
+        if parent.read_at(header.log_offset + at, &mut buf[done..done + n])? != n {
             return Err(io::Error::new(
                 io::ErrorKind::UnexpectedEof,
-                "short region header read",
+                "short VHDX log read",
             ));
         }
+        done += n;
+    }
+    Ok(())
+}
 
-        let region_sig = u32::from_le_bytes([
-            region_header[0],
-            region_header[1],
-            region_header[2],
-            region_header[3],
-        ]);
-        if region_sig != VHDX_REGION_SIGNATURE {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                "invalid region table signature",
-            ));
+/// Parse the log entry at `pos`, if there is a valid one
+fn read_log_entry(parent: &dyn Reader, header: &Header, pos: u64) -> io::Result<Option<LogEntry>> {
+    let mut head = [0u8; 64];
+    read_log(parent, header, pos, &mut head)?;
+    let length = u64::from(le32(&head[8..]));
+    let tail = u64::from(le32(&head[12..]));
+    if &head[..4] != VHDX_LOG_SIGNATURE
+        || length == 0
+        || length % LOG_SECTOR != 0
+        || length > header.log_length
+        || tail % LOG_SECTOR != 0
+        || tail >= header.log_length
+        || head[32..48] != header.log_guid
+    {
+        return Ok(None);
+    }
+    let sequence = le64(&head[16..]);
+    let descriptors = le32(&head[24..]) as u64;
+
+    let mut entry = vec![0u8; length as usize];
+    read_log(parent, header, pos, &mut entry)?;
+    if !checksum_ok(&mut entry, 4) {
+        return Ok(None);
+    }
+
+    // Descriptors follow the 64-byte header, then one 4 KB data sector per
+    // data descriptor
+    let descriptor_sectors = (64 + descriptors * 32).div_ceil(LOG_SECTOR);
+    if descriptor_sectors > length / LOG_SECTOR {
+        return Ok(None);
+    }
+    let mut data_sector = descriptor_sectors * LOG_SECTOR;
+    let mut writes = Vec::with_capacity(descriptors as usize);
+    for i in 0..descriptors as usize {
+        let descriptor = &entry[64 + i * 32..][..32];
+        if le64(&descriptor[24..]) != sequence {
+            return Ok(None);
         }
+        let offset = le64(&descriptor[16..]);
+        match descriptor[..4].try_into().unwrap() {
+            VHDX_ZERO_DESCRIPTOR => writes.push(LogWrite {
+                offset,
+                length: le64(&descriptor[8..]),
+                data: None,
+            }),
+            VHDX_DATA_DESCRIPTOR => {
+                let Some(sector) = entry.get(data_sector as usize..(data_sector + LOG_SECTOR) as usize)
+                else {
+                    return Ok(None);
+                };
+                let stamped = u64::from(le32(&sector[4..])) << 32 | u64::from(le32(&sector[4092..]));
+                if &sector[..4] != VHDX_DATA_SECTOR || stamped != sequence {
+                    return Ok(None);
+                }
+                // The first 8 and last 4 bytes are kept in the descriptor
+                let mut data = Vec::with_capacity(LOG_SECTOR as usize);
+                data.extend_from_slice(&descriptor[8..16]);
+                data.extend_from_slice(&sector[8..4092]);
+                data.extend_from_slice(&descriptor[4..8]);
+                writes.push(LogWrite {
+                    offset,
+                    length: LOG_SECTOR,
+                    data: Some(data),
+                });
+                data_sector += LOG_SECTOR;
+            }
+            _ => return Ok(None),
+        }
+    }
+
+    Ok(Some(LogEntry {
+        sequence,
+        tail,
+        length,
+        writes,
+    }))
+}
 
-        let entry_count = u32::from_le_bytes([
-            region_header[8],
-            region_header[9],
-            region_header[10],
-            region_header[11],
-        ]) as usize;
+/// The writes of the log's active sequence, oldest first: the entries from
+/// the tail of the newest valid entry up to that entry
+fn replay_log(parent: &dyn Reader, header: &Header) -> io::Result<Vec<LogWrite>> {
+    if header.log_length == 0 || header.log_length > MAX_LOG_LENGTH {
+        return Err(invalid_data("invalid VHDX log length"));
+    }
+    let mut entries = std::collections::BTreeMap::new();
+    for pos in (0..header.log_length).step_by(LOG_SECTOR as usize) {
+        if let Some(entry) = read_log_entry(parent, header, pos)? {
+            entries.insert(pos, entry);
+        }
+    }
+    let Some((&head, newest)) = entries.iter().max_by_key(|(_, entry)| entry.sequence) else {
+        return Ok(Vec::new());
+    };
+    let head_sequence = newest.sequence;
+
+    // Walk from the tail, each entry following the last in the log and in
+    // sequence; a broken sequence leaves nothing to replay
+    let mut pos = newest.tail;
+    let mut sequence = None;
+    let mut order = Vec::new();
+    loop {
+        let Some(entry) = entries.get(&pos) else {
+            return Ok(Vec::new());
+        };
+        if sequence.is_some_and(|sequence| entry.sequence != sequence + 1)
+            || entry.sequence > head_sequence
+        {
+            return Ok(Vec::new());
+        }
+        sequence = Some(entry.sequence);
+        order.push(pos);
+        if pos == head {
+            break;
+        }
+        pos = (pos + entry.length) % header.log_length;
+    }
+    Ok(order
+        .into_iter()
+        .flat_map(|pos| entries.remove(&pos).unwrap().writes)
+        .collect())
+}
 
-        // Read region entries (32 bytes each, starting at offset 16)
-        let mut bat_offset = 0u64;
-        let mut bat_length = 0u64;
-        let mut metadata_offset = 0u64;
+/// The file as it reads once the log has been replayed
+struct Replayed {
+    file: Arc<dyn Reader + Send + Sync>,
+    writes: Vec<LogWrite>,
+    size: Option<u64>,
+}
 
-        for i in 0..entry_count.min(2047) {
-            let entry_offset = 0x30000 + 16 + (i * 32);
-            let mut entry = [0u8; 32];
-            if parent.read_at(entry_offset as u64, &mut entry)? != 32 {
+impl Reader for Replayed {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let to_read = match self.size {
+            Some(size) if offset >= size => return Ok(0),
+            Some(size) => buf.len().min((size - offset) as usize),
+            None => buf.len(),
+        };
+        let buf = &mut buf[..to_read];
+        let mut n = 0;
+        while n < to_read {
+            let read = self.file.read_at(offset + n as u64, &mut buf[n..])?;
+            if read == 0 {
                 break;
             }
-
-            let guid = &entry[0..16];
-            let file_offset = u64::from_le_bytes([
-                entry[16], entry[17], entry[18], entry[19],
-                entry[20], entry[21], entry[22], entry[23],
-            ]);
-            let length = u32::from_le_bytes([entry[24], entry[25], entry[26], entry[27]]) as u64;
-
-            if guid == &BAT_GUID {
-                bat_offset = file_offset;
-                bat_length = length;
-            } else if guid == &METADATA_GUID {
-                metadata_offset = file_offset;
+            n += read;
+        }
+        // The log may extend the file
+        buf[n..].fill(0);
+
+        // Later writes land over earlier ones
+        let end = offset + to_read as u64;
+        for write in &self.writes {
+            let start = write.offset.max(offset);
+            let stop = (write.offset + write.length).min(end);
+            if start >= stop {
+                continue;
+            }
+            let target = &mut buf[(start - offset) as usize..(stop - offset) as usize];
+            match &write.data {
+                Some(data) => target.copy_from_slice(
+                    &data[(start - write.offset) as usize..(stop - write.offset) as usize],
+                ),
+                None => target.fill(0),
             }
         }
+        Ok(to_read)
+    }
 
-        if bat_offset == 0 || metadata_offset == 0 {
+    fn size(&self) -> Option<u64> {
+        self.size
+    }
+
+    fn sibling(&self, name: &str) -> io::Result<Arc<dyn Reader + Send + Sync>> {
+        self.file.sibling(name)
+    }
+
+    fn siblings(&self) -> Vec<String> {
+        self.file.siblings()
+    }
+}
+
+/// The metadata items used here
+struct Parameters {
+    virtual_size: u64,
+    block_size: u64,
+    has_parent: bool,
+    logical_sector_size: u32,
+    physical_sector_size: u32,
+    /// Parent locator key/value pairs
+    parent_locator: Vec<(String, String)>,
+}
+
+/// Reader that translates virtual disk offsets through VHDX BAT
+pub struct VhdxReader {
+    /// The file, with the log replayed
+    parent: Arc<dyn Reader + Send + Sync>,
+    bat: Vec<u64>,
+    block_size: u64,
+    virtual_size: u64,
+    logical_sector_size: u32,
+    physical_sector_size: u32,
+    /// Payload blocks per sector bitmap block
+    chunk_ratio: u64,
+    /// Parent paths from the parent locator, best first
+    parent_names: Option<Vec<String>>,
+    backing: Option<Backing>,
+}
+
+impl Layered for VhdxReader {
+    fn open_backing(&mut self, depth: usize) {
+        if let Some(names) = &self.parent_names {
+            self.backing = Some(backing::open(&*self.parent, names, Some("vhdx"), depth));
+        }
+    }
+
+    fn backing(&self) -> Option<&Backing> {
+        self.backing.as_ref()
+    }
+}
+
+impl VhdxReader {
+    pub fn new(parent: Arc<dyn Reader + Send + Sync>) -> io::Result<Self> {
+        // Check file signature
+        let mut sig = [0u8; 8];
+        if parent.read_at(0, &mut sig)? != 8 || &sig != VHDX_FILE_SIGNATURE {
             return Err(io::Error::new(
                 io::ErrorKind::InvalidData,
-                "missing BAT or metadata region",
+                "invalid VHDX signature",
             ));
         }
 
-        // Parse metadata to get virtual size and block size
-        let (virtual_size, block_size) = Self::parse_metadata(&*parent, metadata_offset)?;
+        // Replay the log, if any, before reading metadata and BAT through it
+        let header = current_header(&*parent)?;
+        let parent: Arc<dyn Reader + Send + Sync> = if header.log_guid != [0; 16] {
+            let writes = replay_log(&*parent, &header)?;
+            if writes.is_empty() {
+                parent
+            } else {
+                let end = writes.iter().map(|write| write.offset + write.length).max();
+                let size = parent.size().map(|size| size.max(end.unwrap_or(0)));
+                Arc::new(Replayed {
+                    file: parent,
+                    writes,
+                    size,
+                })
+            }
+        } else {
+            parent
+        };
+
+        let regions = read_regions(&*parent)?;
+        let region = |guid| regions.iter().find(|(id, _, _)| *id == guid);
+        let (Some(&(_, bat_offset, bat_length)), Some(&(_, metadata_offset, _))) =
+            (region(BAT_GUID), region(METADATA_GUID))
+        else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "missing BAT or metadata region",
+            ));
+        };
+
+        let parameters = Self::parse_metadata(&*parent, metadata_offset)?;
+        let block_size = parameters.block_size;
+
+        // A sector bitmap block follows every chunk_ratio payload blocks
+        let chunk_ratio = (CHUNK_SECTORS * u64::from(parameters.logical_sector_size)) / block_size;
 
         // Read BAT
-        let bat_entries = (bat_length / 8) as usize;
-        let mut bat_data = vec![0u8; bat_entries * 8];
+        let bat_bytes = checked_table_size(&*parent, bat_offset, bat_length / 8, 8)?;
+        let mut bat_data = vec![0u8; bat_bytes];
         let read_len = parent.read_at(bat_offset, &mut bat_data)?;
-        let actual_entries = read_len / 8;
-
-        let bat: Vec<u64> = bat_data[..actual_entries * 8]
-            .chunks_exact(8)
-            .map(|chunk| {
-                u64::from_le_bytes([
-                    chunk[0], chunk[1], chunk[2], chunk[3],
-                    chunk[4], chunk[5], chunk[6], chunk[7],
-                ])
-            })
-            .collect();
+        let bat: Vec<u64> = bat_data[..read_len / 8 * 8].chunks_exact(8).map(le64).collect();
+
+        let parent_names = parameters.has_parent.then(|| {
+            let value = |key: &str| {
+                parameters
+                    .parent_locator
+                    .iter()
+                    .find(|(k, _)| k == key)
+                    .map(|(_, value)| value.clone())
+            };
+            let mut names = Vec::new();
+            // ".\parent.vhdx" relative to the child
+            if let Some(path) = value("relative_path") {
+                let path = path.replace('\\', "/");
+                names.push(path.strip_prefix("./").map(str::to_string).unwrap_or(path));
+            }
+            names.extend(value("absolute_win32_path"));
+            names
+        });
 
         Ok(Self {
             parent,
             bat,
             block_size,
-            virtual_size,
+            virtual_size: parameters.virtual_size,
+            logical_sector_size: parameters.logical_sector_size,
+            physical_sector_size: parameters.physical_sector_size,
+            chunk_ratio,
+            parent_names,
+            backing: None,
         })
     }
 
-    fn parse_metadata(parent: &dyn Reader, metadata_offset: u64) -> io::Result<(u64, u64)> {
+    fn parse_metadata(parent: &dyn Reader, metadata_offset: u64) -> io::Result<Parameters> {
         // Metadata header: signature (8) + reserved (2) + entry_count (2)
         let mut header = [0u8; 32];
         if parent.read_at(metadata_offset, &mut header)? != 32 {
@@ -171,12 +561,21 @@ impl VhdxReader {
                 "short metadata header",
             ));
         }
+        if &header[..8] != VHDX_METADATA_SIGNATURE {
+            return Err(invalid_data("invalid VHDX metadata signature"));
+        }
 
         let entry_count =
             u16::from_le_bytes([header[10], header[11]]) as usize;
 
-        let mut virtual_size = 0u64;
-        let mut block_size = 0u32;
+        let mut parameters = Parameters {
+            virtual_size: 0,
+            block_size: 0,
+            has_parent: false,
+            logical_sector_size: 512,
+            physical_sector_size: 512,
+            parent_locator: Vec::new(),
+        };
 
         // Metadata entries start at offset 32, each is 32 bytes
         for i in 0..entry_count.min(2047) {
@@ -186,34 +585,110 @@ impl VhdxReader {
                 break;
             }
 
-            let guid = &entry[0..16];
+            let guid: [u8; 16] = entry[0..16].try_into().unwrap();
             let item_offset =
                 u32::from_le_bytes([entry[16], entry[17], entry[18], entry[19]]) as u64;
             let item_length =
                 u32::from_le_bytes([entry[20], entry[21], entry[22], entry[23]]) as usize;
-
-            if guid == &VIRTUAL_DISK_SIZE_GUID && item_length >= 8 {
-                let mut buf = [0u8; 8];
-                parent.read_at(metadata_offset + item_offset, &mut buf)?;
-                virtual_size = u64::from_le_bytes(buf);
-            } else if guid == &FILE_PARAMETERS_GUID && item_length >= 8 {
-                let mut buf = [0u8; 8];
-                parent.read_at(metadata_offset + item_offset, &mut buf)?;
-                block_size = u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]);
+            let flags = le32(&entry[24..]);
+            let item_at = metadata_offset + item_offset;
+
+            let mut buf = [0u8; 8];
+            match guid {
+                VIRTUAL_DISK_SIZE_GUID if item_length >= 8 => {
+                    parent.read_at(item_at, &mut buf)?;
+                    parameters.virtual_size = u64::from_le_bytes(buf);
+                }
+                FILE_PARAMETERS_GUID if item_length >= 8 => {
+                    parent.read_at(item_at, &mut buf)?;
+                    parameters.block_size = u64::from(le32(&buf));
+                    parameters.has_parent = le32(&buf[4..]) & FILE_PARAMETERS_HAS_PARENT != 0;
+                }
+                LOGICAL_SECTOR_SIZE_GUID if item_length >= 4 => {
+                    parent.read_at(item_at, &mut buf)?;
+                    parameters.logical_sector_size = le32(&buf);
+                }
+                PHYSICAL_SECTOR_SIZE_GUID if item_length >= 4 => {
+                    parent.read_at(item_at, &mut buf)?;
+                    parameters.physical_sector_size = le32(&buf);
+                }
+                PARENT_LOCATOR_GUID => {
+                    parameters.parent_locator = read_parent_locator(parent, item_at, item_length)?;
+                }
+                _ if flags & METADATA_IS_REQUIRED != 0 => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::Unsupported,
+                        "unsupported required VHDX metadata item",
+                    ));
+                }
+                _ => {}
             }
         }
 
-        if virtual_size == 0 || block_size == 0 {
+        if parameters.virtual_size == 0 || parameters.block_size == 0 {
             return Err(io::Error::new(
                 io::ErrorKind::InvalidData,
                 "missing virtual size or block size in metadata",
             ));
         }
+        // Blocks are 1-256 MB and sectors 512 or 4096 bytes, so every
+        // chunk holds a whole number of blocks
+        if !(1 << 20..=256 << 20).contains(&parameters.block_size)
+            || !parameters.block_size.is_power_of_two()
+            || !matches!(parameters.logical_sector_size, 512 | 4096)
+        {
+            return Err(invalid_data("invalid VHDX block or sector size"));
+        }
+
+        Ok(parameters)
+    }
 
-        Ok((virtual_size, block_size as u64))
+    /// Read from the parent disk, which has none for a dynamic disk
+    fn read_parent(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        backing::read_backing(self.backing(), offset, buf)
     }
 }
 
+/// Read a parent locator's key/value pairs (UTF-16LE)
+fn read_parent_locator(
+    parent: &dyn Reader,
+    offset: u64,
+    length: usize,
+) -> io::Result<Vec<(String, String)>> {
+    if !(20..=64 * 1024).contains(&length) {
+        return Err(invalid_data("invalid VHDX parent locator"));
+    }
+    let mut data = vec![0u8; length];
+    if parent.read_at(offset, &mut data)? != length {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "short parent locator read",
+        ));
+    }
+    if data[..16] != VHDX_PARENT_TYPE_GUID {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "unsupported VHDX parent locator type",
+        ));
+    }
+
+    let utf16 = |offset: u32, length: u16| -> Option<String> {
+        let bytes = data.get(offset as usize..offset as usize + length as usize)?;
+        let units: Vec<u16> = bytes.chunks_exact(2).map(le16).collect();
+        Some(String::from_utf16_lossy(&units))
+    };
+    let count = le16(&data[18..]) as usize;
+    let mut pairs = Vec::with_capacity(count);
+    for entry in data[20..].chunks_exact(12).take(count) {
+        let key = utf16(le32(entry), le16(&entry[8..]));
+        let value = utf16(le32(&entry[4..]), le16(&entry[10..]));
+        if let (Some(key), Some(value)) = (key, value) {
+            pairs.push((key, value));
+        }
+    }
+    Ok(pairs)
+}
+
 impl Reader for VhdxReader {
     fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
         if offset >= self.virtual_size {
@@ -221,7 +696,7 @@ impl Reader for VhdxReader {
         }
 
         // Calculate block index and offset within block
-        let block_idx = (offset / self.block_size) as usize;
+        let block_idx = offset / self.block_size;
         let in_block = offset % self.block_size;
 
         // How much can we read from this block?
@@ -232,25 +707,59 @@ impl Reader for VhdxReader {
             .min(remaining_in_block as usize)
             .min(remaining_in_disk as usize);
 
-        // Check bounds
-        if block_idx >= self.bat.len() {
-            buf[..to_read].fill(0);
-            return Ok(to_read);
-        }
-
-        let bat_entry = self.bat[block_idx];
+        // Payload entries are interleaved with a sector bitmap entry after
+        // every chunk_ratio of them
+        let chunk = block_idx / self.chunk_ratio;
+        let bat_entry = match self.bat.get((block_idx + chunk) as usize) {
+            Some(&entry) => entry,
+            None => return self.read_parent(offset, &mut buf[..to_read]),
+        };
         let state = bat_entry & BAT_STATE_MASK;
-
-        // Only FULLY_PRESENT (6) has data
-        if state != BAT_STATE_FULLY_PRESENT {
-            buf[..to_read].fill(0);
-            return Ok(to_read);
-        }
-
         // File offset from upper bits (already in bytes, 1MB aligned)
         let file_offset = bat_entry & BAT_FILE_OFF_MASK;
-        let physical_offset = file_offset + in_block;
-        self.parent.read_at(physical_offset, &mut buf[..to_read])
+
+        match state {
+            BAT_STATE_FULLY_PRESENT => {
+                self.parent.read_at(file_offset + in_block, &mut buf[..to_read])
+            }
+            BAT_STATE_PARTIALLY_PRESENT => {
+                // One bit per logical sector says whether this disk holds it
+                let sector_size = u64::from(self.logical_sector_size);
+                let to_read = to_read.min((sector_size - offset % sector_size) as usize);
+                let bitmap_entry = self
+                    .bat
+                    .get((chunk * (self.chunk_ratio + 1) + self.chunk_ratio) as usize)
+                    .copied()
+                    .unwrap_or(0);
+                if bitmap_entry & BAT_STATE_MASK != BAT_STATE_SB_PRESENT {
+                    return Err(invalid_data("missing VHDX sector bitmap"));
+                }
+                let sector = (offset - chunk * self.chunk_ratio * self.block_size) / sector_size;
+                let mut bits = [0u8; 1];
+                if self
+                    .parent
+                    .read_at((bitmap_entry & BAT_FILE_OFF_MASK) + sector / 8, &mut bits)?
+                    != 1
+                {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "short sector bitmap read",
+                    ));
+                }
+                if bits[0] & (1 << (sector % 8)) != 0 {
+                    self.parent.read_at(file_offset + in_block, &mut buf[..to_read])
+                } else {
+                    self.read_parent(offset, &mut buf[..to_read])
+                }
+            }
+            // Only a differencing disk's parent holds absent blocks
+            BAT_STATE_NOT_PRESENT => self.read_parent(offset, &mut buf[..to_read]),
+            // Zero, unmapped and undefined blocks
+            _ => {
+                buf[..to_read].fill(0);
+                Ok(to_read)
+            }
+        }
     }
 
     fn size(&self) -> Option<u64> {
@@ -261,3 +770,199 @@ impl Reader for VhdxReader {
 // SAFETY: VhdxReader only holds Arc and Vec, safe to send/share
 unsafe impl Send for VhdxReader {}
 unsafe impl Sync for VhdxReader {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::{BytesReader, FilesReader};
+
+    const MB: usize = 1 << 20;
+    const LOG_GUID: [u8; 16] = [7; 16];
+
+    fn put(out: &mut Vec<u8>, at: usize, bytes: &[u8]) {
+        if out.len() < at + bytes.len() {
+            out.resize(at + bytes.len(), 0);
+        }
+        out[at..at + bytes.len()].copy_from_slice(bytes);
+    }
+
+    fn set_checksum(data: &mut [u8]) {
+        let sum = crc32c(data);
+        data[4..8].copy_from_slice(&sum.to_le_bytes());
+    }
+
+    fn utf16(text: &str) -> Vec<u8> {
+        text.encode_utf16().flat_map(u16::to_le_bytes).collect()
+    }
+
+    fn parent_locator(pairs: &[(&str, &str)]) -> Vec<u8> {
+        let mut out = VHDX_PARENT_TYPE_GUID.to_vec();
+        out.extend_from_slice(&[0, 0]);
+        out.extend_from_slice(&(pairs.len() as u16).to_le_bytes());
+        let mut strings = 20 + 12 * pairs.len();
+        let mut tail = Vec::new();
+        for (key, value) in pairs {
+            let (key, value) = (utf16(key), utf16(value));
+            out.extend_from_slice(&(strings as u32).to_le_bytes());
+            out.extend_from_slice(&((strings + key.len()) as u32).to_le_bytes());
+            out.extend_from_slice(&(key.len() as u16).to_le_bytes());
+            out.extend_from_slice(&(value.len() as u16).to_le_bytes());
+            strings += key.len() + value.len();
+            tail.extend(key);
+            tail.extend(value);
+        }
+        out.extend(tail);
+        out
+    }
+
+    /// A 2 MB disk of 1 MB blocks: metadata at 1 MB, BAT at 2 MB, a log
+    /// at 3 MB if `log_guid` is set, and `blocks` at their offsets
+    fn image(log_guid: [u8; 16], bat: &[u64], locator: Option<Vec<u8>>, blocks: &[(usize, u8)]) -> Vec<u8> {
+        let mut out = b"vhdxfile".to_vec();
+
+        let mut header = vec![0u8; HEADER_SIZE];
+        header[..4].copy_from_slice(VHDX_HEADER_SIGNATURE);
+        header[8..16].copy_from_slice(&1u64.to_le_bytes());
+        header[48..64].copy_from_slice(&log_guid);
+        header[66..68].copy_from_slice(&1u16.to_le_bytes());
+        header[68..72].copy_from_slice(&(MB as u32).to_le_bytes());
+        header[72..80].copy_from_slice(&(3 * MB as u64).to_le_bytes());
+        set_checksum(&mut header);
+        put(&mut out, HEADER_OFFSETS[0] as usize, &header);
+
+        let mut regions = vec![0u8; REGION_TABLE_SIZE];
+        regions[..4].copy_from_slice(&VHDX_REGION_SIGNATURE.to_le_bytes());
+        regions[8..12].copy_from_slice(&2u32.to_le_bytes());
+        for (i, (guid, offset)) in [(BAT_GUID, 2 * MB), (METADATA_GUID, MB)].iter().enumerate() {
+            let entry = &mut regions[16 + i * 32..][..32];
+            entry[..16].copy_from_slice(guid);
+            entry[16..24].copy_from_slice(&(*offset as u64).to_le_bytes());
+            entry[24..28].copy_from_slice(&(MB as u32).to_le_bytes());
+            entry[28..32].copy_from_slice(&REGION_REQUIRED.to_le_bytes());
+        }
+        set_checksum(&mut regions);
+        put(&mut out, REGION_TABLE_OFFSETS[0] as usize, &regions);
+
+        let flags = if locator.is_some() { FILE_PARAMETERS_HAS_PARENT } else { 0 };
+        let mut parameters = (MB as u32).to_le_bytes().to_vec();
+        parameters.extend_from_slice(&flags.to_le_bytes());
+        let mut items = vec![
+            (FILE_PARAMETERS_GUID, parameters),
+            (VIRTUAL_DISK_SIZE_GUID, (2 * MB as u64).to_le_bytes().to_vec()),
+            (LOGICAL_SECTOR_SIZE_GUID, 512u32.to_le_bytes().to_vec()),
+            (PHYSICAL_SECTOR_SIZE_GUID, 4096u32.to_le_bytes().to_vec()),
+        ];
+        items.extend(locator.map(|locator| (PARENT_LOCATOR_GUID, locator)));
+        put(&mut out, MB, VHDX_METADATA_SIGNATURE);
+        put(&mut out, MB + 10, &(items.len() as u16).to_le_bytes());
+        let mut item_offset = 0x10000;
+        for (i, (guid, data)) in items.iter().enumerate() {
+            let mut entry = guid.to_vec();
+            entry.extend_from_slice(&(item_offset as u32).to_le_bytes());
+            entry.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            entry.extend_from_slice(&METADATA_IS_REQUIRED.to_le_bytes());
+            put(&mut out, MB + 32 + i * 32, &entry);
+            put(&mut out, MB + item_offset, data);
+            item_offset += data.len().next_multiple_of(8);
+        }
+
+        let bat: Vec<u8> = bat.iter().flat_map(|entry| entry.to_le_bytes()).collect();
+        put(&mut out, 2 * MB, &bat);
+        for &(offset, fill) in blocks {
+            put(&mut out, offset, &vec![fill; MB]);
+        }
+        out.resize(out.len().max(3 * MB), 0);
+        out
+    }
+
+    /// A log entry writing one 4 KB sector of `fill` at `file_offset`
+    fn log_entry(sequence: u64, file_offset: u64, fill: u8) -> Vec<u8> {
+        let mut entry = vec![0u8; 2 * LOG_SECTOR as usize];
+        entry[..4].copy_from_slice(VHDX_LOG_SIGNATURE);
+        entry[8..12].copy_from_slice(&(2 * LOG_SECTOR as u32).to_le_bytes());
+        entry[16..24].copy_from_slice(&sequence.to_le_bytes());
+        entry[24..28].copy_from_slice(&1u32.to_le_bytes());
+        entry[32..48].copy_from_slice(&LOG_GUID);
+
+        let descriptor = &mut entry[64..96];
+        descriptor[..4].copy_from_slice(VHDX_DATA_DESCRIPTOR);
+        descriptor[4..16].fill(fill);
+        descriptor[16..24].copy_from_slice(&file_offset.to_le_bytes());
+        descriptor[24..32].copy_from_slice(&sequence.to_le_bytes());
+
+        let sector = &mut entry[LOG_SECTOR as usize..];
+        sector[..4].copy_from_slice(VHDX_DATA_SECTOR);
+        sector[4..8].copy_from_slice(&((sequence >> 32) as u32).to_le_bytes());
+        sector[8..4092].fill(fill);
+        sector[4092..].copy_from_slice(&(sequence as u32).to_le_bytes());
+        set_checksum(&mut entry);
+        entry
+    }
+
+    fn read(reader: &dyn Reader, offset: u64) -> [u8; 4] {
+        let mut buf = [0xffu8; 4];
+        reader.read_at(offset, &mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn crc32c_check_value() {
+        assert_eq!(crc32c(b"123456789"), 0xE3069283);
+    }
+
+    #[test]
+    fn replays_log() {
+        let present = (4 * MB as u64) | BAT_STATE_FULLY_PRESENT;
+        let mut image = image(LOG_GUID, &[present], None, &[(4 * MB, b'A')]);
+        put(&mut image, 3 * MB, &log_entry(5, 4 * MB as u64, b'L'));
+
+        let reader = VhdxReader::new(Arc::new(BytesReader::new(image.clone()))).unwrap();
+        assert_eq!(&read(&reader, 0), b"LLLL");
+        assert_eq!(&read(&reader, 4092), b"LLLL");
+        assert_eq!(&read(&reader, 4096), b"AAAA");
+
+        // An entry failing its checksum isn't replayed
+        image[3 * MB + 5000] ^= 1;
+        let reader = VhdxReader::new(Arc::new(BytesReader::new(image))).unwrap();
+        assert_eq!(&read(&reader, 0), b"AAAA");
+    }
+
+    #[test]
+    fn reads_through_to_parent() {
+        // Block 0 holds only its first sector; block 1 is in the parent
+        let mut bat = vec![0u64; 4097];
+        bat[0] = (4 * MB as u64) | BAT_STATE_PARTIALLY_PRESENT;
+        bat[1] = BAT_STATE_NOT_PRESENT;
+        bat[4096] = (5 * MB as u64) | BAT_STATE_SB_PRESENT;
+        let locator = parent_locator(&[
+            ("parent_linkage", "{00000000-0000-0000-0000-000000000000}"),
+            ("relative_path", r".\base.vhdx"),
+        ]);
+        let mut child = image([0; 16], &bat, Some(locator), &[(4 * MB, b'C')]);
+        put(&mut child, 5 * MB, &[1]);
+
+        let parent_bat = [
+            (4 * MB as u64) | BAT_STATE_FULLY_PRESENT,
+            (5 * MB as u64) | BAT_STATE_FULLY_PRESENT,
+        ];
+        let parent = image([0; 16], &parent_bat, None, &[(4 * MB, b'P'), (5 * MB, b'Q')]);
+
+        let files = vec![("disk.avhdx", child), ("base.vhdx", parent)];
+        let children = VHDX
+            .children(FilesReader::open("disk.avhdx", files))
+            .unwrap();
+        assert_eq!(
+            children[0].metadata,
+            [
+                ("logical_sector_size", "512".to_string()),
+                ("physical_sector_size", "4096".to_string()),
+                ("backing", "base.vhdx".to_string()),
+            ]
+        );
+
+        let disk = &children[0].reader;
+        assert_eq!(&read(&**disk, 0), b"CCCC");
+        assert_eq!(&read(&**disk, 512), b"PPPP");
+        assert_eq!(&read(&**disk, MB as u64), b"QQQQ");
+    }
+}
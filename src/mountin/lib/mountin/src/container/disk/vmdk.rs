@@ -5,7 +5,18 @@
 //! - VMDK4 (KDMV) - modern sparse
 //! - VMDK4 stream-optimized - compressed with deflate
 //! - seSparse - ESXi sparse format
+//!
+//! A text descriptor, standalone or embedded in a sparse file, lists the
+//! extents making up the disk (FLAT, SPARSE, ZERO, ...), which are opened
+//! as siblings of the descriptor and joined into one virtual disk. A
+//! `parentFileNameHint` names the parent of a delta disk: grains the delta
+//! hasn't allocated read through to it.
+//!
+//! The extents of a split sparse disk (`disk-s001.vmdk`, `disk-s002.vmdk`,
+//! ...) are also joined when the first is opened without its descriptor.
 
+use crate::container::disk::backing::{self, Backing, Layered};
+use crate::container::split::following_parts;
 use crate::container::{checked_table_size, invalid_data, Child, Container};
 use crate::detect::Reader;
 use flate2::read::DeflateDecoder;
@@ -20,6 +31,11 @@ const VMDK4_FLAG_COMPRESS: u32 = 0x10000;
 const VMDK4_FLAG_ZERO_GRAIN: u32 = 0x4;
 const VMDK4_GTE_ZEROED: u32 = 1;
 
+/// Largest descriptor read, standalone or embedded
+const MAX_DESCRIPTOR_SIZE: u64 = 1024 * 1024;
+/// parentCID of a disk without a parent
+const NO_PARENT_CID: &str = "ffffffff";
+
 /// VMDK disk image container
 pub struct VmdkContainer;
 
@@ -28,16 +44,306 @@ pub static VMDK: VmdkContainer = VmdkContainer;
 
 impl Container for VmdkContainer {
     fn children(&self, reader: Arc<dyn Reader + Send + Sync>) -> io::Result<Vec<Child>> {
-        let vmdk_reader = VmdkReader::new(reader)?;
+        let mut disk = VmdkDisk::open(reader)?;
+        disk.open_backing(0);
+        let metadata = disk.chain_metadata();
 
         Ok(vec![Child {
             index: 0,
             offset: 0,
-            reader: Arc::new(vmdk_reader),
+            reader: Arc::new(disk),
+            metadata,
         }])
     }
 }
 
+/// Name of extent `n` of a split sparse disk whose first extent is `first`
+fn extent_name(first: &str, n: u32) -> Option<String> {
+    let stem = first.strip_suffix("-s001.vmdk")?;
+    (n < 1000).then(|| format!("{stem}-s{n:03}.vmdk"))
+}
+
+/// How an extent's data is stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ExtentKind {
+    /// Raw data at an offset in the file (FLAT, VMFS, VMFSRAW, VMFSRDM)
+    Flat,
+    /// A sparse file (SPARSE, VMFSSPARSE, SESPARSE)
+    Sparse,
+    /// No file; reads as zeros
+    Zero,
+}
+
+/// An extent line of a descriptor
+#[derive(Debug, PartialEq, Eq)]
+struct ExtentLine {
+    sectors: u64,
+    kind: ExtentKind,
+    file: Option<String>,
+    /// Start of the data within a flat file, in sectors
+    offset: u64,
+}
+
+/// The parts of a descriptor needed to assemble the disk
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Descriptor {
+    extents: Vec<ExtentLine>,
+    parent_hint: Option<String>,
+}
+
+/// Parse a text descriptor, which needs at least one extent unless
+/// `embedded` in a sparse file, where the extents are the file itself
+fn parse_descriptor(text: &str, embedded: bool) -> io::Result<Descriptor> {
+    let mut descriptor = Descriptor::default();
+    let mut parent_cid = None;
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        // RW 41943040 SPARSE "disk.vmdk" [offset]
+        if let Some(rest) = ["RW ", "RDONLY ", "NOACCESS "]
+            .iter()
+            .find_map(|access| line.strip_prefix(access))
+        {
+            let mut fields = rest.trim_start().splitn(2, char::is_whitespace);
+            let sectors = fields
+                .next()
+                .and_then(|sectors| sectors.parse::<u64>().ok())
+                .ok_or_else(|| invalid_data("invalid VMDK extent size"))?;
+            let rest = fields.next().unwrap_or("").trim_start();
+            let (kind, rest) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+            let kind = match kind {
+                "FLAT" | "VMFS" | "VMFSRAW" | "VMFSRDM" => ExtentKind::Flat,
+                "SPARSE" | "VMFSSPARSE" | "SESPARSE" => ExtentKind::Sparse,
+                "ZERO" => ExtentKind::Zero,
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::Unsupported,
+                        "unsupported VMDK extent type",
+                    ))
+                }
+            };
+            let rest = rest.trim_start();
+            let (file, rest) = match rest.strip_prefix('"') {
+                Some(quoted) => {
+                    let (file, rest) = quoted
+                        .split_once('"')
+                        .ok_or_else(|| invalid_data("unterminated VMDK extent file name"))?;
+                    (Some(file.to_string()), rest.trim())
+                }
+                None => (None, rest),
+            };
+            if file.is_none() && kind != ExtentKind::Zero {
+                return Err(invalid_data("VMDK extent without a file"));
+            }
+            let offset = match rest.split_whitespace().next() {
+                Some(offset) => offset
+                    .parse()
+                    .map_err(|_| invalid_data("invalid VMDK extent offset"))?,
+                None => 0,
+            };
+            descriptor.extents.push(ExtentLine {
+                sectors,
+                kind,
+                file,
+                offset,
+            });
+            continue;
+        }
+
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        let value = value.trim().trim_matches('"');
+        match key.trim() {
+            "parentFileNameHint" if !value.is_empty() => {
+                descriptor.parent_hint = Some(value.to_string());
+            }
+            "parentCID" => parent_cid = Some(value.to_ascii_lowercase()),
+            _ => {}
+        }
+    }
+
+    // A parentCID of ffffffff means the hint is stale
+    if parent_cid.as_deref() == Some(NO_PARENT_CID) {
+        descriptor.parent_hint = None;
+    }
+    if descriptor.extents.is_empty() && !embedded {
+        return Err(invalid_data("VMDK descriptor has no extents"));
+    }
+    Ok(descriptor)
+}
+
+/// Read a descriptor of `size` bytes at `offset`, up to its first NUL
+fn read_descriptor(reader: &dyn Reader, offset: u64, size: u64) -> io::Result<String> {
+    if size > MAX_DESCRIPTOR_SIZE {
+        return Err(invalid_data("VMDK descriptor too large"));
+    }
+    let mut data = vec![0u8; size as usize];
+    let n = reader.read_at(offset, &mut data)?;
+    data.truncate(n);
+    if let Some(end) = data.iter().position(|&b| b == 0) {
+        data.truncate(end);
+    }
+    Ok(String::from_utf8_lossy(&data).into_owned())
+}
+
+/// One extent of an assembled disk
+enum DiskExtent {
+    Flat {
+        file: Arc<dyn Reader + Send + Sync>,
+        /// Start of the data within the file
+        offset: u64,
+    },
+    Sparse(VmdkReader),
+    Zero,
+}
+
+/// A whole virtual disk: extents joined end to end, reading through to
+/// the parent disk where sparse extents are unallocated
+pub struct VmdkDisk {
+    /// The descriptor or sparse file, which extents and parents are
+    /// siblings of
+    file: Arc<dyn Reader + Send + Sync>,
+    extents: Vec<DiskExtent>,
+    /// Offset of each extent within the disk
+    starts: Vec<u64>,
+    size: u64,
+    parent_hint: Option<String>,
+    backing: Option<Backing>,
+}
+
+impl VmdkDisk {
+    /// Open the disk in `file`, a text descriptor or a sparse extent
+    pub fn open(file: Arc<dyn Reader + Send + Sync>) -> io::Result<Self> {
+        let mut magic = [0u8; 8];
+        let n = file.read_at(0, &mut magic)?;
+        let binary = n >= 4
+            && (magic[..4] == VMDK3_MAGIC.to_le_bytes()
+                || magic[..4] == VMDK4_MAGIC.to_le_bytes()
+                || (n == 8 && u64::from_le_bytes(magic) == SESPARSE_MAGIC));
+
+        let mut extents = Vec::new();
+        let parent_hint;
+        if binary {
+            let sparse = VmdkReader::new(Arc::clone(&file))?;
+            let sectors = sparse.virtual_size / 512;
+            parent_hint = match sparse.embedded_descriptor()? {
+                Some(text) => parse_descriptor(&text, true)?.parent_hint,
+                None => None,
+            };
+            extents.push((sectors, DiskExtent::Sparse(sparse)));
+
+            // A split sparse extent opened on its own
+            let name = file
+                .path()
+                .and_then(|path| path.file_name())
+                .and_then(|name| name.to_str());
+            if let Some(name) = name.filter(|name| extent_name(name, 1).is_some()) {
+                for part in following_parts(&*file, |n| extent_name(name, n)) {
+                    let sparse = VmdkReader::new(part)?;
+                    extents.push((sparse.virtual_size / 512, DiskExtent::Sparse(sparse)));
+                }
+            }
+        } else {
+            let size = file.size().unwrap_or(MAX_DESCRIPTOR_SIZE);
+            let descriptor = parse_descriptor(&read_descriptor(&*file, 0, size)?, false)?;
+            for line in descriptor.extents {
+                let extent = match (line.kind, &line.file) {
+                    (ExtentKind::Zero, _) | (_, None) => DiskExtent::Zero,
+                    (kind, Some(name)) => {
+                        let extent = file.sibling(name).map_err(|error| {
+                            io::Error::new(error.kind(), "missing VMDK extent file")
+                        })?;
+                        match kind {
+                            ExtentKind::Sparse => DiskExtent::Sparse(VmdkReader::new(extent)?),
+                            _ => DiskExtent::Flat {
+                                file: extent,
+                                offset: line
+                                    .offset
+                                    .checked_mul(512)
+                                    .ok_or_else(|| invalid_data("VMDK extent offset overflow"))?,
+                            },
+                        }
+                    }
+                };
+                extents.push((line.sectors, extent));
+            }
+            parent_hint = descriptor.parent_hint;
+        }
+
+        let mut starts = Vec::with_capacity(extents.len());
+        let mut size = 0u64;
+        for (sectors, _) in &extents {
+            starts.push(size);
+            size = sectors
+                .checked_mul(512)
+                .and_then(|len| size.checked_add(len))
+                .ok_or_else(|| invalid_data("VMDK disk size overflow"))?;
+        }
+
+        Ok(Self {
+            file,
+            extents: extents.into_iter().map(|(_, extent)| extent).collect(),
+            starts,
+            size,
+            parent_hint,
+            backing: None,
+        })
+    }
+}
+
+impl Layered for VmdkDisk {
+    fn open_backing(&mut self, depth: usize) {
+        if let Some(name) = &self.parent_hint {
+            let names = std::slice::from_ref(name);
+            self.backing = Some(backing::open(&*self.file, names, Some("vmdk"), depth));
+        }
+    }
+
+    fn backing(&self) -> Option<&Backing> {
+        self.backing.as_ref()
+    }
+}
+
+impl Reader for VmdkDisk {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        if offset >= self.size || buf.is_empty() {
+            return Ok(0);
+        }
+        // Last extent starting at or before offset; empty ones are skipped
+        let index = self.starts.partition_point(|&start| start <= offset) - 1;
+        let start = self.starts[index];
+        let end = self.starts.get(index + 1).copied().unwrap_or(self.size);
+        let to_read = buf.len().min((end - offset) as usize);
+        let buf = &mut buf[..to_read];
+        let within = offset - start;
+
+        match &self.extents[index] {
+            DiskExtent::Flat { file, offset } => {
+                let n = file.read_at(offset + within, buf)?;
+                // Flat files may stop short of the extent size
+                buf[n..].fill(0);
+                Ok(to_read)
+            }
+            DiskExtent::Sparse(sparse) if within < sparse.virtual_size => {
+                sparse.read_grain(within, buf, self.backing(), start)
+            }
+            DiskExtent::Sparse(_) => backing::read_backing(self.backing(), offset, buf),
+            DiskExtent::Zero => {
+                buf.fill(0);
+                Ok(to_read)
+            }
+        }
+    }
+
+    fn size(&self) -> Option<u64> {
+        Some(self.size)
+    }
+}
+
 /// VMDK variant-specific data
 enum VmdkVariant {
     /// VMDK3 (COWD) - single-level lookup
@@ -281,6 +587,32 @@ impl VmdkReader {
         })
     }
 
+    /// The descriptor embedded in a VMDK4 sparse file, if any
+    fn embedded_descriptor(&self) -> io::Result<Option<String>> {
+        if !matches!(
+            self.variant,
+            VmdkVariant::Vmdk4 { .. } | VmdkVariant::Vmdk4Compressed { .. }
+        ) {
+            return Ok(None);
+        }
+        let mut fields = [0u8; 16];
+        if self.parent.read_at(0x1c, &mut fields)? != 16 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "short VMDK4 header",
+            ));
+        }
+        let offset = u64::from_le_bytes(fields[..8].try_into().unwrap());
+        let size = u64::from_le_bytes(fields[8..].try_into().unwrap());
+        if offset == 0 || size == 0 {
+            return Ok(None);
+        }
+        let (Some(offset), Some(size)) = (offset.checked_mul(512), size.checked_mul(512)) else {
+            return Err(invalid_data("VMDK4 descriptor offset overflow"));
+        };
+        read_descriptor(&*self.parent, offset, size).map(Some)
+    }
+
     fn read_gt_entry(&self, gt_offset: u64, gt_index: u64) -> io::Result<u32> {
         let mut buf = [0u8; 4];
         let offset = gt_offset + gt_index * 4;
@@ -332,8 +664,16 @@ impl VmdkReader {
     }
 }
 
-impl Reader for VmdkReader {
-    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+impl VmdkReader {
+    /// Read within one grain; unallocated grains read from `backing`
+    /// (zeros without one), where this extent starts at `base`
+    fn read_grain(
+        &self,
+        offset: u64,
+        buf: &mut [u8],
+        backing: Option<&Backing>,
+        base: u64,
+    ) -> io::Result<usize> {
         if offset >= self.virtual_size {
             return Ok(0);
         }
@@ -352,14 +692,12 @@ impl Reader for VmdkReader {
             VmdkVariant::Vmdk3 { l1_table } => {
                 // Single-level lookup
                 if grain_idx as usize >= l1_table.len() {
-                    buf[..to_read].fill(0);
-                    return Ok(to_read);
+                    return backing::read_backing(backing, base + offset, &mut buf[..to_read]);
                 }
 
                 let grain_sector = l1_table[grain_idx as usize];
                 if grain_sector == 0 {
-                    buf[..to_read].fill(0);
-                    return Ok(to_read);
+                    return backing::read_backing(backing, base + offset, &mut buf[..to_read]);
                 }
 
                 let physical = grain_sector as u64 * 512 + in_grain;
@@ -376,21 +714,22 @@ impl Reader for VmdkReader {
                 let gt_idx = grain_idx % *num_gtes_per_gt as u64;
 
                 if gd_idx as usize >= gd.len() {
-                    buf[..to_read].fill(0);
-                    return Ok(to_read);
+                    return backing::read_backing(backing, base + offset, &mut buf[..to_read]);
                 }
 
                 let gt_sector = gd[gd_idx as usize];
                 if gt_sector == 0 {
-                    buf[..to_read].fill(0);
-                    return Ok(to_read);
+                    return backing::read_backing(backing, base + offset, &mut buf[..to_read]);
                 }
 
                 let grain_sector = self.read_gt_entry(gt_sector as u64 * 512, gt_idx)?;
-                if grain_sector == 0 || (*has_zero_grain && grain_sector == VMDK4_GTE_ZEROED) {
+                if *has_zero_grain && grain_sector == VMDK4_GTE_ZEROED {
                     buf[..to_read].fill(0);
                     return Ok(to_read);
                 }
+                if grain_sector == 0 {
+                    return backing::read_backing(backing, base + offset, &mut buf[..to_read]);
+                }
 
                 let physical = grain_sector as u64 * 512 + in_grain;
                 self.parent.read_at(physical, &mut buf[..to_read])
@@ -401,20 +740,17 @@ impl Reader for VmdkReader {
                 let gt_idx = grain_idx % *num_gtes_per_gt as u64;
 
                 if gd_idx as usize >= gd.len() {
-                    buf[..to_read].fill(0);
-                    return Ok(to_read);
+                    return backing::read_backing(backing, base + offset, &mut buf[..to_read]);
                 }
 
                 let gt_sector = gd[gd_idx as usize];
                 if gt_sector == 0 {
-                    buf[..to_read].fill(0);
-                    return Ok(to_read);
+                    return backing::read_backing(backing, base + offset, &mut buf[..to_read]);
                 }
 
                 let grain_sector = self.read_gt_entry(gt_sector as u64 * 512, gt_idx)?;
                 if grain_sector == 0 {
-                    buf[..to_read].fill(0);
-                    return Ok(to_read);
+                    return backing::read_backing(backing, base + offset, &mut buf[..to_read]);
                 }
 
                 let decompressed = self.read_compressed_grain(grain_sector as u64)?;
@@ -428,8 +764,7 @@ impl Reader for VmdkReader {
                 let gt_idx = grain_idx % *gt_size;
 
                 if gd_idx as usize >= gd.len() {
-                    buf[..to_read].fill(0);
-                    return Ok(to_read);
+                    return backing::read_backing(backing, base + offset, &mut buf[..to_read]);
                 }
 
                 let gt_offset = gd[gd_idx as usize];
@@ -443,14 +778,17 @@ impl Reader for VmdkReader {
 
                 let gt_addr = gt_offset & 0x0FFFFFFFFFFFFFFF;
                 if gt_addr == 0 {
-                    buf[..to_read].fill(0);
-                    return Ok(to_read);
+                    return backing::read_backing(backing, base + offset, &mut buf[..to_read]);
                 }
 
                 let grain_entry = self.read_gt_entry_64(gt_addr, gt_idx)?;
                 let grain_state = (grain_entry >> 60) & 0xF;
-                if grain_state != 3 {
+                if grain_state == 0 {
                     // Not allocated
+                    return backing::read_backing(backing, base + offset, &mut buf[..to_read]);
+                }
+                if grain_state != 3 {
+                    // Unmapped or zero
                     buf[..to_read].fill(0);
                     return Ok(to_read);
                 }
@@ -461,6 +799,12 @@ impl Reader for VmdkReader {
             }
         }
     }
+}
+
+impl Reader for VmdkReader {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        self.read_grain(offset, buf, None, 0)
+    }
 
     fn size(&self) -> Option<u64> {
         Some(self.virtual_size)
@@ -474,7 +818,7 @@ unsafe impl Sync for VmdkReader {}
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::container::BytesReader;
+    use crate::container::{BytesReader, FilesReader};
 
     fn vmdk3_header(granularity: u32) -> Vec<u8> {
         let mut data = vec![0u8; 48];
@@ -509,4 +853,125 @@ mod tests {
         let image = Arc::new(BytesReader::new(vmdk4_header(128, 0)));
         assert!(VmdkReader::new(image).is_err());
     }
+
+    fn open(name: &str, files: Vec<(&'static str, Vec<u8>)>) -> Vec<Child> {
+        VMDK.children(FilesReader::open(name, files)).unwrap()
+    }
+
+    /// A two-grain (8 KiB) sparse extent with only the first grain written
+    fn sparse_extent(fill: u8) -> Vec<u8> {
+        let mut data = vmdk4_header(8, 512);
+        data[12..20].copy_from_slice(&16u64.to_le_bytes());
+        data[56..64].copy_from_slice(&1u64.to_le_bytes());
+        data.resize(6 * 512, 0);
+        data[512..516].copy_from_slice(&2u32.to_le_bytes());
+        data[1024..1028].copy_from_slice(&6u32.to_le_bytes());
+        data.extend_from_slice(&[fill; 4096]);
+        data
+    }
+
+    #[test]
+    fn parses_descriptor() {
+        let text = r#"# Disk DescriptorFile
+version=1
+CID=12345678
+parentCID=9abcdef0
+createType="twoGbMaxExtentFlat"
+parentFileNameHint="base disk.vmdk"
+
+# Extent description
+RW 8 FLAT "my disk-f001.vmdk" 0
+RDONLY 16 FLAT "my disk-f002.vmdk" 4
+RW 4 ZERO
+"#;
+        let descriptor = parse_descriptor(text, false).unwrap();
+        assert_eq!(descriptor.parent_hint.as_deref(), Some("base disk.vmdk"));
+        assert_eq!(
+            descriptor.extents,
+            [
+                ExtentLine {
+                    sectors: 8,
+                    kind: ExtentKind::Flat,
+                    file: Some("my disk-f001.vmdk".to_string()),
+                    offset: 0,
+                },
+                ExtentLine {
+                    sectors: 16,
+                    kind: ExtentKind::Flat,
+                    file: Some("my disk-f002.vmdk".to_string()),
+                    offset: 4,
+                },
+                ExtentLine {
+                    sectors: 4,
+                    kind: ExtentKind::Zero,
+                    file: None,
+                    offset: 0,
+                },
+            ]
+        );
+
+        let orphan = "parentCID=ffffffff\nparentFileNameHint=\"old.vmdk\"\nRW 1 ZERO\n";
+        assert_eq!(parse_descriptor(orphan, false).unwrap().parent_hint, None);
+        assert!(parse_descriptor("version=1\n", false).is_err());
+        assert!(parse_descriptor("RW 1 VSAN \"x\"\n", false).is_err());
+    }
+
+    #[test]
+    fn joins_descriptor_extents() {
+        let descriptor = b"# Disk DescriptorFile\n\
+            RW 1 FLAT \"disk-flat.vmdk\" 1\n\
+            RW 1 ZERO\n\
+            RW 16 SPARSE \"disk-s001.vmdk\"\n"
+            .to_vec();
+        let mut flat = vec![b'A'; 512];
+        flat.extend_from_slice(&[b'B'; 512]);
+        let children = open(
+            "disk.vmdk",
+            vec![
+                ("disk.vmdk", descriptor),
+                ("disk-flat.vmdk", flat),
+                ("disk-s001.vmdk", sparse_extent(b'S')),
+            ],
+        );
+        let disk = &children[0].reader;
+        assert_eq!(disk.size(), Some(18 * 512));
+
+        let mut buf = [0u8; 4];
+        for (offset, expected) in [(0, b'B'), (512, 0), (1024, b'S'), (1024 + 4096, 0)] {
+            disk.read_at(offset, &mut buf).unwrap();
+            assert_eq!(buf, [expected; 4]);
+        }
+    }
+
+    #[test]
+    fn reads_through_to_parent() {
+        // A monolithicSparse delta with its descriptor after the grain
+        let mut child = sparse_extent(b'D');
+        child[0x1c..0x24].copy_from_slice(&14u64.to_le_bytes());
+        child[0x24..0x2c].copy_from_slice(&1u64.to_le_bytes());
+        let mut descriptor = b"# Disk DescriptorFile\n\
+            parentCID=00000001\n\
+            parentFileNameHint=\"base.vmdk\"\n\
+            RW 16 SPARSE \"delta.vmdk\"\n"
+            .to_vec();
+        descriptor.resize(512, 0);
+        child.extend_from_slice(&descriptor);
+
+        let base = b"# Disk DescriptorFile\nRW 16 FLAT \"base-flat.vmdk\" 0\n".to_vec();
+        let children = open(
+            "delta.vmdk",
+            vec![
+                ("delta.vmdk", child),
+                ("base.vmdk", base),
+                ("base-flat.vmdk", vec![b'P'; 16 * 512]),
+            ],
+        );
+        assert_eq!(children[0].metadata, [("backing", "base.vmdk".to_string())]);
+
+        let mut buf = [0u8; 4];
+        children[0].reader.read_at(0, &mut buf).unwrap();
+        assert_eq!(&buf, b"DDDD");
+        children[0].reader.read_at(4096, &mut buf).unwrap();
+        assert_eq!(&buf, b"PPPP");
+    }
 }
@@ -5,24 +5,111 @@
 //! children and provide Reader access to each.
 
 pub mod arc;
+pub mod block;
+pub(crate) mod digest;
 pub mod disk;
 pub mod pt;
 pub mod slice;
+pub mod split;
 
 use crate::detect::Reader;
+use std::collections::VecDeque;
 use std::io::{self, Read, Write};
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
 
 /// Maximum size for reading container contents into memory (1 GB)
-const MAX_SIZE: usize = 1024 * 1024 * 1024;
+pub(crate) const MAX_SIZE: usize = 1024 * 1024 * 1024;
 
 /// Maximum amount of image metadata read into memory at once.
 const MAX_TABLE_SIZE: u64 = 64 * 1024 * 1024;
 
+/// Whether containers may write seek indexes beside the files they read
+static PERSIST_INDEXES: AtomicBool = AtomicBool::new(false);
+
+/// Enable or disable persisting seek indexes (e.g. `disk.img.gz.zran`)
+pub fn set_persist_indexes(enabled: bool) {
+    PERSIST_INDEXES.store(enabled, Ordering::Relaxed);
+}
+
+pub(crate) fn persist_indexes() -> bool {
+    PERSIST_INDEXES.load(Ordering::Relaxed)
+}
+
+/// Whether containers check images against the hashes stored in them
+static VERIFY_HASHES: AtomicBool = AtomicBool::new(false);
+
+/// Enable or disable reading whole images to check their stored hashes
+pub fn set_verify_hashes(enabled: bool) {
+    VERIFY_HASHES.store(enabled, Ordering::Relaxed);
+}
+
+pub(crate) fn verify_hashes() -> bool {
+    VERIFY_HASHES.load(Ordering::Relaxed)
+}
+
+/// `seconds` since 1970 as an ISO 8601 UTC date and time
+pub(crate) fn utc_date(seconds: u64) -> String {
+    let (days, time) = (seconds / 86400, seconds % 86400);
+    // Civil date from day count (Howard Hinnant's algorithm)
+    let z = days as i64 + 719468;
+    let era = z / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
+
 pub(crate) fn invalid_data(message: &'static str) -> io::Error {
     io::Error::new(io::ErrorKind::InvalidData, message)
 }
 
+/// Fill `buf` from `offset`, failing if the image ends first
+pub(crate) fn read_exact_at(reader: &dyn Reader, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+    if reader.read_at(offset, buf)? != buf.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "short container read",
+        ));
+    }
+    Ok(())
+}
+
+// Fixed-width integers from the start of a slice, which must be long enough
+
+pub(crate) fn le16(bytes: &[u8]) -> u16 {
+    u16::from_le_bytes(bytes[..2].try_into().unwrap())
+}
+
+pub(crate) fn le32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes(bytes[..4].try_into().unwrap())
+}
+
+pub(crate) fn le64(bytes: &[u8]) -> u64 {
+    u64::from_le_bytes(bytes[..8].try_into().unwrap())
+}
+
+pub(crate) fn be16(bytes: &[u8]) -> u16 {
+    u16::from_be_bytes(bytes[..2].try_into().unwrap())
+}
+
+pub(crate) fn be32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes(bytes[..4].try_into().unwrap())
+}
+
+pub(crate) fn be64(bytes: &[u8]) -> u64 {
+    u64::from_be_bytes(bytes[..8].try_into().unwrap())
+}
+
 /// Validate a table before converting its size to `usize` and allocating it.
 pub(crate) fn checked_table_size(
     reader: &dyn Reader,
@@ -48,6 +135,9 @@ pub(crate) fn checked_table_size(
     usize::try_from(bytes).map_err(|_| invalid_data("container metadata table too large"))
 }
 
+/// Descriptive key/value pairs attached to a child (member name, mode, etc.)
+pub type Metadata = Vec<(&'static str, String)>;
+
 /// A child within a container
 pub struct Child {
     /// Index within parent (partition number, file index, etc.)
@@ -56,12 +146,86 @@ pub struct Child {
     pub offset: u64,
     /// Reader for the child's data
     pub reader: Arc<dyn Reader + Send + Sync>,
+    /// Descriptive metadata, empty when the container has none
+    pub metadata: Metadata,
+}
+
+/// Builds a child's reader when it is first needed
+type OpenFn = Box<dyn FnOnce() -> io::Result<Arc<dyn Reader + Send + Sync>> + Send>;
+
+/// A child as listed by its container, before its reader is built
+pub struct Entry {
+    /// Index within parent, as for `Child`
+    pub index: u32,
+    /// Byte offset within parent, as for `Child`
+    pub offset: u64,
+    /// Size of the child's data, when the listing records it
+    pub size: Option<u64>,
+    /// Descriptive metadata, empty when the container has none
+    pub metadata: Metadata,
+    open: OpenFn,
+}
+
+impl Entry {
+    pub fn new<F>(index: u32, offset: u64, size: Option<u64>, metadata: Metadata, open: F) -> Self
+    where
+        F: FnOnce() -> io::Result<Arc<dyn Reader + Send + Sync>> + Send + 'static,
+    {
+        Self {
+            index,
+            offset,
+            size,
+            metadata,
+            open: Box::new(open),
+        }
+    }
+
+    /// The member name reported by the container, if any
+    pub fn name(&self) -> Option<&str> {
+        self.metadata
+            .iter()
+            .find(|(key, _)| *key == "name")
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Build the child's reader
+    pub fn open(self) -> io::Result<Child> {
+        Ok(Child {
+            index: self.index,
+            offset: self.offset,
+            reader: (self.open)()?,
+            metadata: self.metadata,
+        })
+    }
+}
+
+impl From<Child> for Entry {
+    fn from(child: Child) -> Self {
+        let reader = child.reader;
+        Self {
+            index: child.index,
+            offset: child.offset,
+            size: reader.size(),
+            metadata: child.metadata,
+            open: Box::new(move || Ok(reader)),
+        }
+    }
 }
 
 /// Trait for container formats that hold other detectable content
 pub trait Container: Send + Sync {
     /// Enumerate children within this container
     fn children(&self, reader: Arc<dyn Reader + Send + Sync>) -> io::Result<Vec<Child>>;
+
+    /// List children without building their readers.
+    ///
+    /// The default builds every child through `children`. Containers that
+    /// do real work per member (walking sector chains, parsing coder
+    /// chains) override this so listing and filtering members stays cheap,
+    /// and implement `children` by opening every entry.
+    fn entries(&self, reader: Arc<dyn Reader + Send + Sync>) -> io::Result<Vec<Entry>> {
+        Ok(self.children(reader)?.into_iter().map(Entry::from).collect())
+    }
 }
 
 /// Reader backed by in-memory bytes
@@ -96,6 +260,158 @@ impl Reader for BytesReader {
 unsafe impl Send for BytesReader {}
 unsafe impl Sync for BytesReader {}
 
+/// In-memory files read as siblings of each other, for testing images
+/// stored as several files
+#[cfg(test)]
+pub(crate) struct FilesReader {
+    path: std::path::PathBuf,
+    files: Arc<Vec<(&'static str, Vec<u8>)>>,
+}
+
+#[cfg(test)]
+impl FilesReader {
+    /// The file `name` among `files`
+    pub(crate) fn open(name: &str, files: Vec<(&'static str, Vec<u8>)>) -> Arc<Self> {
+        Arc::new(Self {
+            path: name.into(),
+            files: Arc::new(files),
+        })
+    }
+
+    fn data(&self) -> &[u8] {
+        let name = self.path.to_str().unwrap();
+        &self.files.iter().find(|(file, _)| *file == name).unwrap().1
+    }
+}
+
+#[cfg(test)]
+impl Reader for FilesReader {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let data = self.data().get(offset as usize..).unwrap_or_default();
+        let count = buf.len().min(data.len());
+        buf[..count].copy_from_slice(&data[..count]);
+        Ok(count)
+    }
+
+    fn size(&self) -> Option<u64> {
+        Some(self.data().len() as u64)
+    }
+
+    fn path(&self) -> Option<&std::path::Path> {
+        Some(&self.path)
+    }
+
+    fn sibling(&self, name: &str) -> io::Result<Arc<dyn Reader + Send + Sync>> {
+        if !self.files.iter().any(|(file, _)| *file == name) {
+            return Err(io::Error::from(io::ErrorKind::NotFound));
+        }
+        Ok(Arc::new(Self {
+            path: name.into(),
+            files: Arc::clone(&self.files),
+        }))
+    }
+
+    fn siblings(&self) -> Vec<String> {
+        self.files
+            .iter()
+            .map(|(name, _)| name.to_string())
+            .collect()
+    }
+}
+
+/// Decoder run by a DeferredReader on first access
+type DecodeFn = Box<dyn Fn() -> io::Result<Vec<u8>> + Send + Sync>;
+
+/// Reader over data that is decoded into memory on first access.
+///
+/// Archive members in a shared compressed block (7z folders, solid archives)
+/// are sliced out of one of these, so listing an archive doesn't pay for
+/// decompression until something actually reads a member.
+pub(crate) struct DeferredReader {
+    size: u64,
+    decode: DecodeFn,
+    data: OnceLock<Result<Vec<u8>, (io::ErrorKind, String)>>,
+}
+
+impl DeferredReader {
+    pub(crate) fn new<F>(size: u64, decode: F) -> Self
+    where
+        F: Fn() -> io::Result<Vec<u8>> + Send + Sync + 'static,
+    {
+        Self {
+            size,
+            decode: Box::new(decode),
+            data: OnceLock::new(),
+        }
+    }
+
+    fn data(&self) -> io::Result<&[u8]> {
+        let result = self.data.get_or_init(|| {
+            (self.decode)().map_err(|error| (error.kind(), error.to_string()))
+        });
+        match result {
+            Ok(data) => Ok(data),
+            Err((kind, message)) => Err(io::Error::new(*kind, message.clone())),
+        }
+    }
+}
+
+impl Reader for DeferredReader {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        if offset >= self.size {
+            return Ok(0);
+        }
+        let data = self.data()?;
+        let offset = offset as usize;
+        if offset >= data.len() {
+            return Ok(0);
+        }
+        let to_read = buf.len().min(data.len() - offset);
+        buf[..to_read].copy_from_slice(&data[offset..offset + to_read]);
+        Ok(to_read)
+    }
+
+    fn size(&self) -> Option<u64> {
+        Some(self.size)
+    }
+}
+
+/// Least-recently-used cache of decoded blocks, keyed by block number.
+///
+/// Readers over compressed streams decode a whole block to serve any read
+/// inside it; detection probes the same few regions repeatedly, so keeping
+/// recent blocks avoids decoding them again.
+pub(crate) struct BlockCache {
+    capacity: usize,
+    /// Most recently used first
+    entries: VecDeque<(u64, Arc<Vec<u8>>)>,
+}
+
+impl BlockCache {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    pub(crate) fn get(&mut self, key: u64) -> Option<Arc<Vec<u8>>> {
+        let pos = self.entries.iter().position(|(k, _)| *k == key)?;
+        let entry = self.entries.remove(pos)?;
+        let data = Arc::clone(&entry.1);
+        self.entries.push_front(entry);
+        Some(data)
+    }
+
+    pub(crate) fn insert(&mut self, key: u64, data: Arc<Vec<u8>>) {
+        self.entries.retain(|(k, _)| *k != key);
+        if self.entries.len() >= self.capacity {
+            self.entries.pop_back();
+        }
+        self.entries.push_front((key, data));
+    }
+}
+
 /// Read all data from a Reader into a Vec
 pub fn read_all(reader: &dyn Reader) -> io::Result<Vec<u8>> {
     let mut data = Vec::new();
@@ -160,32 +476,59 @@ impl Write for LimitedBuffer {
 /// Get container reader for a format, if it's a container
 pub fn get_container(format: &str) -> Option<&'static dyn Container> {
     match format {
+        "arc/applesingle" => Some(&arc::applesingle::APPLESINGLE),
+        "arc/ar" => Some(&arc::ar::AR),
+        "arc/arc" => Some(&arc::sea::ARC),
+        "arc/arj" => Some(&arc::arj::ARJ),
+        "arc/binhex" => Some(&arc::binhex::BINHEX),
         "arc/bzip2" => Some(&arc::bzip2::BZIP2),
+        "arc/cab" => Some(&arc::cab::CAB),
         "arc/compress" => Some(&arc::compress::COMPRESS),
         "arc/gzip" => Some(&arc::gzip::GZIP),
+        "arc/lha" => Some(&arc::lha::LHA),
         "arc/lz4" => Some(&arc::lz4::LZ4),
+        "arc/lzip" => Some(&arc::lzip::LZIP),
         "arc/lzma" => Some(&arc::lzma::LZMA),
+        "arc/lzop" => Some(&arc::lzop::LZOP),
+        "arc/macbinary" => Some(&arc::macbinary::MACBINARY),
+        "arc/mscompress" => Some(&arc::mscompress::MSCOMPRESS),
+        "arc/ole" => Some(&arc::ole::OLE),
+        "arc/pbzx" => Some(&arc::pbzx::PBZX),
+        "arc/7z" => Some(&arc::sevenzip::SEVENZIP),
+        "arc/stuffit" => Some(&arc::stuffit::STUFFIT),
+        "arc/xar" => Some(&arc::xar::XAR),
         "arc/xz" => Some(&arc::xz::XZ),
+        "arc/zoo" => Some(&arc::zoo::ZOO),
         "arc/zstd" => Some(&arc::zstd::ZSTD),
         "disk/apridisk" => Some(&disk::apridisk::APRIDISK),
         "disk/atr" => Some(&disk::atr::ATR),
         "disk/bochs" => Some(&disk::bochs::BOCHS),
+        "disk/ccd" => Some(&disk::ccd::CCD),
         "disk/cdi" => Some(&disk::cdi::CDI),
+        "disk/chd" => Some(&disk::chd::CHD),
         "disk/cloop" => Some(&disk::cloop::CLOOP),
+        "disk/cso" => Some(&disk::cso::CSO),
+        "disk/cue" => Some(&disk::cue::CUE),
+        "disk/dax" => Some(&disk::dax::DAX),
         "disk/dms" => Some(&disk::dms::DMS),
         "disk/nrg" => Some(&disk::nrg::NRG),
         "disk/dmg" => Some(&disk::dmg::DMG),
         "disk/ewf" => Some(&disk::ewf::EWF),
+        "disk/mdf" => Some(&disk::mds::MDS),
         "disk/parallels" => Some(&disk::parallels::PARALLELS),
         "disk/qcow" => Some(&disk::qcow::QCOW),
         "disk/qcow2" => Some(&disk::qcow2::QCOW2),
         "disk/qed" => Some(&disk::qed::QED),
         "disk/2img" => Some(&disk::twoimg::TWOIMG),
         "disk/scl" => Some(&disk::scl::SCL),
+        "disk/sparsebundle" => Some(&disk::sparsebundle::SPARSEBUNDLE),
+        "disk/sparseimage" => Some(&disk::sparseimage::SPARSEIMAGE),
+        "disk/toc" => Some(&disk::toc::TOC),
         "disk/vdi" => Some(&disk::vdi::VDI),
         "disk/vhd" => Some(&disk::vhd::VHD),
         "disk/vhdx" => Some(&disk::vhdx::VHDX),
         "disk/vmdk" => Some(&disk::vmdk::VMDK),
+        "disk/zso" => Some(&disk::cso::ZSO),
         "pt/acorn/adfs" => Some(&pt::acorn::adfs::ADFS),
         "pt/acorn/cumana" => Some(&pt::acorn::cumana::CUMANA),
         "pt/acorn/eesox" => Some(&pt::acorn::eesox::EESOX),
@@ -55,6 +55,7 @@ impl Container for AdfsContainer {
                 index: 0,
                 offset: 0,
                 reader: Arc::new(SliceReader::new(Arc::clone(&reader), 0, length)),
+                metadata: Vec::new(),
             });
         }
 
@@ -91,6 +92,7 @@ impl Container for AdfsContainer {
                                 start,
                                 u64::MAX - start,
                             )),
+                            metadata: Vec::new(),
                         });
                     }
                 }
@@ -118,6 +120,7 @@ fn parse_riscix(
             index: 1,
             offset: start,
             reader: Arc::new(SliceReader::new(Arc::clone(reader), start, u64::MAX - start)),
+            metadata: Vec::new(),
         });
         return Ok(());
     }
@@ -132,6 +135,7 @@ fn parse_riscix(
             start_sect * SECTOR_SIZE,
             boot_size,
         )),
+        metadata: Vec::new(),
     });
 
     // Parse 8 partition entries starting at offset 8
@@ -159,6 +163,7 @@ fn parse_riscix(
                 index: slot,
                 offset: start_bytes,
                 reader: Arc::new(SliceReader::new(Arc::clone(reader), start_bytes, length_bytes)),
+                metadata: Vec::new(),
             });
             slot += 1;
         }
@@ -185,6 +190,7 @@ fn parse_linux(
             start_sect * SECTOR_SIZE,
             boot_size,
         )),
+        metadata: Vec::new(),
     });
 
     // Parse Linux partition entries (12 bytes each: magic, start, size)
@@ -209,6 +215,7 @@ fn parse_linux(
                 index: slot,
                 offset: abs_start,
                 reader: Arc::new(SliceReader::new(Arc::clone(reader), abs_start, length)),
+                metadata: Vec::new(),
             });
             slot += 1;
         }
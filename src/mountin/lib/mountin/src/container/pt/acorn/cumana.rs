@@ -64,6 +64,7 @@ impl Container for CumanaContainer {
                     index: slot,
                     offset: start,
                     reader: Arc::new(SliceReader::new(Arc::clone(&reader), start, length)),
+                    metadata: Vec::new(),
                 });
                 slot += 1;
             }
@@ -125,6 +126,7 @@ fn parse_riscix_cumana(
         index: *slot,
         offset: start,
         reader: Arc::new(SliceReader::new(Arc::clone(reader), start, boot_size)),
+        metadata: Vec::new(),
     });
     *slot += 1;
 
@@ -153,6 +155,7 @@ fn parse_riscix_cumana(
                 index: *slot,
                 offset: start_bytes,
                 reader: Arc::new(SliceReader::new(Arc::clone(reader), start_bytes, length_bytes)),
+                metadata: Vec::new(),
             });
             *slot += 1;
         }
@@ -177,6 +180,7 @@ fn parse_linux_cumana(
         index: *slot,
         offset: start,
         reader: Arc::new(SliceReader::new(Arc::clone(reader), start, boot_size)),
+        metadata: Vec::new(),
     });
     *slot += 1;
 
@@ -201,6 +205,7 @@ fn parse_linux_cumana(
                 index: *slot,
                 offset: abs_start,
                 reader: Arc::new(SliceReader::new(Arc::clone(reader), abs_start, length)),
+                metadata: Vec::new(),
             });
             *slot += 1;
         }
@@ -77,6 +77,7 @@ impl Container for EesoxContainer {
                     index: i as u32,
                     offset: start_bytes,
                     reader: Arc::new(SliceReader::new(Arc::clone(&reader), start_bytes, length)),
+                    metadata: Vec::new(),
                 });
             }
         }
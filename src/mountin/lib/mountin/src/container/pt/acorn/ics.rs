@@ -65,6 +65,7 @@ impl Container for IcsContainer {
                     index: slot,
                     offset: start_bytes,
                     reader: Arc::new(SliceReader::new(Arc::clone(&reader), start_bytes, length)),
+                    metadata: Vec::new(),
                 });
                 slot += 1;
             }
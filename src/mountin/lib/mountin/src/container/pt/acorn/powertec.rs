@@ -57,6 +57,7 @@ impl Container for PowertecContainer {
                     index: slot,
                     offset: start_bytes,
                     reader: Arc::new(SliceReader::new(Arc::clone(&reader), start_bytes, length)),
+                    metadata: Vec::new(),
                 });
                 slot += 1;
             }
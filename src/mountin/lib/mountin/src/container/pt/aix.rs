@@ -144,6 +144,7 @@ impl Container for AixContainer {
                 index: lv_idx as u32,
                 offset: start,
                 reader: Arc::new(SliceReader::new(Arc::clone(&reader), start, length)),
+                metadata: Vec::new(),
             });
         }
 
@@ -84,6 +84,7 @@ impl Container for ApmContainer {
                 index: children.len() as u32,
                 offset: start,
                 reader: Arc::new(SliceReader::new(Arc::clone(&reader), start, length)),
+                metadata: Vec::new(),
             });
         }
 
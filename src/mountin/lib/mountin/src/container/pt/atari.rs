@@ -121,6 +121,7 @@ fn add_partition(
         index,
         offset: start,
         reader: Arc::new(SliceReader::new(Arc::clone(parent), start, length)),
+        metadata: Vec::new(),
     });
 }
 
@@ -153,6 +154,7 @@ fn parse_xgm_chain(
                     index: *partition_index,
                     offset: start_bytes,
                     reader: Arc::new(SliceReader::new(Arc::clone(parent), start_bytes, length)),
+                    metadata: Vec::new(),
                 });
                 *partition_index += 1;
             }
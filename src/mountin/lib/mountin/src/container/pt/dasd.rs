@@ -88,6 +88,7 @@ fn parse_lnx1(
         index: 0,
         offset: start,
         reader: Arc::new(SliceReader::new(Arc::clone(reader), start, length)),
+        metadata: Vec::new(),
     }])
 }
 
@@ -132,6 +133,7 @@ fn parse_cms1(
         index: 0,
         offset: start,
         reader: Arc::new(SliceReader::new(Arc::clone(reader), start, length)),
+        metadata: Vec::new(),
     }])
 }
 
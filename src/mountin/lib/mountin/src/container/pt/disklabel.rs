@@ -64,6 +64,7 @@ impl Container for DisklabelContainer {
                 index: i as u32,
                 offset: start,
                 reader: Arc::new(SliceReader::new(Arc::clone(&reader), start, length)),
+                metadata: Vec::new(),
             });
         }
 
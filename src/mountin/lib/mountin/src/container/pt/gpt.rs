@@ -83,6 +83,7 @@ impl Container for GptContainer {
                 index: i,
                 offset: start,
                 reader: Arc::new(SliceReader::new(Arc::clone(&reader), start, length)),
+                metadata: Vec::new(),
             });
         }
 
@@ -59,6 +59,7 @@ impl Container for KarmaContainer {
                 index: i as u32,
                 offset: start,
                 reader: Arc::new(SliceReader::new(Arc::clone(&reader), start, length)),
+                metadata: Vec::new(),
             });
         }
 
@@ -80,6 +80,7 @@ impl Container for MbrContainer {
                         index: slot as u32,
                         offset: start,
                         reader: Arc::new(SliceReader::new(Arc::clone(&reader), start, length)),
+                        metadata: Vec::new(),
                     });
                 }
             }
@@ -150,6 +151,7 @@ fn parse_extended_chain(
                     index: *partition_index,
                     offset: start,
                     reader: Arc::new(SliceReader::new(Arc::clone(parent), start, length)),
+                    metadata: Vec::new(),
                 });
                 *partition_index += 1;
             }
@@ -80,6 +80,7 @@ impl Container for Pc98Container {
                 index: i as u32,
                 offset: start,
                 reader: Arc::new(SliceReader::new(Arc::clone(&reader), start, length)),
+                metadata: Vec::new(),
             });
         }
 
@@ -63,6 +63,7 @@ impl Container for Plan9Container {
                 index: children.len() as u32,
                 offset: start,
                 reader: Arc::new(SliceReader::new(Arc::clone(&reader), start, end - start)),
+                metadata: Vec::new(),
             });
         }
 
@@ -78,6 +78,7 @@ impl Container for RdbContainer {
                 index: children.len() as u32,
                 offset: start,
                 reader: Arc::new(SliceReader::new(Arc::clone(&reader), start, length)),
+                metadata: Vec::new(),
             });
 
             part_blk = next;
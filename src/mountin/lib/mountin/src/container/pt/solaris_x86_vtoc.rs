@@ -63,6 +63,7 @@ impl Container for SolarisX86VtocContainer {
                 index: index as u32,
                 offset: start,
                 reader: Arc::new(SliceReader::new(Arc::clone(&reader), start, length)),
+                metadata: Vec::new(),
             });
         }
 
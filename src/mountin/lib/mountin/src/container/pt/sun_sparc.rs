@@ -70,6 +70,7 @@ impl Container for SunSparcContainer {
                 index: i as u32,
                 offset: start,
                 reader: Arc::new(SliceReader::new(Arc::clone(&reader), start, length)),
+                metadata: Vec::new(),
             });
         }
 
@@ -83,6 +83,7 @@ impl Container for UbiContainer {
                 index: *vol_id,
                 offset: 0, // UBI volumes don't have a simple offset
                 reader: Arc::new(vol_reader),
+                metadata: Vec::new(),
             });
         }
 
@@ -0,0 +1,170 @@
+//! Split image support - joins images stored as several files
+//!
+//! Raw images are often split into numbered parts (`disk.001`, `disk.002`)
+//! or `split`-style lettered parts (`disk.partaa`, `disk.partab`). The
+//! parts are found through `Reader::sibling`, so this only works for
+//! top-level readers whose caller can open neighbouring files.
+
+use crate::container::invalid_data;
+use crate::detect::Reader;
+use std::io;
+use std::sync::Arc;
+
+/// Most parts joined into one image
+const MAX_PARTS: u32 = 10_000;
+
+/// Reader that joins several readers end to end
+pub struct ConcatReader {
+    parts: Vec<Arc<dyn Reader + Send + Sync>>,
+    /// Offset of each part within the joined data
+    starts: Vec<u64>,
+    size: u64,
+}
+
+impl ConcatReader {
+    /// Join `parts` in order; each must know its size
+    pub fn new(parts: Vec<Arc<dyn Reader + Send + Sync>>) -> io::Result<Self> {
+        let mut starts = Vec::with_capacity(parts.len());
+        let mut size = 0u64;
+        for part in &parts {
+            starts.push(size);
+            let len = part
+                .size()
+                .ok_or_else(|| invalid_data("split part has unknown size"))?;
+            size = size
+                .checked_add(len)
+                .ok_or_else(|| invalid_data("split image size overflow"))?;
+        }
+        Ok(Self {
+            parts,
+            starts,
+            size,
+        })
+    }
+}
+
+impl Reader for ConcatReader {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        if offset >= self.size || buf.is_empty() {
+            return Ok(0);
+        }
+        // Last part starting at or before offset; empty parts are skipped
+        let index = self.starts.partition_point(|&start| start <= offset) - 1;
+        let within = offset - self.starts[index];
+        let end = self.starts.get(index + 1).copied().unwrap_or(self.size);
+        let to_read = buf.len().min((end - offset) as usize);
+        self.parts[index].read_at(within, &mut buf[..to_read])
+    }
+
+    fn size(&self) -> Option<u64> {
+        Some(self.size)
+    }
+}
+
+/// Open the parts that follow `first`, named `name(2)`, `name(3)` and so
+/// on, stopping at the first one that can't be opened
+pub fn following_parts<F>(first: &dyn Reader, name: F) -> Vec<Arc<dyn Reader + Send + Sync>>
+where
+    F: Fn(u32) -> Option<String>,
+{
+    let mut parts = Vec::new();
+    for n in 2..=MAX_PARTS {
+        let Some(part) = name(n).and_then(|name| first.sibling(&name).ok()) else {
+            break;
+        };
+        parts.push(part);
+    }
+    parts
+}
+
+/// Name of part `n` (counting from 1) of a split raw image whose first
+/// part is `first`, if `first` is named like one
+fn part_name(first: &str, n: u32) -> Option<String> {
+    let (stem, suffix) = first.rsplit_once('.')?;
+
+    // disk.001, disk.002, ...
+    if suffix.len() >= 3 && suffix.bytes().all(|b| b.is_ascii_digit()) {
+        let width = suffix.len();
+        if suffix.parse::<u32>().ok()? != 1 || n >= 10u32.checked_pow(width as u32)? {
+            return None;
+        }
+        return Some(format!("{stem}.{n:0width$}"));
+    }
+
+    // disk.aa, disk.ab, ... or disk.partaa, disk.partab, ...
+    let prefix = suffix.strip_suffix("aa")?;
+    if !matches!(prefix, "" | "part") || n > 26 * 26 {
+        return None;
+    }
+    let letter = |i: u32| char::from(b'a' + i as u8);
+    let i = n - 1;
+    Some(format!(
+        "{stem}.{prefix}{}{}",
+        letter(i / 26),
+        letter(i % 26)
+    ))
+}
+
+/// Join a split raw image if `reader` is the first part of one and more
+/// parts can be found; otherwise return `reader` unchanged
+pub fn assemble(reader: Arc<dyn Reader + Send + Sync>) -> Arc<dyn Reader + Send + Sync> {
+    let Some(name) = reader
+        .path()
+        .and_then(|path| path.file_name())
+        .and_then(|name| name.to_str())
+        .map(str::to_owned)
+    else {
+        return reader;
+    };
+    if part_name(&name, 1).is_none() {
+        return reader;
+    }
+    let rest = following_parts(&*reader, |n| part_name(&name, n));
+    if rest.is_empty() {
+        return reader;
+    }
+    let parts = std::iter::once(Arc::clone(&reader)).chain(rest).collect();
+    match ConcatReader::new(parts) {
+        Ok(joined) => Arc::new(joined),
+        Err(_) => reader,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::BytesReader;
+
+    #[test]
+    fn names_split_parts() {
+        assert_eq!(part_name("disk.001", 2).as_deref(), Some("disk.002"));
+        assert_eq!(part_name("disk.0001", 12).as_deref(), Some("disk.0012"));
+        assert_eq!(part_name("disk.001", 1000), None);
+        assert_eq!(part_name("disk.002", 3), None);
+        assert_eq!(
+            part_name("disk.img.partaa", 2).as_deref(),
+            Some("disk.img.partab")
+        );
+        assert_eq!(part_name("disk.aa", 27).as_deref(), Some("disk.ba"));
+        assert_eq!(part_name("disk.img", 2), None);
+        assert_eq!(part_name("banana", 2), None);
+    }
+
+    #[test]
+    fn reads_across_parts() {
+        let parts: Vec<Arc<dyn Reader + Send + Sync>> = vec![
+            Arc::new(BytesReader::new(b"abc".to_vec())),
+            Arc::new(BytesReader::new(Vec::new())),
+            Arc::new(BytesReader::new(b"defg".to_vec())),
+        ];
+        let joined = ConcatReader::new(parts).unwrap();
+        assert_eq!(joined.size(), Some(7));
+
+        let mut buf = [0u8; 8];
+        assert_eq!(joined.read_at(1, &mut buf).unwrap(), 2);
+        assert_eq!(&buf[..2], b"bc");
+        assert_eq!(joined.read_at(3, &mut buf).unwrap(), 4);
+        assert_eq!(&buf[..4], b"defg");
+        assert_eq!(joined.read_at(7, &mut buf).unwrap(), 0);
+    }
+}
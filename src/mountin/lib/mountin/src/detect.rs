@@ -7,6 +7,7 @@ use regex::Regex;
 use std::collections::HashSet;
 use std::ffi::CStr;
 use std::io;
+use std::path::Path;
 use std::sync::Arc;
 
 /// Trait for reading bytes at arbitrary offsets (pread-style).
@@ -15,6 +16,26 @@ use std::sync::Arc;
 pub trait Reader {
     fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize>;
     fn size(&self) -> Option<u64>;
+
+    /// Filesystem path of the underlying file, for top-level readers only.
+    /// Containers use it to keep sidecar files such as seek indexes.
+    fn path(&self) -> Option<&Path> {
+        None
+    }
+
+    /// Open a file that belongs with this one, such as the next segment
+    /// of a split image, by name relative to this file's directory.
+    /// Top-level readers only; callers without a filesystem can implement
+    /// it over whatever storage they have.
+    fn sibling(&self, _name: &str) -> io::Result<Arc<dyn Reader + Send + Sync>> {
+        Err(io::Error::new(io::ErrorKind::NotFound, "no sibling files"))
+    }
+
+    /// Names of files `sibling` can open, for formats that find related
+    /// files by their contents rather than by name
+    fn siblings(&self) -> Vec<String> {
+        Vec::new()
+    }
 }
 
 /// Resolve a potentially negative offset using file size.
@@ -270,6 +291,28 @@ mod tests {
             "="
         ));
     }
+
+    fn entry(name: Option<&str>, size: Option<u64>) -> container::Entry {
+        let metadata = name.map(|n| ("name", n.to_string())).into_iter().collect();
+        container::Entry::new(0, 0, size, metadata, || panic!("filtering opened an entry"))
+    }
+
+    #[test]
+    fn filters_named_members() {
+        let filter = Filter {
+            name: Some(Regex::new(r"\.img$").unwrap()),
+            min_size: Some(100),
+            max_size: Some(1000),
+        };
+        assert!(filter.accepts(&entry(Some("disk.img"), Some(500))));
+        assert!(filter.accepts(&entry(Some("disk.img"), None)));
+        assert!(!filter.accepts(&entry(Some("disk.iso"), Some(500))));
+        assert!(!filter.accepts(&entry(Some("disk.img"), Some(99))));
+        assert!(!filter.accepts(&entry(Some("disk.img"), Some(1001))));
+        // Unnamed children such as partitions are always followed
+        assert!(filter.accepts(&entry(None, Some(1))));
+        assert!(Filter::default().accepts(&entry(Some("any"), Some(0))));
+    }
 }
 
 /// Match checksum validation rule
@@ -341,6 +384,15 @@ impl Reader for &dyn Reader {
     fn size(&self) -> Option<u64> {
         (*self).size()
     }
+    fn path(&self) -> Option<&Path> {
+        (*self).path()
+    }
+    fn sibling(&self, name: &str) -> io::Result<Arc<dyn Reader + Send + Sync>> {
+        (*self).sibling(name)
+    }
+    fn siblings(&self) -> Vec<String> {
+        (*self).siblings()
+    }
 }
 
 /// A node in the detection tree
@@ -349,6 +401,8 @@ pub struct DetectNode {
     pub format: &'static CStr,
     /// Index within parent container (partition number, etc.)
     pub index: u32,
+    /// Metadata the parent container reported for this child
+    pub metadata: container::Metadata,
     /// Child nodes (for container formats)
     pub children: Vec<DetectNode>,
 }
@@ -364,21 +418,68 @@ fn is_transform(format: &str) -> bool {
     format.starts_with("arc/")
 }
 
+/// Which archive members detection descends into.
+///
+/// Only named members are filtered; unnamed children such as partitions
+/// or decompressed streams are always followed. A member whose size the
+/// listing doesn't record passes the size limits.
+#[derive(Default)]
+pub struct Filter {
+    /// Pattern the member name must match
+    pub name: Option<Regex>,
+    /// Smallest member size to follow
+    pub min_size: Option<u64>,
+    /// Largest member size to follow
+    pub max_size: Option<u64>,
+}
+
+impl Filter {
+    fn accepts(&self, entry: &container::Entry) -> bool {
+        let Some(name) = entry.name() else {
+            return true;
+        };
+        if self.name.as_ref().is_some_and(|re| !re.is_match(name)) {
+            return false;
+        }
+        match entry.size {
+            Some(size) => {
+                self.min_size.is_none_or(|min| size >= min)
+                    && self.max_size.is_none_or(|max| size <= max)
+            }
+            None => true,
+        }
+    }
+}
+
 /// Detect format tree recursively
 ///
 /// Returns a list of root-level detected formats, each with their
 /// children populated if they are container formats.
 pub fn detect_tree(reader: Arc<dyn Reader + Send + Sync>) -> Vec<DetectNode> {
+    detect_tree_filtered(reader, &Filter::default())
+}
+
+/// Detect format tree, descending only into archive members that pass
+/// `filter`. Rejected members are left out of the tree and their data is
+/// never read. If `reader` is the first part of a split raw image whose
+/// other parts its `sibling` can open, the joined image is detected.
+pub fn detect_tree_filtered(
+    reader: Arc<dyn Reader + Send + Sync>,
+    filter: &Filter,
+) -> Vec<DetectNode> {
     let Some(formats) = FORMATS.get() else {
         return vec![];
     };
+    let reader = container::split::assemble(reader);
     let mut seen = HashSet::new();
-    detect_tree_recursive(reader, formats, 0, 0, String::new(), 0, &mut seen)
+    detect_tree_recursive(reader, formats, filter, 0, 0, String::new(), 0, &mut seen)
 }
 
+#[allow(clippy::too_many_arguments)]
 fn detect_tree_recursive(
     reader: Arc<dyn Reader + Send + Sync>,
     formats: &crate::format::FormatDb,
+    filter: &Filter,
     index: u32,
     depth: u32,
     stream: String,
@@ -404,8 +505,8 @@ fn detect_tree_recursive(
 
         let format_str = format.to_str().unwrap_or("");
         let children = match container::get_container(format_str) {
-            Some(container) => match container.children(Arc::clone(&reader)) {
-                Ok(kids) => {
+            Some(container) => match container.entries(Arc::clone(&reader)) {
+                Ok(entries) => {
                     let child_stream = if is_transform(format_str) {
                         format!("{}/{}", stream, format_str)
                     } else {
@@ -414,25 +515,49 @@ fn detect_tree_recursive(
                     // Clone seen so sibling formats at this level explore
                     // independently — each is a valid path for guest selection
                     let mut branch_seen = seen.clone();
-                    kids.into_iter()
-                        .flat_map(|child| {
-                            let detected = detect_tree_recursive(
-                                Arc::clone(&child.reader),
-                                formats,
-                                child.index,
-                                depth + 1,
-                                child_stream.clone(),
-                                child.offset,
-                                &mut branch_seen,
-                            );
+                    entries
+                        .into_iter()
+                        .filter(|entry| filter.accepts(entry))
+                        .flat_map(|entry| {
+                            let index = entry.index;
+                            let metadata = entry.metadata.clone();
+                            let mut detected = match entry.open() {
+                                Ok(child) => {
+                                    // A child that isn't a slice of this one,
+                                    // such as a cooked CD track or a decoded
+                                    // archive member, is bytes of its own;
+                                    // key it by its index so several don't
+                                    // dedupe as one
+                                    let stream = if child.offset == u64::MAX {
+                                        format!("{}/{}#{}", stream, format_str, child.index)
+                                    } else {
+                                        child_stream.clone()
+                                    };
+                                    detect_tree_recursive(
+                                        child.reader,
+                                        formats,
+                                        filter,
+                                        child.index,
+                                        depth + 1,
+                                        stream,
+                                        child.offset,
+                                        &mut branch_seen,
+                                    )
+                                }
+                                Err(_) => vec![],
+                            };
                             // If nothing detected, emit "data" as fallback
                             if detected.is_empty() {
                                 vec![DetectNode {
                                     format: DATA_FORMAT,
-                                    index: child.index,
+                                    index,
+                                    metadata,
                                     children: vec![],
                                 }]
                             } else {
+                                for node in &mut detected {
+                                    node.metadata = metadata.clone();
+                                }
                                 detected
                             }
                         })
@@ -446,6 +571,7 @@ fn detect_tree_recursive(
         results.push(DetectNode {
             format: *format,
             index,
+            metadata: Vec::new(),
             children,
         });
     }
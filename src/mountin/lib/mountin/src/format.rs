@@ -98,21 +98,26 @@ pub fn load(path: &Path) -> io::Result<()> {
 pub fn init_test_formats() {
     FORMATS.get_or_init(|| FormatDb {
         formats: vec![
-            test_format("disk/atr", &[0x96, 0x02]),
-            test_format("disk/2img", b"2IMG"),
-            test_format("disk/scl", b"SINCLAIR"),
+            test_format("arc/7z", 0, b"7z\xbc\xaf\x27\x1c"),
+            test_format("disk/atr", 0, &[0x96, 0x02]),
+            test_format("disk/2img", 0, b"2IMG"),
+            test_format("disk/scl", 0, b"SINCLAIR"),
+            test_format("disk/nrg", -8, b"NERO"),
+            test_format("disk/qcow2", 0, b"QFI\xfb"),
+            test_format("disk/cue", 0, b"FILE \""),
+            test_format("fs/iso9660", 0x8001, b"CD001"),
         ],
     });
 }
 
 #[cfg(test)]
-fn test_format(name: &str, value: &[u8]) -> (&'static CStr, Detect) {
+fn test_format(name: &str, offset: i64, value: &[u8]) -> (&'static CStr, Detect) {
     let name = Box::leak(CString::new(name).unwrap().into_boxed_c_str());
     (
         name,
         Detect::All {
             all: vec![Rule::Leaf {
-                offset: 0,
+                offset,
                 typ: "string".into(),
                 value: Some(Value::Bytes(
                     value.iter().map(|byte| i64::from(*byte)).collect(),
@@ -5,17 +5,41 @@ mod container;
 mod detect;
 mod format;
 
-use std::ffi::{c_char, c_void, CStr};
+use std::ffi::{c_char, c_void, CStr, CString};
 use std::fs::File;
 use std::io::{self, Read, Seek, SeekFrom};
 use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 
 use detect::Reader;
 
+/// Whether sibling files may be opened outside the image's directory
+static FOLLOW_PARENT_DIRS: AtomicBool = AtomicBool::new(false);
+
 /// File reader - wraps a File with mutex for thread-safe positional reads
 struct FileReader {
     file: Mutex<File>,
+    path: PathBuf,
+    /// Directory of the image detection started from, which siblings
+    /// must stay inside
+    root: PathBuf,
+}
+
+impl FileReader {
+    fn open(path: &Path) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        Ok(Self {
+            file: Mutex::new(file),
+            path: path.to_path_buf(),
+            root: dir.canonicalize()?,
+        })
+    }
 }
 
 impl Reader for FileReader {
@@ -30,6 +54,147 @@ impl Reader for FileReader {
         let mut file = self.file.lock().ok()?;
         file.seek(SeekFrom::End(0)).ok()
     }
+
+    fn path(&self) -> Option<&Path> {
+        Some(&self.path)
+    }
+
+    /// Names come from the images themselves, so only relative names are
+    /// followed, and only to regular files: opening a FIFO or reading a
+    /// terminal would block. Unless parent directories are allowed, the
+    /// file must also be inside the first image's directory, after
+    /// resolving ".." and symbolic links.
+    fn sibling(&self, name: &str) -> io::Result<Arc<dyn Reader + Send + Sync>> {
+        let rooted = Path::new(name)
+            .components()
+            .any(|part| matches!(part, Component::RootDir | Component::Prefix(_)));
+        if rooted {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "absolute sibling name",
+            ));
+        }
+        let follow_parents = FOLLOW_PARENT_DIRS.load(Ordering::Relaxed);
+        let upward = Path::new(name)
+            .components()
+            .any(|part| part == Component::ParentDir);
+        if upward && !follow_parents {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "sibling name leaves the image directory",
+            ));
+        }
+        let dir = self.path.parent().unwrap_or(Path::new(""));
+        let path = dir.join(name);
+        if !std::fs::metadata(&path)?.is_file() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "sibling is not a regular file",
+            ));
+        }
+        if !follow_parents && !path.canonicalize()?.starts_with(&self.root) {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "sibling is outside the image directory",
+            ));
+        }
+        Ok(Arc::new(FileReader {
+            file: Mutex::new(File::open(&path)?),
+            path,
+            root: self.root.clone(),
+        }))
+    }
+
+    /// Files in the same directory, and when parent directories are
+    /// allowed, in the one above as "../name": snapshot disks are often
+    /// kept in a subdirectory of their base
+    fn siblings(&self) -> Vec<String> {
+        let dir = match self.path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let list = |dir: &Path, prefix: &str| -> Vec<String> {
+            let Ok(entries) = std::fs::read_dir(dir) else {
+                return Vec::new();
+            };
+            entries
+                .flatten()
+                .filter(|entry| entry.file_type().is_ok_and(|kind| kind.is_file()))
+                .filter_map(|entry| entry.file_name().into_string().ok())
+                .map(|name| format!("{prefix}{name}"))
+                .collect()
+        };
+        let mut names = list(dir, "");
+        if FOLLOW_PARENT_DIRS.load(Ordering::Relaxed) {
+            names.extend(list(&dir.join(".."), "../"));
+        }
+        names
+    }
+}
+
+/// Reader callbacks for callers without a filesystem (e.g. WASM hosts).
+/// Each callback gets the handle of the file it applies to.
+/// - read: read up to `len` bytes at `offset` into `buf`; returns the count
+///   read, 0 at the end, or a negative value on error
+/// - size: file size, or UINT64_MAX if unknown
+/// - sibling: open a file named relative to this one, or return NULL;
+///   may be NULL when images are never split across files
+/// - close: release a handle returned by `sibling`; may be NULL
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct ReaderCallbacks {
+    pub read: extern "C" fn(handle: *mut c_void, offset: u64, buf: *mut u8, len: usize) -> i64,
+    pub size: extern "C" fn(handle: *mut c_void) -> u64,
+    pub sibling: Option<extern "C" fn(handle: *mut c_void, name: *const c_char) -> *mut c_void>,
+    pub close: Option<extern "C" fn(handle: *mut c_void)>,
+}
+
+/// Callback reader - a handle read through the caller's callbacks
+struct CallbackReader {
+    callbacks: ReaderCallbacks,
+    handle: *mut c_void,
+    /// Opened by `sibling`, so closed when dropped
+    owned: bool,
+}
+
+// Callbacks are only made from the thread that called into the library
+unsafe impl Send for CallbackReader {}
+unsafe impl Sync for CallbackReader {}
+
+impl Reader for CallbackReader {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let read = (self.callbacks.read)(self.handle, offset, buf.as_mut_ptr(), buf.len());
+        usize::try_from(read)
+            .ok()
+            .filter(|&read| read <= buf.len())
+            .ok_or_else(|| io::Error::other("read callback failed"))
+    }
+
+    fn size(&self) -> Option<u64> {
+        Some((self.callbacks.size)(self.handle)).filter(|&size| size != u64::MAX)
+    }
+
+    fn sibling(&self, name: &str) -> io::Result<Arc<dyn Reader + Send + Sync>> {
+        let open = self.callbacks.sibling.ok_or(io::ErrorKind::NotFound)?;
+        let name = CString::new(name).map_err(|_| io::ErrorKind::InvalidInput)?;
+        let handle = open(self.handle, name.as_ptr());
+        if handle.is_null() {
+            return Err(io::ErrorKind::NotFound.into());
+        }
+        Ok(Arc::new(CallbackReader {
+            callbacks: self.callbacks,
+            handle,
+            owned: true,
+        }))
+    }
+}
+
+impl Drop for CallbackReader {
+    fn drop(&mut self) {
+        if let (true, Some(close)) = (self.owned, self.callbacks.close) {
+            close(self.handle);
+        }
+    }
 }
 
 /// Get library version
@@ -49,6 +214,20 @@ pub extern "C" fn mountin_version() -> *const c_char {
 pub type DetectTreeCallback =
     extern "C" fn(format: *const c_char, index: u32, depth: u32, userdata: *mut c_void);
 
+/// Callback type for mountin_detect_tree_metadata
+/// Like DetectTreeCallback, plus the metadata the parent container reported.
+/// - keys/values: `count` parallel NUL-terminated strings, valid only for
+///   the duration of the call
+pub type DetectTreeMetadataCallback = extern "C" fn(
+    format: *const c_char,
+    index: u32,
+    depth: u32,
+    keys: *const *const c_char,
+    values: *const *const c_char,
+    count: u32,
+    userdata: *mut c_void,
+);
+
 /// Load the compiled format catalogue used by subsequent detection calls.
 #[no_mangle]
 pub extern "C" fn mountin_load_catalogue(path: *const c_char) -> bool {
@@ -65,6 +244,31 @@ pub extern "C" fn mountin_load_catalogue(path: *const c_char) -> bool {
     result.unwrap_or(false)
 }
 
+/// Allow containers to write seek indexes beside the files they read.
+/// Off by default. When on, e.g. `disk.img.gz` gets a `disk.img.gz.zran`
+/// checkpoint index so later opens can skip the full decompression pass.
+#[no_mangle]
+pub extern "C" fn mountin_set_persist_indexes(enabled: bool) {
+    container::set_persist_indexes(enabled);
+}
+
+/// Check images against the hashes stored in them when they are opened.
+/// Off by default, since it reads the whole image. Results are reported
+/// as metadata, e.g. `md5_verified` on an EWF image's disk.
+#[no_mangle]
+pub extern "C" fn mountin_set_verify_hashes(enabled: bool) {
+    container::set_verify_hashes(enabled);
+}
+
+/// Let images name sibling files outside their own directory, such as a
+/// parent disk given as "../base.vmdk". Off by default, since those names
+/// come from the images: a crafted one could otherwise read any file the
+/// caller can. Callback readers decide for themselves.
+#[no_mangle]
+pub extern "C" fn mountin_set_follow_parent_dirs(enabled: bool) {
+    FOLLOW_PARENT_DIRS.store(enabled, Ordering::Relaxed);
+}
+
 /// Detect format tree from file path.
 /// Recursively detects formats in containers (gzip, tar, partition tables, etc.)
 /// Calls the callback for each detected format with its position in the tree.
@@ -79,27 +283,119 @@ pub extern "C" fn mountin_detect_tree(
     }));
 }
 
-fn detect_tree_ffi(
+/// Detect format tree from file path, including per-node metadata.
+/// Same traversal as mountin_detect_tree; metadata such as archive member
+/// names is passed to the callback as parallel key/value arrays.
+#[no_mangle]
+pub extern "C" fn mountin_detect_tree_metadata(
     path: *const c_char,
-    callback: DetectTreeCallback,
+    callback: DetectTreeMetadataCallback,
     userdata: *mut c_void,
 ) {
+    let _ = catch_unwind(AssertUnwindSafe(|| {
+        detect_tree_metadata_ffi(path, callback, userdata);
+    }));
+}
+
+/// Detect format tree from file path, descending only into archive members
+/// that match the filter. Members that don't match are left out and their
+/// data is not read; unnamed children such as partitions are always followed.
+/// - name_pattern: regex the member path must match, or NULL for any
+/// - min_size/max_size: member size limits; 0 and UINT64_MAX mean no limit
+///
+/// Returns false if the path is NULL or the pattern is not a valid regex.
+#[no_mangle]
+pub extern "C" fn mountin_detect_tree_filtered(
+    path: *const c_char,
+    name_pattern: *const c_char,
+    min_size: u64,
+    max_size: u64,
+    callback: DetectTreeMetadataCallback,
+    userdata: *mut c_void,
+) -> bool {
+    let result = catch_unwind(AssertUnwindSafe(|| {
+        let Some(filter) = parse_filter(name_pattern, min_size, max_size) else {
+            return false;
+        };
+        let Some(reader) = open_path(path) else {
+            return false;
+        };
+        let tree = detect::detect_tree_filtered(reader, &filter);
+        walk_metadata_tree(&tree, 0, callback, userdata);
+        true
+    }));
+    result.unwrap_or(false)
+}
+
+/// Detect format tree of data read through callbacks rather than from a
+/// path, including per-node metadata. `handle` is passed to the callbacks
+/// for the first file and is not closed.
+///
+/// Returns false if `callbacks` is NULL.
+#[no_mangle]
+pub extern "C" fn mountin_detect_tree_callbacks(
+    callbacks: *const ReaderCallbacks,
+    handle: *mut c_void,
+    callback: DetectTreeMetadataCallback,
+    userdata: *mut c_void,
+) -> bool {
+    let result = catch_unwind(AssertUnwindSafe(|| {
+        let Some(reader) = open_callbacks(callbacks, handle) else {
+            return false;
+        };
+        let tree = detect::detect_tree(reader);
+        walk_metadata_tree(&tree, 0, callback, userdata);
+        true
+    }));
+    result.unwrap_or(false)
+}
+
+fn parse_filter(
+    name_pattern: *const c_char,
+    min_size: u64,
+    max_size: u64,
+) -> Option<detect::Filter> {
+    let name = if name_pattern.is_null() {
+        None
+    } else {
+        let pattern = unsafe { CStr::from_ptr(name_pattern) }.to_str().ok()?;
+        Some(regex::Regex::new(pattern).ok()?)
+    };
+    Some(detect::Filter {
+        name,
+        min_size: (min_size != 0).then_some(min_size),
+        max_size: (max_size != u64::MAX).then_some(max_size),
+    })
+}
+
+fn open_path(path: *const c_char) -> Option<Arc<dyn Reader + Send + Sync>> {
     if path.is_null() {
-        return;
+        return None;
     }
-    let path = match unsafe { CStr::from_ptr(path) }.to_str() {
-        Ok(s) => s,
-        Err(_) => return,
-    };
+    let path = unsafe { CStr::from_ptr(path) }.to_str().ok()?;
+    Some(Arc::new(FileReader::open(Path::new(path)).ok()?))
+}
 
-    let file = match File::open(path) {
-        Ok(f) => f,
-        Err(_) => return,
-    };
+fn open_callbacks(
+    callbacks: *const ReaderCallbacks,
+    handle: *mut c_void,
+) -> Option<Arc<dyn Reader + Send + Sync>> {
+    let callbacks = *unsafe { callbacks.as_ref() }?;
+    Some(Arc::new(CallbackReader {
+        callbacks,
+        handle,
+        owned: false,
+    }))
+}
 
-    let reader: Arc<dyn Reader + Send + Sync> = Arc::new(FileReader {
-        file: Mutex::new(file),
-    });
+fn detect_tree_ffi(
+    path: *const c_char,
+    callback: DetectTreeCallback,
+    userdata: *mut c_void,
+) {
+    let Some(reader) = open_path(path) else {
+        return;
+    };
     let tree = detect::detect_tree(reader);
 
     fn walk_tree(
@@ -116,3 +412,179 @@ fn detect_tree_ffi(
 
     walk_tree(&tree, 0, callback, userdata);
 }
+
+fn detect_tree_metadata_ffi(
+    path: *const c_char,
+    callback: DetectTreeMetadataCallback,
+    userdata: *mut c_void,
+) {
+    let Some(reader) = open_path(path) else {
+        return;
+    };
+    let tree = detect::detect_tree(reader);
+    walk_metadata_tree(&tree, 0, callback, userdata);
+}
+
+fn walk_metadata_tree(
+    nodes: &[detect::DetectNode],
+    depth: u32,
+    callback: DetectTreeMetadataCallback,
+    userdata: *mut c_void,
+) {
+    for node in nodes {
+        // Interior NULs can't cross the C boundary; drop those pairs
+        let pairs: Vec<(CString, CString)> = node
+            .metadata
+            .iter()
+            .filter_map(|(key, value)| {
+                Some((CString::new(*key).ok()?, CString::new(value.as_str()).ok()?))
+            })
+            .collect();
+        let keys: Vec<*const c_char> = pairs.iter().map(|(key, _)| key.as_ptr()).collect();
+        let values: Vec<*const c_char> =
+            pairs.iter().map(|(_, value)| value.as_ptr()).collect();
+
+        callback(
+            node.format.as_ptr(),
+            node.index,
+            depth,
+            keys.as_ptr(),
+            values.as_ptr(),
+            pairs.len() as u32,
+            userdata,
+        );
+        walk_metadata_tree(&node.children, depth + 1, callback, userdata);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sibling_opens_only_relative_regular_files() {
+        let dir = std::env::temp_dir().join(format!("mountin-sibling-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("snapshots")).unwrap();
+        std::fs::write(dir.join("base.img"), b"base").unwrap();
+        std::fs::write(dir.join("snapshots/top.img"), b"top").unwrap();
+        std::fs::write(dir.join("snapshots/delta.img"), b"delta").unwrap();
+        std::os::unix::fs::symlink("../base.img", dir.join("snapshots/link.img")).unwrap();
+        let top = FileReader::open(&dir.join("snapshots/top.img")).unwrap();
+
+        assert_eq!(top.sibling("delta.img").unwrap().size(), Some(5));
+        let absolute = dir.join("base.img");
+        let error = top.sibling(absolute.to_str().unwrap()).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+
+        // Nothing outside snapshots/ unless parent directories are allowed
+        for name in ["../base.img", "link.img", ".."] {
+            let error = top.sibling(name).err().unwrap();
+            assert_eq!(error.kind(), io::ErrorKind::PermissionDenied, "{name}");
+        }
+        assert!(!top.siblings().iter().any(|name| name.starts_with("../")));
+
+        mountin_set_follow_parent_dirs(true);
+        assert_eq!(top.sibling("../base.img").unwrap().size(), Some(4));
+        assert_eq!(top.sibling("link.img").unwrap().size(), Some(4));
+        assert!(top.siblings().contains(&"../base.img".to_string()));
+        let error = top.sibling("..").err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        mountin_set_follow_parent_dirs(false);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    static FILES: [(&str, &[u8]); 2] = [("disk.scl", b"SINCLAIR"), ("disk.002", b"part")];
+    static CLOSED: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+    // Handles are indexes into FILES, plus one so none is NULL
+    fn handle(index: usize) -> *mut c_void {
+        (index + 1) as *mut c_void
+    }
+
+    fn file(handle: *mut c_void) -> &'static [u8] {
+        FILES[handle as usize - 1].1
+    }
+
+    extern "C" fn read(handle: *mut c_void, offset: u64, buf: *mut u8, len: usize) -> i64 {
+        let data = file(handle).get(offset as usize..).unwrap_or_default();
+        let count = data.len().min(len);
+        unsafe { std::ptr::copy_nonoverlapping(data.as_ptr(), buf, count) };
+        count as i64
+    }
+
+    extern "C" fn size(handle: *mut c_void) -> u64 {
+        file(handle).len() as u64
+    }
+
+    extern "C" fn sibling(_: *mut c_void, name: *const c_char) -> *mut c_void {
+        let name = unsafe { CStr::from_ptr(name) }.to_str().unwrap();
+        FILES
+            .iter()
+            .position(|(file, _)| *file == name)
+            .map_or(std::ptr::null_mut(), handle)
+    }
+
+    extern "C" fn close(_: *mut c_void) {
+        CLOSED.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+    }
+
+    const CALLBACKS: ReaderCallbacks = ReaderCallbacks {
+        read,
+        size,
+        sibling: Some(sibling),
+        close: Some(close),
+    };
+
+    #[test]
+    fn callback_reader_opens_and_closes_siblings() {
+        let reader = CallbackReader {
+            callbacks: CALLBACKS,
+            handle: handle(0),
+            owned: false,
+        };
+        let mut buf = [0u8; 16];
+        assert_eq!(reader.read_at(4, &mut buf).unwrap(), 4);
+        assert_eq!(&buf[..4], b"LAIR");
+        assert!(reader.sibling("missing").is_err());
+
+        let part = reader.sibling("disk.002").unwrap();
+        assert_eq!(part.size(), Some(4));
+        let closed = CLOSED.load(std::sync::atomic::Ordering::SeqCst);
+        drop(part);
+        assert_eq!(CLOSED.load(std::sync::atomic::Ordering::SeqCst), closed + 1);
+    }
+
+    #[test]
+    fn detects_through_callbacks() {
+        extern "C" fn collect(
+            format: *const c_char,
+            _: u32,
+            depth: u32,
+            _: *const *const c_char,
+            _: *const *const c_char,
+            _: u32,
+            userdata: *mut c_void,
+        ) {
+            let formats = unsafe { &mut *(userdata as *mut Vec<(String, u32)>) };
+            let format = unsafe { CStr::from_ptr(format) }.to_str().unwrap();
+            formats.push((format.to_string(), depth));
+        }
+
+        format::init_test_formats();
+        let mut formats: Vec<(String, u32)> = Vec::new();
+        assert!(mountin_detect_tree_callbacks(
+            &CALLBACKS,
+            handle(0),
+            collect,
+            &mut formats as *mut _ as *mut c_void,
+        ));
+        assert_eq!(formats, [("disk/scl".to_string(), 0)]);
+        assert!(!mountin_detect_tree_callbacks(
+            std::ptr::null(),
+            std::ptr::null_mut(),
+            collect,
+            std::ptr::null_mut(),
+        ));
+    }
+}